@@ -0,0 +1,602 @@
+// Copyright 2022 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Fake hypervisor objects able to run `AArch64::build_vm` without KVM.
+//!
+//! The fakes accept every request `build_vm` makes and record the state that tests want to check
+//! (registers, memory regions, IRQ registrations); everything used only once the VM runs fails
+//! with `ENOTSUP`.
+
+use std::os::raw::c_int;
+use std::sync::Arc;
+
+use base::AsRawDescriptor;
+use base::Error;
+use base::Event;
+use base::MappedRegion;
+use base::Protection;
+use base::Result;
+use base::SafeDescriptor;
+use devices::Bus;
+use devices::IrqChip;
+use devices::IrqChipAArch64;
+use devices::IrqChipCap;
+use devices::IrqEdgeEvent;
+use devices::IrqEventIndex;
+use devices::IrqEventSource;
+use devices::IrqLevelEvent;
+use devices::VcpuRunState;
+#[cfg(feature = "gdb")]
+use gdbstub::arch::Arch;
+#[cfg(feature = "gdb")]
+use gdbstub_arch::aarch64::AArch64 as GdbArch;
+use hypervisor::ClockState;
+use hypervisor::Datamatch;
+use hypervisor::DeviceKind;
+use hypervisor::HypervHypercall;
+use hypervisor::Hypervisor;
+use hypervisor::HypervisorCap;
+use hypervisor::IoEventAddress;
+use hypervisor::IoParams;
+use hypervisor::IrqRoute;
+use hypervisor::MPState;
+use hypervisor::MemCacheType;
+use hypervisor::MemSlot;
+use hypervisor::PsciVersion;
+use hypervisor::Vcpu;
+use hypervisor::VcpuAArch64;
+use hypervisor::VcpuExit;
+use hypervisor::VcpuFeature;
+use hypervisor::VcpuRegAArch64;
+use hypervisor::VcpuRunHandle;
+use hypervisor::Vm;
+use hypervisor::VmAArch64;
+use hypervisor::VmCap;
+use hypervisor::PSCI_1_0;
+use libc::EBUSY;
+use libc::EIO;
+use libc::ENOENT;
+use libc::ENOTSUP;
+use resources::SystemAllocator;
+use sync::Mutex;
+use vm_memory::GuestAddress;
+use vm_memory::GuestMemory;
+
+fn not_supported<T>() -> Result<T> {
+    Err(Error::new(ENOTSUP))
+}
+
+pub struct FakeHypervisor;
+
+impl Hypervisor for FakeHypervisor {
+    fn try_clone(&self) -> Result<Self> {
+        Ok(FakeHypervisor)
+    }
+
+    fn check_capability(&self, _cap: HypervisorCap) -> bool {
+        false
+    }
+}
+
+/// A memory region added with `Vm::add_memory_region`.
+pub struct FakeMemoryRegion {
+    pub guest_addr: GuestAddress,
+    pub size: usize,
+    pub read_only: bool,
+    pub cache: MemCacheType,
+    pub mapping: Box<dyn MappedRegion>,
+}
+
+pub struct FakeVm {
+    hypervisor: FakeHypervisor,
+    mem: GuestMemory,
+    /// Regions added with `add_memory_region`, indexed by slot.
+    pub memory_regions: Vec<FakeMemoryRegion>,
+    /// The address and size passed to `load_protected_vm_firmware`.
+    pub protected_vm_firmware: Option<(GuestAddress, u64)>,
+}
+
+impl FakeVm {
+    pub fn new(mem: GuestMemory) -> FakeVm {
+        FakeVm {
+            hypervisor: FakeHypervisor,
+            mem,
+            memory_regions: Vec::new(),
+            protected_vm_firmware: None,
+        }
+    }
+}
+
+impl Vm for FakeVm {
+    fn try_clone(&self) -> Result<Self> {
+        not_supported()
+    }
+
+    fn check_capability(&self, _c: VmCap) -> bool {
+        false
+    }
+
+    fn get_guest_phys_addr_bits(&self) -> u8 {
+        40
+    }
+
+    fn get_memory(&self) -> &GuestMemory {
+        &self.mem
+    }
+
+    fn add_memory_region(
+        &mut self,
+        guest_addr: GuestAddress,
+        mem_region: Box<dyn MappedRegion>,
+        read_only: bool,
+        _log_dirty_pages: bool,
+        cache: MemCacheType,
+    ) -> Result<MemSlot> {
+        // Like the real hypervisors, the fake doesn't report `VmCap::MemCacheAttributes`.
+        if cache != MemCacheType::Cached {
+            return not_supported();
+        }
+        self.memory_regions.push(FakeMemoryRegion {
+            guest_addr,
+            size: mem_region.size(),
+            read_only,
+            cache,
+            mapping: mem_region,
+        });
+        Ok((self.memory_regions.len() - 1) as MemSlot)
+    }
+
+    fn msync_memory_region(&mut self, slot: MemSlot, offset: usize, size: usize) -> Result<()> {
+        let region = self
+            .memory_regions
+            .get(slot as usize)
+            .ok_or_else(|| Error::new(ENOENT))?;
+        region
+            .mapping
+            .msync(offset, size)
+            .map_err(|_| Error::new(EIO))
+    }
+
+    fn remove_memory_region(&mut self, _slot: MemSlot) -> Result<Box<dyn MappedRegion>> {
+        not_supported()
+    }
+
+    fn create_device(&self, _kind: DeviceKind) -> Result<SafeDescriptor> {
+        not_supported()
+    }
+
+    fn get_dirty_log(&self, _slot: MemSlot, _dirty_log: &mut [u8]) -> Result<()> {
+        not_supported()
+    }
+
+    fn register_ioevent(
+        &mut self,
+        _evt: &Event,
+        _addr: IoEventAddress,
+        _datamatch: Datamatch,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    fn unregister_ioevent(
+        &mut self,
+        _evt: &Event,
+        _addr: IoEventAddress,
+        _datamatch: Datamatch,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    fn handle_io_events(&self, _addr: IoEventAddress, _data: &[u8]) -> Result<()> {
+        Ok(())
+    }
+
+    fn get_pvclock(&self) -> Result<ClockState> {
+        not_supported()
+    }
+
+    fn set_pvclock(&self, _state: &ClockState) -> Result<()> {
+        not_supported()
+    }
+
+    fn add_fd_mapping(
+        &mut self,
+        _slot: u32,
+        _offset: usize,
+        _size: usize,
+        _fd: &dyn AsRawDescriptor,
+        _fd_offset: u64,
+        _prot: Protection,
+    ) -> Result<()> {
+        not_supported()
+    }
+
+    fn remove_mapping(&mut self, _slot: u32, _offset: usize, _size: usize) -> Result<()> {
+        not_supported()
+    }
+
+    fn handle_inflate(&mut self, _guest_address: GuestAddress, _size: u64) -> Result<()> {
+        not_supported()
+    }
+
+    fn handle_deflate(&mut self, _guest_address: GuestAddress, _size: u64) -> Result<()> {
+        not_supported()
+    }
+}
+
+impl VmAArch64 for FakeVm {
+    fn get_hypervisor(&self) -> &dyn Hypervisor {
+        &self.hypervisor
+    }
+
+    fn load_protected_vm_firmware(
+        &mut self,
+        fw_addr: GuestAddress,
+        fw_max_size: u64,
+    ) -> Result<()> {
+        self.protected_vm_firmware = Some((fw_addr, fw_max_size));
+        Ok(())
+    }
+
+    fn create_vcpu(&self, id: usize) -> Result<Box<dyn VcpuAArch64>> {
+        Ok(Box::new(FakeVcpu::new(id)))
+    }
+}
+
+#[derive(Default)]
+struct FakeVcpuState {
+    features: Vec<VcpuFeature>,
+    regs: Vec<(VcpuRegAArch64, u64)>,
+    pvtime_ipa: Option<u64>,
+    physical_counter: u64,
+    virtual_counter_offset: u64,
+}
+
+pub struct FakeVcpu {
+    id: usize,
+    state: Arc<Mutex<FakeVcpuState>>,
+}
+
+impl FakeVcpu {
+    pub fn new(id: usize) -> FakeVcpu {
+        FakeVcpu {
+            id,
+            state: Default::default(),
+        }
+    }
+
+    /// Returns the features the vcpu was initialized with.
+    pub fn features(&self) -> Vec<VcpuFeature> {
+        self.state.lock().features.clone()
+    }
+
+    /// Returns the last value written to `reg`, if any.
+    pub fn reg(&self, reg: VcpuRegAArch64) -> Option<u64> {
+        self.state
+            .lock()
+            .regs
+            .iter()
+            .rev()
+            .find(|(r, _)| *r == reg)
+            .map(|(_, value)| *value)
+    }
+
+    /// Returns the address passed to `init_pvtime`, if any.
+    pub fn pvtime_ipa(&self) -> Option<u64> {
+        self.state.lock().pvtime_ipa
+    }
+
+    /// Lets `ticks` of the physical counter elapse.
+    pub fn advance_counter(&self, ticks: u64) {
+        let mut state = self.state.lock();
+        state.physical_counter = state.physical_counter.wrapping_add(ticks);
+    }
+
+    /// Returns the offset subtracted from the physical counter to get the virtual counter.
+    pub fn virtual_counter_offset(&self) -> u64 {
+        self.state.lock().virtual_counter_offset
+    }
+}
+
+impl Vcpu for FakeVcpu {
+    fn try_clone(&self) -> Result<Self> {
+        Ok(FakeVcpu {
+            id: self.id,
+            state: self.state.clone(),
+        })
+    }
+
+    fn as_vcpu(&self) -> &dyn Vcpu {
+        self
+    }
+
+    fn take_run_handle(&self, _signal_num: Option<c_int>) -> Result<VcpuRunHandle> {
+        not_supported()
+    }
+
+    fn run(&mut self, _run_handle: &VcpuRunHandle) -> Result<VcpuExit> {
+        not_supported()
+    }
+
+    fn id(&self) -> usize {
+        self.id
+    }
+
+    fn set_immediate_exit(&self, _exit: bool) {}
+
+    fn set_local_immediate_exit(_exit: bool) {}
+
+    fn set_local_immediate_exit_fn(&self) -> extern "C" fn() {
+        extern "C" fn f() {}
+        f
+    }
+
+    fn handle_mmio(&self, _handle_fn: &mut dyn FnMut(IoParams) -> Option<[u8; 8]>) -> Result<()> {
+        not_supported()
+    }
+
+    fn handle_io(&self, _handle_fn: &mut dyn FnMut(IoParams) -> Option<[u8; 8]>) -> Result<()> {
+        not_supported()
+    }
+
+    fn handle_hyperv_hypercall(&self, _func: &mut dyn FnMut(HypervHypercall) -> u64) -> Result<()> {
+        not_supported()
+    }
+
+    fn handle_rdmsr(&self, _data: u64) -> Result<()> {
+        not_supported()
+    }
+
+    fn handle_wrmsr(&self) {}
+
+    fn pvclock_ctrl(&self) -> Result<()> {
+        not_supported()
+    }
+
+    fn set_signal_mask(&self, _signals: &[c_int]) -> Result<()> {
+        not_supported()
+    }
+
+    unsafe fn enable_raw_capability(&self, _cap: u32, _args: &[u64; 4]) -> Result<()> {
+        not_supported()
+    }
+}
+
+impl VcpuAArch64 for FakeVcpu {
+    fn init(&self, features: &[VcpuFeature]) -> Result<()> {
+        self.state.lock().features = features.to_vec();
+        Ok(())
+    }
+
+    fn init_pmu(&self, _irq: u64) -> Result<()> {
+        not_supported()
+    }
+
+    fn has_pvtime_support(&self) -> bool {
+        true
+    }
+
+    fn init_pvtime(&self, pvtime_ipa: u64) -> Result<()> {
+        self.state.lock().pvtime_ipa = Some(pvtime_ipa);
+        Ok(())
+    }
+
+    fn set_one_reg(&self, reg_id: VcpuRegAArch64, data: u64) -> Result<()> {
+        self.state.lock().regs.push((reg_id, data));
+        Ok(())
+    }
+
+    fn get_one_reg(&self, reg_id: VcpuRegAArch64) -> Result<u64> {
+        self.reg(reg_id).ok_or_else(|| Error::new(ENOTSUP))
+    }
+
+    fn get_virtual_counter(&self) -> Result<u64> {
+        let state = self.state.lock();
+        Ok(state
+            .physical_counter
+            .wrapping_sub(state.virtual_counter_offset))
+    }
+
+    fn set_virtual_counter(&self, value: u64) -> Result<()> {
+        let mut state = self.state.lock();
+        state.virtual_counter_offset = state.physical_counter.wrapping_sub(value);
+        Ok(())
+    }
+
+    fn get_psci_version(&self) -> Result<PsciVersion> {
+        Ok(PSCI_1_0)
+    }
+
+    fn inject_external_data_abort(&self) -> Result<()> {
+        not_supported()
+    }
+
+    #[cfg(feature = "gdb")]
+    fn set_guest_debug(&self, _addrs: &[GuestAddress], _enable_singlestep: bool) -> Result<()> {
+        not_supported()
+    }
+
+    #[cfg(feature = "gdb")]
+    fn set_gdb_registers(&self, _regs: &<GdbArch as Arch>::Registers) -> Result<()> {
+        not_supported()
+    }
+
+    #[cfg(feature = "gdb")]
+    fn get_gdb_registers(&self, _regs: &mut <GdbArch as Arch>::Registers) -> Result<()> {
+        not_supported()
+    }
+
+    #[cfg(feature = "gdb")]
+    fn get_max_hw_bps(&self) -> Result<usize> {
+        not_supported()
+    }
+
+    #[cfg(feature = "gdb")]
+    fn set_gdb_register(&self, _reg: <GdbArch as Arch>::RegId, _data: &[u8]) -> Result<()> {
+        not_supported()
+    }
+
+    #[cfg(feature = "gdb")]
+    fn get_gdb_register(&self, _reg: <GdbArch as Arch>::RegId, _data: &mut [u8]) -> Result<usize> {
+        not_supported()
+    }
+}
+
+/// An IRQ event registered with the fake irq chip.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FakeIrqRegistration {
+    pub irq: u32,
+    pub device_name: String,
+}
+
+#[derive(Clone, Default)]
+pub struct FakeIrqChip {
+    pub edge_irqs: Vec<FakeIrqRegistration>,
+    pub level_irqs: Vec<FakeIrqRegistration>,
+    /// IRQs whose registration fails.
+    pub busy_irqs: Vec<u32>,
+}
+
+impl FakeIrqChip {
+    fn register(
+        registrations: &mut Vec<FakeIrqRegistration>,
+        busy_irqs: &[u32],
+        irq: u32,
+        source: IrqEventSource,
+    ) -> Result<Option<IrqEventIndex>> {
+        if busy_irqs.contains(&irq) {
+            return Err(Error::new(EBUSY));
+        }
+        registrations.push(FakeIrqRegistration {
+            irq,
+            device_name: source.device_name,
+        });
+        Ok(None)
+    }
+}
+
+impl IrqChip for FakeIrqChip {
+    fn add_vcpu(&mut self, _vcpu_id: usize, _vcpu: &dyn Vcpu) -> Result<()> {
+        Ok(())
+    }
+
+    fn register_edge_irq_event(
+        &mut self,
+        irq: u32,
+        _irq_event: &IrqEdgeEvent,
+        source: IrqEventSource,
+    ) -> Result<Option<IrqEventIndex>> {
+        Self::register(&mut self.edge_irqs, &self.busy_irqs, irq, source)
+    }
+
+    fn unregister_edge_irq_event(&mut self, irq: u32, _irq_event: &IrqEdgeEvent) -> Result<()> {
+        self.edge_irqs.retain(|r| r.irq != irq);
+        Ok(())
+    }
+
+    fn register_level_irq_event(
+        &mut self,
+        irq: u32,
+        _irq_event: &IrqLevelEvent,
+        source: IrqEventSource,
+    ) -> Result<Option<IrqEventIndex>> {
+        Self::register(&mut self.level_irqs, &self.busy_irqs, irq, source)
+    }
+
+    fn unregister_level_irq_event(&mut self, irq: u32, _irq_event: &IrqLevelEvent) -> Result<()> {
+        self.level_irqs.retain(|r| r.irq != irq);
+        Ok(())
+    }
+
+    fn route_irq(&mut self, _route: IrqRoute) -> Result<()> {
+        Ok(())
+    }
+
+    fn set_irq_routes(&mut self, _routes: &[IrqRoute]) -> Result<()> {
+        Ok(())
+    }
+
+    fn irq_event_tokens(&self) -> Result<Vec<(IrqEventIndex, IrqEventSource, Event)>> {
+        Ok(Vec::new())
+    }
+
+    fn service_irq(&mut self, _irq: u32, _level: bool) -> Result<()> {
+        Ok(())
+    }
+
+    fn service_irq_event(&mut self, _event_index: IrqEventIndex) -> Result<()> {
+        Ok(())
+    }
+
+    fn broadcast_eoi(&self, _vector: u8) -> Result<()> {
+        Ok(())
+    }
+
+    fn inject_interrupts(&self, _vcpu: &dyn Vcpu) -> Result<()> {
+        Ok(())
+    }
+
+    fn halted(&self, _vcpu_id: usize) {}
+
+    fn wait_until_runnable(&self, _vcpu: &dyn Vcpu) -> Result<VcpuRunState> {
+        Ok(VcpuRunState::Runnable)
+    }
+
+    fn kick_halted_vcpus(&self) {}
+
+    fn get_mp_state(&self, _vcpu_id: usize) -> Result<MPState> {
+        not_supported()
+    }
+
+    fn set_mp_state(&mut self, _vcpu_id: usize, _state: &MPState) -> Result<()> {
+        not_supported()
+    }
+
+    fn try_clone(&self) -> Result<Self> {
+        Ok(self.clone())
+    }
+
+    fn finalize_devices(
+        &mut self,
+        _resources: &mut SystemAllocator,
+        _io_bus: &Bus,
+        _mmio_bus: &Bus,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    fn process_delayed_irq_events(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn irq_delayed_event_token(&self) -> Result<Option<Event>> {
+        Ok(None)
+    }
+
+    fn check_capability(&self, _c: IrqChipCap) -> bool {
+        false
+    }
+}
+
+impl IrqChipAArch64 for FakeIrqChip {
+    fn try_box_clone(&self) -> Result<Box<dyn IrqChipAArch64>> {
+        Ok(Box::new(self.clone()))
+    }
+
+    fn as_irq_chip(&self) -> &dyn IrqChip {
+        self
+    }
+
+    fn as_irq_chip_mut(&mut self) -> &mut dyn IrqChip {
+        self
+    }
+
+    fn get_vgic_version(&self) -> DeviceKind {
+        DeviceKind::ArmVgicV3
+    }
+
+    fn finalize(&self) -> Result<()> {
+        Ok(())
+    }
+}
@@ -6,9 +6,15 @@ use std::collections::BTreeMap;
 use std::fs::File;
 use std::io::Read;
 
+use arch::cpu_cache::CpuCache;
+use arch::cpu_cache::CpuCacheType;
+use arch::fdt::apply_overlay;
+use arch::fdt::parse_nodes;
 use arch::fdt::Error;
+use arch::fdt::FdtNode;
 use arch::fdt::FdtWriter;
 use arch::fdt::Result;
+use arch::metrics_page::METRICS_PAGE_SIZE;
 use arch::SERIAL_ADDR;
 // This is a Battery related constant
 use devices::bat::GOLDFISHBAT_MMIO_LEN;
@@ -18,25 +24,26 @@ use devices::PciInterruptPin;
 use hypervisor::PsciVersion;
 use hypervisor::PSCI_0_2;
 use hypervisor::PSCI_1_0;
+use resources::AddressRange;
 use vm_memory::GuestAddress;
 use vm_memory::GuestMemory;
 
 // These are GIC address-space location constants.
+use crate::AARCH64_BOOT_DOORBELL_ADDR;
+use crate::AARCH64_BOOT_DOORBELL_SIZE;
 use crate::AARCH64_GIC_CPUI_BASE;
 use crate::AARCH64_GIC_CPUI_SIZE;
 use crate::AARCH64_GIC_DIST_BASE;
 use crate::AARCH64_GIC_DIST_SIZE;
 use crate::AARCH64_GIC_REDIST_SIZE;
 // This is the start of DRAM in the physical address space.
+use crate::ram_size;
 use crate::AARCH64_PHYS_MEM_START;
 use crate::AARCH64_PMU_IRQ;
 // These are RTC related constants
 use crate::AARCH64_RTC_ADDR;
-use crate::AARCH64_RTC_IRQ;
 use crate::AARCH64_RTC_SIZE;
 // These are serial device related constants.
-use crate::AARCH64_SERIAL_1_3_IRQ;
-use crate::AARCH64_SERIAL_2_4_IRQ;
 use crate::AARCH64_SERIAL_SIZE;
 use crate::AARCH64_SERIAL_SPEED;
 
@@ -48,6 +55,8 @@ const PHANDLE_RESTRICTED_DMA_POOL: u32 = 2;
 
 // CPUs are assigned phandles starting with this number.
 const PHANDLE_CPU0: u32 = 0x100;
+// Caches past level 1 are assigned phandles starting with this number.
+const PHANDLE_CACHE0: u32 = 0x1000;
 
 // These are specified by the Linux GIC bindings
 const GIC_FDT_IRQ_NUM_CELLS: u32 = 3;
@@ -60,7 +69,7 @@ const IRQ_TYPE_LEVEL_HIGH: u32 = 0x00000004;
 const IRQ_TYPE_LEVEL_LOW: u32 = 0x00000008;
 
 fn create_memory_node(fdt: &mut FdtWriter, guest_mem: &GuestMemory) -> Result<()> {
-    let mem_size = guest_mem.memory_size();
+    let mem_size = ram_size(guest_mem);
     let mem_reg_prop = [AARCH64_PHYS_MEM_START, mem_size];
 
     let memory_node = fdt.begin_node("memory")?;
@@ -70,25 +79,66 @@ fn create_memory_node(fdt: &mut FdtWriter, guest_mem: &GuestMemory) -> Result<()
     Ok(())
 }
 
-fn create_resv_memory_node(fdt: &mut FdtWriter, resv_size: Option<u64>) -> Result<Option<u32>> {
-    if let Some(resv_size) = resv_size {
-        let resv_memory_node = fdt.begin_node("reserved-memory")?;
-        fdt.property_u32("#address-cells", 0x2)?;
-        fdt.property_u32("#size-cells", 0x2)?;
-        fdt.property_null("ranges")?;
+fn create_resv_memory_node(
+    fdt: &mut FdtWriter,
+    resv_size: Option<u64>,
+    pmem_regions: &[AddressRange],
+) -> Result<Option<u32>> {
+    if resv_size.is_none() && pmem_regions.is_empty() {
+        return Ok(None);
+    }
+
+    let resv_memory_node = fdt.begin_node("reserved-memory")?;
+    fdt.property_u32("#address-cells", 0x2)?;
+    fdt.property_u32("#size-cells", 0x2)?;
+    fdt.property_null("ranges")?;
 
+    if let Some(resv_size) = resv_size {
         let restricted_dma_pool = fdt.begin_node("restricted_dma_reserved")?;
         fdt.property_u32("phandle", PHANDLE_RESTRICTED_DMA_POOL)?;
         fdt.property_string("compatible", "restricted-dma-pool")?;
         fdt.property_u64("size", resv_size)?;
         fdt.property_u64("alignment", base::pagesize() as u64)?;
         fdt.end_node(restricted_dma_pool)?;
+    }
 
-        fdt.end_node(resv_memory_node)?;
-        Ok(Some(PHANDLE_RESTRICTED_DMA_POOL))
-    } else {
-        Ok(None)
+    for region in pmem_regions {
+        // The ranges were built from non-zero sizes.
+        let size = region.len().unwrap_or_default();
+        let pmem_node = fdt.begin_node(&format!("pmem@{:x}", region.start))?;
+        fdt.property_string("compatible", "pmem-region")?;
+        fdt.property_array_u64("reg", &[region.start, size])?;
+        fdt.property_null("no-map")?;
+        fdt.end_node(pmem_node)?;
     }
+
+    fdt.end_node(resv_memory_node)?;
+    Ok(resv_size.map(|_| PHANDLE_RESTRICTED_DMA_POOL))
+}
+
+// Adds the properties describing `cache` with the given prefix, e.g. "d-cache" for a level 1
+// data cache in a cpu node.
+fn create_cache_properties(fdt: &mut FdtWriter, prefix: &str, cache: &CpuCache) -> Result<()> {
+    if let Some(size) = cache.size {
+        fdt.property_u32(&format!("{}-size", prefix), size)?;
+    }
+    if let Some(line_size) = cache.line_size {
+        fdt.property_u32(&format!("{}-line-size", prefix), line_size)?;
+    }
+    if let Some(sets) = cache.sets {
+        fdt.property_u32(&format!("{}-sets", prefix), sets)?;
+    }
+    Ok(())
+}
+
+// Returns the index in `outer_caches` of the lowest level cache past `level` shared by `cpus`.
+fn next_level_cache(outer_caches: &[&CpuCache], level: u32, cpus: &[usize]) -> Option<usize> {
+    outer_caches
+        .iter()
+        .enumerate()
+        .filter(|(_, cache)| cache.level > level && cpus.iter().all(|cpu| cache.cpus.contains(cpu)))
+        .min_by_key(|(_, cache)| cache.level)
+        .map(|(index, _)| index)
 }
 
 fn create_cpu_nodes(
@@ -96,7 +146,16 @@ fn create_cpu_nodes(
     num_cpus: u32,
     cpu_clusters: Vec<Vec<usize>>,
     cpu_capacity: BTreeMap<usize, u32>,
+    cpu_caches: &[CpuCache],
 ) -> Result<()> {
+    // Caches of unknown size aren't described. Level 1 caches are described by the properties of
+    // the cpu nodes, and the other caches by nodes linked from the cpu nodes by
+    // `next-level-cache`.
+    let (l1_caches, outer_caches): (Vec<&CpuCache>, Vec<&CpuCache>) = cpu_caches
+        .iter()
+        .filter(|cache| cache.size.is_some())
+        .partition(|cache| cache.level == 1);
+
     let cpus_node = fdt.begin_node("cpus")?;
     fdt.property_u32("#address-cells", 0x1)?;
     fdt.property_u32("#size-cells", 0x0)?;
@@ -116,9 +175,43 @@ fn create_cpu_nodes(
             fdt.property_u32("capacity-dmips-mhz", *capacity)?;
         }
 
+        for cache in l1_caches
+            .iter()
+            .filter(|cache| cache.cpus.contains(&(cpu_id as usize)))
+        {
+            let prefix = match cache.cache_type {
+                CpuCacheType::Data => "d-cache",
+                CpuCacheType::Instruction => "i-cache",
+                CpuCacheType::Unified => {
+                    fdt.property_null("cache-unified")?;
+                    "cache"
+                }
+            };
+            create_cache_properties(fdt, prefix, cache)?;
+        }
+        if let Some(next) = next_level_cache(&outer_caches, 1, &[cpu_id as usize]) {
+            fdt.property_u32("next-level-cache", PHANDLE_CACHE0 + next as u32)?;
+        }
+
         fdt.end_node(cpu_node)?;
     }
 
+    let mut caches_per_level = BTreeMap::new();
+    for (index, cache) in outer_caches.iter().enumerate() {
+        let level_index = caches_per_level.entry(cache.level).or_insert(0);
+        let cache_node = fdt.begin_node(&format!("l{}-cache{}", cache.level, level_index))?;
+        *level_index += 1;
+        fdt.property_string("compatible", "cache")?;
+        fdt.property_u32("cache-level", cache.level)?;
+        fdt.property_null("cache-unified")?;
+        create_cache_properties(fdt, "cache", cache)?;
+        fdt.property_u32("phandle", PHANDLE_CACHE0 + index as u32)?;
+        if let Some(next) = next_level_cache(&outer_caches, cache.level, &cache.cpus) {
+            fdt.property_u32("next-level-cache", PHANDLE_CACHE0 + next as u32)?;
+        }
+        fdt.end_node(cache_node)?;
+    }
+
     if !cpu_clusters.is_empty() {
         let cpu_map_node = fdt.begin_node("cpu-map")?;
         for (cluster_idx, cpus) in cpu_clusters.iter().enumerate() {
@@ -201,28 +294,38 @@ fn create_pmu_node(fdt: &mut FdtWriter, num_cpus: u32) -> Result<()> {
     Ok(())
 }
 
-fn create_serial_node(fdt: &mut FdtWriter, addr: u64, irq: u32) -> Result<()> {
+fn create_serial_node(fdt: &mut FdtWriter, addr: u64, irq: Option<u32>) -> Result<()> {
     let serial_reg_prop = [addr, AARCH64_SERIAL_SIZE];
-    let irq = [GIC_FDT_IRQ_TYPE_SPI, irq, IRQ_TYPE_EDGE_RISING];
 
     let serial_node = fdt.begin_node(&format!("U6_16550A@{:x}", addr))?;
     fdt.property_string("compatible", "ns16550a")?;
     fdt.property_array_u64("reg", &serial_reg_prop)?;
     fdt.property_u32("clock-frequency", AARCH64_SERIAL_SPEED)?;
-    fdt.property_array_u32("interrupts", &irq)?;
+    // Without an interrupt, the guest driver polls the port.
+    if let Some(irq) = irq {
+        let irq = [GIC_FDT_IRQ_TYPE_SPI, irq, IRQ_TYPE_EDGE_RISING];
+        fdt.property_array_u32("interrupts", &irq)?;
+    }
     fdt.end_node(serial_node)?;
 
     Ok(())
 }
 
-fn create_serial_nodes(fdt: &mut FdtWriter) -> Result<()> {
+fn create_serial_nodes(
+    fdt: &mut FdtWriter,
+    irqs: &PlatformIrqs,
+    extra_ports: &[(u64, Option<u32>)],
+) -> Result<()> {
     // Note that SERIAL_ADDR contains the I/O port addresses conventionally used
     // for serial ports on x86. This uses the same addresses (but on the MMIO bus)
     // to simplify the shared serial code.
-    create_serial_node(fdt, SERIAL_ADDR[0], AARCH64_SERIAL_1_3_IRQ)?;
-    create_serial_node(fdt, SERIAL_ADDR[1], AARCH64_SERIAL_2_4_IRQ)?;
-    create_serial_node(fdt, SERIAL_ADDR[2], AARCH64_SERIAL_1_3_IRQ)?;
-    create_serial_node(fdt, SERIAL_ADDR[3], AARCH64_SERIAL_2_4_IRQ)?;
+    create_serial_node(fdt, SERIAL_ADDR[0], irqs.serial_1_3)?;
+    create_serial_node(fdt, SERIAL_ADDR[1], irqs.serial_2_4)?;
+    create_serial_node(fdt, SERIAL_ADDR[2], irqs.serial_1_3)?;
+    create_serial_node(fdt, SERIAL_ADDR[3], irqs.serial_2_4)?;
+    for &(addr, irq) in extra_ports {
+        create_serial_node(fdt, addr, irq)?;
+    }
 
     Ok(())
 }
@@ -261,10 +364,13 @@ fn create_chosen_node(
     fdt: &mut FdtWriter,
     cmdline: &str,
     initrd: Option<(GuestAddress, usize)>,
+    vm_uuid: &str,
 ) -> Result<()> {
     let chosen_node = fdt.begin_node("chosen")?;
     fdt.property_u32("linux,pci-probe-only", 1)?;
     fdt.property_string("bootargs", cmdline)?;
+    // Lets the guest tag its logs with the identity of the VM it runs in.
+    fdt.property_string("crosvm,vm-uuid", vm_uuid)?;
     // Used by android bootloader for boot console output
     fdt.property_string("stdout-path", &format!("/U6_16550A@{:x}", SERIAL_ADDR[0]))?;
 
@@ -345,6 +451,16 @@ pub struct VmWdtConfig {
     pub timeout_sec: u32,
 }
 
+/// Interrupts of the fixed platform devices, `None` for a device running without one.
+pub struct PlatformIrqs {
+    /// Interrupt of the RTC alarm.
+    pub rtc: Option<u32>,
+    /// Interrupt shared by serial ports 1 and 3.
+    pub serial_1_3: Option<u32>,
+    /// Interrupt shared by serial ports 2 and 4.
+    pub serial_2_4: Option<u32>,
+}
+
 fn create_pci_nodes(
     fdt: &mut FdtWriter,
     pci_irqs: Vec<(PciAddress, u32, PciInterruptPin)>,
@@ -430,7 +546,7 @@ fn create_pci_nodes(
     Ok(())
 }
 
-fn create_rtc_node(fdt: &mut FdtWriter) -> Result<()> {
+fn create_rtc_node(fdt: &mut FdtWriter, irq: Option<u32>) -> Result<()> {
     // the kernel driver for pl030 really really wants a clock node
     // associated with an AMBA device or it will fail to probe, so we
     // need to make up a clock node to associate with the pl030 rtc
@@ -445,13 +561,15 @@ fn create_rtc_node(fdt: &mut FdtWriter) -> Result<()> {
 
     let rtc_name = format!("rtc@{:x}", AARCH64_RTC_ADDR);
     let reg = [AARCH64_RTC_ADDR, AARCH64_RTC_SIZE];
-    let irq = [GIC_FDT_IRQ_TYPE_SPI, AARCH64_RTC_IRQ, IRQ_TYPE_LEVEL_HIGH];
 
     let rtc_node = fdt.begin_node(&rtc_name)?;
     fdt.property_string("compatible", "arm,primecell")?;
     fdt.property_u32("arm,primecell-periphid", PL030_AMBA_ID)?;
     fdt.property_array_u64("reg", &reg)?;
-    fdt.property_array_u32("interrupts", &irq)?;
+    if let Some(irq) = irq {
+        let irq = [GIC_FDT_IRQ_TYPE_SPI, irq, IRQ_TYPE_LEVEL_HIGH];
+        fdt.property_array_u32("interrupts", &irq)?;
+    }
     fdt.property_u32("clocks", CLK_PHANDLE)?;
     fdt.property_string("clock-names", "apb_pclk")?;
     fdt.end_node(rtc_node)?;
@@ -464,14 +582,16 @@ fn create_rtc_node(fdt: &mut FdtWriter) -> Result<()> {
 ///
 /// * `fdt` - A FdtWriter in which the node is created
 /// * `mmio_base` - The MMIO base address of the battery
-/// * `irq` - The IRQ number of the battery
-fn create_battery_node(fdt: &mut FdtWriter, mmio_base: u64, irq: u32) -> Result<()> {
+/// * `irq` - The IRQ number of the battery, if it has one
+fn create_battery_node(fdt: &mut FdtWriter, mmio_base: u64, irq: Option<u32>) -> Result<()> {
     let reg = [mmio_base, GOLDFISHBAT_MMIO_LEN];
-    let irqs = [GIC_FDT_IRQ_TYPE_SPI, irq, IRQ_TYPE_LEVEL_HIGH];
-    let bat_node = fdt.begin_node("goldfish_battery")?;
+    let bat_node = fdt.begin_node(&format!("goldfish_battery@{:x}", mmio_base))?;
     fdt.property_string("compatible", "google,goldfish-battery")?;
     fdt.property_array_u64("reg", &reg)?;
-    fdt.property_array_u32("interrupts", &irqs)?;
+    if let Some(irq) = irq {
+        let irqs = [GIC_FDT_IRQ_TYPE_SPI, irq, IRQ_TYPE_LEVEL_HIGH];
+        fdt.property_array_u32("interrupts", &irqs)?;
+    }
     fdt.end_node(bat_node)?;
     Ok(())
 }
@@ -488,6 +608,26 @@ fn create_vmwdt_node(fdt: &mut FdtWriter, vmwdt_cfg: VmWdtConfig) -> Result<()>
     Ok(())
 }
 
+fn create_boot_doorbell_node(fdt: &mut FdtWriter) -> Result<()> {
+    let doorbell_name = format!("boot-doorbell@{:x}", AARCH64_BOOT_DOORBELL_ADDR);
+    let reg = [AARCH64_BOOT_DOORBELL_ADDR, AARCH64_BOOT_DOORBELL_SIZE];
+    let doorbell_node = fdt.begin_node(&doorbell_name)?;
+    fdt.property_string("compatible", "crosvm,boot-doorbell")?;
+    fdt.property_array_u64("reg", &reg)?;
+    fdt.end_node(doorbell_node)?;
+    Ok(())
+}
+
+fn create_metrics_page_node(fdt: &mut FdtWriter, addr: u64) -> Result<()> {
+    let metrics_page_name = format!("metrics-page@{:x}", addr);
+    let reg = [addr, METRICS_PAGE_SIZE];
+    let metrics_page_node = fdt.begin_node(&metrics_page_name)?;
+    fdt.property_string("compatible", "crosvm,metrics-page")?;
+    fdt.property_array_u64("reg", &reg)?;
+    fdt.end_node(metrics_page_node)?;
+    Ok(())
+}
+
 /// Creates a flattened device tree containing all of the parameters for the
 /// kernel and loads it into the guest memory at the specified offset.
 ///
@@ -499,16 +639,20 @@ fn create_vmwdt_node(fdt: &mut FdtWriter, vmwdt_cfg: VmWdtConfig) -> Result<()>
 /// * `pci_cfg` - Location of the memory-mapped PCI configuration space.
 /// * `pci_ranges` - Memory ranges accessible via the PCI host controller.
 /// * `num_cpus` - Number of virtual CPUs the guest will have
-/// * `fdt_load_offset` - The offset into physical memory for the device tree
+/// * `cpu_caches` - The caches of the virtual CPUs, checked with `arch::cpu_cache::check_cpu_caches`
+/// * `fdt_address` - The guest physical address of the device tree
 /// * `cmdline` - The kernel commandline
 /// * `initrd` - An optional tuple of initrd guest physical address and size
 /// * `android_fstab` - An optional file holding Android fstab entries
 /// * `is_gicv3` - True if gicv3, false if v2
 /// * `psci_version` - the current PSCI version
-/// * `bat_mmio_base` - The battery base address
-/// * `bat_irq` - The battery irq number
+/// * `batteries` - The base address and irq number of each battery
 /// * `swiotlb` - Reserve a memory pool for DMA
+/// * `pmem_regions` - The persistent memory regions to describe as reserved memory
 /// * `vmwdt_cfg` - The virtual watchdog configuration
+/// * `metrics_page_addr` - The guest physical address of the metrics page, if any
+/// * `platform_irqs` - The interrupts of the fixed platform devices
+/// * `extra_serial_ports` - Address and interrupt of the serial ports past the first four
 pub fn create_fdt(
     fdt_max_size: usize,
     guest_mem: &GuestMemory,
@@ -518,16 +662,23 @@ pub fn create_fdt(
     num_cpus: u32,
     cpu_clusters: Vec<Vec<usize>>,
     cpu_capacity: BTreeMap<usize, u32>,
-    fdt_load_offset: u64,
+    cpu_caches: &[CpuCache],
+    fdt_address: GuestAddress,
     cmdline: &str,
     initrd: Option<(GuestAddress, usize)>,
+    vm_uuid: &str,
     android_fstab: Option<File>,
     is_gicv3: bool,
     use_pmu: bool,
     psci_version: PsciVersion,
     swiotlb: Option<u64>,
-    bat_mmio_base_and_irq: Option<(u64, u32)>,
+    pmem_regions: &[AddressRange],
+    batteries: &[(u64, Option<u32>)],
     vmwdt_cfg: VmWdtConfig,
+    metrics_page_addr: Option<u64>,
+    platform_irqs: PlatformIrqs,
+    extra_serial_ports: &[(u64, Option<u32>)],
+    device_tree_overlay: Option<File>,
 ) -> Result<()> {
     let mut fdt = FdtWriter::new(&[]);
 
@@ -540,29 +691,43 @@ pub fn create_fdt(
     if let Some(android_fstab) = android_fstab {
         arch::android::create_android_fdt(&mut fdt, android_fstab)?;
     }
-    create_chosen_node(&mut fdt, cmdline, initrd)?;
+    create_chosen_node(&mut fdt, cmdline, initrd, vm_uuid)?;
     create_memory_node(&mut fdt, guest_mem)?;
-    let dma_pool_phandle = create_resv_memory_node(&mut fdt, swiotlb)?;
-    create_cpu_nodes(&mut fdt, num_cpus, cpu_clusters, cpu_capacity)?;
+    let dma_pool_phandle = create_resv_memory_node(&mut fdt, swiotlb, pmem_regions)?;
+    create_cpu_nodes(&mut fdt, num_cpus, cpu_clusters, cpu_capacity, cpu_caches)?;
     create_gic_node(&mut fdt, is_gicv3, num_cpus as u64)?;
     create_timer_node(&mut fdt, num_cpus)?;
     if use_pmu {
         create_pmu_node(&mut fdt, num_cpus)?;
     }
-    create_serial_nodes(&mut fdt)?;
+    create_serial_nodes(&mut fdt, &platform_irqs, extra_serial_ports)?;
     create_psci_node(&mut fdt, &psci_version)?;
     create_pci_nodes(&mut fdt, pci_irqs, pci_cfg, pci_ranges, dma_pool_phandle)?;
-    create_rtc_node(&mut fdt)?;
-    if let Some((bat_mmio_base, bat_irq)) = bat_mmio_base_and_irq {
+    create_rtc_node(&mut fdt, platform_irqs.rtc)?;
+    for &(bat_mmio_base, bat_irq) in batteries {
         create_battery_node(&mut fdt, bat_mmio_base, bat_irq)?;
     }
     create_vmwdt_node(&mut fdt, vmwdt_cfg)?;
+    create_boot_doorbell_node(&mut fdt)?;
+    if let Some(metrics_page_addr) = metrics_page_addr {
+        create_metrics_page_node(&mut fdt, metrics_page_addr)?;
+    }
     // End giant node
     fdt.end_node(root_node)?;
 
-    let fdt_final = fdt.finish(fdt_max_size)?;
+    let mut fdt_final = fdt.finish(fdt_max_size)?;
+    if let Some(mut device_tree_overlay) = device_tree_overlay {
+        let mut overlay = Vec::new();
+        device_tree_overlay
+            .read_to_end(&mut overlay)
+            .map_err(Error::FdtIoError)?;
+        // The guest must see the memory and boot parameters crosvm set up.
+        fdt_final = apply_overlay(&fdt_final, &overlay, &["/chosen", "/memory"], fdt_max_size)?;
+    }
+    if cfg!(any(debug_assertions, feature = "fdt-self-check")) {
+        check_fdt(&fdt_final, fdt_max_size)?;
+    }
 
-    let fdt_address = GuestAddress(AARCH64_PHYS_MEM_START + fdt_load_offset);
     let written = guest_mem
         .write_at_addr(fdt_final.as_slice(), fdt_address)
         .map_err(|_| Error::FdtGuestMemoryWriteError)?;
@@ -572,6 +737,170 @@ pub fn create_fdt(
     Ok(())
 }
 
+/// Walks a generated device tree to check that every phandle it references exists and that the
+/// nodes have the properties needed to interpret them, so that mistakes are reported with the
+/// path of the offending node instead of as cryptic guest kernel messages.
+///
+/// # Arguments
+///
+/// * `blob` - The device tree blob
+/// * `max_size` - The amount of space reserved for the device tree
+fn check_fdt(blob: &[u8], max_size: usize) -> Result<()> {
+    let totalsize = blob
+        .get(4..8)
+        .map(|b| u32::from_be_bytes(b.try_into().unwrap()) as usize)
+        .ok_or(Error::InvalidBlob)?;
+    if totalsize > max_size {
+        return Err(Error::TotalSizeTooLarge);
+    }
+
+    let nodes = parse_nodes(blob)?;
+    let mut phandles = BTreeMap::new();
+    for (index, node) in nodes.iter().enumerate() {
+        if let Some(phandle) = node.property_u32("phandle")? {
+            if phandles.insert(phandle, index).is_some() {
+                return Err(Error::DuplicatePhandle {
+                    path: node.path.clone(),
+                    phandle,
+                });
+            }
+        }
+    }
+
+    let checker = FdtChecker {
+        nodes: &nodes,
+        phandles,
+    };
+    for node in &nodes {
+        checker.check_node(node)?;
+    }
+    Ok(())
+}
+
+struct FdtChecker<'a> {
+    nodes: &'a [FdtNode],
+    /// Index in `nodes` of the node holding each phandle.
+    phandles: BTreeMap<u32, usize>,
+}
+
+impl<'a> FdtChecker<'a> {
+    fn check_node(&self, node: &FdtNode) -> Result<()> {
+        for property in ["interrupt-parent", "cpu", "next-level-cache"] {
+            if let Some(phandle) = node.property_u32(property)? {
+                self.resolve(node, property, phandle)?;
+            }
+        }
+        if let Some(phandles) = node.property_cells("memory-region")? {
+            for phandle in phandles {
+                self.resolve(node, "memory-region", phandle)?;
+            }
+        }
+        if let Some(clocks) = node.property_cells("clocks")? {
+            self.check_specifiers(node, "clocks", &clocks, 0, |provider| {
+                cells(provider, "#clock-cells")
+            })?;
+        }
+        if let Some(interrupts) = node.property_cells("interrupts")? {
+            let controller = self.interrupt_parent(node)?;
+            if controller.property("interrupt-controller").is_none() {
+                return Err(controller.missing_property("interrupt-controller"));
+            }
+            let interrupt_cells = cells(controller, "#interrupt-cells")? as usize;
+            if interrupts.is_empty()
+                || interrupt_cells == 0
+                || interrupts.len() % interrupt_cells != 0
+            {
+                return Err(node.invalid_property("interrupts"));
+            }
+        }
+        if let Some(map) = node.property_cells("interrupt-map")? {
+            let child_cells = cells(node, "#address-cells")? + cells(node, "#interrupt-cells")?;
+            self.check_specifiers(node, "interrupt-map", &map, child_cells, |parent| {
+                // Interrupt controllers often have no #address-cells, which then means 0.
+                let address_cells = parent.property_u32("#address-cells")?.unwrap_or(0);
+                Ok(address_cells + cells(parent, "#interrupt-cells")?)
+            })?;
+        }
+        if let Some(reg) = node.property_cells("reg")? {
+            if let Some(parent) = node.parent.map(|p| &self.nodes[p]) {
+                let entry_cells = cells(parent, "#address-cells")? + cells(parent, "#size-cells")?;
+                if reg.is_empty() || entry_cells == 0 || reg.len() % entry_cells as usize != 0 {
+                    return Err(node.invalid_property("reg"));
+                }
+            }
+        }
+        // An empty `ranges` maps the child address space identically.
+        if let Some(ranges) = node.property_cells("ranges")?.filter(|r| !r.is_empty()) {
+            let parent = node
+                .parent
+                .map(|p| &self.nodes[p])
+                .ok_or_else(|| node.invalid_property("ranges"))?;
+            let entry_cells = cells(node, "#address-cells")?
+                + cells(parent, "#address-cells")?
+                + cells(node, "#size-cells")?;
+            if entry_cells == 0 || ranges.len() % entry_cells as usize != 0 {
+                return Err(node.invalid_property("ranges"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the node holding `phandle`, referenced by `property` of `node`.
+    fn resolve(&self, node: &FdtNode, property: &str, phandle: u32) -> Result<&'a FdtNode> {
+        match self.phandles.get(&phandle) {
+            Some(&index) => Ok(&self.nodes[index]),
+            None => Err(Error::DanglingPhandle {
+                path: node.path.clone(),
+                property: property.to_owned(),
+                phandle,
+            }),
+        }
+    }
+
+    /// Returns the interrupt controller of `node`, from the closest `interrupt-parent` property
+    /// of the node or its ancestors.
+    fn interrupt_parent(&self, node: &FdtNode) -> Result<&'a FdtNode> {
+        let mut current = Some(node);
+        while let Some(n) = current {
+            if let Some(phandle) = n.property_u32("interrupt-parent")? {
+                return self.resolve(n, "interrupt-parent", phandle);
+            }
+            current = n.parent.map(|p| &self.nodes[p]);
+        }
+        Err(node.missing_property("interrupt-parent"))
+    }
+
+    /// Checks a list of entries each made of `prefix_cells` cells, a phandle, and the number of
+    /// cells `specifier_cells` returns for the node the phandle references.
+    fn check_specifiers(
+        &self,
+        node: &FdtNode,
+        property: &str,
+        values: &[u32],
+        prefix_cells: u32,
+        specifier_cells: impl Fn(&FdtNode) -> Result<u32>,
+    ) -> Result<()> {
+        let mut pos = 0;
+        while pos < values.len() {
+            pos += prefix_cells as usize;
+            let phandle = *values
+                .get(pos)
+                .ok_or_else(|| node.invalid_property(property))?;
+            pos += 1 + specifier_cells(self.resolve(node, property, phandle)?)? as usize;
+        }
+        if pos != values.len() {
+            return Err(node.invalid_property(property));
+        }
+        Ok(())
+    }
+}
+
+/// Returns the value of the `#...-cells` property `name` of `node`, which must be present.
+fn cells(node: &FdtNode, name: &str) -> Result<u32> {
+    node.property_u32(name)?
+        .ok_or_else(|| node.missing_property(name))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -618,4 +947,286 @@ mod tests {
             vec!["arm,psci-1.0", "arm,psci-0.2"]
         );
     }
+
+    /// Builds a tree with a GIC-like interrupt controller (phandle 1), a fixed clock (phandle 2)
+    /// and the nodes added by `add_nodes`.
+    fn checked_tree(add_nodes: impl FnOnce(&mut FdtWriter) -> Result<()>) -> Result<()> {
+        let mut fdt = FdtWriter::new(&[]);
+        let root_node = fdt.begin_node("")?;
+        fdt.property_u32("interrupt-parent", 1)?;
+        fdt.property_u32("#address-cells", 2)?;
+        fdt.property_u32("#size-cells", 2)?;
+        let intc_node = fdt.begin_node("intc")?;
+        fdt.property_u32("#interrupt-cells", 3)?;
+        fdt.property_null("interrupt-controller")?;
+        fdt.property_array_u64("reg", &[0x1000, 0x1000, 0x2000, 0x1000])?;
+        fdt.property_u32("phandle", 1)?;
+        fdt.end_node(intc_node)?;
+        let clock_node = fdt.begin_node("pclk")?;
+        fdt.property_u32("#clock-cells", 0)?;
+        fdt.property_u32("phandle", 2)?;
+        fdt.end_node(clock_node)?;
+        add_nodes(&mut fdt)?;
+        fdt.end_node(root_node)?;
+        let blob = fdt.finish(0x1000)?;
+        check_fdt(&blob, 0x1000)
+    }
+
+    fn device(fdt: &mut FdtWriter, props: impl FnOnce(&mut FdtWriter) -> Result<()>) -> Result<()> {
+        let node = fdt.begin_node("dev")?;
+        props(fdt)?;
+        fdt.end_node(node)
+    }
+
+    #[test]
+    fn check_valid_tree() {
+        checked_tree(|fdt| {
+            device(fdt, |fdt| {
+                fdt.property_array_u64("reg", &[0x3000, 0x1000])?;
+                fdt.property_array_u32("interrupts", &[0, 5, 4, 0, 6, 4])?;
+                fdt.property_u32("clocks", 2)
+            })
+        })
+        .unwrap();
+    }
+
+    // Returns the node holding `phandle`.
+    fn node_with_phandle(nodes: &[FdtNode], phandle: u32) -> &FdtNode {
+        nodes
+            .iter()
+            .find(|node| node.property_u32("phandle").unwrap() == Some(phandle))
+            .unwrap()
+    }
+
+    // Returns the level of the caches reached from `path` by following `next-level-cache`.
+    fn cache_levels(nodes: &[FdtNode], path: &str) -> Vec<u32> {
+        let mut node = nodes.iter().find(|node| node.path == path).unwrap();
+        let mut levels = Vec::new();
+        while let Some(next) = node.property_u32("next-level-cache").unwrap() {
+            node = node_with_phandle(nodes, next);
+            levels.push(node.property_u32("cache-level").unwrap().unwrap());
+        }
+        levels
+    }
+
+    #[test]
+    fn cpu_cache_nodes() {
+        let cache = |level, cache_type, size, cpus: &[usize]| CpuCache {
+            level,
+            cache_type,
+            size,
+            line_size: Some(64),
+            sets: None,
+            cpus: cpus.to_vec(),
+        };
+        let caches = [
+            cache(1, CpuCacheType::Data, Some(0x8000), &[0]),
+            cache(1, CpuCacheType::Instruction, Some(0x8000), &[0]),
+            cache(1, CpuCacheType::Data, Some(0x8000), &[2]),
+            cache(2, CpuCacheType::Unified, Some(0x80000), &[0, 1]),
+            // Left out for lack of a size, so cpus 2 and 3 go straight to the L3.
+            cache(2, CpuCacheType::Unified, None, &[2, 3]),
+            cache(3, CpuCacheType::Unified, Some(0x400000), &[0, 1, 2, 3]),
+        ];
+
+        let mut fdt = FdtWriter::new(&[]);
+        let root_node = fdt.begin_node("").unwrap();
+        create_cpu_nodes(
+            &mut fdt,
+            4,
+            vec![vec![0, 1], vec![2, 3]],
+            BTreeMap::new(),
+            &caches,
+        )
+        .unwrap();
+        fdt.end_node(root_node).unwrap();
+        let blob = fdt.finish(0x1000).unwrap();
+        check_fdt(&blob, 0x1000).unwrap();
+        let nodes = parse_nodes(&blob).unwrap();
+
+        assert_eq!(cache_levels(&nodes, "/cpus/cpu@0"), vec![2, 3]);
+        assert_eq!(cache_levels(&nodes, "/cpus/cpu@1"), vec![2, 3]);
+        assert_eq!(cache_levels(&nodes, "/cpus/cpu@2"), vec![3]);
+        assert_eq!(cache_levels(&nodes, "/cpus/cpu@3"), vec![3]);
+        let cpu0 = nodes.iter().find(|n| n.path == "/cpus/cpu@0").unwrap();
+        assert_eq!(cpu0.property_u32("d-cache-size").unwrap(), Some(0x8000));
+        assert_eq!(cpu0.property_u32("i-cache-line-size").unwrap(), Some(64));
+        assert!(cpu0.property("d-cache-sets").is_none());
+        let cpu1 = nodes.iter().find(|n| n.path == "/cpus/cpu@1").unwrap();
+        assert!(cpu1.property("d-cache-size").is_none());
+
+        // Both level 2 cache nodes are linked to the same level 3 cache node.
+        let l2 = nodes.iter().find(|n| n.path == "/cpus/l2-cache0").unwrap();
+        let l3 = nodes.iter().find(|n| n.path == "/cpus/l3-cache0").unwrap();
+        assert_eq!(
+            l2.property_u32("next-level-cache").unwrap(),
+            l3.property_u32("phandle").unwrap()
+        );
+        assert_eq!(l3.property_u32("cache-size").unwrap(), Some(0x400000));
+        assert!(!nodes.iter().any(|n| n.path == "/cpus/l2-cache1"));
+    }
+
+    #[test]
+    fn battery_nodes() {
+        let mut fdt = FdtWriter::new(&[]);
+        let root_node = fdt.begin_node("").unwrap();
+        for (mmio_base, irq) in [(0x3000, Some(5)), (0x4000, Some(6))] {
+            create_battery_node(&mut fdt, mmio_base, irq).unwrap();
+        }
+        fdt.end_node(root_node).unwrap();
+        let blob = fdt.finish(0x1000).unwrap();
+        let nodes = parse_nodes(&blob).unwrap();
+
+        let batteries: Vec<_> = nodes
+            .iter()
+            .filter(|n| n.path.starts_with("/goldfish_battery@"))
+            .collect();
+        assert_eq!(batteries.len(), 2);
+        for (battery, (path, mmio_base, irq)) in batteries.iter().zip([
+            ("/goldfish_battery@3000", 0x3000, 5),
+            ("/goldfish_battery@4000", 0x4000, 6),
+        ]) {
+            assert_eq!(battery.path, path);
+            assert_eq!(
+                battery.property_cells("reg").unwrap(),
+                Some(vec![0, mmio_base, 0, GOLDFISHBAT_MMIO_LEN as u32])
+            );
+            assert_eq!(
+                battery.property_cells("interrupts").unwrap(),
+                Some(vec![GIC_FDT_IRQ_TYPE_SPI, irq, IRQ_TYPE_LEVEL_HIGH])
+            );
+        }
+    }
+
+    #[test]
+    fn check_dangling_phandle() {
+        let err = checked_tree(|fdt| device(fdt, |fdt| fdt.property_u32("memory-region", 5)))
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            Error::DanglingPhandle { path, property, phandle: 5 }
+                if path == "/dev" && property == "memory-region"
+        ));
+    }
+
+    #[test]
+    fn check_duplicate_phandle() {
+        let err =
+            checked_tree(|fdt| device(fdt, |fdt| fdt.property_u32("phandle", 1))).unwrap_err();
+        assert!(matches!(
+            err,
+            Error::DuplicatePhandle { path, phandle: 1 } if path == "/dev"
+        ));
+    }
+
+    #[test]
+    fn check_interrupts_size() {
+        let err =
+            checked_tree(|fdt| device(fdt, |fdt| fdt.property_array_u32("interrupts", &[0, 5])))
+                .unwrap_err();
+        assert!(matches!(
+            err,
+            Error::InvalidProperty { path, property } if path == "/dev" && property == "interrupts"
+        ));
+    }
+
+    #[test]
+    fn check_interrupt_parent_not_a_controller() {
+        let err = checked_tree(|fdt| {
+            device(fdt, |fdt| {
+                fdt.property_u32("interrupt-parent", 2)?;
+                fdt.property_array_u32("interrupts", &[0, 5, 4])
+            })
+        })
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            Error::MissingProperty { path, property }
+                if path == "/pclk" && property == "interrupt-controller"
+        ));
+    }
+
+    #[test]
+    fn check_reg_without_address_cells() {
+        let err = checked_tree(|fdt| {
+            let bus_node = fdt.begin_node("bus")?;
+            device(fdt, |fdt| fdt.property_u32("reg", 0))?;
+            fdt.end_node(bus_node)
+        })
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            Error::MissingProperty { path, property }
+                if path == "/bus" && property == "#address-cells"
+        ));
+    }
+
+    #[test]
+    fn check_ranges_size() {
+        let err = checked_tree(|fdt| {
+            let bus_node = fdt.begin_node("bus")?;
+            fdt.property_u32("#address-cells", 1)?;
+            fdt.property_u32("#size-cells", 1)?;
+            // Each entry needs 1 + 2 + 1 cells.
+            fdt.property_array_u32("ranges", &[0, 0, 0x1000])?;
+            fdt.end_node(bus_node)
+        })
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            Error::InvalidProperty { path, property } if path == "/bus" && property == "ranges"
+        ));
+    }
+
+    #[test]
+    fn check_interrupt_map() {
+        let pci = |controller: u32| {
+            move |fdt: &mut FdtWriter| {
+                let pci_node = fdt.begin_node("pci")?;
+                fdt.property_u32("#address-cells", 3)?;
+                fdt.property_u32("#size-cells", 2)?;
+                fdt.property_u32("#interrupt-cells", 1)?;
+                // The controller has no #address-cells, so its unit address takes no cells.
+                fdt.property_array_u32("interrupt-map", &[0x800, 0, 0, 1, controller, 0, 32, 4])?;
+                fdt.end_node(pci_node)
+            }
+        };
+        checked_tree(pci(1)).unwrap();
+        let err = checked_tree(pci(7)).unwrap_err();
+        assert!(matches!(
+            err,
+            Error::DanglingPhandle { path, property, phandle: 7 }
+                if path == "/pci" && property == "interrupt-map"
+        ));
+    }
+
+    #[test]
+    fn chosen_vm_uuid() {
+        const UUID: &str = "23546c3d-962d-4ebc-94d9-4acf50996944";
+        let mut fdt = FdtWriter::new(&[]);
+        let root_node = fdt.begin_node("").unwrap();
+        create_chosen_node(&mut fdt, "console=ttyS0", None, UUID).unwrap();
+        fdt.end_node(root_node).unwrap();
+        let blob = fdt.finish(0x1000).unwrap();
+
+        let nodes = parse_nodes(&blob).unwrap();
+        let chosen = nodes.iter().find(|node| node.path == "/chosen").unwrap();
+        assert_eq!(
+            chosen.property("crosvm,vm-uuid"),
+            Some(format!("{}\0", UUID).as_bytes())
+        );
+    }
+
+    #[test]
+    fn check_size() {
+        let mut fdt = FdtWriter::new(&[]);
+        let root_node = fdt.begin_node("").unwrap();
+        fdt.end_node(root_node).unwrap();
+        let blob = fdt.finish(0x1000).unwrap();
+        check_fdt(&blob, 0x1000).unwrap();
+        assert!(matches!(
+            check_fdt(&blob, 0x10),
+            Err(Error::TotalSizeTooLarge)
+        ));
+    }
 }
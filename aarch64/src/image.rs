@@ -0,0 +1,194 @@
+// Copyright 2022 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+// Parser for the header of arm64 Linux kernel Images as described in
+// https://www.kernel.org/doc/Documentation/arm64/booting.rst
+
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+
+use data_model::DataInit;
+use remain::sorted;
+use thiserror::Error;
+
+/// "ARM\x64" in little endian.
+const ARM64_IMAGE_MAGIC: u32 = 0x644d5241;
+/// The kernel is placed `text_offset` bytes past a base address aligned to this.
+const ARM64_IMAGE_BASE_ALIGN: u64 = 0x200000;
+/// `text_offset` of kernels older than 3.17, whose header has a zero `image_size`.
+const ARM64_IMAGE_LEGACY_TEXT_OFFSET: u64 = 0x80000;
+/// Set in `flags` when the kernel can be placed anywhere in physical memory.
+const ARM64_IMAGE_FLAG_PHYS_BASE_ANYWHERE: u64 = 1 << 3;
+
+#[sorted]
+#[derive(Error, Debug, PartialEq)]
+pub enum Error {
+    #[error("bad arm64 Image magic {0:#x}")]
+    BadMagic(u32),
+    #[error("text_offset {0:#x} is not within the 2MiB aligned base")]
+    InvalidTextOffset(u64),
+    #[error("unable to read the arm64 Image header")]
+    ReadHeader,
+    #[error("unable to seek to the arm64 Image header")]
+    SeekHeader,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// The 64-byte header at the start of an arm64 kernel Image, stored in little endian.
+#[allow(dead_code)]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+struct Arm64ImageHeader {
+    code0: u32,
+    code1: u32,
+    text_offset: u64,
+    image_size: u64,
+    flags: u64,
+    res2: u64,
+    res3: u64,
+    res4: u64,
+    magic: u32,
+    res5: u32,
+}
+
+// Safe because Arm64ImageHeader only contains plain data.
+unsafe impl DataInit for Arm64ImageHeader {}
+
+/// Where an Image expects to be loaded, relative to a base address aligned to
+/// `ARM64_IMAGE_BASE_ALIGN`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ImagePlacement {
+    /// Offset of the kernel from the base address.
+    pub text_offset: u64,
+    /// Number of bytes the kernel uses from its load address, including its bss, or 0 if the
+    /// header doesn't say.
+    pub image_size: u64,
+    /// Whether the kernel can be placed anywhere in physical memory, in which case it also maps
+    /// the FDT and initrd wherever they are in RAM.
+    pub phys_base_anywhere: bool,
+}
+
+/// Reads the placement of the kernel from the header of `kernel_image`.
+pub fn read_placement<F>(kernel_image: &mut F) -> Result<ImagePlacement>
+where
+    F: Read + Seek,
+{
+    kernel_image
+        .seek(SeekFrom::Start(0))
+        .map_err(|_| Error::SeekHeader)?;
+    let header =
+        Arm64ImageHeader::from_reader(&mut *kernel_image).map_err(|_| Error::ReadHeader)?;
+
+    let magic = u32::from_le(header.magic);
+    if magic != ARM64_IMAGE_MAGIC {
+        return Err(Error::BadMagic(magic));
+    }
+
+    let image_size = u64::from_le(header.image_size);
+    // The flags are only meaningful in headers with an image_size.
+    let (text_offset, flags) = if image_size == 0 {
+        (ARM64_IMAGE_LEGACY_TEXT_OFFSET, 0)
+    } else {
+        (u64::from_le(header.text_offset), u64::from_le(header.flags))
+    };
+    if text_offset >= ARM64_IMAGE_BASE_ALIGN {
+        return Err(Error::InvalidTextOffset(text_offset));
+    }
+
+    Ok(ImagePlacement {
+        text_offset,
+        image_size,
+        phys_base_anywhere: flags & ARM64_IMAGE_FLAG_PHYS_BASE_ANYWHERE != 0,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn image(text_offset: u64, image_size: u64, magic: u32) -> Cursor<Vec<u8>> {
+        image_with_flags(text_offset, image_size, 0, magic)
+    }
+
+    fn image_with_flags(
+        text_offset: u64,
+        image_size: u64,
+        flags: u64,
+        magic: u32,
+    ) -> Cursor<Vec<u8>> {
+        let header = Arm64ImageHeader {
+            text_offset: text_offset.to_le(),
+            image_size: image_size.to_le(),
+            flags: flags.to_le(),
+            magic: magic.to_le(),
+            ..Default::default()
+        };
+        let mut image = header.as_slice().to_vec();
+        image.resize(0x1000, 0);
+        Cursor::new(image)
+    }
+
+    #[test]
+    fn placement() {
+        assert_eq!(
+            read_placement(&mut image(0x80000, 0x1400000, ARM64_IMAGE_MAGIC)),
+            Ok(ImagePlacement {
+                text_offset: 0x80000,
+                image_size: 0x1400000,
+                phys_base_anywhere: false,
+            })
+        );
+        assert_eq!(
+            read_placement(&mut image_with_flags(
+                0x80000,
+                0x1400000,
+                ARM64_IMAGE_FLAG_PHYS_BASE_ANYWHERE,
+                ARM64_IMAGE_MAGIC
+            )),
+            Ok(ImagePlacement {
+                text_offset: 0x80000,
+                image_size: 0x1400000,
+                phys_base_anywhere: true,
+            })
+        );
+    }
+
+    #[test]
+    fn legacy_text_offset() {
+        // The text_offset and flags of kernels without an image_size are unreliable.
+        assert_eq!(
+            read_placement(&mut image_with_flags(
+                0,
+                0,
+                ARM64_IMAGE_FLAG_PHYS_BASE_ANYWHERE,
+                ARM64_IMAGE_MAGIC
+            )),
+            Ok(ImagePlacement {
+                text_offset: ARM64_IMAGE_LEGACY_TEXT_OFFSET,
+                image_size: 0,
+                phys_base_anywhere: false,
+            })
+        );
+    }
+
+    #[test]
+    fn malformed_header() {
+        assert_eq!(
+            read_placement(&mut image(0x80000, 0x1400000, 0x5a5a5a5a)),
+            Err(Error::BadMagic(0x5a5a5a5a))
+        );
+        assert_eq!(
+            read_placement(&mut image(0x200000, 0x1400000, ARM64_IMAGE_MAGIC)),
+            Err(Error::InvalidTextOffset(0x200000))
+        );
+        assert_eq!(
+            read_placement(&mut Cursor::new(vec![0; 32])),
+            Err(Error::ReadHeader)
+        );
+    }
+}
@@ -7,20 +7,34 @@
 #![cfg(any(target_arch = "arm", target_arch = "aarch64"))]
 
 use std::collections::BTreeMap;
+use std::ffi::CString;
 use std::io;
 use std::sync::mpsc;
 use std::sync::Arc;
 
 use arch::get_serial_cmdline;
+use arch::metrics_page::MetricsPage;
+use arch::metrics_page::METRICS_PAGE_SIZE;
+use arch::pvtime::PvtimeRegion;
+use arch::pvtime::PVTIME_STRUCT_SIZE;
+use arch::ClusterAffinity;
+use arch::FdtPosition;
 use arch::GetSerialCmdlineError;
 use arch::MsrConfig;
 use arch::MsrExitHandlerError;
+use arch::PmemRegion;
 use arch::RunnableLinuxVm;
+use arch::SerialPortHandles;
+use arch::StaticMmioMap;
+use arch::VcpuAffinity;
 use arch::VmComponents;
 use arch::VmImage;
+use base::warn;
 use base::Event;
 use base::MemoryMappingBuilder;
 use base::SendTube;
+use base::SharedMemory;
+use base::Tube;
 use devices::serial_device::SerialHardware;
 use devices::serial_device::SerialParameters;
 use devices::vmwdt::VMWDT_DEFAULT_CLOCK_HZ;
@@ -28,13 +42,17 @@ use devices::vmwdt::VMWDT_DEFAULT_TIMEOUT_SEC;
 use devices::Bus;
 use devices::BusDeviceObj;
 use devices::BusError;
+use devices::BusResumeDevice;
 use devices::IrqChip;
 use devices::IrqChipAArch64;
 use devices::IrqEventSource;
+use devices::IrqLevelEvent;
 use devices::PciAddress;
 use devices::PciConfigMmio;
 use devices::PciDevice;
+use devices::PciInterruptPin;
 use devices::PciRootCommand;
+use devices::PreferredIrq;
 use devices::Serial;
 #[cfg(all(target_arch = "aarch64", feature = "gdb"))]
 use gdbstub::arch::Arch;
@@ -44,6 +62,7 @@ use hypervisor::CpuConfigAArch64;
 use hypervisor::DeviceKind;
 use hypervisor::Hypervisor;
 use hypervisor::HypervisorCap;
+use hypervisor::MemCacheType;
 use hypervisor::ProtectionType;
 use hypervisor::VcpuAArch64;
 use hypervisor::VcpuFeature;
@@ -51,25 +70,46 @@ use hypervisor::VcpuInitAArch64;
 use hypervisor::VcpuRegAArch64;
 use hypervisor::Vm;
 use hypervisor::VmAArch64;
+use hypervisor::MIDR_EL1;
+use hypervisor::REVIDR_EL1;
 use minijail::Minijail;
 use remain::sorted;
 use resources::AddressRange;
+use resources::AllocOptions;
 use resources::SystemAllocator;
 use resources::SystemAllocatorConfig;
 use sync::Mutex;
 use thiserror::Error;
 use vm_control::BatControl;
 use vm_control::BatteryType;
+use vm_control::BootMilestones;
+use vm_control::PhaseTimer;
+use vm_control::SetKernelCmdlineError;
 use vm_memory::GuestAddress;
 use vm_memory::GuestMemory;
 use vm_memory::GuestMemoryError;
+use vm_memory::MemoryAccessPolicy;
 
+#[cfg(test)]
+mod fake;
 mod fdt;
+mod image;
+mod suspend_time;
 
-// We place the kernel at offset 8MB
+pub use suspend_time::SuspendedCounter;
+
+// We place the kernel at offset 8MB, or at the text_offset given by its Image header past that.
 const AARCH64_KERNEL_OFFSET: u64 = 0x800000;
 const AARCH64_FDT_MAX_SIZE: u64 = 0x200000;
+// The FDT is kept in a 2MB block of its own when placed after the kernel, as the kernel maps it
+// with blocks of up to 2MB.
+const AARCH64_FDT_ALIGN: u64 = 0x200000;
 const AARCH64_INITRD_ALIGN: u64 = 0x1000000;
+// The FDT and initrd are kept below 4GiB, which every kernel can reach early in boot, unless the
+// Image header says the kernel can be placed anywhere in physical memory.
+const AARCH64_BOOT_DATA_LIMIT: u64 = 0x1_0000_0000;
+// Space left between the FDT and the end of the memory it is placed in.
+const AARCH64_FDT_END_GAP: u64 = 0x10000;
 
 // These constants indicate the address space used by the ARM vGIC.
 const AARCH64_GIC_DIST_SIZE: u64 = 0x10000;
@@ -78,6 +118,7 @@ const AARCH64_GIC_CPUI_SIZE: u64 = 0x20000;
 // This indicates the start of DRAM inside the physical address space.
 const AARCH64_PHYS_MEM_START: u64 = 0x80000000;
 const AARCH64_AXI_BASE: u64 = 0x40000000;
+// Minimum size of the platform MMIO region; it grows to fit the regions of all platform devices.
 const AARCH64_PLATFORM_MMIO_SIZE: u64 = 0x800000;
 
 // FDT is placed at the front of RAM when booting in BIOS mode.
@@ -92,7 +133,7 @@ const AARCH64_PROTECTED_VM_FW_START: u64 =
 
 const AARCH64_PVTIME_IPA_MAX_SIZE: u64 = 0x10000;
 const AARCH64_PVTIME_IPA_START: u64 = AARCH64_MMIO_BASE - AARCH64_PVTIME_IPA_MAX_SIZE;
-const AARCH64_PVTIME_SIZE: u64 = 64;
+const AARCH64_PVTIME_SIZE: u64 = PVTIME_STRUCT_SIZE;
 
 // These constants indicate the placement of the GIC registers in the physical
 // address space.
@@ -123,6 +164,9 @@ const AARCH64_SERIAL_SPEED: u32 = 1843200;
 // Which gets mapped to the first SPI interrupt (physical 32).
 const AARCH64_SERIAL_1_3_IRQ: u32 = 0;
 const AARCH64_SERIAL_2_4_IRQ: u32 = 2;
+// Serial ports past the four at `arch::SERIAL_ADDR` are placed in the low MMIO pool, each with its
+// own interrupt.
+const AARCH64_SERIAL_MAX_PORTS: u8 = 32;
 
 // Place the RTC device at page 2
 const AARCH64_RTC_ADDR: u64 = 0x2000;
@@ -136,6 +180,14 @@ const AARCH64_VMWDT_ADDR: u64 = 0x3000;
 // The virtual watchdog device gets one 4k page
 const AARCH64_VMWDT_SIZE: u64 = 0x1000;
 
+// Place the boot doorbell device at page 4
+const AARCH64_BOOT_DOORBELL_ADDR: u64 = 0x4000;
+// The boot doorbell device gets one 4k page
+const AARCH64_BOOT_DOORBELL_SIZE: u64 = devices::BOOT_DOORBELL_SIZE;
+
+// Place the metrics page, which is backed by guest memory, at page 5
+const AARCH64_METRICS_PAGE_ADDR: u64 = 0x5000;
+
 // PCI MMIO configuration region base address.
 const AARCH64_PCI_CFG_BASE: u64 = 0x10000;
 // PCI MMIO configuration region size.
@@ -147,6 +199,12 @@ const AARCH64_MMIO_SIZE: u64 = 0x2000000;
 // Virtio devices start at SPI interrupt number 3
 const AARCH64_IRQ_BASE: u32 = 3;
 
+// Persistent memory regions are carved out of the high MMIO region, from a fixed address so they
+// can be mapped before the VM, and so the size of its address space, is known.
+const AARCH64_PMEM_BASE: u64 = 0x80_0000_0000;
+// Persistent memory regions are 2 MiB aligned and sized so the guest can map them with DAX.
+const AARCH64_PMEM_ALIGN: u64 = 0x20_0000;
+
 // PMU PPI interrupt, same as qemu
 const AARCH64_PMU_IRQ: u32 = 7;
 
@@ -155,6 +213,8 @@ const AARCH64_PMU_IRQ: u32 = 7;
 pub enum Error {
     #[error("failed to allocate IRQ number")]
     AllocateIrq,
+    #[error("failed to allocate MMIO for serial port {0}: {1}")]
+    AllocateSerialMmio(u8, resources::Error),
     #[error("bios could not be loaded: {0}")]
     BiosLoadFailure(arch::LoadImageError),
     #[error("failed to build arm pvtime memory: {0}")]
@@ -163,8 +223,20 @@ pub enum Error {
     CloneEvent(base::Error),
     #[error("failed to clone IRQ chip: {0}")]
     CloneIrqChip(base::Error),
+    #[error("cluster affinity can't be set along with a vcpu affinity")]
+    ClusterAffinityConflict,
+    #[error("no host CPUs given for the vcpus of cluster {0}")]
+    ClusterAffinityEmpty(usize),
+    #[error("cluster affinity given for cluster {0}, which doesn't exist")]
+    ClusterAffinityInvalidCluster(usize),
+    #[error("vcpu {0} of a cluster with an affinity is in several clusters")]
+    ClusterAffinityOverlap(usize),
+    #[error("vcpu {0} of a cluster with an affinity doesn't exist")]
+    ClusterAffinityVcpu(usize),
     #[error("the given kernel command line was invalid: {0}")]
     Cmdline(kernel_cmdline::Error),
+    #[error("failed to configure hotplugged PCI device: {0}")]
+    ConfigurePciDevice(arch::DeviceRegistrationError),
     #[error("unable to create battery devices: {0}")]
     CreateBatDevices(arch::DeviceRegistrationError),
     #[error("unable to make an Event: {0}")]
@@ -177,16 +249,32 @@ pub enum Error {
     CreatePciRoot(arch::DeviceRegistrationError),
     #[error("failed to create platform bus: {0}")]
     CreatePlatformBus(arch::DeviceRegistrationError),
+    #[error("failed to create arm pvtime shared memory: {0}")]
+    CreatePvtimeMemory(base::Error),
     #[error("unable to create serial devices: {0}")]
     CreateSerialDevices(arch::DeviceRegistrationError),
     #[error("failed to create socket: {0}")]
     CreateSocket(io::Error),
     #[error("failed to create VCPU: {0}")]
     CreateVcpu(base::Error),
+    #[error("failed to create the vmwdt control tube: {0}")]
+    CreateVmwdtTube(base::TubeError),
     #[error("vm created wrong kind of vcpu")]
     DowncastVcpu,
     #[error("failed to enable singlestep execution: {0}")]
     EnableSinglestep(base::Error),
+    #[error("failed to expand platform MMIO region to {0:#x} bytes: {1}")]
+    ExpandPlatformMmio(u64, resources::Error),
+    #[error("FDT at {0:#x} ends above {1:#x}, which the kernel may not reach early in boot")]
+    FdtAboveLimit(u64, u64),
+    #[error("FDT address {0:#x} is not 8-byte aligned")]
+    FdtMisaligned(u64),
+    #[error("no room for the FDT in guest RAM below {0:#x}")]
+    FdtNoRoom(u64),
+    #[error("FDT at {0:#x} does not fit in guest RAM")]
+    FdtOutOfRam(u64),
+    #[error("FDT at {0:#x} overlaps the {1}")]
+    FdtOverlap(u64, &'static str),
     #[error("failed to finalize IRQ chip: {0}")]
     FinalizeIrqChip(base::Error),
     #[error("failed to get HW breakpoint count: {0}")]
@@ -195,16 +283,34 @@ pub enum Error {
     GetPsciVersion(base::Error),
     #[error("failed to get serial cmdline: {0}")]
     GetSerialCmdline(GetSerialCmdlineError),
+    #[error("failed to initialize the PMU: {0}")]
+    InitPmu(base::Error),
     #[error("failed to initialize arm pvtime: {0}")]
     InitPvtimeError(base::Error),
+    #[error("initrd at {0:#x} starts above {1:#x}, which the kernel may not reach early in boot")]
+    InitrdAboveLimit(u64, u64),
     #[error("initrd could not be loaded: {0}")]
     InitrdLoadFailure(arch::LoadImageError),
+    #[error("{0}")]
+    InvalidSerialParameters(arch::InvalidSerialParameters),
     #[error("kernel could not be loaded: {0}")]
     KernelLoadFailure(arch::LoadImageError),
     #[error("error loading Kernel from Elf image: {0}")]
     LoadElfKernel(kernel_loader::Error),
     #[error("failed to map arm pvtime memory: {0}")]
     MapPvtimeError(base::Error),
+    #[error("failed to set up the metrics page: {0}")]
+    MetricsPage(arch::metrics_page::Error),
+    #[error("PCI hotplug needs a PCIe root port to announce the device to the guest")]
+    NoPcieRootPort,
+    #[error("pmem region {0} is outside of the MMIO address space")]
+    PmemRegionOutOfRange(AddressRange),
+    #[error("guest RAM ends at {0:#x}, past the start of the pmem regions")]
+    PmemRegionRamOverlap(u64),
+    #[error("pmem region size {0:#x} is not a non-zero multiple of 2 MiB")]
+    PmemRegionSize(u64),
+    #[error("a PMU was requested but the hypervisor doesn't support it")]
+    PmuNotSupported,
     #[error("failed to protect vm: {0}")]
     ProtectVm(base::Error),
     #[error("pVM firmware could not be loaded: {0}")]
@@ -223,6 +329,8 @@ pub enum Error {
     RegisterPci(BusError),
     #[error("error registering virtual socket device: {0}")]
     RegisterVsock(arch::DeviceRegistrationError),
+    #[error("failed to reserve pmem region {0}: {1}")]
+    ReservePmemRegion(AddressRange, resources::Error),
     #[error("failed to set device attr: {0}")]
     SetDeviceAttr(base::Error),
     #[error("failed to set a hardware breakpoint: {0}")]
@@ -231,8 +339,8 @@ pub enum Error {
     SetReg(base::Error),
     #[error("failed to set up guest memory: {0}")]
     SetupGuestMemory(GuestMemoryError),
-    #[error("this function isn't supported")]
-    Unsupported,
+    #[error("failed to place a device at its fixed MMIO address: {0}")]
+    StaticMmio(arch::DeviceRegistrationError),
     #[error("failed to initialize VCPU: {0}")]
     VcpuInit(base::Error),
     #[error("error writing guest memory: {0}")]
@@ -245,17 +353,190 @@ pub enum Error {
 
 pub type Result<T> = std::result::Result<T, Error>;
 
-fn fdt_offset(mem_size: u64, has_bios: bool) -> u64 {
+// Returns the size of the guest RAM starting at `AARCH64_PHYS_MEM_START`, which excludes the
+// protected VM firmware region and the metrics page.
+fn ram_size(mem: &GuestMemory) -> u64 {
+    mem.regions()
+        .find(|region| region.guest_addr == GuestAddress(AARCH64_PHYS_MEM_START))
+        .map_or(0, |region| region.size as u64)
+}
+
+// Returns the end of the guest RAM in `regions` that the FDT and initrd may use: the end of RAM,
+// but no higher than `AARCH64_BOOT_DATA_LIMIT` unless `phys_base_anywhere` is set.
+fn boot_data_limit(regions: &[(GuestAddress, u64)], phys_base_anywhere: bool) -> u64 {
+    let ram_end = regions
+        .iter()
+        .filter(|(addr, _)| addr.offset() >= AARCH64_PHYS_MEM_START)
+        .map(|(addr, size)| addr.offset().saturating_add(*size))
+        .max()
+        .unwrap_or(AARCH64_PHYS_MEM_START);
+    if phys_base_anywhere {
+        ram_end
+    } else {
+        std::cmp::min(ram_end, AARCH64_BOOT_DATA_LIMIT)
+    }
+}
+
+// Returns the offset of the FDT from `AARCH64_PHYS_MEM_START`, near the top of the highest RAM in
+// `regions` below `limit`.
+fn fdt_offset(regions: &[(GuestAddress, u64)], has_bios: bool, limit: u64) -> Result<u64> {
     // TODO(rammuthiah) make kernel and BIOS startup use FDT from the same location. ARCVM startup
     // currently expects the kernel at 0x80080000 and the FDT at the end of RAM for unknown reasons.
     // Root cause and figure out how to fold these code paths together.
     if has_bios {
-        AARCH64_FDT_OFFSET_IN_BIOS_MODE
-    } else {
-        // Put fdt up near the top of memory
-        // TODO(sonnyrao): will have to handle this differently if there's
-        // > 4GB memory
-        mem_size - AARCH64_FDT_MAX_SIZE - 0x10000
+        return Ok(AARCH64_FDT_OFFSET_IN_BIOS_MODE);
+    }
+    regions
+        .iter()
+        .filter(|(addr, _)| addr.offset() >= AARCH64_PHYS_MEM_START)
+        .filter_map(|(addr, size)| {
+            let end = std::cmp::min(addr.offset().saturating_add(*size), limit);
+            end.checked_sub(AARCH64_FDT_MAX_SIZE + AARCH64_FDT_END_GAP)
+                .filter(|fdt_addr| *fdt_addr >= addr.offset())
+        })
+        .max()
+        .map(|fdt_addr| fdt_addr - AARCH64_PHYS_MEM_START)
+        .ok_or(Error::FdtNoRoom(limit))
+}
+
+// Returns the guest address of the FDT for `position`, where `image_end` is the end of the loaded
+// kernel or BIOS image and `limit` the end of the RAM the FDT may use.
+fn fdt_address(
+    position: FdtPosition,
+    regions: &[(GuestAddress, u64)],
+    has_bios: bool,
+    image_end: u64,
+    limit: u64,
+) -> Result<u64> {
+    Ok(match position {
+        FdtPosition::End => AARCH64_PHYS_MEM_START + fdt_offset(regions, has_bios, limit)?,
+        FdtPosition::AfterKernel => {
+            (image_end + (AARCH64_FDT_ALIGN - 1)) & !(AARCH64_FDT_ALIGN - 1)
+        }
+        FdtPosition::Address(addr) => addr,
+    })
+}
+
+// Returns the address of an initrd placed after `initrd_start` and the most bytes it may take
+// below `limit`.
+fn initrd_placement(initrd_start: u64, limit: u64) -> Result<(GuestAddress, u64)> {
+    let initrd_addr = (initrd_start + (AARCH64_INITRD_ALIGN - 1)) & !(AARCH64_INITRD_ALIGN - 1);
+    if initrd_addr >= limit {
+        return Err(Error::InitrdAboveLimit(initrd_addr, limit));
+    }
+    Ok((GuestAddress(initrd_addr), limit - initrd_addr))
+}
+
+// Checks that the FDT at `fdt_addr` is in RAM below `limit` and doesn't overlap the loaded images.
+fn check_fdt_placement(
+    fdt_addr: u64,
+    mem_size: u64,
+    limit: u64,
+    image: AddressRange,
+    initrd: Option<AddressRange>,
+) -> Result<()> {
+    if fdt_addr % 8 != 0 {
+        return Err(Error::FdtMisaligned(fdt_addr));
+    }
+    let fdt = AddressRange::from_start_and_size(fdt_addr, AARCH64_FDT_MAX_SIZE)
+        .ok_or(Error::FdtOutOfRam(fdt_addr))?;
+    let ram = AddressRange::from_start_and_size(AARCH64_PHYS_MEM_START, mem_size)
+        .ok_or(Error::FdtOutOfRam(fdt_addr))?;
+    if !ram.contains_range(fdt) {
+        return Err(Error::FdtOutOfRam(fdt_addr));
+    }
+    // `AddressRange::end` is inclusive.
+    if fdt.end >= limit {
+        return Err(Error::FdtAboveLimit(fdt_addr, limit));
+    }
+    if fdt.overlaps(image) {
+        return Err(Error::FdtOverlap(fdt_addr, "kernel"));
+    }
+    if initrd.map_or(false, |initrd| fdt.overlaps(initrd)) {
+        return Err(Error::FdtOverlap(fdt_addr, "initrd"));
+    }
+    Ok(())
+}
+
+/// Returns the size of the platform MMIO region needed to hold MMIO regions of `region_sizes`
+/// bytes, each placed at page granularity, but never less than `AARCH64_PLATFORM_MMIO_SIZE`.
+fn platform_mmio_size<I: IntoIterator<Item = u64>>(region_sizes: I) -> u64 {
+    let page_size = base::pagesize() as u64;
+    let needed = region_sizes.into_iter().fold(0u64, |total, size| {
+        total.saturating_add(size.saturating_add(page_size - 1) & !(page_size - 1))
+    });
+    std::cmp::max(needed, AARCH64_PLATFORM_MMIO_SIZE)
+}
+
+/// Expands `cluster_affinity`, keyed by index into `cpu_clusters`, into the affinity of each vcpu
+/// of those clusters, and adds the vcpus of the real-time clusters to `rt_cpus`. `vcpu_affinity`
+/// is returned as is if there is no cluster affinity.
+///
+/// The clusters must not overlap, so that each vcpu is in a single cluster of the FDT `cpu-map`,
+/// with the affinity of that cluster.
+fn expand_cluster_affinity(
+    cpu_clusters: &[Vec<usize>],
+    cluster_affinity: &BTreeMap<usize, ClusterAffinity>,
+    vcpu_count: usize,
+    vcpu_affinity: Option<VcpuAffinity>,
+    rt_cpus: &mut Vec<usize>,
+) -> Result<Option<VcpuAffinity>> {
+    if cluster_affinity.is_empty() {
+        return Ok(vcpu_affinity);
+    }
+    if vcpu_affinity.is_some() {
+        return Err(Error::ClusterAffinityConflict);
+    }
+    if let Some(&cluster) = cluster_affinity.keys().find(|&&c| c >= cpu_clusters.len()) {
+        return Err(Error::ClusterAffinityInvalidCluster(cluster));
+    }
+
+    let mut clusters = BTreeMap::new();
+    for (cluster, vcpus) in cpu_clusters.iter().enumerate() {
+        for &vcpu in vcpus {
+            if vcpu >= vcpu_count {
+                return Err(Error::ClusterAffinityVcpu(vcpu));
+            }
+            if clusters.insert(vcpu, cluster).is_some() {
+                return Err(Error::ClusterAffinityOverlap(vcpu));
+            }
+        }
+    }
+
+    let mut affinity = BTreeMap::new();
+    for (vcpu, cluster) in clusters {
+        let cluster_affinity = match cluster_affinity.get(&cluster) {
+            Some(cluster_affinity) => cluster_affinity,
+            None => continue,
+        };
+        if cluster_affinity.host_cpus.is_empty() {
+            return Err(Error::ClusterAffinityEmpty(cluster));
+        }
+        affinity.insert(vcpu, cluster_affinity.host_cpus.clone());
+        if cluster_affinity.rt && !rt_cpus.contains(&vcpu) {
+            rt_cpus.push(vcpu);
+        }
+    }
+    Ok(Some(VcpuAffinity::PerVcpu(affinity)))
+}
+
+/// Returns the interrupt `irq` of the optional device `device`, or `None` if it couldn't be set up
+/// and the device should run without an interrupt. In that case `device` is added to
+/// `degraded_devices`, unless `strict_irqs` is set and the error is returned instead.
+fn optional_device_irq(
+    irq: Result<u32>,
+    device: &str,
+    strict_irqs: bool,
+    degraded_devices: &mut Vec<String>,
+) -> Result<Option<u32>> {
+    match irq {
+        Ok(irq) => Ok(Some(irq)),
+        Err(e) if strict_irqs => Err(e),
+        Err(e) => {
+            warn!("{} will run without an interrupt: {}", device, e);
+            degraded_devices.push(device.to_string());
+            Ok(None)
+        }
     }
 }
 
@@ -272,6 +553,23 @@ impl arch::LinuxArch for AArch64 {
         let mut memory_regions =
             vec![(GuestAddress(AARCH64_PHYS_MEM_START), components.memory_size)];
 
+        // The metrics page sits below the RAM, among the platform devices.
+        if components.metrics_page {
+            memory_regions.insert(
+                0,
+                (GuestAddress(AARCH64_METRICS_PAGE_ADDR), METRICS_PAGE_SIZE),
+            );
+        }
+
+        // The pmem regions are mapped from their files separately, but RAM must end before them.
+        if !components.pmem_regions.is_empty() {
+            Self::pmem_region_layout(&components.pmem_regions)?;
+            let ram_end = AARCH64_PHYS_MEM_START.saturating_add(components.memory_size);
+            if ram_end > AARCH64_PMEM_BASE {
+                return Err(Error::PmemRegionRamOverlap(ram_end));
+            }
+        }
+
         // Allocate memory for the pVM firmware.
         if matches!(
             components.hv_cfg.protection_type,
@@ -286,10 +584,24 @@ impl arch::LinuxArch for AArch64 {
         Ok(memory_regions)
     }
 
+    fn guest_memory_access_policies(
+        components: &VmComponents,
+    ) -> Vec<(GuestAddress, MemoryAccessPolicy)> {
+        // The hypervisor loads the firmware of protected VMs into memory the host can't touch.
+        match components.hv_cfg.protection_type {
+            ProtectionType::Protected => vec![(
+                GuestAddress(AARCH64_PROTECTED_VM_FW_START),
+                MemoryAccessPolicy::Inaccessible,
+            )],
+            _ => Vec::new(),
+        }
+    }
+
     fn get_system_allocator_config<V: Vm>(vm: &V) -> SystemAllocatorConfig {
         Self::get_resource_allocator_config(
-            vm.get_memory().memory_size(),
+            ram_size(vm.get_memory()),
             vm.get_guest_phys_addr_bits(),
+            AARCH64_PLATFORM_MMIO_SIZE,
         )
     }
 
@@ -299,7 +611,7 @@ impl arch::LinuxArch for AArch64 {
         system_allocator: &mut SystemAllocator,
         serial_parameters: &BTreeMap<(SerialHardware, u8), SerialParameters>,
         serial_jail: Option<Minijail>,
-        (bat_type, bat_jail): (Option<BatteryType>, Option<Minijail>),
+        batteries: Vec<(BatteryType, Option<Minijail>)>,
         mut vm: V,
         ramoops_region: Option<arch::pstore::RamoopsRegion>,
         devs: Vec<(Box<dyn BusDeviceObj>, Option<Minijail>)>,
@@ -311,54 +623,139 @@ impl arch::LinuxArch for AArch64 {
         V: VmAArch64,
         Vcpu: VcpuAArch64,
     {
+        let mut timer = PhaseTimer::new();
+        arch::check_serial_parameters(serial_parameters, AARCH64_SERIAL_MAX_PORTS)
+            .map_err(Error::InvalidSerialParameters)?;
+
         let has_bios = matches!(components.vm_image, VmImage::Bios(_));
         let mem = vm.get_memory().clone();
 
         // separate out image loading from other setup to get a specific error for
         // image loading
-        let mut initrd = None;
-        let image_size = match components.vm_image {
+        let (image_addr, image_size, image_range, phys_base_anywhere) = match components.vm_image {
             VmImage::Bios(ref mut bios) => {
-                arch::load_image(&mem, bios, get_bios_addr(), AARCH64_BIOS_MAX_LEN)
-                    .map_err(Error::BiosLoadFailure)?
+                let bios_size = arch::load_image(&mem, bios, get_bios_addr(), AARCH64_BIOS_MAX_LEN)
+                    .map_err(Error::BiosLoadFailure)?;
+                (
+                    get_bios_addr(),
+                    bios_size,
+                    AddressRange::from_start_and_end(
+                        get_bios_addr().offset(),
+                        get_bios_addr().offset() + bios_size as u64 - 1,
+                    ),
+                    false,
+                )
             }
             VmImage::Kernel(ref mut kernel_image) => {
-                let kernel_end: u64;
-                let kernel_size: usize;
                 let elf_result = kernel_loader::load_elf64(&mem, get_kernel_addr(), kernel_image);
                 if elf_result == Err(kernel_loader::Error::InvalidElfMagicNumber) {
-                    kernel_size =
-                        arch::load_image(&mem, kernel_image, get_kernel_addr(), u64::max_value())
-                            .map_err(Error::KernelLoadFailure)?;
-                    kernel_end = get_kernel_addr().offset() + kernel_size as u64;
+                    let placement = match image::read_placement(kernel_image) {
+                        Ok(placement) => Some(placement),
+                        Err(e) => {
+                            warn!("loading the kernel without an Image header: {}", e);
+                            None
+                        }
+                    };
+                    let kernel_addr = GuestAddress(
+                        get_kernel_addr().offset() + placement.map_or(0, |p| p.text_offset),
+                    );
+                    let kernel_size = arch::load_image_with_progress(
+                        &mem,
+                        kernel_image,
+                        kernel_addr,
+                        u64::max_value(),
+                        "kernel",
+                    )
+                    .map_err(Error::KernelLoadFailure)?;
+                    // The kernel also needs the memory past the end of the file for its bss, so
+                    // nothing else is placed there.
+                    let kernel_end = kernel_addr.offset()
+                        + std::cmp::max(kernel_size as u64, placement.map_or(0, |p| p.image_size));
+                    (
+                        kernel_addr,
+                        kernel_size,
+                        AddressRange::from_start_and_end(kernel_addr.offset(), kernel_end - 1),
+                        placement.map_or(false, |p| p.phys_base_anywhere),
+                    )
                 } else {
                     let loaded_kernel = elf_result.map_err(Error::LoadElfKernel)?;
-                    kernel_size = loaded_kernel.size as usize;
-                    kernel_end = loaded_kernel.address_range.end;
+                    (
+                        get_kernel_addr(),
+                        loaded_kernel.size as usize,
+                        loaded_kernel.address_range,
+                        false,
+                    )
                 }
-                initrd = match components.initrd_image {
-                    Some(initrd_file) => {
-                        let mut initrd_file = initrd_file;
-                        let initrd_addr =
-                            (kernel_end + (AARCH64_INITRD_ALIGN - 1)) & !(AARCH64_INITRD_ALIGN - 1);
-                        let initrd_max_size =
-                            components.memory_size - (initrd_addr - AARCH64_PHYS_MEM_START);
-                        let initrd_addr = GuestAddress(initrd_addr);
-                        let initrd_size =
-                            arch::load_image(&mem, &mut initrd_file, initrd_addr, initrd_max_size)
-                                .map_err(Error::InitrdLoadFailure)?;
-                        Some((initrd_addr, initrd_size))
-                    }
-                    None => None,
+            }
+        };
+        // `AddressRange::end` is inclusive.
+        let image_end = image_range.end + 1;
+        timer.phase_done("load image");
+
+        let ram_regions: Vec<(GuestAddress, u64)> = mem
+            .regions()
+            .map(|region| (region.guest_addr, region.size as u64))
+            .collect();
+        let boot_data_end = boot_data_limit(&ram_regions, phys_base_anywhere);
+        let fdt_addr = fdt_address(
+            components.fdt_position,
+            &ram_regions,
+            has_bios,
+            image_end,
+            boot_data_end,
+        )?;
+
+        let initrd = match components.initrd_image {
+            Some(mut initrd_file) if !has_bios => {
+                // The initrd follows the FDT when the FDT follows the kernel.
+                let initrd_start = match components.fdt_position {
+                    FdtPosition::AfterKernel => fdt_addr + AARCH64_FDT_MAX_SIZE,
+                    _ => image_end,
                 };
-                kernel_size
+                let (initrd_addr, initrd_max_size) = initrd_placement(initrd_start, boot_data_end)?;
+                let initrd_size = arch::load_image_with_progress(
+                    &mem,
+                    &mut initrd_file,
+                    initrd_addr,
+                    initrd_max_size,
+                    "initrd",
+                )
+                .map_err(Error::InitrdLoadFailure)?;
+                Some((initrd_addr, initrd_size))
             }
+            _ => None,
         };
+        timer.phase_done("load initrd");
+
+        check_fdt_placement(
+            fdt_addr,
+            components.memory_size,
+            boot_data_end,
+            image_range,
+            initrd.and_then(|(addr, size)| {
+                AddressRange::from_start_and_size(addr.offset(), size as u64)
+            }),
+        )?;
 
-        let mut use_pmu = vm
+        let has_pmu = vm
             .get_hypervisor()
             .check_capability(HypervisorCap::ArmPmuV3);
+        // Some hosts advertise a PMU whose interrupts don't work, so it can be turned off. When it
+        // is explicitly requested, the VM doesn't start without it.
+        let pmu_required = components.pmu == Some(true);
+        let mut use_pmu = match components.pmu {
+            Some(true) if !has_pmu => return Err(Error::PmuNotSupported),
+            Some(pmu) => pmu,
+            None => has_pmu,
+        };
         let vcpu_count = components.vcpu_count;
+        let vcpu_affinity = expand_cluster_affinity(
+            &components.cpu_clusters,
+            &components.cluster_affinity,
+            vcpu_count,
+            components.vcpu_affinity.take(),
+            &mut components.rt_cpus,
+        )?;
         let mut has_pvtime = true;
         let mut vcpus = Vec::with_capacity(vcpu_count);
         for vcpu_id in 0..vcpu_count {
@@ -368,23 +765,29 @@ impl arch::LinuxArch for AArch64 {
                 .downcast::<Vcpu>()
                 .map_err(|_| Error::DowncastVcpu)?;
             Self::configure_vcpu_early(
-                vm.get_memory(),
                 &vcpu,
                 vcpu_id,
                 use_pmu,
-                has_bios,
+                image_addr,
                 image_size,
+                fdt_addr,
                 components.hv_cfg.protection_type,
             )?;
             has_pvtime &= vcpu.has_pvtime_support();
             vcpus.push(vcpu);
             vcpu_ids.push(vcpu_id);
         }
+        timer.phase_done("create vcpus");
 
         irq_chip.finalize().map_err(Error::FinalizeIrqChip)?;
+        timer.phase_done("finalize irqchip");
 
-        if has_pvtime {
+        let pvtime = if has_pvtime {
+            // The region is mapped a second time to read the stolen time of the vcpus.
+            let pvtime_shm = SharedMemory::new("pvtime", AARCH64_PVTIME_IPA_MAX_SIZE)
+                .map_err(Error::CreatePvtimeMemory)?;
             let pvtime_mem = MemoryMappingBuilder::new(AARCH64_PVTIME_IPA_MAX_SIZE as usize)
+                .from_shared_memory(&pvtime_shm)
                 .build()
                 .map_err(Error::BuildPvtimeError)?;
             vm.add_memory_region(
@@ -392,9 +795,18 @@ impl arch::LinuxArch for AArch64 {
                 Box::new(pvtime_mem),
                 false,
                 false,
+                MemCacheType::Cached,
             )
             .map_err(Error::MapPvtimeError)?;
-        }
+            let pvtime_view = MemoryMappingBuilder::new(AARCH64_PVTIME_IPA_MAX_SIZE as usize)
+                .from_shared_memory(&pvtime_shm)
+                .build()
+                .map_err(Error::BuildPvtimeError)?;
+            Some(PvtimeRegion::new(pvtime_view, vcpu_count))
+        } else {
+            None
+        };
+        timer.phase_done("map pvtime");
 
         match components.hv_cfg.protection_type {
             ProtectionType::Protected => {
@@ -409,24 +821,33 @@ impl arch::LinuxArch for AArch64 {
                 // Load pVM firmware ourself, as the VM is not really protected.
                 // `components.pvm_fw` is safe to unwrap because `protection_type` is
                 // `UnprotectedWithFirmware`.
-                arch::load_image(
+                arch::load_image_with_progress(
                     &mem,
                     &mut components.pvm_fw.unwrap(),
                     GuestAddress(AARCH64_PROTECTED_VM_FW_START),
                     AARCH64_PROTECTED_VM_FW_MAX_SIZE,
+                    "pvmfw",
                 )
                 .map_err(Error::PvmFwLoadFailure)?;
             }
             ProtectionType::Unprotected | ProtectionType::ProtectedWithoutFirmware => {}
         }
+        timer.phase_done("pvmfw");
 
         for (vcpu_id, vcpu) in vcpus.iter().enumerate() {
-            use_pmu &= vcpu.init_pmu(AARCH64_PMU_IRQ as u64 + 16).is_ok();
+            if use_pmu {
+                match vcpu.init_pmu(AARCH64_PMU_IRQ as u64 + 16) {
+                    Ok(()) => {}
+                    Err(e) if pmu_required => return Err(Error::InitPmu(e)),
+                    Err(_) => use_pmu = false,
+                }
+            }
             if has_pvtime {
                 vcpu.init_pvtime(AARCH64_PVTIME_IPA_START + (vcpu_id as u64 * AARCH64_PVTIME_SIZE))
                     .map_err(Error::InitPvtimeError)?;
             }
         }
+        timer.phase_done("init vcpu features");
 
         let mmio_bus = Arc::new(devices::Bus::new());
 
@@ -445,6 +866,66 @@ impl arch::LinuxArch for AArch64 {
             .into_iter()
             .map(|(dev, jail_orig)| (dev.into_pci_device().unwrap(), jail_orig))
             .collect();
+
+        let (platform_devices, _others): (Vec<_>, Vec<_>) = others
+            .into_iter()
+            .partition(|(dev, _)| dev.as_platform_device().is_some());
+
+        let platform_devices: Vec<_> = platform_devices
+            .into_iter()
+            .map(|(dev, jail_orig)| (*(dev.into_platform_device().unwrap()), jail_orig))
+            .collect();
+
+        // Grow the platform MMIO region before PCI BARs are allocated from the high MMIO region
+        // that follows it.
+        let plat_mmio_size = platform_mmio_size(
+            platform_devices
+                .iter()
+                .flat_map(|(dev, _)| dev.mmio_region_sizes()),
+        );
+        if plat_mmio_size > AARCH64_PLATFORM_MMIO_SIZE {
+            let config = Self::get_resource_allocator_config(
+                ram_size(vm.get_memory()),
+                vm.get_guest_phys_addr_bits(),
+                plat_mmio_size,
+            );
+            // `platform_mmio` is always set by `get_resource_allocator_config`.
+            system_allocator
+                .expand_platform_mmio(config.platform_mmio.unwrap())
+                .map_err(|e| Error::ExpandPlatformMmio(plat_mmio_size, e))?;
+        }
+
+        // Keep the pmem regions out of the pools PCI BARs are allocated from.
+        let pmem_ranges = Self::pmem_region_layout(&components.pmem_regions)?;
+        for &range in &pmem_ranges {
+            if !system_allocator
+                .mmio_pools()
+                .iter()
+                .any(|pool| pool.contains_range(range))
+            {
+                return Err(Error::PmemRegionOutOfRange(range));
+            }
+            if let Some(reserved) = system_allocator.reserved_region() {
+                if reserved.overlaps(range) {
+                    return Err(Error::ReservePmemRegion(
+                        range,
+                        resources::Error::RegionOverlap(reserved),
+                    ));
+                }
+            }
+            system_allocator
+                .reserve_mmio(range)
+                .map_err(|e| Error::ReservePmemRegion(range, e))?;
+        }
+        let reserved_pmem_ranges: Vec<AddressRange> = components
+            .pmem_regions
+            .iter()
+            .zip(pmem_ranges)
+            .filter(|(region, _)| region.reserved_memory)
+            .map(|(_, range)| range)
+            .collect();
+        timer.phase_done("reserve device memory");
+
         let (pci, pci_irqs, mut pid_debug_label_map, _amls) = arch::generate_pci_root(
             pci_devices,
             irq_chip.as_irq_chip_mut(),
@@ -456,17 +937,10 @@ impl arch::LinuxArch for AArch64 {
             None,
         )
         .map_err(Error::CreatePciRoot)?;
+        timer.phase_done("generate_pci_root");
 
         let pci_root = Arc::new(Mutex::new(pci));
         let pci_bus = Arc::new(Mutex::new(PciConfigMmio::new(pci_root.clone(), 8)));
-        let (platform_devices, _others): (Vec<_>, Vec<_>) = others
-            .into_iter()
-            .partition(|(dev, _)| dev.as_platform_device().is_some());
-
-        let platform_devices = platform_devices
-            .into_iter()
-            .map(|(dev, jail_orig)| (*(dev.into_platform_device().unwrap()), jail_orig))
-            .collect();
         let (platform_devices, mut platform_pid_debug_label_map) =
             arch::sys::unix::generate_platform_bus(
                 platform_devices,
@@ -477,22 +951,38 @@ impl arch::LinuxArch for AArch64 {
             .map_err(Error::CreatePlatformBus)?;
         pid_debug_label_map.append(&mut platform_pid_debug_label_map);
 
-        Self::add_arch_devs(
+        let mut degraded_devices = Vec::new();
+        let mut resume_notify_devices = Vec::new();
+        let mut static_mmio = StaticMmioMap::new();
+        let (vmwdt_control, vmwdt_device_tube) = Tube::pair().map_err(Error::CreateVmwdtTube)?;
+        let rtc_irq = Self::add_arch_devs(
             irq_chip.as_irq_chip_mut(),
             &mmio_bus,
+            &mut static_mmio,
             vcpu_count,
             _vm_evt_wrtube,
+            vmwdt_device_tube,
+            &components.boot_milestones,
+            components.strict_irqs,
+            &mut degraded_devices,
+            &mut resume_notify_devices,
         )?;
 
         let com_evt_1_3 = devices::IrqEdgeEvent::new().map_err(Error::CreateEvent)?;
         let com_evt_2_4 = devices::IrqEdgeEvent::new().map_err(Error::CreateEvent)?;
-        arch::add_serial_devices(
+        for (i, addr) in arch::SERIAL_ADDR.iter().enumerate() {
+            static_mmio
+                .record(&format!("serial {}", i + 1), *addr, 0x8)
+                .map_err(Error::StaticMmio)?;
+        }
+        let mut serial_ports = arch::add_serial_devices(
             components.hv_cfg.protection_type,
             &mmio_bus,
             com_evt_1_3.get_trigger(),
             com_evt_2_4.get_trigger(),
             serial_parameters,
-            serial_jail,
+            serial_jail.as_ref(),
+            &components.boot_milestones,
         )
         .map_err(Error::CreateSerialDevices)?;
 
@@ -501,13 +991,80 @@ impl arch::LinuxArch for AArch64 {
             queue_id: 0,
             device_name: Serial::debug_label(),
         };
-        irq_chip
-            .register_edge_irq_event(AARCH64_SERIAL_1_3_IRQ, &com_evt_1_3, source.clone())
-            .map_err(Error::RegisterIrqfd)?;
-        irq_chip
-            .register_edge_irq_event(AARCH64_SERIAL_2_4_IRQ, &com_evt_2_4, source)
-            .map_err(Error::RegisterIrqfd)?;
+        let serial_1_3_irq = optional_device_irq(
+            irq_chip
+                .register_edge_irq_event(AARCH64_SERIAL_1_3_IRQ, &com_evt_1_3, source.clone())
+                .map(|_| AARCH64_SERIAL_1_3_IRQ)
+                .map_err(Error::RegisterIrqfd),
+            "serial ports 1 and 3",
+            components.strict_irqs,
+            &mut degraded_devices,
+        )?;
+        let serial_2_4_irq = optional_device_irq(
+            irq_chip
+                .register_edge_irq_event(AARCH64_SERIAL_2_4_IRQ, &com_evt_2_4, source)
+                .map(|_| AARCH64_SERIAL_2_4_IRQ)
+                .map_err(Error::RegisterIrqfd),
+            "serial ports 2 and 4",
+            components.strict_irqs,
+            &mut degraded_devices,
+        )?;
+
+        let mut extra_serial_ports = Vec::new();
+        let mut extra_earlycon = None;
+        for (&(hardware, num), param) in serial_parameters {
+            if hardware != SerialHardware::Serial || num as usize <= arch::SERIAL_ADDR.len() {
+                continue;
+            }
+            let alloc = system_allocator.get_anon_alloc();
+            let addr = system_allocator
+                .allocate_mmio(
+                    AARCH64_SERIAL_SIZE,
+                    alloc,
+                    format!("serial {}", num),
+                    AllocOptions::new().align(AARCH64_SERIAL_SIZE),
+                )
+                .map_err(|e| Error::AllocateSerialMmio(num, e))?;
+            let com_evt = devices::IrqEdgeEvent::new().map_err(Error::CreateEvent)?;
+            let handles = arch::add_serial_device(
+                components.hv_cfg.protection_type,
+                &mmio_bus,
+                addr,
+                com_evt.get_trigger(),
+                param,
+                serial_jail.as_ref(),
+                &components.boot_milestones,
+            )
+            .map_err(Error::CreateSerialDevices)?;
+            serial_ports.insert(num, handles);
+            let source = IrqEventSource {
+                device_id: Serial::device_id(),
+                queue_id: 0,
+                device_name: Serial::debug_label(),
+            };
+            let irq = optional_device_irq(
+                system_allocator
+                    .allocate_irq()
+                    .ok_or(Error::AllocateIrq)
+                    .and_then(|irq| {
+                        irq_chip
+                            .register_edge_irq_event(irq, &com_evt, source)
+                            .map(|_| irq)
+                            .map_err(Error::RegisterIrqfd)
+                    }),
+                &format!("serial port {}", num),
+                components.strict_irqs,
+                &mut degraded_devices,
+            )?;
+            if param.earlycon {
+                extra_earlycon = Some(addr);
+            }
+            extra_serial_ports.push((addr, irq));
+        }
 
+        static_mmio
+            .record("pci config", AARCH64_PCI_CFG_BASE, AARCH64_PCI_CFG_SIZE)
+            .map_err(Error::StaticMmio)?;
         mmio_bus
             .insert(pci_bus, AARCH64_PCI_CFG_BASE, AARCH64_PCI_CFG_SIZE)
             .map_err(Error::RegisterPci)?;
@@ -515,6 +1072,12 @@ impl arch::LinuxArch for AArch64 {
         let mut cmdline = Self::get_base_linux_cmdline();
         get_serial_cmdline(&mut cmdline, serial_parameters, "mmio")
             .map_err(Error::GetSerialCmdline)?;
+        // `get_serial_cmdline` only knows the addresses of the first four ports.
+        if let Some(addr) = extra_earlycon {
+            cmdline
+                .insert("earlycon", &format!("uart8250,mmio,0x{:x}", addr))
+                .map_err(Error::Cmdline)?;
+        }
         for param in components.extra_kernel_params {
             cmdline.insert_str(&param).map_err(Error::Cmdline)?;
         }
@@ -543,30 +1106,50 @@ impl arch::LinuxArch for AArch64 {
             })
             .collect();
 
-        let (bat_control, bat_mmio_base_and_irq) = match bat_type {
-            Some(BatteryType::Goldfish) => {
-                let bat_irq = system_allocator.allocate_irq().ok_or(Error::AllocateIrq)?;
-
-                // a dummy AML buffer. Aarch64 crosvm doesn't use ACPI.
-                let mut amls = Vec::new();
-                let (control_tube, mmio_base) = arch::sys::unix::add_goldfish_battery(
-                    &mut amls,
-                    bat_jail,
-                    &mmio_bus,
-                    irq_chip.as_irq_chip_mut(),
-                    bat_irq,
-                    system_allocator,
-                )
-                .map_err(Error::CreateBatDevices)?;
-                (
-                    Some(BatControl {
+        // Each battery gets its own MMIO page and interrupt.
+        let mut bat_control = Vec::new();
+        let mut bat_mmio_bases_and_irqs = Vec::new();
+        for (index, (bat_type, bat_jail)) in batteries.into_iter().enumerate() {
+            match bat_type {
+                BatteryType::Goldfish => {
+                    let name = format!("goldfish battery {}", index);
+                    let bat_irq = optional_device_irq(
+                        system_allocator.allocate_irq().ok_or(Error::AllocateIrq),
+                        &name,
+                        components.strict_irqs,
+                        &mut degraded_devices,
+                    )?;
+
+                    // a dummy AML buffer. Aarch64 crosvm doesn't use ACPI.
+                    let mut amls = Vec::new();
+                    let (control_tube, mmio_base) = arch::sys::unix::add_goldfish_battery(
+                        &mut amls,
+                        bat_jail,
+                        &mmio_bus,
+                        irq_chip.as_irq_chip_mut(),
+                        bat_irq,
+                        system_allocator,
+                    )
+                    .map_err(Error::CreateBatDevices)?;
+                    static_mmio
+                        .record(&name, mmio_base, devices::bat::GOLDFISHBAT_MMIO_LEN)
+                        .map_err(Error::StaticMmio)?;
+                    bat_control.push(BatControl {
                         type_: BatteryType::Goldfish,
                         control_tube,
-                    }),
-                    Some((mmio_base, bat_irq)),
-                )
+                    });
+                    bat_mmio_bases_and_irqs.push((mmio_base, bat_irq));
+                }
             }
-            None => (None, None),
+        }
+
+        let metrics_page = if components.metrics_page {
+            Some(
+                MetricsPage::new(mem.clone(), GuestAddress(AARCH64_METRICS_PAGE_ADDR))
+                    .map_err(Error::MetricsPage)?,
+            )
+        } else {
+            None
         };
 
         let vmwdt_cfg = fdt::VmWdtConfig {
@@ -575,6 +1158,7 @@ impl arch::LinuxArch for AArch64 {
             clock_hz: VMWDT_DEFAULT_CLOCK_HZ,
             timeout_sec: VMWDT_DEFAULT_TIMEOUT_SEC,
         };
+        timer.phase_done("add devices");
 
         fdt::create_fdt(
             AARCH64_FDT_MAX_SIZE as usize,
@@ -585,44 +1169,70 @@ impl arch::LinuxArch for AArch64 {
             vcpu_count as u32,
             components.cpu_clusters,
             components.cpu_capacity,
-            fdt_offset(components.memory_size, has_bios),
+            &components.cpu_caches,
+            GuestAddress(fdt_addr),
             cmdline.as_str(),
             initrd,
+            &components.vm_uuid.to_string(),
             components.android_fstab,
             irq_chip.get_vgic_version() == DeviceKind::ArmVgicV3,
             use_pmu,
             psci_version,
             components.swiotlb,
-            bat_mmio_base_and_irq,
+            &reserved_pmem_ranges,
+            &bat_mmio_bases_and_irqs,
             vmwdt_cfg,
+            metrics_page.as_ref().map(|_| AARCH64_METRICS_PAGE_ADDR),
+            fdt::PlatformIrqs {
+                rtc: rtc_irq,
+                serial_1_3: serial_1_3_irq,
+                serial_2_4: serial_2_4_irq,
+            },
+            &extra_serial_ports,
+            components.device_tree_overlay,
         )
         .map_err(Error::CreateFdt)?;
+        timer.phase_done("create_fdt");
+        timer.finish("build_vm", &components.boot_milestones);
 
         let vcpu_init = vec![VcpuInitAArch64::default(); vcpu_count];
+        let (serial_console_buffers, serial_inputs, serial_output_files) =
+            SerialPortHandles::split(serial_ports);
 
         Ok(RunnableLinuxVm {
             vm,
             vcpu_count,
             vcpus: Some(vcpus),
             vcpu_init,
-            vcpu_affinity: components.vcpu_affinity,
+            vcpu_affinity,
             no_smt: components.no_smt,
             irq_chip: irq_chip.try_box_clone().map_err(Error::CloneIrqChip)?,
+            metrics_page,
+            pvtime,
+            ramoops_region,
             has_bios,
             io_bus,
             mmio_bus,
             pid_debug_label_map,
             suspend_evt,
             rt_cpus: components.rt_cpus,
+            serial_console_buffers,
+            serial_inputs,
+            serial_output_files,
+            static_mmio_map: static_mmio.regions(),
             delay_rt: components.delay_rt,
+            degraded_devices,
+            fdt_address: Some(GuestAddress(fdt_addr)),
             bat_control,
+            boot_milestones: components.boot_milestones,
             #[cfg(all(target_arch = "aarch64", feature = "gdb"))]
             gdb: components.gdb,
             pm: None,
-            resume_notify_devices: Vec::new(),
+            resume_notify_devices,
             root_config: pci_root,
             platform_devices,
             hotplug_bus: BTreeMap::new(),
+            vmwdt_control: Some(vmwdt_control),
         })
     }
 
@@ -630,26 +1240,96 @@ impl arch::LinuxArch for AArch64 {
         _vm: &V,
         _hypervisor: &dyn Hypervisor,
         _irq_chip: &mut dyn IrqChipAArch64,
-        _vcpu: &mut dyn VcpuAArch64,
+        vcpu: &mut dyn VcpuAArch64,
         _vcpu_init: VcpuInitAArch64,
-        _vcpu_id: usize,
+        vcpu_id: usize,
         _num_cpus: usize,
         _has_bios: bool,
-        _cpu_config: Option<CpuConfigAArch64>,
+        cpu_config: Option<CpuConfigAArch64>,
     ) -> std::result::Result<(), Self::Error> {
-        // AArch64 doesn't configure vcpus on the vcpu thread, so nothing to do here.
+        // AArch64 configures vcpus in `configure_vcpu_early`, only identification overrides are
+        // left for the vcpu thread.
+        if let Some(cpu_config) = cpu_config {
+            let overrides = [
+                (MIDR_EL1, "MIDR_EL1", cpu_config.midr),
+                (REVIDR_EL1, "REVIDR_EL1", cpu_config.revidr),
+            ];
+            for (reg, name, value) in overrides {
+                if let Some(value) = value {
+                    // Older KVM treats the ID registers as invariant and rejects the write, in
+                    // which case only reads exiting to `MsrHandlers` see the override.
+                    if let Err(e) = vcpu.set_one_reg(VcpuRegAArch64::System(reg), value.into()) {
+                        warn!(
+                            "vcpu {}: hypervisor rejected {} override {:#x}: {}",
+                            vcpu_id, name, value, e
+                        );
+                    }
+                }
+            }
+        }
         Ok(())
     }
 
+    /// Hotplugs `device` into the running VM.
+    ///
+    /// The guest finds new devices through the ECAM region already in the FDT, but only looks for
+    /// them when told to by the hotplug slot of a PCIe root port, so the VM must have been created
+    /// with one (see `pcie_root_port`). Devices without a fixed interrupt get a new SPI for INTx.
     fn register_pci_device<V: VmAArch64, Vcpu: VcpuAArch64>(
-        _linux: &mut RunnableLinuxVm<V, Vcpu>,
-        _device: Box<dyn PciDevice>,
-        _minijail: Option<Minijail>,
-        _resources: &mut SystemAllocator,
-        _tube: &mpsc::Sender<PciRootCommand>,
+        linux: &mut RunnableLinuxVm<V, Vcpu>,
+        mut device: Box<dyn PciDevice>,
+        minijail: Option<Minijail>,
+        resources: &mut SystemAllocator,
+        hp_control_tube: &mpsc::Sender<PciRootCommand>,
     ) -> std::result::Result<PciAddress, Self::Error> {
-        // hotplug function isn't verified on AArch64, so set it unsupported here.
-        Err(Error::Unsupported)
+        if linux.hotplug_bus.is_empty() {
+            return Err(Error::NoPcieRootPort);
+        }
+
+        if let PreferredIrq::Any = device.preferred_irq() {
+            let irq = resources.allocate_irq().ok_or(Error::AllocateIrq)?;
+            let intx_event = IrqLevelEvent::new().map_err(Error::CreateEvent)?;
+            linux
+                .irq_chip
+                .as_irq_chip_mut()
+                .register_level_irq_event(irq, &intx_event, IrqEventSource::from_device(&device))
+                .map_err(Error::RegisterIrqfd)?;
+            device.assign_irq(intx_event, PciInterruptPin::IntA, irq);
+        }
+
+        arch::configure_pci_device(linux, device, minijail, resources, hp_control_tube)
+            .map_err(Error::ConfigurePciDevice)
+    }
+
+    fn set_kernel_cmdline<V: VmAArch64, Vcpu: VcpuAArch64>(
+        linux: &RunnableLinuxVm<V, Vcpu>,
+        cmdline: &str,
+    ) -> std::result::Result<(), SetKernelCmdlineError> {
+        let mut new_cmdline = kernel_cmdline::Cmdline::new(base::pagesize());
+        new_cmdline
+            .insert_str(cmdline)
+            .map_err(|e| SetKernelCmdlineError::InvalidCmdline(e.to_string()))?;
+        // `Cmdline` only accepts printable ASCII, so there is no NUL in the middle.
+        let bootargs = CString::new(new_cmdline.as_str()).unwrap();
+
+        // The guest reads the command line from the /chosen node of the FDT `build_vm` wrote.
+        let mem = linux.vm.get_memory();
+        let fdt_addr = linux
+            .fdt_address
+            .ok_or_else(|| SetKernelCmdlineError::Failed("no FDT to update".to_string()))?;
+        let mut fdt = vec![0u8; AARCH64_FDT_MAX_SIZE as usize];
+        mem.read_exact_at_addr(&mut fdt, fdt_addr)
+            .map_err(|e| SetKernelCmdlineError::Failed(e.to_string()))?;
+        let fdt = arch::fdt::set_property(
+            &fdt,
+            "/chosen",
+            "bootargs",
+            bootargs.to_bytes_with_nul(),
+            AARCH64_FDT_MAX_SIZE as usize,
+        )
+        .map_err(|e| SetKernelCmdlineError::Failed(e.to_string()))?;
+        mem.write_all_at_addr(&fdt, fdt_addr)
+            .map_err(|e| SetKernelCmdlineError::Failed(e.to_string()))
     }
 }
 
@@ -726,6 +1406,24 @@ impl<T: VcpuAArch64> arch::GdbOps<T> for AArch64 {
 }
 
 impl AArch64 {
+    /// Returns where `regions` are placed in the guest physical address space, one after the
+    /// other from `AARCH64_PMEM_BASE`.
+    pub fn pmem_region_layout(regions: &[PmemRegion]) -> Result<Vec<AddressRange>> {
+        let mut next = AARCH64_PMEM_BASE;
+        regions
+            .iter()
+            .map(|region| {
+                if region.size == 0 || region.size % AARCH64_PMEM_ALIGN != 0 {
+                    return Err(Error::PmemRegionSize(region.size));
+                }
+                let range = AddressRange::from_start_and_size(next, region.size)
+                    .ok_or(Error::PmemRegionSize(region.size))?;
+                next = range.end.saturating_add(1);
+                Ok(range)
+            })
+            .collect()
+    }
+
     /// This returns a base part of the kernel command for this architecture
     fn get_base_linux_cmdline() -> kernel_cmdline::Cmdline {
         let mut cmdline = kernel_cmdline::Cmdline::new(base::pagesize());
@@ -739,14 +1437,16 @@ impl AArch64 {
     ///
     /// * `mem_size` - Size of guest memory (RAM) in bytes.
     /// * `guest_phys_addr_bits` - Size of guest physical addresses (IPA) in bits.
+    /// * `plat_mmio_size` - Size of the platform MMIO region, see `platform_mmio_size`.
     fn get_resource_allocator_config(
         mem_size: u64,
         guest_phys_addr_bits: u8,
+        plat_mmio_size: u64,
     ) -> SystemAllocatorConfig {
         let guest_phys_end = 1u64 << guest_phys_addr_bits;
         // The platform MMIO region is immediately past the end of RAM.
         let plat_mmio_base = AARCH64_PHYS_MEM_START + mem_size;
-        let plat_mmio_size = AARCH64_PLATFORM_MMIO_SIZE;
+        let plat_mmio_size = std::cmp::max(plat_mmio_size, AARCH64_PLATFORM_MMIO_SIZE);
         // The high MMIO region is the rest of the address space after the platform MMIO region.
         let high_mmio_base = plat_mmio_base + plat_mmio_size;
         let high_mmio_size = guest_phys_end
@@ -771,40 +1471,92 @@ impl AArch64 {
         }
     }
 
-    /// This adds any early platform devices for this architecture.
+    /// This adds any early platform devices for this architecture, returning the interrupt of the
+    /// RTC if it has one.
     ///
     /// # Arguments
     ///
     /// * `irq_chip` - The IRQ chip to add irqs to.
     /// * `bus` - The bus to add devices to.
+    /// * `static_mmio` - Where the addresses given to the devices are recorded
     /// * `vcpu_count` - The number of virtual CPUs for this guest VM
     /// * `vm_evt_wrtube` - The notification channel
+    /// * `vmwdt_control` - The tube the watchdog serves `VmwdtCommand`s on
+    /// * `boot_milestones` - Where the boot doorbell records boot completion
+    /// * `strict_irqs` - Fail instead of adding the RTC without an interrupt
+    /// * `degraded_devices` - Where devices added without an interrupt are recorded
+    /// * `resume_notify_devices` - Where devices to notify when the VM resumes are added
     fn add_arch_devs(
         irq_chip: &mut dyn IrqChip,
         bus: &Bus,
+        static_mmio: &mut StaticMmioMap,
         vcpu_count: usize,
         vm_evt_wrtube: &SendTube,
-    ) -> Result<()> {
+        vmwdt_control: Tube,
+        boot_milestones: &BootMilestones,
+        strict_irqs: bool,
+        degraded_devices: &mut Vec<String>,
+        resume_notify_devices: &mut Vec<Arc<Mutex<dyn BusResumeDevice>>>,
+    ) -> Result<Option<u32>> {
         let rtc_evt = devices::IrqEdgeEvent::new().map_err(Error::CreateEvent)?;
         let rtc = devices::pl030::Pl030::new(rtc_evt.try_clone().map_err(Error::CloneEvent)?);
-        irq_chip
-            .register_edge_irq_event(AARCH64_RTC_IRQ, &rtc_evt, IrqEventSource::from_device(&rtc))
-            .map_err(Error::RegisterIrqfd)?;
-
-        bus.insert(
-            Arc::new(Mutex::new(rtc)),
-            AARCH64_RTC_ADDR,
-            AARCH64_RTC_SIZE,
-        )
-        .expect("failed to add rtc device");
+        let rtc_irq = optional_device_irq(
+            irq_chip
+                .register_edge_irq_event(
+                    AARCH64_RTC_IRQ,
+                    &rtc_evt,
+                    IrqEventSource::from_device(&rtc),
+                )
+                .map(|_| AARCH64_RTC_IRQ)
+                .map_err(Error::RegisterIrqfd),
+            "rtc",
+            strict_irqs,
+            degraded_devices,
+        )?;
+
+        static_mmio
+            .insert(
+                bus,
+                "rtc",
+                Arc::new(Mutex::new(rtc)),
+                AARCH64_RTC_ADDR,
+                AARCH64_RTC_SIZE,
+            )
+            .map_err(Error::StaticMmio)?;
 
         let vm_wdt = Arc::new(Mutex::new(
-            devices::vmwdt::Vmwdt::new(vcpu_count, vm_evt_wrtube.try_clone().unwrap()).unwrap(),
+            devices::vmwdt::Vmwdt::new(
+                vcpu_count,
+                vm_evt_wrtube.try_clone().unwrap(),
+                Some(vmwdt_control),
+            )
+            .unwrap(),
         ));
-        bus.insert(vm_wdt, AARCH64_VMWDT_ADDR, AARCH64_VMWDT_SIZE)
-            .expect("failed to add vmwdt device");
+        static_mmio
+            .insert(
+                bus,
+                "vmwdt",
+                vm_wdt.clone(),
+                AARCH64_VMWDT_ADDR,
+                AARCH64_VMWDT_SIZE,
+            )
+            .map_err(Error::StaticMmio)?;
+        resume_notify_devices.push(vm_wdt);
+
+        let boot_doorbell = Arc::new(Mutex::new(devices::BootDoorbell::new(
+            boot_milestones.clone(),
+        )));
+        static_mmio
+            .insert(
+                bus,
+                "boot doorbell",
+                boot_doorbell,
+                AARCH64_BOOT_DOORBELL_ADDR,
+                AARCH64_BOOT_DOORBELL_SIZE,
+            )
+            .map_err(Error::StaticMmio)?;
 
-        Ok(())
+        Ok(rtc_irq)
     }
 
     /// Sets up `vcpu`.
@@ -816,17 +1568,19 @@ impl AArch64 {
     ///
     /// # Arguments
     ///
-    /// * `guest_mem` - The guest memory object.
     /// * `vcpu` - The vcpu to configure.
     /// * `vcpu_id` - The VM's index for `vcpu`.
     /// * `use_pmu` - Should `vcpu` be configured to use the Performance Monitor Unit.
+    /// * `image_addr` - The guest physical address the BIOS or kernel was loaded at.
+    /// * `fdt_addr` - The guest physical address of the FDT, which is passed to the kernel in X0
+    ///                and must be within reach of its early boot code.
     fn configure_vcpu_early(
-        guest_mem: &GuestMemory,
         vcpu: &dyn VcpuAArch64,
         vcpu_id: usize,
         use_pmu: bool,
-        has_bios: bool,
+        image_addr: GuestAddress,
         image_size: usize,
+        fdt_addr: u64,
         protection_type: ProtectionType,
     ) -> Result<()> {
         let mut features = vec![VcpuFeature::PsciV0_2];
@@ -846,12 +1600,6 @@ impl AArch64 {
 
         // Other cpus are powered off initially
         if vcpu_id == 0 {
-            let image_addr = if has_bios {
-                get_bios_addr()
-            } else {
-                get_kernel_addr()
-            };
-
             let entry_addr = match protection_type {
                 ProtectionType::Protected => None, // Hypervisor controls the entry point
                 ProtectionType::UnprotectedWithFirmware => Some(AARCH64_PROTECTED_VM_FW_START),
@@ -867,8 +1615,6 @@ impl AArch64 {
             }
 
             /* X0 -- fdt address */
-            let mem_size = guest_mem.memory_size();
-            let fdt_addr = (AARCH64_PHYS_MEM_START + fdt_offset(mem_size, has_bios)) as u64;
             vcpu.set_one_reg(VcpuRegAArch64::X(0), fdt_addr)
                 .map_err(Error::SetReg)?;
 
@@ -890,15 +1636,39 @@ impl AArch64 {
     }
 }
 
-pub struct MsrHandlers;
+/// Handlers for guest system register accesses that exit to userspace.
+///
+/// Only fixed values overriding reads (see `add_sysreg_override`) are supported.
+pub struct MsrHandlers {
+    overrides: BTreeMap<u32, u64>,
+}
 
 impl MsrHandlers {
     pub fn new() -> Self {
-        Self {}
+        Self {
+            overrides: BTreeMap::new(),
+        }
     }
 
-    pub fn read(&self, _index: u32) -> Option<u64> {
-        None
+    /// Registers the handlers for the identification registers overridden by `cpu_config`.
+    pub fn from_cpu_config(cpu_config: &CpuConfigAArch64) -> Self {
+        let mut handlers = Self::new();
+        if let Some(midr) = cpu_config.midr {
+            handlers.add_sysreg_override(MIDR_EL1, midr.into());
+        }
+        if let Some(revidr) = cpu_config.revidr {
+            handlers.add_sysreg_override(REVIDR_EL1, revidr.into());
+        }
+        handlers
+    }
+
+    /// Makes guest reads of the system register `reg` return `value`.
+    pub fn add_sysreg_override(&mut self, reg: u16, value: u64) {
+        self.overrides.insert(reg.into(), value);
+    }
+
+    pub fn read(&self, index: u32) -> Option<u64> {
+        self.overrides.get(&index).copied()
     }
 
     pub fn write(&self, _index: u32, _data: u64) -> Option<()> {
@@ -914,3 +1684,1086 @@ impl MsrHandlers {
         Ok(())
     }
 }
+
+impl Default for MsrHandlers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::Seek;
+    use std::io::SeekFrom;
+    use std::io::Write;
+
+    use arch::fdt::parse_nodes;
+    use arch::LinuxArch;
+    use base::RecvTube;
+    use base::Tube;
+    use devices::serial_device::SerialType;
+    use devices::HostHotPlugKey;
+    use devices::HotPlugBus;
+    use devices::PciClassCode;
+    use devices::StubPciDevice;
+    use devices::StubPciParameters;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::fake::FakeIrqChip;
+    use crate::fake::FakeIrqRegistration;
+    use crate::fake::FakeVcpu;
+    use crate::fake::FakeVm;
+
+    const TEST_MEMORY_SIZES: [u64; 3] = [0x400_0000, 0x1000_0000, 0x4000_0000];
+
+    fn test_image(size: usize) -> File {
+        let mut file = tempfile::tempfile().unwrap();
+        // Not an ELF file, so the kernel is loaded as a raw image.
+        file.write_all(&vec![0x5a; size]).unwrap();
+        file
+    }
+
+    /// Returns a 64KiB arm64 Image whose header has `text_offset`, `image_size` and `magic`.
+    fn test_kernel_image(text_offset: u64, image_size: u64, magic: u32) -> File {
+        let mut header = [0u8; 64];
+        header[8..16].copy_from_slice(&text_offset.to_le_bytes());
+        header[16..24].copy_from_slice(&image_size.to_le_bytes());
+        header[56..60].copy_from_slice(&magic.to_le_bytes());
+        let mut file = test_image(0x10000);
+        file.seek(SeekFrom::Start(0)).unwrap();
+        file.write_all(&header).unwrap();
+        file
+    }
+
+    struct TestVm {
+        linux: RunnableLinuxVm<FakeVm, FakeVcpu>,
+        irq_chip: FakeIrqChip,
+        _vm_evt_rdtube: RecvTube,
+    }
+
+    /// Runs `build_vm` for a kernel boot using the fake hypervisor.
+    fn build_test_vm(memory_size: u64, protection_type: ProtectionType) -> TestVm {
+        try_build_test_vm(
+            test_components(memory_size, protection_type),
+            FakeIrqChip::default(),
+        )
+        .expect("build_vm failed")
+    }
+
+    fn test_components(memory_size: u64, protection_type: ProtectionType) -> VmComponents {
+        VmComponents {
+            acpi_sdts: Vec::new(),
+            android_fstab: None,
+            boot_milestones: BootMilestones::new(),
+            cluster_affinity: BTreeMap::new(),
+            cpu_caches: Vec::new(),
+            cpu_capacity: BTreeMap::new(),
+            cpu_clusters: Vec::new(),
+            delay_rt: false,
+            device_tree_overlay: None,
+            dmi_path: None,
+            extra_kernel_params: Vec::new(),
+            fdt_position: FdtPosition::End,
+            #[cfg(feature = "gdb")]
+            gdb: None,
+            host_cpu_topology: false,
+            hugepages: false,
+            hv_cfg: hypervisor::Config {
+                protection_type,
+                ..Default::default()
+            },
+            initrd_image: None,
+            itmt: false,
+            memory_size,
+            metrics_page: false,
+            no_i8042: false,
+            no_rtc: false,
+            no_smt: false,
+            pflash_block_size: 0,
+            pflash_image: None,
+            pmem_regions: Vec::new(),
+            pmu: None,
+            pstore: None,
+            pvm_fw: match protection_type {
+                ProtectionType::UnprotectedWithFirmware => Some(test_image(0x1000)),
+                _ => None,
+            },
+            rt_cpus: Vec::new(),
+            strict_irqs: false,
+            swiotlb: None,
+            vcpu_affinity: None,
+            vcpu_count: 2,
+            vm_image: VmImage::Kernel(test_image(0x10000)),
+            vm_uuid: Uuid::nil(),
+        }
+    }
+
+    fn try_build_test_vm(components: VmComponents, irq_chip: FakeIrqChip) -> Result<TestVm> {
+        try_build_test_vm_with_serial(components, irq_chip, 4)
+    }
+
+    /// Like `try_build_test_vm` with `serial_ports` sink serial ports.
+    fn try_build_test_vm_with_serial(
+        components: VmComponents,
+        irq_chip: FakeIrqChip,
+        serial_ports: u8,
+    ) -> Result<TestVm> {
+        try_build_test_vm_with_devices(components, irq_chip, serial_ports, Vec::new())
+    }
+
+    /// Like `try_build_test_vm` with `serial_ports` sink serial ports and `batteries`.
+    fn try_build_test_vm_with_devices(
+        components: VmComponents,
+        mut irq_chip: FakeIrqChip,
+        serial_ports: u8,
+        batteries: Vec<(BatteryType, Option<Minijail>)>,
+    ) -> Result<TestVm> {
+        let mem = GuestMemory::new_with_access_policies(
+            &AArch64::guest_memory_layout(&components).unwrap(),
+            Vec::new(),
+            &AArch64::guest_memory_access_policies(&components),
+        )
+        .unwrap();
+        let vm = FakeVm::new(mem);
+        let mut system_allocator =
+            SystemAllocator::new(AArch64::get_system_allocator_config(&vm), None, &[]).unwrap();
+        let serial_parameters = (1..=serial_ports)
+            .map(|num| {
+                (
+                    (SerialHardware::Serial, num),
+                    SerialParameters {
+                        type_: SerialType::Sink,
+                        hardware: SerialHardware::Serial,
+                        num,
+                        ..Default::default()
+                    },
+                )
+            })
+            .collect();
+        let (vm_evt_wrtube, vm_evt_rdtube) = Tube::directional_pair().unwrap();
+
+        let linux = AArch64::build_vm::<FakeVm, FakeVcpu>(
+            components,
+            &vm_evt_wrtube,
+            &mut system_allocator,
+            &serial_parameters,
+            None,
+            batteries,
+            vm,
+            None,
+            Vec::new(),
+            &mut irq_chip,
+            &mut Vec::new(),
+            None,
+        )?;
+
+        Ok(TestVm {
+            linux,
+            irq_chip,
+            _vm_evt_rdtube: vm_evt_rdtube,
+        })
+    }
+
+    #[test]
+    fn build_vm_registers_serial_irqs() {
+        let test_vm = build_test_vm(TEST_MEMORY_SIZES[0], ProtectionType::Unprotected);
+        let serial = |irq| FakeIrqRegistration {
+            irq,
+            device_name: Serial::debug_label(),
+        };
+        assert!(test_vm
+            .irq_chip
+            .edge_irqs
+            .contains(&serial(AARCH64_SERIAL_1_3_IRQ)));
+        assert!(test_vm
+            .irq_chip
+            .edge_irqs
+            .contains(&serial(AARCH64_SERIAL_2_4_IRQ)));
+        assert!(test_vm
+            .irq_chip
+            .edge_irqs
+            .iter()
+            .any(|r| r.irq == AARCH64_RTC_IRQ));
+    }
+
+    #[test]
+    fn build_vm_inserts_pci_cfg() {
+        let test_vm = build_test_vm(TEST_MEMORY_SIZES[0], ProtectionType::Unprotected);
+        let mmio_bus = &test_vm.linux.mmio_bus;
+        let mut data = [0u8; 4];
+        assert!(mmio_bus.read(AARCH64_PCI_CFG_BASE, &mut data));
+        assert!(mmio_bus.read(AARCH64_PCI_CFG_BASE + AARCH64_PCI_CFG_SIZE - 4, &mut data));
+        assert!(!mmio_bus.read(AARCH64_PCI_CFG_BASE + AARCH64_PCI_CFG_SIZE, &mut data));
+    }
+
+    #[test]
+    fn build_vm_maps_pvtime() {
+        let test_vm = build_test_vm(TEST_MEMORY_SIZES[0], ProtectionType::Unprotected);
+        let regions = &test_vm.linux.vm.memory_regions;
+        assert_eq!(regions.len(), 1);
+        assert_eq!(
+            regions[0].guest_addr,
+            GuestAddress(AARCH64_PVTIME_IPA_START)
+        );
+        assert_eq!(regions[0].size, AARCH64_PVTIME_IPA_MAX_SIZE as usize);
+        assert!(!regions[0].read_only);
+        assert_eq!(regions[0].cache, MemCacheType::Cached);
+
+        let vcpus = test_vm.linux.vcpus.as_ref().unwrap();
+        let pvtime = test_vm.linux.pvtime.as_ref().unwrap();
+        for (vcpu_id, vcpu) in vcpus.iter().enumerate() {
+            assert_eq!(
+                vcpu.pvtime_ipa(),
+                Some(AARCH64_PVTIME_IPA_START + vcpu_id as u64 * AARCH64_PVTIME_SIZE)
+            );
+            assert_eq!(pvtime.stolen_time(vcpu_id).unwrap(), 0);
+        }
+        assert!(pvtime.stolen_time(vcpus.len()).is_err());
+    }
+
+    #[test]
+    fn pstore_persists_in_file() {
+        const PSTORE_SIZE: u32 = 0x2000;
+        let dir = tempfile::tempdir().unwrap();
+        let pstore = arch::Pstore {
+            path: dir.path().join("pstore"),
+            size: PSTORE_SIZE,
+        };
+        // Records from a previous boot.
+        std::fs::write(&pstore.path, b"stale").unwrap();
+
+        let components = test_components(TEST_MEMORY_SIZES[0], ProtectionType::Unprotected);
+        let mem = GuestMemory::new(&AArch64::guest_memory_layout(&components).unwrap()).unwrap();
+        let mut vm = FakeVm::new(mem);
+        let range = AddressRange::from_start_and_size(0x1_0000_0000, PSTORE_SIZE as u64).unwrap();
+        let ramoops_region = arch::pstore::create_memory_region(&mut vm, range, &pstore).unwrap();
+
+        let region = &vm.memory_regions[ramoops_region.slot as usize];
+        assert_eq!(region.guest_addr, GuestAddress(0x1_0000_0000));
+        assert_eq!(region.size, PSTORE_SIZE as usize);
+        // Safe because the mapping is alive and `PSTORE_SIZE` bytes long, and nothing else
+        // accesses it while the slice exists.
+        let guest_view = unsafe {
+            std::slice::from_raw_parts_mut(region.mapping.as_ptr(), PSTORE_SIZE as usize)
+        };
+        assert_eq!(&guest_view[..5], b"stale");
+        for (i, byte) in guest_view.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        arch::pstore::sync(&mut vm, &ramoops_region).unwrap();
+        drop(vm);
+
+        let contents = std::fs::read(&pstore.path).unwrap();
+        assert_eq!(contents.len(), PSTORE_SIZE as usize);
+        assert!(contents.iter().enumerate().all(|(i, &b)| b == i as u8));
+    }
+
+    #[test]
+    fn build_vm_extra_serial_ports() {
+        let test_vm = try_build_test_vm_with_serial(
+            test_components(TEST_MEMORY_SIZES[0], ProtectionType::Unprotected),
+            FakeIrqChip::default(),
+            6,
+        )
+        .expect("build_vm failed");
+        let serial_irqs: Vec<u32> = test_vm
+            .irq_chip
+            .edge_irqs
+            .iter()
+            .filter(|r| r.device_name == Serial::debug_label())
+            .map(|r| r.irq)
+            .collect();
+        assert_eq!(serial_irqs.len(), 4);
+        let extra_irqs = &serial_irqs[2..];
+        assert_ne!(extra_irqs[0], extra_irqs[1]);
+        for irq in extra_irqs {
+            assert!(![
+                AARCH64_SERIAL_1_3_IRQ,
+                AARCH64_SERIAL_2_4_IRQ,
+                AARCH64_RTC_IRQ
+            ]
+            .contains(irq));
+        }
+
+        // Nothing else allocates from the low MMIO pool in this VM.
+        let mmio_bus = &test_vm.linux.mmio_bus;
+        let mut data = [0u8; 1];
+        assert!(mmio_bus.read(AARCH64_MMIO_BASE, &mut data));
+        assert!(mmio_bus.read(AARCH64_MMIO_BASE + AARCH64_SERIAL_SIZE, &mut data));
+        assert!(!mmio_bus.read(AARCH64_MMIO_BASE + 2 * AARCH64_SERIAL_SIZE, &mut data));
+    }
+
+    #[test]
+    fn build_vm_two_batteries() {
+        let test_vm = try_build_test_vm_with_devices(
+            test_components(TEST_MEMORY_SIZES[0], ProtectionType::Unprotected),
+            FakeIrqChip::default(),
+            4,
+            vec![(BatteryType::Goldfish, None), (BatteryType::Goldfish, None)],
+        )
+        .expect("build_vm failed");
+        assert_eq!(test_vm.linux.bat_control.len(), 2);
+
+        let bat_irqs: Vec<u32> = test_vm
+            .irq_chip
+            .level_irqs
+            .iter()
+            .filter(|r| r.device_name == "GoldfishBattery")
+            .map(|r| r.irq)
+            .collect();
+        assert_eq!(bat_irqs.len(), 2);
+        assert_ne!(bat_irqs[0], bat_irqs[1]);
+        let bat_mmio: Vec<u64> = ["goldfish battery 0", "goldfish battery 1"]
+            .iter()
+            .map(|name| {
+                test_vm
+                    .linux
+                    .static_mmio_map
+                    .iter()
+                    .find(|region| region.name == *name)
+                    .unwrap()
+                    .range
+                    .start
+            })
+            .collect();
+        assert_ne!(bat_mmio[0], bat_mmio[1]);
+
+        let mem = test_vm.linux.vm.get_memory();
+        let fdt_addr = test_vm.linux.fdt_address.unwrap();
+        let mut fdt = vec![0u8; AARCH64_FDT_MAX_SIZE as usize];
+        mem.read_exact_at_addr(&mut fdt, fdt_addr).unwrap();
+        let nodes = parse_nodes(&fdt).unwrap();
+        for (mmio_base, irq) in bat_mmio.into_iter().zip(bat_irqs) {
+            let path = format!("/goldfish_battery@{:x}", mmio_base);
+            let node = nodes.iter().find(|n| n.path == path).unwrap();
+            assert_eq!(
+                node.property_cells("interrupts").unwrap(),
+                Some(vec![0, irq, 4])
+            );
+        }
+    }
+
+    #[test]
+    fn build_vm_records_phases() {
+        let test_vm = build_test_vm(TEST_MEMORY_SIZES[0], ProtectionType::Unprotected);
+        let phases = test_vm.linux.boot_milestones.times().build_vm_phases;
+        let names: Vec<&str> = phases.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "load image",
+                "load initrd",
+                "create vcpus",
+                "finalize irqchip",
+                "map pvtime",
+                "pvmfw",
+                "init vcpu features",
+                "reserve device memory",
+                "generate_pci_root",
+                "add devices",
+                "create_fdt",
+            ]
+        );
+    }
+
+    #[test]
+    fn build_vm_places_fdt() {
+        let protection_types = [
+            ProtectionType::Unprotected,
+            ProtectionType::Protected,
+            ProtectionType::UnprotectedWithFirmware,
+        ];
+        for memory_size in TEST_MEMORY_SIZES {
+            for protection_type in protection_types {
+                let test_vm = build_test_vm(memory_size, protection_type);
+                let regions = [(GuestAddress(AARCH64_PHYS_MEM_START), memory_size)];
+                let fdt_addr = AARCH64_PHYS_MEM_START
+                    + fdt_offset(&regions, false, AARCH64_BOOT_DATA_LIMIT).unwrap();
+                assert!(fdt_addr + AARCH64_FDT_MAX_SIZE <= AARCH64_PHYS_MEM_START + memory_size);
+
+                let magic: u32 = test_vm
+                    .linux
+                    .vm
+                    .get_memory()
+                    .read_obj_from_addr(GuestAddress(fdt_addr))
+                    .unwrap();
+                assert_eq!(
+                    u32::from_be(magic),
+                    0xd00dfeed,
+                    "no fdt for {:#x} bytes of {:?} memory",
+                    memory_size,
+                    protection_type
+                );
+
+                let vcpus = test_vm.linux.vcpus.as_ref().unwrap();
+                assert_eq!(vcpus[0].reg(VcpuRegAArch64::X(0)), Some(fdt_addr));
+                let entry = match protection_type {
+                    ProtectionType::Protected => None,
+                    ProtectionType::UnprotectedWithFirmware => Some(AARCH64_PROTECTED_VM_FW_START),
+                    _ => Some(get_kernel_addr().offset()),
+                };
+                assert_eq!(vcpus[0].reg(VcpuRegAArch64::Pc), entry);
+                // Secondary cpus are powered off and not set up to boot.
+                assert!(vcpus[1].features().contains(&VcpuFeature::PowerOff));
+                assert_eq!(vcpus[1].reg(VcpuRegAArch64::X(0)), None);
+
+                assert_eq!(
+                    test_vm.linux.vm.protected_vm_firmware,
+                    match protection_type {
+                        ProtectionType::Protected => Some((
+                            GuestAddress(AARCH64_PROTECTED_VM_FW_START),
+                            AARCH64_PROTECTED_VM_FW_MAX_SIZE
+                        )),
+                        _ => None,
+                    }
+                );
+
+                // Only the hypervisor may access the firmware of a protected VM.
+                let fw_read = test_vm
+                    .linux
+                    .vm
+                    .get_memory()
+                    .read_obj_from_addr::<u32>(GuestAddress(AARCH64_PROTECTED_VM_FW_START));
+                match protection_type {
+                    ProtectionType::Protected => assert!(matches!(
+                        fw_read,
+                        Err(GuestMemoryError::ProtectedRegionAccess(_))
+                    )),
+                    ProtectionType::UnprotectedWithFirmware => assert!(fw_read.is_ok()),
+                    _ => assert!(fw_read.is_err()),
+                }
+            }
+        }
+    }
+
+    fn assert_fdt_at(test_vm: &TestVm, fdt_addr: u64) {
+        let magic: u32 = test_vm
+            .linux
+            .vm
+            .get_memory()
+            .read_obj_from_addr(GuestAddress(fdt_addr))
+            .unwrap();
+        assert_eq!(u32::from_be(magic), 0xd00dfeed);
+        assert_eq!(test_vm.linux.fdt_address, Some(GuestAddress(fdt_addr)));
+        let vcpus = test_vm.linux.vcpus.as_ref().unwrap();
+        assert_eq!(vcpus[0].reg(VcpuRegAArch64::X(0)), Some(fdt_addr));
+    }
+
+    #[test]
+    fn build_vm_pmu() {
+        // The fake hypervisor doesn't support a PMU.
+        let mut components = test_components(TEST_MEMORY_SIZES[0], ProtectionType::Unprotected);
+        components.pmu = Some(true);
+        assert!(matches!(
+            try_build_test_vm(components, FakeIrqChip::default()),
+            Err(Error::PmuNotSupported)
+        ));
+
+        for pmu in [None, Some(false)] {
+            let mut components = test_components(TEST_MEMORY_SIZES[0], ProtectionType::Unprotected);
+            components.pmu = pmu;
+            let test_vm = try_build_test_vm(components, FakeIrqChip::default()).unwrap();
+            for vcpu in test_vm.linux.vcpus.as_ref().unwrap() {
+                assert!(!vcpu.features().contains(&VcpuFeature::PmuV3));
+            }
+        }
+    }
+
+    #[test]
+    fn build_vm_places_fdt_after_kernel() {
+        let mut components = test_components(TEST_MEMORY_SIZES[0], ProtectionType::Unprotected);
+        components.fdt_position = FdtPosition::AfterKernel;
+        let test_vm = try_build_test_vm(components, FakeIrqChip::default()).unwrap();
+        // The 64KiB kernel is followed by the next 2MiB aligned address.
+        assert_fdt_at(&test_vm, get_kernel_addr().offset() + AARCH64_FDT_ALIGN);
+    }
+
+    #[test]
+    fn build_vm_places_fdt_after_kernel_with_initrd() {
+        let mut components = test_components(TEST_MEMORY_SIZES[0], ProtectionType::Unprotected);
+        components.fdt_position = FdtPosition::AfterKernel;
+        components.initrd_image = Some(test_image(0x1000));
+        let test_vm = try_build_test_vm(components, FakeIrqChip::default()).unwrap();
+        let fdt_addr = get_kernel_addr().offset() + AARCH64_FDT_ALIGN;
+        assert_fdt_at(&test_vm, fdt_addr);
+
+        // The initrd is moved past the FDT.
+        let initrd_addr = AARCH64_PHYS_MEM_START + AARCH64_INITRD_ALIGN;
+        assert!(initrd_addr >= fdt_addr + AARCH64_FDT_MAX_SIZE);
+        let initrd: [u8; 4] = test_vm
+            .linux
+            .vm
+            .get_memory()
+            .read_obj_from_addr(GuestAddress(initrd_addr))
+            .unwrap();
+        assert_eq!(initrd, [0x5a; 4]);
+    }
+
+    #[test]
+    fn build_vm_loads_kernel_at_text_offset() {
+        let mut components = test_components(TEST_MEMORY_SIZES[0], ProtectionType::Unprotected);
+        components.fdt_position = FdtPosition::AfterKernel;
+        components.vm_image = VmImage::Kernel(test_kernel_image(0x80000, 0x300000, 0x644d5241));
+        let test_vm = try_build_test_vm(components, FakeIrqChip::default()).unwrap();
+
+        let kernel_addr = get_kernel_addr().offset() + 0x80000;
+        let vcpus = test_vm.linux.vcpus.as_ref().unwrap();
+        assert_eq!(vcpus[0].reg(VcpuRegAArch64::Pc), Some(kernel_addr));
+        let magic: u32 = test_vm
+            .linux
+            .vm
+            .get_memory()
+            .read_obj_from_addr(GuestAddress(kernel_addr + 56))
+            .unwrap();
+        assert_eq!(magic, 0x644d5241);
+        // The FDT follows the image_size bytes of the kernel rather than the 64KiB file.
+        assert_fdt_at(&test_vm, get_kernel_addr().offset() + 2 * AARCH64_FDT_ALIGN);
+    }
+
+    #[test]
+    fn build_vm_ignores_image_header_with_bad_magic() {
+        let mut components = test_components(TEST_MEMORY_SIZES[0], ProtectionType::Unprotected);
+        components.fdt_position = FdtPosition::AfterKernel;
+        components.vm_image = VmImage::Kernel(test_kernel_image(0x80000, 0x300000, 0x5a5a5a5a));
+        let test_vm = try_build_test_vm(components, FakeIrqChip::default()).unwrap();
+
+        let vcpus = test_vm.linux.vcpus.as_ref().unwrap();
+        assert_eq!(
+            vcpus[0].reg(VcpuRegAArch64::Pc),
+            Some(get_kernel_addr().offset())
+        );
+        assert_fdt_at(&test_vm, get_kernel_addr().offset() + AARCH64_FDT_ALIGN);
+    }
+
+    #[test]
+    fn build_vm_reports_static_mmio_map() {
+        let components = test_components(TEST_MEMORY_SIZES[0], ProtectionType::Unprotected);
+        let test_vm = try_build_test_vm(components, FakeIrqChip::default()).unwrap();
+
+        let names: Vec<&str> = test_vm
+            .linux
+            .static_mmio_map
+            .iter()
+            .map(|region| region.name.as_str())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "serial 4",
+                "serial 2",
+                "serial 3",
+                "serial 1",
+                "rtc",
+                "vmwdt",
+                "boot doorbell",
+                "pci config",
+            ]
+        );
+        let rtc = &test_vm.linux.static_mmio_map[4];
+        assert_eq!(rtc.range.start, AARCH64_RTC_ADDR);
+        assert_eq!(rtc.range.len(), Some(AARCH64_RTC_SIZE));
+    }
+
+    #[test]
+    fn build_vm_places_fdt_at_address() {
+        let memory_size = TEST_MEMORY_SIZES[0];
+        let fdt_addr = AARCH64_PHYS_MEM_START + 0x200_0000;
+        let mut components = test_components(memory_size, ProtectionType::Unprotected);
+        components.fdt_position = FdtPosition::Address(fdt_addr);
+        let test_vm = try_build_test_vm(components, FakeIrqChip::default()).unwrap();
+        assert_fdt_at(&test_vm, fdt_addr);
+
+        let invalid = [
+            (get_kernel_addr().offset(), "kernel"),
+            (AARCH64_PHYS_MEM_START + AARCH64_INITRD_ALIGN, "initrd"),
+        ];
+        for (fdt_addr, overlapped) in invalid {
+            let mut components = test_components(memory_size, ProtectionType::Unprotected);
+            components.fdt_position = FdtPosition::Address(fdt_addr);
+            components.initrd_image = Some(test_image(0x1000));
+            match try_build_test_vm(components, FakeIrqChip::default()) {
+                Err(Error::FdtOverlap(addr, image)) => {
+                    assert_eq!(addr, fdt_addr);
+                    assert_eq!(image, overlapped);
+                }
+                _ => panic!("FDT at {:#x} doesn't overlap the {}", fdt_addr, overlapped),
+            }
+        }
+
+        let out_of_ram = [
+            AARCH64_PHYS_MEM_START - AARCH64_FDT_MAX_SIZE,
+            AARCH64_PHYS_MEM_START + memory_size - 0x1000,
+        ];
+        for fdt_addr in out_of_ram {
+            let mut components = test_components(memory_size, ProtectionType::Unprotected);
+            components.fdt_position = FdtPosition::Address(fdt_addr);
+            assert!(matches!(
+                try_build_test_vm(components, FakeIrqChip::default()),
+                Err(Error::FdtOutOfRam(addr)) if addr == fdt_addr
+            ));
+        }
+
+        let mut components = test_components(memory_size, ProtectionType::Unprotected);
+        components.fdt_position = FdtPosition::Address(fdt_addr + 4);
+        assert!(matches!(
+            try_build_test_vm(components, FakeIrqChip::default()),
+            Err(Error::FdtMisaligned(_))
+        ));
+    }
+
+    #[test]
+    fn build_vm_sets_up_metrics_page() {
+        let memory_size = TEST_MEMORY_SIZES[0];
+        let mut components = test_components(memory_size, ProtectionType::Unprotected);
+        components.metrics_page = true;
+        let mut test_vm = try_build_test_vm(components, FakeIrqChip::default()).unwrap();
+        let mem = test_vm.linux.vm.get_memory().clone();
+        assert_eq!(ram_size(&mem), memory_size);
+
+        let metrics_page = test_vm.linux.metrics_page.as_mut().unwrap();
+        metrics_page.update(1, 42).unwrap();
+        let record = MetricsPage::read(&mem, GuestAddress(AARCH64_METRICS_PAGE_ADDR), 1)
+            .unwrap()
+            .unwrap();
+        assert_eq!(record.value, 42);
+
+        // The metrics page doesn't move the FDT, which is placed relative to the end of RAM.
+        let fdt_addr = AARCH64_PHYS_MEM_START + fdt_offset(memory_size, false);
+        let magic: u32 = mem.read_obj_from_addr(GuestAddress(fdt_addr)).unwrap();
+        assert_eq!(u32::from_be(magic), 0xd00dfeed);
+        let vcpus = test_vm.linux.vcpus.as_ref().unwrap();
+        assert_eq!(vcpus[0].reg(VcpuRegAArch64::X(0)), Some(fdt_addr));
+    }
+
+    #[test]
+    fn build_vm_degrades_busy_irqs() {
+        let irq_chip = FakeIrqChip {
+            busy_irqs: vec![AARCH64_RTC_IRQ, AARCH64_SERIAL_2_4_IRQ],
+            ..Default::default()
+        };
+        let test_vm = try_build_test_vm(
+            test_components(TEST_MEMORY_SIZES[0], ProtectionType::Unprotected),
+            irq_chip,
+        )
+        .unwrap();
+        assert_eq!(
+            test_vm.linux.degraded_devices,
+            vec!["rtc".to_string(), "serial ports 2 and 4".to_string()]
+        );
+        assert!(test_vm
+            .irq_chip
+            .edge_irqs
+            .iter()
+            .any(|r| r.irq == AARCH64_SERIAL_1_3_IRQ));
+    }
+
+    #[test]
+    fn build_vm_strict_irqs() {
+        let irq_chip = FakeIrqChip {
+            busy_irqs: vec![AARCH64_SERIAL_1_3_IRQ],
+            ..Default::default()
+        };
+        let mut components = test_components(TEST_MEMORY_SIZES[0], ProtectionType::Unprotected);
+        components.strict_irqs = true;
+        assert!(matches!(
+            try_build_test_vm(components, irq_chip),
+            Err(Error::RegisterIrqfd(_))
+        ));
+    }
+
+    #[test]
+    fn msr_handlers_return_id_overrides() {
+        let handlers = MsrHandlers::from_cpu_config(&CpuConfigAArch64 {
+            midr: Some(0x410f_d083),
+            revidr: None,
+        });
+        assert_eq!(handlers.read(MIDR_EL1.into()), Some(0x410f_d083));
+        assert_eq!(handlers.read(REVIDR_EL1.into()), None);
+        assert_eq!(handlers.write(MIDR_EL1.into(), 0), None);
+    }
+
+    const PMEM_2M: PmemRegion = PmemRegion {
+        size: 0x20_0000,
+        reserved_memory: false,
+    };
+    const PMEM_64M_RESERVED: PmemRegion = PmemRegion {
+        size: 0x400_0000,
+        reserved_memory: true,
+    };
+
+    #[test]
+    fn pmem_region_layout() {
+        assert_eq!(AArch64::pmem_region_layout(&[]).unwrap(), Vec::new());
+        assert_eq!(
+            AArch64::pmem_region_layout(&[PMEM_2M, PMEM_64M_RESERVED, PMEM_2M]).unwrap(),
+            vec![
+                AddressRange::from_start_and_size(AARCH64_PMEM_BASE, 0x20_0000).unwrap(),
+                AddressRange::from_start_and_size(AARCH64_PMEM_BASE + 0x20_0000, 0x400_0000)
+                    .unwrap(),
+                AddressRange::from_start_and_size(AARCH64_PMEM_BASE + 0x420_0000, 0x20_0000)
+                    .unwrap(),
+            ]
+        );
+        for size in [0, 0x1000, 0x30_0000] {
+            assert!(matches!(
+                AArch64::pmem_region_layout(&[PMEM_2M, PmemRegion { size, ..PMEM_2M }]),
+                Err(Error::PmemRegionSize(s)) if s == size
+            ));
+        }
+    }
+
+    #[test]
+    fn pmem_region_ram_overlap() {
+        let mut components = test_components(TEST_MEMORY_SIZES[0], ProtectionType::Unprotected);
+        components.pmem_regions = vec![PMEM_2M];
+        assert!(AArch64::guest_memory_layout(&components).is_ok());
+
+        components.memory_size = AARCH64_PMEM_BASE;
+        assert!(matches!(
+            AArch64::guest_memory_layout(&components),
+            Err(Error::PmemRegionRamOverlap(_))
+        ));
+    }
+
+    #[test]
+    fn build_vm_reserves_pmem_regions() {
+        let mut components = test_components(TEST_MEMORY_SIZES[0], ProtectionType::Unprotected);
+        components.pmem_regions = vec![PMEM_2M, PMEM_64M_RESERVED];
+        let test_vm = try_build_test_vm(components, FakeIrqChip::default()).unwrap();
+
+        let mem = test_vm.linux.vm.get_memory();
+        let fdt_addr = test_vm.linux.fdt_address.unwrap();
+        let mut fdt = vec![0u8; AARCH64_FDT_MAX_SIZE as usize];
+        mem.read_exact_at_addr(&mut fdt, fdt_addr).unwrap();
+        let contains = |needle: &[u8]| fdt.windows(needle.len()).any(|w| w == needle);
+        // Only the second region is described as reserved memory.
+        assert!(contains(b"pmem-region"));
+        assert!(contains(
+            format!("pmem@{:x}", AARCH64_PMEM_BASE + 0x20_0000).as_bytes()
+        ));
+        assert!(!contains(
+            format!("pmem@{:x}", AARCH64_PMEM_BASE).as_bytes()
+        ));
+    }
+
+    #[test]
+    fn build_vm_pmem_region_out_of_range() {
+        let mut components = test_components(TEST_MEMORY_SIZES[0], ProtectionType::Unprotected);
+        // The fake VM has a 40 bit address space, which this region doesn't fit in.
+        components.pmem_regions = vec![PmemRegion {
+            size: (1 << 40) - AARCH64_PMEM_BASE + AARCH64_PMEM_ALIGN,
+            reserved_memory: false,
+        }];
+        assert!(matches!(
+            try_build_test_vm(components, FakeIrqChip::default()),
+            Err(Error::PmemRegionOutOfRange(_))
+        ));
+    }
+
+    fn affinity(host_cpus: Vec<usize>, rt: bool) -> ClusterAffinity {
+        ClusterAffinity { host_cpus, rt }
+    }
+
+    #[test]
+    fn expand_cluster_affinity_per_vcpu() {
+        let clusters = vec![vec![0, 1], vec![2, 3], vec![4]];
+        let mut cluster_affinity = BTreeMap::new();
+        cluster_affinity.insert(0, affinity(vec![0, 1, 2, 3], false));
+        cluster_affinity.insert(1, affinity(vec![4, 5], true));
+        let mut rt_cpus = vec![3, 4];
+
+        let vcpu_affinity =
+            expand_cluster_affinity(&clusters, &cluster_affinity, 5, None, &mut rt_cpus).unwrap();
+
+        // The vcpu of the cluster without an affinity is left alone.
+        assert_eq!(
+            vcpu_affinity,
+            Some(VcpuAffinity::PerVcpu(BTreeMap::from([
+                (0, vec![0, 1, 2, 3]),
+                (1, vec![0, 1, 2, 3]),
+                (2, vec![4, 5]),
+                (3, vec![4, 5]),
+            ])))
+        );
+        assert_eq!(rt_cpus, vec![3, 4, 2]);
+    }
+
+    #[test]
+    fn expand_cluster_affinity_none() {
+        let mut rt_cpus = Vec::new();
+        let vcpu_affinity = Some(VcpuAffinity::Global(vec![1]));
+        assert_eq!(
+            expand_cluster_affinity(
+                &[vec![0, 1]],
+                &BTreeMap::new(),
+                2,
+                vcpu_affinity.clone(),
+                &mut rt_cpus
+            )
+            .unwrap(),
+            vcpu_affinity
+        );
+        assert!(rt_cpus.is_empty());
+    }
+
+    #[test]
+    fn expand_cluster_affinity_invalid() {
+        let cluster_affinity = BTreeMap::from([(1, affinity(vec![4, 5], false))]);
+        let expand = |clusters: &[Vec<usize>], vcpu_affinity| {
+            expand_cluster_affinity(
+                clusters,
+                &cluster_affinity,
+                4,
+                vcpu_affinity,
+                &mut Vec::new(),
+            )
+        };
+
+        assert!(matches!(
+            expand(&[vec![0, 1], vec![1, 2]], None),
+            Err(Error::ClusterAffinityOverlap(1))
+        ));
+        assert!(matches!(
+            expand(&[vec![0, 1]], None),
+            Err(Error::ClusterAffinityInvalidCluster(1))
+        ));
+        assert!(matches!(
+            expand(&[vec![0, 1], vec![2, 4]], None),
+            Err(Error::ClusterAffinityVcpu(4))
+        ));
+        assert!(matches!(
+            expand(
+                &[vec![0, 1], vec![2, 3]],
+                Some(VcpuAffinity::PerVcpu(BTreeMap::new()))
+            ),
+            Err(Error::ClusterAffinityConflict)
+        ));
+
+        let empty = BTreeMap::from([(0, affinity(Vec::new(), true))]);
+        assert!(matches!(
+            expand_cluster_affinity(&[vec![0, 1]], &empty, 2, None, &mut Vec::new()),
+            Err(Error::ClusterAffinityEmpty(0))
+        ));
+    }
+
+    #[test]
+    fn build_vm_expands_cluster_affinity() {
+        let mut components = test_components(TEST_MEMORY_SIZES[0], ProtectionType::Unprotected);
+        components.cpu_clusters = vec![vec![0], vec![1]];
+        components.cluster_affinity = BTreeMap::from([(1, affinity(vec![6, 7], true))]);
+        let test_vm = try_build_test_vm(components, FakeIrqChip::default()).unwrap();
+
+        assert_eq!(
+            test_vm.linux.vcpu_affinity,
+            Some(VcpuAffinity::PerVcpu(BTreeMap::from([(1, vec![6, 7])])))
+        );
+        assert_eq!(test_vm.linux.rt_cpus, vec![1]);
+    }
+
+    struct FakeHotPlugBus;
+
+    impl HotPlugBus for FakeHotPlugBus {
+        fn hot_plug(&mut self, _addr: PciAddress) {}
+        fn hot_unplug(&mut self, _addr: PciAddress) {}
+        fn is_match(&self, _host_addr: PciAddress) -> Option<u8> {
+            Some(0)
+        }
+        fn add_hotplug_device(&mut self, _host_key: HostHotPlugKey, _guest_addr: PciAddress) {}
+        fn get_hotplug_device(&self, _host_key: HostHotPlugKey) -> Option<PciAddress> {
+            None
+        }
+        fn is_empty(&self) -> bool {
+            true
+        }
+        fn get_hotplug_key(&self) -> Option<HostHotPlugKey> {
+            None
+        }
+    }
+
+    fn stub_pci_device(address: PciAddress) -> Box<dyn PciDevice> {
+        Box::new(StubPciDevice::new(&StubPciParameters {
+            address,
+            vendor_id: 0x1234,
+            device_id: 0x5678,
+            class: PciClassCode::Other,
+            subclass: 0,
+            programming_interface: 0,
+            subsystem_vendor_id: 0,
+            subsystem_device_id: 0,
+            revision_id: 0,
+        }))
+    }
+
+    #[test]
+    fn register_pci_device_needs_root_port() {
+        let mut test_vm = build_test_vm(TEST_MEMORY_SIZES[0], ProtectionType::Unprotected);
+        let mut allocator = SystemAllocator::new(
+            AArch64::get_system_allocator_config(&test_vm.linux.vm),
+            None,
+            &[],
+        )
+        .unwrap();
+        let (tx, rx) = mpsc::channel();
+        let address = PciAddress {
+            bus: 0,
+            dev: 0x1f,
+            func: 0,
+        };
+        assert!(matches!(
+            AArch64::register_pci_device(
+                &mut test_vm.linux,
+                stub_pci_device(address),
+                None,
+                &mut allocator,
+                &tx,
+            ),
+            Err(Error::NoPcieRootPort)
+        ));
+        assert!(rx.try_recv().is_err());
+
+        test_vm
+            .linux
+            .hotplug_bus
+            .insert(1, Arc::new(Mutex::new(FakeHotPlugBus)));
+        // The device gets the first free SPI.
+        let spi = SystemAllocator::new(
+            AArch64::get_system_allocator_config(&test_vm.linux.vm),
+            None,
+            &[],
+        )
+        .unwrap()
+        .allocate_irq();
+        assert_eq!(
+            AArch64::register_pci_device(
+                &mut test_vm.linux,
+                stub_pci_device(address),
+                None,
+                &mut allocator,
+                &tx,
+            )
+            .unwrap(),
+            address
+        );
+        match rx.try_recv() {
+            Ok(PciRootCommand::Add(added, _)) => assert_eq!(added, address),
+            _ => panic!("device wasn't added to the PCI root"),
+        }
+        assert_ne!(allocator.allocate_irq(), spi);
+    }
+
+    const GIB: u64 = 1 << 30;
+
+    #[test]
+    fn boot_data_placement_below_4gb() {
+        let low_fdt = AARCH64_BOOT_DATA_LIMIT - AARCH64_FDT_MAX_SIZE - AARCH64_FDT_END_GAP;
+        // (RAM size, FDT address, FDT address when the kernel can be placed anywhere)
+        let configs = [
+            (2 * GIB, low_fdt, low_fdt),
+            (
+                4 * GIB,
+                low_fdt,
+                AARCH64_PHYS_MEM_START + 4 * GIB - AARCH64_FDT_MAX_SIZE - AARCH64_FDT_END_GAP,
+            ),
+            (
+                8 * GIB,
+                low_fdt,
+                AARCH64_PHYS_MEM_START + 8 * GIB - AARCH64_FDT_MAX_SIZE - AARCH64_FDT_END_GAP,
+            ),
+        ];
+        for (memory_size, fdt_addr, high_fdt_addr) in configs {
+            let regions = [
+                (GuestAddress(AARCH64_METRICS_PAGE_ADDR), METRICS_PAGE_SIZE),
+                (GuestAddress(AARCH64_PHYS_MEM_START), memory_size),
+            ];
+            let limit = boot_data_limit(&regions, false);
+            assert_eq!(limit, AARCH64_BOOT_DATA_LIMIT);
+            assert_eq!(
+                fdt_address(FdtPosition::End, &regions, false, 0, limit).unwrap(),
+                fdt_addr
+            );
+            // The initrd after a kernel at the start of RAM fits in the rest of the first 4GiB.
+            let (initrd_addr, initrd_max_size) =
+                initrd_placement(get_kernel_addr().offset() + 0x10000, limit).unwrap();
+            assert_eq!(
+                initrd_addr.offset(),
+                AARCH64_PHYS_MEM_START + AARCH64_INITRD_ALIGN
+            );
+            assert_eq!(
+                initrd_addr.offset() + initrd_max_size,
+                AARCH64_BOOT_DATA_LIMIT
+            );
+
+            let high_limit = boot_data_limit(&regions, true);
+            assert_eq!(high_limit, AARCH64_PHYS_MEM_START + memory_size);
+            assert_eq!(
+                fdt_address(FdtPosition::End, &regions, false, 0, high_limit).unwrap(),
+                high_fdt_addr
+            );
+        }
+    }
+
+    #[test]
+    fn boot_data_placement_errors() {
+        let regions = [(GuestAddress(AARCH64_PHYS_MEM_START), 8 * GIB)];
+        let limit = boot_data_limit(&regions, false);
+
+        // A kernel filling the memory below 4GiB leaves no room for the initrd.
+        assert!(matches!(
+            initrd_placement(AARCH64_BOOT_DATA_LIMIT - 0x1000, limit),
+            Err(Error::InitrdAboveLimit(addr, l))
+                if addr == AARCH64_BOOT_DATA_LIMIT && l == limit
+        ));
+
+        // An FDT above 4GiB is in RAM, but out of reach of the kernel.
+        let fdt_addr = AARCH64_BOOT_DATA_LIMIT;
+        let image = AddressRange::from_start_and_size(get_kernel_addr().offset(), 0x10000).unwrap();
+        assert!(matches!(
+            check_fdt_placement(fdt_addr, 8 * GIB, limit, image, None),
+            Err(Error::FdtAboveLimit(addr, l)) if addr == fdt_addr && l == limit
+        ));
+        let high_limit = boot_data_limit(&regions, true);
+        assert!(check_fdt_placement(fdt_addr, 8 * GIB, high_limit, image, None).is_ok());
+
+        // RAM too small to hold the FDT.
+        let regions = [(GuestAddress(AARCH64_PHYS_MEM_START), AARCH64_FDT_MAX_SIZE)];
+        assert!(matches!(
+            fdt_offset(&regions, false, boot_data_limit(&regions, false)),
+            Err(Error::FdtNoRoom(_))
+        ));
+        // The BIOS always finds the FDT at the start of RAM.
+        assert_eq!(
+            fdt_offset(&regions, true, AARCH64_BOOT_DATA_LIMIT).unwrap(),
+            AARCH64_FDT_OFFSET_IN_BIOS_MODE
+        );
+    }
+
+    #[test]
+    fn platform_mmio_size_floor() {
+        assert_eq!(platform_mmio_size(Vec::new()), AARCH64_PLATFORM_MMIO_SIZE);
+        assert_eq!(
+            platform_mmio_size(vec![0x1000; 4]),
+            AARCH64_PLATFORM_MMIO_SIZE
+        );
+    }
+
+    #[test]
+    fn resource_allocator_config_large_platform_devices() {
+        let page_size = base::pagesize() as u64;
+        // 64 devices with three regions each, one of them not page sized.
+        let sizes: Vec<u64> = (0..64)
+            .flat_map(|_| vec![0x10_0000, 0x4_0000, 0x100])
+            .collect();
+        let plat_mmio_size = platform_mmio_size(sizes);
+        assert_eq!(plat_mmio_size, 64 * (0x10_0000 + 0x4_0000 + page_size));
+
+        let mem_size = 0x4000_0000;
+        let config = AArch64::get_resource_allocator_config(mem_size, 40, plat_mmio_size);
+        let platform_mmio = config.platform_mmio.unwrap();
+        assert_eq!(platform_mmio.start, AARCH64_PHYS_MEM_START + mem_size);
+        assert_eq!(platform_mmio.len(), Some(plat_mmio_size));
+        assert_eq!(config.high_mmio.start, platform_mmio.end + 1);
+        assert_eq!(config.high_mmio.end, (1u64 << 40) - 1);
+    }
+}
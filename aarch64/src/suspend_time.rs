@@ -0,0 +1,122 @@
+// Copyright 2022 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Hides the time the VM spends suspended from the guest's virtual counter.
+//!
+//! The virtual counter keeps counting while the VCPUs are suspended, e.g. while the host itself
+//! is suspended, so on resume the guest sees all that time pass at once: its monotonic clock
+//! jumps and every timer that expired in the meantime fires. Instead, the value of the counter
+//! can be saved when the VCPUs are suspended and given back to the guest when they resume, which
+//! moves the virtual counter offset forward by the suspended duration.
+
+use base::Result;
+use hypervisor::VcpuAArch64;
+
+/// The virtual counter of a suspended VM.
+#[derive(Default)]
+pub struct SuspendedCounter {
+    saved: Option<u64>,
+}
+
+impl SuspendedCounter {
+    pub fn new() -> SuspendedCounter {
+        SuspendedCounter { saved: None }
+    }
+
+    /// Saves the value of the virtual counter as the VM is suspended. If it already is, the value
+    /// saved when it was first suspended is kept.
+    pub fn suspend(&mut self, vcpu: &dyn VcpuAArch64) -> Result<()> {
+        if self.saved.is_none() {
+            self.saved = Some(vcpu.get_virtual_counter()?);
+        }
+        Ok(())
+    }
+
+    /// Makes the virtual counter resume from the value saved by `suspend`, returning the number of
+    /// ticks that were hidden from the guest.
+    pub fn resume(&mut self, vcpu: &dyn VcpuAArch64) -> Result<u64> {
+        let saved = match self.saved.take() {
+            Some(saved) => saved,
+            None => return Ok(0),
+        };
+        let now = vcpu.get_virtual_counter()?;
+        vcpu.set_virtual_counter(saved)?;
+        Ok(now.wrapping_sub(saved))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake::FakeVcpu;
+
+    #[test]
+    fn resume_hides_suspended_ticks() {
+        let vcpu = FakeVcpu::new(0);
+        vcpu.advance_counter(1000);
+        let mut counter = SuspendedCounter::new();
+
+        counter.suspend(&vcpu).unwrap();
+        vcpu.advance_counter(5000);
+        assert_eq!(counter.resume(&vcpu).unwrap(), 5000);
+        assert_eq!(vcpu.get_virtual_counter().unwrap(), 1000);
+        assert_eq!(vcpu.virtual_counter_offset(), 5000);
+
+        // The counter keeps running from where it was suspended.
+        vcpu.advance_counter(10);
+        assert_eq!(vcpu.get_virtual_counter().unwrap(), 1010);
+    }
+
+    #[test]
+    fn offsets_accumulate() {
+        let vcpu = FakeVcpu::new(0);
+        let mut counter = SuspendedCounter::new();
+
+        for _ in 0..3 {
+            vcpu.advance_counter(100);
+            counter.suspend(&vcpu).unwrap();
+            vcpu.advance_counter(2000);
+            assert_eq!(counter.resume(&vcpu).unwrap(), 2000);
+        }
+        assert_eq!(vcpu.virtual_counter_offset(), 6000);
+        assert_eq!(vcpu.get_virtual_counter().unwrap(), 300);
+    }
+
+    #[test]
+    fn repeated_suspend_keeps_first_value() {
+        let vcpu = FakeVcpu::new(0);
+        vcpu.advance_counter(1000);
+        let mut counter = SuspendedCounter::new();
+
+        counter.suspend(&vcpu).unwrap();
+        vcpu.advance_counter(500);
+        counter.suspend(&vcpu).unwrap();
+        vcpu.advance_counter(500);
+        assert_eq!(counter.resume(&vcpu).unwrap(), 1000);
+        assert_eq!(vcpu.get_virtual_counter().unwrap(), 1000);
+    }
+
+    #[test]
+    fn resume_without_suspend() {
+        let vcpu = FakeVcpu::new(0);
+        vcpu.advance_counter(1000);
+        let mut counter = SuspendedCounter::new();
+
+        assert_eq!(counter.resume(&vcpu).unwrap(), 0);
+        assert_eq!(vcpu.virtual_counter_offset(), 0);
+        assert_eq!(vcpu.get_virtual_counter().unwrap(), 1000);
+    }
+
+    #[test]
+    fn counter_wraps() {
+        let vcpu = FakeVcpu::new(0);
+        vcpu.advance_counter(u64::MAX - 10);
+        let mut counter = SuspendedCounter::new();
+
+        counter.suspend(&vcpu).unwrap();
+        vcpu.advance_counter(100);
+        assert_eq!(counter.resume(&vcpu).unwrap(), 100);
+        assert_eq!(vcpu.get_virtual_counter().unwrap(), u64::MAX - 10);
+    }
+}
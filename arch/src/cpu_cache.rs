@@ -0,0 +1,435 @@
+// Copyright 2022 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Describes the caches of the vcpus to the guest, so that its scheduler knows which vcpus share
+//! a cache.
+//!
+//! The caches are given with `--cpu-cache`, or read from the host sysfs with
+//! `--host-cpu-topology`, where vcpu N runs on host CPU N.
+
+use std::fs;
+use std::path::Path;
+
+use base::warn;
+use remain::sorted;
+use serde::Deserialize;
+use serde::Serialize;
+use thiserror::Error;
+
+/// Where the cache topology of the host CPUs is read from by `host_cpu_caches`.
+pub const HOST_CPU_SYSFS_DIR: &str = "/sys/devices/system/cpu";
+
+/// The kind of memory held by a cache.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CpuCacheType {
+    Data,
+    Instruction,
+    Unified,
+}
+
+/// A cache shared by a set of vcpus.
+///
+/// The geometry of the cache is left out of the guest description when unknown, and a cache of
+/// unknown size isn't described at all.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct CpuCache {
+    /// 1 for the caches closest to the CPU.
+    pub level: u32,
+    #[serde(rename = "type")]
+    pub cache_type: CpuCacheType,
+    /// Size in bytes.
+    #[serde(default)]
+    pub size: Option<u32>,
+    /// Size of a cache line in bytes.
+    #[serde(default)]
+    pub line_size: Option<u32>,
+    #[serde(default)]
+    pub sets: Option<u32>,
+    /// The vcpus sharing the cache, in increasing order.
+    pub cpus: Vec<usize>,
+}
+
+impl CpuCache {
+    /// Returns whether every vcpu sharing the cache also shares `other`.
+    pub fn is_within(&self, other: &CpuCache) -> bool {
+        self.cpus.iter().all(|cpu| other.cpus.contains(cpu))
+    }
+
+    fn shares_cpus_with(&self, other: &CpuCache) -> bool {
+        self.cpus.iter().any(|cpu| other.cpus.contains(cpu))
+    }
+}
+
+/// A problem with the caches given to `check_cpu_caches`.
+#[sorted]
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum CpuCacheError {
+    #[error(
+        "level {level} cache shares vcpu {cpu} with another level {level} {cache_type:?} cache"
+    )]
+    DuplicateCache {
+        level: u32,
+        cache_type: CpuCacheType,
+        cpu: usize,
+    },
+    #[error("level {level} cache is shared by only part of the vcpus of a level {outer} cache")]
+    InconsistentHierarchy { level: u32, outer: u32 },
+    #[error("level {0} cache splits a cpu cluster")]
+    InconsistentWithClusters(u32),
+    #[error("cache level must be at least 1")]
+    InvalidLevel,
+    #[error("level {0} cache isn't shared by any vcpu")]
+    NoVcpus(u32),
+    #[error("level {0} cache must be unified, only level 1 caches can be split")]
+    NotUnified(u32),
+    #[error("level {level} cache is shared by vcpu {cpu}, but there are only {num_cpus} vcpus")]
+    VcpuOutOfRange {
+        level: u32,
+        cpu: usize,
+        num_cpus: usize,
+    },
+}
+
+fn check_cpu_cache(
+    cache: &CpuCache,
+    num_cpus: usize,
+    cpu_clusters: &[Vec<usize>],
+) -> Result<(), CpuCacheError> {
+    let level = cache.level;
+    if level == 0 {
+        return Err(CpuCacheError::InvalidLevel);
+    }
+    if level > 1 && cache.cache_type != CpuCacheType::Unified {
+        return Err(CpuCacheError::NotUnified(level));
+    }
+    if cache.cpus.is_empty() {
+        return Err(CpuCacheError::NoVcpus(level));
+    }
+    if let Some(&cpu) = cache.cpus.iter().find(|&&cpu| cpu >= num_cpus) {
+        return Err(CpuCacheError::VcpuOutOfRange {
+            level,
+            cpu,
+            num_cpus,
+        });
+    }
+    // A cache is either within a cluster or shared by whole clusters.
+    for cluster in cpu_clusters {
+        let shared = cluster
+            .iter()
+            .filter(|cpu| cache.cpus.contains(cpu))
+            .count();
+        let within = cache.cpus.iter().all(|cpu| cluster.contains(cpu));
+        if shared > 0 && shared < cluster.len() && !within {
+            return Err(CpuCacheError::InconsistentWithClusters(level));
+        }
+    }
+    Ok(())
+}
+
+/// Checks that `cpu_caches` describe a hierarchy of caches of `num_cpus` vcpus consistent with
+/// `cpu_clusters`: each vcpu has at most one cache of each level and type, and the vcpus sharing
+/// a cache all share the same caches of the higher levels.
+pub fn check_cpu_caches(
+    cpu_caches: &[CpuCache],
+    num_cpus: usize,
+    cpu_clusters: &[Vec<usize>],
+) -> Result<(), CpuCacheError> {
+    for (i, cache) in cpu_caches.iter().enumerate() {
+        check_cpu_cache(cache, num_cpus, cpu_clusters)?;
+        for other in &cpu_caches[..i] {
+            if other.level == cache.level && other.cache_type == cache.cache_type {
+                if let Some(&cpu) = cache.cpus.iter().find(|cpu| other.cpus.contains(cpu)) {
+                    return Err(CpuCacheError::DuplicateCache {
+                        level: cache.level,
+                        cache_type: cache.cache_type,
+                        cpu,
+                    });
+                }
+            }
+            let (inner, outer) = if other.level < cache.level {
+                (other, cache)
+            } else {
+                (cache, other)
+            };
+            if inner.level < outer.level && inner.shares_cpus_with(outer) && !inner.is_within(outer)
+            {
+                return Err(CpuCacheError::InconsistentHierarchy {
+                    level: inner.level,
+                    outer: outer.level,
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Reads the caches of the first `num_cpus` host CPUs from the sysfs CPU directory `cpu_dir`.
+///
+/// Caches whose description can't be read are left out, as are caches also shared by CPUs past
+/// `num_cpus`.
+pub fn read_cpu_caches(cpu_dir: &Path, num_cpus: usize) -> Vec<CpuCache> {
+    let mut caches: Vec<CpuCache> = Vec::new();
+    for cpu in 0..num_cpus {
+        let cache_dir = cpu_dir.join(format!("cpu{}", cpu)).join("cache");
+        let entries = match fs::read_dir(&cache_dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries.flatten() {
+            let is_index = entry
+                .file_name()
+                .to_str()
+                .map_or(false, |name| name.starts_with("index"));
+            if !is_index {
+                continue;
+            }
+            if let Some(cache) = read_cache_index(&entry.path()) {
+                if cache.cpus.iter().all(|&cpu| cpu < num_cpus) && !caches.contains(&cache) {
+                    caches.push(cache);
+                }
+            }
+        }
+    }
+    caches.sort_by(|a, b| (a.level, a.cache_type, &a.cpus).cmp(&(b.level, b.cache_type, &b.cpus)));
+    caches
+}
+
+/// Reads the caches of the host CPUs, as `read_cpu_caches` does from `HOST_CPU_SYSFS_DIR`.
+///
+/// Returns no caches if they fail `check_cpu_caches` with `cpu_clusters`.
+pub fn host_cpu_caches(num_cpus: usize, cpu_clusters: &[Vec<usize>]) -> Vec<CpuCache> {
+    let caches = read_cpu_caches(Path::new(HOST_CPU_SYSFS_DIR), num_cpus);
+    match check_cpu_caches(&caches, num_cpus, cpu_clusters) {
+        Ok(()) => caches,
+        Err(e) => {
+            warn!("not describing the host CPU caches to the guest: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+// Reads a cache from a sysfs `cache/indexN` directory.
+fn read_cache_index(dir: &Path) -> Option<CpuCache> {
+    let read = |name: &str| fs::read_to_string(dir.join(name)).ok();
+    // The geometry is left out rather than given as 0 when unknown.
+    let read_u32 = |name: &str| {
+        read(name)
+            .and_then(|s| s.trim().parse::<u32>().ok())
+            .filter(|&v| v != 0)
+    };
+
+    let cache_type = match read("type")?.trim() {
+        "Data" => CpuCacheType::Data,
+        "Instruction" => CpuCacheType::Instruction,
+        "Unified" => CpuCacheType::Unified,
+        _ => return None,
+    };
+    Some(CpuCache {
+        level: read_u32("level")?,
+        cache_type,
+        size: read("size").and_then(|s| parse_cache_size(s.trim())),
+        line_size: read_u32("coherency_line_size"),
+        sets: read_u32("number_of_sets"),
+        cpus: parse_cpu_list(read("shared_cpu_list")?.trim())?,
+    })
+}
+
+// Parses a size such as "32K", as found in sysfs.
+fn parse_cache_size(s: &str) -> Option<u32> {
+    let (digits, multiplier) = match s.strip_suffix('K') {
+        Some(digits) => (digits, 1 << 10),
+        None => match s.strip_suffix('M') {
+            Some(digits) => (digits, 1 << 20),
+            None => (s, 1),
+        },
+    };
+    digits
+        .parse::<u32>()
+        .ok()
+        .and_then(|size| size.checked_mul(multiplier))
+        .filter(|&size| size != 0)
+}
+
+// Parses a list of CPUs such as "0-3,8", as found in sysfs.
+fn parse_cpu_list(s: &str) -> Option<Vec<usize>> {
+    let mut cpus = Vec::new();
+    for range in s.split(',') {
+        match range.split_once('-') {
+            Some((first, last)) => cpus.extend(first.parse::<usize>().ok()?..=last.parse().ok()?),
+            None => cpus.push(range.parse().ok()?),
+        }
+    }
+    cpus.sort_unstable();
+    cpus.dedup();
+    Some(cpus)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn cache(level: u32, cache_type: CpuCacheType, cpus: &[usize]) -> CpuCache {
+        CpuCache {
+            level,
+            cache_type,
+            size: Some(0x8000 << level),
+            line_size: Some(64),
+            sets: None,
+            cpus: cpus.to_vec(),
+        }
+    }
+
+    fn write_index(cpu_dir: &Path, cpu: usize, index: usize, files: &[(&str, &str)]) {
+        let dir = cpu_dir
+            .join(format!("cpu{}", cpu))
+            .join("cache")
+            .join(format!("index{}", index));
+        fs::create_dir_all(&dir).unwrap();
+        for (name, contents) in files {
+            fs::write(dir.join(name), format!("{}\n", contents)).unwrap();
+        }
+    }
+
+    #[test]
+    fn read_sysfs_caches() {
+        let cpu_dir: PathBuf =
+            std::env::temp_dir().join(format!("arch_cpu_cache_{}_sysfs", std::process::id()));
+        for cpu in 0..2 {
+            let cpu_list = cpu.to_string();
+            write_index(
+                &cpu_dir,
+                cpu,
+                0,
+                &[
+                    ("level", "1"),
+                    ("type", "Data"),
+                    ("size", "32K"),
+                    ("coherency_line_size", "64"),
+                    ("number_of_sets", "0"),
+                    ("shared_cpu_list", &cpu_list),
+                ],
+            );
+            write_index(
+                &cpu_dir,
+                cpu,
+                1,
+                &[
+                    ("level", "2"),
+                    ("type", "Unified"),
+                    ("size", "1M"),
+                    ("shared_cpu_list", "0-1"),
+                ],
+            );
+            // Shared with a host CPU the VM doesn't have.
+            write_index(
+                &cpu_dir,
+                cpu,
+                2,
+                &[
+                    ("level", "3"),
+                    ("type", "Unified"),
+                    ("size", "8M"),
+                    ("shared_cpu_list", "0-2"),
+                ],
+            );
+        }
+
+        let caches = read_cpu_caches(&cpu_dir, 2);
+        fs::remove_dir_all(&cpu_dir).unwrap();
+        assert_eq!(
+            caches,
+            vec![
+                CpuCache {
+                    level: 1,
+                    cache_type: CpuCacheType::Data,
+                    size: Some(32 << 10),
+                    line_size: Some(64),
+                    sets: None,
+                    cpus: vec![0],
+                },
+                CpuCache {
+                    level: 1,
+                    cache_type: CpuCacheType::Data,
+                    size: Some(32 << 10),
+                    line_size: Some(64),
+                    sets: None,
+                    cpus: vec![1],
+                },
+                CpuCache {
+                    level: 2,
+                    cache_type: CpuCacheType::Unified,
+                    size: Some(1 << 20),
+                    line_size: None,
+                    sets: None,
+                    cpus: vec![0, 1],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_sysfs_values() {
+        assert_eq!(parse_cache_size("48K"), Some(48 << 10));
+        assert_eq!(parse_cache_size("2M"), Some(2 << 20));
+        assert_eq!(parse_cache_size("0K"), None);
+        assert_eq!(parse_cpu_list("0-2,5"), Some(vec![0, 1, 2, 5]));
+        assert_eq!(parse_cpu_list("a"), None);
+    }
+
+    #[test]
+    fn check_consistent_caches() {
+        use CpuCacheType::*;
+        let clusters = vec![vec![0, 1], vec![2, 3]];
+        let caches = vec![
+            cache(1, Data, &[0]),
+            cache(1, Instruction, &[0]),
+            cache(2, Unified, &[0, 1]),
+            cache(2, Unified, &[2, 3]),
+            cache(3, Unified, &[0, 1, 2, 3]),
+        ];
+        assert_eq!(check_cpu_caches(&caches, 4, &clusters), Ok(()));
+    }
+
+    #[test]
+    fn check_inconsistent_caches() {
+        use CpuCacheType::*;
+        let clusters = vec![vec![0, 1], vec![2, 3]];
+        assert_eq!(
+            check_cpu_caches(&[cache(2, Unified, &[1, 2])], 4, &clusters),
+            Err(CpuCacheError::InconsistentWithClusters(2))
+        );
+        assert_eq!(
+            check_cpu_caches(
+                &[cache(2, Unified, &[0, 1]), cache(3, Unified, &[1])],
+                4,
+                &[]
+            ),
+            Err(CpuCacheError::InconsistentHierarchy { level: 2, outer: 3 })
+        );
+        assert_eq!(
+            check_cpu_caches(&[cache(1, Data, &[0]), cache(1, Data, &[0])], 4, &[]),
+            Err(CpuCacheError::DuplicateCache {
+                level: 1,
+                cache_type: Data,
+                cpu: 0
+            })
+        );
+        assert_eq!(
+            check_cpu_caches(&[cache(2, Data, &[0])], 4, &[]),
+            Err(CpuCacheError::NotUnified(2))
+        );
+        assert_eq!(
+            check_cpu_caches(&[cache(1, Data, &[4])], 4, &[]),
+            Err(CpuCacheError::VcpuOutOfRange {
+                level: 1,
+                cpu: 4,
+                num_cpus: 4
+            })
+        );
+    }
+}
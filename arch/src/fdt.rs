@@ -2,7 +2,7 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
-//! This module writes Flattened Devicetree blobs as defined here:
+//! This module writes and inspects Flattened Devicetree blobs as defined here:
 //! <https://devicetree-specification.readthedocs.io/en/stable/flattened-format.html>
 
 use std::collections::BTreeMap;
@@ -17,18 +17,40 @@ use thiserror::Error as ThisError;
 #[sorted]
 #[derive(ThisError, Debug)]
 pub enum Error {
+    #[error("Node {path} property {property} references missing phandle {phandle:#x}")]
+    DanglingPhandle {
+        path: String,
+        property: String,
+        phandle: u32,
+    },
+    #[error("Node {path} reuses phandle {phandle:#x}")]
+    DuplicatePhandle { path: String, phandle: u32 },
     #[error("Parse error reading FDT parameters")]
     FdtFileParseError,
     #[error("Error writing FDT to guest memory")]
     FdtGuestMemoryWriteError,
     #[error("I/O error reading FDT parameters code={0}")]
     FdtIoError(io::Error),
+    #[error("Devicetree blob is malformed")]
+    InvalidBlob,
+    #[error("Devicetree overlay is invalid: {0}")]
+    InvalidOverlay(String),
+    #[error("Node {path} has a malformed {property} property")]
+    InvalidProperty { path: String, property: String },
     #[error("Strings cannot contain NUL")]
     InvalidString,
+    #[error("Node {path} is missing required property {property}")]
+    MissingProperty { path: String, property: String },
     #[error("Attempted to end a node that was not the most recent")]
     OutOfOrderEndNode,
+    #[error("Devicetree overlay may not modify node {0}")]
+    OverlayModifiesProtectedNode(String),
+    #[error("Devicetree overlay targets missing node {0}")]
+    OverlayTargetNotFound(String),
     #[error("Properties may not be added after a node has been ended")]
     PropertyAfterEndNode,
+    #[error("Property {0} not found")]
+    PropertyNotFound(String),
     #[error("Property value size must fit in 32 bits")]
     PropertyValueTooLarge,
     #[error("Total size must fit in 32 bits")]
@@ -48,6 +70,7 @@ const FDT_MAGIC: u32 = 0xd00dfeed;
 const FDT_BEGIN_NODE: u32 = 0x00000001;
 const FDT_END_NODE: u32 = 0x00000002;
 const FDT_PROP: u32 = 0x00000003;
+const FDT_NOP: u32 = 0x00000004;
 const FDT_END: u32 = 0x00000009;
 
 /// Interface for writing a Flattened Devicetree (FDT) and emitting a Devicetree Blob (DTB).
@@ -353,6 +376,542 @@ impl FdtWriter {
     }
 }
 
+fn read_u32(blob: &[u8], offset: usize) -> Result<u32> {
+    let bytes = blob.get(offset..offset + 4).ok_or(Error::InvalidBlob)?;
+    Ok(u32::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+fn align4(offset: usize) -> usize {
+    (offset + 3) & !3
+}
+
+/// The blocks of a DTB walked when inspecting it.
+struct BlobBlocks<'a> {
+    /// The DTB without the trailing bytes past its `totalsize`.
+    blob: &'a [u8],
+    strings: &'a [u8],
+    off_dt_struct: usize,
+    struct_end: usize,
+}
+
+impl<'a> BlobBlocks<'a> {
+    fn new(blob: &'a [u8]) -> Result<Self> {
+        if read_u32(blob, 0)? != FDT_MAGIC {
+            return Err(Error::InvalidBlob);
+        }
+        let totalsize = read_u32(blob, 4)? as usize;
+        let off_dt_struct = read_u32(blob, 2 * 4)? as usize;
+        let off_dt_strings = read_u32(blob, 3 * 4)? as usize;
+        let size_dt_strings = read_u32(blob, 8 * 4)? as usize;
+        let size_dt_struct = read_u32(blob, 9 * 4)? as usize;
+        let blob = blob.get(..totalsize).ok_or(Error::InvalidBlob)?;
+        let strings = blob
+            .get(off_dt_strings..off_dt_strings + size_dt_strings)
+            .ok_or(Error::InvalidBlob)?;
+        let struct_end = off_dt_struct + size_dt_struct;
+        if struct_end > totalsize {
+            return Err(Error::InvalidBlob);
+        }
+        Ok(BlobBlocks {
+            blob,
+            strings,
+            off_dt_struct,
+            struct_end,
+        })
+    }
+
+    /// Returns the name of the node starting at `pos` and the offset following it.
+    fn node_name(&self, pos: usize) -> Result<(&'a [u8], usize)> {
+        let name_start = pos + 4;
+        let name_len = self
+            .blob
+            .get(name_start..self.struct_end)
+            .ok_or(Error::InvalidBlob)?
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or(Error::InvalidBlob)?;
+        Ok((
+            &self.blob[name_start..name_start + name_len],
+            align4(name_start + name_len + 1),
+        ))
+    }
+
+    /// Returns the name and length of the property starting at `pos`.
+    fn property_header(&self, pos: usize) -> Result<(&'a [u8], usize)> {
+        let len = read_u32(self.blob, pos + 4)? as usize;
+        let nameoff = read_u32(self.blob, pos + 8)? as usize;
+        let name = self.strings.get(nameoff..).ok_or(Error::InvalidBlob)?;
+        let name = &name[..name
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or(Error::InvalidBlob)?];
+        Ok((name, len))
+    }
+}
+
+/// Replace the value of an existing property in a Devicetree Blob (DTB).
+///
+/// Returns a copy of `blob` where property `name` of the node at `node_path` (e.g. "/chosen")
+/// holds `val`, padded with zeroes up to `max_size` like `FdtWriter::finish`.
+///
+/// # Arguments
+///
+/// `blob` - DTB to modify; trailing bytes past its `totalsize` are ignored.
+/// `node_path` - absolute path of the node holding the property.
+/// `name` - name of the property to replace.
+/// `val` - new value of the property (raw byte array).
+/// `max_size` - Maximum size of the modified DTB in bytes.
+pub fn set_property(
+    blob: &[u8],
+    node_path: &str,
+    name: &str,
+    val: &[u8],
+    max_size: usize,
+) -> Result<Vec<u8>> {
+    let blocks = BlobBlocks::new(blob)?;
+    let blob = blocks.blob;
+    let totalsize = blob.len();
+    let off_dt_struct = blocks.off_dt_struct;
+    let off_dt_strings = read_u32(blob, 3 * 4)? as usize;
+    let struct_end = blocks.struct_end;
+
+    let target: Vec<&str> = node_path.split('/').filter(|c| !c.is_empty()).collect();
+    // Names of the nodes enclosing the current position, excluding the root node.
+    let mut path: Vec<&[u8]> = Vec::new();
+    let mut depth = 0;
+    let mut pos = off_dt_struct;
+    let (prop_offset, old_len) = loop {
+        if pos >= struct_end {
+            return Err(Error::InvalidBlob);
+        }
+        match read_u32(blob, pos)? {
+            FDT_BEGIN_NODE => {
+                let (node_name, next) = blocks.node_name(pos)?;
+                if depth > 0 {
+                    path.push(node_name);
+                }
+                depth += 1;
+                pos = next;
+            }
+            FDT_END_NODE => {
+                if depth == 0 {
+                    return Err(Error::InvalidBlob);
+                }
+                depth -= 1;
+                path.pop();
+                pos += 4;
+            }
+            FDT_PROP => {
+                let (prop_name, len) = blocks.property_header(pos)?;
+                if depth == target.len() + 1
+                    && path.iter().copied().eq(target.iter().map(|c| c.as_bytes()))
+                    && prop_name == name.as_bytes()
+                {
+                    break (pos, len);
+                }
+                pos = align4(pos + 12 + len);
+            }
+            FDT_NOP => pos += 4,
+            FDT_END => return Err(Error::PropertyNotFound(format!("{}:{}", node_path, name))),
+            _ => return Err(Error::InvalidBlob),
+        }
+    };
+
+    let len: u32 = val
+        .len()
+        .try_into()
+        .map_err(|_| Error::PropertyValueTooLarge)?;
+    let old_end = align4(prop_offset + 12 + old_len);
+    if old_end > struct_end {
+        return Err(Error::InvalidBlob);
+    }
+
+    let mut data = Vec::with_capacity(max_size);
+    data.extend_from_slice(&blob[..prop_offset + 4]);
+    data.extend_from_slice(&len.to_be_bytes());
+    data.extend_from_slice(&blob[prop_offset + 8..prop_offset + 12]);
+    data.extend_from_slice(val);
+    data.resize(align4(data.len()), 0);
+    data.extend_from_slice(&blob[old_end..]);
+
+    // Blocks after the property move by the change in its padded size.
+    let new_end = data.len() - (blob.len() - old_end);
+    let shift = |offset: usize| -> Result<u32> {
+        let offset = if offset >= old_end {
+            offset - old_end + new_end
+        } else {
+            offset
+        };
+        offset.try_into().map_err(|_| Error::TotalSizeTooLarge)
+    };
+    let header = [
+        (1, shift(totalsize)?),
+        (2, shift(off_dt_struct)?),
+        (3, shift(off_dt_strings)?),
+        (4, shift(read_u32(blob, 4 * 4)? as usize)?),
+        (9, shift(struct_end)? - shift(off_dt_struct)?),
+    ];
+    for (field, val) in header {
+        data[field * 4..field * 4 + 4].copy_from_slice(&val.to_be_bytes());
+    }
+
+    if data.len() > max_size {
+        Err(Error::TotalSizeTooLarge)
+    } else {
+        data.resize(max_size, 0);
+        Ok(data)
+    }
+}
+
+/// A node of a Devicetree Blob, as returned by `parse_nodes`.
+pub struct FdtNode {
+    /// Absolute path of the node, e.g. "/cpus/cpu@0".
+    pub path: String,
+    /// Index of the parent node in the list returned by `parse_nodes`, `None` for the root node.
+    pub parent: Option<usize>,
+    /// Properties of the node, in the order they appear in the blob.
+    pub properties: Vec<(String, Vec<u8>)>,
+}
+
+impl FdtNode {
+    /// Returns the raw value of property `name`, if the node has it.
+    pub fn property(&self, name: &str) -> Option<&[u8]> {
+        self.properties
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, val)| val.as_slice())
+    }
+
+    /// Returns the value of property `name` as a list of cells, if the node has it.
+    pub fn property_cells(&self, name: &str) -> Result<Option<Vec<u32>>> {
+        let val = match self.property(name) {
+            Some(val) => val,
+            None => return Ok(None),
+        };
+        if val.len() % 4 != 0 {
+            return Err(self.invalid_property(name));
+        }
+        Ok(Some(
+            val.chunks_exact(4)
+                .map(|cell| u32::from_be_bytes(cell.try_into().unwrap()))
+                .collect(),
+        ))
+    }
+
+    /// Returns the value of property `name` as a single cell, if the node has it.
+    pub fn property_u32(&self, name: &str) -> Result<Option<u32>> {
+        match self.property_cells(name)? {
+            None => Ok(None),
+            Some(cells) if cells.len() == 1 => Ok(Some(cells[0])),
+            Some(_) => Err(self.invalid_property(name)),
+        }
+    }
+
+    /// Returns an `InvalidProperty` error for property `name` of this node.
+    pub fn invalid_property(&self, name: &str) -> Error {
+        Error::InvalidProperty {
+            path: self.path.clone(),
+            property: name.to_owned(),
+        }
+    }
+
+    /// Returns a `MissingProperty` error for property `name` of this node.
+    pub fn missing_property(&self, name: &str) -> Error {
+        Error::MissingProperty {
+            path: self.path.clone(),
+            property: name.to_owned(),
+        }
+    }
+}
+
+/// Reads all the nodes of a Devicetree Blob (DTB), parents before their children.
+///
+/// # Arguments
+///
+/// `blob` - DTB to read; trailing bytes past its `totalsize` are ignored.
+pub fn parse_nodes(blob: &[u8]) -> Result<Vec<FdtNode>> {
+    let blocks = BlobBlocks::new(blob)?;
+    let mut nodes: Vec<FdtNode> = Vec::new();
+    // Indices of the nodes enclosing the current position.
+    let mut open: Vec<usize> = Vec::new();
+    let mut pos = blocks.off_dt_struct;
+    loop {
+        if pos >= blocks.struct_end {
+            return Err(Error::InvalidBlob);
+        }
+        match read_u32(blocks.blob, pos)? {
+            FDT_BEGIN_NODE => {
+                let (name, next) = blocks.node_name(pos)?;
+                let name = std::str::from_utf8(name).map_err(|_| Error::InvalidBlob)?;
+                let parent = open.last().copied();
+                let path = match parent {
+                    None => "/".to_owned(),
+                    Some(p) if p == 0 => format!("/{}", name),
+                    Some(p) => format!("{}/{}", nodes[p].path, name),
+                };
+                open.push(nodes.len());
+                nodes.push(FdtNode {
+                    path,
+                    parent,
+                    properties: Vec::new(),
+                });
+                pos = next;
+            }
+            FDT_END_NODE => {
+                open.pop().ok_or(Error::InvalidBlob)?;
+                pos += 4;
+            }
+            FDT_PROP => {
+                let node = *open.last().ok_or(Error::InvalidBlob)?;
+                let (name, len) = blocks.property_header(pos)?;
+                let name = std::str::from_utf8(name).map_err(|_| Error::InvalidBlob)?;
+                let val = blocks
+                    .blob
+                    .get(pos + 12..pos + 12 + len)
+                    .ok_or(Error::InvalidBlob)?;
+                nodes[node].properties.push((name.to_owned(), val.to_vec()));
+                pos = align4(pos + 12 + len);
+            }
+            FDT_NOP => pos += 4,
+            FDT_END if open.is_empty() => return Ok(nodes),
+            _ => return Err(Error::InvalidBlob),
+        }
+    }
+}
+
+/// Applies a Devicetree overlay blob (DTBO) to a Devicetree Blob (DTB).
+///
+/// Returns a copy of `blob` where the `__overlay__` node of each `fragment@N` node of `overlay`
+/// is merged into the node named by the `target-path` property of the fragment: its properties
+/// replace or are added to those of the target and its child nodes are merged recursively.
+/// Phandles defined by the overlay are renumbered after those of `blob` as described by its
+/// `__local_fixups__` node. The result is padded with zeroes up to `max_size` like
+/// `FdtWriter::finish`.
+///
+/// # Arguments
+///
+/// `blob` - DTB to modify; trailing bytes past its `totalsize` are ignored.
+/// `overlay` - DTBO to apply.
+/// `protected_nodes` - absolute paths of nodes, e.g. "/chosen", which the overlay may neither
+///                     modify nor add, with any unit address, nor add children to.
+/// `max_size` - Maximum size of the modified DTB in bytes.
+pub fn apply_overlay(
+    blob: &[u8],
+    overlay: &[u8],
+    protected_nodes: &[&str],
+    max_size: usize,
+) -> Result<Vec<u8>> {
+    let mut nodes = parse_nodes(blob)?;
+    let mut overlay_nodes = parse_nodes(overlay)
+        .map_err(|e| Error::InvalidOverlay(format!("failed to parse the blob: {}", e)))?;
+
+    if let Some(fixups) = overlay_nodes.iter().find(|n| n.path == "/__fixups__") {
+        if let Some((label, _)) = fixups.properties.first() {
+            return Err(Error::InvalidOverlay(format!(
+                "references label {} outside of the overlay",
+                label
+            )));
+        }
+    }
+    let mut max_phandle = 0;
+    for node in &nodes {
+        if let Some(phandle) = node.property_u32("phandle")? {
+            max_phandle = max_phandle.max(phandle);
+        }
+    }
+    renumber_overlay_phandles(&mut overlay_nodes, max_phandle)?;
+
+    for (index, fragment) in overlay_nodes.iter().enumerate() {
+        // Only the children of the root node are fragments, apart from the metadata nodes like
+        // `__symbols__` generated by dtc.
+        if fragment.parent != Some(0) || fragment.path.starts_with("/__") {
+            continue;
+        }
+        let target = match fragment.property("target-path") {
+            Some(target) => target
+                .strip_suffix(&[0])
+                .and_then(|t| std::str::from_utf8(t).ok())
+                .ok_or_else(|| fragment.invalid_property("target-path"))?,
+            None if fragment.property("target").is_some() => {
+                return Err(Error::InvalidOverlay(format!(
+                    "{} targets a phandle instead of a path",
+                    fragment.path
+                )))
+            }
+            None => return Err(fragment.missing_property("target-path")),
+        };
+        let source = overlay_nodes
+            .iter()
+            .position(|n| n.parent == Some(index) && n.path.ends_with("/__overlay__"))
+            .ok_or_else(|| {
+                Error::InvalidOverlay(format!("{} has no __overlay__ node", fragment.path))
+            })?;
+        let target = nodes
+            .iter()
+            .position(|n| n.path == target)
+            .ok_or_else(|| Error::OverlayTargetNotFound(target.to_owned()))?;
+        merge_overlay_node(&mut nodes, target, &overlay_nodes, source, protected_nodes)?;
+    }
+
+    let header = BlobBlocks::new(blob)?.blob;
+    let mut reservations = Vec::new();
+    let mut pos = read_u32(header, 4 * 4)? as usize;
+    loop {
+        let address = (read_u32(header, pos)? as u64) << 32 | read_u32(header, pos + 4)? as u64;
+        let size = (read_u32(header, pos + 8)? as u64) << 32 | read_u32(header, pos + 12)? as u64;
+        if address == 0 && size == 0 {
+            break;
+        }
+        reservations.push(FdtReserveEntry { address, size });
+        pos += 16;
+    }
+    let mut fdt = FdtWriter::new(&reservations);
+    fdt.set_boot_cpuid_phys(read_u32(header, 7 * 4)?);
+
+    let mut children = vec![Vec::new(); nodes.len()];
+    for (index, node) in nodes.iter().enumerate().skip(1) {
+        children[node.parent.ok_or(Error::InvalidBlob)?].push(index);
+    }
+    write_node(&mut fdt, &nodes, &children, 0)?;
+    fdt.finish(max_size)
+}
+
+/// Adds `offset` to the phandles the overlay defines and to the references to them listed in its
+/// `__local_fixups__` node, which mirrors the overlay nodes holding the references and gives the
+/// byte offsets of the phandles in each referencing property.
+fn renumber_overlay_phandles(nodes: &mut [FdtNode], offset: u32) -> Result<()> {
+    let renumber = |val: &mut [u8], pos: usize| -> Result<()> {
+        let cell = val
+            .get_mut(pos..pos + 4)
+            .ok_or_else(|| Error::InvalidOverlay("phandle fixup out of bounds".to_owned()))?;
+        let phandle = u32::from_be_bytes((&*cell).try_into().unwrap())
+            .checked_add(offset)
+            .ok_or_else(|| Error::InvalidOverlay("too many phandles".to_owned()))?;
+        cell.copy_from_slice(&phandle.to_be_bytes());
+        Ok(())
+    };
+
+    let mut fixups = Vec::new();
+    for node in nodes.iter() {
+        if let Some(path) = node.path.strip_prefix("/__local_fixups__") {
+            for (property, _) in &node.properties {
+                let offsets = node
+                    .property_cells(property)?
+                    .ok_or_else(|| node.missing_property(property))?;
+                fixups.push((path.to_owned(), property.clone(), offsets));
+            }
+        }
+    }
+    for (path, property, offsets) in fixups {
+        let val = nodes
+            .iter_mut()
+            .find(|n| n.path == path)
+            .and_then(|n| n.properties.iter_mut().find(|(name, _)| *name == property))
+            .map(|(_, val)| val)
+            .ok_or_else(|| {
+                Error::InvalidOverlay(format!("fixup of missing property {}:{}", path, property))
+            })?;
+        for pos in offsets {
+            renumber(val, pos as usize)?;
+        }
+    }
+
+    for node in nodes.iter_mut() {
+        for (name, val) in node.properties.iter_mut() {
+            if name == "phandle" || name == "linux,phandle" {
+                renumber(val, 0)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns whether `path` is one of `protected_nodes`, possibly with a unit address, or a
+/// descendant of one.
+fn is_protected_node(path: &str, protected_nodes: &[&str]) -> bool {
+    protected_nodes.iter().any(|protected| {
+        path.strip_prefix(protected).map_or(false, |rest| {
+            rest.is_empty() || rest.starts_with('@') || rest.starts_with('/')
+        })
+    })
+}
+
+/// Merges node `source` of `overlay` and its descendants into node `target` of `nodes`.
+fn merge_overlay_node(
+    nodes: &mut Vec<FdtNode>,
+    target: usize,
+    overlay: &[FdtNode],
+    source: usize,
+    protected_nodes: &[&str],
+) -> Result<()> {
+    if !overlay[source].properties.is_empty()
+        && is_protected_node(&nodes[target].path, protected_nodes)
+    {
+        return Err(Error::OverlayModifiesProtectedNode(
+            nodes[target].path.clone(),
+        ));
+    }
+    for (name, val) in &overlay[source].properties {
+        match nodes[target].properties.iter_mut().find(|(n, _)| n == name) {
+            Some(property) => property.1 = val.clone(),
+            None => nodes[target].properties.push((name.clone(), val.clone())),
+        }
+    }
+
+    for (child, child_node) in overlay.iter().enumerate() {
+        if child_node.parent != Some(source) {
+            continue;
+        }
+        let name = child_node.path.rsplit('/').next().unwrap_or_default();
+        let path = match nodes[target].path.as_str() {
+            "/" => format!("/{}", name),
+            parent => format!("{}/{}", parent, name),
+        };
+        if is_protected_node(&path, protected_nodes) {
+            return Err(Error::OverlayModifiesProtectedNode(path));
+        }
+        let index = match nodes
+            .iter()
+            .position(|n| n.parent == Some(target) && n.path == path)
+        {
+            Some(index) => index,
+            None => {
+                nodes.push(FdtNode {
+                    path,
+                    parent: Some(target),
+                    properties: Vec::new(),
+                });
+                nodes.len() - 1
+            }
+        };
+        merge_overlay_node(nodes, index, overlay, child, protected_nodes)?;
+    }
+    Ok(())
+}
+
+/// Writes node `index` of `nodes` and its descendants, given the indices of the `children` of
+/// each node.
+fn write_node(
+    fdt: &mut FdtWriter,
+    nodes: &[FdtNode],
+    children: &[Vec<usize>],
+    index: usize,
+) -> Result<()> {
+    let node = &nodes[index];
+    let name = match node.parent {
+        None => "",
+        Some(_) => node.path.rsplit('/').next().unwrap_or_default(),
+    };
+    let fdt_node = fdt.begin_node(name)?;
+    for (name, val) in &node.properties {
+        fdt.property(name, val)?;
+    }
+    for &child in &children[index] {
+        write_node(fdt, nodes, children, child)?;
+    }
+    fdt.end_node(fdt_node)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -745,4 +1304,212 @@ mod tests {
         fdt.finish(0x100)
             .expect_err("finish without ending all nodes");
     }
+
+    fn chosen_fdt(bootargs: &str) -> Vec<u8> {
+        let mut fdt = FdtWriter::new(&[FdtReserveEntry {
+            address: 0x1000,
+            size: 0x2000,
+        }]);
+        let root_node = fdt.begin_node("").unwrap();
+        fdt.property_u32("#address-cells", 0x2).unwrap();
+        let chosen_node = fdt.begin_node("chosen").unwrap();
+        fdt.property_u32("linux,pci-probe-only", 1).unwrap();
+        fdt.property_string("bootargs", bootargs).unwrap();
+        fdt.property_u64("kaslr-seed", 0x1234).unwrap();
+        fdt.end_node(chosen_node).unwrap();
+        let nested_node = fdt.begin_node("nested").unwrap();
+        fdt.property_string("bootargs", "unrelated").unwrap();
+        fdt.end_node(nested_node).unwrap();
+        fdt.end_node(root_node).unwrap();
+        fdt.finish(0x200).unwrap()
+    }
+
+    #[test]
+    fn set_property_grow_and_shrink() {
+        let blob = chosen_fdt("panic=-1");
+        for bootargs in ["panic=-1 console=hvc0 root=/dev/vda", "", "a", "abc"] {
+            let val = CString::new(bootargs).unwrap();
+            assert_eq!(
+                set_property(&blob, "/chosen", "bootargs", val.to_bytes_with_nul(), 0x200).unwrap(),
+                chosen_fdt(bootargs)
+            );
+        }
+    }
+
+    #[test]
+    fn set_property_not_found() {
+        let blob = chosen_fdt("panic=-1");
+        assert!(matches!(
+            set_property(&blob, "/chosen", "missing", &[], 0x200),
+            Err(Error::PropertyNotFound(_))
+        ));
+        assert!(matches!(
+            set_property(&blob, "/", "bootargs", &[], 0x200),
+            Err(Error::PropertyNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn set_property_too_large() {
+        let blob = chosen_fdt("panic=-1");
+        assert!(matches!(
+            set_property(&blob, "/chosen", "bootargs", &[b'a'; 0x200], 0x200),
+            Err(Error::TotalSizeTooLarge)
+        ));
+    }
+
+    #[test]
+    fn parse_nodes_paths_and_properties() {
+        let nodes = parse_nodes(&chosen_fdt("panic=-1")).unwrap();
+        let paths: Vec<&str> = nodes.iter().map(|n| n.path.as_str()).collect();
+        assert_eq!(paths, ["/", "/chosen", "/nested"]);
+        assert_eq!(nodes[0].parent, None);
+        assert_eq!(nodes[1].parent, Some(0));
+        assert_eq!(nodes[0].property_u32("#address-cells").unwrap(), Some(2));
+        assert_eq!(nodes[1].property("bootargs"), Some(&b"panic=-1\0"[..]));
+        assert_eq!(
+            nodes[1].property_cells("kaslr-seed").unwrap(),
+            Some(vec![0, 0x1234])
+        );
+        assert!(matches!(
+            nodes[1].property_u32("kaslr-seed"),
+            Err(Error::InvalidProperty { .. })
+        ));
+        assert_eq!(nodes[2].property("missing"), None);
+    }
+
+    #[test]
+    fn set_property_invalid_blob() {
+        let mut blob = chosen_fdt("panic=-1");
+        blob[0] = 0;
+        assert!(matches!(
+            set_property(&blob, "/chosen", "bootargs", &[], 0x200),
+            Err(Error::InvalidBlob)
+        ));
+    }
+
+    /// Writes the contents of the `__overlay__` node of a fragment.
+    type OverlayContents<'a> = &'a dyn Fn(&mut FdtWriter);
+
+    /// Builds an overlay with a fragment for each `(target-path, contents)`.
+    fn overlay_fdt(fragments: &[(&str, OverlayContents)]) -> Vec<u8> {
+        let mut fdt = FdtWriter::new(&[]);
+        let root_node = fdt.begin_node("").unwrap();
+        for (index, (target, contents)) in fragments.iter().enumerate() {
+            let fragment_node = fdt.begin_node(&format!("fragment@{}", index)).unwrap();
+            fdt.property_string("target-path", target).unwrap();
+            let overlay_node = fdt.begin_node("__overlay__").unwrap();
+            contents(&mut fdt);
+            fdt.end_node(overlay_node).unwrap();
+            fdt.end_node(fragment_node).unwrap();
+        }
+        fdt.end_node(root_node).unwrap();
+        fdt.finish(0x400).unwrap()
+    }
+
+    #[test]
+    fn apply_overlay_adds_nodes_and_properties() {
+        let overlay = overlay_fdt(&[
+            ("/", &|fdt| {
+                let node = fdt.begin_node("vendor@1000").unwrap();
+                fdt.property_string("compatible", "vendor,device").unwrap();
+                fdt.end_node(node).unwrap();
+            }),
+            ("/nested", &|fdt| {
+                fdt.property_string("bootargs", "replaced").unwrap();
+                fdt.property_string("status", "okay").unwrap();
+            }),
+        ]);
+        let blob = apply_overlay(&chosen_fdt("panic=-1"), &overlay, &[], 0x400).unwrap();
+        assert_eq!(blob.len(), 0x400);
+
+        let nodes = parse_nodes(&blob).unwrap();
+        let paths: Vec<&str> = nodes.iter().map(|n| n.path.as_str()).collect();
+        assert_eq!(paths, ["/", "/chosen", "/nested", "/vendor@1000"]);
+        assert_eq!(nodes[1].property("bootargs"), Some(&b"panic=-1\0"[..]));
+        assert_eq!(nodes[2].property("bootargs"), Some(&b"replaced\0"[..]));
+        assert_eq!(nodes[2].property("status"), Some(&b"okay\0"[..]));
+        assert_eq!(
+            nodes[3].property("compatible"),
+            Some(&b"vendor,device\0"[..])
+        );
+        // The memory reservations of the original blob are kept.
+        assert_eq!(blob[0x28..0x38], chosen_fdt("panic=-1")[0x28..0x38]);
+
+        assert!(matches!(
+            apply_overlay(&chosen_fdt("panic=-1"), &overlay, &[], 0x100),
+            Err(Error::TotalSizeTooLarge)
+        ));
+    }
+
+    #[test]
+    fn apply_overlay_protected_nodes() {
+        let protected = ["/chosen", "/memory"];
+        let overlay = overlay_fdt(&[("/chosen", &|fdt| {
+            fdt.property_string("bootargs", "console=ttyS0").unwrap();
+        })]);
+        assert!(matches!(
+            apply_overlay(&chosen_fdt("panic=-1"), &overlay, &protected, 0x400),
+            Err(Error::OverlayModifiesProtectedNode(path)) if path == "/chosen"
+        ));
+
+        let overlay = overlay_fdt(&[("/", &|fdt| {
+            let node = fdt.begin_node("memory@80000000").unwrap();
+            fdt.property_string("device_type", "memory").unwrap();
+            fdt.end_node(node).unwrap();
+        })]);
+        assert!(matches!(
+            apply_overlay(&chosen_fdt("panic=-1"), &overlay, &protected, 0x400),
+            Err(Error::OverlayModifiesProtectedNode(path)) if path == "/memory@80000000"
+        ));
+
+        let overlay = overlay_fdt(&[("/missing", &|_| {})]);
+        assert!(matches!(
+            apply_overlay(&chosen_fdt("panic=-1"), &overlay, &protected, 0x400),
+            Err(Error::OverlayTargetNotFound(path)) if path == "/missing"
+        ));
+    }
+
+    #[test]
+    fn apply_overlay_renumbers_phandles() {
+        let mut fdt = FdtWriter::new(&[]);
+        let root_node = fdt.begin_node("").unwrap();
+        let gic_node = fdt.begin_node("intc").unwrap();
+        fdt.property_u32("phandle", 1).unwrap();
+        fdt.end_node(gic_node).unwrap();
+        fdt.end_node(root_node).unwrap();
+        let base = fdt.finish(0x400).unwrap();
+
+        let mut fdt = FdtWriter::new(&[]);
+        let root_node = fdt.begin_node("").unwrap();
+        let fragment_node = fdt.begin_node("fragment@0").unwrap();
+        fdt.property_string("target-path", "/").unwrap();
+        let overlay_node = fdt.begin_node("__overlay__").unwrap();
+        let provider_node = fdt.begin_node("provider").unwrap();
+        fdt.property_u32("phandle", 1).unwrap();
+        fdt.end_node(provider_node).unwrap();
+        let consumer_node = fdt.begin_node("consumer").unwrap();
+        fdt.property_array_u32("clocks", &[1, 7]).unwrap();
+        fdt.end_node(consumer_node).unwrap();
+        fdt.end_node(overlay_node).unwrap();
+        fdt.end_node(fragment_node).unwrap();
+        let fixups_node = fdt.begin_node("__local_fixups__").unwrap();
+        let fragment_node = fdt.begin_node("fragment@0").unwrap();
+        let overlay_node = fdt.begin_node("__overlay__").unwrap();
+        let consumer_node = fdt.begin_node("consumer").unwrap();
+        fdt.property_u32("clocks", 0).unwrap();
+        fdt.end_node(consumer_node).unwrap();
+        fdt.end_node(overlay_node).unwrap();
+        fdt.end_node(fragment_node).unwrap();
+        fdt.end_node(fixups_node).unwrap();
+        fdt.end_node(root_node).unwrap();
+        let overlay = fdt.finish(0x400).unwrap();
+
+        let nodes = parse_nodes(&apply_overlay(&base, &overlay, &[], 0x400).unwrap()).unwrap();
+        let paths: Vec<&str> = nodes.iter().map(|n| n.path.as_str()).collect();
+        assert_eq!(paths, ["/", "/intc", "/provider", "/consumer"]);
+        assert_eq!(nodes[1].property_u32("phandle").unwrap(), Some(1));
+        assert_eq!(nodes[2].property_u32("phandle").unwrap(), Some(2));
+        assert_eq!(nodes[3].property_cells("clocks").unwrap(), Some(vec![2, 7]));
+    }
 }
@@ -0,0 +1,276 @@
+// Copyright 2022 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Streaming image loader that copies kernels, initrds and firmware into guest memory in chunks
+//! from an async executor, so large images report progress and oversized ones are rejected as
+//! soon as they go over their size limit.
+
+use std::cmp::min;
+use std::fs::File;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::sync::Arc;
+
+use base::info;
+use cros_async::BackingMemory;
+use cros_async::Executor;
+use cros_async::IoSourceExt;
+use cros_async::MemRegion;
+use cros_async::ReadAsync;
+use vm_memory::GuestAddress;
+use vm_memory::GuestMemory;
+use vm_memory::GuestMemoryError;
+
+use crate::LoadImageError;
+
+/// Size of the reads issued by `load_image_async`.
+const LOAD_IMAGE_CHUNK_SIZE: u64 = 1 << 20;
+
+/// Images smaller than this are loaded without logging progress.
+const LOG_PROGRESS_MIN_SIZE: u64 = 64 << 20;
+
+/// Progress of an image being loaded by `load_image_async`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LoadImageProgress {
+    /// Number of bytes loaded so far.
+    pub loaded: u64,
+    /// Size of the image, if known before loading it.
+    pub total: Option<u64>,
+}
+
+// Returns the number of bytes between `addr` and the end of the memory region containing it.
+fn region_remaining(guest_mem: &GuestMemory, addr: GuestAddress) -> Option<u64> {
+    guest_mem.regions().find_map(|region| {
+        let end = region.guest_addr.offset() + region.size as u64;
+        if addr >= region.guest_addr && addr.offset() < end {
+            Some(end - addr.offset())
+        } else {
+            None
+        }
+    })
+}
+
+/// Loads an image into guest memory at `guest_addr`, reading `image` sequentially from its current
+/// position until end of file.
+///
+/// Loading fails as soon as the image is found to be bigger than `max_size` bytes or than the
+/// guest memory available at `guest_addr`, without reading the rest of it. `progress` is called
+/// after each chunk is loaded, with `total` set to `size_hint`.
+///
+/// The size in bytes of the loaded image is returned.
+pub async fn load_image_async<F>(
+    guest_mem: &GuestMemory,
+    image: &dyn IoSourceExt<F>,
+    size_hint: Option<u64>,
+    guest_addr: GuestAddress,
+    max_size: u64,
+    progress: &mut dyn FnMut(LoadImageProgress),
+) -> Result<usize, LoadImageError> {
+    let mem: Arc<dyn BackingMemory + Send + Sync> = Arc::new(guest_mem.clone());
+    let mut loaded: u64 = 0;
+    loop {
+        let addr = guest_addr
+            .checked_add(loaded)
+            .ok_or(LoadImageError::ImageSizeTooLarge(loaded))?;
+        let room = min(
+            max_size - loaded,
+            region_remaining(guest_mem, addr).unwrap_or(0),
+        );
+
+        if room == 0 {
+            // Out of space: the image must end here. Probe for one more byte to find out.
+            let (extra, _) = image
+                .read_to_vec(None, vec![0u8; 1])
+                .await
+                .map_err(LoadImageError::ReadAsync)?;
+            if extra == 0 {
+                break;
+            }
+            if loaded == max_size {
+                return Err(LoadImageError::ImageSizeTooLarge(
+                    size_hint.unwrap_or(loaded + extra as u64),
+                ));
+            }
+            return Err(LoadImageError::ReadToMemory(
+                GuestMemoryError::InvalidGuestAddress(addr),
+            ));
+        }
+
+        let len = min(room, LOAD_IMAGE_CHUNK_SIZE) as usize;
+        let read = image
+            .read_to_mem(
+                None,
+                mem.clone(),
+                &[MemRegion {
+                    offset: addr.offset(),
+                    len,
+                }],
+            )
+            .await
+            .map_err(LoadImageError::ReadAsync)?;
+        if read == 0 {
+            break;
+        }
+        loaded += read as u64;
+        progress(LoadImageProgress {
+            loaded,
+            total: size_hint,
+        });
+    }
+
+    usize::try_from(loaded).map_err(|_| LoadImageError::ImageSizeTooLarge(loaded))
+}
+
+/// Loads `image` into guest memory at `guest_addr` like `load_image`, but streams it with
+/// `load_image_async` and logs the progress of large images, naming them `name`.
+pub fn load_image_with_progress(
+    guest_mem: &GuestMemory,
+    image: &mut File,
+    guest_addr: GuestAddress,
+    max_size: u64,
+    name: &str,
+) -> Result<usize, LoadImageError> {
+    let size = image.seek(SeekFrom::End(0)).map_err(LoadImageError::Seek)?;
+    if size > max_size {
+        return Err(LoadImageError::ImageSizeTooLarge(size));
+    }
+    image
+        .seek(SeekFrom::Start(0))
+        .map_err(LoadImageError::Seek)?;
+
+    let ex = Executor::new().map_err(LoadImageError::ReadAsync)?;
+    let source = ex
+        .async_from(image.try_clone().map_err(LoadImageError::CloneImage)?)
+        .map_err(LoadImageError::ReadAsync)?;
+
+    let mut last_percent = 0;
+    let mut log_progress = |progress: LoadImageProgress| {
+        if size < LOG_PROGRESS_MIN_SIZE {
+            return;
+        }
+        let percent = progress.loaded * 100 / size;
+        if percent / 10 > last_percent / 10 {
+            info!("loading {}: {}% of {} bytes", name, percent, size);
+            last_percent = percent;
+        }
+    };
+
+    ex.run_until(load_image_async(
+        guest_mem,
+        source.as_ref(),
+        Some(size),
+        guest_addr,
+        max_size,
+        &mut log_progress,
+    ))
+    .map_err(LoadImageError::ReadAsync)?
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::io::Write;
+    use std::thread;
+    use std::time::Duration;
+
+    use base::pipe;
+
+    use super::*;
+
+    const MEM_START: u64 = 0x10000;
+    const MEM_SIZE: u64 = 0x40_0000;
+
+    // Returns the read end of a pipe that receives `data` in `chunk` sized writes, with a delay
+    // before each one.
+    fn slow_reader(data: Vec<u8>, chunk: usize) -> (File, thread::JoinHandle<()>) {
+        let (read, mut write) = pipe(true).unwrap();
+        let writer = thread::spawn(move || {
+            for c in data.chunks(chunk) {
+                thread::sleep(Duration::from_millis(5));
+                // The reader may stop early when the image is too large.
+                if write.write_all(c).is_err() {
+                    return;
+                }
+            }
+        });
+        (read, writer)
+    }
+
+    fn load(
+        data: Vec<u8>,
+        guest_addr: u64,
+        max_size: u64,
+    ) -> (
+        GuestMemory,
+        Result<usize, LoadImageError>,
+        Vec<LoadImageProgress>,
+    ) {
+        let guest_mem = GuestMemory::new(&[(GuestAddress(MEM_START), MEM_SIZE)]).unwrap();
+        let (read, writer) = slow_reader(data, 0x8000);
+        let ex = Executor::new().unwrap();
+        let source = ex.async_from(read).unwrap();
+        let mut progress = Vec::new();
+        let result = ex
+            .run_until(load_image_async(
+                &guest_mem,
+                source.as_ref(),
+                None,
+                GuestAddress(guest_addr),
+                max_size,
+                &mut |p| progress.push(p),
+            ))
+            .unwrap();
+        drop(source);
+        writer.join().unwrap();
+        (guest_mem, result, progress)
+    }
+
+    #[test]
+    fn loads_slow_image() {
+        let data: Vec<u8> = (0..0x2_1000).map(|i| i as u8).collect();
+        let (guest_mem, result, progress) = load(data.clone(), MEM_START + 0x1000, u64::MAX);
+
+        assert_eq!(result.unwrap(), data.len());
+        let mut loaded = vec![0u8; data.len()];
+        guest_mem
+            .read_exact_at_addr(&mut loaded, GuestAddress(MEM_START + 0x1000))
+            .unwrap();
+        assert_eq!(loaded, data);
+
+        // The image trickles in, so it is loaded over several reads.
+        assert!(progress.len() > 1);
+        assert!(progress.windows(2).all(|p| p[0].loaded < p[1].loaded));
+        assert_eq!(
+            progress.last(),
+            Some(&LoadImageProgress {
+                loaded: data.len() as u64,
+                total: None,
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_image_over_max_size() {
+        let (_, result, progress) = load(vec![0x5a; 0x2_0000], MEM_START, 0x1_0000);
+
+        match result {
+            Err(LoadImageError::ImageSizeTooLarge(_)) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+        // Nothing past the limit was loaded.
+        assert_eq!(progress.last().unwrap().loaded, 0x1_0000);
+    }
+
+    #[test]
+    fn rejects_image_past_end_of_memory() {
+        let guest_addr = MEM_START + MEM_SIZE - 0x1000;
+        let (_, result, _) = load(vec![0x5a; 0x2000], guest_addr, u64::MAX);
+
+        match result {
+            Err(LoadImageError::ReadToMemory(GuestMemoryError::InvalidGuestAddress(addr))) => {
+                assert_eq!(addr, GuestAddress(MEM_START + MEM_SIZE))
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+}
@@ -5,9 +5,14 @@
 //! Virtual machine architecture support code.
 
 pub mod android;
+pub mod cpu_cache;
 pub mod fdt;
+mod image_loader;
+pub mod metrics_page;
 pub mod pstore;
+pub mod pvtime;
 pub mod serial;
+mod static_mmio;
 
 pub mod sys;
 
@@ -38,6 +43,7 @@ use devices::BusDevice;
 use devices::BusDeviceObj;
 use devices::BusError;
 use devices::BusResumeDevice;
+use devices::ConsoleBuffer;
 use devices::HotPlugBus;
 use devices::IrqChip;
 #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
@@ -75,6 +81,7 @@ use hypervisor::Hypervisor as HypervisorArch;
 #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
 use hypervisor::HypervisorX86_64 as HypervisorArch;
 use hypervisor::IoEventAddress;
+use hypervisor::MemCacheType;
 #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
 use hypervisor::VcpuAArch64 as VcpuArch;
 #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
@@ -88,28 +95,46 @@ use hypervisor::Vm;
 use hypervisor::VmAArch64 as VmArch;
 #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
 use hypervisor::VmX86_64 as VmArch;
+pub use image_loader::load_image_async;
+pub use image_loader::load_image_with_progress;
+pub use image_loader::LoadImageProgress;
+use metrics_page::MetricsPage;
 #[cfg(unix)]
 use minijail::Minijail;
+use pvtime::PvtimeRegion;
 use remain::sorted;
-#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
 use resources::AddressRange;
 use resources::SystemAllocator;
 use resources::SystemAllocatorConfig;
 use serde::Deserialize;
 use serde::Serialize;
+pub use serial::add_serial_device;
 pub use serial::add_serial_devices;
+pub use serial::check_serial_parameters;
 pub use serial::get_serial_cmdline;
+pub use serial::reopen_serial_output;
+pub use serial::send_serial_input;
 pub use serial::set_default_serial_parameters;
 pub use serial::GetSerialCmdlineError;
+pub use serial::InvalidSerialParameters;
+pub use serial::SerialOutputFile;
+pub use serial::SerialParameterError;
+pub use serial::SerialPortHandles;
 pub use serial::SERIAL_ADDR;
+pub use static_mmio::StaticMmioMap;
 use sync::Mutex;
 use thiserror::Error;
+use uuid::Uuid;
 use vm_control::BatControl;
 use vm_control::BatteryType;
+use vm_control::BootMilestones;
 use vm_control::PmResource;
+use vm_control::SetKernelCmdlineError;
+use vm_control::StaticMmioRegion;
 use vm_memory::GuestAddress;
 use vm_memory::GuestMemory;
 use vm_memory::GuestMemoryError;
+use vm_memory::MemoryAccessPolicy;
 
 pub enum VmImage {
     Kernel(File),
@@ -122,6 +147,32 @@ pub struct Pstore {
     pub size: u32,
 }
 
+/// A persistent memory region placed by the architecture's memory layout, outside of RAM.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PmemRegion {
+    pub size: u64,
+    /// Whether the region is part of guest memory and described by a reserved-memory node in the
+    /// device tree, for the guest to map it directly. Otherwise a virtio-pmem device describes it.
+    pub reserved_memory: bool,
+}
+
+/// Where to place the flattened device tree in guest memory, on architectures that boot with one.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub enum FdtPosition {
+    /// At the architecture's default location, near the end of RAM.
+    End,
+    /// Right after the kernel (or BIOS) image. The initrd, if any, follows the device tree.
+    AfterKernel,
+    /// At the given guest physical address.
+    Address(u64),
+}
+
+impl Default for FdtPosition {
+    fn default() -> Self {
+        Self::End
+    }
+}
+
 /// Mapping of guest VCPU threads to host CPU cores.
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub enum VcpuAffinity {
@@ -134,21 +185,39 @@ pub enum VcpuAffinity {
     PerVcpu(BTreeMap<usize, Vec<usize>>),
 }
 
+/// Host placement of the VCPUs of one of the `VmComponents::cpu_clusters`, e.g. to run the VCPUs
+/// of a big cluster on the big cores of the host.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub struct ClusterAffinity {
+    /// The host CPU cores the VCPU threads of the cluster will be allowed to run on.
+    pub host_cpus: Vec<usize>,
+    /// Whether the VCPU threads of the cluster run with real-time priority, as if they were listed
+    /// in `VmComponents::rt_cpus`.
+    pub rt: bool,
+}
+
 /// Holds the pieces needed to build a VM. Passed to `build_vm` in the `LinuxArch` trait below to
 /// create a `RunnableLinuxVm`.
 #[sorted]
 pub struct VmComponents {
     pub acpi_sdts: Vec<SDT>,
     pub android_fstab: Option<File>,
+    pub boot_milestones: BootMilestones,
+    /// Placement of the VCPUs of `cpu_clusters`, keyed by cluster index. Expanded by `build_vm`
+    /// into a per-VCPU `vcpu_affinity`, which must not be set as well.
+    pub cluster_affinity: BTreeMap<usize, ClusterAffinity>,
+    pub cpu_caches: Vec<cpu_cache::CpuCache>,
     pub cpu_capacity: BTreeMap<usize, u32>,
     pub cpu_clusters: Vec<Vec<usize>>,
     pub delay_rt: bool,
+    pub device_tree_overlay: Option<File>,
     #[cfg(feature = "direct")]
     pub direct_fixed_evts: Vec<devices::ACPIPMFixedEvent>,
     #[cfg(feature = "direct")]
     pub direct_gpe: Vec<u32>,
     pub dmi_path: Option<PathBuf>,
     pub extra_kernel_params: Vec<String>,
+    pub fdt_position: FdtPosition,
     #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
     pub force_s2idle: bool,
     #[cfg(all(any(target_arch = "x86_64", target_arch = "aarch64"), feature = "gdb"))]
@@ -159,6 +228,8 @@ pub struct VmComponents {
     pub initrd_image: Option<File>,
     pub itmt: bool,
     pub memory_size: u64,
+    /// Whether to set up a metrics page in guest memory.
+    pub metrics_page: bool,
     pub no_i8042: bool,
     pub no_rtc: bool,
     pub no_smt: bool,
@@ -170,38 +241,68 @@ pub struct VmComponents {
     pub pcie_ecam: Option<AddressRange>,
     pub pflash_block_size: u32,
     pub pflash_image: Option<File>,
+    #[cfg(target_arch = "aarch64")]
+    pub pmem_regions: Vec<PmemRegion>,
+    /// Whether to expose a PMU to the guest, or `None` to expose it if the hypervisor supports it.
+    #[cfg(target_arch = "aarch64")]
+    pub pmu: Option<bool>,
     pub pstore: Option<Pstore>,
     /// A file to load as pVM firmware. Must be `Some` iff
     /// `hv_cfg.protection_type == ProtectionType::UnprotectedWithFirmware`.
     pub pvm_fw: Option<File>,
     pub rt_cpus: Vec<usize>,
+    /// Fail `build_vm` if the interrupt of an optional device can't be set up, instead of
+    /// running the device without it.
+    pub strict_irqs: bool,
     pub swiotlb: Option<u64>,
     pub vcpu_affinity: Option<VcpuAffinity>,
     pub vcpu_count: usize,
     pub vm_image: VmImage,
+    /// Identifies the VM to the guest and to clients of the control socket.
+    pub vm_uuid: Uuid,
 }
 
 /// Holds the elements needed to run a Linux VM. Created by `build_vm`.
 #[sorted]
 pub struct RunnableLinuxVm<V: VmArch, Vcpu: VcpuArch> {
-    pub bat_control: Option<BatControl>,
+    pub bat_control: Vec<BatControl>,
+    pub boot_milestones: BootMilestones,
     pub delay_rt: bool,
+    /// Optional devices running without an interrupt because it couldn't be set up.
+    pub degraded_devices: Vec<String>,
+    /// Where the flattened device tree the guest boots with was written, if it is at a fixed
+    /// address.
+    pub fdt_address: Option<GuestAddress>,
     #[cfg(all(any(target_arch = "x86_64", target_arch = "aarch64"), feature = "gdb"))]
     pub gdb: Option<(u32, Tube)>,
     pub has_bios: bool,
     pub hotplug_bus: BTreeMap<u8, Arc<Mutex<dyn HotPlugBus>>>,
     pub io_bus: Arc<Bus>,
     pub irq_chip: Box<dyn IrqChipArch>,
+    pub metrics_page: Option<MetricsPage>,
     pub mmio_bus: Arc<Bus>,
     pub no_smt: bool,
     pub pid_debug_label_map: BTreeMap<u32, String>,
     #[cfg(unix)]
     pub platform_devices: Vec<Arc<Mutex<dyn BusDevice>>>,
     pub pm: Option<Arc<Mutex<dyn PmResource>>>,
+    /// The stolen time structures of the vcpus, if the hypervisor provides them.
+    pub pvtime: Option<PvtimeRegion>,
+    /// The pstore region mapped from a file, if any.
+    pub ramoops_region: Option<pstore::RamoopsRegion>,
     /// Devices to be notified before the system resumes from the S3 suspended state.
     pub resume_notify_devices: Vec<Arc<Mutex<dyn BusResumeDevice>>>,
     pub root_config: Arc<Mutex<PciRoot>>,
     pub rt_cpus: Vec<usize>,
+    /// The buffers recording the output of the `SerialHardware::Serial` ports with a
+    /// `console_buffer`, by port number.
+    pub serial_console_buffers: BTreeMap<u8, ConsoleBuffer>,
+    /// The tubes injecting input into the `SerialHardware::Serial` ports, by port number.
+    pub serial_inputs: BTreeMap<u8, Tube>,
+    /// The output files of the `SerialHardware::Serial` ports writing to one, by port number.
+    pub serial_output_files: BTreeMap<u8, SerialOutputFile>,
+    /// The devices the architecture code placed at fixed MMIO addresses.
+    pub static_mmio_map: Vec<StaticMmioRegion>,
     pub suspend_evt: Event,
     pub vcpu_affinity: Option<VcpuAffinity>,
     pub vcpu_count: usize,
@@ -210,6 +311,8 @@ pub struct RunnableLinuxVm<V: VmArch, Vcpu: VcpuArch> {
     /// If it's Some, then `build_vm` already created the vcpus.
     pub vcpus: Option<Vec<Vcpu>>,
     pub vm: V,
+    /// The tube serving `VmwdtCommand`s for the virtual watchdog of the vcpus, if there is one.
+    pub vmwdt_control: Option<Tube>,
 }
 
 /// The device and optional jail.
@@ -233,6 +336,19 @@ pub trait LinuxArch {
         components: &VmComponents,
     ) -> std::result::Result<Vec<(GuestAddress, u64)>, Self::Error>;
 
+    /// Returns the regions of the layout from `guest_memory_layout`, by start address, that the
+    /// host must not access freely, with what it may do with them. Other regions are readable
+    /// and writable.
+    ///
+    /// # Arguments
+    ///
+    /// * `components` - Parts used to determine the memory layout.
+    fn guest_memory_access_policies(
+        _components: &VmComponents,
+    ) -> Vec<(GuestAddress, MemoryAccessPolicy)> {
+        Vec::new()
+    }
+
     /// Gets the configuration for a new `SystemAllocator` that fits the given `Vm`'s memory layout.
     ///
     /// This is the per-architecture template for constructing the `SystemAllocator`. Platform
@@ -255,7 +371,7 @@ pub trait LinuxArch {
     ///   `get_system_allocator_config`.
     /// * `serial_parameters` - Definitions for how the serial devices should be configured.
     /// * `serial_jail` - Jail used for serial devices created here.
-    /// * `battery` - Defines what battery device will be created.
+    /// * `batteries` - The battery devices to create, with the jail of each.
     /// * `vm` - A VM implementation to build upon.
     /// * `ramoops_region` - Region allocated for ramoops.
     /// * `devices` - The devices to be built into the VM.
@@ -268,7 +384,7 @@ pub trait LinuxArch {
         system_allocator: &mut SystemAllocator,
         serial_parameters: &BTreeMap<(SerialHardware, u8), SerialParameters>,
         serial_jail: Option<Minijail>,
-        battery: (Option<BatteryType>, Option<Minijail>),
+        batteries: Vec<(BatteryType, Option<Minijail>)>,
         vm: V,
         ramoops_region: Option<pstore::RamoopsRegion>,
         devices: Vec<(Box<dyn BusDeviceObj>, Option<Minijail>)>,
@@ -314,6 +430,21 @@ pub trait LinuxArch {
         resources: &mut SystemAllocator,
         hp_control_tube: &mpsc::Sender<PciRootCommand>,
     ) -> Result<PciAddress, Self::Error>;
+
+    /// Replaces the kernel command line the guest will boot with.
+    ///
+    /// `cmdline` replaces the whole command line, including the parameters crosvm added to it.
+    /// This must be called before any vcpu runs, as the guest only reads the command line while
+    /// booting.
+    ///
+    /// # Arguments
+    ///
+    /// * `linux` - The VM created by `build_vm`.
+    /// * `cmdline` - The new kernel command line.
+    fn set_kernel_cmdline<V: VmArch, Vcpu: VcpuArch>(
+        linux: &RunnableLinuxVm<V, Vcpu>,
+        cmdline: &str,
+    ) -> Result<(), SetKernelCmdlineError>;
 }
 
 #[cfg(all(any(target_arch = "x86_64", target_arch = "aarch64"), feature = "gdb"))]
@@ -425,6 +556,24 @@ pub enum DeviceRegistrationError {
     /// Could not add a device to the mmio bus.
     #[error("failed to add to mmio bus: {0}")]
     MmioInsert(BusError),
+    /// A device was given an empty or overflowing fixed MMIO range.
+    #[error("{name} has an invalid MMIO range of {len:#x} bytes at {base:#x}")]
+    MmioInvalidRange { name: String, base: u64, len: u64 },
+    /// A device was placed at a fixed MMIO range overlapping another device.
+    #[error("{name} at {range} overlaps {other_name} at {other_range}")]
+    MmioOverlap {
+        name: String,
+        range: AddressRange,
+        other_name: String,
+        other_range: AddressRange,
+    },
+    /// The platform MMIO window is too small for a device's regions.
+    #[error("platform MMIO window {window} has no room for {size:#x} bytes for {device}")]
+    PlatformMmioExhausted {
+        device: String,
+        size: u64,
+        window: AddressRange,
+    },
     #[cfg(unix)]
     /// Failed to initialize proxy device for jailed device.
     #[error("failed to create proxy device: {0}")]
@@ -915,6 +1064,7 @@ pub fn generate_pci_root(
                     Box::new(mmap),
                     false,
                     false,
+                    MemCacheType::Cached,
                 );
             }
         }
@@ -957,8 +1107,12 @@ pub fn generate_pci_root(
 pub enum LoadImageError {
     #[error("Alignment not a power of two: {0}")]
     BadAlignment(u64),
+    #[error("Failed to clone image file: {0}")]
+    CloneImage(io::Error),
     #[error("Image size too large: {0}")]
     ImageSizeTooLarge(u64),
+    #[error("Reading image failed: {0}")]
+    ReadAsync(cros_async::AsyncError),
     #[error("Reading image into memory failed: {0}")]
     ReadToMemory(GuestMemoryError),
     #[error("Seek failed: {0}")]
@@ -998,7 +1152,7 @@ where
         .map_err(LoadImageError::Seek)?;
 
     guest_mem
-        .read_to_memory(guest_addr, image, size)
+        .read_to_memory_exact(guest_addr, image, size)
         .map_err(LoadImageError::ReadToMemory)?;
 
     Ok(size)
@@ -1049,7 +1203,7 @@ where
     let size = size as usize;
 
     guest_mem
-        .read_to_memory(guest_addr, image, size)
+        .read_to_memory_exact(guest_addr, image, size)
         .map_err(LoadImageError::ReadToMemory)?;
 
     Ok((guest_addr, size))
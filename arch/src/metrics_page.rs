@@ -0,0 +1,318 @@
+// Copyright 2022 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! A page of guest memory in which the VMM publishes metrics records for the guest to read.
+//!
+//! The layout is documented for guest authors in `docs/book/src/appendix/metrics_page.md`. All
+//! fields are little-endian.
+//!
+//! The page starts with a 64 byte header:
+//!
+//! | Offset | Type  | Field          |
+//! | ------ | ----- | -------------- |
+//! | 0      | u32   | magic ("CVMP") |
+//! | 4      | u32   | layout version |
+//! | 8      | u32   | record size    |
+//! | 12     | u32   | record count   |
+//!
+//! followed by `record count` records of `record size` bytes:
+//!
+//! | Offset | Type  | Field                                        |
+//! | ------ | ----- | -------------------------------------------- |
+//! | 0      | u32   | sequence number, odd while being updated     |
+//! | 4      | u32   | record id, 0 if the slot is unused           |
+//! | 8      | u64   | generation, incremented by every update      |
+//! | 16     | u64   | value                                        |
+//!
+//! Each record is protected by its own seqlock: readers retry until they see the same even
+//! sequence number before and after reading the record.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::fence;
+use std::sync::atomic::Ordering;
+
+use data_model::DataInit;
+use remain::sorted;
+use thiserror::Error;
+use vm_memory::GuestAddress;
+use vm_memory::GuestMemory;
+use vm_memory::GuestMemoryError;
+
+/// Size of the metrics page.
+pub const METRICS_PAGE_SIZE: u64 = 0x1000;
+
+/// Value of the header's magic field.
+pub const METRICS_PAGE_MAGIC: u32 = u32::from_le_bytes(*b"CVMP");
+/// Version of the layout described in this module.
+pub const METRICS_PAGE_VERSION: u32 = 1;
+
+const HEADER_SIZE: u64 = 64;
+const RECORD_SIZE: u64 = 32;
+const RECORD_COUNT: u64 = (METRICS_PAGE_SIZE - HEADER_SIZE) / RECORD_SIZE;
+
+const HEADER_MAGIC_OFFSET: u64 = 0;
+const HEADER_VERSION_OFFSET: u64 = 4;
+const HEADER_RECORD_SIZE_OFFSET: u64 = 8;
+const HEADER_RECORD_COUNT_OFFSET: u64 = 12;
+
+const RECORD_SEQ_OFFSET: u64 = 0;
+const RECORD_ID_OFFSET: u64 = 4;
+const RECORD_GENERATION_OFFSET: u64 = 8;
+const RECORD_VALUE_OFFSET: u64 = 16;
+
+#[sorted]
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("record id 0 is reserved for unused records")]
+    InvalidId,
+    #[error("failed to access the metrics page: {0}")]
+    MemoryAccess(#[source] GuestMemoryError),
+    #[error("all {0} metrics records are in use")]
+    PageFull(u64),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// A metrics record as read by the guest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MetricsRecord {
+    pub id: u32,
+    pub generation: u64,
+    pub value: u64,
+}
+
+/// The VMM side of the metrics page, which is its only writer.
+pub struct MetricsPage {
+    mem: GuestMemory,
+    addr: GuestAddress,
+    // Index of the record used for each id.
+    slots: BTreeMap<u32, u64>,
+}
+
+impl MetricsPage {
+    /// Initializes the metrics page at `addr` in `mem` with no records.
+    pub fn new(mem: GuestMemory, addr: GuestAddress) -> Result<MetricsPage> {
+        mem.write_all_at_addr(&[0u8; METRICS_PAGE_SIZE as usize], addr)
+            .map_err(Error::MemoryAccess)?;
+        let page = MetricsPage {
+            mem,
+            addr,
+            slots: BTreeMap::new(),
+        };
+        page.store(HEADER_MAGIC_OFFSET, METRICS_PAGE_MAGIC)?;
+        page.store(HEADER_VERSION_OFFSET, METRICS_PAGE_VERSION)?;
+        page.store(HEADER_RECORD_SIZE_OFFSET, RECORD_SIZE as u32)?;
+        page.store(HEADER_RECORD_COUNT_OFFSET, RECORD_COUNT as u32)?;
+        Ok(page)
+    }
+
+    /// Sets the value of the record `id`, adding the record if it doesn't exist yet.
+    pub fn update(&mut self, id: u32, value: u64) -> Result<()> {
+        if id == 0 {
+            return Err(Error::InvalidId);
+        }
+        let next_slot = self.slots.len() as u64;
+        let slot = match self.slots.get(&id) {
+            Some(&slot) => slot,
+            None if next_slot < RECORD_COUNT => next_slot,
+            None => return Err(Error::PageFull(RECORD_COUNT)),
+        };
+        let record = HEADER_SIZE + slot * RECORD_SIZE;
+
+        let seq: u32 = self.load(record + RECORD_SEQ_OFFSET)?;
+        let generation: u64 = self.load(record + RECORD_GENERATION_OFFSET)?;
+        self.store(record + RECORD_SEQ_OFFSET, seq.wrapping_add(1))?;
+        fence(Ordering::Release);
+        self.store(record + RECORD_ID_OFFSET, id)?;
+        self.store(
+            record + RECORD_GENERATION_OFFSET,
+            generation.wrapping_add(1),
+        )?;
+        self.store(record + RECORD_VALUE_OFFSET, value)?;
+        fence(Ordering::Release);
+        self.store(record + RECORD_SEQ_OFFSET, seq.wrapping_add(2))?;
+
+        self.slots.insert(id, slot);
+        Ok(())
+    }
+
+    /// Reads the record `id` from the metrics page at `addr` the way a guest would, returning
+    /// `None` if it doesn't exist.
+    pub fn read(mem: &GuestMemory, addr: GuestAddress, id: u32) -> Result<Option<MetricsRecord>> {
+        let load_u32 = |offset| -> Result<u32> {
+            Ok(mem
+                .get_ref_at_addr(addr.unchecked_add(offset))
+                .map_err(Error::MemoryAccess)?
+                .load())
+        };
+        let load_u64 = |offset| -> Result<u64> {
+            Ok(mem
+                .get_ref_at_addr(addr.unchecked_add(offset))
+                .map_err(Error::MemoryAccess)?
+                .load())
+        };
+
+        for slot in 0..u64::from(load_u32(HEADER_RECORD_COUNT_OFFSET)?) {
+            let record = HEADER_SIZE + slot * RECORD_SIZE;
+            loop {
+                let seq = load_u32(record + RECORD_SEQ_OFFSET)?;
+                if seq % 2 != 0 {
+                    std::hint::spin_loop();
+                    continue;
+                }
+                fence(Ordering::Acquire);
+                let read = MetricsRecord {
+                    id: load_u32(record + RECORD_ID_OFFSET)?,
+                    generation: load_u64(record + RECORD_GENERATION_OFFSET)?,
+                    value: load_u64(record + RECORD_VALUE_OFFSET)?,
+                };
+                fence(Ordering::Acquire);
+                if load_u32(record + RECORD_SEQ_OFFSET)? != seq {
+                    continue;
+                }
+                if read.id == 0 {
+                    // Records are allocated in order, so there are no more after an unused one.
+                    return Ok(None);
+                }
+                if read.id == id {
+                    return Ok(Some(read));
+                }
+                break;
+            }
+        }
+        Ok(None)
+    }
+
+    fn load<T: DataInit>(&self, offset: u64) -> Result<T> {
+        Ok(self
+            .mem
+            .get_ref_at_addr(self.addr.unchecked_add(offset))
+            .map_err(Error::MemoryAccess)?
+            .load())
+    }
+
+    fn store<T: DataInit>(&self, offset: u64, val: T) -> Result<()> {
+        self.mem
+            .get_ref_at_addr(self.addr.unchecked_add(offset))
+            .map_err(Error::MemoryAccess)?
+            .store(val);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+    use std::thread;
+
+    use super::*;
+
+    const PAGE_ADDR: GuestAddress = GuestAddress(0x1000);
+    // Multiplier used to derive record values from their generation.
+    const VALUE_MULTIPLIER: u64 = 0x9e37_79b9_7f4a_7c15;
+
+    fn test_page() -> (GuestMemory, MetricsPage) {
+        let mem = GuestMemory::new(&[(GuestAddress(0), 0x3000)]).unwrap();
+        let page = MetricsPage::new(mem.clone(), PAGE_ADDR).unwrap();
+        (mem, page)
+    }
+
+    #[test]
+    fn header() {
+        let (mem, _page) = test_page();
+        let header: [u32; 4] = mem.read_obj_from_addr(PAGE_ADDR).unwrap();
+        assert_eq!(
+            header,
+            [
+                METRICS_PAGE_MAGIC,
+                METRICS_PAGE_VERSION,
+                RECORD_SIZE as u32,
+                RECORD_COUNT as u32
+            ]
+        );
+    }
+
+    #[test]
+    fn add_and_update_records() {
+        let (mem, mut page) = test_page();
+        assert_eq!(MetricsPage::read(&mem, PAGE_ADDR, 7).unwrap(), None);
+
+        page.update(7, 100).unwrap();
+        page.update(9, 200).unwrap();
+        page.update(7, 101).unwrap();
+        assert_eq!(
+            MetricsPage::read(&mem, PAGE_ADDR, 7).unwrap(),
+            Some(MetricsRecord {
+                id: 7,
+                generation: 2,
+                value: 101
+            })
+        );
+        assert_eq!(
+            MetricsPage::read(&mem, PAGE_ADDR, 9).unwrap(),
+            Some(MetricsRecord {
+                id: 9,
+                generation: 1,
+                value: 200
+            })
+        );
+        assert_eq!(MetricsPage::read(&mem, PAGE_ADDR, 8).unwrap(), None);
+    }
+
+    #[test]
+    fn invalid_id() {
+        let (_mem, mut page) = test_page();
+        assert!(matches!(page.update(0, 1), Err(Error::InvalidId)));
+    }
+
+    #[test]
+    fn page_full() {
+        let (mem, mut page) = test_page();
+        for id in 1..=RECORD_COUNT as u32 {
+            page.update(id, id.into()).unwrap();
+        }
+        assert!(matches!(
+            page.update(RECORD_COUNT as u32 + 1, 0),
+            Err(Error::PageFull(RECORD_COUNT))
+        ));
+        // Existing records can still be updated.
+        page.update(1, 5).unwrap();
+        assert_eq!(
+            MetricsPage::read(&mem, PAGE_ADDR, 1)
+                .unwrap()
+                .unwrap()
+                .value,
+            5
+        );
+    }
+
+    #[test]
+    fn concurrent_update_and_read() {
+        let (mem, mut page) = test_page();
+        page.update(1, VALUE_MULTIPLIER).unwrap();
+
+        let done = Arc::new(AtomicBool::new(false));
+        let reader_done = done.clone();
+        let reader = thread::spawn(move || loop {
+            let record = MetricsPage::read(&mem, PAGE_ADDR, 1).unwrap().unwrap();
+            // The writer always stores a value derived from the generation, so a torn read shows
+            // up as a mismatch.
+            assert_eq!(
+                record.value,
+                record.generation.wrapping_mul(VALUE_MULTIPLIER)
+            );
+            if reader_done.load(Ordering::Relaxed) {
+                break;
+            }
+        });
+
+        for generation in 2..200_000u64 {
+            page.update(1, generation.wrapping_mul(VALUE_MULTIPLIER))
+                .unwrap();
+        }
+        done.store(true, Ordering::Relaxed);
+        reader.join().unwrap();
+    }
+}
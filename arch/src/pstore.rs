@@ -8,6 +8,8 @@ use anyhow::bail;
 use anyhow::Context;
 use anyhow::Result;
 use base::MemoryMappingBuilder;
+use hypervisor::MemCacheType;
+use hypervisor::MemSlot;
 use hypervisor::Vm;
 use resources::AddressRange;
 use vm_memory::GuestAddress;
@@ -16,12 +18,18 @@ use crate::Pstore;
 
 mod sys;
 
+#[derive(Clone, Copy, Debug)]
 pub struct RamoopsRegion {
     pub address: u64,
     pub size: u32,
+    /// The memory slot mapping the pstore file into the guest.
+    pub slot: MemSlot,
 }
 
 /// Creates a mmio memory region for pstore.
+///
+/// The region maps the pstore file shared, so the records the guest writes end up in the file and
+/// the records left in an existing file by a previous boot are found again by the guest.
 pub fn create_memory_region(
     vm: &mut impl Vm,
     region: AddressRange,
@@ -47,20 +55,30 @@ pub fn create_memory_region(
         .build()
         .context("failed to mmap pstore")?;
 
-    vm.add_memory_region(
-        GuestAddress(region.start),
-        Box::new(memory_mapping),
-        false,
-        false,
-    )
-    .context("failed to add pstore region")?;
+    let slot = vm
+        .add_memory_region(
+            GuestAddress(region.start),
+            Box::new(memory_mapping),
+            false,
+            false,
+            MemCacheType::Cached,
+        )
+        .context("failed to add pstore region")?;
 
     Ok(RamoopsRegion {
         address: region.start,
         size: pstore.size,
+        slot,
     })
 }
 
+/// Writes the records in the pstore region back to its file, so that they survive the host
+/// crashing as well as the VM exiting.
+pub fn sync(vm: &mut impl Vm, ramoops_region: &RamoopsRegion) -> Result<()> {
+    vm.msync_memory_region(ramoops_region.slot, 0, ramoops_region.size as usize)
+        .context("failed to sync pstore region")
+}
+
 pub fn add_ramoops_kernel_cmdline(
     cmdline: &mut kernel_cmdline::Cmdline,
     ramoops_region: &RamoopsRegion,
@@ -0,0 +1,100 @@
+// Copyright 2022 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Host view of the stolen time structures of the Arm paravirtualized time interface (DEN0057A).
+//!
+//! The hypervisor keeps one 64 byte structure per vcpu up to date in a region of guest memory,
+//! laid out as follows, in little endian:
+//!
+//! | Offset | Type | Field                      |
+//! | ------ | ---- | -------------------------- |
+//! | 0      | u32  | revision, 0                |
+//! | 4      | u32  | attributes, 0              |
+//! | 8      | u64  | stolen time in nanoseconds |
+
+use base::MemoryMapping;
+use base::MmapError;
+use remain::sorted;
+use thiserror::Error;
+
+/// Size of the stolen time structure of a vcpu.
+pub const PVTIME_STRUCT_SIZE: u64 = 64;
+
+const STOLEN_TIME_OFFSET: u64 = 8;
+
+#[sorted]
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("no stolen time structure for vcpu {0}")]
+    InvalidVcpu(usize),
+    #[error("failed to read the stolen time of vcpu {0}: {1}")]
+    ReadStolenTime(usize, MmapError),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// A mapping of the region holding the stolen time structures of all the vcpus, in vcpu order.
+pub struct PvtimeRegion {
+    mapping: MemoryMapping,
+    vcpu_count: usize,
+}
+
+impl PvtimeRegion {
+    /// Creates the view of the structures of `vcpu_count` vcpus at the start of `mapping`, which
+    /// must share its memory with the region given to the guest.
+    pub fn new(mapping: MemoryMapping, vcpu_count: usize) -> Self {
+        PvtimeRegion {
+            mapping,
+            vcpu_count,
+        }
+    }
+
+    /// Returns how long `vcpu_id` was ready to run but couldn't, in nanoseconds.
+    pub fn stolen_time(&self, vcpu_id: usize) -> Result<u64> {
+        if vcpu_id >= self.vcpu_count {
+            return Err(Error::InvalidVcpu(vcpu_id));
+        }
+        let offset = vcpu_id as u64 * PVTIME_STRUCT_SIZE + STOLEN_TIME_OFFSET;
+        self.mapping
+            .read_obj_volatile::<u64>(offset as usize)
+            .map(u64::from_le)
+            .map_err(|e| Error::ReadStolenTime(vcpu_id, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use base::MemoryMappingBuilder;
+    use base::SharedMemory;
+
+    use super::*;
+
+    #[test]
+    fn stolen_time() {
+        let size = 2 * PVTIME_STRUCT_SIZE;
+        let shm = SharedMemory::new("pvtime", size).unwrap();
+        let guest = MemoryMappingBuilder::new(size as usize)
+            .from_shared_memory(&shm)
+            .build()
+            .unwrap();
+        let pvtime = PvtimeRegion::new(
+            MemoryMappingBuilder::new(size as usize)
+                .from_shared_memory(&shm)
+                .build()
+                .unwrap(),
+            2,
+        );
+
+        assert_eq!(pvtime.stolen_time(1).unwrap(), 0);
+        guest
+            .write_obj(
+                1234u64.to_le(),
+                (PVTIME_STRUCT_SIZE + STOLEN_TIME_OFFSET) as usize,
+            )
+            .unwrap();
+        assert_eq!(pvtime.stolen_time(0).unwrap(), 0);
+        assert_eq!(pvtime.stolen_time(1).unwrap(), 1234);
+        assert!(matches!(pvtime.stolen_time(2), Err(Error::InvalidVcpu(2))));
+    }
+}
@@ -3,12 +3,21 @@
 // found in the LICENSE file.
 
 use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
 
+use base::open_file;
+use base::AsRawDescriptor;
 use base::Event;
+use base::FileSerdeWrapper;
+use base::Tube;
 use devices::serial_device::SerialHardware;
 use devices::serial_device::SerialParameters;
 use devices::serial_device::SerialType;
 use devices::Bus;
+use devices::ConsoleBuffer;
 #[cfg(windows)]
 use devices::Minijail;
 use devices::Serial;
@@ -17,6 +26,7 @@ use hypervisor::ProtectionType;
 use minijail::Minijail;
 use remain::sorted;
 use thiserror::Error as ThisError;
+use vm_control::BootMilestones;
 
 use crate::DeviceRegistrationError;
 
@@ -74,6 +84,183 @@ pub fn set_default_serial_parameters(
     }
 }
 
+/// A problem with the parameters of one serial device.
+#[sorted]
+#[derive(ThisError, Debug)]
+pub enum SerialParameterError {
+    #[error("console_buffer is not supported for {0} hardware")]
+    ConsoleBufferNotSupported(SerialHardware),
+    #[error("{0} is already the console")]
+    DuplicateConsole(String),
+    #[error("{0} is already the earlycon")]
+    DuplicateEarlycon(String),
+    #[error("{0} is already connected to standard input")]
+    DuplicateStdin(String),
+    #[error("earlycon is not supported for {0} hardware")]
+    EarlyconNotSupported(SerialHardware),
+    #[error("failed to open input file {0}: {1}")]
+    InputFile(PathBuf, io::Error),
+    #[error("stdin and input are mutually exclusive")]
+    InputWithStdin,
+    #[error("registered as hardware={0},num={1}")]
+    MismatchedIndex(SerialHardware, u8),
+    #[error("num must be between 1 and {0}")]
+    NumOutOfRange(u8),
+    #[error("failed to open output file {0}: {1}")]
+    OutputFile(PathBuf, io::Error),
+    #[error("path is required for type {0}")]
+    PathRequired(SerialType),
+    #[error("invalid path {0} for type {1}")]
+    SystemPath(PathBuf, SerialType),
+    #[error("watch_resize is not supported for {0} hardware")]
+    WatchResizeNotSupported(SerialHardware),
+    #[error("watch_resize requires the output to be a terminal, such as a PTY: {0}")]
+    WatchResizeNotTerminal(io::Error),
+}
+
+/// A serial device whose parameters failed `check_serial_parameters`.
+#[derive(ThisError, Debug)]
+#[error("invalid --serial {arg}: {error}")]
+pub struct InvalidSerialParameters {
+    /// Identifies the offending `--serial` argument.
+    pub arg: String,
+    #[source]
+    pub error: SerialParameterError,
+}
+
+pub type CheckSerialParametersResult = std::result::Result<(), InvalidSerialParameters>;
+
+// Identifies the `--serial` argument `params` came from.
+fn serial_arg(params: &SerialParameters) -> String {
+    format!("hardware={},num={}", params.hardware, params.num)
+}
+
+#[cfg(unix)]
+fn is_valid_system_path(path: &Path) -> bool {
+    // The socket itself may only be created once the VM is running, but not its directory.
+    path.parent()
+        .map_or(false, |dir| dir.as_os_str().is_empty() || dir.is_dir())
+}
+
+#[cfg(windows)]
+fn is_valid_system_path(path: &Path) -> bool {
+    path.to_str()
+        .map_or(false, |path| path.starts_with(r"\\.\pipe\"))
+}
+
+/// Checks the parameters of every serial device before any of them is created, so that a bad
+/// `--serial` argument is reported as such instead of failing device creation halfway through
+/// building the VM.
+///
+/// `max_serial_num` is the number of `SerialHardware::Serial` ports the architecture can create.
+///
+/// Input and output files are opened as the devices would open them, which creates missing output
+/// files.
+pub fn check_serial_parameters(
+    serial_parameters: &BTreeMap<(SerialHardware, u8), SerialParameters>,
+    max_serial_num: u8,
+) -> CheckSerialParametersResult {
+    let mut console = None;
+    let mut earlycon = None;
+    let mut stdin = None;
+    for (&(hardware, num), params) in serial_parameters {
+        let arg = serial_arg(params);
+        let invalid = |error| InvalidSerialParameters {
+            arg: arg.clone(),
+            error,
+        };
+
+        if (params.hardware, params.num) != (hardware, num) {
+            return Err(invalid(SerialParameterError::MismatchedIndex(
+                hardware, num,
+            )));
+        }
+        let max_num = match hardware {
+            SerialHardware::Serial => max_serial_num,
+            _ => u8::MAX,
+        };
+        if num < 1 || num > max_num {
+            return Err(invalid(SerialParameterError::NumOutOfRange(max_num)));
+        }
+
+        if params.console {
+            if let Some(previous) = console.replace(arg.clone()) {
+                return Err(invalid(SerialParameterError::DuplicateConsole(previous)));
+            }
+        }
+        if params.earlycon {
+            if hardware != SerialHardware::Serial {
+                return Err(invalid(SerialParameterError::EarlyconNotSupported(
+                    hardware,
+                )));
+            }
+            if let Some(previous) = earlycon.replace(arg.clone()) {
+                return Err(invalid(SerialParameterError::DuplicateEarlycon(previous)));
+            }
+        }
+        if params.console_buffer > 0 && hardware != SerialHardware::Serial {
+            return Err(invalid(SerialParameterError::ConsoleBufferNotSupported(
+                hardware,
+            )));
+        }
+        if params.stdin {
+            if params.input.is_some() {
+                return Err(invalid(SerialParameterError::InputWithStdin));
+            }
+            if let Some(previous) = stdin.replace(arg.clone()) {
+                return Err(invalid(SerialParameterError::DuplicateStdin(previous)));
+            }
+        }
+
+        if let Some(input) = &params.input {
+            OpenOptions::new()
+                .read(true)
+                .open(input)
+                .map_err(|e| invalid(SerialParameterError::InputFile(input.clone(), e)))?;
+        }
+        match params.type_ {
+            SerialType::File => {
+                let path = params
+                    .path
+                    .as_ref()
+                    .ok_or_else(|| invalid(SerialParameterError::PathRequired(SerialType::File)))?;
+                OpenOptions::new()
+                    .append(true)
+                    .create(true)
+                    .open(path)
+                    .map_err(|e| invalid(SerialParameterError::OutputFile(path.clone(), e)))?;
+            }
+            SerialType::SystemSerialType => {
+                let path = params.path.as_ref().ok_or_else(|| {
+                    invalid(SerialParameterError::PathRequired(
+                        SerialType::SystemSerialType,
+                    ))
+                })?;
+                if !is_valid_system_path(path) {
+                    return Err(invalid(SerialParameterError::SystemPath(
+                        path.clone(),
+                        SerialType::SystemSerialType,
+                    )));
+                }
+            }
+            SerialType::Stdout | SerialType::Sink | SerialType::Syslog => {}
+        }
+        if params.watch_resize {
+            // Only the virtio console can tell the guest the size of the terminal.
+            if hardware != SerialHardware::VirtioConsole || cfg!(windows) {
+                return Err(invalid(SerialParameterError::WatchResizeNotSupported(
+                    hardware,
+                )));
+            }
+            #[cfg(unix)]
+            params
+                .open_output_terminal()
+                .map_err(|e| invalid(SerialParameterError::WatchResizeNotTerminal(e)))?;
+        }
+    }
+    Ok(())
+}
+
 /// Address for Serial ports in x86
 pub const SERIAL_ADDR: [u64; 4] = [0x3f8, 0x2f8, 0x3e8, 0x2e8];
 
@@ -90,14 +277,18 @@ pub const SERIAL_ADDR: [u64; 4] = [0x3f8, 0x2f8, 0x3e8, 0x2e8];
 /// * `serial_parameters` - definitions of serial parameter configurations.
 /// * `serial_jail` - minijail object cloned for use with each serial device.
 ///   All four of the traditional PC-style serial ports (COM1-COM4) must be specified.
+///
+/// Returns the handles to the ports, by port number.
 pub fn add_serial_devices(
     protection_type: ProtectionType,
     io_bus: &Bus,
     com_evt_1_3: &Event,
     com_evt_2_4: &Event,
     serial_parameters: &BTreeMap<(SerialHardware, u8), SerialParameters>,
-    #[cfg_attr(windows, allow(unused_variables))] serial_jail: Option<Minijail>,
-) -> std::result::Result<(), DeviceRegistrationError> {
+    serial_jail: Option<&Minijail>,
+    boot_milestones: &BootMilestones,
+) -> std::result::Result<BTreeMap<u8, SerialPortHandles>, DeviceRegistrationError> {
+    let mut ports = BTreeMap::new();
     for com_num in 0..=3 {
         let com_evt = match com_num {
             0 => &com_evt_1_3,
@@ -113,35 +304,167 @@ pub fn add_serial_devices(
                 com_num + 1,
             ))?;
 
-        let mut preserved_descriptors = Vec::new();
-        let com = param
-            .create_serial_device::<Serial>(protection_type, com_evt, &mut preserved_descriptors)
-            .map_err(DeviceRegistrationError::CreateSerialDevice)?;
-
-        #[cfg(unix)]
-        let serial_jail = if let Some(serial_jail) = serial_jail.as_ref() {
-            Some(
-                serial_jail
-                    .try_clone()
-                    .map_err(DeviceRegistrationError::CloneJail)?,
-            )
-        } else {
-            None
-        };
-        #[cfg(windows)]
-        let serial_jail = None;
-
-        sys::add_serial_device(
-            com_num as usize,
-            com,
+        let handles = add_serial_device(
+            protection_type,
+            io_bus,
+            SERIAL_ADDR[com_num as usize],
+            com_evt,
             param,
             serial_jail,
-            preserved_descriptors,
-            io_bus,
+            boot_milestones,
         )?;
+        ports.insert(com_num + 1, handles);
     }
 
-    Ok(())
+    Ok(ports)
+}
+
+/// The handles the VMM keeps to a serial device added by `add_serial_device`.
+pub struct SerialPortHandles {
+    /// The buffer recording the output of the port, if it has a `console_buffer`.
+    pub console_buffer: Option<ConsoleBuffer>,
+    /// Sends `Vec<u8>` messages whose bytes the guest reads as input of the port.
+    pub input: Tube,
+    /// Reopens the output file of the port, if it writes to one.
+    pub output_file: Option<SerialOutputFile>,
+}
+
+impl SerialPortHandles {
+    /// Splits `ports` into the console buffers, the input tubes and the output files of the ports,
+    /// by port number.
+    pub fn split(
+        ports: BTreeMap<u8, SerialPortHandles>,
+    ) -> (
+        BTreeMap<u8, ConsoleBuffer>,
+        BTreeMap<u8, Tube>,
+        BTreeMap<u8, SerialOutputFile>,
+    ) {
+        let mut console_buffers = BTreeMap::new();
+        let mut inputs = BTreeMap::new();
+        let mut output_files = BTreeMap::new();
+        for (num, port) in ports {
+            if let Some(buffer) = port.console_buffer {
+                console_buffers.insert(num, buffer);
+            }
+            inputs.insert(num, port.input);
+            if let Some(output_file) = port.output_file {
+                output_files.insert(num, output_file);
+            }
+        }
+        (console_buffers, inputs, output_files)
+    }
+}
+
+/// The output file of a serial port with `type=file`, which the VMM can reopen so that the file
+/// can be rotated while the VM runs.
+pub struct SerialOutputFile {
+    path: PathBuf,
+    tube: Tube,
+}
+
+impl SerialOutputFile {
+    /// Opens the file at the output path of the port again, creating it if it was moved away, and
+    /// has the port write to it in place of the file it had open.
+    pub fn reopen(&self) -> base::Result<()> {
+        let file = open_file(&self.path, OpenOptions::new().append(true).create(true))?;
+        self.tube
+            .send(&FileSerdeWrapper(file))
+            .map_err(|_| base::Error::new(libc::EIO))
+    }
+}
+
+/// Sends `data` to the guest as input of the port numbered `num` in `inputs`, which holds the
+/// `SerialPortHandles::input` tubes by port number.
+pub fn send_serial_input(inputs: &BTreeMap<u8, Tube>, num: u8, data: Vec<u8>) -> base::Result<()> {
+    let input = inputs
+        .get(&num)
+        .ok_or_else(|| base::Error::new(libc::ENODEV))?;
+    input.send(&data).map_err(|_| base::Error::new(libc::EIO))
+}
+
+/// Reopens the output file of the port numbered `num` in `output_files`, which holds the
+/// `SerialPortHandles::output_file` of the ports by port number.
+pub fn reopen_serial_output(
+    output_files: &BTreeMap<u8, SerialOutputFile>,
+    num: u8,
+) -> base::Result<()> {
+    output_files
+        .get(&num)
+        .ok_or_else(|| base::Error::new(libc::ENODEV))?
+        .reopen()
+}
+
+/// Adds a single serial device described by `param` to `bus` at `addr`, triggering `evt` to
+/// interrupt the guest.
+///
+/// This is how platforms with serial ports beyond the four PC-style ones add the extra ports.
+///
+/// Returns the handles to the port.
+pub fn add_serial_device(
+    protection_type: ProtectionType,
+    bus: &Bus,
+    addr: u64,
+    evt: &Event,
+    param: &SerialParameters,
+    #[cfg_attr(windows, allow(unused_variables))] serial_jail: Option<&Minijail>,
+    boot_milestones: &BootMilestones,
+) -> std::result::Result<SerialPortHandles, DeviceRegistrationError> {
+    let mut preserved_descriptors = Vec::new();
+    let mut com = param
+        .create_serial_device::<Serial>(protection_type, evt, &mut preserved_descriptors)
+        .map_err(DeviceRegistrationError::CreateSerialDevice)?;
+    com.set_output_policy(param.output_policy);
+
+    let (input, device_input) = Tube::pair().map_err(DeviceRegistrationError::CreateTube)?;
+    preserved_descriptors.push(device_input.as_raw_descriptor());
+    com.set_injected_input(device_input);
+
+    let output_file = match (&param.type_, &param.path) {
+        (SerialType::File, Some(path)) => {
+            let (tube, device_tube) = Tube::pair().map_err(DeviceRegistrationError::CreateTube)?;
+            preserved_descriptors.push(device_tube.as_raw_descriptor());
+            com.set_output_reopen(device_tube);
+            Some(SerialOutputFile {
+                path: path.clone(),
+                tube,
+            })
+        }
+        _ => None,
+    };
+
+    #[cfg(unix)]
+    let serial_jail = if let Some(serial_jail) = serial_jail {
+        Some(
+            serial_jail
+                .try_clone()
+                .map_err(DeviceRegistrationError::CloneJail)?,
+        )
+    } else {
+        None
+    };
+    #[cfg(windows)]
+    let serial_jail = None;
+
+    let console_buffer = if param.console_buffer > 0 {
+        Some(ConsoleBuffer::new(param.console_buffer))
+    } else {
+        None
+    };
+    sys::add_serial_device(
+        addr,
+        com,
+        param,
+        serial_jail,
+        preserved_descriptors,
+        bus,
+        boot_milestones,
+        console_buffer.clone(),
+    )?;
+    Ok(SerialPortHandles {
+        console_buffer,
+        input,
+        output_file,
+    })
 }
 
 #[sorted]
@@ -211,10 +534,207 @@ pub fn get_serial_cmdline(
 
 #[cfg(test)]
 mod tests {
+    use std::fs;
+
     use kernel_cmdline::Cmdline;
 
     use super::*;
 
+    fn serial(hardware: SerialHardware, num: u8) -> SerialParameters {
+        SerialParameters {
+            hardware,
+            num,
+            ..Default::default()
+        }
+    }
+
+    fn check(params: Vec<SerialParameters>) -> CheckSerialParametersResult {
+        check_serial_parameters(
+            &params
+                .into_iter()
+                .map(|p| ((p.hardware, p.num), p))
+                .collect(),
+            SERIAL_ADDR.len() as u8,
+        )
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("arch_serial_{}_{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn check_serial_parameters_default() {
+        let mut serial_parameters = BTreeMap::new();
+        serial_parameters.insert(
+            (SerialHardware::VirtioConsole, 1),
+            serial(SerialHardware::VirtioConsole, 1),
+        );
+        set_default_serial_parameters(&mut serial_parameters, false);
+        check_serial_parameters(&serial_parameters, SERIAL_ADDR.len() as u8)
+            .expect("default parameters are invalid");
+    }
+
+    #[test]
+    fn check_serial_parameters_index() {
+        let mut serial_parameters = BTreeMap::new();
+        serial_parameters.insert(
+            (SerialHardware::Serial, 1),
+            serial(SerialHardware::Serial, 2),
+        );
+        let err = check_serial_parameters(&serial_parameters, SERIAL_ADDR.len() as u8).unwrap_err();
+        assert_eq!(err.arg, "hardware=serial,num=2");
+        assert!(matches!(
+            err.error,
+            SerialParameterError::MismatchedIndex(SerialHardware::Serial, 1)
+        ));
+
+        let err = check(vec![serial(SerialHardware::Serial, 5)]).unwrap_err();
+        assert_eq!(err.arg, "hardware=serial,num=5");
+        assert!(matches!(err.error, SerialParameterError::NumOutOfRange(4)));
+        let params = serial(SerialHardware::Serial, 5);
+        check_serial_parameters(&[((SerialHardware::Serial, 5), params)].into(), 8)
+            .expect("extra serial port is invalid");
+
+        let err = check(vec![serial(SerialHardware::VirtioConsole, 0)]).unwrap_err();
+        assert!(matches!(
+            err.error,
+            SerialParameterError::NumOutOfRange(255)
+        ));
+    }
+
+    #[test]
+    fn check_serial_parameters_exclusive() {
+        let mut first = serial(SerialHardware::Serial, 1);
+        let mut second = serial(SerialHardware::VirtioConsole, 1);
+        first.console = true;
+        second.console = true;
+        let err = check(vec![first, second]).unwrap_err();
+        assert_eq!(err.arg, "hardware=virtio-console,num=1");
+        assert!(
+            matches!(err.error, SerialParameterError::DuplicateConsole(ref previous) if previous == "hardware=serial,num=1")
+        );
+
+        let mut first = serial(SerialHardware::Serial, 1);
+        let mut second = serial(SerialHardware::Serial, 2);
+        first.stdin = true;
+        second.stdin = true;
+        let err = check(vec![first, second]).unwrap_err();
+        assert_eq!(err.arg, "hardware=serial,num=2");
+        assert!(matches!(err.error, SerialParameterError::DuplicateStdin(_)));
+
+        let mut earlycon = serial(SerialHardware::VirtioConsole, 1);
+        earlycon.earlycon = true;
+        let err = check(vec![earlycon]).unwrap_err();
+        assert!(matches!(
+            err.error,
+            SerialParameterError::EarlyconNotSupported(SerialHardware::VirtioConsole)
+        ));
+
+        let mut buffered = serial(SerialHardware::VirtioConsole, 1);
+        buffered.console_buffer = 4096;
+        let err = check(vec![buffered]).unwrap_err();
+        assert!(matches!(
+            err.error,
+            SerialParameterError::ConsoleBufferNotSupported(SerialHardware::VirtioConsole)
+        ));
+        let mut buffered = serial(SerialHardware::Serial, 1);
+        buffered.console_buffer = 4096;
+        check(vec![buffered]).expect("console buffer is invalid");
+
+        let input = temp_path("stdin_input");
+        fs::write(&input, b"input").unwrap();
+        let mut params = serial(SerialHardware::Serial, 1);
+        params.stdin = true;
+        params.input = Some(input.clone());
+        let err = check(vec![params]).unwrap_err();
+        assert!(matches!(err.error, SerialParameterError::InputWithStdin));
+        fs::remove_file(input).unwrap();
+    }
+
+    #[test]
+    fn check_serial_parameters_input() {
+        let input = temp_path("input");
+        let mut params = serial(SerialHardware::Serial, 1);
+        params.input = Some(input.clone());
+        let err = check(vec![params.clone()]).unwrap_err();
+        assert_eq!(err.arg, "hardware=serial,num=1");
+        assert!(
+            matches!(err.error, SerialParameterError::InputFile(ref path, _) if path == &input)
+        );
+
+        fs::write(&input, b"input").unwrap();
+        check(vec![params]).expect("input file is invalid");
+        fs::remove_file(input).unwrap();
+    }
+
+    #[test]
+    fn check_serial_parameters_output() {
+        let mut params = serial(SerialHardware::Serial, 1);
+        params.type_ = SerialType::File;
+        let err = check(vec![params.clone()]).unwrap_err();
+        assert!(matches!(
+            err.error,
+            SerialParameterError::PathRequired(SerialType::File)
+        ));
+
+        let missing_dir = temp_path("missing_dir").join("output");
+        params.path = Some(missing_dir.clone());
+        let err = check(vec![params.clone()]).unwrap_err();
+        assert!(
+            matches!(err.error, SerialParameterError::OutputFile(ref path, _) if path == &missing_dir)
+        );
+
+        let output = temp_path("output");
+        params.path = Some(output.clone());
+        check(vec![params]).expect("output file is invalid");
+        assert!(output.exists());
+        fs::remove_file(output).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn check_serial_parameters_watch_resize() {
+        let mut params = serial(SerialHardware::Serial, 1);
+        params.watch_resize = true;
+        let err = check(vec![params]).unwrap_err();
+        assert!(matches!(
+            err.error,
+            SerialParameterError::WatchResizeNotSupported(SerialHardware::Serial)
+        ));
+
+        let output = temp_path("resize_output");
+        let mut params = serial(SerialHardware::VirtioConsole, 1);
+        params.type_ = SerialType::File;
+        params.path = Some(output.clone());
+        params.watch_resize = true;
+        let err = check(vec![params]).unwrap_err();
+        assert!(matches!(
+            err.error,
+            SerialParameterError::WatchResizeNotTerminal(_)
+        ));
+        fs::remove_file(output).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn check_serial_parameters_socket() {
+        let mut params = serial(SerialHardware::Serial, 1);
+        params.type_ = SerialType::SystemSerialType;
+        let err = check(vec![params.clone()]).unwrap_err();
+        assert!(matches!(
+            err.error,
+            SerialParameterError::PathRequired(SerialType::SystemSerialType)
+        ));
+
+        params.path = Some(temp_path("missing_dir").join("socket"));
+        let err = check(vec![params.clone()]).unwrap_err();
+        assert!(matches!(err.error, SerialParameterError::SystemPath(..)));
+
+        // The socket doesn't need to exist yet.
+        params.path = Some(temp_path("socket"));
+        check(vec![params]).expect("socket path is invalid");
+    }
+
     #[test]
     fn get_serial_cmdline_default() {
         let mut cmdline = Cmdline::new(4096);
@@ -241,12 +761,16 @@ mod tests {
                 hardware: SerialHardware::VirtioConsole,
                 path: None,
                 input: None,
+                input_rate: None,
                 num: 1,
                 console: true,
                 earlycon: false,
                 stdin: true,
                 out_timestamp: false,
+                output_policy: Default::default(),
                 debugcon_port: 0,
+                console_buffer: 0,
+                watch_resize: false,
             },
         );
 
@@ -271,12 +795,16 @@ mod tests {
                 hardware: SerialHardware::VirtioConsole,
                 path: None,
                 input: None,
+                input_rate: None,
                 num: 1,
                 console: true,
                 earlycon: false,
                 stdin: true,
                 out_timestamp: false,
+                output_policy: Default::default(),
                 debugcon_port: 0,
+                console_buffer: 0,
+                watch_resize: false,
             },
         );
 
@@ -288,12 +816,16 @@ mod tests {
                 hardware: SerialHardware::Serial,
                 path: None,
                 input: None,
+                input_rate: None,
                 num: 1,
                 console: false,
                 earlycon: true,
                 stdin: false,
                 out_timestamp: false,
+                output_policy: Default::default(),
                 debugcon_port: 0,
+                console_buffer: 0,
+                watch_resize: false,
             },
         );
 
@@ -319,12 +851,16 @@ mod tests {
                 hardware: SerialHardware::VirtioConsole,
                 path: None,
                 input: None,
+                input_rate: None,
                 num: 1,
                 console: false,
                 earlycon: true,
                 stdin: true,
                 out_timestamp: false,
+                output_policy: Default::default(),
                 debugcon_port: 0,
+                console_buffer: 0,
+                watch_resize: false,
             },
         );
 
@@ -332,4 +868,42 @@ mod tests {
         get_serial_cmdline(&mut cmdline, &serial_parameters, "io")
             .expect_err("get_serial_cmdline succeeded");
     }
+
+    #[test]
+    fn send_serial_input_to_port() {
+        let (input, device_input) = Tube::pair().unwrap();
+        let inputs = BTreeMap::from([(1, input)]);
+
+        send_serial_input(&inputs, 1, b"abc".to_vec()).unwrap();
+        assert_eq!(device_input.recv::<Vec<u8>>().unwrap(), b"abc");
+        assert_eq!(
+            send_serial_input(&inputs, 2, b"abc".to_vec())
+                .unwrap_err()
+                .errno(),
+            libc::ENODEV
+        );
+    }
+
+    #[test]
+    fn reopen_serial_output_creates_file() {
+        let path = temp_path("reopen_output");
+        let (tube, device_tube) = Tube::pair().unwrap();
+        let output_files = BTreeMap::from([(
+            1,
+            SerialOutputFile {
+                path: path.clone(),
+                tube,
+            },
+        )]);
+
+        reopen_serial_output(&output_files, 1).unwrap();
+        let FileSerdeWrapper(mut file) = device_tube.recv().unwrap();
+        io::Write::write_all(&mut file, b"rotated").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"rotated");
+        assert_eq!(
+            reopen_serial_output(&output_files, 2).unwrap_err().errno(),
+            libc::ENODEV
+        );
+        fs::remove_file(path).unwrap();
+    }
 }
@@ -8,21 +8,26 @@ use base::RawDescriptor;
 use devices::serial_device::SerialParameters;
 use devices::Bus;
 use devices::BusDevice;
+use devices::ConsoleBuffer;
 use devices::ProxyDevice;
 use devices::Serial;
+use devices::SerialConsoleRecorder;
+use devices::SerialOutputMilestone;
 use minijail::Minijail;
 use sync::Mutex;
+use vm_control::BootMilestones;
 
-use crate::serial::SERIAL_ADDR;
 use crate::DeviceRegistrationError;
 
 pub fn add_serial_device(
-    com_num: usize,
+    addr: u64,
     com: Serial,
     _serial_parameters: &SerialParameters,
     serial_jail: Option<Minijail>,
     preserved_descriptors: Vec<RawDescriptor>,
     io_bus: &Bus,
+    boot_milestones: &BootMilestones,
+    console_buffer: Option<ConsoleBuffer>,
 ) -> std::result::Result<(), DeviceRegistrationError> {
     let com: Arc<Mutex<dyn BusDevice>> = if let Some(serial_jail) = serial_jail {
         Arc::new(Mutex::new(
@@ -32,6 +37,15 @@ pub fn add_serial_device(
     } else {
         Arc::new(Mutex::new(com))
     };
-    io_bus.insert(com, SERIAL_ADDR[com_num], 0x8).unwrap();
-    Ok(())
+    let com: Arc<Mutex<dyn BusDevice>> = match console_buffer {
+        Some(buffer) => Arc::new(Mutex::new(SerialConsoleRecorder::new(com, buffer))),
+        None => com,
+    };
+    let com = Arc::new(Mutex::new(SerialOutputMilestone::new(
+        com,
+        boot_milestones.clone(),
+    )));
+    io_bus
+        .insert(com, addr, 0x8)
+        .map_err(DeviceRegistrationError::MmioInsert)
 }
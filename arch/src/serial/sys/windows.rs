@@ -14,11 +14,15 @@ use base::Result;
 use devices::serial_device::SerialParameters;
 use devices::serial_device::SerialType;
 use devices::Bus;
+use devices::BusDevice;
+use devices::ConsoleBuffer;
 use devices::Minijail;
 use devices::Serial;
+use devices::SerialConsoleRecorder;
+use devices::SerialOutputMilestone;
 use sync::Mutex;
+use vm_control::BootMilestones;
 
-use crate::serial::SERIAL_ADDR;
 use crate::DeviceRegistrationError;
 
 /// A type for queueing input bytes to a serial device that abstracts if the device is local or part
@@ -46,20 +50,32 @@ impl SerialInput {
 }
 
 pub fn add_serial_device(
-    com_num: usize,
+    addr: u64,
     com: Serial,
     serial_params: &SerialParameters,
     serial_jail: Option<Minijail>,
     _preserved_descriptors: Vec<RawDescriptor>,
     io_bus: &Bus,
+    boot_milestones: &BootMilestones,
+    console_buffer: Option<ConsoleBuffer>,
 ) -> std::result::Result<(), DeviceRegistrationError> {
     match serial_jail {
         Some(_) => (),
         None => {
             let com = Arc::new(Mutex::new(com));
+            let recorded_com: Arc<Mutex<dyn BusDevice>> = match console_buffer {
+                Some(buffer) => {
+                    Arc::new(Mutex::new(SerialConsoleRecorder::new(com.clone(), buffer)))
+                }
+                None => com.clone(),
+            };
+            let bus_com = Arc::new(Mutex::new(SerialOutputMilestone::new(
+                recorded_com,
+                boot_milestones.clone(),
+            )));
             io_bus
-                .insert(com.clone(), SERIAL_ADDR[com_num], 0x8)
-                .unwrap();
+                .insert(bus_com, addr, 0x8)
+                .map_err(DeviceRegistrationError::MmioInsert)?;
 
             if !serial_params.stdin {
                 if let SerialType::SystemSerialType = serial_params.type_ {
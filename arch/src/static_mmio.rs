@@ -0,0 +1,146 @@
+// Copyright 2022 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Bookkeeping of the devices placed at fixed MMIO addresses by the architecture code.
+
+use std::sync::Arc;
+
+use devices::Bus;
+use devices::BusDevice;
+use resources::AddressRange;
+use sync::Mutex;
+use vm_control::StaticMmioRegion;
+
+use crate::DeviceRegistrationError;
+
+/// Names the device at each fixed MMIO range, so that a device placed over another one is
+/// reported with both of them instead of an anonymous `BusError`.
+#[derive(Default)]
+pub struct StaticMmioMap {
+    regions: Vec<StaticMmioRegion>,
+}
+
+impl StaticMmioMap {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Records that `name` occupies `len` bytes at `base`, failing if that overlaps a range
+    /// recorded before. Used for devices that are inserted in the bus by helpers shared between
+    /// architectures.
+    pub fn record(
+        &mut self,
+        name: &str,
+        base: u64,
+        len: u64,
+    ) -> Result<(), DeviceRegistrationError> {
+        let range = AddressRange::from_start_and_size(base, len)
+            .filter(|range| !range.is_empty())
+            .ok_or_else(|| DeviceRegistrationError::MmioInvalidRange {
+                name: name.to_owned(),
+                base,
+                len,
+            })?;
+        if let Some(other) = self.regions.iter().find(|r| r.range.overlaps(range)) {
+            return Err(DeviceRegistrationError::MmioOverlap {
+                name: name.to_owned(),
+                range,
+                other_name: other.name.clone(),
+                other_range: other.range,
+            });
+        }
+        self.regions.push(StaticMmioRegion {
+            name: name.to_owned(),
+            range,
+        });
+        Ok(())
+    }
+
+    /// Records `device` as `name`, then inserts it in `bus` at `base`.
+    pub fn insert(
+        &mut self,
+        bus: &Bus,
+        name: &str,
+        device: Arc<Mutex<dyn BusDevice>>,
+        base: u64,
+        len: u64,
+    ) -> Result<(), DeviceRegistrationError> {
+        self.record(name, base, len)?;
+        bus.insert(device, base, len)
+            .map_err(DeviceRegistrationError::MmioInsert)
+    }
+
+    /// Returns the recorded ranges, sorted by address.
+    pub fn regions(&self) -> Vec<StaticMmioRegion> {
+        let mut regions = self.regions.clone();
+        regions.sort_by_key(|r| r.range.start);
+        regions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use devices::CrosvmDeviceId;
+    use devices::DeviceId;
+
+    use super::*;
+
+    struct DummyDevice;
+
+    impl BusDevice for DummyDevice {
+        fn device_id(&self) -> DeviceId {
+            CrosvmDeviceId::Cmos.into()
+        }
+        fn debug_label(&self) -> String {
+            "dummy".to_owned()
+        }
+    }
+
+    fn dummy() -> Arc<Mutex<dyn BusDevice>> {
+        Arc::new(Mutex::new(DummyDevice))
+    }
+
+    #[test]
+    fn overlap_names_both_devices() {
+        let bus = Bus::new();
+        let mut map = StaticMmioMap::new();
+        map.insert(&bus, "rtc", dummy(), 0x2000, 0x1000).unwrap();
+        map.insert(&bus, "vmwdt", dummy(), 0x3000, 0x1000).unwrap();
+
+        let err = map
+            .insert(&bus, "battery", dummy(), 0x2800, 0x1000)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "battery at 0x2800..=0x37ff overlaps rtc at 0x2000..=0x2fff"
+        );
+
+        assert_eq!(
+            map.regions(),
+            vec![
+                StaticMmioRegion {
+                    name: "rtc".to_owned(),
+                    range: AddressRange::from_start_and_size(0x2000, 0x1000).unwrap(),
+                },
+                StaticMmioRegion {
+                    name: "vmwdt".to_owned(),
+                    range: AddressRange::from_start_and_size(0x3000, 0x1000).unwrap(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn recorded_ranges_checked_before_insert() {
+        let mut map = StaticMmioMap::new();
+        map.record("serial 1", 0x3f8, 0x8).unwrap();
+        let err = map.record("serial 2", 0x3fc, 0x8).unwrap_err();
+        assert!(
+            matches!(err, DeviceRegistrationError::MmioOverlap { ref other_name, .. } if other_name == "serial 1"),
+            "{}",
+            err
+        );
+        assert!(map.record("empty", 0x1000, 0).is_err());
+    }
+}
@@ -17,6 +17,7 @@ use devices::ProxyDevice;
 use devices::VfioPlatformDevice;
 use libc::sched_getcpu;
 use minijail::Minijail;
+use resources::AddressRange;
 use resources::AllocOptions;
 use resources::SystemAllocator;
 use sync::Mutex;
@@ -46,14 +47,14 @@ impl MsrValueFrom {
 /// * `battery_jail` - used when sandbox is enabled
 /// * `mmio_bus` - bus to add the devices to
 /// * `irq_chip` - the IrqChip object for registering irq events
-/// * `irq_num` - assigned interrupt to use
+/// * `irq_num` - assigned interrupt to use, or `None` to add the battery without an interrupt
 /// * `resources` - the SystemAllocator to allocate IO and MMIO for acpi
 pub fn add_goldfish_battery(
     amls: &mut Vec<u8>,
     battery_jail: Option<Minijail>,
     mmio_bus: &Bus,
     irq_chip: &mut dyn IrqChip,
-    irq_num: u32,
+    irq_num: Option<u32>,
     resources: &mut SystemAllocator,
 ) -> Result<(Tube, u64), DeviceRegistrationError> {
     let alloc = resources.get_anon_alloc();
@@ -91,13 +92,15 @@ pub fn add_goldfish_battery(
     .map_err(DeviceRegistrationError::RegisterBattery)?;
     goldfish_bat.to_aml_bytes(amls);
 
-    irq_chip
-        .register_level_irq_event(
-            irq_num,
-            &irq_evt,
-            IrqEventSource::from_device(&goldfish_bat),
-        )
-        .map_err(DeviceRegistrationError::RegisterIrqfd)?;
+    if let Some(irq_num) = irq_num {
+        irq_chip
+            .register_level_irq_event(
+                irq_num,
+                &irq_evt,
+                IrqEventSource::from_device(&goldfish_bat),
+            )
+            .map_err(DeviceRegistrationError::RegisterIrqfd)?;
+    }
 
     match battery_jail {
         #[cfg(not(windows))]
@@ -144,9 +147,20 @@ pub fn generate_platform_bus(
 
     // Allocate ranges that may need to be in the Platform MMIO region (MmioType::Platform).
     for (mut device, jail) in devices.into_iter() {
-        let ranges = device
-            .allocate_regions(resources)
-            .map_err(DeviceRegistrationError::AllocateIoResource)?;
+        let ranges = device.allocate_regions(resources).map_err(|e| match e {
+            resources::Error::OutOfSpace => {
+                let window = resources
+                    .mmio_platform_allocator()
+                    .and_then(|allocator| allocator.pools().first().copied())
+                    .unwrap_or_else(AddressRange::empty);
+                DeviceRegistrationError::PlatformMmioExhausted {
+                    device: device.debug_label(),
+                    size: device.mmio_region_sizes().iter().sum(),
+                    window,
+                }
+            }
+            e => DeviceRegistrationError::AllocateIoResource(e),
+        })?;
 
         let mut keep_rds = device.keep_rds();
         syslog::push_descriptors(&mut keep_rds);
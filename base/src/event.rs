@@ -24,6 +24,15 @@ pub enum EventReadResult {
     Timeout,
 }
 
+/// Result of waiting for an Event with a timeout.
+#[derive(Debug, PartialEq, Eq)]
+pub enum EventWaitResult {
+    /// The event was signaled.
+    Signaled,
+    /// Timed out before the event was signaled.
+    TimedOut,
+}
+
 #[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(transparent)]
 pub struct Event(pub(crate) PlatformEvent);
@@ -44,6 +53,14 @@ impl Event {
         self.0.read_timeout(timeout)
     }
 
+    /// Blocks for a maximum of `timeout` until the event is signaled, without resetting it.
+    ///
+    /// Unlike `read_timeout`, the event stays signaled for the next reader, except for Windows
+    /// auto-reset events, which waiting resets.
+    pub fn wait_timeout(&self, timeout: Duration) -> Result<EventWaitResult> {
+        self.0.wait_timeout(timeout)
+    }
+
     pub fn try_clone(&self) -> Result<Event> {
         self.0.try_clone().map(Event)
     }
@@ -12,6 +12,7 @@ mod errno;
 mod event;
 mod mmap;
 mod notifiers;
+mod rate_limiter;
 mod shm;
 pub mod syslog;
 mod timer;
@@ -29,6 +30,7 @@ pub use errno::Error;
 pub use errno::Result;
 pub use event::Event;
 pub use event::EventReadResult;
+pub use event::EventWaitResult;
 pub use mmap::ExternalMapping;
 pub use mmap::MappedRegion;
 pub use mmap::MemoryMapping;
@@ -42,6 +44,9 @@ pub use platform::ioctl::ioctl_with_ptr;
 pub use platform::ioctl::ioctl_with_ref;
 pub use platform::ioctl::ioctl_with_val;
 pub use platform::ioctl::IoctlNr;
+pub use rate_limiter::LogRateLimiter;
+pub use rate_limiter::RateLimiter;
+pub use rate_limiter::TimeSource;
 pub use shm::SharedMemory;
 pub use sys::platform;
 pub use timer::FakeTimer;
@@ -67,11 +72,11 @@ cfg_if::cfg_if! {
         pub use unix::net;
 
         // File related exports.
-        pub use platform::{FileFlags, get_max_open_files};
+        pub use platform::{AccessMode, FileFlags, get_max_open_files};
 
         // memory/mmap related exports.
         pub use platform::{
-            MemfdSeals, MemoryMappingBuilderUnix, Unix as MemoryMappingUnix,
+            memfd_seals, MemfdSeals, MemoryMappingBuilderUnix, Unix as MemoryMappingUnix,
             SharedMemoryUnix,
         };
 
@@ -89,7 +94,8 @@ cfg_if::cfg_if! {
         };
 
         pub use platform::{
-            chown, drop_capabilities, iov_max, kernel_has_memfd, pipe, read_raw_stdin
+            chown, drop_capabilities, iov_max, kernel_has_memfd, pipe, read_raw_stdin,
+            terminal_size,
         };
         pub use platform::{enable_core_scheduling, set_rt_prio_limit, set_rt_round_robin};
         pub use platform::{flock, FlockOperation};
@@ -185,11 +191,19 @@ pub fn generate_uuid() -> String {
 
 use serde::Deserialize;
 use serde::Serialize;
-#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
-pub enum VmEventType {
-    Exit,
-    Reset,
-    Crash,
-    Panic(u8),
-    WatchdogReset,
+/// Why a VM stopped or is about to stop, as sent over the vm_evt tube to the main loop.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub enum VmExitReason {
+    /// The guest powered itself off, e.g. with an ACPI S5 request or PSCI SYSTEM_OFF, or its
+    /// display window was closed.
+    GuestShutdown,
+    /// The guest asked to be reset, e.g. through the i8042 or PSCI SYSTEM_RESET.
+    GuestReset,
+    /// The watchdog of `vcpu` expired without the guest petting it.
+    WatchdogBite { vcpu: usize },
+    /// The guest reported a panic through pvpanic, with the pvpanic event bits in `info`. This
+    /// alone doesn't stop the VM.
+    Panic { info: u8 },
+    /// A vcpu hit an unrecoverable error, or a device process died of `signal`.
+    Crash { signal: Option<i32> },
 }
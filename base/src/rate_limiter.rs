@@ -0,0 +1,276 @@
+// Copyright 2022 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Token bucket rate limiting.
+
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+
+use log::warn;
+use sync::Mutex;
+
+use crate::Clock;
+use crate::FakeClock;
+
+/// A source of the current time for a `RateLimiter`, so that tests can control it with a
+/// `FakeClock`.
+pub trait TimeSource {
+    fn now(&self) -> Instant;
+}
+
+impl TimeSource for Clock {
+    fn now(&self) -> Instant {
+        Clock::now(self)
+    }
+}
+
+impl TimeSource for Arc<Mutex<FakeClock>> {
+    fn now(&self) -> Instant {
+        self.lock().now()
+    }
+}
+
+/// A token bucket holding up to `capacity` tokens and refilled with `refill` tokens per `period`,
+/// spread evenly over the period.
+///
+/// The bucket starts full. Refilling is computed lazily from the clock when tokens are requested,
+/// so a call costs one clock read and no system call besides.
+pub struct RateLimiter<C = Clock> {
+    clock: C,
+    capacity: u64,
+    tokens: u64,
+    refill: u64,
+    period: Duration,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    /// Creates a limiter using the monotonic clock.
+    pub fn new(capacity: u64, refill: u64, period: Duration) -> RateLimiter {
+        RateLimiter::with_clock(Clock::new(), capacity, refill, period)
+    }
+}
+
+impl<C: TimeSource> RateLimiter<C> {
+    /// Creates a limiter using `clock`. A zero `refill` never refills the bucket and a zero
+    /// `period` keeps it full.
+    pub fn with_clock(clock: C, capacity: u64, refill: u64, period: Duration) -> RateLimiter<C> {
+        let last_refill = clock.now();
+        RateLimiter {
+            clock,
+            capacity,
+            tokens: capacity,
+            refill,
+            period,
+            last_refill,
+        }
+    }
+
+    // Adds the tokens accrued since the last refill and returns the current time.
+    fn refill(&mut self) -> Instant {
+        let now = self.clock.now();
+        if self.tokens >= self.capacity || self.period.is_zero() {
+            self.tokens = self.capacity;
+            self.last_refill = now;
+            return now;
+        }
+        if self.refill == 0 {
+            return now;
+        }
+        let period_ns = self.period.as_nanos();
+        let elapsed_ns = now.saturating_duration_since(self.last_refill).as_nanos();
+        let accrued = elapsed_ns * self.refill as u128 / period_ns;
+        if accrued >= (self.capacity - self.tokens) as u128 {
+            self.tokens = self.capacity;
+            self.last_refill = now;
+        } else if accrued > 0 {
+            self.tokens += accrued as u64;
+            // Only move forward by the time the accrued tokens took, keeping the remainder
+            // towards the next token.
+            let accrued_ns = div_ceil(accrued * period_ns, self.refill as u128);
+            self.last_refill += Duration::from_nanos(accrued_ns as u64);
+        }
+        now
+    }
+
+    /// Returns the number of tokens that can be taken right now.
+    pub fn available(&mut self) -> u64 {
+        self.refill();
+        self.tokens
+    }
+
+    /// Takes `n` tokens if they are all available and returns whether it did.
+    pub fn try_acquire(&mut self, n: u64) -> bool {
+        self.refill();
+        if self.tokens < n {
+            return false;
+        }
+        self.tokens -= n;
+        true
+    }
+
+    /// Returns how long until `n` tokens are available, zero if they already are, or `None` if
+    /// they never will be because `n` is more than the capacity or the bucket isn't refilled.
+    pub fn time_until_available(&mut self, n: u64) -> Option<Duration> {
+        let now = self.refill();
+        if self.tokens >= n {
+            return Some(Duration::ZERO);
+        }
+        if n > self.capacity || self.refill == 0 {
+            return None;
+        }
+        let missing = (n - self.tokens) as u128;
+        let needed_ns = div_ceil(missing * self.period.as_nanos(), self.refill as u128);
+        let elapsed_ns = now.saturating_duration_since(self.last_refill).as_nanos();
+        Some(Duration::from_nanos(
+            needed_ns.saturating_sub(elapsed_ns) as u64
+        ))
+    }
+
+    /// Returns the most tokens the bucket holds.
+    pub fn capacity(&self) -> u64 {
+        self.capacity
+    }
+}
+
+fn div_ceil(a: u128, b: u128) -> u128 {
+    (a + b - 1) / b
+}
+
+/// Rate limits a kind of log message, allowing bursts of `burst` messages refilled over `period`.
+///
+/// The messages dropped in between are counted, and how many there were is logged before the next
+/// message that is allowed.
+pub struct LogRateLimiter<C = Clock> {
+    what: &'static str,
+    limiter: RateLimiter<C>,
+    suppressed: u64,
+}
+
+impl LogRateLimiter {
+    /// Creates a limiter for messages about `what`, which is used in the suppressed messages
+    /// summary.
+    pub fn new(what: &'static str, burst: u64, period: Duration) -> LogRateLimiter {
+        LogRateLimiter::with_clock(Clock::new(), what, burst, period)
+    }
+}
+
+impl<C: TimeSource> LogRateLimiter<C> {
+    pub fn with_clock(
+        clock: C,
+        what: &'static str,
+        burst: u64,
+        period: Duration,
+    ) -> LogRateLimiter<C> {
+        LogRateLimiter {
+            what,
+            limiter: RateLimiter::with_clock(clock, burst, burst, period),
+            suppressed: 0,
+        }
+    }
+
+    /// Returns whether a message may be logged now, counting it as suppressed if not.
+    pub fn allow(&mut self) -> bool {
+        if !self.limiter.try_acquire(1) {
+            self.suppressed += 1;
+            return false;
+        }
+        if self.suppressed > 0 {
+            warn!(
+                "suppressed {} messages about {}",
+                self.suppressed, self.what
+            );
+            self.suppressed = 0;
+        }
+        true
+    }
+
+    /// Returns the number of messages suppressed since the last one allowed.
+    pub fn suppressed(&self) -> u64 {
+        self.suppressed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fake_clock() -> Arc<Mutex<FakeClock>> {
+        Arc::new(Mutex::new(FakeClock::new()))
+    }
+
+    fn ms(ms: u64) -> u64 {
+        Duration::from_millis(ms).as_nanos() as u64
+    }
+
+    #[test]
+    fn burst_then_refill() {
+        let clock = fake_clock();
+        let mut limiter = RateLimiter::with_clock(clock.clone(), 4, 2, Duration::from_secs(1));
+        assert!(limiter.try_acquire(3));
+        assert!(!limiter.try_acquire(2));
+        assert!(limiter.try_acquire(1));
+        assert!(!limiter.try_acquire(1));
+
+        // One token every 500ms.
+        clock.lock().add_ns(ms(499));
+        assert_eq!(limiter.available(), 0);
+        clock.lock().add_ns(ms(1));
+        assert_eq!(limiter.available(), 1);
+        clock.lock().add_ns(ms(750));
+        assert_eq!(limiter.available(), 2);
+        // The 250ms left over count towards the next token.
+        clock.lock().add_ns(ms(250));
+        assert_eq!(limiter.available(), 3);
+
+        // Never more than the capacity.
+        clock.lock().add_ns(ms(10_000));
+        assert_eq!(limiter.available(), 4);
+    }
+
+    #[test]
+    fn time_until_available() {
+        let clock = fake_clock();
+        let mut limiter = RateLimiter::with_clock(clock.clone(), 3, 3, Duration::from_millis(30));
+        assert_eq!(limiter.time_until_available(3), Some(Duration::ZERO));
+        assert!(limiter.try_acquire(3));
+        assert_eq!(
+            limiter.time_until_available(2),
+            Some(Duration::from_millis(20))
+        );
+        clock.lock().add_ns(ms(15));
+        assert_eq!(
+            limiter.time_until_available(2),
+            Some(Duration::from_millis(5))
+        );
+        assert_eq!(limiter.time_until_available(4), None);
+    }
+
+    #[test]
+    fn no_refill() {
+        let clock = fake_clock();
+        let mut limiter = RateLimiter::with_clock(clock.clone(), 1, 0, Duration::from_secs(1));
+        assert!(limiter.try_acquire(1));
+        clock.lock().add_ns(ms(10_000));
+        assert!(!limiter.try_acquire(1));
+        assert_eq!(limiter.time_until_available(1), None);
+    }
+
+    #[test]
+    fn log_suppression() {
+        let clock = fake_clock();
+        let mut limiter =
+            LogRateLimiter::with_clock(clock.clone(), "tests", 2, Duration::from_secs(1));
+        assert!(limiter.allow());
+        assert!(limiter.allow());
+        assert!(!limiter.allow());
+        assert!(!limiter.allow());
+        assert_eq!(limiter.suppressed(), 2);
+
+        clock.lock().add_ns(ms(500));
+        assert!(limiter.allow());
+        assert_eq!(limiter.suppressed(), 0);
+    }
+}
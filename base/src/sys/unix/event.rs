@@ -25,6 +25,7 @@ use crate::descriptor::FromRawDescriptor;
 use crate::descriptor::IntoRawDescriptor;
 use crate::descriptor::SafeDescriptor;
 use crate::EventReadResult;
+use crate::EventWaitResult;
 
 /// A safe wrapper around a Linux eventfd (man 2 eventfd).
 ///
@@ -92,6 +93,29 @@ impl PlatformEvent {
     /// and the count is reset to 0. If a timeout does occur then this function will return
     /// EventReadResult::Timeout.
     pub fn read_timeout(&self, timeout: Duration) -> Result<EventReadResult> {
+        if self.wait_timeout(timeout)? == EventWaitResult::TimedOut {
+            return Ok(EventReadResult::Timeout);
+        }
+
+        let mut buf = 0u64;
+        // This is safe because we made this fd and the pointer we pass can not overflow because
+        // we give the syscall's size parameter properly.
+        let ret = unsafe {
+            libc::read(
+                self.as_raw_descriptor(),
+                &mut buf as *mut _ as *mut c_void,
+                mem::size_of::<u64>(),
+            )
+        };
+        if ret < 0 {
+            return errno_result();
+        }
+        Ok(EventReadResult::Count(buf))
+    }
+
+    /// Blocks for a maximum of `timeout` duration until the eventfd's count is non-zero, leaving
+    /// the count as is.
+    pub fn wait_timeout(&self, timeout: Duration) -> Result<EventWaitResult> {
         let mut pfd = libc::pollfd {
             fd: self.as_raw_descriptor(),
             events: POLLIN,
@@ -113,23 +137,9 @@ impl PlatformEvent {
 
         // no return events (revents) means we got a timeout
         if pfd.revents == 0 {
-            return Ok(EventReadResult::Timeout);
-        }
-
-        let mut buf = 0u64;
-        // This is safe because we made this fd and the pointer we pass can not overflow because
-        // we give the syscall's size parameter properly.
-        let ret = unsafe {
-            libc::read(
-                self.as_raw_descriptor(),
-                &mut buf as *mut _ as *mut c_void,
-                mem::size_of::<u64>(),
-            )
-        };
-        if ret < 0 {
-            return errno_result();
+            return Ok(EventWaitResult::TimedOut);
         }
-        Ok(EventReadResult::Count(buf))
+        Ok(EventWaitResult::Signaled)
     }
 
     /// Clones this eventfd, internally creating a new file descriptor. The new eventfd will share
@@ -206,4 +216,33 @@ mod tests {
             EventReadResult::Timeout
         );
     }
+
+    #[test]
+    fn wait_timeout() {
+        let evt = PlatformEvent::new().unwrap();
+        assert_eq!(
+            evt.wait_timeout(Duration::from_millis(1)),
+            Ok(EventWaitResult::TimedOut)
+        );
+
+        evt.write(3).unwrap();
+        assert_eq!(
+            evt.wait_timeout(Duration::from_secs(10)),
+            Ok(EventWaitResult::Signaled)
+        );
+        // Waiting leaves the count for the reader.
+        assert_eq!(evt.read(), Ok(3));
+    }
+
+    #[test]
+    fn wait_timeout_signaled_by_other_thread() {
+        let evt = PlatformEvent::new().unwrap();
+        let writer_evt = evt.try_clone().unwrap();
+        let writer = std::thread::spawn(move || writer_evt.write(1).unwrap());
+        assert_eq!(
+            evt.wait_timeout(Duration::from_secs(10)),
+            Ok(EventWaitResult::Signaled)
+        );
+        writer.join().unwrap();
+    }
 }
@@ -8,41 +8,98 @@ use libc::fcntl;
 use libc::EINVAL;
 use libc::F_GETFL;
 use libc::O_ACCMODE;
+use libc::O_APPEND;
+use libc::O_DIRECT;
+use libc::O_NONBLOCK;
 use libc::O_RDONLY;
 use libc::O_RDWR;
+use libc::O_SYNC;
 use libc::O_WRONLY;
 
+use super::add_fd_flags;
+use super::clear_fd_flags;
 use super::errno_result;
 use super::Error;
 use super::Result;
 
+/// The access mode a file was opened with.
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
-pub enum FileFlags {
+pub enum AccessMode {
     Read,
     Write,
     ReadWrite,
 }
 
+/// The file status flags of an open file.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct FileFlags {
+    pub access_mode: AccessMode,
+    /// `O_NONBLOCK`: I/O returns `EAGAIN` instead of waiting.
+    pub nonblock: bool,
+    /// `O_APPEND`: writes always go to the end of the file.
+    pub append: bool,
+    /// `O_DIRECT`: I/O bypasses the page cache.
+    pub direct: bool,
+    /// `O_SYNC`: writes wait for the data and metadata to reach the storage.
+    pub sync: bool,
+}
+
 impl FileFlags {
     pub fn from_file(file: &dyn AsRawFd) -> Result<FileFlags> {
         // Trivially safe because fcntl with the F_GETFL command is totally safe and we check for
         // error.
         let flags = unsafe { fcntl(file.as_raw_fd(), F_GETFL) };
         if flags == -1 {
-            errno_result()
-        } else {
-            match flags & O_ACCMODE {
-                O_RDONLY => Ok(FileFlags::Read),
-                O_WRONLY => Ok(FileFlags::Write),
-                O_RDWR => Ok(FileFlags::ReadWrite),
-                _ => Err(Error::new(EINVAL)),
-            }
+            return errno_result();
         }
+        let access_mode = match flags & O_ACCMODE {
+            O_RDONLY => AccessMode::Read,
+            O_WRONLY => AccessMode::Write,
+            O_RDWR => AccessMode::ReadWrite,
+            _ => return Err(Error::new(EINVAL)),
+        };
+        Ok(FileFlags {
+            access_mode,
+            nonblock: flags & O_NONBLOCK != 0,
+            append: flags & O_APPEND != 0,
+            direct: flags & O_DIRECT != 0,
+            // O_SYNC includes the O_DSYNC bit, which may be set on its own.
+            sync: flags & O_SYNC == O_SYNC,
+        })
+    }
+
+    pub fn access_mode(&self) -> AccessMode {
+        self.access_mode
+    }
+
+    /// Sets or clears `O_NONBLOCK` on `file`, leaving its other flags alone.
+    pub fn set_nonblock(file: &dyn AsRawFd, nonblock: bool) -> Result<()> {
+        set_flag(file, O_NONBLOCK, nonblock)
+    }
+
+    /// Sets or clears `O_DIRECT` on `file`, leaving its other flags alone.
+    ///
+    /// Fails with `EINVAL` if the file system of `file` doesn't support direct I/O.
+    pub fn set_direct(file: &dyn AsRawFd, direct: bool) -> Result<()> {
+        set_flag(file, O_DIRECT, direct)
+    }
+}
+
+fn set_flag(file: &dyn AsRawFd, flag: libc::c_int, enable: bool) -> Result<()> {
+    if enable {
+        add_fd_flags(file.as_raw_fd(), flag)
+    } else {
+        clear_fd_flags(file.as_raw_fd(), flag)
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::fs::OpenOptions;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    use tempfile::NamedTempFile;
+
     use super::super::pipe;
     use super::super::PlatformEvent;
     use super::*;
@@ -50,13 +107,63 @@ mod tests {
     #[test]
     fn pipe_pair() {
         let (read_pipe, write_pipe) = pipe(true).unwrap();
-        assert_eq!(FileFlags::from_file(&read_pipe).unwrap(), FileFlags::Read);
-        assert_eq!(FileFlags::from_file(&write_pipe).unwrap(), FileFlags::Write);
+        let read_flags = FileFlags::from_file(&read_pipe).unwrap();
+        let write_flags = FileFlags::from_file(&write_pipe).unwrap();
+        assert_eq!(read_flags.access_mode(), AccessMode::Read);
+        assert_eq!(write_flags.access_mode(), AccessMode::Write);
+        for flags in [read_flags, write_flags] {
+            assert!(!flags.nonblock);
+            assert!(!flags.append);
+            assert!(!flags.direct);
+            assert!(!flags.sync);
+        }
     }
 
     #[test]
     fn event() {
         let evt = PlatformEvent::new().unwrap();
-        assert_eq!(FileFlags::from_file(&evt).unwrap(), FileFlags::ReadWrite);
+        assert_eq!(
+            FileFlags::from_file(&evt).unwrap().access_mode(),
+            AccessMode::ReadWrite
+        );
+    }
+
+    #[test]
+    fn direct_file() {
+        let temp = NamedTempFile::new().unwrap();
+        let file = match OpenOptions::new()
+            .read(true)
+            .append(true)
+            .custom_flags(O_DIRECT | O_SYNC)
+            .open(temp.path())
+        {
+            Ok(file) => file,
+            // The file system of the temporary directory doesn't support direct I/O.
+            Err(e) if e.raw_os_error() == Some(EINVAL) => return,
+            Err(e) => panic!("failed to open {}: {}", temp.path().display(), e),
+        };
+        let flags = FileFlags::from_file(&file).unwrap();
+        assert_eq!(flags.access_mode(), AccessMode::ReadWrite);
+        assert!(flags.direct);
+        assert!(flags.append);
+        assert!(flags.sync);
+        assert!(!flags.nonblock);
+
+        FileFlags::set_direct(&file, false).unwrap();
+        let flags = FileFlags::from_file(&file).unwrap();
+        assert!(!flags.direct);
+        assert!(flags.append);
+        assert!(flags.sync);
+    }
+
+    #[test]
+    fn toggle_nonblock() {
+        let (read_pipe, _write_pipe) = pipe(true).unwrap();
+        for nonblock in [true, true, false, true, false] {
+            FileFlags::set_nonblock(&read_pipe, nonblock).unwrap();
+            let flags = FileFlags::from_file(&read_pipe).unwrap();
+            assert_eq!(flags.nonblock, nonblock);
+            assert_eq!(flags.access_mode(), AccessMode::Read);
+        }
     }
 }
@@ -31,6 +31,8 @@ use crate::SafeDescriptor;
 pub enum Error {
     #[error("`add_fd_mapping` is unsupported")]
     AddFdMappingIsUnsupported,
+    #[error("access to the mapping raised SIGBUS")]
+    BusError,
     #[error("requested memory out of range")]
     InvalidAddress,
     #[error("invalid argument provided when creating mapping")]
@@ -89,6 +91,29 @@ impl dyn MappedRegion {
             Err(Error::SystemCallFailed(ErrnoError::last()))
         }
     }
+
+    /// Calls mprotect to change the protection of `size` bytes starting at `offset` from the start
+    /// of the region to `prot`. `offset` must be page aligned and `offset`..`offset+size` must be
+    /// contained within the `MappedRegion`.
+    pub fn mprotect(&self, offset: usize, size: usize, prot: Protection) -> Result<()> {
+        validate_includes_range(self.size(), offset, size)?;
+
+        // Safe because the MemoryMapping/MemoryMappingArena interface ensures our pointer and size
+        // are correct, and we've validated that `offset`..`offset+size` is in the range owned by
+        // this `MappedRegion`.
+        let ret = unsafe {
+            libc::mprotect(
+                (self.as_ptr() as usize + offset) as *mut libc::c_void,
+                size,
+                prot.into(),
+            )
+        };
+        if ret != -1 {
+            Ok(())
+        } else {
+            Err(Error::SystemCallFailed(ErrnoError::last()))
+        }
+    }
 }
 
 /// Wraps an anonymous shared memory mapping in the current process. Provides
@@ -360,6 +385,9 @@ impl MemoryMapping {
 
     /// Reads data from a file descriptor and writes it to guest memory.
     ///
+    /// Returns the number of bytes read, which is less than `count` if `src` reached its end or
+    /// failed after some of the data was read. Interrupted reads are retried.
+    ///
     /// # Arguments
     /// * `mem_offset` - Begin writing memory at this offset.
     /// * `src` - Read from `src` to memory.
@@ -383,43 +411,48 @@ impl MemoryMapping {
     /// ```
     pub fn read_to_memory(
         &self,
-        mut mem_offset: usize,
+        mem_offset: usize,
         src: &dyn AsRawDescriptor,
-        mut count: usize,
-    ) -> Result<()> {
+        count: usize,
+    ) -> Result<usize> {
+        if count == 0 {
+            return Ok(0);
+        }
         self.range_end(mem_offset, count)
             .map_err(|_| Error::InvalidRange(mem_offset, count, self.size()))?;
-        while count > 0 {
+        let mut done = 0;
+        while done < count {
             // The check above ensures that no memory outside this slice will get accessed by this
             // read call.
             match unsafe {
                 read(
                     src.as_raw_descriptor(),
-                    self.as_ptr().add(mem_offset) as *mut c_void,
-                    count,
+                    self.as_ptr().add(mem_offset + done) as *mut c_void,
+                    count - done,
                 )
             } {
-                0 => {
-                    return Err(Error::ReadToMemory(io::Error::from(
-                        io::ErrorKind::UnexpectedEof,
-                    )))
-                }
-                r if r < 0 => return Err(Error::ReadToMemory(io::Error::last_os_error())),
-                ret => {
-                    let bytes_read = ret as usize;
-                    match count.checked_sub(bytes_read) {
-                        Some(count_remaining) => count = count_remaining,
-                        None => break,
+                0 => break,
+                r if r < 0 => {
+                    let e = io::Error::last_os_error();
+                    if e.kind() == io::ErrorKind::Interrupted {
+                        continue;
+                    }
+                    if done > 0 {
+                        break;
                     }
-                    mem_offset += ret as usize;
+                    return Err(Error::ReadToMemory(e));
                 }
+                ret => done += ret as usize,
             }
         }
-        Ok(())
+        Ok(done)
     }
 
     /// Writes data from memory to a file descriptor.
     ///
+    /// Returns the number of bytes written, which is less than `count` if `dst` stopped accepting
+    /// data or failed after some of the data was written. Interrupted writes are retried.
+    ///
     /// # Arguments
     /// * `mem_offset` - Begin reading memory from this offset.
     /// * `dst` - Write from memory to `dst`.
@@ -442,39 +475,41 @@ impl MemoryMapping {
     /// ```
     pub fn write_from_memory(
         &self,
-        mut mem_offset: usize,
+        mem_offset: usize,
         dst: &dyn AsRawDescriptor,
-        mut count: usize,
-    ) -> Result<()> {
+        count: usize,
+    ) -> Result<usize> {
+        if count == 0 {
+            return Ok(0);
+        }
         self.range_end(mem_offset, count)
             .map_err(|_| Error::InvalidRange(mem_offset, count, self.size()))?;
-        while count > 0 {
+        let mut done = 0;
+        while done < count {
             // The check above ensures that no memory outside this slice will get accessed by this
             // write call.
             match unsafe {
                 write(
                     dst.as_raw_descriptor(),
-                    self.as_ptr().add(mem_offset) as *const c_void,
-                    count,
+                    self.as_ptr().add(mem_offset + done) as *const c_void,
+                    count - done,
                 )
             } {
-                0 => {
-                    return Err(Error::WriteFromMemory(io::Error::from(
-                        io::ErrorKind::WriteZero,
-                    )))
-                }
-                ret if ret < 0 => return Err(Error::WriteFromMemory(io::Error::last_os_error())),
-                ret => {
-                    let bytes_written = ret as usize;
-                    match count.checked_sub(bytes_written) {
-                        Some(count_remaining) => count = count_remaining,
-                        None => break,
+                0 => break,
+                r if r < 0 => {
+                    let e = io::Error::last_os_error();
+                    if e.kind() == io::ErrorKind::Interrupted {
+                        continue;
+                    }
+                    if done > 0 {
+                        break;
                     }
-                    mem_offset += ret as usize;
+                    return Err(Error::WriteFromMemory(e));
                 }
+                ret => done += ret as usize,
             }
         }
-        Ok(())
+        Ok(done)
     }
 
     /// Uses madvise to tell the kernel to remove the specified range.  Subsequent reads
@@ -498,6 +533,48 @@ impl MemoryMapping {
         }
     }
 
+    /// Madvise the kernel to use Huge Pages for `count` bytes of the mapping at `mem_offset`, or to
+    /// never use them if `enable` is false. `mem_offset` must be page aligned.
+    pub fn set_hugepages_range(&self, mem_offset: usize, count: usize, enable: bool) -> Result<()> {
+        self.range_end(mem_offset, count)
+            .map_err(|_| Error::InvalidRange(mem_offset, count, self.size()))?;
+        let advice = if enable {
+            libc::MADV_HUGEPAGE
+        } else {
+            libc::MADV_NOHUGEPAGE
+        };
+        // This is safe because the range was checked to be within the mapping, the advice doesn't
+        // change its contents, and the return value is checked.
+        let ret =
+            unsafe { libc::madvise((self.addr as usize + mem_offset) as *mut _, count, advice) };
+        if ret < 0 {
+            Err(Error::SystemCallFailed(super::Error::last()))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Disable host swap for `count` bytes of the mapping at `mem_offset`.
+    pub fn lock_range(&self, mem_offset: usize, count: usize) -> Result<()> {
+        self.range_end(mem_offset, count)
+            .map_err(|_| Error::InvalidRange(mem_offset, count, self.size()))?;
+        // Safe because MLOCK_ONFAULT only affects the swap behavior of the kernel, so it has no
+        // impact on rust semantics, and the range was checked to be within the mapping.
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_mlock2,
+                (self.addr as usize + mem_offset) as *const libc::c_void,
+                count,
+                libc::MLOCK_ONFAULT,
+            )
+        };
+        if ret < 0 {
+            Err(Error::SystemCallFailed(super::Error::last()))
+        } else {
+            Ok(())
+        }
+    }
+
     /// Disable host swap for this mapping.
     pub fn lock_all(&self) -> Result<()> {
         let ret = unsafe {
@@ -768,7 +845,7 @@ impl CrateMemoryMapping {
         mem_offset: usize,
         src: &dyn AsRawDescriptor,
         count: usize,
-    ) -> Result<()> {
+    ) -> Result<usize> {
         self.mapping.read_to_memory(mem_offset, src, count)
     }
 
@@ -777,7 +854,7 @@ impl CrateMemoryMapping {
         mem_offset: usize,
         dst: &dyn AsRawDescriptor,
         count: usize,
-    ) -> Result<()> {
+    ) -> Result<usize> {
         self.mapping.write_from_memory(mem_offset, dst, count)
     }
 
@@ -796,6 +873,10 @@ pub trait Unix {
     fn remove_range(&self, mem_offset: usize, count: usize) -> Result<()>;
     /// Disable host swap for this mapping.
     fn lock_all(&self) -> Result<()>;
+    /// Disable host swap for the specified range of the mapping.
+    fn lock_range(&self, mem_offset: usize, count: usize) -> Result<()>;
+    /// Allow or forbid Huge Pages for the specified range of the mapping.
+    fn set_hugepages_range(&self, mem_offset: usize, count: usize, enable: bool) -> Result<()>;
 }
 
 impl Unix for CrateMemoryMapping {
@@ -805,6 +886,12 @@ impl Unix for CrateMemoryMapping {
     fn lock_all(&self) -> Result<()> {
         self.mapping.lock_all()
     }
+    fn lock_range(&self, mem_offset: usize, count: usize) -> Result<()> {
+        self.mapping.lock_range(mem_offset, count)
+    }
+    fn set_hugepages_range(&self, mem_offset: usize, count: usize, enable: bool) -> Result<()> {
+        self.mapping.set_hugepages_range(mem_offset, count, enable)
+    }
 }
 
 pub trait MemoryMappingBuilderUnix<'a> {
@@ -881,6 +968,9 @@ impl<'a> MemoryMappingBuilder<'a> {
 
 #[cfg(test)]
 mod tests {
+    use std::io::Read;
+    use std::io::Write;
+
     use data_model::VolatileMemory;
     use data_model::VolatileMemoryError;
     use tempfile::tempfile;
@@ -1088,4 +1178,81 @@ mod tests {
             e => panic!("unexpected error: {}", e),
         }
     }
+
+    #[test]
+    fn read_to_memory_zero_count() {
+        let m = MemoryMappingBuilder::new(1024).build().unwrap();
+        let (r, w) = crate::pipe(true).unwrap();
+        assert_eq!(m.read_to_memory(0, &r, 0).unwrap(), 0);
+        // A zero count is never out of range.
+        assert_eq!(m.read_to_memory(4096, &r, 0).unwrap(), 0);
+        assert_eq!(m.write_from_memory(4096, &w, 0).unwrap(), 0);
+    }
+
+    #[test]
+    fn read_to_memory_chunks() {
+        let m = MemoryMappingBuilder::new(1024).build().unwrap();
+        let (r, mut w) = crate::pipe(true).unwrap();
+        let writer = std::thread::spawn(move || {
+            for i in 0..16u8 {
+                w.write_all(&[i; 4]).unwrap();
+                std::thread::sleep(std::time::Duration::from_millis(1));
+            }
+        });
+
+        // The pipe delivers the data a few bytes at a time and then ends before `count`.
+        assert_eq!(m.read_to_memory(32, &r, 128).unwrap(), 64);
+        writer.join().unwrap();
+        for i in 0..16 {
+            assert_eq!(
+                m.read_obj::<u32>(32 + i * 4).unwrap(),
+                u32::from_ne_bytes([i as u8; 4])
+            );
+        }
+        assert_eq!(m.read_obj::<u32>(96).unwrap(), 0);
+    }
+
+    #[test]
+    fn write_from_memory_chunks() {
+        let m = MemoryMappingBuilder::new(1024).build().unwrap();
+        m.write_slice(&[0x5a; 256], 0).unwrap();
+        let (mut r, w) = crate::pipe(true).unwrap();
+        let reader = std::thread::spawn(move || {
+            let mut data = Vec::new();
+            let mut buf = [0u8; 8];
+            loop {
+                match r.read(&mut buf).unwrap() {
+                    0 => return data,
+                    n => data.extend_from_slice(&buf[..n]),
+                }
+            }
+        });
+
+        assert_eq!(m.write_from_memory(0, &w, 256).unwrap(), 256);
+        drop(w);
+        assert_eq!(reader.join().unwrap(), [0x5a; 256]);
+    }
+
+    #[test]
+    fn mprotect() {
+        let ps = pagesize();
+        let m = MemoryMappingBuilder::new(2 * ps).build().unwrap();
+        m.write_obj(0x55aa_u32, ps).unwrap();
+
+        <dyn MappedRegion>::mprotect(&m, ps, ps, Protection::read()).unwrap();
+        assert_eq!(m.read_obj::<u32>(ps).unwrap(), 0x55aa);
+        <dyn MappedRegion>::mprotect(&m, ps, ps, Protection::read_write()).unwrap();
+        m.write_obj(0xaa55_u32, ps).unwrap();
+        assert_eq!(m.read_obj::<u32>(ps).unwrap(), 0xaa55);
+
+        match <dyn MappedRegion>::mprotect(&m, ps, 2 * ps, Protection::read()).unwrap_err() {
+            Error::InvalidAddress => {}
+            e => panic!("unexpected error: {}", e),
+        }
+        // The start of the range must be page aligned.
+        match <dyn MappedRegion>::mprotect(&m, 1, ps, Protection::read()).unwrap_err() {
+            Error::SystemCallFailed(e) => assert_eq!(e.errno(), libc::EINVAL),
+            e => panic!("unexpected error: {}", e),
+        }
+    }
 }
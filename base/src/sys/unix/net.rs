@@ -399,6 +399,35 @@ impl UnixSeqpacket {
         Ok(UnixSeqpacket { fd })
     }
 
+    /// Like `connect`, but fails with `ETIMEDOUT` if the listener doesn't make room for the
+    /// connection within `timeout`, e.g. because it stopped accepting connections.
+    pub fn connect_with_timeout<P: AsRef<Path>>(path: P, timeout: Duration) -> io::Result<Self> {
+        // Safe socket initialization since we handle the returned error.
+        let socket = unsafe {
+            match libc::socket(libc::AF_UNIX, libc::SOCK_SEQPACKET, 0) {
+                -1 => return Err(io::Error::last_os_error()),
+                fd => UnixSeqpacket::from_raw_fd(fd),
+            }
+        };
+        // A blocking connect of a unix socket waits for the listener's backlog for at most the
+        // send timeout of the socket.
+        socket.set_write_timeout(Some(timeout))?;
+
+        let (addr, len) = sockaddr_un(path.as_ref())?;
+        // Safe connect since we handle the error and use the right length generated from
+        // `sockaddr_un`.
+        let ret = unsafe { libc::connect(socket.fd, &addr as *const _ as *const _, len) };
+        if ret < 0 {
+            let err = io::Error::last_os_error();
+            return Err(match err.raw_os_error() {
+                Some(libc::EAGAIN) => io::Error::from_raw_os_error(libc::ETIMEDOUT),
+                _ => err,
+            });
+        }
+        socket.set_write_timeout(None)?;
+        Ok(socket)
+    }
+
     /// Creates a pair of connected `SOCK_SEQPACKET` sockets.
     ///
     /// Both returned file descriptors have the `CLOEXEC` flag set.s
@@ -1041,6 +1070,27 @@ mod tests {
             UnixSeqpacket::connect(socket_path.as_path()).expect("UnixSeqpacket::connect failed");
     }
 
+    #[test]
+    fn unix_seqpacket_connect_with_timeout() {
+        let mut socket_path = tmpdir();
+        socket_path.push("connect_with_timeout");
+        let _listener = UnlinkUnixSeqpacketListener(
+            UnixSeqpacketListener::bind(&socket_path)
+                .expect("failed to create UnixSeqpacketListener"),
+        );
+
+        // Nothing accepts the connections, so they eventually fill the listener's backlog.
+        let mut sockets = Vec::new();
+        let err = loop {
+            match UnixSeqpacket::connect_with_timeout(&socket_path, Duration::from_millis(10)) {
+                Ok(socket) => sockets.push(socket),
+                Err(e) => break e,
+            }
+            assert!(sockets.len() <= 1024, "backlog never filled");
+        };
+        assert_eq!(err.raw_os_error(), Some(libc::ETIMEDOUT));
+    }
+
     #[test]
     fn unix_seqpacket_path_listener_accept_with_timeout() {
         let mut socket_path = tmpdir();
@@ -189,11 +189,7 @@ impl SharedMemory {
     ///
     /// This may fail if this instance was not constructed from a memfd.
     pub fn get_seals(&self) -> Result<MemfdSeals> {
-        let ret = unsafe { fcntl(self.fd.as_raw_descriptor(), F_GET_SEALS) };
-        if ret < 0 {
-            return errno_result();
-        }
-        Ok(MemfdSeals(ret))
+        memfd_seals(&self.fd)
     }
 
     /// Adds the given set of memfd seals.
@@ -310,6 +306,17 @@ impl From<SharedMemory> for File {
     }
 }
 
+/// Gets the memfd seals of `descriptor`. This fails if `descriptor` is not a memfd, e.g. to check
+/// that a descriptor passed to crosvm holds a file in memory.
+pub fn memfd_seals(descriptor: &dyn AsRawDescriptor) -> Result<MemfdSeals> {
+    // Safe because this doesn't modify any memory and we check the return value.
+    let ret = unsafe { fcntl(descriptor.as_raw_descriptor(), F_GET_SEALS) };
+    if ret < 0 {
+        return errno_result();
+    }
+    Ok(MemfdSeals(ret))
+}
+
 /// Checks if the kernel we are running on has memfd_create. It was introduced in 3.17.
 /// Only to be used from tests to prevent running on ancient kernels that won't
 /// support the functionality anyways.
@@ -354,6 +361,7 @@ mod tests {
     use data_model::VolatileMemory;
 
     use super::kernel_has_memfd;
+    use super::memfd_seals;
     use super::SharedMemory;
     use crate::MemoryMappingBuilder;
 
@@ -421,6 +429,17 @@ mod tests {
         shm.add_seals(seals).unwrap_err();
     }
 
+    #[test]
+    fn memfd_seals_of_file() {
+        if !kernel_has_memfd() {
+            return;
+        }
+        let shm = create_test_shmem();
+        assert_eq!(memfd_seals(&shm).expect("failed to get seals").bitmask(), 0);
+        let file = tempfile::tempfile().expect("failed to create tempfile");
+        assert_eq!(memfd_seals(&file).unwrap_err().errno(), libc::EINVAL);
+    }
+
     #[test]
     fn mmap_page() {
         if !kernel_has_memfd() {
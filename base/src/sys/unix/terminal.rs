@@ -11,17 +11,20 @@ use libc::read;
 use libc::tcgetattr;
 use libc::tcsetattr;
 use libc::termios;
+use libc::winsize;
 use libc::ECHO;
 use libc::ICANON;
 use libc::ISIG;
 use libc::O_NONBLOCK;
 use libc::STDIN_FILENO;
 use libc::TCSANOW;
+use libc::TIOCGWINSZ;
 
 use super::add_fd_flags;
 use super::clear_fd_flags;
 use super::errno_result;
 use super::Result;
+use crate::AsRawDescriptor;
 
 fn modify_mode<F: FnOnce(&mut termios)>(fd: RawFd, f: F) -> Result<()> {
     // Safe because we check the return value of isatty.
@@ -67,6 +70,19 @@ pub fn read_raw_stdin(out: &mut [u8]) -> Result<usize> {
     unsafe { read_raw(STDIN_FILENO, out) }
 }
 
+/// Returns the size of the terminal `descriptor` as `(columns, rows)`. This fails if `descriptor`
+/// isn't a terminal.
+pub fn terminal_size(descriptor: &dyn AsRawDescriptor) -> Result<(u16, u16)> {
+    // Safe because winsize is plain old data.
+    let mut size: winsize = unsafe { zeroed() };
+    // Safe because the kernel only writes a winsize to `size`, and we check the return value.
+    let ret = unsafe { libc::ioctl(descriptor.as_raw_descriptor(), TIOCGWINSZ, &mut size) };
+    if ret < 0 {
+        return errno_result();
+    }
+    Ok((size.ws_col, size.ws_row))
+}
+
 /// Trait for file descriptors that are TTYs, according to `isatty(3)`.
 ///
 /// This is marked unsafe because the implementation must promise that the returned RawFd is a valid
@@ -39,6 +39,7 @@ use crate::descriptor::IntoRawDescriptor;
 use crate::descriptor::SafeDescriptor;
 use crate::Event;
 use crate::EventReadResult;
+use crate::EventWaitResult;
 
 /// A safe wrapper around Windows synchapi methods used to mimic Linux eventfd (man 2 eventfd).
 /// Since the eventfd isn't using "EFD_SEMAPHORE", we don't need to keep count so we can just use
@@ -174,6 +175,22 @@ impl PlatformEvent {
     /// and the event resets. If a timeout does occur then this function will return
     /// EventReadResult::Timeout.
     pub fn read_timeout(&self, timeout: Duration) -> Result<EventReadResult> {
+        match self.wait_timeout(timeout)? {
+            EventWaitResult::TimedOut => Ok(EventReadResult::Timeout),
+            EventWaitResult::Signaled => {
+                // Safe because self manages the handle and we know it was valid as it
+                // was just successfully waited upon. It is safe to reset a non manual reset event as well.
+                match unsafe { ResetEvent(self.event_handle.as_raw_descriptor()) } {
+                    0 => errno_result(),
+                    _ => Ok(EventReadResult::Count(1)),
+                }
+            }
+        }
+    }
+
+    /// Blocks for a maximum of `timeout` duration until the event is signaled. A manual reset
+    /// event stays signaled, while the wait resets an auto reset event.
+    pub fn wait_timeout(&self, timeout: Duration) -> Result<EventWaitResult> {
         let wait_result = unsafe {
             WaitForSingleObject(
                 self.event_handle.as_raw_descriptor(),
@@ -184,15 +201,8 @@ impl PlatformEvent {
         // We are using an infinite timeout so we can ignore WAIT_ABANDONED
         match wait_result {
             WAIT_FAILED => errno_result(),
-            WAIT_TIMEOUT => Ok(EventReadResult::Timeout),
-            _ => {
-                // Safe because self manages the handle and we know it was valid as it
-                // was just successfully waited upon. It is safe to reset a non manual reset event as well.
-                match unsafe { ResetEvent(self.event_handle.as_raw_descriptor()) } {
-                    0 => errno_result(),
-                    _ => Ok(EventReadResult::Count(1)),
-                }
-            }
+            WAIT_TIMEOUT => Ok(EventWaitResult::TimedOut),
+            _ => Ok(EventWaitResult::Signaled),
         }
     }
 
@@ -324,4 +334,33 @@ mod tests {
             EventReadResult::Timeout
         );
     }
+
+    #[test]
+    fn wait_timeout() {
+        let evt = PlatformEvent::new().unwrap();
+        assert_eq!(
+            evt.wait_timeout(Duration::from_millis(1)),
+            Ok(EventWaitResult::TimedOut)
+        );
+
+        evt.write(1).unwrap();
+        assert_eq!(
+            evt.wait_timeout(Duration::from_secs(10)),
+            Ok(EventWaitResult::Signaled)
+        );
+        // Waiting leaves a manual reset event signaled for the reader.
+        assert_eq!(evt.read(), Ok(1));
+    }
+
+    #[test]
+    fn wait_timeout_signaled_by_other_thread() {
+        let evt = PlatformEvent::new().unwrap();
+        let writer_evt = evt.try_clone().unwrap();
+        let writer = std::thread::spawn(move || writer_evt.write(1).unwrap());
+        assert_eq!(
+            evt.wait_timeout(Duration::from_secs(10)),
+            Ok(EventWaitResult::Signaled)
+        );
+        writer.join().unwrap();
+    }
 }
@@ -104,6 +104,37 @@ impl dyn MappedRegion {
             Err(Error::SystemCallFailed(super::Error::last()))
         }
     }
+
+    /// Calls VirtualProtect to make `size` bytes starting at `offset` from the start of the region
+    /// read-only, or read/write if `prot` allows writes. `offset`..`offset+size` must be contained
+    /// within the `MappedRegion`.
+    pub fn mprotect(&self, offset: usize, size: usize, prot: Protection) -> Result<()> {
+        validate_includes_range(self.size(), offset, size)?;
+
+        let new_protect = if prot.allows(&Protection::write()) {
+            PAGE_READWRITE
+        } else {
+            winapi::um::winnt::PAGE_READONLY
+        };
+        let mut old_protect = 0;
+        // Safe because the MemoryMapping/MemoryMappingArena interface ensures our pointer and size
+        // are correct, and we've validated that `offset`..`offset+size` is in the range owned by
+        // this `MappedRegion`.
+        let ret = unsafe {
+            use winapi::um::memoryapi::VirtualProtect;
+            VirtualProtect(
+                (self.as_ptr() as usize + offset) as *mut libc::c_void,
+                size,
+                new_protect,
+                &mut old_protect,
+            )
+        };
+        if ret != 0 {
+            Ok(())
+        } else {
+            Err(Error::SystemCallFailed(super::Error::last()))
+        }
+    }
 }
 
 /// Wraps an anonymous shared memory mapping in the current process. Provides
@@ -185,7 +216,7 @@ impl CrateMemoryMapping {
         mem_offset: usize,
         src: &mut F,
         count: usize,
-    ) -> Result<()> {
+    ) -> Result<usize> {
         self.mapping.read_to_memory(mem_offset, src, count)
     }
 
@@ -194,7 +225,7 @@ impl CrateMemoryMapping {
         mem_offset: usize,
         dst: &mut F,
         count: usize,
-    ) -> Result<()> {
+    ) -> Result<usize> {
         self.mapping.write_from_memory(mem_offset, dst, count)
     }
 
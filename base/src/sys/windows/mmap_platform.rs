@@ -204,6 +204,9 @@ impl MemoryMapping {
 
     /// Reads data from a file descriptor and writes it to guest memory.
     ///
+    /// Returns the number of bytes read, which is less than `count` if `src` reached its end or
+    /// failed after some of the data was read. Interrupted reads are retried.
+    ///
     /// # Arguments
     /// * `mem_offset` - Begin writing memory at this offset.
     /// * `src` - Read from `src` to memory.
@@ -233,20 +236,33 @@ impl MemoryMapping {
         mem_offset: usize,
         src: &mut F,
         count: usize,
-    ) -> Result<()> {
+    ) -> Result<usize> {
+        if count == 0 {
+            return Ok(0);
+        }
         self.range_end(mem_offset, count)
             .map_err(|_| Error::InvalidRange(mem_offset, count, self.size()))?;
         // Safe because the check above ensures that no memory outside this slice will get accessed
         // by this read call.
         let buf: &mut [u8] = unsafe { from_raw_parts_mut(self.as_ptr().add(mem_offset), count) };
-        match src.read_exact(buf) {
-            Err(e) => Err(Error::ReadToMemory(e)),
-            Ok(()) => Ok(()),
+        let mut done = 0;
+        while done < count {
+            match src.read(&mut buf[done..]) {
+                Ok(0) => break,
+                Ok(n) => done += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(_) if done > 0 => break,
+                Err(e) => return Err(Error::ReadToMemory(e)),
+            }
         }
+        Ok(done)
     }
 
     /// Writes data from memory to a file descriptor.
     ///
+    /// Returns the number of bytes written, which is less than `count` if `dst` stopped accepting
+    /// data or failed after some of the data was written. Interrupted writes are retried.
+    ///
     /// # Arguments
     /// * `mem_offset` - Begin reading memory from this offset.
     /// * `dst` - Write from memory to `dst`.
@@ -275,16 +291,26 @@ impl MemoryMapping {
         mem_offset: usize,
         dst: &mut F,
         count: usize,
-    ) -> Result<()> {
+    ) -> Result<usize> {
+        if count == 0 {
+            return Ok(0);
+        }
         self.range_end(mem_offset, count)
             .map_err(|_| Error::InvalidRange(mem_offset, count, self.size()))?;
         // Safe because the check above ensures that no memory outside this slice will get accessed
         // by this write call.
         let buf: &[u8] = unsafe { from_raw_parts(self.as_ptr().add(mem_offset), count) };
-        match dst.write_all(buf) {
-            Err(e) => Err(Error::WriteFromMemory(e)),
-            Ok(()) => Ok(()),
+        let mut done = 0;
+        while done < count {
+            match dst.write(&buf[done..]) {
+                Ok(0) => break,
+                Ok(n) => done += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(_) if done > 0 => break,
+                Err(e) => return Err(Error::WriteFromMemory(e)),
+            }
         }
+        Ok(done)
     }
 }
 
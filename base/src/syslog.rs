@@ -244,6 +244,9 @@ where
     pub syslog: bool,
     /// Facility to use for syslog output
     pub syslog_facility: Facility,
+    /// UUID of the VM to include in formatted log lines, so that they can be correlated with the
+    /// logs of the guest
+    pub vm_uuid: Option<String>,
 }
 
 impl<'a> Default
@@ -259,6 +262,7 @@ impl<'a> Default
             syslog_facility: Facility::User,
             pipe_formatter: FORMATTER_NONE,
             pipe_fd: None,
+            vm_uuid: None,
         }
     }
 }
@@ -276,17 +280,21 @@ impl State {
 
         let create_formatted_builder = || {
             let mut builder = env_logger::Builder::new();
+            let vm_uuid = cfg.vm_uuid.clone();
 
             // Output log lines w/ local ISO 8601 timestamps.
-            builder.format(|buf, record| {
-                writeln!(
+            builder.format(move |buf, record| {
+                write!(
                     buf,
-                    "[{} {:5} {}] {}",
+                    "[{} {:5} {}",
                     Local::now().format("%Y-%m-%dT%H:%M:%S%.9f%:z"),
                     record.level(),
                     record.module_path().unwrap_or("<missing module path>"),
-                    record.args()
-                )
+                )?;
+                if let Some(vm_uuid) = &vm_uuid {
+                    write!(buf, " vm={}", vm_uuid)?;
+                }
+                writeln!(buf, "] {}", record.args())
             });
             builder
         };
@@ -680,6 +688,34 @@ mod tests {
         assert_eq!(Vec::<u8>::new(), output.into_inner());
     }
 
+    #[test]
+    fn vm_uuid() {
+        let output = MockWrite::new();
+        let state = State::new(LogConfig {
+            pipe: Some(Box::new(output.clone())),
+            vm_uuid: Some("23546c3d-962d-4ebc-94d9-4acf50996944".to_owned()),
+            ..Default::default()
+        })
+        .unwrap();
+        state.log(
+            &log::RecordBuilder::new()
+                .level(Level::Info)
+                .module_path(Some("base::syslog"))
+                .args(format_args!("hello syslog"))
+                .build(),
+        );
+
+        std::mem::drop(state);
+        let line = String::from_utf8(output.into_inner()).unwrap();
+        assert!(
+            line.ends_with(
+                " INFO  base::syslog vm=23546c3d-962d-4ebc-94d9-4acf50996944] hello syslog\n"
+            ),
+            "{}",
+            line
+        );
+    }
+
     #[test]
     fn log_priority_try_from_number() {
         assert_eq!("0".try_into(), Ok(Priority::Emergency));
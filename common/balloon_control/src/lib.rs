@@ -21,6 +21,13 @@ pub enum BalloonTubeCommand {
         // size.
         allow_failure: bool,
     },
+    // Like Adjust without allow_failure, but asks the guest to take the pages from the guest
+    // memory region at `preferred_region_index`, in the order of `GuestMemory::regions`. Guests
+    // that don't support this get a plain Adjust.
+    AdjustWithPreference {
+        num_bytes: u64,
+        preferred_region_index: usize,
+    },
     // Fetch balloon stats. The ID can be used to discard stale states
     // if any previous stats requests failed or timed out.
     Stats {
@@ -43,6 +50,10 @@ pub struct BalloonStats {
     pub hugetlb_failures: Option<u64>,
     pub shared_memory: Option<u64>,
     pub unevictable_memory: Option<u64>,
+    // Bytes in the balloon that belong to each guest memory region, in the order of
+    // `GuestMemory::regions`.
+    #[serde(default)]
+    pub inflated_bytes_per_region: Vec<u64>,
 }
 
 // BalloonTubeResult are results to BalloonTubeCommand defined above.
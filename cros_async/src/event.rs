@@ -3,21 +3,28 @@
 // found in the LICENSE file.
 
 use std::mem::ManuallyDrop;
+use std::time::Duration;
 
 use base::AsRawDescriptor;
 use base::Event;
+use base::EventReadResult;
 use base::FromRawDescriptor;
+use base::Timer;
+use futures::pin_mut;
 
+use crate::select2;
 use crate::AsyncError;
 use crate::AsyncResult;
 use crate::Executor;
 use crate::IntoAsync;
 use crate::IoSourceExt;
+use crate::SelectResult;
+use crate::TimerAsync;
 
 /// An async version of `base::Event`.
 pub struct EventAsync {
     pub(crate) io_source: Box<dyn IoSourceExt<Event>>,
-    #[cfg(windows)]
+    // Whether `next_val` leaves the event unsignaled, as an auto-reset event would.
     pub(crate) reset_after_read: bool,
 }
 
@@ -44,9 +51,111 @@ impl EventAsync {
             ex,
         )
     }
+
+    /// Resets the event and returns the number of times it was signaled since the last reset, or
+    /// `None` if it isn't signaled, without waiting.
+    ///
+    /// Signals that arrive before the event is reset coalesce into a single count, which
+    /// `wait_and_reset` discards. On Windows the count is always 1.
+    pub fn next_val_nonblocking(&self) -> AsyncResult<Option<u64>> {
+        match self
+            .io_source
+            .as_source()
+            .read_timeout(Duration::ZERO)
+            .map_err(AsyncError::EventAsync)?
+        {
+            EventReadResult::Count(count) => Ok(Some(count)),
+            EventReadResult::Timeout => Ok(None),
+        }
+    }
+
+    /// Gets the next value from the event like `next_val`, or `None` if it isn't signaled within
+    /// `timeout`.
+    ///
+    /// A signal that comes after the timeout is left for the next call.
+    pub async fn next_val_timeout(
+        &self,
+        ex: &Executor,
+        timeout: Duration,
+    ) -> AsyncResult<Option<u64>> {
+        let mut timer = Timer::new().map_err(AsyncError::EventAsync)?;
+        timer.reset(timeout, None).map_err(AsyncError::EventAsync)?;
+        let timer = TimerAsync::new(timer, ex)?;
+
+        let next_val = self.next_val_cancel_safe();
+        pin_mut!(next_val);
+        let expired = timer.next_val();
+        pin_mut!(expired);
+        match select2(next_val, expired).await {
+            (SelectResult::Finished(res), _) => res.map(Some),
+            (SelectResult::Pending(_), SelectResult::Finished(res)) => res.map(|_| None),
+            (SelectResult::Pending(_), SelectResult::Pending(_)) => {
+                unreachable!("select2 returned before a future finished")
+            }
+        }
+    }
 }
 
 impl IntoAsync for Event {}
 
 // Safe because an `Event` is used underneath, which is safe to pass between threads.
 unsafe impl Send for EventAsync {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn next_val_timeout_expires() {
+        async fn go(event: Event, ex: &Executor) {
+            let event_async = EventAsync::new(event, ex).unwrap();
+            assert_eq!(
+                event_async
+                    .next_val_timeout(ex, Duration::from_millis(10))
+                    .await
+                    .unwrap(),
+                None
+            );
+        }
+
+        let ex = Executor::new().unwrap();
+        ex.run_until(go(Event::new().unwrap(), &ex)).unwrap();
+    }
+
+    #[test]
+    fn next_val_timeout_signaled() {
+        async fn go(event: Event, ex: &Executor) -> Option<u64> {
+            let event_async = EventAsync::new(event, ex).unwrap();
+            event_async
+                .next_val_timeout(ex, Duration::from_secs(10))
+                .await
+                .unwrap()
+        }
+
+        let event = Event::new().unwrap();
+        event.write(1).unwrap();
+        let ex = Executor::new().unwrap();
+        // The value is always 0 on Windows.
+        assert!(ex.run_until(go(event, &ex)).unwrap().is_some());
+    }
+
+    #[test]
+    fn next_val_timeout_keeps_late_signal() {
+        async fn go(event: Event, ex: &Executor) {
+            let writer = event.try_clone().unwrap();
+            let event_async = EventAsync::new(event, ex).unwrap();
+            assert_eq!(
+                event_async
+                    .next_val_timeout(ex, Duration::from_millis(10))
+                    .await
+                    .unwrap(),
+                None
+            );
+            writer.write(1).unwrap();
+            event_async.next_val().await.unwrap();
+        }
+
+        let ex = Executor::new().unwrap();
+        ex.run_until(go(Event::new().unwrap(), &ex)).unwrap();
+    }
+}
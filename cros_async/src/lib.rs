@@ -27,6 +27,9 @@
 //! to poll only when necessary. See the docs for [`select2`](fn.select2.html),
 //! [`select3`](fn.select3.html), [`select4`](fn.select4.html), and [`select5`](fn.select5.html).
 //!
+//! To wait for whichever of several events is signaled first, like a device worker waiting for its
+//! queue and kill events, use [`select_events`](fn.select_events.html).
+//!
 //! ## Completing all of several futures.
 //!
 //! If there are several top level tasks that all need to be completed, use the "complete" family
@@ -66,6 +69,7 @@ mod event;
 mod io_ext;
 pub mod mem;
 mod queue;
+mod rate_limiter;
 mod select;
 pub mod sync;
 pub mod sys;
@@ -102,9 +106,12 @@ pub use io_ext::Result as AsyncResult;
 pub use io_ext::WriteAsync;
 pub use mem::BackingMemory;
 pub use mem::MemRegion;
+pub use rate_limiter::acquire_tokens;
 use remain::sorted;
 pub use select::SelectResult;
 pub use sys::run_one;
+#[cfg(unix)]
+pub use sys::unix::event::select_events;
 use thiserror::Error as ThisError;
 pub use timer::TimerAsync;
 
@@ -122,6 +129,9 @@ pub enum Error {
     #[cfg(unix)]
     #[error("An error with a poll source: {0}")]
     PollSource(sys::unix::poll_source::Error),
+    /// More tokens were requested than a rate limiter holds.
+    #[error("requested {0} tokens from a rate limiter holding at most {1}")]
+    RateLimiterCapacity(u64, u64),
     /// Error from Timer.
     #[error("Failure in Timer: {0}")]
     Timer(base::Error),
@@ -12,6 +12,9 @@ pub enum Error {
     /// Invalid offset or length given for an iovec in backing memory.
     #[error("Invalid offset/len for getting a slice from {0} with len {1}.")]
     InvalidOffset(u64, usize),
+    /// The backing memory at the offset is protected from access by this process.
+    #[error("Backing memory at {0} with len {1} is protected from access.")]
+    ProtectedRegionAccess(u64, usize),
 }
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -0,0 +1,56 @@
+// Copyright 2022 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+use base::RateLimiter;
+use base::TimeSource;
+
+use crate::Error;
+use crate::Executor;
+use crate::Result;
+use crate::TimerAsync;
+
+/// Waits until `n` tokens are available from `limiter` and takes them.
+///
+/// Fails if `limiter` can never hold `n` tokens.
+pub async fn acquire_tokens<C: TimeSource>(
+    ex: &Executor,
+    limiter: &mut RateLimiter<C>,
+    n: u64,
+) -> Result<()> {
+    loop {
+        match limiter.time_until_available(n) {
+            None => return Err(Error::RateLimiterCapacity(n, limiter.capacity())),
+            Some(delay) if delay.is_zero() => {
+                if limiter.try_acquire(n) {
+                    return Ok(());
+                }
+            }
+            Some(delay) => TimerAsync::sleep(ex, delay).await?,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use std::time::Instant;
+
+    use super::*;
+
+    #[test]
+    fn paces_acquisitions() {
+        async fn this_test(ex: &Executor) {
+            let mut limiter = RateLimiter::new(2, 1, Duration::from_millis(50));
+            let now = Instant::now();
+            acquire_tokens(ex, &mut limiter, 2).await.unwrap();
+            acquire_tokens(ex, &mut limiter, 1).await.unwrap();
+            acquire_tokens(ex, &mut limiter, 1).await.unwrap();
+            assert!(now.elapsed() >= Duration::from_millis(100));
+            assert!(acquire_tokens(ex, &mut limiter, 3).await.is_err());
+        }
+
+        let ex = Executor::new().expect("creating an executor failed");
+        ex.run_until(this_test(&ex)).unwrap();
+    }
+}
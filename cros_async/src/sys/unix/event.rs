@@ -3,39 +3,126 @@
 // found in the LICENSE file.
 
 use base::Event;
+use futures::future::select_all;
 
 #[cfg(test)]
 use super::FdExecutor;
 #[cfg(test)]
 use super::URingExecutor;
+use crate::AsyncError;
 use crate::AsyncResult;
 use crate::EventAsync;
 use crate::Executor;
 
 impl EventAsync {
     pub fn new(event: Event, ex: &Executor) -> AsyncResult<EventAsync> {
-        ex.async_from(event)
-            .map(|io_source| EventAsync { io_source })
+        Self::new_auto_reset(event, ex)
+    }
+
+    /// Creates an `EventAsync` whose `next_val` resets the eventfd, so it stays unsignaled until
+    /// it is written again.
+    pub fn new_auto_reset(event: Event, ex: &Executor) -> AsyncResult<EventAsync> {
+        ex.async_from(event).map(|io_source| EventAsync {
+            io_source,
+            reset_after_read: true,
+        })
+    }
+
+    /// Creates an `EventAsync` whose `next_val` leaves the eventfd signaled. It stays signaled
+    /// until it is reset with `wait_and_reset` or `next_val_nonblocking`.
+    pub fn new_manual_reset(event: Event, ex: &Executor) -> AsyncResult<EventAsync> {
+        ex.async_from(event).map(|io_source| EventAsync {
+            io_source,
+            reset_after_read: false,
+        })
     }
 
     /// Gets the next value from the eventfd.
     pub async fn next_val(&self) -> AsyncResult<u64> {
-        self.io_source.read_u64().await
+        let count = self.io_source.read_u64().await?;
+        if !self.reset_after_read {
+            // Reading an eventfd always resets it, so signal it again. Writes that raced with the
+            // read are added to the count instead of being lost.
+            self.io_source
+                .as_source()
+                .write(count)
+                .map_err(AsyncError::EventAsync)?;
+        }
+        Ok(count)
+    }
+
+    // Like `next_val`, but a signal isn't consumed if the future is dropped before it completes.
+    pub(crate) async fn next_val_cancel_safe(&self) -> AsyncResult<u64> {
+        // A read submitted to io_uring still completes when its future is dropped, so only read
+        // once the eventfd is readable.
+        self.io_source.wait_readable().await?;
+        self.next_val().await
+    }
+
+    /// Waits until the eventfd is signaled, then resets it.
+    pub async fn wait_and_reset(&self) -> AsyncResult<()> {
+        self.io_source.read_u64().await.map(|_| ())
+    }
+
+    // Gets the value of an eventfd that was seen readable like `next_val` would, or `None` if it
+    // was reset since.
+    fn take_val(&self) -> AsyncResult<Option<u64>> {
+        let count = self.next_val_nonblocking()?;
+        if let Some(count) = count {
+            if !self.reset_after_read {
+                self.io_source
+                    .as_source()
+                    .write(count)
+                    .map_err(AsyncError::EventAsync)?;
+            }
+        }
+        Ok(count)
     }
 
     #[cfg(test)]
     pub(crate) fn new_poll(event: Event, ex: &FdExecutor) -> AsyncResult<EventAsync> {
-        super::executor::async_poll_from(event, ex).map(|io_source| EventAsync { io_source })
+        super::executor::async_poll_from(event, ex).map(|io_source| EventAsync {
+            io_source,
+            reset_after_read: true,
+        })
     }
 
     #[cfg(test)]
     pub(crate) fn new_uring(event: Event, ex: &URingExecutor) -> AsyncResult<EventAsync> {
-        super::executor::async_uring_from(event, ex).map(|io_source| EventAsync { io_source })
+        super::executor::async_uring_from(event, ex).map(|io_source| EventAsync {
+            io_source,
+            reset_after_read: true,
+        })
+    }
+}
+
+/// Waits until any of `events` is signaled, then gets its value like `next_val` would. Returns the
+/// index of the event in `events` and its value.
+///
+/// The events are waited on together: with io_uring, the polls of all the eventfds are submitted
+/// at once. Only the returned event is read, so the signals of the others are left for later,
+/// including when the returned future is dropped before it completes.
+///
+/// # Panics
+///
+/// Panics if `events` is empty.
+pub async fn select_events(events: &[&EventAsync]) -> AsyncResult<(usize, u64)> {
+    loop {
+        let (res, index, _) =
+            select_all(events.iter().map(|event| event.io_source.wait_readable())).await;
+        res?;
+        // Another reader may have reset the event since it was seen readable.
+        if let Some(val) = events[index].take_val()? {
+            return Ok((index, val));
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::future::Future;
+    use std::thread;
+
     use super::*;
     use crate::sys::unix::uring_executor::is_uring_stable;
 
@@ -79,4 +166,166 @@ mod tests {
             .unwrap();
         assert_eq!(val, 0xaa);
     }
+
+    // Writes 1, 2 and 3 to `event` from another thread.
+    fn signal_from_thread(event: &Event) -> thread::JoinHandle<()> {
+        let event = event.try_clone().unwrap();
+        thread::spawn(move || {
+            for v in 1..=3 {
+                event.write(v).unwrap();
+            }
+        })
+    }
+
+    async fn wait_and_reset_from_thread(event_async: EventAsync, event: Event) {
+        let signaler = signal_from_thread(&event);
+        event_async.wait_and_reset().await.unwrap();
+        signaler.join().unwrap();
+        // The writes that came after the reset are still counted.
+        let rest = event_async.next_val_nonblocking().unwrap().unwrap_or(0);
+        assert!(rest < 6);
+        assert_eq!(event_async.next_val_nonblocking().unwrap(), None);
+    }
+
+    async fn manual_reset_from_thread(mut event_async: EventAsync, event: Event) {
+        event_async.reset_after_read = false;
+        let signaler = signal_from_thread(&event);
+        let first = event_async.next_val().await.unwrap();
+        assert!((1..=6).contains(&first));
+        signaler.join().unwrap();
+        // No write was lost, and the event stayed signaled until it was reset.
+        assert_eq!(event_async.next_val().await.unwrap(), 6);
+        assert_eq!(event_async.next_val_nonblocking().unwrap(), Some(6));
+        assert_eq!(event_async.next_val_nonblocking().unwrap(), None);
+    }
+
+    async fn auto_reset_from_thread(event_async: EventAsync, event: Event) {
+        let signaler = signal_from_thread(&event);
+        let first = event_async.next_val().await.unwrap();
+        signaler.join().unwrap();
+        let rest = event_async.next_val_nonblocking().unwrap().unwrap_or(0);
+        assert_eq!(first + rest, 6);
+    }
+
+    fn run_poll<F, Fut>(test: F)
+    where
+        F: FnOnce(EventAsync, Event) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        let ex = FdExecutor::new().unwrap();
+        let event = Event::new().unwrap();
+        let event_async = EventAsync::new_poll(event.try_clone().unwrap(), &ex).unwrap();
+        ex.run_until(test(event_async, event)).unwrap();
+    }
+
+    fn run_uring<F, Fut>(test: F)
+    where
+        F: FnOnce(EventAsync, Event) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        let ex = URingExecutor::new().unwrap();
+        let event = Event::new().unwrap();
+        let event_async = EventAsync::new_uring(event.try_clone().unwrap(), &ex).unwrap();
+        ex.run_until(test(event_async, event)).unwrap();
+    }
+
+    #[test]
+    fn signal_from_thread_poll() {
+        run_poll(wait_and_reset_from_thread);
+        run_poll(manual_reset_from_thread);
+        run_poll(auto_reset_from_thread);
+    }
+
+    #[test]
+    fn signal_from_thread_uring() {
+        if !is_uring_stable() {
+            return;
+        }
+
+        run_uring(wait_and_reset_from_thread);
+        run_uring(manual_reset_from_thread);
+        run_uring(auto_reset_from_thread);
+    }
+
+    async fn select_events_both_signaled(first: EventAsync, second: EventAsync) {
+        first.io_source.as_source().write(1).unwrap();
+        second.io_source.as_source().write(2).unwrap();
+
+        // Whichever event is selected first, the signal of the other one isn't consumed.
+        let (index, val) = select_events(&[&first, &second]).await.unwrap();
+        let (other, other_val) = select_events(&[&first, &second]).await.unwrap();
+        assert_ne!(index, other);
+        assert_eq!(val + other_val, 3);
+        assert_eq!(val, index as u64 + 1);
+        assert_eq!(first.next_val_nonblocking().unwrap(), None);
+        assert_eq!(second.next_val_nonblocking().unwrap(), None);
+    }
+
+    async fn select_events_from_thread(first: EventAsync, second: EventAsync) {
+        let first_writer = first.io_source.as_source().try_clone().unwrap();
+        let second_writer = second.io_source.as_source().try_clone().unwrap();
+        let signaler = thread::spawn(move || {
+            first_writer.write(1).unwrap();
+            second_writer.write(2).unwrap();
+        });
+
+        let mut vals = [0; 2];
+        while vals.contains(&0) {
+            let (index, val) = select_events(&[&first, &second]).await.unwrap();
+            vals[index] += val;
+        }
+        signaler.join().unwrap();
+        assert_eq!(vals, [1, 2]);
+    }
+
+    async fn select_events_manual_reset(first: EventAsync, mut second: EventAsync) {
+        second.reset_after_read = false;
+        second.io_source.as_source().write(2).unwrap();
+
+        // The selected event stays signaled, like with `next_val`.
+        assert_eq!(select_events(&[&first, &second]).await.unwrap(), (1, 2));
+        assert_eq!(select_events(&[&first, &second]).await.unwrap(), (1, 2));
+        assert_eq!(second.next_val_nonblocking().unwrap(), Some(2));
+        assert_eq!(first.next_val_nonblocking().unwrap(), None);
+    }
+
+    fn run_select_poll<F, Fut>(test: F)
+    where
+        F: FnOnce(EventAsync, EventAsync) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        let ex = FdExecutor::new().unwrap();
+        let first = EventAsync::new_poll(Event::new().unwrap(), &ex).unwrap();
+        let second = EventAsync::new_poll(Event::new().unwrap(), &ex).unwrap();
+        ex.run_until(test(first, second)).unwrap();
+    }
+
+    fn run_select_uring<F, Fut>(test: F)
+    where
+        F: FnOnce(EventAsync, EventAsync) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        let ex = URingExecutor::new().unwrap();
+        let first = EventAsync::new_uring(Event::new().unwrap(), &ex).unwrap();
+        let second = EventAsync::new_uring(Event::new().unwrap(), &ex).unwrap();
+        ex.run_until(test(first, second)).unwrap();
+    }
+
+    #[test]
+    fn select_events_poll() {
+        run_select_poll(select_events_both_signaled);
+        run_select_poll(select_events_from_thread);
+        run_select_poll(select_events_manual_reset);
+    }
+
+    #[test]
+    fn select_events_uring() {
+        if !is_uring_stable() {
+            return;
+        }
+
+        run_select_uring(select_events_both_signaled);
+        run_select_uring(select_events_from_thread);
+        run_select_uring(select_events_manual_reset);
+    }
 }
@@ -12,21 +12,33 @@ use crate::Executor;
 
 impl EventAsync {
     pub fn new(event: Event, ex: &Executor) -> AsyncResult<EventAsync> {
+        Self::new_auto_reset(event, ex)
+    }
+
+    /// Creates an `EventAsync` whose `next_val` resets the event, so it stays unsignaled until
+    /// it is set again, whether `event` is an auto-reset or a manual-reset event.
+    pub fn new_auto_reset(event: Event, ex: &Executor) -> AsyncResult<EventAsync> {
         ex.async_from(event).map(|io_source| EventAsync {
             io_source,
             reset_after_read: true,
         })
     }
 
-    /// For Windows events, especially those used in overlapped IO, we don't want to reset them
-    /// after "reading" from them because the signaling state is entirely managed by the kernel.
-    pub fn new_without_reset(event: Event, ex: &Executor) -> AsyncResult<EventAsync> {
+    /// Creates an `EventAsync` whose `next_val` leaves the event signaled. It stays signaled
+    /// until it is reset with `wait_and_reset` or `next_val_nonblocking`, or by the kernel.
+    pub fn new_manual_reset(event: Event, ex: &Executor) -> AsyncResult<EventAsync> {
         ex.async_from(event).map(|io_source| EventAsync {
             io_source,
             reset_after_read: false,
         })
     }
 
+    /// For Windows events, especially those used in overlapped IO, we don't want to reset them
+    /// after "reading" from them because the signaling state is entirely managed by the kernel.
+    pub fn new_without_reset(event: Event, ex: &Executor) -> AsyncResult<EventAsync> {
+        Self::new_manual_reset(event, ex)
+    }
+
     /// Gets the next value from the eventfd.
     pub async fn next_val(&self) -> AsyncResult<u64> {
         let res = self.io_source.wait_for_handle().await;
@@ -39,4 +51,19 @@ impl EventAsync {
         }
         res
     }
+
+    // Like `next_val`, but a signal isn't consumed if the future is dropped before it completes.
+    pub(crate) async fn next_val_cancel_safe(&self) -> AsyncResult<u64> {
+        // The wait is unregistered when its future is dropped.
+        self.next_val().await
+    }
+
+    /// Waits until the event is signaled, then resets it.
+    pub async fn wait_and_reset(&self) -> AsyncResult<()> {
+        self.io_source.wait_for_handle().await?;
+        self.io_source
+            .as_source()
+            .reset()
+            .map_err(AsyncError::EventAsync)
+    }
 }
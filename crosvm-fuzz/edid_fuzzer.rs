@@ -0,0 +1,30 @@
+// Copyright 2022 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+#![no_main]
+
+use std::convert::TryInto;
+
+use cros_fuzz::fuzz_target;
+use devices::virtio::gpu::display_params_edid;
+use vm_control::gpu::DisplayMode;
+use vm_control::gpu::DisplayParameters;
+
+fn read_u32(data: &[u8], index: usize) -> u32 {
+    data.get(index * 4..index * 4 + 4)
+        .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+        .unwrap_or(0)
+}
+
+fuzz_target!(|data| {
+    let params = DisplayParameters::new(
+        DisplayMode::Windowed(read_u32(data, 0), read_u32(data, 1)),
+        false,
+        read_u32(data, 2),
+    );
+    if let Ok(edid) = display_params_edid(&params) {
+        assert_eq!(edid.len(), 128);
+        assert_eq!(edid.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)), 0);
+    }
+});
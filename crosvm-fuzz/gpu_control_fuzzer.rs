@@ -0,0 +1,29 @@
+// Copyright 2022 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+#![no_main]
+
+use cros_fuzz::fuzz_target;
+use devices::virtio::gpu::display_params_edid;
+use vm_control::gpu::GpuControlCommand;
+
+fuzz_target!(|data| {
+    // Gpu control commands arrive from the control socket as JSON.
+    let cmd: GpuControlCommand = match serde_json::from_slice(data) {
+        Ok(cmd) => cmd,
+        Err(_) => return,
+    };
+    // Displays are checked this way before the gpu device attaches them.
+    match cmd {
+        GpuControlCommand::AddDisplays { displays } => {
+            for params in &displays {
+                let _ = display_params_edid(params);
+            }
+        }
+        GpuControlCommand::SetDisplayMode { parameters, .. } => {
+            let _ = display_params_edid(&parameters);
+        }
+        _ => {}
+    }
+});
@@ -16,9 +16,12 @@
 use std::convert::TryFrom;
 use std::convert::TryInto;
 use std::ffi::CStr;
+#[cfg(windows)]
+use std::marker::PhantomData;
 use std::panic::catch_unwind;
 use std::path::Path;
 use std::path::PathBuf;
+use std::ptr;
 
 use libc::c_char;
 use libc::ssize_t;
@@ -26,6 +29,9 @@ use vm_control::client::*;
 use vm_control::BalloonControlCommand;
 use vm_control::BalloonStats;
 use vm_control::DiskControlCommand;
+use vm_control::DisplayMode;
+use vm_control::DisplayParameters;
+use vm_control::GpuControlResult;
 use vm_control::UsbControlAttachedDevice;
 use vm_control::UsbControlResult;
 use vm_control::VmRequest;
@@ -63,7 +69,7 @@ pub extern "C" fn crosvm_client_stop_vm(socket_path: *const c_char) -> bool {
 pub extern "C" fn crosvm_client_suspend_vm(socket_path: *const c_char) -> bool {
     catch_unwind(|| {
         if let Some(socket_path) = validate_socket_path(socket_path) {
-            vms_request(&VmRequest::Suspend, &socket_path).is_ok()
+            vms_run_state_request(&VmRequest::Suspend, &socket_path).is_ok()
         } else {
             false
         }
@@ -78,7 +84,7 @@ pub extern "C" fn crosvm_client_suspend_vm(socket_path: *const c_char) -> bool {
 pub extern "C" fn crosvm_client_resume_vm(socket_path: *const c_char) -> bool {
     catch_unwind(|| {
         if let Some(socket_path) = validate_socket_path(socket_path) {
-            vms_request(&VmRequest::Resume, &socket_path).is_ok()
+            vms_run_state_request(&VmRequest::Resume, &socket_path).is_ok()
         } else {
             false
         }
@@ -250,7 +256,7 @@ pub extern "C" fn crosvm_client_usb_detach(socket_path: *const c_char, port: u8)
 }
 
 /// Modifies the battery status of crosvm instance whose control socket is listening on
-/// `socket_path`.
+/// `socket_path`. Only the first battery of `battery_type` is addressed.
 ///
 /// The function returns true on success or false if an error occured.
 #[no_mangle]
@@ -272,6 +278,7 @@ pub extern "C" fn crosvm_client_modify_battery(
             do_modify_battery(
                 &socket_path,
                 battery_type.to_str().unwrap(),
+                0,
                 property.to_str().unwrap(),
                 target.to_str().unwrap(),
             )
@@ -393,3 +400,292 @@ pub extern "C" fn crosvm_client_balloon_stats(
     })
     .unwrap_or(false)
 }
+
+/// A display in a window of the given size.
+pub const CROSVM_DISPLAY_MODE_WINDOWED: u32 = 0;
+/// A borderless display covering the host screen, whose size is decided by crosvm. Only supported
+/// on Windows.
+pub const CROSVM_DISPLAY_MODE_BORDERLESS_FULL_SCREEN: u32 = 1;
+
+/// Similar to `DisplayParameters`, with the display mode encoded as one of the
+/// `CROSVM_DISPLAY_MODE_*` constants followed by its size.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DisplayParametersFfi {
+    /// One of the `CROSVM_DISPLAY_MODE_*` constants.
+    mode: u32,
+    /// Width of a windowed display, 0 for other modes.
+    width: u32,
+    /// Height of a windowed display, 0 for other modes.
+    height: u32,
+    /// Refresh rate in Hz.
+    refresh_rate: u32,
+    /// Whether the display is hidden.
+    hidden: bool,
+}
+
+impl From<&DisplayParameters> for DisplayParametersFfi {
+    fn from(other: &DisplayParameters) -> Self {
+        let (mode, width, height) = match other.mode {
+            DisplayMode::Windowed(width, height) => (CROSVM_DISPLAY_MODE_WINDOWED, width, height),
+            #[cfg(windows)]
+            DisplayMode::BorderlessFullScreen(_) => {
+                (CROSVM_DISPLAY_MODE_BORDERLESS_FULL_SCREEN, 0, 0)
+            }
+        };
+        Self {
+            mode,
+            width,
+            height,
+            refresh_rate: other.refresh_rate,
+            hidden: other.hidden,
+        }
+    }
+}
+
+impl TryFrom<&DisplayParametersFfi> for DisplayParameters {
+    type Error = ();
+
+    fn try_from(other: &DisplayParametersFfi) -> Result<Self, Self::Error> {
+        let mode = match other.mode {
+            CROSVM_DISPLAY_MODE_WINDOWED => DisplayMode::Windowed(other.width, other.height),
+            #[cfg(windows)]
+            CROSVM_DISPLAY_MODE_BORDERLESS_FULL_SCREEN => {
+                DisplayMode::BorderlessFullScreen(PhantomData)
+            }
+            _ => return Err(()),
+        };
+        Ok(DisplayParameters::new(
+            mode,
+            other.hidden,
+            other.refresh_rate,
+        ))
+    }
+}
+
+/// Represents a display of the GPU device.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DisplayEntry {
+    /// ID used to remove the display.
+    display_id: u32,
+    params: DisplayParametersFfi,
+}
+
+/// Adds a display to the GPU device of the crosvm instance whose control socket is listening on
+/// `socket_path`.
+///
+/// The function returns true on success or false if an error occured.
+#[no_mangle]
+pub extern "C" fn crosvm_client_gpu_add_display(
+    socket_path: *const c_char,
+    params: *const DisplayParametersFfi,
+) -> bool {
+    catch_unwind(|| {
+        if let Some(socket_path) = validate_socket_path(socket_path) {
+            if params.is_null() {
+                return false;
+            }
+            let params = match DisplayParameters::try_from(unsafe { &*params }) {
+                Ok(params) => params,
+                Err(()) => return false,
+            };
+            matches!(
+                do_gpu_display_add(&socket_path, vec![params]),
+                Ok(GpuControlResult::DisplaysUpdated)
+            )
+        } else {
+            false
+        }
+    })
+    .unwrap_or(false)
+}
+
+/// Removes the display `display_id` from the GPU device of the crosvm instance whose control
+/// socket is listening on `socket_path`.
+///
+/// The function returns true on success or false if an error occured.
+#[no_mangle]
+pub extern "C" fn crosvm_client_gpu_remove_display(
+    socket_path: *const c_char,
+    display_id: u32,
+) -> bool {
+    catch_unwind(|| {
+        if let Some(socket_path) = validate_socket_path(socket_path) {
+            matches!(
+                do_gpu_display_remove(&socket_path, vec![display_id]),
+                Ok(GpuControlResult::DisplaysUpdated)
+            )
+        } else {
+            false
+        }
+    })
+    .unwrap_or(false)
+}
+
+// Moves `entries` to a buffer owned by the caller, who must release it with
+// `crosvm_client_gpu_free_display_list`. An empty list is returned as a null pointer.
+fn display_list_into_raw(entries: Vec<DisplayEntry>) -> (*mut DisplayEntry, usize) {
+    if entries.is_empty() {
+        return (ptr::null_mut(), 0);
+    }
+    let entries = entries.into_boxed_slice();
+    let len = entries.len();
+    (Box::into_raw(entries) as *mut DisplayEntry, len)
+}
+
+/// Lists the displays of the GPU device of the crosvm instance whose control socket is listening
+/// on `socket_path`.
+///
+/// On success, `*entries` is set to a buffer of `*entries_length` entries, which is owned by the
+/// caller and must be released with [`crosvm_client_gpu_free_display_list()`], and must not be
+/// freed in any other way. If there are no displays, `*entries` is set to null. On failure,
+/// `entries` and `entries_length` are left untouched.
+///
+/// The function returns true on success or false if an error occured.
+#[no_mangle]
+pub extern "C" fn crosvm_client_gpu_list_displays(
+    socket_path: *const c_char,
+    entries: *mut *mut DisplayEntry,
+    entries_length: *mut usize,
+) -> bool {
+    catch_unwind(|| {
+        if let Some(socket_path) = validate_socket_path(socket_path) {
+            if entries.is_null() || entries_length.is_null() {
+                return false;
+            }
+            if let Ok(GpuControlResult::DisplayList { displays, .. }) =
+                do_gpu_display_list(&socket_path)
+            {
+                let list = displays
+                    .iter()
+                    .map(|(&display_id, params)| DisplayEntry {
+                        display_id,
+                        params: params.into(),
+                    })
+                    .collect();
+                let (list, len) = display_list_into_raw(list);
+                unsafe {
+                    *entries = list;
+                    *entries_length = len;
+                }
+                true
+            } else {
+                false
+            }
+        } else {
+            false
+        }
+    })
+    .unwrap_or(false)
+}
+
+/// Releases a buffer returned by [`crosvm_client_gpu_list_displays()`]. `entries` may be null, in
+/// which case nothing is done.
+///
+/// # Safety
+///
+/// `entries` and `entries_length` must have been returned together by
+/// [`crosvm_client_gpu_list_displays()`], and `entries` must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn crosvm_client_gpu_free_display_list(
+    entries: *mut DisplayEntry,
+    entries_length: usize,
+) {
+    if entries.is_null() {
+        return;
+    }
+    // `entries` was created by `display_list_into_raw` from a boxed slice of `entries_length`
+    // elements, which the caller gives back to us.
+    drop(Box::from_raw(ptr::slice_from_raw_parts_mut(
+        entries,
+        entries_length,
+    )));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CString;
+
+    use super::*;
+
+    #[test]
+    fn display_parameters_round_trip() {
+        let params = DisplayParameters::new(DisplayMode::Windowed(1920, 1080), true, 120);
+        let ffi = DisplayParametersFfi::from(&params);
+        assert_eq!(
+            ffi,
+            DisplayParametersFfi {
+                mode: CROSVM_DISPLAY_MODE_WINDOWED,
+                width: 1920,
+                height: 1080,
+                refresh_rate: 120,
+                hidden: true,
+            }
+        );
+        assert_eq!(DisplayParameters::try_from(&ffi), Ok(params));
+    }
+
+    #[test]
+    fn display_parameters_invalid_mode() {
+        let ffi = DisplayParametersFfi {
+            mode: 100,
+            width: 640,
+            height: 480,
+            refresh_rate: 60,
+            hidden: false,
+        };
+        assert_eq!(DisplayParameters::try_from(&ffi), Err(()));
+    }
+
+    #[test]
+    fn display_list_ownership() {
+        let entries: Vec<_> = (0..3)
+            .map(|display_id| DisplayEntry {
+                display_id,
+                params: (&DisplayParameters::default()).into(),
+            })
+            .collect();
+        let (list, len) = display_list_into_raw(entries.clone());
+        assert_eq!(len, 3);
+        // Safe because `list` holds `len` initialized entries.
+        assert_eq!(
+            unsafe { std::slice::from_raw_parts(list, len) },
+            &entries[..]
+        );
+        // Safe because `list` and `len` were returned by `display_list_into_raw`.
+        unsafe { crosvm_client_gpu_free_display_list(list, len) };
+
+        let (list, len) = display_list_into_raw(Vec::new());
+        assert!(list.is_null());
+        assert_eq!(len, 0);
+        // Safe because a null list is ignored.
+        unsafe { crosvm_client_gpu_free_display_list(list, len) };
+    }
+
+    #[test]
+    fn gpu_functions_reject_null_arguments() {
+        let params = DisplayParametersFfi::from(&DisplayParameters::default());
+        assert!(!crosvm_client_gpu_add_display(ptr::null(), &params));
+        assert!(!crosvm_client_gpu_remove_display(ptr::null(), 0));
+
+        let socket_path = CString::new("/nonexistent/crosvm.sock").unwrap();
+        assert!(!crosvm_client_gpu_add_display(
+            socket_path.as_ptr(),
+            ptr::null()
+        ));
+        let mut entries = ptr::null_mut();
+        let mut entries_length = 0;
+        assert!(!crosvm_client_gpu_list_displays(
+            ptr::null(),
+            &mut entries,
+            &mut entries_length
+        ));
+        assert!(!crosvm_client_gpu_list_displays(
+            socket_path.as_ptr(),
+            ptr::null_mut(),
+            &mut entries_length
+        ));
+        assert!(entries.is_null());
+    }
+}
@@ -21,7 +21,7 @@ use base::Error as SysError;
 use base::Event;
 use base::EventToken;
 use base::SendTube;
-use base::VmEventType;
+use base::VmExitReason;
 use base::WaitContext;
 use serde::Deserialize;
 use serde::Serialize;
@@ -859,7 +859,10 @@ impl BusDevice for ACPIPMResource {
                 if (val & BITMASK_PM1CNT_SLEEP_ENABLE) != 0 {
                     // only support S5 in direct mode
                     #[cfg(feature = "direct")]
-                    if let Err(e) = self.exit_evt_wrtube.send::<VmEventType>(&VmEventType::Exit) {
+                    if let Err(e) = self
+                        .exit_evt_wrtube
+                        .send::<VmExitReason>(&VmExitReason::GuestShutdown)
+                    {
                         error!("ACPIPM: failed to trigger exit event: {}", e);
                     }
                     #[cfg(not(feature = "direct"))]
@@ -870,8 +873,9 @@ impl BusDevice for ACPIPMResource {
                             }
                         }
                         SLEEP_TYPE_S5 => {
-                            if let Err(e) =
-                                self.exit_evt_wrtube.send::<VmEventType>(&VmEventType::Exit)
+                            if let Err(e) = self
+                                .exit_evt_wrtube
+                                .send::<VmExitReason>(&VmExitReason::GuestShutdown)
                             {
                                 error!("ACPIPM: failed to trigger exit event: {}", e);
                             }
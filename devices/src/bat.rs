@@ -57,6 +57,9 @@ pub(crate) struct GoldfishBatteryState {
     current: u32,
     charge_counter: u32,
     charge_full: u32,
+    // Temperature in tenths of a degree Celsius, stored as the raw register value.
+    temperature: u32,
+    cycle_count: u32,
 }
 
 macro_rules! create_battery_func {
@@ -93,6 +96,10 @@ impl GoldfishBatteryState {
 
     create_battery_func!(set_capacity, capacity, BATTERY_STATUS_CHANGED);
 
+    create_battery_func!(set_temperature, temperature, BATTERY_STATUS_CHANGED);
+
+    create_battery_func!(set_cycle_count, cycle_count, BATTERY_STATUS_CHANGED);
+
     #[cfg(unix)]
     create_battery_func!(set_voltage, voltage, BATTERY_STATUS_CHANGED);
 
@@ -110,7 +117,7 @@ impl GoldfishBatteryState {
 pub struct GoldfishBattery {
     state: Arc<Mutex<GoldfishBatteryState>>,
     mmio_base: u32,
-    irq_num: u32,
+    irq_num: Option<u32>,
     irq_evt: IrqLevelEvent,
     activated: bool,
     monitor_thread: Option<thread::JoinHandle<()>>,
@@ -217,6 +224,12 @@ fn command_monitor(
                             let v = if ac_online != 0 { 1 } else { 0 };
                             bat_state.set_ac_online(v)
                         }
+                        BatControlCommand::SetTemperature(temperature) => {
+                            bat_state.set_temperature(temperature as u32)
+                        }
+                        BatControlCommand::SetCycleCount(cycle_count) => {
+                            bat_state.set_cycle_count(cycle_count)
+                        }
                     };
 
                     if inject_irq {
@@ -255,13 +268,14 @@ impl GoldfishBattery {
     ///
     /// * `mmio_base` - The 32-bit mmio base address.
     /// * `irq_num` - The corresponding interrupt number of the irq_evt
-    ///               which will be put into the ACPI DSDT.
+    ///               which will be put into the ACPI DSDT, or `None` if the
+    ///               battery has no interrupt and the driver polls it.
     /// * `irq_evt` - The interrupt event used to notify driver about
     ///               the battery properties changing.
     /// * `socket` - Battery control socket
     pub fn new(
         mmio_base: u64,
-        irq_num: u32,
+        irq_num: Option<u32>,
         irq_evt: IrqLevelEvent,
         tube: Tube,
         #[cfg(unix)] create_power_monitor: Option<Box<dyn CreatePowerMonitorFn>>,
@@ -281,6 +295,8 @@ impl GoldfishBattery {
             current: 0,
             charge_counter: 0,
             charge_full: 0,
+            temperature: 0,
+            cycle_count: 0,
         }));
 
         Ok(GoldfishBattery {
@@ -408,14 +424,14 @@ impl BusDevice for GoldfishBattery {
             BATTERY_PRESENT => self.state.lock().present,
             BATTERY_CAPACITY => self.state.lock().capacity,
             BATTERY_VOLTAGE => self.state.lock().voltage,
-            BATTERY_TEMP => 0,
+            BATTERY_TEMP => self.state.lock().temperature,
             BATTERY_CHARGE_COUNTER => self.state.lock().charge_counter,
             BATTERY_VOLTAGE_MAX => 0,
             BATTERY_CURRENT_MAX => 0,
             BATTERY_CURRENT_NOW => self.state.lock().current,
             BATTERY_CURRENT_AVG => 0,
             BATTERY_CHARGE_FULL_UAH => self.state.lock().charge_full,
-            BATTERY_CYCLE_COUNT => 0,
+            BATTERY_CYCLE_COUNT => self.state.lock().cycle_count,
             _ => {
                 warn!("{}: unsupported read address {}", self.debug_label(), info);
                 return;
@@ -456,19 +472,119 @@ impl BusDevice for GoldfishBattery {
 
 impl Aml for GoldfishBattery {
     fn to_aml_bytes(&self, bytes: &mut Vec<u8>) {
+        let memory = aml::Memory32Fixed::new(true, self.mmio_base, GOLDFISHBAT_MMIO_LEN as u32);
+        let interrupt = self
+            .irq_num
+            .map(|irq_num| aml::Interrupt::new(true, false, false, true, irq_num));
+        let mut resources: Vec<&dyn Aml> = vec![&memory];
+        if let Some(interrupt) = &interrupt {
+            resources.push(interrupt);
+        }
         aml::Device::new(
             "GFBY".into(),
             vec![
                 &aml::Name::new("_HID".into(), &"GFSH0001"),
-                &aml::Name::new(
-                    "_CRS".into(),
-                    &aml::ResourceTemplate::new(vec![
-                        &aml::Memory32Fixed::new(true, self.mmio_base, GOLDFISHBAT_MMIO_LEN as u32),
-                        &aml::Interrupt::new(true, false, false, true, self.irq_num),
-                    ]),
-                ),
+                &aml::Name::new("_CRS".into(), &aml::ResourceTemplate::new(resources)),
             ],
         )
         .to_aml_bytes(bytes);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use vm_control::BatHealth;
+
+    use super::*;
+
+    fn read_reg(bat: &mut GoldfishBattery, offset: u32) -> u32 {
+        let mut data = [0u8; 4];
+        bat.read(
+            BusAccessInfo {
+                address: u64::from(bat.mmio_base + offset),
+                offset: offset.into(),
+                id: 0,
+            },
+            &mut data,
+        );
+        u32::from_ne_bytes(data)
+    }
+
+    fn write_reg(bat: &mut GoldfishBattery, offset: u32, val: u32) {
+        bat.write(
+            BusAccessInfo {
+                address: u64::from(bat.mmio_base + offset),
+                offset: offset.into(),
+                id: 0,
+            },
+            &val.to_ne_bytes(),
+        );
+    }
+
+    fn send_command(tube: &Tube, cmd: BatControlCommand) {
+        tube.send(&cmd).unwrap();
+        match tube.recv::<BatControlResult>().unwrap() {
+            BatControlResult::Ok => {}
+            r => panic!("unexpected battery control result: {}", r),
+        }
+    }
+
+    #[test]
+    fn battery_properties_read_back() {
+        let irq_evt = IrqLevelEvent::new().unwrap();
+        let (host_tube, device_tube) = Tube::pair().unwrap();
+        let mut bat = GoldfishBattery::new(
+            0x1000,
+            Some(5),
+            irq_evt.try_clone().unwrap(),
+            device_tube,
+            #[cfg(unix)]
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(read_reg(&mut bat, BATTERY_TEMP), 0);
+        assert_eq!(read_reg(&mut bat, BATTERY_CYCLE_COUNT), 0);
+        assert_eq!(
+            read_reg(&mut bat, BATTERY_HEALTH),
+            BATTERY_HEALTH_VAL_UNKNOWN
+        );
+
+        // Enabling interrupts starts the thread servicing the control tube.
+        write_reg(&mut bat, BATTERY_INT_ENABLE, BATTERY_INT_MASK);
+
+        send_command(&host_tube, BatControlCommand::SetTemperature(-52));
+        assert_eq!(read_reg(&mut bat, BATTERY_TEMP) as i32, -52);
+        irq_evt.get_trigger().read().unwrap();
+        assert_eq!(
+            read_reg(&mut bat, BATTERY_INT_STATUS),
+            BATTERY_STATUS_CHANGED
+        );
+
+        send_command(&host_tube, BatControlCommand::SetCycleCount(321));
+        assert_eq!(read_reg(&mut bat, BATTERY_CYCLE_COUNT), 321);
+        irq_evt.get_trigger().read().unwrap();
+        assert_eq!(
+            read_reg(&mut bat, BATTERY_INT_STATUS),
+            BATTERY_STATUS_CHANGED
+        );
+
+        send_command(
+            &host_tube,
+            BatControlCommand::SetHealth(BatHealth::Overheat),
+        );
+        assert_eq!(
+            read_reg(&mut bat, BATTERY_HEALTH),
+            u32::from(BatHealth::Overheat)
+        );
+        irq_evt.get_trigger().read().unwrap();
+        assert_eq!(
+            read_reg(&mut bat, BATTERY_INT_STATUS),
+            BATTERY_STATUS_CHANGED
+        );
+
+        // Setting an unchanged value must not raise another interrupt.
+        send_command(&host_tube, BatControlCommand::SetCycleCount(321));
+        assert_eq!(read_reg(&mut bat, BATTERY_INT_STATUS), 0);
+    }
+}
@@ -0,0 +1,188 @@
+// Copyright 2022 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Devices that record guest boot milestones.
+
+use std::sync::Arc;
+
+use base::warn;
+use sync::Mutex;
+use vm_control::BootMilestone;
+use vm_control::BootMilestones;
+
+use crate::pci::CrosvmDeviceId;
+use crate::BusAccessInfo;
+use crate::BusDevice;
+use crate::DeviceId;
+
+/// Size of the boot doorbell register block.
+pub const BOOT_DOORBELL_SIZE: u64 = 0x1000;
+
+// Writing any 32-bit value to this register signals that the guest finished booting. Reading it
+// returns 1 once boot completion was signaled and 0 before.
+const BOOT_DOORBELL_COMPLETE: u64 = 0x0;
+
+/// A doorbell the guest writes to once it considers itself booted, typically from the last step
+/// of its init sequence.
+pub struct BootDoorbell {
+    boot_milestones: BootMilestones,
+    complete: bool,
+}
+
+impl BootDoorbell {
+    pub fn new(boot_milestones: BootMilestones) -> BootDoorbell {
+        BootDoorbell {
+            boot_milestones,
+            complete: false,
+        }
+    }
+}
+
+impl BusDevice for BootDoorbell {
+    fn device_id(&self) -> DeviceId {
+        CrosvmDeviceId::BootDoorbell.into()
+    }
+
+    fn debug_label(&self) -> String {
+        "boot doorbell".to_owned()
+    }
+
+    fn read(&mut self, info: BusAccessInfo, data: &mut [u8]) {
+        if data.len() != std::mem::size_of::<u32>() || info.offset != BOOT_DOORBELL_COMPLETE {
+            warn!("{}: bad read {}", self.debug_label(), info);
+            return;
+        }
+        data.copy_from_slice(&u32::from(self.complete).to_le_bytes());
+    }
+
+    fn write(&mut self, info: BusAccessInfo, data: &[u8]) {
+        if data.len() != std::mem::size_of::<u32>() || info.offset != BOOT_DOORBELL_COMPLETE {
+            warn!("{}: bad write {}", self.debug_label(), info);
+            return;
+        }
+        self.boot_milestones
+            .record(BootMilestone::GuestBootComplete);
+        self.complete = true;
+    }
+}
+
+// 8250 UART registers observed to find the first byte written by the guest.
+const UART_DATA: u64 = 0;
+const UART_LCR: u64 = 3;
+const UART_LCR_DLAB: u8 = 0x80;
+
+/// Wraps a serial device on the bus to record the first byte of output written by the guest.
+///
+/// The wrapper sits in front of the device on the bus, so it works the same whether the serial
+/// device runs in this process or in a sandboxed device process.
+pub struct SerialOutputMilestone {
+    serial: Arc<Mutex<dyn BusDevice>>,
+    boot_milestones: BootMilestones,
+    // Writes to the data register program the divisor latch while DLAB is set.
+    dlab: bool,
+    recorded: bool,
+}
+
+impl SerialOutputMilestone {
+    pub fn new(
+        serial: Arc<Mutex<dyn BusDevice>>,
+        boot_milestones: BootMilestones,
+    ) -> SerialOutputMilestone {
+        SerialOutputMilestone {
+            serial,
+            boot_milestones,
+            dlab: false,
+            recorded: false,
+        }
+    }
+}
+
+impl BusDevice for SerialOutputMilestone {
+    fn device_id(&self) -> DeviceId {
+        self.serial.lock().device_id()
+    }
+
+    fn debug_label(&self) -> String {
+        self.serial.lock().debug_label()
+    }
+
+    fn read(&mut self, info: BusAccessInfo, data: &mut [u8]) {
+        self.serial.lock().read(info, data)
+    }
+
+    fn write(&mut self, info: BusAccessInfo, data: &[u8]) {
+        if let Some(&value) = data.first() {
+            match info.offset {
+                UART_LCR => self.dlab = value & UART_LCR_DLAB != 0,
+                UART_DATA if !self.dlab && !self.recorded => {
+                    self.boot_milestones
+                        .record(BootMilestone::FirstSerialOutput);
+                    self.recorded = true;
+                }
+                _ => {}
+            }
+        }
+        self.serial.lock().write(info, data)
+    }
+
+    fn destroy_device(&mut self) {
+        self.serial.lock().destroy_device()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct NullDevice;
+
+    impl BusDevice for NullDevice {
+        fn device_id(&self) -> DeviceId {
+            CrosvmDeviceId::Serial.into()
+        }
+
+        fn debug_label(&self) -> String {
+            "null".to_owned()
+        }
+    }
+
+    fn access(offset: u64) -> BusAccessInfo {
+        BusAccessInfo {
+            offset,
+            address: offset,
+            id: 0,
+        }
+    }
+
+    #[test]
+    fn serial_output_ignores_divisor_writes() {
+        let boot_milestones = BootMilestones::new();
+        let mut serial =
+            SerialOutputMilestone::new(Arc::new(Mutex::new(NullDevice)), boot_milestones.clone());
+
+        serial.write(access(UART_LCR), &[UART_LCR_DLAB | 0x3]);
+        serial.write(access(UART_DATA), &[0x1]);
+        assert_eq!(boot_milestones.times().first_serial_output, None);
+
+        serial.write(access(UART_LCR), &[0x3]);
+        serial.write(access(UART_DATA), &[b'x']);
+        assert!(boot_milestones.times().first_serial_output.is_some());
+    }
+
+    #[test]
+    fn doorbell_records_boot_complete() {
+        let boot_milestones = BootMilestones::new();
+        let mut doorbell = BootDoorbell::new(boot_milestones.clone());
+        let mut data = [0u8; 4];
+
+        doorbell.read(access(BOOT_DOORBELL_COMPLETE), &mut data);
+        assert_eq!(u32::from_le_bytes(data), 0);
+        assert_eq!(boot_milestones.times().guest_boot_complete, None);
+
+        doorbell.write(access(BOOT_DOORBELL_COMPLETE), &1u32.to_le_bytes());
+        doorbell.read(access(BOOT_DOORBELL_COMPLETE), &mut data);
+        assert_eq!(u32::from_le_bytes(data), 1);
+        assert!(boot_milestones.times().guest_boot_complete.is_some());
+    }
+}
@@ -9,15 +9,20 @@ use std::cmp::Ordering;
 use std::cmp::PartialEq;
 use std::cmp::PartialOrd;
 use std::collections::btree_map::BTreeMap;
+use std::collections::VecDeque;
 use std::fmt;
 use std::result;
 use std::sync::Arc;
+use std::time::Duration;
 
+use base::warn;
+use base::LogRateLimiter;
 use remain::sorted;
 use serde::Deserialize;
 use serde::Serialize;
 use sync::Mutex;
 use thiserror::Error;
+use vm_control::UnmappedAccess;
 
 #[cfg(feature = "stats")]
 use crate::bus_stats::BusOperation;
@@ -75,6 +80,68 @@ pub enum BusType {
     Io,
 }
 
+/// How a bus handles guest accesses to addresses without a device.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum UnmappedAccessPolicy {
+    /// Reads leave the data untouched and writes are dropped.
+    Ignore,
+    /// Like `Ignore`, but the accesses are logged, with rate limiting, and the most recent ones are
+    /// kept for `Bus::unmapped_accesses`.
+    Log,
+    /// Like `Log`, and the access faults in the guest where the vcpu loop supports it.
+    Fault,
+}
+
+impl Default for UnmappedAccessPolicy {
+    fn default() -> Self {
+        UnmappedAccessPolicy::Ignore
+    }
+}
+
+// Number of unmapped accesses kept for `Bus::unmapped_accesses`.
+const UNMAPPED_ACCESS_HISTORY: usize = 64;
+// At most `UNMAPPED_ACCESS_LOG_BURST` unmapped accesses are logged per
+// `UNMAPPED_ACCESS_LOG_INTERVAL`.
+const UNMAPPED_ACCESS_LOG_BURST: u64 = 10;
+const UNMAPPED_ACCESS_LOG_INTERVAL: Duration = Duration::from_secs(1);
+
+struct UnmappedAccesses {
+    policy: UnmappedAccessPolicy,
+    recent: VecDeque<UnmappedAccess>,
+    total: u64,
+    log_limiter: LogRateLimiter,
+}
+
+impl Default for UnmappedAccesses {
+    fn default() -> Self {
+        UnmappedAccesses {
+            policy: Default::default(),
+            recent: VecDeque::new(),
+            total: 0,
+            log_limiter: LogRateLimiter::new(
+                "accesses without a device",
+                UNMAPPED_ACCESS_LOG_BURST,
+                UNMAPPED_ACCESS_LOG_INTERVAL,
+            ),
+        }
+    }
+}
+
+impl UnmappedAccesses {
+    fn record(&mut self, access: UnmappedAccess) {
+        self.total += 1;
+        if self.recent.len() == UNMAPPED_ACCESS_HISTORY {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(access);
+
+        if self.log_limiter.allow() {
+            warn!("guest access without a device: {}", access);
+        }
+    }
+}
+
 /// Trait for devices that respond to reads or writes in an arbitrary address space.
 ///
 /// The device does not care where it exists in address space as each method is only given an offset
@@ -295,6 +362,7 @@ enum BusDeviceEntry {
 pub struct Bus {
     devices: Arc<Mutex<BTreeMap<BusRange, BusEntry>>>,
     access_id: usize,
+    unmapped: Arc<Mutex<UnmappedAccesses>>,
     #[cfg(feature = "stats")]
     pub stats: Arc<Mutex<BusStatistics>>,
 }
@@ -305,6 +373,7 @@ impl Bus {
         Bus {
             devices: Arc::new(Mutex::new(BTreeMap::new())),
             access_id: 0,
+            unmapped: Arc::new(Mutex::new(Default::default())),
             #[cfg(feature = "stats")]
             stats: Arc::new(Mutex::new(BusStatistics::new())),
         }
@@ -315,6 +384,42 @@ impl Bus {
         self.access_id = id;
     }
 
+    /// Sets how accesses to addresses without a device are handled, for all clones of this bus.
+    pub fn set_unmapped_access_policy(&self, policy: UnmappedAccessPolicy) {
+        self.unmapped.lock().policy = policy;
+    }
+
+    pub fn unmapped_access_policy(&self) -> UnmappedAccessPolicy {
+        self.unmapped.lock().policy
+    }
+
+    /// Returns the most recent accesses to addresses without a device, oldest first, and the total
+    /// number of such accesses. Accesses are only recorded if the policy isn't `Ignore`.
+    pub fn unmapped_accesses(&self) -> (Vec<UnmappedAccess>, u64) {
+        let unmapped = self.unmapped.lock();
+        (unmapped.recent.iter().copied().collect(), unmapped.total)
+    }
+
+    fn unmapped_access(
+        &self,
+        address: u64,
+        size: usize,
+        write: bool,
+        pc: impl FnOnce() -> Option<u64>,
+    ) {
+        if self.unmapped_access_policy() == UnmappedAccessPolicy::Ignore {
+            return;
+        }
+        let access = UnmappedAccess {
+            address,
+            size,
+            write,
+            vcpu_id: self.access_id,
+            pc: pc(),
+        };
+        self.unmapped.lock().record(access);
+    }
+
     fn first_before(&self, addr: u64) -> Option<(BusRange, BusEntry)> {
         let devices = self.devices.lock();
         let (range, entry) = devices
@@ -475,6 +580,17 @@ impl Bus {
     ///
     /// Returns true on success, otherwise `data` is untouched.
     pub fn read(&self, addr: u64, data: &mut [u8]) -> bool {
+        self.read_with_pc(addr, data, || None)
+    }
+
+    /// Like `read`, with `pc` returning the program counter of the access in case it has to be
+    /// recorded as an access without a device.
+    pub fn read_with_pc(
+        &self,
+        addr: u64,
+        data: &mut [u8],
+        pc: impl FnOnce() -> Option<u64>,
+    ) -> bool {
         #[cfg(feature = "stats")]
         let start = self.stats.lock().start_stat();
 
@@ -495,6 +611,7 @@ impl Bus {
             let index = Some(());
             index
         } else {
+            self.unmapped_access(addr, data.len(), false, pc);
             None
         };
 
@@ -513,6 +630,12 @@ impl Bus {
     ///
     /// Returns true on success, otherwise `data` is untouched.
     pub fn write(&self, addr: u64, data: &[u8]) -> bool {
+        self.write_with_pc(addr, data, || None)
+    }
+
+    /// Like `write`, with `pc` returning the program counter of the access in case it has to be
+    /// recorded as an access without a device.
+    pub fn write_with_pc(&self, addr: u64, data: &[u8], pc: impl FnOnce() -> Option<u64>) -> bool {
         #[cfg(feature = "stats")]
         let start = self.stats.lock().start_stat();
 
@@ -534,6 +657,7 @@ impl Bus {
             let index = Some(());
             index
         } else {
+            self.unmapped_access(addr, data.len(), true, pc);
             None
         };
 
@@ -728,6 +852,85 @@ mod tests {
         assert!(bus.write(0x15, &values));
     }
 
+    #[test]
+    fn unmapped_access_ignore() {
+        let bus = Bus::new();
+        assert_eq!(bus.unmapped_access_policy(), UnmappedAccessPolicy::Ignore);
+
+        let mut values = [0xff; 4];
+        assert!(!bus.read_with_pc(0x10, &mut values, || Some(0x1234)));
+        assert_eq!(values, [0xff; 4]);
+        assert!(!bus.write(0x10, &values));
+        assert_eq!(bus.unmapped_accesses(), (Vec::new(), 0));
+    }
+
+    #[test]
+    fn unmapped_access_log() {
+        let mut bus = Bus::new();
+        bus.set_unmapped_access_policy(UnmappedAccessPolicy::Log);
+        bus.set_access_id(2);
+
+        let mut values = [0xff; 4];
+        assert!(!bus.read_with_pc(0x10, &mut values, || Some(0x1234)));
+        assert_eq!(values, [0xff; 4]);
+        assert!(!bus.write(0x20, &values[..2]));
+        assert_eq!(
+            bus.unmapped_accesses(),
+            (
+                vec![
+                    UnmappedAccess {
+                        address: 0x10,
+                        size: 4,
+                        write: false,
+                        vcpu_id: 2,
+                        pc: Some(0x1234),
+                    },
+                    UnmappedAccess {
+                        address: 0x20,
+                        size: 2,
+                        write: true,
+                        vcpu_id: 2,
+                        pc: None,
+                    },
+                ],
+                2
+            )
+        );
+    }
+
+    #[test]
+    fn unmapped_access_log_history() {
+        let bus = Bus::new();
+        bus.set_unmapped_access_policy(UnmappedAccessPolicy::Log);
+        // Clones share the policy and the recorded accesses.
+        let clone = bus.clone();
+
+        let total = UNMAPPED_ACCESS_HISTORY as u64 + 10;
+        for address in 0..total {
+            assert!(!clone.write(address, &[0]));
+        }
+        let (accesses, recorded) = bus.unmapped_accesses();
+        assert_eq!(recorded, total);
+        assert_eq!(accesses.len(), UNMAPPED_ACCESS_HISTORY);
+        assert_eq!(accesses[0].address, 10);
+        assert_eq!(accesses[UNMAPPED_ACCESS_HISTORY - 1].address, total - 1);
+    }
+
+    #[test]
+    fn unmapped_access_fault() {
+        let bus = Bus::new();
+        bus.set_unmapped_access_policy(UnmappedAccessPolicy::Fault);
+
+        let mut values = [0xff; 8];
+        assert!(!bus.read(0x10, &mut values));
+        assert_eq!(values, [0xff; 8]);
+        assert_eq!(bus.unmapped_access_policy(), UnmappedAccessPolicy::Fault);
+        let (accesses, total) = bus.unmapped_accesses();
+        assert_eq!(total, 1);
+        assert_eq!(accesses[0].address, 0x10);
+        assert_eq!(accesses[0].size, 8);
+    }
+
     suspendable_tests! {
         dummy_device: DummyDevice,
         constant_device_true: ConstantDevice {
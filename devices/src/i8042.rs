@@ -4,7 +4,7 @@
 
 use base::error;
 use base::SendTube;
-use base::VmEventType;
+use base::VmExitReason;
 
 use crate::pci::CrosvmDeviceId;
 use crate::BusAccessInfo;
@@ -48,7 +48,7 @@ impl BusDevice for I8042Device {
         if data.len() == 1 && data[0] == 0xfe && info.address == 0x64 {
             if let Err(e) = self
                 .reset_evt_wrtube
-                .send::<VmEventType>(&VmEventType::Reset)
+                .send::<VmExitReason>(&VmExitReason::GuestReset)
             {
                 error!("failed to trigger i8042 reset event: {}", e);
             }
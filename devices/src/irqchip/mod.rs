@@ -19,6 +19,11 @@ use crate::BusDevice;
 use crate::IrqEdgeEvent;
 use crate::IrqLevelEvent;
 
+mod moderation;
+pub use moderation::ModeratedIrqEvent;
+pub use moderation::Moderation;
+pub use moderation::ModerationStats;
+
 cfg_if::cfg_if! {
     if #[cfg(unix)] {
         mod kvm;
@@ -147,6 +152,23 @@ pub trait IrqChip: Send {
     /// Unregister an event with edge-trigger semantic for a particular GSI.
     fn unregister_edge_irq_event(&mut self, irq: u32, irq_event: &IrqEdgeEvent) -> Result<()>;
 
+    /// Register an event with edge-trigger semantic whose injections are limited by
+    /// `moderation`. Triggers arriving faster than the allowed rate are coalesced into a single
+    /// interrupt. The returned `ModeratedIrqEvent` must be kept alive for as long as the
+    /// registration is in use, and its `chip_event()` is the event to pass to
+    /// `unregister_edge_irq_event`.
+    fn register_moderated_edge_irq_event(
+        &mut self,
+        irq: u32,
+        irq_event: &IrqEdgeEvent,
+        source: IrqEventSource,
+        moderation: Moderation,
+    ) -> Result<(ModeratedIrqEvent, Option<IrqEventIndex>)> {
+        let moderated = ModeratedIrqEvent::new(irq_event, source.clone(), moderation)?;
+        let index = self.register_edge_irq_event(irq, moderated.chip_event(), source)?;
+        Ok((moderated, index))
+    }
+
     /// Register an event with level-trigger semantic that can trigger an interrupt for a particular GSI.
     fn register_level_irq_event(
         &mut self,
@@ -0,0 +1,363 @@
+// Copyright 2022 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Interrupt moderation for edge triggered irq events.
+//!
+//! A moderated irq event sits between a device's `IrqEdgeEvent` and the event registered with
+//! the irq chip. Triggers arriving faster than the configured rate are coalesced into a single
+//! injection that is delivered once the moderation interval has elapsed, so no interrupt is lost
+//! but the guest never sees more than the configured rate.
+
+use std::cmp::max;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+use base::error;
+use base::Event;
+use base::EventToken;
+use base::Result;
+use base::WaitContext;
+use sync::Mutex;
+
+use crate::IrqEdgeEvent;
+use crate::IrqEventSource;
+
+/// Limits on how often a moderated irq event may be injected into the guest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Moderation {
+    /// Maximum number of injections per second. Zero means no rate limit.
+    pub max_rate: u32,
+    /// Minimum time between two injections.
+    pub min_interval: Duration,
+}
+
+impl Moderation {
+    /// The minimum time between two injections allowed by both limits.
+    fn interval(&self) -> Duration {
+        let rate_interval = if self.max_rate == 0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs(1) / self.max_rate
+        };
+        max(self.min_interval, rate_interval)
+    }
+}
+
+/// Counters for a moderated irq event.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ModerationStats {
+    /// Number of times the device triggered the event.
+    pub triggers: u64,
+    /// Number of interrupts injected into the guest.
+    pub injections: u64,
+    /// Number of triggers that were coalesced into another injection.
+    pub suppressed: u64,
+}
+
+struct Moderator {
+    interval: Duration,
+    last_injection: Option<Instant>,
+    pending: bool,
+    stats: ModerationStats,
+}
+
+impl Moderator {
+    fn new(moderation: Moderation) -> Self {
+        Moderator {
+            interval: moderation.interval(),
+            last_injection: None,
+            pending: false,
+            stats: Default::default(),
+        }
+    }
+
+    /// Records `count` triggers at `now`. Returns true if an interrupt should be injected now.
+    fn trigger(&mut self, now: Instant, count: u64) -> bool {
+        if count == 0 {
+            return false;
+        }
+        self.stats.triggers += count;
+        // Triggers beyond the first are always coalesced with it.
+        self.stats.suppressed += count - 1;
+
+        if self.pending {
+            self.stats.suppressed += 1;
+            return false;
+        }
+
+        match self.last_injection {
+            Some(last) if now < last + self.interval => {
+                self.pending = true;
+                false
+            }
+            _ => {
+                self.inject(now);
+                true
+            }
+        }
+    }
+
+    /// Returns the time at which a deferred injection is due, if any.
+    fn deadline(&self) -> Option<Instant> {
+        if self.pending {
+            self.last_injection.map(|last| last + self.interval)
+        } else {
+            None
+        }
+    }
+
+    /// Returns true if a deferred injection is due at `now`.
+    fn flush(&mut self, now: Instant) -> bool {
+        match self.deadline() {
+            Some(deadline) if now >= deadline => {
+                self.pending = false;
+                self.inject(now);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn inject(&mut self, now: Instant) {
+        self.last_injection = Some(now);
+        self.stats.injections += 1;
+    }
+}
+
+#[derive(EventToken)]
+enum Token {
+    Trigger,
+    Kill,
+}
+
+fn run_moderator(
+    device_evt: Event,
+    chip_evt: IrqEdgeEvent,
+    kill_evt: Event,
+    moderator: Arc<Mutex<Moderator>>,
+) {
+    let wait_ctx: WaitContext<Token> =
+        match WaitContext::build_with(&[(&device_evt, Token::Trigger), (&kill_evt, Token::Kill)]) {
+            Ok(ctx) => ctx,
+            Err(e) => {
+                error!("failed to build WaitContext: {}", e);
+                return;
+            }
+        };
+
+    loop {
+        let deadline = moderator.lock().deadline();
+        let events = match deadline {
+            Some(deadline) => {
+                wait_ctx.wait_timeout(deadline.saturating_duration_since(Instant::now()))
+            }
+            None => wait_ctx.wait(),
+        };
+        let events = match events {
+            Ok(v) => v,
+            Err(e) => {
+                error!("failed to wait for moderated irq event: {}", e);
+                return;
+            }
+        };
+
+        for event in events.iter().filter(|e| e.is_readable) {
+            match event.token {
+                Token::Trigger => {
+                    let count = match device_evt.read() {
+                        Ok(count) => count,
+                        Err(e) => {
+                            error!("failed to read moderated irq event: {}", e);
+                            continue;
+                        }
+                    };
+                    if moderator.lock().trigger(Instant::now(), count) {
+                        if let Err(e) = chip_evt.trigger() {
+                            error!("failed to inject moderated irq: {}", e);
+                        }
+                    }
+                }
+                Token::Kill => return,
+            }
+        }
+
+        if moderator.lock().flush(Instant::now()) {
+            if let Err(e) = chip_evt.trigger() {
+                error!("failed to inject moderated irq: {}", e);
+            }
+        }
+    }
+}
+
+/// An edge triggered irq event whose injections are limited by a `Moderation`.
+///
+/// Triggers of the device's event are relayed to `chip_event()` by a worker thread, which lives
+/// as long as this object. The irq chip registration must be removed by unregistering
+/// `chip_event()`.
+pub struct ModeratedIrqEvent {
+    source: IrqEventSource,
+    chip_evt: IrqEdgeEvent,
+    moderator: Arc<Mutex<Moderator>>,
+    kill_evt: Event,
+    worker: Option<thread::JoinHandle<()>>,
+}
+
+impl ModeratedIrqEvent {
+    /// Starts moderating triggers of `irq_event` according to `moderation`.
+    pub fn new(
+        irq_event: &IrqEdgeEvent,
+        source: IrqEventSource,
+        moderation: Moderation,
+    ) -> Result<ModeratedIrqEvent> {
+        let device_evt = irq_event.get_trigger().try_clone()?;
+        let chip_evt = IrqEdgeEvent::new()?;
+        let kill_evt = Event::new()?;
+        let moderator = Arc::new(Mutex::new(Moderator::new(moderation)));
+
+        let worker_chip_evt = chip_evt.try_clone()?;
+        let worker_kill_evt = kill_evt.try_clone()?;
+        let worker_moderator = moderator.clone();
+        let worker = thread::Builder::new()
+            .name(format!("{} irq moderation", source.device_name))
+            .spawn(move || {
+                run_moderator(
+                    device_evt,
+                    worker_chip_evt,
+                    worker_kill_evt,
+                    worker_moderator,
+                )
+            })
+            .map_err(base::Error::from)?;
+
+        Ok(ModeratedIrqEvent {
+            source,
+            chip_evt,
+            moderator,
+            kill_evt,
+            worker: Some(worker),
+        })
+    }
+
+    /// The event that should be registered with the irq chip.
+    pub fn chip_event(&self) -> &IrqEdgeEvent {
+        &self.chip_evt
+    }
+
+    /// The device this event moderates interrupts for.
+    pub fn source(&self) -> &IrqEventSource {
+        &self.source
+    }
+
+    /// Returns the current trigger, injection and suppression counters.
+    pub fn stats(&self) -> ModerationStats {
+        self.moderator.lock().stats
+    }
+}
+
+impl Drop for ModeratedIrqEvent {
+    fn drop(&mut self) {
+        // Ignore the result because there is nothing we can do with a failure.
+        let _ = self.kill_evt.write(1);
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pci::CrosvmDeviceId;
+
+    fn test_source() -> IrqEventSource {
+        IrqEventSource {
+            device_id: CrosvmDeviceId::DebugConsole.into(),
+            queue_id: 0,
+            device_name: "test".to_owned(),
+        }
+    }
+
+    #[test]
+    fn moderator_coalesces_within_interval() {
+        let mut moderator = Moderator::new(Moderation {
+            max_rate: 10,
+            min_interval: Duration::ZERO,
+        });
+        let start = Instant::now();
+
+        assert!(moderator.trigger(start, 1));
+        assert!(!moderator.trigger(start + Duration::from_millis(10), 1));
+        assert!(!moderator.trigger(start + Duration::from_millis(20), 3));
+        assert_eq!(
+            moderator.deadline(),
+            Some(start + Duration::from_millis(100))
+        );
+        assert!(!moderator.flush(start + Duration::from_millis(99)));
+        assert!(moderator.flush(start + Duration::from_millis(100)));
+        assert_eq!(moderator.deadline(), None);
+
+        assert_eq!(
+            moderator.stats,
+            ModerationStats {
+                triggers: 5,
+                injections: 2,
+                suppressed: 3,
+            }
+        );
+    }
+
+    #[test]
+    fn moderator_min_interval_overrides_rate() {
+        let mut moderator = Moderator::new(Moderation {
+            max_rate: 1000,
+            min_interval: Duration::from_millis(50),
+        });
+        let start = Instant::now();
+
+        assert!(moderator.trigger(start, 1));
+        assert!(!moderator.trigger(start + Duration::from_millis(10), 1));
+        assert!(moderator.flush(start + Duration::from_millis(50)));
+        assert!(moderator.trigger(start + Duration::from_millis(100), 1));
+    }
+
+    #[test]
+    fn moderated_event_respects_rate_cap() {
+        const MAX_RATE: u32 = 100;
+        let device_evt = IrqEdgeEvent::new().unwrap();
+        let moderated = ModeratedIrqEvent::new(
+            &device_evt,
+            test_source(),
+            Moderation {
+                max_rate: MAX_RATE,
+                min_interval: Duration::ZERO,
+            },
+        )
+        .unwrap();
+
+        let start = Instant::now();
+        for _ in 0..1000 {
+            device_evt.trigger().unwrap();
+            thread::sleep(Duration::from_micros(200));
+        }
+        let elapsed = start.elapsed();
+
+        // Wait for any deferred injection to be flushed.
+        thread::sleep(Duration::from_secs(1) / MAX_RATE * 2);
+        let injected = moderated.chip_event().get_trigger().read().unwrap();
+        let stats = moderated.stats();
+
+        let cap = (elapsed.as_secs_f64() * f64::from(MAX_RATE)).ceil() as u64 + 1;
+        assert!(
+            injected <= cap,
+            "injected {} interrupts, cap {}",
+            injected,
+            cap
+        );
+        assert_eq!(stats.injections, injected);
+        assert_eq!(stats.triggers, 1000);
+        assert_eq!(stats.triggers, stats.injections + stats.suppressed);
+    }
+}
@@ -8,6 +8,7 @@
 
 pub mod acpi;
 pub mod bat;
+mod boot_milestones;
 mod bus;
 #[cfg(feature = "stats")]
 mod bus_stats;
@@ -48,6 +49,9 @@ pub use self::acpi::ACPIPMFixedEvent;
 pub use self::acpi::ACPIPMResource;
 pub use self::bat::BatteryError;
 pub use self::bat::GoldfishBattery;
+pub use self::boot_milestones::BootDoorbell;
+pub use self::boot_milestones::SerialOutputMilestone;
+pub use self::boot_milestones::BOOT_DOORBELL_SIZE;
 pub use self::bus::Bus;
 pub use self::bus::BusAccessInfo;
 pub use self::bus::BusDevice;
@@ -59,6 +63,7 @@ pub use self::bus::BusType;
 pub use self::bus::Error as BusError;
 pub use self::bus::HostHotPlugKey;
 pub use self::bus::HotPlugBus;
+pub use self::bus::UnmappedAccessPolicy;
 #[cfg(feature = "stats")]
 pub use self::bus_stats::BusStatistics;
 pub use self::cmos::Cmos;
@@ -94,10 +99,13 @@ pub use self::pci::PreferredIrq;
 pub use self::pci::StubPciDevice;
 pub use self::pci::StubPciParameters;
 pub use self::pl030::Pl030;
+pub use self::serial::ConsoleBuffer;
 pub use self::serial::Serial;
+pub use self::serial::SerialConsoleRecorder;
 pub use self::serial_device::Error as SerialError;
 pub use self::serial_device::SerialDevice;
 pub use self::serial_device::SerialHardware;
+pub use self::serial_device::SerialOutputPolicy;
 pub use self::serial_device::SerialParameters;
 pub use self::serial_device::SerialType;
 #[cfg(feature = "tpm")]
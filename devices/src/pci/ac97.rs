@@ -5,6 +5,8 @@
 pub(crate) mod sys;
 
 use std::default::Default;
+#[cfg(windows)]
+use std::path::PathBuf;
 use std::str::FromStr;
 
 use base::error;
@@ -96,6 +98,14 @@ pub struct Ac97Parameters {
     #[cfg(feature = "audio_cras")]
     #[serde(skip)]
     client_type: Option<CrasClientType>,
+    /// Keep a copy of the rendered frames for `crosvm snd capture`.
+    #[cfg(windows)]
+    #[serde(default)]
+    pub loopback: bool,
+    /// Also write the rendered frames to this WAV file.
+    #[cfg(windows)]
+    #[serde(default)]
+    pub loopback_wav: Option<PathBuf>,
     #[cfg(feature = "audio_cras")]
     #[serde(skip)]
     socket_type: Option<CrasSocketType>,
@@ -11,11 +11,14 @@ use serde::Serialize;
 use sync::Mutex;
 use vm_memory::GuestMemory;
 use win_audio::create_win_audio_device;
+use win_audio::loopback::enable_loopback;
+use win_audio::loopback::LoopbackConfig;
 use win_audio::WinAudioServer;
 
 use crate::pci::ac97::Ac97Dev;
 use crate::pci::ac97::Ac97Error;
 use crate::pci::ac97::Ac97Parameters;
+use crate::pci::pci_device;
 use crate::pci::pci_device::Result;
 
 pub(crate) type AudioStreamSource = Arc<Mutex<dyn WinAudioServer>>;
@@ -29,11 +32,17 @@ impl Ac97Dev {
     pub(in crate::pci::ac97) fn initialize_backend(
         ac97_backend: &Ac97Backend,
         mem: GuestMemory,
-        _param: &Ac97Parameters,
+        param: &Ac97Parameters,
         ac97_device_tube: Tube,
     ) -> Result<Self> {
         match ac97_backend {
             Ac97Backend::WinAudio => {
+                if param.loopback {
+                    enable_loopback(LoopbackConfig {
+                        wav_path: param.loopback_wav.clone(),
+                    })
+                    .map_err(pci_device::Error::EnableAudioLoopback)?;
+                }
                 let win_audio = Arc::new(Mutex::new(create_win_audio_device().unwrap()));
 
                 let win_audio_device = Self::new(
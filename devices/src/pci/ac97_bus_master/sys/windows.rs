@@ -35,6 +35,7 @@ use vm_memory::GuestAddress;
 use vm_memory::GuestMemory;
 use win_audio::intermediate_resampler_buffer::IntermediateResamplerBuffer;
 use win_audio::intermediate_resampler_buffer::STEREO_CHANNEL_COUNT;
+use win_audio::AudioSharedFormatUpdates;
 use winapi::um::winbase::THREAD_PRIORITY_TIME_CRITICAL;
 
 use crate::pci::ac97::sys::AudioStreamSource;
@@ -210,6 +211,7 @@ impl Ac97BusMaster {
                         sample_rate,
                         buffer_frames,
                     )?;
+                let format_updates = self.audio_server.lock().shared_format_updates();
                 self.po_info.stream_control = Some(Box::new(NoopStreamControl::new()));
                 self.update_mixer_settings(mixer);
                 let mute = self.mute.clone();
@@ -229,6 +231,7 @@ impl Ac97BusMaster {
                                 audio_shared_format.shared_audio_engine_period_in_frames,
                                 audio_shared_format.channels,
                                 audio_shared_format.channel_mask,
+                                audio_shared_format.sample_format,
                             )
                             .unwrap();
                             if let Err(e) = audio_out_thread(
@@ -237,6 +240,7 @@ impl Ac97BusMaster {
                                 &thread_run,
                                 output_stream,
                                 intermediate_buffer,
+                                format_updates,
                                 mute,
                                 guest_num_channels,
                             ) {
@@ -376,9 +380,11 @@ fn audio_out_thread(
     thread_run: &AtomicBool,
     output_stream: Arc<Mutex<Box<dyn PlaybackBufferStream>>>,
     mut intermediate_resampler_buffer: IntermediateResamplerBuffer,
+    format_updates: AudioSharedFormatUpdates,
     mute: Arc<Mutex<bool>>,
     guest_num_channels: usize,
 ) -> AudioResult<()> {
+    let frame_rate = intermediate_resampler_buffer.frame_rate();
     while thread_run.load(Ordering::Relaxed) {
         // If the intermediate buffer length + the next guest period isn't enough to fill the
         // next Windows audio engine period, then read from the guest again.
@@ -414,6 +420,21 @@ fn audio_out_thread(
             .next_playback_buffer()
             .map_err(AudioError::StreamError)
             .and_then(|mut pb_buf| {
+                // The stream may have renegotiated its format while waiting for the buffer, in
+                // which case the buffer is sized for the new audio engine period.
+                if let Some(format) = format_updates.take() {
+                    if format.frame_rate != frame_rate {
+                        warn!(
+                            "Audio engine frame rate changed from {} to {}, playback may be \
+                             distorted.",
+                            frame_rate, format.frame_rate
+                        );
+                    }
+                    intermediate_resampler_buffer.set_shared_audio_engine_period_in_frames(
+                        format.shared_audio_engine_period_in_frames,
+                    );
+                    intermediate_resampler_buffer.set_sample_format(format.sample_format);
+                }
                 let res = play_buffer(
                     &mut regs.lock(),
                     &mem,
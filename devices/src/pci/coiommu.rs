@@ -49,6 +49,7 @@ use base::TubeError;
 use base::WaitContext;
 use data_model::DataInit;
 use hypervisor::Datamatch;
+use hypervisor::MemCacheType;
 use resources::Alloc;
 use resources::AllocOptions;
 use resources::SystemAllocator;
@@ -1146,6 +1147,7 @@ impl CoIommuDev {
             },
             dest: VmMemoryDestination::GuestPhysicalAddress(gpa),
             prot,
+            cache: MemCacheType::Cached,
         };
         self.send_msg(&request)
     }
@@ -1326,7 +1328,10 @@ impl CoIommuDev {
                     bar: bar_num,
                 },
                 name.to_string(),
-                AllocOptions::new().prefetchable(true).align(size),
+                AllocOptions::new()
+                    .prefetchable(true)
+                    .align(size)
+                    .best_fit(true),
             )
             .map_err(|e| PciDeviceError::IoAllocationFailed(size, e))?;
 
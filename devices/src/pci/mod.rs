@@ -136,6 +136,7 @@ pub enum CrosvmDeviceId {
     VmWatchdog = 17,
     Pflash = 18,
     VirtioMmio = 19,
+    BootDoorbell = 20,
 }
 
 impl TryFrom<u16> for CrosvmDeviceId {
@@ -162,6 +163,7 @@ impl TryFrom<u16> for CrosvmDeviceId {
             17 => Ok(CrosvmDeviceId::VmWatchdog),
             18 => Ok(CrosvmDeviceId::Pflash),
             19 => Ok(CrosvmDeviceId::VirtioMmio),
+            20 => Ok(CrosvmDeviceId::BootDoorbell),
             _ => Err(base::Error::new(EINVAL)),
         }
     }
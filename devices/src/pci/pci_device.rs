@@ -77,6 +77,10 @@ pub enum Error {
     /// Device not exist on this bus
     #[error("pci device {0} does not located on bus {1}")]
     DeviceNotExist(PciAddress, u8),
+    /// Enabling the loopback capture of the audio backend failed.
+    #[cfg(all(windows, feature = "audio"))]
+    #[error("failed to enable audio loopback capture: {0}")]
+    EnableAudioLoopback(std::io::Error),
     /// Allocating space for an IO BAR failed.
     #[error("failed to allocate space for an IO BAR, size={0}: {1}")]
     IoAllocationFailed(u64, SystemAllocatorFaliure),
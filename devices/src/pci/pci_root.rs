@@ -11,7 +11,7 @@ use std::sync::Weak;
 use base::error;
 use base::RawDescriptor;
 use base::SendTube;
-use base::VmEventType;
+use base::VmExitReason;
 use resources::SystemAllocator;
 use sync::Mutex;
 
@@ -416,7 +416,7 @@ impl BusDevice for PciConfigIo {
             _o @ 1 if data.len() == 1 && data[0] & PCI_RESET_CPU_BIT != 0 => {
                 if let Err(e) = self
                     .reset_evt_wrtube
-                    .send::<VmEventType>(&VmEventType::Reset)
+                    .send::<VmExitReason>(&VmExitReason::GuestReset)
                 {
                     error!("failed to trigger PCI 0xcf9 reset event: {}", e);
                 }
@@ -17,7 +17,7 @@ use std::fmt;
 use base::error;
 use base::RawDescriptor;
 use base::SendTube;
-use base::VmEventType;
+use base::VmExitReason;
 use resources::Alloc;
 use resources::AllocOptions;
 use resources::SystemAllocator;
@@ -198,7 +198,7 @@ impl PciDevice for PvPanicPciDevice {
 
         if let Err(e) = self
             .evt_wrtube
-            .send::<VmEventType>(&VmEventType::Panic(data[0]))
+            .send::<VmExitReason>(&VmExitReason::Panic { info: data[0] })
         {
             error!("Failed to write to the event tube: {}", e);
         }
@@ -260,7 +260,12 @@ mod test {
         device.write_bar(mmio_addr, &data);
 
         // Verify the event
-        let val = evt_rdtube.recv::<VmEventType>().unwrap();
-        assert_eq!(val, VmEventType::Panic(PVPANIC_CRASH_LOADED));
+        let val = evt_rdtube.recv::<VmExitReason>().unwrap();
+        assert_eq!(
+            val,
+            VmExitReason::Panic {
+                info: PVPANIC_CRASH_LOADED
+            }
+        );
     }
 }
@@ -33,6 +33,7 @@ use base::Protection;
 use base::RawDescriptor;
 use base::Tube;
 use base::WaitContext;
+use hypervisor::MemCacheType;
 use hypervisor::MemSlot;
 use resources::AddressRange;
 use resources::Alloc;
@@ -1144,6 +1145,7 @@ impl VfioPciDevice {
                         },
                         dest: VmMemoryDestination::GuestPhysicalAddress(guest_map_start),
                         prot: Protection::read_write(),
+                        cache: MemCacheType::Cached,
                     })
                     .is_err()
                 {
@@ -1400,7 +1402,8 @@ impl VfioPciDevice {
                             } else {
                                 u32::MAX.into()
                             })
-                            .align(bar_size),
+                            .align(bar_size)
+                            .best_fit(true),
                     )
                     .map_err(|e| PciDeviceError::IoAllocationFailed(bar_size, e))?;
                 ranges.push(BarRange {
@@ -20,6 +20,7 @@ use base::MemoryMappingBuilder;
 use base::Protection;
 use base::RawDescriptor;
 use base::Tube;
+use hypervisor::MemCacheType;
 use resources::SystemAllocator;
 use vfio_sys::*;
 use vm_control::VmMemoryDestination;
@@ -152,6 +153,13 @@ impl VfioPlatformDevice {
         None
     }
 
+    /// Returns the size of each MMIO region exposed by the device, in region index order.
+    pub fn mmio_region_sizes(&self) -> Vec<u64> {
+        (0..self.device.get_region_count())
+            .map(|i| self.device.get_region_size(i))
+            .collect()
+    }
+
     pub fn allocate_regions(
         &mut self,
         resources: &mut SystemAllocator,
@@ -208,6 +216,7 @@ impl VfioPlatformDevice {
                         },
                         dest: VmMemoryDestination::GuestPhysicalAddress(guest_map_start),
                         prot: Protection::read_write(),
+                        cache: MemCacheType::Cached,
                     })
                     .is_err()
                 {
@@ -2,6 +2,8 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
+mod console_buffer;
+mod output;
 pub(crate) mod sys;
 
 use std::collections::VecDeque;
@@ -10,17 +12,27 @@ use std::sync::atomic::AtomicU8;
 use std::sync::atomic::Ordering;
 use std::sync::mpsc::channel;
 use std::sync::mpsc::Receiver;
+use std::sync::mpsc::Sender;
 use std::sync::mpsc::TryRecvError;
 use std::sync::Arc;
 use std::thread;
 
 use base::error;
 use base::Event;
+use base::FileSerdeWrapper;
 use base::Result;
-
+use base::Tube;
+use base::TubeError;
+
+pub use self::console_buffer::ConsoleBuffer;
+pub use self::console_buffer::SerialConsoleRecorder;
+use self::output::OutputQueue;
+use self::output::OutputSink;
+use self::output::OUTPUT_QUEUE_SIZE;
 use crate::bus::BusAccessInfo;
 use crate::pci::CrosvmDeviceId;
 use crate::serial_device::SerialInput;
+use crate::serial_device::SerialOutputPolicy;
 use crate::BusDevice;
 use crate::DeviceId;
 
@@ -56,6 +68,11 @@ const MCR_OUT1_BIT: u8 = 0x04;
 const MCR_OUT2_BIT: u8 = 0x08;
 const MCR_LOOP_BIT: u8 = 0x10;
 
+const MSR_DELTA_CTS_BIT: u8 = 0x01;
+const MSR_DELTA_DSR_BIT: u8 = 0x02;
+const MSR_TRAILING_RI_BIT: u8 = 0x04; // Ring Indicator went from on to off
+const MSR_DELTA_DCD_BIT: u8 = 0x08;
+const MSR_DELTA_BITS: u8 = 0x0f;
 const MSR_CTS_BIT: u8 = 0x10; // Clear to Send
 const MSR_DSR_BIT: u8 = 0x20; // Data Set Ready
 const MSR_RI_BIT: u8 = 0x40; // Ring Indicator
@@ -72,7 +89,13 @@ const DEFAULT_BAUD_DIVISOR: u16 = 12; // 9600 bps
 ///
 /// This can optionally write the guest's output to a Write trait object. To send input to the
 /// guest, use `queue_input_bytes` directly, or give a Read trait object which will be used queue
-/// bytes when `used_command` is called.
+/// bytes when `used_command` is called. The VMM can also inject input through the tube given to
+/// `set_injected_input`.
+///
+/// Output is written to the Write trait object by a worker thread through a bounded queue, so a
+/// slow sink doesn't stall the guest. What happens when the queue is full is decided by the
+/// `SerialOutputPolicy`. The VMM can replace an output file, e.g. to rotate it, through the tube
+/// given to `set_output_reopen`.
 pub struct Serial {
     // Serial port registers
     interrupt_enable: Arc<AtomicU8>,
@@ -89,7 +112,11 @@ pub struct Serial {
     in_buffer: VecDeque<u8>,
     in_channel: Option<Receiver<u8>>,
     input: Option<Box<dyn SerialInput>>,
+    injected_input: Option<Tube>,
     out: Option<Box<dyn io::Write + Send>>,
+    out_queue: Option<OutputQueue>,
+    output_policy: SerialOutputPolicy,
+    output_reopen: Option<Tube>,
     #[cfg(windows)]
     pub system_params: sys::windows::SystemSerialParams,
 }
@@ -114,7 +141,11 @@ impl Serial {
             in_buffer: Default::default(),
             in_channel: None,
             input,
+            injected_input: None,
             out,
+            out_queue: None,
+            output_policy: Default::default(),
+            output_reopen: None,
             #[cfg(windows)]
             system_params,
         }
@@ -130,6 +161,25 @@ impl Serial {
         "serial".to_owned()
     }
 
+    /// Sets what happens to guest output when the output queue is full.
+    pub fn set_output_policy(&mut self, policy: SerialOutputPolicy) {
+        self.output_policy = policy;
+    }
+
+    /// Writes the output to the files received from `tube` from then on, in place of the current
+    /// one. Each message is a `FileSerdeWrapper` of the output file, reopened by the VMM.
+    ///
+    /// The previous file is synced before it is closed. No guest output is lost in the switch,
+    /// though bytes written just before a file is received may end up in either file.
+    pub fn set_output_reopen(&mut self, tube: Tube) {
+        self.output_reopen = Some(tube);
+    }
+
+    /// Returns the number of bytes of guest output dropped because the output queue was full.
+    pub fn dropped_output_bytes(&self) -> u64 {
+        self.out_queue.as_ref().map_or(0, |q| q.dropped())
+    }
+
     /// Queues raw bytes for the guest to read and signals the interrupt if the line status would
     /// change. These bytes will be read by the guest before any bytes from the input stream that
     /// have not already been queued.
@@ -143,14 +193,24 @@ impl Serial {
         Ok(())
     }
 
-    fn spawn_input_thread(&mut self) {
-        let mut rx = match self.input.take() {
-            Some(input) => input,
-            None => return,
-        };
+    /// Queues the bytes received from `tube` for the guest as if they had been read from the
+    /// input of the port. Each message is a `Vec<u8>`, sent by the VMM on behalf of the user.
+    pub fn set_injected_input(&mut self, tube: Tube) {
+        self.injected_input = Some(tube);
+    }
 
+    fn spawn_input_threads(&mut self) {
         let (send_channel, recv_channel) = channel();
+        if let Some(input) = self.input.take() {
+            self.spawn_input_thread(input, send_channel.clone());
+        }
+        if let Some(tube) = self.injected_input.take() {
+            self.spawn_injected_input_thread(tube, send_channel);
+        }
+        self.in_channel = Some(recv_channel);
+    }
 
+    fn spawn_input_thread(&mut self, mut rx: Box<dyn SerialInput>, send_channel: Sender<u8>) {
         // The interrupt enable and interrupt event are used to trigger the guest serial driver to
         // read the serial device, which will give the VCPU threads time to queue input bytes from
         // the input thread's buffer, changing the serial device state accordingly.
@@ -198,14 +258,51 @@ impl Serial {
             });
         if let Err(e) = res {
             error!("failed to spawn input thread: {}", e);
-            return;
         }
-        self.in_channel = Some(recv_channel);
+    }
+
+    fn spawn_injected_input_thread(&mut self, tube: Tube, send_channel: Sender<u8>) {
+        let interrupt_enable = self.interrupt_enable.clone();
+        let interrupt_evt = match self.interrupt_evt.try_clone() {
+            Ok(e) => e,
+            Err(e) => {
+                error!("failed to clone interrupt event: {}", e);
+                return;
+            }
+        };
+
+        // Exits when the VMM closes the tube or the serial device is dropped, like the input
+        // thread.
+        let res = thread::Builder::new()
+            .name(format!("{} injected input thread", self.debug_label()))
+            .spawn(move || loop {
+                match tube.recv::<Vec<u8>>() {
+                    Ok(bytes) => {
+                        for byte in bytes {
+                            if send_channel.send(byte).is_err() {
+                                // The receiver has disconnected.
+                                return;
+                            }
+                        }
+                        if (interrupt_enable.load(Ordering::SeqCst) & IER_RECV_BIT) != 0 {
+                            interrupt_evt.write(1).unwrap();
+                        }
+                    }
+                    Err(TubeError::Disconnected) => return,
+                    Err(e) => {
+                        error!("failed to receive injected serial input: {}", e);
+                        return;
+                    }
+                }
+            });
+        if let Err(e) = res {
+            error!("failed to spawn injected input thread: {}", e);
+        }
     }
 
     fn handle_input_thread(&mut self) {
-        if self.input.is_some() {
-            self.spawn_input_thread();
+        if self.input.is_some() || self.injected_input.is_some() {
+            self.spawn_input_threads();
         }
 
         loop {
@@ -226,6 +323,98 @@ impl Serial {
         }
     }
 
+    fn spawn_output_thread(&mut self) {
+        let out = match self.out.take() {
+            Some(out) => out,
+            None => return,
+        };
+        let interrupt_evt = match self.interrupt_evt.try_clone() {
+            Ok(e) => e,
+            Err(e) => {
+                error!("failed to clone interrupt event: {}", e);
+                return;
+            }
+        };
+        let new_sinks = self
+            .output_reopen
+            .take()
+            .map(|tube| self.spawn_output_reopen_thread(tube));
+        match OutputQueue::spawn(
+            format!("{} output thread", self.debug_label()),
+            OUTPUT_QUEUE_SIZE,
+            out,
+            self.take_output_sync(),
+            new_sinks,
+            self.interrupt_enable.clone(),
+            interrupt_evt,
+        ) {
+            Ok(queue) => self.out_queue = Some(queue),
+            Err(e) => error!("failed to spawn output thread: {}", e),
+        }
+    }
+
+    // Forwards the files received from `tube` to the output thread as new sinks.
+    fn spawn_output_reopen_thread(&mut self, tube: Tube) -> Receiver<OutputSink> {
+        let (send_sink, recv_sink) = channel();
+        let new_sink = self.output_sink_fn();
+
+        // Exits when the VMM closes the tube or the output thread exits, like the injected input
+        // thread.
+        let res = thread::Builder::new()
+            .name(format!("{} output reopen thread", self.debug_label()))
+            .spawn(move || loop {
+                match tube.recv::<FileSerdeWrapper>() {
+                    Ok(file) => match new_sink(file.0) {
+                        Ok(sink) => {
+                            if send_sink.send(sink).is_err() {
+                                // The output thread has exited.
+                                return;
+                            }
+                        }
+                        Err(e) => error!("failed to use reopened serial output: {}", e),
+                    },
+                    Err(TubeError::Disconnected) => return,
+                    Err(e) => {
+                        error!("failed to receive reopened serial output: {}", e);
+                        return;
+                    }
+                }
+            });
+        if let Err(e) = res {
+            error!("failed to spawn output reopen thread: {}", e);
+        }
+        recv_sink
+    }
+
+    fn queue_output(&mut self, v: u8) {
+        // Spawned lazily, like the input thread, so that it runs in the device's process when the
+        // device is sandboxed.
+        if self.out.is_some() {
+            self.spawn_output_thread();
+        }
+
+        let queue = match self.out_queue.as_mut() {
+            Some(q) => q,
+            None => return,
+        };
+        queue.push(v);
+        if self.output_policy == SerialOutputPolicy::FlowControl && queue.is_full() && queue.stall()
+        {
+            // Report the transmitter as busy until the worker makes room, so that the guest
+            // driver waits before writing more.
+            self.line_status &= !(LSR_EMPTY_BIT | LSR_IDLE_BIT);
+        }
+    }
+
+    fn handle_output_queue(&mut self) -> Result<()> {
+        let busy = self.line_status & LSR_EMPTY_BIT == 0;
+        if busy && !self.out_queue.as_ref().map_or(false, |q| q.is_full()) {
+            self.line_status |= LSR_EMPTY_BIT | LSR_IDLE_BIT;
+            self.trigger_thr_empty()?;
+        }
+        Ok(())
+    }
+
     /// Gets the interrupt event used to interrupt the driver when it needs to respond to this
     /// device.
     pub fn interrupt_event(&self) -> &Event {
@@ -248,6 +437,49 @@ impl Serial {
         (self.modem_control & MCR_LOOP_BIT) != 0
     }
 
+    /// The modem status inputs, which are wired to the modem control outputs in loopback mode.
+    fn modem_status_inputs(&self) -> u8 {
+        let inputs = MSR_DSR_BIT | MSR_CTS_BIT | MSR_RI_BIT | MSR_DCD_BIT;
+        if !self.is_loop() {
+            return self.modem_status & inputs;
+        }
+        let mut msr = 0;
+        if self.modem_control & MCR_DTR_BIT != 0 {
+            msr |= MSR_DSR_BIT;
+        }
+        if self.modem_control & MCR_RTS_BIT != 0 {
+            msr |= MSR_CTS_BIT;
+        }
+        if self.modem_control & MCR_OUT1_BIT != 0 {
+            msr |= MSR_RI_BIT;
+        }
+        if self.modem_control & MCR_OUT2_BIT != 0 {
+            msr |= MSR_DCD_BIT;
+        }
+        msr
+    }
+
+    /// Writes the modem control register, recording in the modem status how its inputs changed
+    /// until the guest reads it.
+    fn set_modem_control(&mut self, v: u8) {
+        let old = self.modem_status_inputs();
+        self.modem_control = v;
+        let new = self.modem_status_inputs();
+        let changed = old ^ new;
+        if changed & MSR_CTS_BIT != 0 {
+            self.modem_status |= MSR_DELTA_CTS_BIT;
+        }
+        if changed & MSR_DSR_BIT != 0 {
+            self.modem_status |= MSR_DELTA_DSR_BIT;
+        }
+        if old & !new & MSR_RI_BIT != 0 {
+            self.modem_status |= MSR_TRAILING_RI_BIT;
+        }
+        if changed & MSR_DCD_BIT != 0 {
+            self.modem_status |= MSR_DELTA_DCD_BIT;
+        }
+    }
+
     fn add_intr_bit(&mut self, bit: u8) {
         self.interrupt_identification &= !IIR_NONE_BIT;
         self.interrupt_identification |= bit;
@@ -307,16 +539,20 @@ impl Serial {
                         self.set_data_bit();
                         self.trigger_recv_interrupt()?;
                     }
-                } else {
-                    self.system_handle_write(v)?;
+                    // The byte went straight to the receiver, so the transmitter is empty again.
                     self.trigger_thr_empty()?;
+                } else {
+                    self.queue_output(v);
+                    if self.line_status & LSR_EMPTY_BIT != 0 {
+                        self.trigger_thr_empty()?;
+                    }
                 }
             }
             IER => self
                 .interrupt_enable
                 .store(v & IER_FIFO_BITS, Ordering::SeqCst),
             LCR => self.line_control = v,
-            MCR => self.modem_control = v,
+            MCR => self.set_modem_control(v),
             SCR => self.scratch = v,
             _ => {}
         }
@@ -338,8 +574,9 @@ impl BusDevice for Serial {
             return;
         }
 
-        #[cfg(windows)]
-        self.handle_sync_thread();
+        if let Err(e) = self.handle_output_queue() {
+            error!("serial failed output queue: {}", e);
+        }
 
         if let Err(e) = self.handle_write(info.offset as u8, data[0]) {
             error!("serial failed write: {}", e);
@@ -352,6 +589,9 @@ impl BusDevice for Serial {
         }
 
         self.handle_input_thread();
+        if let Err(e) = self.handle_output_queue() {
+            error!("serial failed output queue: {}", e);
+        }
 
         data[0] = match info.offset as u8 {
             DLAB_LOW if self.is_dlab_set() => self.baud_divisor as u8,
@@ -373,25 +613,10 @@ impl BusDevice for Serial {
             MCR => self.modem_control,
             LSR => self.line_status,
             MSR => {
-                if self.is_loop() {
-                    let mut msr =
-                        self.modem_status & !(MSR_DSR_BIT | MSR_CTS_BIT | MSR_RI_BIT | MSR_DCD_BIT);
-                    if self.modem_control & MCR_DTR_BIT != 0 {
-                        msr |= MSR_DSR_BIT;
-                    }
-                    if self.modem_control & MCR_RTS_BIT != 0 {
-                        msr |= MSR_CTS_BIT;
-                    }
-                    if self.modem_control & MCR_OUT1_BIT != 0 {
-                        msr |= MSR_RI_BIT;
-                    }
-                    if self.modem_control & MCR_OUT2_BIT != 0 {
-                        msr |= MSR_DCD_BIT;
-                    }
-                    msr
-                } else {
-                    self.modem_status
-                }
+                let msr = self.modem_status_inputs() | (self.modem_status & MSR_DELTA_BITS);
+                // Reading the register acknowledges the changes.
+                self.modem_status &= !MSR_DELTA_BITS;
+                msr
             }
             SCR => self.scratch,
             _ => 0,
@@ -402,7 +627,10 @@ impl BusDevice for Serial {
 #[cfg(test)]
 mod tests {
     use std::io;
+    use std::sync::mpsc::Sender;
     use std::sync::Arc;
+    use std::time::Duration;
+    use std::time::Instant;
 
     use hypervisor::ProtectionType;
     use sync::Mutex;
@@ -421,6 +649,43 @@ mod tests {
                 buf: Arc::new(Mutex::new(Vec::new())),
             }
         }
+
+        /// Waits for the output thread to write output for which `f` returns true.
+        pub(super) fn wait_for(&self, f: impl Fn(&[u8]) -> bool) {
+            let start = Instant::now();
+            while !f(self.buf.lock().as_slice()) {
+                assert!(
+                    start.elapsed() < Duration::from_secs(10),
+                    "timed out waiting for serial output"
+                );
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
+    }
+
+    /// A sink that blocks every write until `gate` is dropped.
+    struct GatedSink {
+        gate: Receiver<()>,
+        out: SharedBuffer,
+    }
+
+    impl GatedSink {
+        fn new() -> (Sender<()>, SharedBuffer, GatedSink) {
+            let (gate_send, gate) = channel();
+            let out = SharedBuffer::new();
+            (gate_send, out.clone(), GatedSink { gate, out })
+        }
+    }
+
+    impl io::Write for GatedSink {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            // Nothing is ever sent, this only returns once the sender is dropped.
+            let _ = self.gate.recv();
+            self.out.write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
     }
 
     impl io::Write for SharedBuffer {
@@ -459,7 +724,234 @@ mod tests {
         serial.write(serial_bus_address(DATA), &[b'a']);
         serial.write(serial_bus_address(DATA), &[b'b']);
         serial.write(serial_bus_address(DATA), &[b'c']);
-        assert_eq!(serial_out.buf.lock().as_slice(), &[b'a', b'b', b'c']);
+        serial_out.wait_for(|buf| buf == [b'a', b'b', b'c']);
+    }
+
+    #[test]
+    fn serial_output_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let first_path = dir.path().join("first.log");
+        let second_path = dir.path().join("second.log");
+        let open = |path| {
+            std::fs::OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(path)
+                .unwrap()
+        };
+        let (vmm_tube, device_tube) = Tube::pair().unwrap();
+
+        let mut serial = Serial::new(
+            ProtectionType::Unprotected,
+            Event::new().unwrap(),
+            None,
+            Some(Box::new(open(&first_path))),
+            None,
+            false,
+            Vec::new(),
+        );
+        serial.set_output_reopen(device_tube);
+
+        let mut written = Vec::new();
+        for &c in b"before" {
+            serial.write(serial_bus_address(DATA), &[c]);
+            written.push(c);
+        }
+        let start = Instant::now();
+        while std::fs::metadata(&first_path).unwrap().len() < written.len() as u64 {
+            assert!(
+                start.elapsed() < Duration::from_secs(10),
+                "timed out waiting for serial output"
+            );
+            thread::sleep(Duration::from_millis(1));
+        }
+        vmm_tube
+            .send(&FileSerdeWrapper(open(&second_path)))
+            .unwrap();
+
+        // Keep writing until the output thread switches to the new file.
+        let start = Instant::now();
+        while std::fs::metadata(&second_path).unwrap().len() == 0 {
+            assert!(
+                start.elapsed() < Duration::from_secs(10),
+                "timed out waiting for the serial output to be reopened"
+            );
+            serial.write(serial_bus_address(DATA), &[b'x']);
+            written.push(b'x');
+            thread::sleep(Duration::from_millis(1));
+        }
+        drop(serial);
+
+        // The output thread exits once the device is dropped, having written everything.
+        let start = Instant::now();
+        loop {
+            let mut output = std::fs::read(&first_path).unwrap();
+            output.extend(std::fs::read(&second_path).unwrap());
+            if output == written {
+                break;
+            }
+            assert!(
+                start.elapsed() < Duration::from_secs(10),
+                "serial output lost across the reopen"
+            );
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert!(std::fs::read(&first_path).unwrap().starts_with(b"before"));
+    }
+
+    fn gated_serial(policy: SerialOutputPolicy) -> (Sender<()>, SharedBuffer, Serial) {
+        let (gate, serial_out, sink) = GatedSink::new();
+        let mut serial = Serial::new(
+            ProtectionType::Unprotected,
+            Event::new().unwrap(),
+            None,
+            Some(Box::new(sink)),
+            None,
+            false,
+            Vec::new(),
+        );
+        serial.set_output_policy(policy);
+        (gate, serial_out, serial)
+    }
+
+    fn read_register(serial: &mut Serial, offset: u8) -> u8 {
+        let mut data = [0u8];
+        serial.read(serial_bus_address(offset), &mut data);
+        data[0]
+    }
+
+    #[test]
+    fn serial_output_stalled_sink_drops() {
+        let (gate, serial_out, mut serial) = gated_serial(SerialOutputPolicy::Drop);
+
+        // The sink never drains, so writes past the queue size must be dropped rather than block
+        // the vCPU.
+        let len = OUTPUT_QUEUE_SIZE * 2;
+        let start = Instant::now();
+        for _ in 0..len {
+            serial.write(serial_bus_address(DATA), &[b'x']);
+        }
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_ne!(read_register(&mut serial, LSR) & LSR_EMPTY_BIT, 0);
+
+        // The output thread may have taken some bytes off the queue before blocking.
+        let dropped = serial.dropped_output_bytes() as usize;
+        assert!(dropped > 0 && dropped <= len - OUTPUT_QUEUE_SIZE);
+
+        drop(gate);
+        serial_out.wait_for(|buf| buf.len() == len - dropped);
+    }
+
+    #[test]
+    fn serial_output_stalled_sink_flow_control() {
+        let (gate, serial_out, mut serial) = gated_serial(SerialOutputPolicy::FlowControl);
+        serial.write(serial_bus_address(IER), &[IER_THR_BIT]);
+
+        // Write until the transmitter reports busy, like a polling guest driver would.
+        let start = Instant::now();
+        let mut written = 0;
+        while read_register(&mut serial, LSR) & LSR_EMPTY_BIT != 0 {
+            assert!(written <= OUTPUT_QUEUE_SIZE * 3);
+            serial.write(serial_bus_address(DATA), &[b'x']);
+            written += 1;
+        }
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(read_register(&mut serial, LSR) & LSR_IDLE_BIT, 0);
+        assert_eq!(serial.dropped_output_bytes(), 0);
+        // Clear the THR empty interrupt raised by the writes.
+        read_register(&mut serial, IIR);
+
+        // Once the sink drains, the transmitter is reported empty again and the guest is
+        // interrupted.
+        drop(gate);
+        let start = Instant::now();
+        while read_register(&mut serial, LSR) & LSR_EMPTY_BIT == 0 {
+            assert!(start.elapsed() < Duration::from_secs(10));
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(read_register(&mut serial, IIR) & IIR_THR_BIT, IIR_THR_BIT);
+        assert_eq!(serial.dropped_output_bytes(), 0);
+        serial_out.wait_for(|buf| buf.len() == written);
+    }
+
+    fn loopback_serial() -> (Event, SharedBuffer, Serial) {
+        let intr_evt = Event::new().unwrap();
+        let serial_out = SharedBuffer::new();
+        let serial = Serial::new(
+            ProtectionType::Unprotected,
+            intr_evt.try_clone().unwrap(),
+            None,
+            Some(Box::new(serial_out.clone())),
+            None,
+            false,
+            Vec::new(),
+        );
+        (intr_evt, serial_out, serial)
+    }
+
+    #[test]
+    fn serial_loopback_data() {
+        let (intr_evt, serial_out, mut serial) = loopback_serial();
+        serial.write(serial_bus_address(IER), &[IER_RECV_BIT]);
+        serial.write(serial_bus_address(MCR), &[MCR_LOOP_BIT]);
+
+        // Probe sequence of the Linux 8250 driver: a byte written in loopback mode is received.
+        serial.write(serial_bus_address(DATA), &[0x5a]);
+        assert_eq!(intr_evt.read(), Ok(1));
+        assert_eq!(read_register(&mut serial, LSR) & LSR_DATA_BIT, LSR_DATA_BIT);
+        assert_eq!(read_register(&mut serial, IIR) & IIR_RECV_BIT, IIR_RECV_BIT);
+        assert_eq!(read_register(&mut serial, DATA), 0x5a);
+        assert_eq!(read_register(&mut serial, LSR) & LSR_DATA_BIT, 0);
+
+        // Host input is ignored in loopback mode.
+        serial.queue_input_bytes(&[b'x']).unwrap();
+        assert_eq!(read_register(&mut serial, LSR) & LSR_DATA_BIT, 0);
+
+        // Nothing reached the output, which works again once loopback is off.
+        serial.write(serial_bus_address(MCR), &[DEFAULT_MODEM_CONTROL]);
+        serial.write(serial_bus_address(DATA), &[b'a']);
+        serial_out.wait_for(|buf| buf == [b'a']);
+    }
+
+    #[test]
+    fn serial_loopback_modem_status() {
+        let (_intr_evt, _serial_out, mut serial) = loopback_serial();
+        assert_eq!(read_register(&mut serial, MSR), DEFAULT_MODEM_STATUS);
+
+        // Probe sequence of the Linux 8250 driver: with only OUT2 set, only DCD is on. Entering
+        // loopback drops DSR and CTS, which is reported as a change.
+        serial.write(serial_bus_address(MCR), &[MCR_LOOP_BIT | MCR_OUT2_BIT]);
+        assert_eq!(
+            read_register(&mut serial, MSR),
+            MSR_DCD_BIT | MSR_DELTA_DSR_BIT | MSR_DELTA_CTS_BIT
+        );
+        // The changes were acknowledged by the read.
+        assert_eq!(read_register(&mut serial, MSR), MSR_DCD_BIT);
+
+        // Every modem control output shows on its modem status input.
+        let all = MCR_LOOP_BIT | MCR_DTR_BIT | MCR_RTS_BIT | MCR_OUT1_BIT | MCR_OUT2_BIT;
+        serial.write(serial_bus_address(MCR), &[all]);
+        assert_eq!(
+            read_register(&mut serial, MSR),
+            MSR_DSR_BIT
+                | MSR_CTS_BIT
+                | MSR_RI_BIT
+                | MSR_DCD_BIT
+                | MSR_DELTA_DSR_BIT
+                | MSR_DELTA_CTS_BIT
+        );
+
+        // Only the trailing edge of the ring indicator is reported.
+        serial.write(serial_bus_address(MCR), &[all & !MCR_OUT1_BIT]);
+        assert_eq!(
+            read_register(&mut serial, MSR),
+            MSR_DSR_BIT | MSR_CTS_BIT | MSR_DCD_BIT | MSR_TRAILING_RI_BIT
+        );
+
+        // Leaving loopback mode restores the modem status of the port.
+        serial.write(serial_bus_address(MCR), &[DEFAULT_MODEM_CONTROL]);
+        assert_eq!(read_register(&mut serial, MSR), DEFAULT_MODEM_STATUS);
+        assert_eq!(read_register(&mut serial, MCR), DEFAULT_MODEM_CONTROL);
     }
 
     #[test]
@@ -489,4 +981,32 @@ mod tests {
         serial.read(serial_bus_address(DATA), &mut data[..]);
         assert_eq!(data[0], b'c');
     }
+
+    #[test]
+    fn serial_injected_input() {
+        let intr_evt = Event::new().unwrap();
+        let (vmm_tube, device_tube) = Tube::pair().unwrap();
+
+        let mut serial = Serial::new(
+            ProtectionType::Unprotected,
+            intr_evt.try_clone().unwrap(),
+            None,
+            None,
+            None,
+            false,
+            Vec::new(),
+        );
+        serial.set_injected_input(device_tube);
+        serial.write(serial_bus_address(IER), &[IER_RECV_BIT]);
+
+        // Reading any register starts the input threads.
+        assert_eq!(read_register(&mut serial, LSR) & LSR_DATA_BIT, 0);
+        vmm_tube.send(&b"hi".to_vec()).unwrap();
+        assert_eq!(intr_evt.read(), Ok(1));
+
+        assert_ne!(read_register(&mut serial, LSR) & LSR_DATA_BIT, 0);
+        assert_eq!(read_register(&mut serial, DATA), b'h');
+        assert_eq!(read_register(&mut serial, DATA), b'i');
+        assert_eq!(read_register(&mut serial, LSR) & LSR_DATA_BIT, 0);
+    }
 }
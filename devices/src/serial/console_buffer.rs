@@ -0,0 +1,245 @@
+// Copyright 2022 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Keeps the most recent output of a serial port in memory, so that it can be dumped over the
+//! control socket after it scrolled past or the output file was lost.
+
+use std::sync::atomic::fence;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::AtomicU8;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use sync::Mutex;
+
+use crate::BusAccessInfo;
+use crate::BusDevice;
+use crate::DeviceId;
+
+struct Ring {
+    bytes: Box<[AtomicU8]>,
+    // Number of bytes the writer started to write, incremented before a byte is overwritten.
+    started: AtomicU64,
+    // Number of bytes completely written.
+    written: AtomicU64,
+}
+
+/// A ring buffer of the last bytes written to a serial port.
+///
+/// Writing never waits for readers, which copy the contents without disturbing them and drop the
+/// bytes overwritten while they were copying. There must be a single writer at a time.
+#[derive(Clone)]
+pub struct ConsoleBuffer {
+    ring: Arc<Ring>,
+}
+
+impl ConsoleBuffer {
+    /// Creates a buffer keeping the last `capacity` bytes, which must not be 0.
+    pub fn new(capacity: usize) -> ConsoleBuffer {
+        assert!(capacity > 0, "console buffer can't be empty");
+        ConsoleBuffer {
+            ring: Arc::new(Ring {
+                bytes: (0..capacity).map(|_| AtomicU8::new(0)).collect(),
+                started: AtomicU64::new(0),
+                written: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.ring.bytes.len()
+    }
+
+    /// Appends `byte`, overwriting the oldest byte once the buffer is full.
+    pub fn push(&self, byte: u8) {
+        let ring = &self.ring;
+        let n = ring.written.load(Ordering::Relaxed);
+        ring.started.store(n + 1, Ordering::Relaxed);
+        // Readers that see the new byte also see that the old one is gone.
+        fence(Ordering::Release);
+        ring.bytes[(n % self.capacity() as u64) as usize].store(byte, Ordering::Relaxed);
+        ring.written.store(n + 1, Ordering::Release);
+    }
+
+    /// Returns the bytes in the buffer, oldest first.
+    pub fn contents(&self) -> Vec<u8> {
+        let ring = &self.ring;
+        let capacity = self.capacity() as u64;
+        let end = ring.written.load(Ordering::Acquire);
+        let start = end.saturating_sub(capacity);
+        let mut contents = (start..end)
+            .map(|i| ring.bytes[(i % capacity) as usize].load(Ordering::Relaxed))
+            .collect::<Vec<u8>>();
+
+        // Drop the bytes the writer may have overwritten while they were copied.
+        fence(Ordering::Acquire);
+        let oldest_intact = ring
+            .started
+            .load(Ordering::Relaxed)
+            .saturating_sub(capacity);
+        let overwritten = oldest_intact
+            .saturating_sub(start)
+            .min(contents.len() as u64);
+        contents.drain(..overwritten as usize);
+        contents
+    }
+
+    /// Returns the number of bytes pushed since the buffer was created.
+    pub fn total(&self) -> u64 {
+        self.ring.written.load(Ordering::Acquire)
+    }
+}
+
+// 8250 UART registers observed to find the bytes transmitted by the guest.
+const UART_DATA: u64 = 0;
+const UART_LCR: u64 = 3;
+const UART_MCR: u64 = 4;
+const UART_LCR_DLAB: u8 = 0x80;
+const UART_MCR_LOOP: u8 = 0x10;
+
+/// Wraps a serial device on the bus to record the bytes transmitted by the guest in a
+/// `ConsoleBuffer`.
+///
+/// Like `SerialOutputMilestone`, the wrapper sits in front of the device on the bus, so the buffer
+/// can be read from this process even when the serial device runs in a sandboxed device process.
+pub struct SerialConsoleRecorder {
+    serial: Arc<Mutex<dyn BusDevice>>,
+    buffer: ConsoleBuffer,
+    // Writes to the data register program the divisor latch while DLAB is set, and go back to the
+    // receiver in loopback mode.
+    dlab: bool,
+    loopback: bool,
+}
+
+impl SerialConsoleRecorder {
+    pub fn new(serial: Arc<Mutex<dyn BusDevice>>, buffer: ConsoleBuffer) -> SerialConsoleRecorder {
+        SerialConsoleRecorder {
+            serial,
+            buffer,
+            dlab: false,
+            loopback: false,
+        }
+    }
+}
+
+impl BusDevice for SerialConsoleRecorder {
+    fn device_id(&self) -> DeviceId {
+        self.serial.lock().device_id()
+    }
+
+    fn debug_label(&self) -> String {
+        self.serial.lock().debug_label()
+    }
+
+    fn read(&mut self, info: BusAccessInfo, data: &mut [u8]) {
+        self.serial.lock().read(info, data)
+    }
+
+    fn write(&mut self, info: BusAccessInfo, data: &[u8]) {
+        if let [value] = data {
+            match info.offset {
+                UART_LCR => self.dlab = value & UART_LCR_DLAB != 0,
+                UART_MCR => self.loopback = value & UART_MCR_LOOP != 0,
+                UART_DATA if !self.dlab && !self.loopback => self.buffer.push(*value),
+                _ => {}
+            }
+        }
+        self.serial.lock().write(info, data)
+    }
+
+    fn destroy_device(&mut self) {
+        self.serial.lock().destroy_device()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::pci::CrosvmDeviceId;
+
+    struct NullDevice;
+
+    impl BusDevice for NullDevice {
+        fn device_id(&self) -> DeviceId {
+            CrosvmDeviceId::Serial.into()
+        }
+
+        fn debug_label(&self) -> String {
+            "null".to_owned()
+        }
+    }
+
+    fn access(offset: u64) -> BusAccessInfo {
+        BusAccessInfo {
+            offset,
+            address: offset,
+            id: 0,
+        }
+    }
+
+    #[test]
+    fn console_buffer_wraparound() {
+        let buffer = ConsoleBuffer::new(4);
+        assert!(buffer.contents().is_empty());
+        for &byte in b"abc" {
+            buffer.push(byte);
+        }
+        assert_eq!(buffer.contents(), b"abc");
+
+        for &byte in b"defghi" {
+            buffer.push(byte);
+        }
+        assert_eq!(buffer.contents(), b"fghi");
+        // Reading doesn't consume the contents.
+        assert_eq!(buffer.contents(), b"fghi");
+        assert_eq!(buffer.total(), 9);
+    }
+
+    #[test]
+    fn console_buffer_concurrent_dump() {
+        const CAPACITY: usize = 64;
+        const BYTES: u64 = 200_000;
+        let buffer = ConsoleBuffer::new(CAPACITY);
+        let writer = {
+            let buffer = buffer.clone();
+            thread::spawn(move || {
+                for i in 0..BYTES {
+                    buffer.push(i as u8);
+                }
+            })
+        };
+
+        // Every dump must be a run of consecutive bytes, however the writer overtakes it.
+        while buffer.total() < BYTES {
+            let contents = buffer.contents();
+            assert!(contents.len() <= CAPACITY);
+            for pair in contents.windows(2) {
+                assert_eq!(pair[1], pair[0].wrapping_add(1));
+            }
+        }
+        writer.join().unwrap();
+        let contents = buffer.contents();
+        assert_eq!(contents.len(), CAPACITY);
+        assert_eq!(*contents.last().unwrap(), (BYTES - 1) as u8);
+    }
+
+    #[test]
+    fn recorder_ignores_divisor_and_loopback_writes() {
+        let buffer = ConsoleBuffer::new(16);
+        let mut serial =
+            SerialConsoleRecorder::new(Arc::new(Mutex::new(NullDevice)), buffer.clone());
+
+        serial.write(access(UART_DATA), &[b'a']);
+        serial.write(access(UART_LCR), &[UART_LCR_DLAB | 0x3]);
+        serial.write(access(UART_DATA), &[0x1]);
+        serial.write(access(UART_LCR), &[0x3]);
+        serial.write(access(UART_MCR), &[UART_MCR_LOOP]);
+        serial.write(access(UART_DATA), &[0x5a]);
+        serial.write(access(UART_MCR), &[0x8]);
+        serial.write(access(UART_DATA), &[b'b']);
+        assert_eq!(buffer.contents(), b"ab");
+    }
+}
@@ -0,0 +1,246 @@
+// Copyright 2022 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Bounded queue between the transmit holding register and the host sink.
+//!
+//! Writing to a slow or stalled sink directly from the vCPU thread stalls the guest, so bytes
+//! written by the guest are handed to a worker thread that owns the sink instead.
+
+use std::io;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU8;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::sync_channel;
+use std::sync::mpsc::Receiver;
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::mpsc::SyncSender;
+use std::sync::mpsc::TryRecvError;
+use std::sync::mpsc::TrySendError;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+use base::error;
+use base::Event;
+use base::FileSync;
+
+use super::IER_THR_BIT;
+
+/// Number of bytes the guest can write ahead of the sink.
+pub(in crate::serial) const OUTPUT_QUEUE_SIZE: usize = 4096;
+
+// How often the sink is synced to disk while output is being written, if it can be.
+const SYNC_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Where the worker writes the guest output, and what it syncs to disk if it can.
+pub(in crate::serial) struct OutputSink {
+    pub out: Box<dyn io::Write + Send>,
+    pub sync: Option<Box<dyn FileSync + Send>>,
+}
+
+/// The vCPU side of the output queue.
+pub(in crate::serial) struct OutputQueue {
+    // `None` once the worker has exited.
+    sender: Option<SyncSender<u8>>,
+    capacity: usize,
+    queued: Arc<AtomicUsize>,
+    stalled: Arc<AtomicBool>,
+    dropped: u64,
+}
+
+impl OutputQueue {
+    /// Spawns a worker thread writing queued bytes to `out`, periodically syncing `sync` if given.
+    ///
+    /// Sinks received from `new_sinks` take the place of the current one, which is synced first,
+    /// before the next bytes are written. This is how the output file is rotated.
+    ///
+    /// While the queue is stalled (see `stall`), the worker signals `interrupt_evt` as soon as
+    /// there is room in the queue again, provided the THR empty interrupt is enabled.
+    ///
+    /// The worker runs detached because writing to the sink can block indefinitely. It exits once
+    /// the queue is dropped and the remaining bytes have been written.
+    pub(in crate::serial) fn spawn(
+        name: String,
+        capacity: usize,
+        out: Box<dyn io::Write + Send>,
+        sync: Option<Box<dyn FileSync + Send>>,
+        new_sinks: Option<Receiver<OutputSink>>,
+        interrupt_enable: Arc<AtomicU8>,
+        interrupt_evt: Event,
+    ) -> io::Result<OutputQueue> {
+        let (sender, receiver) = sync_channel(capacity);
+        let queued = Arc::new(AtomicUsize::new(0));
+        let stalled = Arc::new(AtomicBool::new(false));
+        let mut worker = OutputWorker {
+            receiver,
+            out,
+            sync,
+            new_sinks,
+            queued: queued.clone(),
+            stalled: stalled.clone(),
+            interrupt_enable,
+            interrupt_evt,
+        };
+        thread::Builder::new()
+            .name(name)
+            .spawn(move || worker.run())?;
+        Ok(OutputQueue {
+            sender: Some(sender),
+            capacity,
+            queued,
+            stalled,
+            dropped: 0,
+        })
+    }
+
+    /// Queues `v` without blocking. Returns false and counts the byte as dropped if the queue is
+    /// full.
+    pub(in crate::serial) fn push(&mut self, v: u8) -> bool {
+        let sender = match self.sender.as_ref() {
+            Some(s) => s,
+            None => return true,
+        };
+        // Count the byte before sending it so the worker never sees it go negative.
+        self.queued.fetch_add(1, Ordering::SeqCst);
+        match sender.try_send(v) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                self.queued.fetch_sub(1, Ordering::SeqCst);
+                if self.dropped == 0 {
+                    error!("serial output queue is full, dropping guest output");
+                }
+                self.dropped += 1;
+                false
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                // The worker already logged why it exited, nothing can be written anymore.
+                self.sender = None;
+                true
+            }
+        }
+    }
+
+    pub(in crate::serial) fn is_full(&self) -> bool {
+        self.sender.is_some() && self.queued.load(Ordering::SeqCst) >= self.capacity
+    }
+
+    /// Asks the worker to signal the interrupt once there is room in the queue. Returns whether
+    /// the queue is still full, in which case the caller should report the transmitter as busy.
+    pub(in crate::serial) fn stall(&self) -> bool {
+        self.stalled.store(true, Ordering::SeqCst);
+        // The worker may have made room before it could see the flag.
+        self.is_full()
+    }
+
+    pub(in crate::serial) fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
+
+struct OutputWorker {
+    receiver: Receiver<u8>,
+    out: Box<dyn io::Write + Send>,
+    sync: Option<Box<dyn FileSync + Send>>,
+    new_sinks: Option<Receiver<OutputSink>>,
+    queued: Arc<AtomicUsize>,
+    stalled: Arc<AtomicBool>,
+    interrupt_enable: Arc<AtomicU8>,
+    interrupt_evt: Event,
+}
+
+impl OutputWorker {
+    fn run(&mut self) {
+        let mut buf = Vec::new();
+        let mut unsynced = false;
+        let mut last_sync = Instant::now();
+        loop {
+            match self.receiver.recv_timeout(SYNC_INTERVAL) {
+                Ok(v) => {
+                    buf.push(v);
+                    buf.extend(self.receiver.try_iter());
+                    self.queued.fetch_sub(buf.len(), Ordering::SeqCst);
+                    self.notify_room();
+                    self.replace_sink(&mut unsynced);
+
+                    // Errors are not fatal: some sinks, like non-blocking pipes, fail while the
+                    // other end isn't ready and recover later.
+                    if let Err(e) = self.out.write_all(&buf).and_then(|()| self.out.flush()) {
+                        error!("failed to write serial output: {}", e);
+                    }
+                    buf.clear();
+                    unsynced = true;
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    self.replace_sink(&mut unsynced);
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    if unsynced {
+                        self.sync();
+                    }
+                    return;
+                }
+            }
+
+            if unsynced && last_sync.elapsed() >= SYNC_INTERVAL {
+                self.sync();
+                unsynced = false;
+                last_sync = Instant::now();
+            }
+        }
+    }
+
+    // Switches to the latest sink received from `new_sinks`, if any, syncing the current one
+    // first if it has `unsynced` output.
+    //
+    // Bytes the guest wrote just before the sink was replaced may go to either sink, but none of
+    // them are lost.
+    fn replace_sink(&mut self, unsynced: &mut bool) {
+        let receiver = match self.new_sinks.as_ref() {
+            Some(r) => r,
+            None => return,
+        };
+        let mut sink = None;
+        loop {
+            match receiver.try_recv() {
+                Ok(s) => sink = Some(s),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.new_sinks = None;
+                    break;
+                }
+            }
+        }
+        let sink = match sink {
+            Some(s) => s,
+            None => return,
+        };
+        if *unsynced {
+            self.sync();
+            *unsynced = false;
+        }
+        self.out = sink.out;
+        self.sync = sink.sync;
+    }
+
+    fn notify_room(&self) {
+        if self.stalled.swap(false, Ordering::SeqCst)
+            && (self.interrupt_enable.load(Ordering::SeqCst) & IER_THR_BIT) != 0
+        {
+            if let Err(e) = self.interrupt_evt.write(1) {
+                error!("failed to signal serial output queue room: {}", e);
+            }
+        }
+    }
+
+    fn sync(&mut self) {
+        if let Some(sync) = self.sync.as_mut() {
+            if let Err(e) = sync.fsync() {
+                error!("failed to fsync serial device, no longer syncing: {}", e);
+                self.sync = None;
+            }
+        }
+    }
+}
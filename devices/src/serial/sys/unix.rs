@@ -2,14 +2,15 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
+use std::fs::File;
 use std::io;
 
 use base::Event;
 use base::FileSync;
 use base::RawDescriptor;
-use base::Result;
 use hypervisor::ProtectionType;
 
+use crate::serial::output::OutputSink;
 use crate::serial_device::SerialInput;
 use crate::sys::serial_device::SerialDevice;
 use crate::Serial;
@@ -36,11 +37,20 @@ impl SerialDevice for Serial {
 }
 
 impl Serial {
-    pub(in crate::serial) fn system_handle_write(&mut self, v: u8) -> Result<()> {
-        if let Some(out) = self.out.as_mut() {
-            out.write_all(&[v])?;
-            out.flush()?;
+    pub(in crate::serial) fn take_output_sync(&mut self) -> Option<Box<dyn FileSync + Send>> {
+        None
+    }
+
+    // Makes the sink of a reopened output file, which is used as is, like the file given to
+    // `new`.
+    pub(in crate::serial) fn output_sink_fn(
+        &self,
+    ) -> impl Fn(File) -> io::Result<OutputSink> + Send + 'static {
+        |file| {
+            Ok(OutputSink {
+                out: Box::new(file),
+                sync: None,
+            })
         }
-        Ok(())
     }
 }
@@ -2,23 +2,17 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
+use std::fs::File;
 use std::io;
 use std::io::Write;
-use std::thread;
-use std::thread::JoinHandle;
-use std::time::Duration;
 
-use base::error;
 use base::named_pipes::PipeConnection;
 use base::Event;
-use base::EventToken;
 use base::FileSync;
 use base::RawDescriptor;
-use base::Result;
-use base::WaitContext;
 use hypervisor::ProtectionType;
 
-use crate::bus::BusDevice;
+use crate::serial::output::OutputSink;
 use crate::serial_device::SerialInput;
 use crate::sys::serial_device::SerialDevice;
 use crate::Serial;
@@ -29,84 +23,85 @@ pub(crate) type InStreamType = Box<PipeConnection>;
 
 const TIMESTAMP_PREFIX_FMT: &str = "[ %F %T%.9f ]: ";
 
-pub enum LineState {
-    NeverWritten,
-    Midline,
-    Newline,
-}
-
 /// Windows specific paramters for the serial device.
 pub struct SystemSerialParams {
-    pub out_timestamp: bool,
-    pub out_line_state: LineState,
     pub in_stream: Option<InStreamType>,
+    /// Synced periodically by the output thread once it is spawned.
     pub sync: Option<Box<dyn FileSync + Send>>,
-    pub sync_thread: Option<JoinHandle<SyncWorker>>,
-    pub kill_evt: Option<Event>,
+    /// Whether the lines of the output are prefixed with a timestamp.
+    pub out_timestamp: bool,
 }
 
 impl Serial {
-    // Spawn the worker thread if it hasn't been spawned yet.
-    pub(in crate::serial) fn handle_sync_thread(&mut self) {
-        if self.system_params.sync.is_some() {
-            let sync = match self.system_params.sync.take() {
-                Some(sync) => sync,
-                None => return,
-            };
+    pub(in crate::serial) fn take_output_sync(&mut self) -> Option<Box<dyn FileSync + Send>> {
+        self.system_params.sync.take()
+    }
 
-            let (self_kill_evt, kill_evt) = match Event::new().and_then(|e| Ok((e.try_clone()?, e)))
-            {
-                Ok(v) => v,
-                Err(e) => {
-                    error!("failed creating kill Event pair: {}", e);
-                    return;
-                }
-            };
-            self.system_params.kill_evt = Some(self_kill_evt);
-
-            match thread::Builder::new()
-                .name(format!("{} sync thread", self.debug_label()))
-                .spawn(move || {
-                    let mut worker = SyncWorker {
-                        kill_evt,
-                        file: sync,
-                    };
-                    worker.run();
-                    worker
-                }) {
-                Err(e) => {
-                    error!("failed to spawn sync thread: {}", e);
-                }
-                Ok(sync_thread) => self.system_params.sync_thread = Some(sync_thread),
+    // Makes the sink of a reopened output file, timestamped and synced like the file given to
+    // `new`.
+    pub(in crate::serial) fn output_sink_fn(
+        &self,
+    ) -> impl Fn(File) -> io::Result<OutputSink> + Send + 'static {
+        let out_timestamp = self.system_params.out_timestamp;
+        move |file| {
+            let sync = file.try_clone()?;
+            let out: Box<dyn io::Write + Send> = if out_timestamp {
+                Box::new(TimestampWriter::new(Box::new(file)))
+            } else {
+                Box::new(file)
             };
+            Ok(OutputSink {
+                out,
+                sync: Some(Box::new(sync)),
+            })
         }
     }
+}
 
-    pub(in crate::serial) fn system_handle_write(&mut self, v: u8) -> Result<()> {
-        if let Some(out) = self.out.as_mut() {
-            if self.system_params.out_timestamp {
-                match self.system_params.out_line_state {
-                    LineState::NeverWritten | LineState::Newline => {
-                        out.write_all(
-                            chrono::Local::now()
-                                .format(TIMESTAMP_PREFIX_FMT)
-                                .to_string()
-                                .as_bytes(),
-                        )
-                        .expect("Failed to write");
-                        self.system_params.out_line_state = LineState::Midline;
-                    }
-                    LineState::Midline if v == b'\n' => {
-                        self.system_params.out_line_state = LineState::Newline;
-                    }
-                    _ => {}
-                }
-            }
+/// Prefixes every line written to `out` with a timestamp.
+///
+/// Used behind the output queue, so the timestamps record when the output thread wrote the line
+/// rather than when the guest did.
+struct TimestampWriter {
+    out: Box<dyn io::Write + Send>,
+    at_line_start: bool,
+}
+
+impl TimestampWriter {
+    fn new(out: Box<dyn io::Write + Send>) -> TimestampWriter {
+        TimestampWriter {
+            out,
+            at_line_start: true,
+        }
+    }
+}
 
-            out.write_all(&[v])?;
-            out.flush()?;
+impl io::Write for TimestampWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.at_line_start {
+            self.out.write_all(
+                chrono::Local::now()
+                    .format(TIMESTAMP_PREFIX_FMT)
+                    .to_string()
+                    .as_bytes(),
+            )?;
+            self.at_line_start = false;
         }
-        Ok(())
+        // Write up to the end of the line so the next line gets its own timestamp.
+        let len = buf
+            .iter()
+            .position(|&b| b == b'\n')
+            .map_or(buf.len(), |i| i + 1);
+        self.out.write_all(&buf[..len])?;
+        self.at_line_start = buf[len - 1] == b'\n';
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
     }
 }
 
@@ -124,12 +119,14 @@ impl SerialDevice for Serial {
         _keep_rds: Vec<RawDescriptor>,
     ) -> Serial {
         let system_params = SystemSerialParams {
-            out_timestamp,
-            out_line_state: LineState::NeverWritten,
             in_stream: None,
             sync,
-            sync_thread: None,
-            kill_evt: None,
+            out_timestamp,
+        };
+        let out = if out_timestamp {
+            out.map(|out| Box::new(TimestampWriter::new(out)) as Box<dyn io::Write + Send>)
+        } else {
+            out
         };
         Serial::new_common(interrupt_evt, input, out, system_params)
     }
@@ -146,97 +143,14 @@ impl SerialDevice for Serial {
         _keep_rds: Vec<RawDescriptor>,
     ) -> Serial {
         let system_params = SystemSerialParams {
-            out_timestamp: false,
-            out_line_state: LineState::NeverWritten,
             in_stream: Some(Box::new(pipe_in)),
             sync: None,
-            sync_thread: None,
-            kill_evt: None,
+            out_timestamp: false,
         };
         Serial::new_common(interrupt_evt, None, Some(Box::new(pipe_out)), system_params)
     }
 }
 
-impl Drop for Serial {
-    fn drop(&mut self) {
-        if let Some(kill_evt) = self.system_params.kill_evt.take() {
-            // Ignore the result because there is nothing we can do about it.
-            let _ = kill_evt.write(1);
-        }
-
-        if let Some(sync_thread) = self.system_params.sync_thread.take() {
-            let _ = sync_thread.join();
-        }
-    }
-}
-
-/// Worker to help with flusing contents of `file` to disk.
-pub struct SyncWorker {
-    kill_evt: Event,
-    file: Box<dyn FileSync + Send>,
-}
-
-impl SyncWorker {
-    pub(in crate::serial) fn run(&mut self) {
-        let mut timer = match base::Timer::new() {
-            Err(e) => {
-                error!("failed to create timer for SyncWorker: {}", e);
-                return;
-            }
-            Ok(timer) => timer,
-        };
-
-        if let Err(e) = timer.reset(Duration::from_secs(1), Some(Duration::from_secs(1))) {
-            error!("failed to set timer for SyncWorker: {}", e);
-            return;
-        }
-
-        #[derive(EventToken)]
-        enum Token {
-            Sync,
-            Kill,
-        }
-
-        let wait_ctx: WaitContext<Token> = match WaitContext::build_with(&[
-            (&timer, Token::Sync),
-            (&self.kill_evt, Token::Kill),
-        ]) {
-            Ok(ec) => ec,
-            Err(e) => {
-                error!("failed creating WaitContext: {}", e);
-                return;
-            }
-        };
-        loop {
-            let events = match wait_ctx.wait() {
-                Ok(v) => v,
-                Err(e) => {
-                    error!("failed polling for events: {}", e);
-                    return;
-                }
-            };
-
-            for event in events.iter().filter(|e| e.is_readable) {
-                match event.token {
-                    Token::Sync => {
-                        if let Err(e) = self.file.fsync() {
-                            error!("failed to fsync serial device, stopping sync thread: {}", e);
-                            return;
-                        }
-                    }
-                    Token::Kill => {
-                        if let Err(e) = self.file.fsync() {
-                            error!("failed to fsync serial device, stopping sync thread: {}", e);
-                            return;
-                        }
-                        return;
-                    }
-                }
-            }
-        }
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use regex::Regex;
@@ -270,16 +184,19 @@ mod tests {
 
         serial.write(serial_bus_address(DATA), &[b'a']);
         serial.write(serial_bus_address(DATA), &[b'\n']);
+        serial_out.wait_for(|buf| buf.ends_with(b"\n"));
         assert_timestamp_is_present(serial_out.buf.lock().as_slice(), "a");
         serial_out.buf.lock().clear();
 
         serial.write(serial_bus_address(DATA), &[b'b']);
         serial.write(serial_bus_address(DATA), &[b'\n']);
+        serial_out.wait_for(|buf| buf.ends_with(b"\n"));
         assert_timestamp_is_present(serial_out.buf.lock().as_slice(), "b");
         serial_out.buf.lock().clear();
 
         serial.write(serial_bus_address(DATA), &[b'c']);
         serial.write(serial_bus_address(DATA), &[b'\n']);
+        serial_out.wait_for(|buf| buf.ends_with(b"\n"));
         assert_timestamp_is_present(serial_out.buf.lock().as_slice(), "c");
         serial_out.buf.lock().clear();
     }
@@ -330,7 +247,11 @@ mod tests {
 
             let mut read_buf: [u8; 2] = [0; 2];
 
-            assert_eq!(client_pipe.read(&mut read_buf).unwrap(), 2);
+            // The output thread may write the bytes separately.
+            let mut read = 0;
+            while read < read_buf.len() {
+                read += client_pipe.read(&mut read_buf[read..]).unwrap();
+            }
             assert_eq!(read_buf, [b'T', b'D']);
 
             // Check that pipe_in is the other end of client_pipe. It's not actually wired up to
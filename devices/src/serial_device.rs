@@ -10,6 +10,8 @@ use std::io;
 use std::io::stdin;
 use std::io::stdout;
 use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
 
 use base::error;
 use base::open_file;
@@ -19,6 +21,7 @@ use base::syslog;
 use base::AsRawDescriptor;
 use base::Event;
 use base::FileSync;
+use base::RateLimiter;
 use base::RawDescriptor;
 use base::ReadNotifier;
 use hypervisor::ProtectionType;
@@ -60,6 +63,56 @@ impl SerialInput for File {}
 #[cfg(windows)]
 impl SerialInput for WinConsole {}
 
+// Bytes that paced input lets through back to back, like the receive FIFO of a 16550.
+const PACED_INPUT_BURST: u64 = 16;
+
+/// Input passed on at no more than a given rate, for guests that lose input arriving faster than
+/// a real UART would deliver it.
+struct PacedInput {
+    input: Box<dyn SerialInput>,
+    limiter: RateLimiter,
+}
+
+impl PacedInput {
+    fn new(input: Box<dyn SerialInput>, bytes_per_sec: u32) -> PacedInput {
+        PacedInput {
+            input,
+            limiter: RateLimiter::new(
+                PACED_INPUT_BURST,
+                bytes_per_sec as u64,
+                Duration::from_secs(1),
+            ),
+        }
+    }
+}
+
+impl io::Read for PacedInput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let mut available = self.limiter.available();
+        while available == 0 {
+            if let Some(delay) = self.limiter.time_until_available(1) {
+                thread::sleep(delay);
+            }
+            available = self.limiter.available();
+        }
+        let len = buf.len().min(available as usize);
+        let read = self.input.read(&mut buf[..len])?;
+        self.limiter.try_acquire(read as u64);
+        Ok(read)
+    }
+}
+
+impl ReadNotifier for PacedInput {
+    fn get_read_notifier(&self) -> &dyn AsRawDescriptor {
+        self.input.get_read_notifier()
+    }
+}
+
+impl SerialInput for PacedInput {}
+
 /// Enum for possible type of serial devices
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 #[serde(rename_all = "kebab-case")]
@@ -120,6 +173,24 @@ impl Display for SerialHardware {
     }
 }
 
+/// What an 8250 UART does with guest output when its output queue is full because the sink is
+/// slower than the guest.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SerialOutputPolicy {
+    /// Drop the output and count the dropped bytes.
+    Drop,
+    /// Report the transmitter as busy until the queue drains, so the guest driver throttles
+    /// itself.
+    FlowControl,
+}
+
+impl Default for SerialOutputPolicy {
+    fn default() -> Self {
+        Self::Drop
+    }
+}
+
 fn serial_parameters_default_num() -> u8 {
     1
 }
@@ -137,14 +208,18 @@ pub struct SerialParameters {
     pub hardware: SerialHardware,
     pub path: Option<PathBuf>,
     pub input: Option<PathBuf>,
+    pub input_rate: Option<u32>,
     #[serde(default = "serial_parameters_default_num")]
     pub num: u8,
     pub console: bool,
     pub earlycon: bool,
     pub stdin: bool,
     pub out_timestamp: bool,
+    pub output_policy: SerialOutputPolicy,
     #[serde(default = "serial_parameters_default_debugcon_port")]
     pub debugcon_port: u16,
+    pub console_buffer: usize,
+    pub watch_resize: bool,
 }
 
 impl SerialParameters {
@@ -176,6 +251,12 @@ impl SerialParameters {
         } else {
             None
         };
+        let input = match (input, self.input_rate) {
+            (Some(input), Some(rate)) if rate > 0 => {
+                Some(Box::new(PacedInput::new(input, rate)) as Box<dyn SerialInput>)
+            }
+            (input, _) => input,
+        };
         let (output, sync): (
             Option<Box<dyn io::Write + Send>>,
             Option<Box<dyn FileSync + Send>>,
@@ -248,12 +329,16 @@ mod tests {
                 hardware: SerialHardware::Serial,
                 path: None,
                 input: None,
+                input_rate: None,
                 num: 1,
                 console: false,
                 earlycon: false,
                 stdin: false,
                 out_timestamp: false,
+                output_policy: SerialOutputPolicy::Drop,
                 debugcon_port: 0x402,
+                console_buffer: 0,
+                watch_resize: false,
             }
         );
 
@@ -337,12 +422,34 @@ mod tests {
         let params = from_serial_arg("out_timestamp=foobar");
         assert!(params.is_err());
 
+        // output_policy parameter
+        let params = from_serial_arg("output_policy=drop").unwrap();
+        assert_eq!(params.output_policy, SerialOutputPolicy::Drop);
+        let params = from_serial_arg("output_policy=flow-control").unwrap();
+        assert_eq!(params.output_policy, SerialOutputPolicy::FlowControl);
+        let params = from_serial_arg("output_policy=foobar");
+        assert!(params.is_err());
+
         // debugcon port parameter
         let params = from_serial_arg("debugcon_port=1026").unwrap();
         assert_eq!(params.debugcon_port, 1026);
 
+        // console_buffer parameter
+        let params = from_serial_arg("console_buffer=65536").unwrap();
+        assert_eq!(params.console_buffer, 65536);
+        let params = from_serial_arg("console_buffer=-1");
+        assert!(params.is_err());
+
+        // watch_resize parameter
+        let params = from_serial_arg("watch_resize").unwrap();
+        assert!(params.watch_resize);
+        let params = from_serial_arg("watch_resize=false").unwrap();
+        assert!(!params.watch_resize);
+        let params = from_serial_arg("watch_resize=foobar");
+        assert!(params.is_err());
+
         // all together
-        let params = from_serial_arg("type=stdout,path=/some/path,hardware=virtio-console,num=5,earlycon,console,stdin,input=/some/input,out_timestamp,debugcon_port=12").unwrap();
+        let params = from_serial_arg("type=stdout,path=/some/path,hardware=virtio-console,num=5,earlycon,console,stdin,input=/some/input,input_rate=960,out_timestamp,output_policy=flow-control,debugcon_port=12,console_buffer=4096,watch_resize").unwrap();
         assert_eq!(
             params,
             SerialParameters {
@@ -350,12 +457,16 @@ mod tests {
                 hardware: SerialHardware::VirtioConsole,
                 path: Some("/some/path".into()),
                 input: Some("/some/input".into()),
+                input_rate: Some(960),
                 num: 5,
                 console: true,
                 earlycon: true,
                 stdin: true,
                 out_timestamp: true,
+                output_policy: SerialOutputPolicy::FlowControl,
                 debugcon_port: 12,
+                console_buffer: 4096,
+                watch_resize: true,
             }
         );
 
@@ -363,4 +474,25 @@ mod tests {
         let params = from_serial_arg("type=stdout,foo=bar");
         assert!(params.is_err());
     }
+
+    #[test]
+    fn paced_input() {
+        let mut file = tempfile::tempfile().unwrap();
+        io::Write::write_all(&mut file, &[0u8; 48]).unwrap();
+        io::Seek::rewind(&mut file).unwrap();
+
+        // One byte per ms after the first burst.
+        let mut input = PacedInput::new(Box::new(file), 1000);
+        let mut buf = [0u8; 64];
+        let start = std::time::Instant::now();
+        assert_eq!(
+            io::Read::read(&mut input, &mut buf).unwrap(),
+            PACED_INPUT_BURST as usize
+        );
+        let mut total = PACED_INPUT_BURST as usize;
+        while total < 48 {
+            total += io::Read::read(&mut input, &mut buf).unwrap();
+        }
+        assert!(start.elapsed() >= Duration::from_millis(32));
+    }
 }
@@ -3,22 +3,28 @@
 // found in the LICENSE file.
 
 use std::borrow::Cow;
+use std::fs::File;
 use std::fs::OpenOptions;
 use std::io;
 use std::io::ErrorKind;
 use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
 use std::os::unix::net::UnixDatagram;
 use std::path::Path;
 use std::path::PathBuf;
 use std::thread;
 use std::time::Duration;
 
+use base::clone_descriptor;
 use base::error;
 use base::info;
+use base::open_file;
 use base::read_raw_stdin;
+use base::terminal_size;
 use base::AsRawDescriptor;
 use base::Event;
 use base::FileSync;
+use base::FromRawDescriptor;
 use base::RawDescriptor;
 use base::ReadNotifier;
 use hypervisor::ProtectionType;
@@ -26,6 +32,7 @@ use hypervisor::ProtectionType;
 use crate::serial_device::Error;
 use crate::serial_device::SerialInput;
 use crate::serial_device::SerialParameters;
+use crate::serial_device::SerialType;
 
 pub const SYSTEM_SERIAL_TYPE_NAME: &str = "UnixSocket";
 
@@ -53,6 +60,33 @@ impl ReadNotifier for ConsoleInput {
 
 impl SerialInput for ConsoleInput {}
 
+impl SerialParameters {
+    /// Opens the terminal the output of the device is written to, e.g. a PTY, to follow its size
+    /// with `watch_resize`. Fails if the output isn't a terminal.
+    pub fn open_output_terminal(&self) -> io::Result<File> {
+        let terminal = match (&self.type_, &self.path) {
+            (SerialType::Stdout, _) => {
+                let fd = clone_descriptor(&io::stdout())?;
+                // Safe because the descriptor was just duplicated and nothing else owns it.
+                unsafe { File::from_raw_descriptor(fd) }
+            }
+            // Don't make the terminal the controlling terminal of crosvm if it has none.
+            (SerialType::File, Some(path)) => open_file(
+                path,
+                OpenOptions::new().read(true).custom_flags(libc::O_NOCTTY),
+            )?,
+            (type_, _) => {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("type {} has no terminal output", type_),
+                ))
+            }
+        };
+        terminal_size(&terminal)?;
+        Ok(terminal)
+    }
+}
+
 /// Abstraction over serial-like devices that can be created given an event and optional input and
 /// output streams.
 pub trait SerialDevice {
@@ -4,6 +4,7 @@
 
 mod sys;
 
+use std::cell::RefCell;
 use std::collections::VecDeque;
 use std::sync::Arc;
 use std::thread;
@@ -96,12 +97,25 @@ pub enum BalloonFeatures {
 const VIRTIO_BALLOON_F_RESPONSIVE_DEVICE: u32 = 6; // Device actively watching guest memory
 const VIRTIO_BALLOON_F_EVENTS_VQ: u32 = 7; // Event vq is enabled
 
-// virtio_balloon_config is the balloon device configuration space defined by the virtio spec.
+// crosvm extension: the driver takes the pages to inflate from the guest memory region in
+// `virtio_balloon_config::preferred_region` when it can.
+const VIRTIO_BALLOON_F_PREFERRED_REGION: u32 = 8;
+
+// Value of `virtio_balloon_config::preferred_region` when any region will do.
+const VIRTIO_BALLOON_NO_PREFERRED_REGION: u32 = u32::MAX;
+
+// virtio_balloon_config is the balloon device configuration space defined by the virtio spec,
+// followed by the fields of crosvm extensions.
 #[derive(Copy, Clone, Debug, Default)]
 #[repr(C)]
 struct virtio_balloon_config {
     num_pages: Le32,
     actual: Le32,
+    // Only used with VIRTIO_BALLOON_F_FREE_PAGE_HINT and VIRTIO_BALLOON_F_PAGE_POISON, which this
+    // device doesn't offer.
+    free_page_hint_cmd_id: Le32,
+    poison_val: Le32,
+    preferred_region: Le32,
 }
 
 // Safe because it only has data and has no implicit padding.
@@ -116,6 +130,58 @@ struct BalloonState {
     // is set by an Adjust command that has allow_failure set, and is cleared when the
     // Adjusted success/failure response is sent.
     failable_update: bool,
+    // Index of the guest memory region the driver should inflate from, set by an
+    // AdjustWithPreference command and cleared by an Adjust command.
+    preferred_region: Option<usize>,
+}
+
+// Pages in the balloon from each guest memory region, in the order of `GuestMemory::regions`.
+struct InflatedRegions {
+    // Guest address and size of each region.
+    regions: Vec<(GuestAddress, u64)>,
+    pages: Vec<u64>,
+}
+
+impl InflatedRegions {
+    fn new(mem: &GuestMemory) -> InflatedRegions {
+        let regions: Vec<_> = mem
+            .regions()
+            .map(|region| (region.guest_addr, region.size as u64))
+            .collect();
+        InflatedRegions {
+            pages: vec![0; regions.len()],
+            regions,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.regions.len()
+    }
+
+    // Accounts for the pages of the range at `guest_address` that was inflated or deflated.
+    fn update(&mut self, guest_address: GuestAddress, len: u64, inflated: bool) {
+        let range_end = guest_address.offset().saturating_add(len);
+        for ((start, size), pages) in self.regions.iter().zip(self.pages.iter_mut()) {
+            let region_end = start.offset().saturating_add(*size);
+            let overlap = range_end
+                .min(region_end)
+                .saturating_sub(guest_address.offset().max(start.offset()));
+            let overlap_pages = overlap / VIRTIO_BALLOON_PF_SIZE;
+            if inflated {
+                *pages += overlap_pages;
+            } else {
+                // The driver may deflate pages that it inflated before the device was activated.
+                *pages = pages.saturating_sub(overlap_pages);
+            }
+        }
+    }
+
+    fn bytes(&self) -> Vec<u64> {
+        self.pages
+            .iter()
+            .map(|pages| pages << VIRTIO_BALLOON_PFN_SHIFT)
+            .collect()
+    }
 }
 
 // The constants defining stats types in virtio_baloon_stat
@@ -379,6 +445,7 @@ async fn handle_stats_queue(
     mut stats_rx: mpsc::Receiver<u64>,
     command_tube: &AsyncTube,
     state: Arc<AsyncMutex<BalloonState>>,
+    inflated_regions: &RefCell<InflatedRegions>,
     interrupt: Interrupt,
 ) {
     // Consume the first stats buffer sent from the guest at startup. It was not
@@ -419,7 +486,8 @@ async fn handle_stats_queue(
                 continue;
             }
         };
-        let stats = parse_balloon_stats(&mut reader);
+        let mut stats = parse_balloon_stats(&mut reader);
+        stats.inflated_bytes_per_region = inflated_regions.borrow().bytes();
 
         let actual_pages = state.lock().await.actual_pages as u64;
         let result = BalloonTubeResult::Stats {
@@ -493,6 +561,23 @@ async fn handle_events_queue(
     }
 }
 
+// Returns the region the driver should inflate from for an AdjustWithPreference command, or None
+// to fall back to a plain adjustment if the region doesn't exist or the driver can't honor it.
+fn preferred_region(index: usize, num_regions: usize, acked_features: u64) -> Option<usize> {
+    if index >= num_regions {
+        warn!(
+            "balloon: no guest memory region {}, adjusting without preference",
+            index
+        );
+        None
+    } else if acked_features & (1 << VIRTIO_BALLOON_F_PREFERRED_REGION) == 0 {
+        warn!("balloon: driver ignores preferred regions, adjusting without one");
+        None
+    } else {
+        Some(index)
+    }
+}
+
 // Async task that handles the command socket. The command socket handles messages from the host
 // requesting that the guest balloon be adjusted or to report guest memory statistics.
 async fn handle_command_tube(
@@ -500,6 +585,8 @@ async fn handle_command_tube(
     interrupt: Interrupt,
     state: Arc<AsyncMutex<BalloonState>>,
     mut stats_tx: mpsc::Sender<u64>,
+    num_regions: usize,
+    acked_features: u64,
 ) -> Result<()> {
     loop {
         match command_tube.next().await {
@@ -512,6 +599,7 @@ async fn handle_command_tube(
                     let mut state = state.lock().await;
 
                     state.num_pages = num_pages;
+                    state.preferred_region = None;
                     interrupt.signal_config_changed();
 
                     if allow_failure {
@@ -523,6 +611,18 @@ async fn handle_command_tube(
                         }
                     }
                 }
+                BalloonTubeCommand::AdjustWithPreference {
+                    num_bytes,
+                    preferred_region_index,
+                } => {
+                    let num_pages = (num_bytes >> VIRTIO_BALLOON_PFN_SHIFT) as u32;
+                    let mut state = state.lock().await;
+
+                    state.num_pages = num_pages;
+                    state.preferred_region =
+                        preferred_region(preferred_region_index, num_regions, acked_features);
+                    interrupt.signal_config_changed();
+                }
                 BalloonTubeCommand::Stats { id } => {
                     if let Err(e) = stats_tx.try_send(id) {
                         error!("failed to signal the stat handler: {}", e);
@@ -558,6 +658,8 @@ fn run_worker(
         .map(|e| EventAsync::new(e, &ex).expect("failed to create async event"))
         .collect();
     let mut queues = VecDeque::from(queues);
+    let inflated_regions = RefCell::new(InflatedRegions::new(&mem));
+    let num_regions = inflated_regions.borrow().len();
 
     // We need a block to release all references to command_tube at the end before returning it.
     {
@@ -576,7 +678,10 @@ fn run_worker(
                     &dynamic_mapping_tube,
                     #[cfg(unix)]
                     &mem,
-                )
+                );
+                inflated_regions
+                    .borrow_mut()
+                    .update(guest_address, len, true);
             },
         );
         pin_mut!(inflate);
@@ -594,7 +699,10 @@ fn run_worker(
                     len,
                     #[cfg(windows)]
                     &dynamic_mapping_tube,
-                )
+                );
+                inflated_regions
+                    .borrow_mut()
+                    .update(guest_address, len, false);
             },
         );
         pin_mut!(deflate);
@@ -611,6 +719,7 @@ fn run_worker(
                 stats_rx,
                 &command_tube,
                 state.clone(),
+                &inflated_regions,
                 interrupt.clone(),
             )
             .left_future()
@@ -645,8 +754,14 @@ fn run_worker(
         pin_mut!(reporting);
 
         // Future to handle command messages that resize the balloon.
-        let command =
-            handle_command_tube(&command_tube, interrupt.clone(), state.clone(), stats_tx);
+        let command = handle_command_tube(
+            &command_tube,
+            interrupt.clone(),
+            state.clone(),
+            stats_tx,
+            num_regions,
+            acked_features,
+        );
         pin_mut!(command);
 
         // Process any requests to resample the irq value.
@@ -727,6 +842,7 @@ impl Balloon {
             | 1 << VIRTIO_BALLOON_F_MUST_TELL_HOST
             | 1 << VIRTIO_BALLOON_F_STATS_VQ
             | 1 << VIRTIO_BALLOON_F_EVENTS_VQ
+            | 1 << VIRTIO_BALLOON_F_PREFERRED_REGION
             | enabled_features
             | if mode == BalloonMode::Strict {
                 1 << VIRTIO_BALLOON_F_RESPONSIVE_DEVICE
@@ -743,6 +859,7 @@ impl Balloon {
                 num_pages: (init_balloon_size >> VIRTIO_BALLOON_PFN_SHIFT) as u32,
                 actual_pages: 0,
                 failable_update: false,
+                preferred_region: None,
             })),
             kill_evt: None,
             worker_thread: None,
@@ -756,6 +873,11 @@ impl Balloon {
         virtio_balloon_config {
             num_pages: state.num_pages.into(),
             actual: state.actual_pages.into(),
+            preferred_region: state
+                .preferred_region
+                .map_or(VIRTIO_BALLOON_NO_PREFERRED_REGION, |index| index as u32)
+                .into(),
+            ..Default::default()
         }
     }
 
@@ -959,6 +1081,36 @@ mod tests {
         );
     }
 
+    #[test]
+    fn inflated_regions_accounting() {
+        let memory =
+            GuestMemory::new(&[(GuestAddress(0x0), 0x4000), (GuestAddress(0x4000), 0x8000)])
+                .unwrap();
+        let mut inflated = InflatedRegions::new(&memory);
+        assert_eq!(inflated.bytes(), vec![0, 0]);
+
+        inflated.update(GuestAddress(0x1000), 0x1000, true);
+        // A range of consecutive pages spanning both regions.
+        inflated.update(GuestAddress(0x3000), 0x3000, true);
+        assert_eq!(inflated.bytes(), vec![0x2000, 0x2000]);
+
+        inflated.update(GuestAddress(0x3000), 0x2000, false);
+        assert_eq!(inflated.bytes(), vec![0x1000, 0x1000]);
+
+        // Pages the device didn't see inflated don't make the count wrap around.
+        inflated.update(GuestAddress(0x8000), 0x4000, false);
+        assert_eq!(inflated.bytes(), vec![0x1000, 0]);
+    }
+
+    #[test]
+    fn preferred_region_fallback() {
+        let acked = 1 << VIRTIO_BALLOON_F_PREFERRED_REGION;
+        assert_eq!(preferred_region(1, 2, acked), Some(1));
+        // The driver didn't negotiate the extension, so the adjustment ignores the preference.
+        assert_eq!(preferred_region(1, 2, 1 << VIRTIO_BALLOON_F_STATS_VQ), None);
+        assert_eq!(preferred_region(2, 2, acked), None);
+    }
+
     #[test]
     fn num_expected_queues() {
         let to_feature_bits =
@@ -15,6 +15,7 @@ use std::io::Read;
 use std::io::Write;
 use std::ops::DerefMut;
 use std::result;
+use std::slice;
 use std::sync::Arc;
 use std::thread;
 
@@ -32,14 +33,15 @@ use hypervisor::ProtectionType;
 use remain::sorted;
 use sync::Mutex;
 use thiserror::Error as ThisError;
+use vm_memory::GuestAddress;
 use vm_memory::GuestMemory;
 
 use crate::virtio::base_features;
 use crate::virtio::copy_config;
+use crate::virtio::DescriptorChain;
 use crate::virtio::DeviceType;
 use crate::virtio::Interrupt;
 use crate::virtio::Queue;
-use crate::virtio::Reader;
 use crate::virtio::SignalableInterrupt;
 use crate::virtio::VirtioDevice;
 use crate::virtio::Writer;
@@ -123,19 +125,58 @@ fn handle_input<I: SignalableInterrupt>(
     }
 }
 
-/// Writes the available data from the reader into the given output queue.
+/// Writes `len` bytes of guest memory starting at `addr` to `output`.
+///
+/// The bytes are written straight out of guest memory when they are all in the same region of it,
+/// and only copied into an intermediate buffer when they span several regions.
+fn write_guest_memory(
+    mem: &GuestMemory,
+    addr: GuestAddress,
+    len: usize,
+    output: &mut dyn io::Write,
+) -> io::Result<()> {
+    if let Ok(buf) = mem.get_slice_at_addr(addr, len) {
+        // Safe because the slice is valid guest memory for as long as `mem` is borrowed. The guest
+        // may change the bytes while they are written, but that only changes what is written.
+        let data = unsafe { slice::from_raw_parts(buf.as_ptr(), buf.size()) };
+        return output.write_all(data);
+    }
+
+    let mut data = vec![0u8; len];
+    let mut copied = 0;
+    while copied < len {
+        let region_addr = addr
+            .checked_add(copied as u64)
+            .ok_or_else(|| io::Error::from(io::ErrorKind::InvalidInput))?;
+        match mem.read_at_addr(&mut data[copied..], region_addr) {
+            Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
+            Ok(n) => copied += n,
+            Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidInput, e)),
+        }
+    }
+    output.write_all(&data)
+}
+
+/// Writes the readable buffers of the descriptor chain into the given output sink.
 ///
 /// # Arguments
 ///
-/// * `reader` - The Reader with the data we want to write.
+/// * `mem` - The GuestMemory to take the data from
+/// * `desc_chain` - The descriptor chain with the data we want to write.
 /// * `output` - The output sink we are going to write the data to.
-fn process_transmit_request(mut reader: Reader, output: &mut dyn io::Write) -> io::Result<()> {
-    let len = reader.available_bytes();
-    let mut data = vec![0u8; len];
-    reader.read_exact(&mut data)?;
-    output.write_all(&data)?;
-    output.flush()?;
-    Ok(())
+fn process_transmit_request(
+    mem: &GuestMemory,
+    desc_chain: DescriptorChain,
+    output: &mut dyn io::Write,
+) -> io::Result<()> {
+    for desc in desc_chain.into_iter().readable() {
+        // Regions exported by the IOMMU must stay exported while they are accessed.
+        let (regions, _exported_region) = desc.into_mem_regions();
+        for region in regions {
+            write_guest_memory(mem, region.gpa, region.len as usize, output)?;
+        }
+    }
+    output.flush()
 }
 
 /// Processes the data taken from the given transmit queue into the output sink.
@@ -156,13 +197,8 @@ fn process_transmit_queue<I: SignalableInterrupt>(
     while let Some(avail_desc) = transmit_queue.pop(mem) {
         let desc_index = avail_desc.index;
 
-        match Reader::new(mem.clone(), avail_desc) {
-            Ok(reader) => process_transmit_request(reader, output)
-                .unwrap_or_else(|e| error!("console: process_transmit_request failed: {}", e)),
-            Err(e) => {
-                error!("console: failed to create reader: {}", e);
-            }
-        };
+        process_transmit_request(mem, avail_desc, output)
+            .unwrap_or_else(|e| error!("console: process_transmit_request failed: {}", e));
 
         transmit_queue.add_used(mem, desc_index, 0);
         needs_interrupt = true;
@@ -539,3 +575,113 @@ impl VirtioDevice for Console {
         false
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::virtio::descriptor_utils::create_descriptor_chain;
+    use crate::virtio::descriptor_utils::DescriptorType;
+
+    // Records the address and contents of every buffer written to it.
+    #[derive(Default)]
+    struct RecordingSink {
+        writes: Vec<(usize, Vec<u8>)>,
+    }
+
+    impl io::Write for RecordingSink {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.writes.push((buf.as_ptr() as usize, buf.to_vec()));
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn host_address(mem: &GuestMemory, addr: u64) -> usize {
+        mem.get_host_address(GuestAddress(addr)).unwrap() as usize
+    }
+
+    #[test]
+    fn transmit_writes_from_guest_memory() {
+        let memory = GuestMemory::new(&[(GuestAddress(0), 0x10000)]).unwrap();
+        memory
+            .write_all_at_addr(b"hello ", GuestAddress(0x100))
+            .unwrap();
+        memory
+            .write_all_at_addr(b"world", GuestAddress(0x200))
+            .unwrap();
+        let chain = create_descriptor_chain(
+            &memory,
+            GuestAddress(0),
+            GuestAddress(0x100),
+            vec![(DescriptorType::Readable, 6), (DescriptorType::Readable, 5)],
+            0xfa,
+        )
+        .expect("create_descriptor_chain failed");
+
+        let mut sink = RecordingSink::default();
+        process_transmit_request(&memory, chain, &mut sink).unwrap();
+
+        // Each buffer is written without being copied out of guest memory first.
+        assert_eq!(
+            sink.writes,
+            [
+                (host_address(&memory, 0x100), b"hello ".to_vec()),
+                (host_address(&memory, 0x200), b"world".to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn transmit_skips_writable_buffers() {
+        let memory = GuestMemory::new(&[(GuestAddress(0), 0x10000)]).unwrap();
+        memory
+            .write_all_at_addr(b"output", GuestAddress(0x100))
+            .unwrap();
+        let chain = create_descriptor_chain(
+            &memory,
+            GuestAddress(0),
+            GuestAddress(0x100),
+            vec![(DescriptorType::Readable, 6), (DescriptorType::Writable, 6)],
+            0,
+        )
+        .expect("create_descriptor_chain failed");
+
+        let mut sink = RecordingSink::default();
+        process_transmit_request(&memory, chain, &mut sink).unwrap();
+        assert_eq!(sink.writes.len(), 1);
+        assert_eq!(sink.writes[0].1, b"output");
+    }
+
+    #[test]
+    fn write_spanning_regions_copies() {
+        let memory =
+            GuestMemory::new(&[(GuestAddress(0), 0x1000), (GuestAddress(0x1000), 0x1000)]).unwrap();
+        memory
+            .write_all_at_addr(b"span", GuestAddress(0xffc))
+            .unwrap();
+        memory
+            .write_all_at_addr(b"ning", GuestAddress(0x1000))
+            .unwrap();
+
+        let mut sink = RecordingSink::default();
+        write_guest_memory(&memory, GuestAddress(0xffc), 8, &mut sink).unwrap();
+
+        // The bytes from both regions are gathered into a single write.
+        assert_eq!(sink.writes.len(), 1);
+        assert_ne!(sink.writes[0].0, host_address(&memory, 0xffc));
+        assert_eq!(sink.writes[0].1, b"spanning");
+    }
+
+    #[test]
+    fn write_past_end_of_memory() {
+        let memory =
+            GuestMemory::new(&[(GuestAddress(0), 0x1000), (GuestAddress(0x1000), 0x1000)]).unwrap();
+
+        let mut sink = RecordingSink::default();
+        assert!(write_guest_memory(&memory, GuestAddress(0x1ffc), 8, &mut sink).is_err());
+        assert!(sink.writes.is_empty());
+    }
+}
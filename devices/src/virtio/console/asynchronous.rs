@@ -5,11 +5,15 @@
 //! Asynchronous console device which implementation can be shared by VMM and vhost-user.
 
 use std::collections::VecDeque;
+use std::fs::File;
 use std::io;
+use std::sync::Arc;
 use std::thread;
+use std::time::Duration;
 
 use anyhow::Context;
 use base::error;
+use base::terminal_size;
 use base::warn;
 use base::AsRawDescriptor;
 use base::Event;
@@ -21,9 +25,11 @@ use cros_async::EventAsync;
 use cros_async::Executor;
 use cros_async::IntoAsync;
 use cros_async::IoSourceExt;
+use cros_async::TimerAsync;
 use data_model::DataInit;
 use futures::FutureExt;
 use hypervisor::ProtectionType;
+use sync::Mutex;
 use vm_memory::GuestMemory;
 use vmm_vhost::message::VhostUserVirtioFeatures;
 
@@ -45,6 +51,74 @@ use crate::virtio::SignalableInterrupt;
 use crate::virtio::VirtioDevice;
 use crate::SerialDevice;
 
+/// Feature bit advertising that the `cols` and `rows` fields of the config space are valid.
+const VIRTIO_CONSOLE_F_SIZE: u32 = 0;
+
+/// How often the size of the terminal is checked. A resize can't be waited on: it doesn't make the
+/// terminal readable and SIGWINCH is only sent to the foreground process group of the terminal,
+/// which crosvm usually isn't part of.
+const RESIZE_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Provides the size of the terminal the console output goes to.
+pub trait ConsoleSizeSource: Send + Sync {
+    /// Returns the current size of the terminal as `(columns, rows)`.
+    fn size(&self) -> base::Result<(u16, u16)>;
+}
+
+impl ConsoleSizeSource for File {
+    fn size(&self) -> base::Result<(u16, u16)> {
+        terminal_size(self)
+    }
+}
+
+/// Last size read from a `ConsoleSizeSource`, as exposed in the config space.
+#[derive(Clone)]
+struct ConsoleSize {
+    source: Arc<dyn ConsoleSizeSource>,
+    current: Arc<Mutex<(u16, u16)>>,
+}
+
+impl ConsoleSize {
+    fn new(source: Arc<dyn ConsoleSizeSource>) -> base::Result<ConsoleSize> {
+        let current = source.size()?;
+        Ok(ConsoleSize {
+            source,
+            current: Arc::new(Mutex::new(current)),
+        })
+    }
+
+    fn get(&self) -> (u16, u16) {
+        *self.current.lock()
+    }
+
+    /// Reads the size of the terminal again and returns whether it changed.
+    fn update(&self) -> base::Result<bool> {
+        let size = self.source.size()?;
+        let mut current = self.current.lock();
+        let changed = *current != size;
+        *current = size;
+        Ok(changed)
+    }
+}
+
+/// Notifies the driver through a config change interrupt every time the terminal is resized.
+async fn watch_resize<I: SignalableInterrupt>(ex: Executor, size: ConsoleSize, interrupt: I) {
+    loop {
+        if let Err(e) = TimerAsync::sleep(&ex, RESIZE_POLL_INTERVAL).await {
+            error!("Failed to wait for console resize: {}", e);
+            return;
+        }
+        match size.update() {
+            Ok(true) => interrupt.signal_config_changed(),
+            Ok(false) => {}
+            Err(e) => {
+                error!("Failed to read console size: {}", e);
+                return;
+            }
+        }
+    }
+}
+
 /// Wrapper that makes any `SerialInput` usable as an async source by providing an implementation of
 /// `IntoAsync`.
 struct AsyncSerialInput(Box<dyn SerialInput>);
@@ -234,6 +308,21 @@ pub struct AsyncConsole {
     state: VirtioConsoleState,
     base_features: u64,
     keep_rds: Vec<RawDescriptor>,
+    size: Option<ConsoleSize>,
+}
+
+impl AsyncConsole {
+    /// Reports the size of `terminal` to the guest and follows its resizes while the device is
+    /// running.
+    pub fn watch_terminal_size(&mut self, terminal: File) -> base::Result<()> {
+        self.keep_rds.push(terminal.as_raw_descriptor());
+        self.set_size_source(Arc::new(terminal))
+    }
+
+    fn set_size_source(&mut self, source: Arc<dyn ConsoleSizeSource>) -> base::Result<()> {
+        self.size = Some(ConsoleSize::new(source)?);
+        Ok(())
+    }
 }
 
 impl SerialDevice for AsyncConsole {
@@ -258,6 +347,7 @@ impl SerialDevice for AsyncConsole {
             )),
             base_features: base_features(protection_type),
             keep_rds,
+            size: None,
         }
     }
 }
@@ -274,7 +364,11 @@ impl VirtioDevice for AsyncConsole {
     }
 
     fn features(&self) -> u64 {
-        self.base_features
+        if self.size.is_some() {
+            self.base_features | 1 << VIRTIO_CONSOLE_F_SIZE
+        } else {
+            self.base_features
+        }
     }
 
     fn device_type(&self) -> DeviceType {
@@ -286,7 +380,10 @@ impl VirtioDevice for AsyncConsole {
     }
 
     fn read_config(&self, offset: u64, data: &mut [u8]) {
+        let (cols, rows) = self.size.as_ref().map_or((0, 0), ConsoleSize::get);
         let config = virtio_console_config {
+            cols: cols.into(),
+            rows: rows.into(),
             max_nr_ports: 1.into(),
             ..Default::default()
         };
@@ -335,6 +432,7 @@ impl VirtioDevice for AsyncConsole {
         let receive_evt = queue_evts.remove(0);
         let transmit_queue = queues.remove(0);
         let transmit_evt = queue_evts.remove(0);
+        let size = self.size.clone();
 
         let worker_result = thread::Builder::new()
             .name("virtio_console".to_string())
@@ -349,6 +447,11 @@ impl VirtioDevice for AsyncConsole {
                     receive_evt,
                 )?;
 
+                if let Some(size) = size {
+                    ex.spawn_local(watch_resize(ex.clone(), size, interrupt.clone()))
+                        .detach();
+                }
+
                 console.start_transmit_queue(&ex, mem, transmit_queue, interrupt, transmit_evt)?;
 
                 // Run until the kill event is signaled and cancel all tasks.
@@ -417,3 +520,58 @@ impl VirtioDevice for AsyncConsole {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct MockSizeSource(Mutex<(u16, u16)>);
+
+    impl ConsoleSizeSource for MockSizeSource {
+        fn size(&self) -> base::Result<(u16, u16)> {
+            Ok(*self.0.lock())
+        }
+    }
+
+    fn new_console() -> AsyncConsole {
+        AsyncConsole::new(
+            ProtectionType::Unprotected,
+            Event::new().unwrap(),
+            None,
+            None,
+            None,
+            false,
+            Vec::new(),
+        )
+    }
+
+    fn read_size(console: &AsyncConsole) -> (u16, u16) {
+        let mut config = virtio_console_config::default();
+        console.read_config(0, config.as_mut_slice());
+        (config.cols.into(), config.rows.into())
+    }
+
+    #[test]
+    fn size_not_reported_by_default() {
+        let console = new_console();
+        assert_eq!(console.features() & 1 << VIRTIO_CONSOLE_F_SIZE, 0);
+        assert_eq!(read_size(&console), (0, 0));
+    }
+
+    #[test]
+    fn size_follows_source() {
+        let source = Arc::new(MockSizeSource(Mutex::new((80, 24))));
+        let mut console = new_console();
+        console.set_size_source(source.clone()).unwrap();
+        assert_ne!(console.features() & 1 << VIRTIO_CONSOLE_F_SIZE, 0);
+        assert_eq!(read_size(&console), (80, 24));
+
+        let size = console.size.clone().unwrap();
+        assert!(!size.update().unwrap());
+
+        *source.0.lock() = (132, 43);
+        assert!(size.update().unwrap());
+        assert_eq!(read_size(&console), (132, 43));
+        assert!(!size.update().unwrap());
+    }
+}
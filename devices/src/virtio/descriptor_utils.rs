@@ -29,6 +29,7 @@ use disk::AsyncDisk;
 use remain::sorted;
 use smallvec::SmallVec;
 use thiserror::Error;
+use vm_memory::AccessContext;
 use vm_memory::GuestAddress;
 use vm_memory::GuestMemory;
 
@@ -832,8 +833,17 @@ pub fn create_descriptor_chain(
         );
     }
 
-    DescriptorChain::checked_new(memory, descriptor_array_addr, 0x100, 0, 0, None, None)
-        .map_err(Error::InvalidChain)
+    DescriptorChain::checked_new(
+        memory,
+        descriptor_array_addr,
+        0x100,
+        0,
+        0,
+        None,
+        None,
+        AccessContext::new("descriptor chain"),
+    )
+    .map_err(Error::InvalidChain)
 }
 
 #[cfg(test)]
@@ -31,6 +31,7 @@ use base::ioctl_iowr_nr;
 use base::ioctl_with_mut_ptr;
 use base::ioctl_with_ptr;
 use base::syscall;
+use base::AccessMode;
 use base::AsRawDescriptor;
 use base::FileFlags;
 use base::FromRawDescriptor;
@@ -1234,12 +1235,12 @@ impl PassthroughFs {
                 // operation so the extra latency should be fine.
                 let mut file = data.file.lock();
                 let flags = FileFlags::from_file(&*file).map_err(io::Error::from)?;
-                match flags {
-                    FileFlags::ReadWrite | FileFlags::Write => {
+                match flags.access_mode() {
+                    AccessMode::ReadWrite | AccessMode::Write => {
                         // We need to get a read-only handle for this file.
                         *file = self.open_fd(file.as_raw_descriptor(), libc::O_RDONLY)?;
                     }
-                    FileFlags::Read => {}
+                    AccessMode::Read => {}
                 }
             }
 
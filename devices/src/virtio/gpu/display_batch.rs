@@ -0,0 +1,276 @@
+// Copyright 2022 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Checks a `GpuControlCommand::Batch` before any of its commands is applied.
+//!
+//! The commands are checked in order against a model of the displays, which each command updates
+//! the way the gpu device would, so that a command can refer to a display added or removed by an
+//! earlier one. Only once the whole batch passes are the commands applied to the device.
+
+use std::collections::BTreeMap as Map;
+
+use vm_control::gpu::DisplayInput;
+use vm_control::gpu::DisplayParameters;
+use vm_control::gpu::GpuControlCommand;
+use vm_control::gpu::GpuControlResult;
+
+use super::display_params_edid;
+
+/// The displays of the gpu device, as the commands of a batch would leave them.
+pub struct DisplayModel {
+    displays: Map<u32, DisplayParameters>,
+    max_displays: usize,
+    spare_event_devices: usize,
+}
+
+impl DisplayModel {
+    /// Creates a model of `displays`, by display id, with room for `max_displays` displays and
+    /// `spare_event_devices` input devices left for displays with `DisplayInput::PerDisplay`.
+    pub fn new(
+        displays: Map<u32, DisplayParameters>,
+        max_displays: usize,
+        spare_event_devices: usize,
+    ) -> DisplayModel {
+        DisplayModel {
+            displays,
+            max_displays,
+            spare_event_devices,
+        }
+    }
+
+    /// Checks `commands` in order, and returns the index of the first one that would fail along
+    /// with its result.
+    pub fn check_batch(
+        &mut self,
+        commands: &[GpuControlCommand],
+    ) -> Result<(), (usize, GpuControlResult)> {
+        for (index, command) in commands.iter().enumerate() {
+            self.check(command).map_err(|result| (index, result))?;
+        }
+        Ok(())
+    }
+
+    fn check(&mut self, command: &GpuControlCommand) -> Result<(), GpuControlResult> {
+        match command {
+            GpuControlCommand::AddDisplays { displays } => self.add_displays(displays),
+            GpuControlCommand::RemoveDisplays { display_ids } => self.remove_displays(display_ids),
+            GpuControlCommand::SetDisplayMode {
+                display_id,
+                parameters,
+            } => self.set_display_mode(*display_id, parameters),
+            GpuControlCommand::SetRefreshRate {
+                display_id,
+                refresh_rate,
+            } => self.set_refresh_rate(*display_id, *refresh_rate),
+            GpuControlCommand::Batch { .. }
+            | GpuControlCommand::DisplayState
+            | GpuControlCommand::GetDisplayTrace
+            | GpuControlCommand::ListDisplays => Err(GpuControlResult::InvalidParameters {
+                field: "commands".to_string(),
+                reason: "only display changes can be batched".to_string(),
+            }),
+        }
+    }
+
+    fn add_displays(&mut self, displays: &[DisplayParameters]) -> Result<(), GpuControlResult> {
+        if self.displays.len() + displays.len() > self.max_displays {
+            return Err(GpuControlResult::TooManyDisplays(self.max_displays));
+        }
+        // Displays take the lowest free ids, like the scanouts of the device.
+        let display_ids: Vec<u32> = (0..)
+            .filter(|display_id| !self.displays.contains_key(display_id))
+            .take(displays.len())
+            .collect();
+        for (params, &display_id) in displays.iter().zip(&display_ids) {
+            display_params_edid(params)
+                .map_err(|reason| GpuControlResult::EdidGenerationFailed { display_id, reason })?;
+        }
+        let per_display_inputs = displays
+            .iter()
+            .filter(|params| params.input == DisplayInput::PerDisplay)
+            .count();
+        if per_display_inputs > self.spare_event_devices {
+            return Err(GpuControlResult::InvalidDisplay {
+                reason: format!(
+                    "{} displays with input=per-display but only {} spare input devices",
+                    per_display_inputs, self.spare_event_devices
+                ),
+            });
+        }
+        self.spare_event_devices -= per_display_inputs;
+
+        for (params, display_id) in displays.iter().zip(display_ids) {
+            self.displays.insert(display_id, params.clone());
+        }
+        Ok(())
+    }
+
+    fn remove_displays(&mut self, display_ids: &[u32]) -> Result<(), GpuControlResult> {
+        if let Some(&display_id) = display_ids
+            .iter()
+            .find(|display_id| !self.displays.contains_key(display_id))
+        {
+            return Err(GpuControlResult::NoSuchDisplay { display_id });
+        }
+        for display_id in display_ids {
+            if let Some(params) = self.displays.remove(display_id) {
+                if params.input == DisplayInput::PerDisplay {
+                    self.spare_event_devices += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn set_display_mode(
+        &mut self,
+        display_id: u32,
+        params: &DisplayParameters,
+    ) -> Result<(), GpuControlResult> {
+        let current = self
+            .displays
+            .get_mut(&display_id)
+            .ok_or(GpuControlResult::NoSuchDisplay { display_id })?;
+        display_params_edid(params)
+            .map_err(|reason| GpuControlResult::EdidGenerationFailed { display_id, reason })?;
+        if current.input != params.input {
+            return Err(GpuControlResult::InvalidParameters {
+                field: "input".to_string(),
+                reason: format!("the input of display {} can't be changed", display_id),
+            });
+        }
+        *current = params.clone();
+        Ok(())
+    }
+
+    fn set_refresh_rate(
+        &mut self,
+        display_id: u32,
+        refresh_rate: u32,
+    ) -> Result<(), GpuControlResult> {
+        let current = self
+            .displays
+            .get_mut(&display_id)
+            .ok_or(GpuControlResult::NoSuchDisplay { display_id })?;
+        let mut params = current.clone();
+        params.refresh_rate = refresh_rate;
+        display_params_edid(&params)
+            .map_err(|reason| GpuControlResult::EdidGenerationFailed { display_id, reason })?;
+        *current = params;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use vm_control::gpu::DisplayMode;
+
+    use super::*;
+
+    const MAX_DISPLAYS: usize = 4;
+
+    fn windowed(width: u32, height: u32) -> DisplayParameters {
+        DisplayParameters::default_with_mode(DisplayMode::Windowed(width, height))
+    }
+
+    fn model(display_count: u32) -> DisplayModel {
+        DisplayModel::new(
+            (0..display_count)
+                .map(|display_id| (display_id, DisplayParameters::default()))
+                .collect(),
+            MAX_DISPLAYS,
+            1,
+        )
+    }
+
+    fn add(count: usize) -> GpuControlCommand {
+        GpuControlCommand::AddDisplays {
+            displays: vec![DisplayParameters::default(); count],
+        }
+    }
+
+    fn remove(display_ids: &[u32]) -> GpuControlCommand {
+        GpuControlCommand::RemoveDisplays {
+            display_ids: display_ids.to_vec(),
+        }
+    }
+
+    #[test]
+    fn final_display_count_is_checked() {
+        // Removing displays first makes room for the added ones.
+        assert!(model(3).check_batch(&[remove(&[0, 1]), add(3)]).is_ok());
+        assert!(matches!(
+            model(3).check_batch(&[add(3), remove(&[0, 1])]),
+            Err((0, GpuControlResult::TooManyDisplays(MAX_DISPLAYS)))
+        ));
+        assert!(matches!(
+            model(3).check_batch(&[remove(&[0]), add(1), add(2)]),
+            Err((2, GpuControlResult::TooManyDisplays(MAX_DISPLAYS)))
+        ));
+    }
+
+    #[test]
+    fn commands_see_earlier_changes() {
+        // Display 1 is removed, then added again by the second command.
+        let set_mode = GpuControlCommand::SetDisplayMode {
+            display_id: 1,
+            parameters: windowed(800, 600),
+        };
+        assert!(model(2)
+            .check_batch(&[remove(&[1]), add(1), set_mode.clone()])
+            .is_ok());
+        assert!(matches!(
+            model(2).check_batch(&[remove(&[1]), set_mode]),
+            Err((1, GpuControlResult::NoSuchDisplay { display_id: 1 }))
+        ));
+        assert!(matches!(
+            model(2).check_batch(&[remove(&[0]), remove(&[0])]),
+            Err((1, GpuControlResult::NoSuchDisplay { display_id: 0 }))
+        ));
+    }
+
+    #[test]
+    fn invalid_commands_are_reported() {
+        let mut bad_refresh_rate = DisplayParameters::default();
+        bad_refresh_rate.refresh_rate = 0;
+        assert!(matches!(
+            model(1).check_batch(&[
+                add(1),
+                GpuControlCommand::AddDisplays {
+                    displays: vec![bad_refresh_rate],
+                },
+            ]),
+            Err((
+                1,
+                GpuControlResult::EdidGenerationFailed { display_id: 2, .. }
+            ))
+        ));
+        assert!(matches!(
+            model(1).check_batch(&[add(1), GpuControlCommand::ListDisplays]),
+            Err((1, GpuControlResult::InvalidParameters { .. }))
+        ));
+        assert!(matches!(
+            model(1).check_batch(&[GpuControlCommand::Batch {
+                commands: vec![add(1)]
+            }]),
+            Err((0, GpuControlResult::InvalidParameters { .. }))
+        ));
+    }
+
+    #[test]
+    fn per_display_inputs_are_returned_on_removal() {
+        let mut per_display = DisplayParameters::default();
+        per_display.input = DisplayInput::PerDisplay;
+        let add_per_display = GpuControlCommand::AddDisplays {
+            displays: vec![per_display],
+        };
+        assert!(matches!(
+            model(0).check_batch(&[add_per_display.clone(), add_per_display.clone()]),
+            Err((1, GpuControlResult::InvalidDisplay { .. }))
+        ));
+        assert!(model(0)
+            .check_batch(&[add_per_display.clone(), remove(&[0]), add_per_display])
+            .is_ok());
+    }
+}
@@ -0,0 +1,117 @@
+// Copyright 2022 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Coalesces the config change interrupts raised by display changes from the control tube.
+//!
+//! Each `AddDisplays` or `RemoveDisplays` request is applied at once, but tools often send one
+//! request per display in quick succession. Interrupting the guest for each of them makes it
+//! re-read the display info while it keeps changing, so the first change arms a timer and all
+//! the changes made until it fires are reported with a single interrupt. Replies to the requests
+//! are not delayed.
+
+use std::time::Duration;
+
+use base::AsRawDescriptor;
+use base::Result;
+use base::Timer;
+
+/// How long after the first display change the guest is notified.
+pub const DISPLAY_CHANGE_WINDOW: Duration = Duration::from_millis(20);
+
+pub struct DisplayChanges {
+    timer: Timer,
+    window: Duration,
+    pending: bool,
+}
+
+impl DisplayChanges {
+    pub fn new(window: Duration) -> Result<DisplayChanges> {
+        Ok(DisplayChanges {
+            timer: Timer::new()?,
+            window,
+            pending: false,
+        })
+    }
+
+    /// Records that the displays changed, arming the timer unless a notification is already
+    /// pending.
+    pub fn changed(&mut self) -> Result<()> {
+        if !self.pending {
+            self.timer.reset(self.window, None)?;
+            self.pending = true;
+        }
+        Ok(())
+    }
+
+    /// Handles the timer firing and returns whether the guest should be notified.
+    pub fn timer_fired(&mut self) -> bool {
+        let _ = self.timer.mark_waited();
+        std::mem::replace(&mut self.pending, false)
+    }
+
+    /// The timer to wait on for `timer_fired`.
+    pub fn timer(&self) -> &dyn AsRawDescriptor {
+        &self.timer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use base::EventToken;
+    use base::WaitContext;
+
+    use super::*;
+
+    #[derive(EventToken)]
+    enum Token {
+        Timer,
+    }
+
+    // Waits for the changes to be reported and returns the number of notifications.
+    fn notifications(changes: &mut DisplayChanges) -> usize {
+        let wait_ctx = WaitContext::build_with(&[(changes.timer(), Token::Timer)]).unwrap();
+        let mut count = 0;
+        loop {
+            let events = wait_ctx.wait_timeout(DISPLAY_CHANGE_WINDOW * 5).unwrap();
+            if events.is_empty() {
+                return count;
+            }
+            for event in events.iter().filter(|e| e.is_readable) {
+                match event.token {
+                    Token::Timer => {
+                        if changes.timer_fired() {
+                            count += 1;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn one_notification_per_burst() {
+        let mut changes = DisplayChanges::new(DISPLAY_CHANGE_WINDOW).unwrap();
+        // A 3-display AddDisplays request followed by one request per display.
+        changes.changed().unwrap();
+        for _ in 0..3 {
+            changes.changed().unwrap();
+        }
+        assert_eq!(notifications(&mut changes), 1);
+    }
+
+    #[test]
+    fn notifies_again_after_window() {
+        let mut changes = DisplayChanges::new(DISPLAY_CHANGE_WINDOW).unwrap();
+        changes.changed().unwrap();
+        assert_eq!(notifications(&mut changes), 1);
+        changes.changed().unwrap();
+        assert_eq!(notifications(&mut changes), 1);
+    }
+
+    #[test]
+    fn no_notification_without_changes() {
+        let mut changes = DisplayChanges::new(DISPLAY_CHANGE_WINDOW).unwrap();
+        assert_eq!(notifications(&mut changes), 0);
+    }
+}
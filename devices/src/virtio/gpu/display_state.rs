@@ -0,0 +1,207 @@
+// Copyright 2022 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! The mode of a scanout as presented to the guest.
+//!
+//! Guests consult both GET_DISPLAY_INFO and GET_EDID, and get confused when they describe
+//! different modes. Both are answered from the scanout's `DisplayState`, and every change to the
+//! mode of a scanout goes through `DisplayState::set_size`, `DisplayState::set_refresh_rate` or
+//! `DisplayState::set_identifiers`, which bump the generation so an EDID can be tied to the display
+//! info it was generated with.
+
+use super::edid::DisplayInfo;
+use super::edid::EdidBytes;
+use super::edid::EdidIdentifiers;
+use super::protocol::GpuResponse::OkEdid;
+use super::protocol::VirtioGpuResult;
+
+pub struct DisplayState {
+    width: u32,
+    height: u32,
+    refresh_rate: u32,
+    identifiers: EdidIdentifiers,
+    generation: u64,
+}
+
+impl DisplayState {
+    pub fn new(width: u32, height: u32, refresh_rate: u32) -> DisplayState {
+        DisplayState {
+            width,
+            height,
+            refresh_rate,
+            identifiers: Default::default(),
+            generation: 0,
+        }
+    }
+
+    /// Identifies the display with `identifiers` in its EDID.
+    pub fn with_identifiers(mut self, identifiers: EdidIdentifiers) -> DisplayState {
+        self.identifiers = identifiers;
+        self
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    pub fn refresh_rate(&self) -> u32 {
+        self.refresh_rate
+    }
+
+    /// Incremented every time the mode changes.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Changes the size of the display, returning whether it was different.
+    pub fn set_size(&mut self, width: u32, height: u32) -> bool {
+        if (width, height) == self.size() {
+            return false;
+        }
+        self.width = width;
+        self.height = height;
+        self.generation += 1;
+        true
+    }
+
+    /// Changes the refresh rate of the display, returning whether it was different. Only the
+    /// EDID reports it.
+    pub fn set_refresh_rate(&mut self, refresh_rate: u32) -> bool {
+        if refresh_rate == self.refresh_rate {
+            return false;
+        }
+        self.refresh_rate = refresh_rate;
+        self.generation += 1;
+        true
+    }
+
+    /// Changes the identifiers of the display, returning whether they were different. Only the
+    /// EDID reports them.
+    pub fn set_identifiers(&mut self, identifiers: EdidIdentifiers) -> bool {
+        if identifiers == self.identifiers {
+            return false;
+        }
+        self.identifiers = identifiers;
+        self.generation += 1;
+        true
+    }
+
+    /// The `(width, height, enabled)` rectangle reported by GET_DISPLAY_INFO.
+    pub fn display_info(&self) -> (u32, u32, bool) {
+        (self.width, self.height, true)
+    }
+
+    /// The EDID reported by GET_EDID, whose preferred mode is the GET_DISPLAY_INFO rectangle.
+    pub fn edid(&self) -> VirtioGpuResult {
+        let info = DisplayInfo::new(self.width, self.height, self.refresh_rate)
+            .with_identifiers(self.identifiers);
+        let resp = EdidBytes::new(&[info])?;
+        if let OkEdid(edid) = &resp {
+            debug_assert_eq!(
+                edid.preferred_size(),
+                self.size(),
+                "EDID of generation {} doesn't match the display info",
+                self.generation
+            );
+        }
+        Ok(resp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use vm_control::gpu::DisplayParameters;
+
+    use super::*;
+
+    fn edid_size(state: &DisplayState) -> (u32, u32) {
+        match state.edid() {
+            Ok(OkEdid(edid)) => edid.preferred_size(),
+            _ => panic!("failed to create EDID"),
+        }
+    }
+
+    #[test]
+    fn edid_matches_display_info() {
+        for (width, height) in [(1280, 1024), (1920, 1080), (3840, 2160), (4095, 256)] {
+            let state = DisplayState::new(width, height, 30);
+            let (info_width, info_height, enabled) = state.display_info();
+            assert!(enabled);
+            assert_eq!(edid_size(&state), (info_width, info_height));
+        }
+    }
+
+    #[test]
+    fn edid_follows_resize() {
+        let mut state = DisplayState::new(1280, 1024, 60);
+        assert_eq!(state.generation(), 0);
+
+        assert!(state.set_size(1920, 1080));
+        assert_eq!(state.generation(), 1);
+        let (width, height, _) = state.display_info();
+        assert_eq!((width, height), (1920, 1080));
+        assert_eq!(edid_size(&state), (1920, 1080));
+
+        // Setting the same size isn't a change.
+        assert!(!state.set_size(1920, 1080));
+        assert_eq!(state.generation(), 1);
+
+        assert!(state.set_size(1280, 1024));
+        assert_eq!(state.generation(), 2);
+        assert_eq!(edid_size(&state), (1280, 1024));
+    }
+
+    #[test]
+    fn edid_follows_refresh_rate() {
+        let mut state = DisplayState::new(1920, 1080, 60);
+        let edid_60 = match state.edid() {
+            Ok(OkEdid(edid)) => edid,
+            _ => panic!("failed to create EDID"),
+        };
+
+        assert!(state.set_refresh_rate(30));
+        assert!(!state.set_refresh_rate(30));
+        assert_eq!(state.generation(), 1);
+        assert_eq!(state.display_info(), (1920, 1080, true));
+        match state.edid() {
+            Ok(OkEdid(edid)) => {
+                assert_eq!(edid.preferred_size(), (1920, 1080));
+                assert_ne!(edid.as_bytes(), edid_60.as_bytes());
+            }
+            _ => panic!("failed to create EDID"),
+        }
+    }
+
+    #[test]
+    fn edid_follows_identifiers() {
+        let mut state = DisplayState::new(1920, 1080, 60);
+        let params = DisplayParameters {
+            serial: Some(42),
+            ..Default::default()
+        };
+        let identifiers = EdidIdentifiers::new(&params, 0).unwrap();
+
+        assert!(!state.set_identifiers(Default::default()));
+        assert!(state.set_identifiers(identifiers));
+        assert!(!state.set_identifiers(identifiers));
+        assert_eq!(state.generation(), 1);
+        assert_eq!(edid_size(&state), (1920, 1080));
+    }
+
+    #[test]
+    fn no_edid_for_invalid_size() {
+        let mut state = DisplayState::new(1280, 1024, 60);
+        state.set_size(64, 64);
+        assert!(state.edid().is_err());
+        assert_eq!(state.display_info(), (64, 64, true));
+    }
+}
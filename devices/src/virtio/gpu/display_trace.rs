@@ -0,0 +1,277 @@
+// Copyright 2022 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! A bounded, rate limited trace of the EDID and scanout requests handled by the gpu device.
+//!
+//! The trace is meant to debug display bring-up: which EDID the guest was offered and which
+//! scanouts it configured in response. Entries are kept in a ring of `TRACE_CAPACITY` entries and
+//! recorded at no more than `TRACE_RATE` entries per second after an initial burst of
+//! `TRACE_BURST`, so a guest spamming requests can't grow memory or hide the start of the
+//! negotiation.
+//!
+//! `RequestedModes` separately keeps the last few sizes the guest set on each scanout so that a
+//! guest rendering at a size the host display doesn't provide can be spotted in `ListDisplays`.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+use std::time::Instant;
+
+use base::debug;
+use base::warn;
+use base::Clock;
+use base::RateLimiter;
+use base::TimeSource;
+use vm_control::gpu::DisplayTraceEntry;
+use vm_control::gpu::DisplayTraceEvent;
+use vm_control::gpu::GpuControlResult;
+use vm_control::gpu::GuestRequestedModes;
+
+/// Maximum number of entries kept in the trace.
+const TRACE_CAPACITY: usize = 256;
+/// Number of entries that can be recorded back to back.
+const TRACE_BURST: u64 = 64;
+/// Sustained number of entries recorded per second.
+const TRACE_RATE: u64 = 16;
+/// Number of guest requested sizes kept per scanout.
+const REQUESTED_MODES_CAPACITY: usize = 8;
+
+pub struct DisplayTrace<C = Clock> {
+    clock: C,
+    created: Instant,
+    entries: VecDeque<DisplayTraceEntry>,
+    next_seq: u64,
+    rate_limited: u64,
+    limiter: RateLimiter<C>,
+}
+
+impl DisplayTrace {
+    pub fn new() -> DisplayTrace {
+        DisplayTrace::with_clock(Clock::new())
+    }
+}
+
+impl<C: TimeSource + Clone> DisplayTrace<C> {
+    fn with_clock(clock: C) -> DisplayTrace<C> {
+        DisplayTrace {
+            created: clock.now(),
+            entries: VecDeque::with_capacity(TRACE_CAPACITY),
+            next_seq: 0,
+            rate_limited: 0,
+            limiter: RateLimiter::with_clock(
+                clock.clone(),
+                TRACE_BURST,
+                TRACE_RATE,
+                Duration::from_secs(1),
+            ),
+            clock,
+        }
+    }
+
+    /// Records `event` unless entries are being recorded faster than the rate limit allows.
+    pub fn record(&mut self, event: DisplayTraceEvent) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+
+        if !self.limiter.try_acquire(1) {
+            self.rate_limited += 1;
+            return;
+        }
+
+        debug!("display trace {}: {:?}", seq, event);
+        if self.entries.len() == TRACE_CAPACITY {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(DisplayTraceEntry {
+            seq,
+            timestamp: self.clock.now().saturating_duration_since(self.created),
+            event,
+        });
+    }
+
+    /// Returns the recorded entries, oldest first.
+    pub fn get(&self) -> GpuControlResult {
+        GpuControlResult::DisplayTrace {
+            entries: self.entries.iter().cloned().collect(),
+            rate_limited: self.rate_limited,
+        }
+    }
+}
+
+impl Default for DisplayTrace {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The sizes a guest set on a scanout, oldest first.
+#[derive(Default)]
+pub struct RequestedModes {
+    requested: VecDeque<(u32, u32)>,
+    mismatches: u64,
+}
+
+impl RequestedModes {
+    /// Records that the guest set a resource of size `requested` on scanout `scanout_id`, whose
+    /// host display is `granted`.
+    pub fn record(&mut self, scanout_id: u32, requested: (u32, u32), granted: (u32, u32)) {
+        if requested != granted {
+            // Only warn when the guest changes size to avoid a warning per frame.
+            if self.requested.back() != Some(&requested) {
+                warn!(
+                    "scanout {}: guest requested {}x{} but the display is {}x{}",
+                    scanout_id, requested.0, requested.1, granted.0, granted.1
+                );
+            }
+            self.mismatches += 1;
+        }
+        if self.requested.len() == REQUESTED_MODES_CAPACITY {
+            self.requested.pop_front();
+        }
+        self.requested.push_back(requested);
+    }
+
+    /// Returns whether the guest never set a resource on the scanout.
+    pub fn is_empty(&self) -> bool {
+        self.requested.is_empty()
+    }
+
+    pub fn get(&self, granted: (u32, u32)) -> GuestRequestedModes {
+        GuestRequestedModes {
+            requested: self.requested.iter().copied().collect(),
+            granted,
+            mismatches: self.mismatches,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use base::FakeClock;
+    use sync::Mutex;
+
+    use super::*;
+
+    fn fake_trace() -> (DisplayTrace<Arc<Mutex<FakeClock>>>, Arc<Mutex<FakeClock>>) {
+        let clock = Arc::new(Mutex::new(FakeClock::new()));
+        (DisplayTrace::with_clock(clock.clone()), clock)
+    }
+
+    fn advance(clock: &Arc<Mutex<FakeClock>>, duration: Duration) {
+        clock.lock().add_ns(duration.as_nanos() as u64);
+    }
+
+    fn set_scanout(scanout_id: u32, resource_id: u32) -> DisplayTraceEvent {
+        DisplayTraceEvent::SetScanout {
+            scanout_id,
+            resource_id,
+            blob_size: None,
+            error: None,
+        }
+    }
+
+    fn trace_entries<C: TimeSource + Clone>(
+        trace: &DisplayTrace<C>,
+    ) -> (Vec<DisplayTraceEntry>, u64) {
+        match trace.get() {
+            GpuControlResult::DisplayTrace {
+                entries,
+                rate_limited,
+            } => (entries, rate_limited),
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn records_scanout_changes() {
+        let (mut trace, clock) = fake_trace();
+
+        trace.record(DisplayTraceEvent::GetEdid { scanout_id: 0 });
+        trace.record(set_scanout(0, 1));
+        advance(&clock, Duration::from_millis(10));
+        trace.record(set_scanout(0, 2));
+        advance(&clock, Duration::from_millis(10));
+        trace.record(set_scanout(0, 0));
+
+        let (entries, rate_limited) = trace_entries(&trace);
+        assert_eq!(rate_limited, 0);
+        assert_eq!(
+            entries.iter().map(|e| e.seq).collect::<Vec<_>>(),
+            vec![0, 1, 2, 3]
+        );
+        assert_eq!(
+            entries[0].event,
+            DisplayTraceEvent::GetEdid { scanout_id: 0 }
+        );
+        assert_eq!(entries[2].event, set_scanout(0, 2));
+        assert_eq!(entries[3].timestamp, Duration::from_millis(20));
+    }
+
+    #[test]
+    fn rate_limits_bursts() {
+        let (mut trace, clock) = fake_trace();
+
+        for i in 0..TRACE_BURST as u32 + 10 {
+            trace.record(set_scanout(0, i));
+        }
+        let (entries, rate_limited) = trace_entries(&trace);
+        assert_eq!(entries.len(), TRACE_BURST as usize);
+        assert_eq!(rate_limited, 10);
+
+        // Tokens come back at the sustained rate.
+        advance(&clock, Duration::from_secs(1));
+        for i in 0..TRACE_RATE as u32 + 1 {
+            trace.record(set_scanout(1, i));
+        }
+        let (entries, rate_limited) = trace_entries(&trace);
+        assert_eq!(entries.len(), (TRACE_BURST + TRACE_RATE) as usize);
+        assert_eq!(rate_limited, 11);
+        // The gap in sequence numbers shows where entries were dropped.
+        assert_eq!(entries[TRACE_BURST as usize].seq, TRACE_BURST + 10);
+    }
+
+    #[test]
+    fn bounded_to_capacity() {
+        let (mut trace, clock) = fake_trace();
+
+        // Record slowly enough to never hit the rate limit.
+        let total = TRACE_CAPACITY as u64 + 20;
+        for i in 0..total {
+            trace.record(set_scanout(0, i as u32));
+            advance(&clock, Duration::from_secs(1));
+        }
+        let (entries, rate_limited) = trace_entries(&trace);
+        assert_eq!(rate_limited, 0);
+        assert_eq!(entries.len(), TRACE_CAPACITY);
+        assert_eq!(entries.first().unwrap().seq, 20);
+        assert_eq!(entries.last().unwrap().seq, total - 1);
+    }
+
+    #[test]
+    fn requested_modes_count_mismatches() {
+        let mut modes = RequestedModes::default();
+        assert!(modes.is_empty());
+
+        modes.record(0, (1280, 1024), (1280, 1024));
+        modes.record(0, (1920, 1080), (1280, 1024));
+        modes.record(0, (1920, 1080), (1280, 1024));
+        assert_eq!(
+            modes.get((1280, 1024)),
+            GuestRequestedModes {
+                requested: vec![(1280, 1024), (1920, 1080), (1920, 1080)],
+                granted: (1280, 1024),
+                mismatches: 2,
+            }
+        );
+
+        for i in 0..REQUESTED_MODES_CAPACITY as u32 {
+            modes.record(0, (640 + i, 480), (640, 480));
+        }
+        let modes = modes.get((640, 480));
+        assert_eq!(modes.requested.len(), REQUESTED_MODES_CAPACITY);
+        assert_eq!(modes.requested[0], (640, 480));
+        assert_eq!(modes.mismatches, 2 + REQUESTED_MODES_CAPACITY as u64 - 1);
+    }
+}
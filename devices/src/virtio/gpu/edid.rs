@@ -8,10 +8,56 @@
 use std::fmt;
 use std::fmt::Debug;
 
+use vm_control::gpu::check_manufacturer;
+use vm_control::gpu::DisplayParameters;
+
 use super::protocol::GpuResponse::*;
 use super::protocol::VirtioGpuResult;
 
 const EDID_DATA_LENGTH: usize = 128;
+// Size of each of the 4 descriptor blocks.
+const DESCRIPTOR_LENGTH: usize = 18;
+// Offset of the first descriptor block in the base block.
+const DESCRIPTORS_OFFSET: usize = 54;
+// The base block holds up to 3 detailed timings followed by the display name and, when fewer
+// timings leave room for it, the display range limits.
+const BASE_DETAILED_TIMINGS: usize = 3;
+// Offset of the display gamma in the base block, stored as (gamma * 100) - 100.
+const GAMMA_OFFSET: usize = 23;
+const SRGB_GAMMA: u8 = 120;
+// Offset of the feature support byte in the base block.
+const FEATURE_SUPPORT_OFFSET: usize = 24;
+// Feature support bits: RGB color display, sRGB default color space, preferred timing mode
+// including the native pixel format and refresh rate.
+const FEATURE_SUPPORT: u8 = 0x08 | 0x04 | 0x02;
+// Feature support bit set when the display range limits descriptor describes the modes the display
+// accepts besides the listed ones.
+const FEATURE_CONTINUOUS_FREQUENCY: u8 = 0x01;
+// Offset of the chromaticity coordinates in the base block.
+const CHROMATICITY_OFFSET: usize = 25;
+// The red, green, blue and white point (x, y) coordinates of sRGB, in 1/1024 units.
+const SRGB_CHROMATICITY: [(u16, u16); 4] = [(655, 338), (307, 614), (154, 61), (320, 337)];
+// Offset of the standard timings in the base block.
+const STANDARD_TIMINGS_OFFSET: usize = 0x26;
+// Range of refresh rates a standard timing can encode, as an offset from the lowest one.
+const STANDARD_TIMING_MIN_REFRESH_RATE: u32 = 60;
+const STANDARD_TIMING_MAX_REFRESH_RATE: u32 = STANDARD_TIMING_MIN_REFRESH_RATE + 0x3F;
+const RANGE_LIMITS_TAG: u8 = 0xFD;
+// Rates above 255 are stored in the range limits descriptor as an offset from 255.
+const RANGE_LIMITS_RATE_OFFSET: u32 = 255;
+// Video timing support flag of a range limits descriptor without secondary timing formulas.
+const RANGE_LIMITS_ONLY: u8 = 0x01;
+// Offset of the number of extension blocks in the base block.
+const EXTENSION_COUNT_OFFSET: usize = 126;
+const CTA_EXTENSION_TAG: u8 = 0x02;
+const CTA_EXTENSION_REVISION: u8 = 3;
+// Offset of the data block collection in a CTA-861 extension block.
+const CTA_DATA_BLOCKS_OFFSET: usize = 4;
+const CTA_VIDEO_DATA_BLOCK_TAG: u8 = 2;
+// The length of a CTA-861 data block is stored in 5 bits.
+const CTA_MAX_DATA_BLOCK_LENGTH: usize = 0x1F;
+// Fills the 13 bytes left in the display product name descriptor.
+const DISPLAY_NAME: &[u8; 13] = b"CrosvmDisplay";
 const DEFAULT_HORIZONTAL_BLANKING: u16 = 560;
 const DEFAULT_VERTICAL_BLANKING: u16 = 50;
 const DEFAULT_HORIZONTAL_FRONT_PORCH: u16 = 64;
@@ -19,6 +65,35 @@ const DEFAULT_VERTICAL_FRONT_PORCH: u16 = 1;
 const DEFAULT_HORIZONTAL_SYNC_PULSE: u16 = 192;
 const DEFAULT_VERTICAL_SYNC_PULSE: u16 = 3;
 
+// Bounds on the displays we are willing to describe. Guest drivers have been seen to crash on
+// EDIDs describing degenerate modes, so anything outside these is rejected.
+const MIN_WIDTH: u32 = 256;
+const MIN_HEIGHT: u32 = 144;
+// The detailed timing descriptor stores the addressable width and height in 12 bits.
+const MAX_WIDTH: u32 = 0xFFF;
+const MAX_HEIGHT: u32 = 0xFFF;
+const MIN_REFRESH_RATE: u32 = 10;
+const MAX_REFRESH_RATE: u32 = 480;
+// The detailed timing descriptor stores the pixel clock in 16 bits, in 10 kHz units.
+const MAX_PIXEL_CLOCK: u64 = u16::MAX as u64;
+
+// CTA-861 video identification codes of the modes a detailed timing descriptor can't describe,
+// because of their size or pixel clock, as (width, height, refresh rate, VIC).
+const CTA_VIDEO_FORMATS: &[(u32, u32, u32, u8)] = &[
+    (3840, 2160, 100, 117),
+    (3840, 2160, 120, 118),
+    (4096, 2160, 24, 98),
+    (4096, 2160, 25, 99),
+    (4096, 2160, 30, 100),
+    (4096, 2160, 50, 101),
+    (4096, 2160, 60, 102),
+    (4096, 2160, 100, 218),
+    (4096, 2160, 120, 219),
+    (7680, 4320, 30, 196),
+    (7680, 4320, 60, 199),
+    (7680, 4320, 120, 201),
+];
+
 /// This class is used to create the Extended Display Identification Data (EDID), which will be
 /// exposed to the guest system.
 ///
@@ -27,11 +102,13 @@ const DEFAULT_VERTICAL_SYNC_PULSE: u16 = 3;
 /// pixel clock).
 ///
 /// The EDID spec defines a number of methods to provide mode information, but in priority order the
-/// "detailed" timing information is first, so we provide a single block of detailed timing
-/// information and no other form of timing information.
-#[repr(C)]
+/// "detailed" timing information is first, so each mode gets a detailed timing descriptor, the
+/// first one being the preferred mode. The base block has room for three of them; the others go in
+/// a CTA-861 extension block, along with the video identification codes of the modes too large for
+/// a detailed timing descriptor.
 pub struct EdidBytes {
-    bytes: [u8; EDID_DATA_LENGTH],
+    bytes: Vec<u8>,
+    preferred_size: (u32, u32),
 }
 
 impl EdidBytes {
@@ -42,6 +119,11 @@ impl EdidBytes {
     pub fn as_bytes(&self) -> &[u8] {
         &self.bytes
     }
+
+    /// Returns the size of the preferred mode.
+    pub fn preferred_size(&self) -> (u32, u32) {
+        self.preferred_size
+    }
 }
 
 impl Debug for EdidBytes {
@@ -68,8 +150,10 @@ impl Resolution {
     }
 
     fn get_aspect_ratio(&self) -> (u32, u32) {
-        let divisor = gcd(self.width, self.height);
-        (self.width / divisor, self.height / divisor)
+        match gcd(self.width, self.height) {
+            0 => (0, 0),
+            divisor => (self.width / divisor, self.height / divisor),
+        }
     }
 }
 
@@ -80,9 +164,53 @@ fn gcd(x: u32, y: u32) -> u32 {
     }
 }
 
+/// The manufacturer, product and serial number identifying a display in its EDID.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct EdidIdentifiers {
+    manufacturer: [u8; 3],
+    product_id: u16,
+    serial: u32,
+}
+
+impl EdidIdentifiers {
+    /// Returns the identifiers of the display with index `display_index` added with `params`.
+    /// Displays without an explicit serial number get one derived from their index, so that the
+    /// guest can tell them apart.
+    pub fn new(params: &DisplayParameters, display_index: u32) -> Result<Self, String> {
+        let default = EdidIdentifiers::default();
+        let manufacturer = match &params.manufacturer {
+            Some(manufacturer) => {
+                check_manufacturer(manufacturer)?;
+                let mut id = [0; 3];
+                id.copy_from_slice(manufacturer.to_ascii_uppercase().as_bytes());
+                id
+            }
+            None => default.manufacturer,
+        };
+        Ok(EdidIdentifiers {
+            manufacturer,
+            product_id: params.product_id.unwrap_or(default.product_id),
+            serial: params
+                .serial
+                .unwrap_or_else(|| display_index.wrapping_add(default.serial)),
+        })
+    }
+}
+
+impl Default for EdidIdentifiers {
+    fn default() -> Self {
+        EdidIdentifiers {
+            manufacturer: *b"GGL",
+            product_id: 1,
+            serial: 1,
+        }
+    }
+}
+
 #[derive(Copy, Clone)]
 pub struct DisplayInfo {
     resolution: Resolution,
+    identifiers: EdidIdentifiers,
     refresh_rate: u32,
     horizontal_blanking: u16,
     vertical_blanking: u16,
@@ -99,6 +227,7 @@ impl DisplayInfo {
     pub fn new(width: u32, height: u32, refresh_rate: u32) -> Self {
         Self {
             resolution: Resolution::new(width, height),
+            identifiers: Default::default(),
             refresh_rate,
             horizontal_blanking: DEFAULT_HORIZONTAL_BLANKING,
             vertical_blanking: DEFAULT_VERTICAL_BLANKING,
@@ -109,6 +238,12 @@ impl DisplayInfo {
         }
     }
 
+    /// Identifies the display with `identifiers` instead of the default ones.
+    pub fn with_identifiers(mut self, identifiers: EdidIdentifiers) -> Self {
+        self.identifiers = identifiers;
+        self
+    }
+
     pub fn width(&self) -> u32 {
         self.resolution.width
     }
@@ -116,38 +251,263 @@ impl DisplayInfo {
     pub fn height(&self) -> u32 {
         self.resolution.height
     }
+
+    /// Checks that this display can be described by an EDID, returning the violated constraint
+    /// otherwise.
+    pub fn validate(&self) -> Result<(), String> {
+        let (width, height) = (self.width(), self.height());
+        if width < MIN_WIDTH || height < MIN_HEIGHT {
+            return Err(format!(
+                "display size {}x{} is smaller than the minimum of {}x{}",
+                width, height, MIN_WIDTH, MIN_HEIGHT
+            ));
+        }
+        // Modes with a CTA-861 video identification code don't need a detailed timing descriptor.
+        if self.cta_vic().is_some() {
+            return Ok(());
+        }
+        if width > MAX_WIDTH || height > MAX_HEIGHT {
+            return Err(format!(
+                "display size {}x{} is larger than the maximum of {}x{}",
+                width, height, MAX_WIDTH, MAX_HEIGHT
+            ));
+        }
+        if !(MIN_REFRESH_RATE..=MAX_REFRESH_RATE).contains(&self.refresh_rate) {
+            return Err(format!(
+                "refresh rate {}Hz is outside of {}Hz to {}Hz",
+                self.refresh_rate, MIN_REFRESH_RATE, MAX_REFRESH_RATE
+            ));
+        }
+        let clock = self.pixel_clock();
+        if clock > MAX_PIXEL_CLOCK {
+            return Err(format!(
+                "pixel clock {}0 kHz for {}x{}@{}Hz exceeds the maximum of {}0 kHz",
+                clock, width, height, self.refresh_rate, MAX_PIXEL_CLOCK
+            ));
+        }
+        Ok(())
+    }
+
+    // Whether a detailed timing descriptor can describe this mode.
+    fn fits_detailed_timing(&self) -> bool {
+        self.width() <= MAX_WIDTH
+            && self.height() <= MAX_HEIGHT
+            && self.pixel_clock() <= MAX_PIXEL_CLOCK
+    }
+
+    // Returns the CTA-861 video identification code of this mode if it needs one because it
+    // doesn't fit in a detailed timing descriptor.
+    fn cta_vic(&self) -> Option<u8> {
+        if self.fits_detailed_timing() {
+            return None;
+        }
+        CTA_VIDEO_FORMATS
+            .iter()
+            .find(|&&(width, height, refresh_rate, _)| {
+                (width, height, refresh_rate) == (self.width(), self.height(), self.refresh_rate)
+            })
+            .map(|&(_, _, _, vic)| vic)
+    }
+
+    // The pixel clock is what controls the refresh timing information.
+    //
+    // The formula for getting refresh rate out of this value is:
+    //   refresh_rate = clk * 10000 / (htotal * vtotal)
+    // Solving for clk:
+    //   clk = (refresh_rate * htotal * votal) / 10000
+    //
+    // where:
+    //   clk - The setting here
+    //   vtotal - Total lines
+    //   htotal - Total pixels per line
+    //
+    // Value here is pixel clock + 10,000, in 10khz steps.
+    //
+    // Pseudocode of kernel logic for vrefresh:
+    //    vtotal := mode->vtotal;
+    //    calc_val := (clock * 1000) / htotal
+    //    refresh := (calc_val + vtotal / 2) / vtotal
+    //    if flags & INTERLACE: refresh *= 2
+    //    if flags & DBLSCAN: refresh /= 2
+    //    if vscan > 1: refresh /= vscan
+    //
+    fn pixel_clock(&self) -> u64 {
+        let htotal = u64::from(self.width()) + u64::from(self.horizontal_blanking);
+        let vtotal = u64::from(self.height()) + u64::from(self.vertical_blanking);
+        // Saturate rather than overflow, `validate` rejects any clock this large anyway.
+        let clock = u64::from(self.refresh_rate)
+            .saturating_mul(htotal)
+            .saturating_mul(vtotal)
+            / 10000;
+        // Round to nearest 10khz.
+        clock.saturating_add(5) / 10 * 10
+    }
 }
 
 impl EdidBytes {
-    /// Creates a virtual EDID block.
-    pub fn new(info: &DisplayInfo) -> VirtioGpuResult {
-        let mut edid: [u8; EDID_DATA_LENGTH] = [0; EDID_DATA_LENGTH];
+    /// Creates a virtual EDID advertising `modes`, the first one being the preferred mode.
+    ///
+    /// When the preferred mode only has a CTA-861 video identification code, the base block has no
+    /// detailed timings, so that the guest doesn't take another mode for the preferred one.
+    pub fn new(modes: &[DisplayInfo]) -> VirtioGpuResult {
+        let preferred = modes
+            .first()
+            .ok_or_else(|| ErrEdid("no display modes to describe".to_string()))?;
+        for info in modes {
+            info.validate().map_err(ErrEdid)?;
+        }
+        let (detailed, vics): (Vec<&DisplayInfo>, Vec<&DisplayInfo>) =
+            modes.iter().partition(|info| info.fits_detailed_timing());
+        // `validate` only accepts modes that don't fit a detailed timing if they have a VIC.
+        let vics: Vec<u8> = vics.iter().filter_map(|info| info.cta_vic()).collect();
+
+        let mut edid = vec![0u8; EDID_DATA_LENGTH];
 
-        populate_header(&mut edid);
+        populate_header(&mut edid, &preferred.identifiers);
         populate_edid_version(&mut edid);
-        populate_standard_timings(&mut edid)?;
+        populate_chromaticity(&mut edid);
+        populate_standard_timings(&mut edid, preferred.refresh_rate)?;
 
-        // 4 available descriptor blocks
-        let block0 = &mut edid[54..72];
-        populate_detailed_timing(block0, info);
+        let base_detailed = if preferred.fits_detailed_timing() {
+            detailed.len().min(BASE_DETAILED_TIMINGS)
+        } else {
+            0
+        };
+        for (index, info) in detailed[..base_detailed].iter().enumerate() {
+            populate_detailed_timing(descriptor_mut(&mut edid, index), info)?;
+        }
+        populate_display_name(descriptor_mut(&mut edid, base_detailed))?;
+        let has_range_limits = base_detailed < BASE_DETAILED_TIMINGS;
+        if has_range_limits {
+            populate_range_limits(descriptor_mut(&mut edid, base_detailed + 1), modes)?;
+        }
+        populate_display_features(&mut edid, has_range_limits);
+
+        let extension_detailed = &detailed[base_detailed..];
+        if !extension_detailed.is_empty() || !vics.is_empty() {
+            edid[EXTENSION_COUNT_OFFSET] = 1;
+            let mut extension = [0u8; EDID_DATA_LENGTH];
+            populate_cta_extension(&mut extension, extension_detailed, &vics)?;
+            edid.extend_from_slice(&extension);
+        }
+
+        for block in edid.chunks_mut(EDID_DATA_LENGTH) {
+            calculate_checksum(block);
+        }
+
+        Ok(OkEdid(Self {
+            bytes: edid,
+            preferred_size: (preferred.width(), preferred.height()),
+        }))
+    }
+}
 
-        let block1 = &mut edid[72..90];
-        populate_display_name(block1);
+// Returns the descriptor block at `index` of the base block.
+fn descriptor_mut(edid: &mut [u8], index: usize) -> &mut [u8] {
+    let start = DESCRIPTORS_OFFSET + index * DESCRIPTOR_LENGTH;
+    &mut edid[start..start + DESCRIPTOR_LENGTH]
+}
 
-        calculate_checksum(&mut edid);
+// Returns an error unless `edid_block` is the size of an 18 byte descriptor.
+fn check_descriptor_len(edid_block: &[u8]) -> VirtioGpuResult {
+    if edid_block.len() != DESCRIPTOR_LENGTH {
+        return Err(ErrEdid(format!(
+            "descriptor block is {} bytes instead of {}",
+            edid_block.len(),
+            DESCRIPTOR_LENGTH
+        )));
+    }
+    Ok(OkNoData)
+}
 
-        Ok(OkEdid(Self { bytes: edid }))
+/// Generates the EDID the guest will read for a display added with `params`.
+///
+/// Displays added from the control socket are checked with this before being attached, so that
+/// parameters no EDID can describe are rejected with the reason instead of reaching the guest.
+pub fn display_params_edid(params: &DisplayParameters) -> Result<Vec<u8>, String> {
+    let (width, height) = params.get_virtual_display_size();
+    let identifiers = EdidIdentifiers::new(params, 0)?;
+    let info = DisplayInfo::new(width, height, params.refresh_rate).with_identifiers(identifiers);
+    match EdidBytes::new(&[info]) {
+        Ok(OkEdid(edid)) => Ok(edid.as_bytes().to_vec()),
+        Ok(_) => Err("unexpected EDID response".to_string()),
+        Err(ErrEdid(reason)) => Err(reason),
+        Err(e) => Err(e.to_string()),
     }
 }
 
-fn populate_display_name(edid_block: &mut [u8]) {
+fn populate_display_name(edid_block: &mut [u8]) -> VirtioGpuResult {
+    check_descriptor_len(edid_block)?;
+
     // Display Product Name String Descriptor Tag
-    edid_block[0..5].clone_from_slice(&[0x00, 0x00, 0x00, 0xFC, 0x00]);
-    edid_block[5..].clone_from_slice("CrosvmDisplay".as_bytes());
+    edid_block[0..5].copy_from_slice(&[0x00, 0x00, 0x00, 0xFC, 0x00]);
+    edid_block[5..].copy_from_slice(DISPLAY_NAME);
+    Ok(OkNoData)
+}
+
+// Encodes a rate of the display range limits descriptor, returning whether it is stored as an
+// offset from 255. Rates too large even for that are saturated.
+fn encode_range_rate(rate: u32) -> (u8, bool) {
+    if rate > RANGE_LIMITS_RATE_OFFSET {
+        let offset = (rate - RANGE_LIMITS_RATE_OFFSET).min(u8::MAX.into());
+        (offset as u8, true)
+    } else {
+        (rate as u8, false)
+    }
+}
+
+// Fills a display range limits descriptor covering the vertical and horizontal rates and pixel
+// clocks of `modes`, so that the guest accepts other modes within them.
+fn populate_range_limits(edid_block: &mut [u8], modes: &[DisplayInfo]) -> VirtioGpuResult {
+    check_descriptor_len(edid_block)?;
+
+    let mut min_vertical = u32::MAX;
+    let mut max_vertical = 0;
+    let mut min_horizontal = u64::MAX;
+    let mut max_horizontal = 0;
+    let mut max_clock = 0;
+    for info in modes {
+        min_vertical = min_vertical.min(info.refresh_rate);
+        max_vertical = max_vertical.max(info.refresh_rate);
+        // Horizontal rate in kHz, from the pixel clock in 10 kHz units.
+        let clock = info.pixel_clock().saturating_mul(10);
+        let htotal = u64::from(info.width()) + u64::from(info.horizontal_blanking);
+        min_horizontal = min_horizontal.min(clock / htotal);
+        max_horizontal = max_horizontal.max((clock + htotal - 1) / htotal);
+        // The maximum pixel clock is stored in 10 MHz units.
+        max_clock = max_clock.max((info.pixel_clock() + 999) / 1000);
+    }
+
+    let (min_vertical, min_vertical_offset) = encode_range_rate(min_vertical);
+    let (max_vertical, max_vertical_offset) = encode_range_rate(max_vertical);
+    let (min_horizontal, min_horizontal_offset) =
+        encode_range_rate(u32::try_from(min_horizontal.max(1)).unwrap_or(u32::MAX));
+    let (max_horizontal, max_horizontal_offset) =
+        encode_range_rate(u32::try_from(max_horizontal).unwrap_or(u32::MAX));
+
+    // Display Range Limits Descriptor Tag
+    edid_block[0..4].copy_from_slice(&[0x00, 0x00, 0x00, RANGE_LIMITS_TAG]);
+    // Which rates are offsets from 255. A minimum can only be one if its maximum also is.
+    edid_block[4] = u8::from(max_vertical_offset) << 1
+        | u8::from(min_vertical_offset)
+        | u8::from(max_horizontal_offset) << 3
+        | u8::from(min_horizontal_offset) << 2;
+    // Vertical rates in Hz.
+    edid_block[5] = min_vertical;
+    edid_block[6] = max_vertical;
+    // Horizontal rates in kHz.
+    edid_block[7] = min_horizontal;
+    edid_block[8] = max_horizontal;
+    edid_block[9] = max_clock.clamp(1, u8::MAX.into()) as u8;
+    edid_block[10] = RANGE_LIMITS_ONLY;
+    // No timing formula parameters: a line feed followed by spaces.
+    edid_block[11] = 0x0A;
+    edid_block[12..].fill(0x20);
+    Ok(OkNoData)
 }
 
-fn populate_detailed_timing(edid_block: &mut [u8], info: &DisplayInfo) {
-    assert_eq!(edid_block.len(), 18);
+fn populate_detailed_timing(edid_block: &mut [u8], info: &DisplayInfo) -> VirtioGpuResult {
+    check_descriptor_len(edid_block)?;
 
     // Detailed timings
     //
@@ -164,33 +524,12 @@ fn populate_detailed_timing(edid_block: &mut [u8], info: &DisplayInfo) {
     let vertical_blanking_lsb: u8 = (info.vertical_blanking & 0xFF) as u8;
     let vertical_blanking_msb: u8 = ((info.vertical_blanking >> 8) & 0x0F) as u8;
 
-    // The pixel clock is what controls the refresh timing information.
-    //
-    // The formula for getting refresh rate out of this value is:
-    //   refresh_rate = clk * 10000 / (htotal * vtotal)
-    // Solving for clk:
-    //   clk = (refresh_rate * htotal * votal) / 10000
-    //
-    // where:
-    //   clk - The setting here
-    //   vtotal - Total lines
-    //   htotal - Total pixels per line
-    //
-    // Value here is pixel clock + 10,000, in 10khz steps.
-    //
-    // Pseudocode of kernel logic for vrefresh:
-    //    vtotal := mode->vtotal;
-    //    calc_val := (clock * 1000) / htotal
-    //    refresh := (calc_val + vtotal / 2) / vtotal
-    //    if flags & INTERLACE: refresh *= 2
-    //    if flags & DBLSCAN: refresh /= 2
-    //    if vscan > 1: refresh /= vscan
-    //
-    let htotal = info.width() + (info.horizontal_blanking as u32);
-    let vtotal = info.height() + (info.vertical_blanking as u32);
-    let mut clock: u16 = ((info.refresh_rate * htotal * vtotal) / 10000) as u16;
-    // Round to nearest 10khz.
-    clock = ((clock + 5) / 10) * 10;
+    let clock = u16::try_from(info.pixel_clock()).map_err(|_| {
+        ErrEdid(format!(
+            "pixel clock {}0 kHz doesn't fit in the detailed timing descriptor",
+            info.pixel_clock()
+        ))
+    })?;
     edid_block[0..2].copy_from_slice(&clock.to_le_bytes());
 
     let width_lsb: u8 = (info.width() & 0xFF) as u8;
@@ -235,10 +574,11 @@ fn populate_detailed_timing(edid_block: &mut [u8], info: &DisplayInfo) {
         | (vertical_front_msb << 2)
         | (horizontal_sync_msb << 4)
         | (horizontal_front_msb << 6);
+    Ok(OkNoData)
 }
 
 // The EDID header. This is defined by the EDID spec.
-fn populate_header(edid: &mut [u8]) {
+fn populate_header(edid: &mut [u8], identifiers: &EdidIdentifiers) {
     edid[0] = 0x00;
     edid[1] = 0xFF;
     edid[2] = 0xFF;
@@ -248,19 +588,17 @@ fn populate_header(edid: &mut [u8]) {
     edid[6] = 0xFF;
     edid[7] = 0x00;
 
-    let manufacturer_name: [char; 3] = ['G', 'G', 'L'];
     // 00001 -> A, 00010 -> B, etc
-    let manufacturer_id: u16 = manufacturer_name
+    let manufacturer_id: u16 = identifiers
+        .manufacturer
         .iter()
-        .map(|c| (*c as u8 - b'A' + 1) & 0x1F)
+        .map(|c| (*c - b'A' + 1) & 0x1F)
         .fold(0u16, |res, lsb| (res << 5) | (lsb as u16));
     edid[8..10].copy_from_slice(&manufacturer_id.to_be_bytes());
 
-    let manufacture_product_id: u16 = 1;
-    edid[10..12].copy_from_slice(&manufacture_product_id.to_le_bytes());
+    edid[10..12].copy_from_slice(&identifiers.product_id.to_le_bytes());
 
-    let serial_id: u32 = 1;
-    edid[12..16].copy_from_slice(&serial_id.to_le_bytes());
+    edid[12..16].copy_from_slice(&identifiers.serial.to_le_bytes());
 
     let manufacture_week: u8 = 8;
     edid[16] = manufacture_week;
@@ -269,9 +607,35 @@ fn populate_header(edid: &mut [u8]) {
     edid[17] = (manufacture_year - 1990u32) as u8;
 }
 
+// Fills the gamma and feature support bytes of a display using the sRGB color space.
+// `continuous_frequency` tells whether the base block has a display range limits descriptor.
+fn populate_display_features(edid: &mut [u8], continuous_frequency: bool) {
+    edid[GAMMA_OFFSET] = SRGB_GAMMA;
+    edid[FEATURE_SUPPORT_OFFSET] = if continuous_frequency {
+        FEATURE_SUPPORT | FEATURE_CONTINUOUS_FREQUENCY
+    } else {
+        FEATURE_SUPPORT
+    };
+}
+
+// Fills the chromaticity coordinates with those of sRGB. The 2 low bits of each 10-bit coordinate
+// are packed in the first two bytes, followed by the 8 high bits of each.
+fn populate_chromaticity(edid: &mut [u8]) {
+    let coordinates: Vec<u16> = SRGB_CHROMATICITY
+        .iter()
+        .flat_map(|&(x, y)| [x, y])
+        .collect();
+    for (index, coordinate) in coordinates.iter().enumerate() {
+        edid[CHROMATICITY_OFFSET + index / 4] |=
+            ((coordinate & 0x3) as u8) << (6 - 2 * (index % 4));
+        edid[CHROMATICITY_OFFSET + 2 + index] = (coordinate >> 2) as u8;
+    }
+}
+
 // The standard timings are 8 timing modes with a lower priority (and different data format)
-// than the 4 detailed timing modes.
-fn populate_standard_timings(edid: &mut [u8]) -> VirtioGpuResult {
+// than the 4 detailed timing modes. They use `refresh_rate` when they can encode it, 60Hz
+// otherwise.
+fn populate_standard_timings(edid: &mut [u8], refresh_rate: u32) -> VirtioGpuResult {
     let resolutions = [
         Resolution::new(1440, 900),
         Resolution::new(1600, 900),
@@ -283,11 +647,21 @@ fn populate_standard_timings(edid: &mut [u8]) -> VirtioGpuResult {
         Resolution::new(1920, 1200),
     ];
 
+    let refresh_rate = if (STANDARD_TIMING_MIN_REFRESH_RATE..=STANDARD_TIMING_MAX_REFRESH_RATE)
+        .contains(&refresh_rate)
+    {
+        refresh_rate
+    } else {
+        STANDARD_TIMING_MIN_REFRESH_RATE
+    };
+
     // Index 0 is horizontal pixels / 8 - 31
-    // Index 1 is a combination of the refresh_rate - 60 (so we are setting to 0, for now) and two
-    // bits for the aspect ratio.
+    // Index 1 is a combination of two bits for the aspect ratio and the refresh_rate - 60.
     for (index, r) in resolutions.iter().enumerate() {
-        edid[0x26 + (index * 2)] = (r.width / 8 - 31) as u8;
+        edid[STANDARD_TIMINGS_OFFSET + (index * 2)] = (r.width / 8)
+            .checked_sub(31)
+            .and_then(|w| u8::try_from(w).ok())
+            .ok_or_else(|| ErrEdid(format!("Unsupported standard timing width: {}", r.width)))?;
         let ar_bits = match r.get_aspect_ratio() {
             (8, 5) => 0x0,
             (4, 3) => 0x1,
@@ -295,7 +669,49 @@ fn populate_standard_timings(edid: &mut [u8]) -> VirtioGpuResult {
             (16, 9) => 0x3,
             (x, y) => return Err(ErrEdid(format!("Unsupported aspect ratio: {} {}", x, y))),
         };
-        edid[0x27 + (index * 2)] = ar_bits;
+        edid[STANDARD_TIMINGS_OFFSET + 1 + (index * 2)] =
+            (ar_bits << 6) | (refresh_rate - STANDARD_TIMING_MIN_REFRESH_RATE) as u8;
+    }
+    Ok(OkNoData)
+}
+
+// Fills a CTA-861 extension block with a video data block listing `vics` and the `detailed`
+// timings following the data blocks.
+fn populate_cta_extension(
+    extension: &mut [u8],
+    detailed: &[&DisplayInfo],
+    vics: &[u8],
+) -> VirtioGpuResult {
+    if vics.len() > CTA_MAX_DATA_BLOCK_LENGTH {
+        return Err(ErrEdid(format!(
+            "{} CTA-861 video formats don't fit in a video data block",
+            vics.len()
+        )));
+    }
+    let mut offset = CTA_DATA_BLOCKS_OFFSET;
+    if !vics.is_empty() {
+        extension[offset] = (CTA_VIDEO_DATA_BLOCK_TAG << 5) | vics.len() as u8;
+        extension[offset + 1..offset + 1 + vics.len()].copy_from_slice(vics);
+        offset += 1 + vics.len();
+    }
+
+    // The last byte is the checksum.
+    let room = (EDID_DATA_LENGTH - 1 - offset) / DESCRIPTOR_LENGTH;
+    if detailed.len() > room {
+        return Err(ErrEdid(format!(
+            "{} more detailed timings don't fit in the extension block, which has room for {}",
+            detailed.len(),
+            room
+        )));
+    }
+
+    extension[0] = CTA_EXTENSION_TAG;
+    extension[1] = CTA_EXTENSION_REVISION;
+    // Offset of the detailed timings, which follow the data blocks.
+    extension[2] = offset as u8;
+    for info in detailed {
+        populate_detailed_timing(&mut extension[offset..offset + DESCRIPTOR_LENGTH], info)?;
+        offset += DESCRIPTOR_LENGTH;
     }
     Ok(OkNoData)
 }
@@ -306,9 +722,10 @@ fn populate_edid_version(edid: &mut [u8]) {
     edid[19] = 4;
 }
 
-fn calculate_checksum(edid: &mut [u8]) {
+// Sets the last byte of a 128 byte EDID block so that the block sums to 0.
+fn calculate_checksum(block: &mut [u8]) {
     let mut checksum: u8 = 0;
-    for byte in edid.iter().take(EDID_DATA_LENGTH - 1) {
+    for byte in block.iter().take(EDID_DATA_LENGTH - 1) {
         checksum = checksum.wrapping_add(*byte);
     }
 
@@ -316,5 +733,496 @@ fn calculate_checksum(edid: &mut [u8]) {
         checksum = 255 - checksum + 1;
     }
 
-    edid[127] = checksum;
+    block[EDID_DATA_LENGTH - 1] = checksum;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(width: u32, height: u32, refresh_rate: u32) -> Result<(), String> {
+        let info = DisplayInfo::new(width, height, refresh_rate);
+        let validated = info.validate();
+        // `EdidBytes::new` must agree with `validate`.
+        match EdidBytes::new(&[info]) {
+            Ok(OkEdid(_)) => assert!(validated.is_ok()),
+            Err(ErrEdid(e)) => assert_eq!(validated, Err(e)),
+            _ => panic!("unexpected EdidBytes::new result"),
+        }
+        validated
+    }
+
+    #[test]
+    fn accepts_common_modes() {
+        assert!(check(1280, 1024, 60).is_ok());
+        assert!(check(1920, 1080, 60).is_ok());
+        assert!(check(3840, 2160, 30).is_ok());
+    }
+
+    #[test]
+    fn size_bounds() {
+        assert!(check(0, 0, 60).unwrap_err().contains("smaller"));
+        assert!(check(MIN_WIDTH, MIN_HEIGHT, 60).is_ok());
+        assert!(check(MIN_WIDTH - 1, MIN_HEIGHT, 60)
+            .unwrap_err()
+            .contains("smaller"));
+        assert!(check(MIN_WIDTH, MIN_HEIGHT - 1, 60)
+            .unwrap_err()
+            .contains("smaller"));
+        assert!(check(MAX_WIDTH, 1024, 30).is_ok());
+        assert!(check(MAX_WIDTH + 1, 1024, 30)
+            .unwrap_err()
+            .contains("larger"));
+        assert!(check(1024, MAX_HEIGHT, 30).is_ok());
+        assert!(check(1024, MAX_HEIGHT + 1, 30)
+            .unwrap_err()
+            .contains("larger"));
+        assert!(check(100000, 2, 60).is_err());
+    }
+
+    #[test]
+    fn refresh_rate_bounds() {
+        assert!(check(640, 480, MIN_REFRESH_RATE).is_ok());
+        assert!(check(640, 480, MIN_REFRESH_RATE - 1)
+            .unwrap_err()
+            .contains("refresh rate"));
+        assert!(check(640, 480, MAX_REFRESH_RATE).is_ok());
+        assert!(check(640, 480, MAX_REFRESH_RATE + 1)
+            .unwrap_err()
+            .contains("refresh rate"));
+    }
+
+    #[test]
+    fn pixel_clock_bound() {
+        // Both dimensions and the refresh rate are in range, but the pixel clock doesn't fit.
+        assert!(check(MAX_WIDTH, MAX_HEIGHT, MAX_REFRESH_RATE)
+            .unwrap_err()
+            .contains("pixel clock"));
+        assert!(check(MAX_WIDTH, MAX_HEIGHT, 30).is_ok());
+        // Too large for a detailed timing, but described by a CTA-861 VIC.
+        assert!(check(3840, 2160, 120).is_ok());
+        assert!(check(7680, 4320, 60).is_ok());
+        assert!(check(3840, 2160, 119).unwrap_err().contains("pixel clock"));
+    }
+
+    #[test]
+    fn checksum_is_valid() {
+        let edid = match EdidBytes::new(&[DisplayInfo::new(1920, 1080, 60)]) {
+            Ok(OkEdid(edid)) => edid,
+            _ => panic!("failed to create EDID"),
+        };
+        let sum = edid
+            .as_bytes()
+            .iter()
+            .fold(0u8, |sum, b| sum.wrapping_add(*b));
+        assert_eq!(sum, 0);
+    }
+
+    fn edid(modes: &[DisplayInfo]) -> EdidBytes {
+        match EdidBytes::new(modes) {
+            Ok(OkEdid(edid)) => edid,
+            _ => panic!("failed to create EDID"),
+        }
+    }
+
+    // Decodes the (width, height, refresh rate) of a detailed timing descriptor.
+    fn decode_detailed_timing(block: &[u8]) -> (u32, u32, u32) {
+        let clock = u64::from(u16::from_le_bytes([block[0], block[1]])) * 10000;
+        let width = u32::from(block[2]) | (u32::from(block[4] >> 4) << 8);
+        let hblank = u32::from(block[3]) | (u32::from(block[4] & 0xF) << 8);
+        let height = u32::from(block[5]) | (u32::from(block[7] >> 4) << 8);
+        let vblank = u32::from(block[6]) | (u32::from(block[7] & 0xF) << 8);
+        let total = u64::from(width + hblank) * u64::from(height + vblank);
+        let refresh_rate = (clock + total / 2) / total;
+        (width, height, refresh_rate as u32)
+    }
+
+    fn is_detailed_timing(block: &[u8]) -> bool {
+        block[0] != 0 || block[1] != 0
+    }
+
+    fn block_sum(block: &[u8]) -> u8 {
+        block.iter().fold(0u8, |sum, b| sum.wrapping_add(*b))
+    }
+
+    #[test]
+    fn single_mode_has_no_extension() {
+        let edid = edid(&[DisplayInfo::new(1920, 1080, 60)]);
+        let bytes = edid.as_bytes();
+        assert_eq!(bytes.len(), EDID_DATA_LENGTH);
+        assert_eq!(bytes[EXTENSION_COUNT_OFFSET], 0);
+        assert_eq!(decode_detailed_timing(&bytes[54..72]), (1920, 1080, 60));
+        assert_eq!(&bytes[77..90], DISPLAY_NAME);
+        assert_eq!(edid.preferred_size(), (1920, 1080));
+    }
+
+    #[test]
+    fn multiple_modes() {
+        let modes = [
+            DisplayInfo::new(1920, 1080, 60),
+            DisplayInfo::new(1280, 720, 60),
+            DisplayInfo::new(3840, 2160, 120),
+            DisplayInfo::new(1024, 768, 75),
+            DisplayInfo::new(800, 600, 60),
+        ];
+        let edid = edid(&modes);
+        let bytes = edid.as_bytes();
+        assert_eq!(bytes.len(), 2 * EDID_DATA_LENGTH);
+        let (base, extension) = bytes.split_at(EDID_DATA_LENGTH);
+        assert_eq!(block_sum(base), 0);
+        assert_eq!(block_sum(extension), 0);
+        assert_eq!(base[EXTENSION_COUNT_OFFSET], 1);
+        assert_eq!(edid.preferred_size(), (1920, 1080));
+
+        // Three detailed timings in the base block, in order, then the display name.
+        let base_modes: Vec<_> = base[54..108]
+            .chunks(DESCRIPTOR_LENGTH)
+            .map(decode_detailed_timing)
+            .collect();
+        assert_eq!(
+            base_modes,
+            [(1920, 1080, 60), (1280, 720, 60), (1024, 768, 75)]
+        );
+        assert_eq!(&base[108..113], &[0x00, 0x00, 0x00, 0xFC, 0x00]);
+
+        // The extension lists the 4K mode by its VIC and has the last detailed timing.
+        assert_eq!(extension[0], CTA_EXTENSION_TAG);
+        assert_eq!(extension[1], CTA_EXTENSION_REVISION);
+        assert_eq!(
+            &extension[4..6],
+            &[(CTA_VIDEO_DATA_BLOCK_TAG << 5) | 1, 118]
+        );
+        let dtd_offset = extension[2] as usize;
+        assert_eq!(dtd_offset, 6);
+        assert_eq!(
+            decode_detailed_timing(&extension[dtd_offset..dtd_offset + DESCRIPTOR_LENGTH]),
+            (800, 600, 60)
+        );
+        assert!(!is_detailed_timing(
+            &extension[dtd_offset + DESCRIPTOR_LENGTH..]
+        ));
+    }
+
+    #[test]
+    fn preferred_mode_with_vic() {
+        let edid = edid(&[
+            DisplayInfo::new(3840, 2160, 120),
+            DisplayInfo::new(1920, 1080, 60),
+        ]);
+        let bytes = edid.as_bytes();
+        assert_eq!(edid.preferred_size(), (3840, 2160));
+        // No detailed timing in the base block could be taken for the preferred mode.
+        assert!(!is_detailed_timing(&bytes[54..72]));
+        assert_eq!(&bytes[54..59], &[0x00, 0x00, 0x00, 0xFC, 0x00]);
+        let extension = &bytes[EDID_DATA_LENGTH..];
+        assert_eq!(
+            &extension[4..6],
+            &[(CTA_VIDEO_DATA_BLOCK_TAG << 5) | 1, 118]
+        );
+        assert_eq!(decode_detailed_timing(&extension[6..24]), (1920, 1080, 60));
+        assert_eq!(block_sum(&bytes[..EDID_DATA_LENGTH]), 0);
+        assert_eq!(block_sum(extension), 0);
+    }
+
+    // Decodes the (manufacturer, product, serial) from the EDID header.
+    fn decode_identifiers(edid: &[u8]) -> (String, u16, u32) {
+        let id = u16::from_be_bytes([edid[8], edid[9]]);
+        let manufacturer = [10, 5, 0]
+            .iter()
+            .map(|shift| (b'A' - 1 + ((id >> shift) & 0x1F) as u8) as char)
+            .collect();
+        let product = u16::from_le_bytes([edid[10], edid[11]]);
+        let serial = u32::from_le_bytes(edid[12..16].try_into().unwrap());
+        (manufacturer, product, serial)
+    }
+
+    #[test]
+    fn identifiers() {
+        let info = DisplayInfo::new(1920, 1080, 60);
+        let bytes = edid(&[info]).as_bytes().to_vec();
+        assert_eq!(decode_identifiers(&bytes), ("GGL".to_string(), 1, 1));
+
+        let params = DisplayParameters {
+            manufacturer: Some("xyz".to_string()),
+            product_id: Some(0x1234),
+            serial: Some(0xdeadbeef),
+            ..Default::default()
+        };
+        let identifiers = EdidIdentifiers::new(&params, 3).unwrap();
+        let bytes = edid(&[info.with_identifiers(identifiers)])
+            .as_bytes()
+            .to_vec();
+        assert_eq!(
+            decode_identifiers(&bytes),
+            ("XYZ".to_string(), 0x1234, 0xdeadbeef)
+        );
+        assert_eq!(block_sum(&bytes), 0);
+
+        // Displays without a serial number get distinct ones.
+        let params = DisplayParameters::default();
+        let first = EdidIdentifiers::new(&params, 0).unwrap();
+        let second = EdidIdentifiers::new(&params, 1).unwrap();
+        assert_eq!(first, EdidIdentifiers::default());
+        assert_ne!(first, second);
+
+        let params = DisplayParameters {
+            manufacturer: Some("G1L".to_string()),
+            ..Default::default()
+        };
+        assert!(EdidIdentifiers::new(&params, 0).is_err());
+        assert!(display_params_edid(&params).is_err());
+    }
+
+    #[test]
+    fn too_many_modes() {
+        // 3 detailed timings fit in the base block and 6 in the extension.
+        let modes: Vec<_> = (0..10)
+            .map(|i| DisplayInfo::new(640 + 16 * i, 480, 60))
+            .collect();
+        assert!(EdidBytes::new(&modes[..9]).is_ok());
+        assert!(EdidBytes::new(&modes).is_err());
+        assert!(EdidBytes::new(&[]).is_err());
+    }
+
+    #[test]
+    fn descriptor_size_mismatch_is_an_error() {
+        let info = DisplayInfo::new(1920, 1080, 60);
+        let mut block = [0u8; DESCRIPTOR_LENGTH + 1];
+        assert!(populate_detailed_timing(&mut block, &info).is_err());
+        assert!(populate_display_name(&mut block).is_err());
+        assert!(populate_detailed_timing(&mut block[..DESCRIPTOR_LENGTH], &info).is_ok());
+        assert!(populate_display_name(&mut block[..DESCRIPTOR_LENGTH - 1]).is_err());
+    }
+
+    #[test]
+    fn extreme_values_do_not_panic() {
+        for value in [0, 1, u32::MAX] {
+            assert!(check(value, value, value).is_err());
+            assert!(check(1920, 1080, value).is_err());
+        }
+    }
+
+    struct RangeLimits {
+        // (min, max) vertical rates in Hz.
+        vertical: (u32, u32),
+        // (min, max) horizontal rates in kHz.
+        horizontal: (u32, u32),
+        // Maximum pixel clock in 10 MHz units.
+        max_clock: u32,
+    }
+
+    // Decodes a display range limits descriptor, checking it like a guest EDID parser does.
+    fn decode_range_limits(block: &[u8]) -> Result<RangeLimits, String> {
+        let flags = block[4];
+        let rate = |byte: u8, offset_bit: u8| {
+            u32::from(byte)
+                + if flags & (1 << offset_bit) != 0 {
+                    255
+                } else {
+                    0
+                }
+        };
+        // A minimum rate may only be offset if its maximum is.
+        if flags & 0x3 == 0x1 || flags & 0xC == 0x4 || flags & 0xF0 != 0 {
+            return Err(format!("bad range descriptor rate offsets {:#x}", flags));
+        }
+        let vertical = (rate(block[5], 0), rate(block[6], 1));
+        let horizontal = (rate(block[7], 2), rate(block[8], 3));
+        for (min, max) in [vertical, horizontal] {
+            if min == 0 || min > max {
+                return Err(format!("bad range descriptor rates {}-{}", min, max));
+            }
+        }
+        if block[9] == 0 {
+            return Err("range descriptor has no maximum pixel clock".to_string());
+        }
+        if block[10] == RANGE_LIMITS_ONLY
+            && (block[11] != 0x0A || block[12..].iter().any(|&b| b != 0x20))
+        {
+            return Err("bad range descriptor padding".to_string());
+        }
+        Ok(RangeLimits {
+            vertical,
+            horizontal,
+            max_clock: u32::from(block[9]),
+        })
+    }
+
+    // Checks the structure of an EDID the way a guest EDID parser does, returning the first
+    // problem found.
+    fn validate_edid(bytes: &[u8]) -> Result<(), String> {
+        if bytes.is_empty() || bytes.len() % EDID_DATA_LENGTH != 0 {
+            return Err(format!(
+                "EDID length {} isn't a number of blocks",
+                bytes.len()
+            ));
+        }
+        for (index, block) in bytes.chunks(EDID_DATA_LENGTH).enumerate() {
+            if block_sum(block) != 0 {
+                return Err(format!("EDID block {} checksum invalid", index));
+            }
+        }
+        let (base, extensions) = bytes.split_at(EDID_DATA_LENGTH);
+        if base[..8] != [0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00] {
+            return Err("bad EDID header".to_string());
+        }
+        if base[18..20] != [1, 4] {
+            return Err(format!("unexpected EDID version {}.{}", base[18], base[19]));
+        }
+        if usize::from(base[EXTENSION_COUNT_OFFSET]) != extensions.len() / EDID_DATA_LENGTH {
+            return Err("extension count doesn't match the extension blocks".to_string());
+        }
+
+        let low_bits = u16::from_be_bytes([base[25], base[26]]);
+        for index in 0..8 {
+            let coordinate =
+                u16::from(base[27 + index]) << 2 | (low_bits >> (14 - 2 * index)) & 0x3;
+            if coordinate == 0 {
+                return Err(format!("chromaticity coordinate {} is zero", index));
+            }
+        }
+
+        for timing in base[STANDARD_TIMINGS_OFFSET..STANDARD_TIMINGS_OFFSET + 16].chunks(2) {
+            if timing != [0x01, 0x01] && timing[0] == 0 {
+                return Err(format!("bad standard timing {:?}", timing));
+            }
+        }
+
+        let mut detailed_timings = Vec::new();
+        let mut range_limits = None;
+        for block in base[DESCRIPTORS_OFFSET..EXTENSION_COUNT_OFFSET].chunks(DESCRIPTOR_LENGTH) {
+            if is_detailed_timing(block) {
+                detailed_timings.push(block);
+            } else if block[..3] != [0, 0, 0] {
+                return Err(format!("bad display descriptor {:?}", block));
+            } else if block[3] == RANGE_LIMITS_TAG {
+                range_limits = Some(decode_range_limits(block)?);
+            } else if block[4] != 0 {
+                return Err(format!("bad display descriptor {:?}", block));
+            }
+        }
+        if let Some(extension) = extensions.get(..EDID_DATA_LENGTH) {
+            let mut offset = usize::from(extension[2]);
+            while offset + DESCRIPTOR_LENGTH < EDID_DATA_LENGTH
+                && is_detailed_timing(&extension[offset..offset + DESCRIPTOR_LENGTH])
+            {
+                detailed_timings.push(&extension[offset..offset + DESCRIPTOR_LENGTH]);
+                offset += DESCRIPTOR_LENGTH;
+            }
+        }
+
+        let continuous = base[FEATURE_SUPPORT_OFFSET] & FEATURE_CONTINUOUS_FREQUENCY != 0;
+        if continuous != range_limits.is_some() {
+            return Err("continuous frequency flag doesn't match the range limits".to_string());
+        }
+        // The guest discards the detailed timings outside of the range limits.
+        if let Some(limits) = range_limits {
+            for block in detailed_timings {
+                let (width, _, refresh_rate) = decode_detailed_timing(block);
+                let clock = u32::from(u16::from_le_bytes([block[0], block[1]]));
+                let htotal = width + (u32::from(block[3]) | (u32::from(block[4] & 0xF) << 8));
+                let horizontal_rate = clock * 10 / htotal;
+                if !(limits.vertical.0..=limits.vertical.1).contains(&refresh_rate)
+                    || !(limits.horizontal.0..=limits.horizontal.1).contains(&horizontal_rate)
+                    || clock > limits.max_clock * 1000
+                {
+                    return Err(format!(
+                        "detailed timing {:?} outside of the range limits",
+                        block
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn edid_structure_is_valid() {
+        let mode_lists = [
+            vec![DisplayInfo::new(1920, 1080, 60)],
+            vec![DisplayInfo::new(MIN_WIDTH, MIN_HEIGHT, MIN_REFRESH_RATE)],
+            vec![DisplayInfo::new(640, 480, MAX_REFRESH_RATE)],
+            vec![
+                DisplayInfo::new(1920, 1080, 75),
+                DisplayInfo::new(1280, 720, 60),
+            ],
+            vec![
+                DisplayInfo::new(3840, 2160, 120),
+                DisplayInfo::new(1920, 1080, 60),
+            ],
+            (0..9)
+                .map(|i| DisplayInfo::new(640 + 16 * i, 480, 50 + i))
+                .collect(),
+        ];
+        for modes in &mode_lists {
+            let edid = edid(modes);
+            if let Err(e) = validate_edid(edid.as_bytes()) {
+                panic!("invalid EDID for {} modes: {}", modes.len(), e);
+            }
+        }
+    }
+
+    #[test]
+    fn chromaticity_and_range_limits() {
+        let bytes = edid(&[
+            DisplayInfo::new(1920, 1080, 75),
+            DisplayInfo::new(1280, 720, 60),
+        ])
+        .as_bytes()
+        .to_vec();
+        // The sRGB primaries and white point.
+        assert_eq!(
+            &bytes[25..35],
+            &[0xEE, 0x91, 0xA3, 0x54, 0x4C, 0x99, 0x26, 0x0F, 0x50, 0x54]
+        );
+        assert_eq!(bytes[GAMMA_OFFSET], SRGB_GAMMA);
+        assert_eq!(
+            bytes[FEATURE_SUPPORT_OFFSET],
+            FEATURE_SUPPORT | FEATURE_CONTINUOUS_FREQUENCY
+        );
+        // The range limits follow the display name, after the two detailed timings.
+        assert_eq!(&bytes[90..95], &[0x00, 0x00, 0x00, 0xFC, 0x00]);
+        let range_limits = &bytes[108..126];
+        assert_eq!(range_limits[3], RANGE_LIMITS_TAG);
+        assert_eq!(
+            decode_range_limits(range_limits).unwrap().vertical,
+            (60, 75)
+        );
+
+        // Rates above 255 are offsets from 255.
+        let bytes = edid(&[DisplayInfo::new(640, 480, 300)]).as_bytes().to_vec();
+        let range_limits = &bytes[90..108];
+        assert_eq!(range_limits[4] & 0x3, 0x3);
+        assert_eq!(
+            decode_range_limits(range_limits).unwrap().vertical,
+            (300, 300)
+        );
+
+        // Without room left in the base block, there are no range limits.
+        let modes: Vec<_> = (0..3)
+            .map(|i| DisplayInfo::new(640 + 16 * i, 480, 60))
+            .collect();
+        let bytes = edid(&modes).as_bytes().to_vec();
+        assert_eq!(bytes[FEATURE_SUPPORT_OFFSET], FEATURE_SUPPORT);
+        assert!(!bytes[54..126]
+            .chunks(DESCRIPTOR_LENGTH)
+            .any(|block| block[..4] == [0x00, 0x00, 0x00, RANGE_LIMITS_TAG]));
+    }
+
+    #[test]
+    fn standard_timings_refresh_rate() {
+        // 1440x900 is 16:10, 1600x900 is 16:9.
+        let bytes = edid(&[DisplayInfo::new(1920, 1080, 75)])
+            .as_bytes()
+            .to_vec();
+        assert_eq!(bytes[0x26..0x2A], [0x95, 15, 0xA9, (3 << 6) | 15]);
+        // Rates a standard timing can't encode fall back to 60Hz.
+        for refresh_rate in [30, 60, 144] {
+            let bytes = edid(&[DisplayInfo::new(1920, 1080, refresh_rate)])
+                .as_bytes()
+                .to_vec();
+            assert_eq!(bytes[0x27], 0);
+            assert_eq!(bytes[0x29], 3 << 6);
+        }
+    }
 }
@@ -0,0 +1,134 @@
+// Copyright 2022 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Gets the guest to read the EDIDs regenerated while their displays stay connected.
+//!
+//! When a display changes, the guest gets a config change interrupt with `VIRTIO_GPU_EVENT_DISPLAY`
+//! set, upon which it is expected to read the display info and the EDIDs again. Some guests only
+//! read EDIDs when a display is plugged in, so if an EDID is still stale a while after the
+//! interrupt, its display is reported disconnected, then connected again `RECONNECT_DELAY` later.
+
+use std::time::Duration;
+
+use base::AsRawDescriptor;
+use base::Result;
+use base::Timer;
+
+/// How long the displays stay disconnected when simulating a hotplug.
+pub const RECONNECT_DELAY: Duration = Duration::from_millis(200);
+
+/// What to do when the timer fires.
+#[derive(Debug, PartialEq, Eq)]
+pub enum EdidRereadStep {
+    /// Disconnect the displays whose EDID the guest didn't read since it was notified.
+    DisconnectStale,
+    /// Connect the disconnected displays again.
+    Reconnect,
+}
+
+enum State {
+    Idle,
+    Notified,
+    Disconnected,
+}
+
+pub struct EdidReread {
+    timer: Timer,
+    timeout: Option<Duration>,
+    state: State,
+}
+
+impl EdidReread {
+    /// Creates the tracker, which gives the guest `timeout` to read the EDIDs after it is
+    /// notified. Hotplugs are never simulated if `timeout` is `None`.
+    pub fn new(timeout: Option<Duration>) -> Result<EdidReread> {
+        Ok(EdidReread {
+            timer: Timer::new()?,
+            timeout,
+            state: State::Idle,
+        })
+    }
+
+    /// Records that the guest was notified of display changes, restarting the timeout.
+    pub fn notified(&mut self) -> Result<()> {
+        match (self.timeout, &self.state) {
+            // The pending reconnect makes the guest read the EDIDs anyway.
+            (_, State::Disconnected) | (None, _) => {}
+            (Some(timeout), _) => {
+                self.timer.reset(timeout, None)?;
+                self.state = State::Notified;
+            }
+        }
+        Ok(())
+    }
+
+    /// Records that the stale displays were disconnected, arming the timer to reconnect them.
+    pub fn disconnected(&mut self) -> Result<()> {
+        self.timer.reset(RECONNECT_DELAY, None)?;
+        self.state = State::Disconnected;
+        Ok(())
+    }
+
+    /// Handles the timer firing and returns what to do, if anything.
+    pub fn timer_fired(&mut self) -> Option<EdidRereadStep> {
+        let _ = self.timer.mark_waited();
+        match std::mem::replace(&mut self.state, State::Idle) {
+            State::Idle => None,
+            State::Notified => Some(EdidRereadStep::DisconnectStale),
+            State::Disconnected => Some(EdidRereadStep::Reconnect),
+        }
+    }
+
+    /// The timer to wait on for `timer_fired`.
+    pub fn timer(&self) -> &dyn AsRawDescriptor {
+        &self.timer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use base::EventToken;
+    use base::WaitContext;
+
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_millis(20);
+
+    #[derive(EventToken)]
+    enum Token {
+        Timer,
+    }
+
+    // Waits for the timer and returns the step, or `None` if it doesn't fire.
+    fn next_step(reread: &mut EdidReread) -> Option<EdidRereadStep> {
+        let wait_ctx = WaitContext::build_with(&[(reread.timer(), Token::Timer)]).unwrap();
+        let events = wait_ctx.wait_timeout(RECONNECT_DELAY * 5).unwrap();
+        match events.iter().find(|e| e.is_readable)?.token {
+            Token::Timer => reread.timer_fired(),
+        }
+    }
+
+    #[test]
+    fn disconnect_then_reconnect() {
+        let mut reread = EdidReread::new(Some(TIMEOUT)).unwrap();
+        reread.notified().unwrap();
+        assert_eq!(
+            next_step(&mut reread),
+            Some(EdidRereadStep::DisconnectStale)
+        );
+
+        reread.disconnected().unwrap();
+        // Notifications while disconnected don't delay the reconnection.
+        reread.notified().unwrap();
+        assert_eq!(next_step(&mut reread), Some(EdidRereadStep::Reconnect));
+        assert_eq!(next_step(&mut reread), None);
+    }
+
+    #[test]
+    fn disabled() {
+        let mut reread = EdidReread::new(None).unwrap();
+        reread.notified().unwrap();
+        assert_eq!(next_step(&mut reread), None);
+    }
+}
@@ -2,7 +2,12 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
+mod display_batch;
+mod display_changes;
+mod display_state;
+mod display_trace;
 mod edid;
+mod edid_reread;
 mod parameters;
 mod protocol;
 mod virtio_gpu;
@@ -18,6 +23,7 @@ use std::sync::atomic::AtomicBool;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::thread;
+use std::time::Duration;
 
 use anyhow::Context;
 use base::debug;
@@ -31,26 +37,34 @@ use base::Result;
 use base::SafeDescriptor;
 use base::SendTube;
 use base::Tube;
-use base::VmEventType;
+use base::VmExitReason;
 use base::WaitContext;
 use data_model::*;
 pub use gpu_display::EventDevice;
+pub use gpu_display::EventDeviceDisplay;
 use gpu_display::*;
 pub use parameters::GpuParameters;
 use rutabaga_gfx::*;
 use serde::Deserialize;
 use serde::Serialize;
 use sync::Mutex;
+use vm_control::gpu::serve_gpu_control;
 pub use vm_control::gpu::DisplayMode as GpuDisplayMode;
 pub use vm_control::gpu::DisplayParameters as GpuDisplayParameters;
 use vm_control::gpu::GpuControlCommand;
 use vm_control::gpu::GpuControlResult;
+use vm_control::gpu::GpuControlServed;
 pub use vm_control::gpu::DEFAULT_DISPLAY_HEIGHT;
 pub use vm_control::gpu::DEFAULT_DISPLAY_WIDTH;
 pub use vm_control::gpu::DEFAULT_REFRESH_RATE;
 use vm_memory::GuestAddress;
 use vm_memory::GuestMemory;
 
+use self::display_changes::DisplayChanges;
+use self::display_changes::DISPLAY_CHANGE_WINDOW;
+pub use self::edid::display_params_edid;
+use self::edid_reread::EdidReread;
+use self::edid_reread::EdidRereadStep;
 pub use self::protocol::virtio_gpu_config;
 pub use self::protocol::VIRTIO_GPU_F_CONTEXT_INIT;
 pub use self::protocol::VIRTIO_GPU_F_CREATE_GUEST_HANDLE;
@@ -234,6 +248,46 @@ fn build(
     )
 }
 
+/// Answers the commands received on `gpu_control_tube` with
+/// `GpuControlResult::DisplayBackendUnavailable` until `kill_evt` is signaled, for when the device
+/// failed to initialize, so that requesters learn why instead of timing out.
+fn serve_gpu_control_unavailable(gpu_control_tube: &Tube, kill_evt: &Event) {
+    let wait_ctx = match WaitContext::build_with(&[
+        (gpu_control_tube, WorkerToken::GpuControl),
+        (kill_evt, WorkerToken::Kill),
+    ]) {
+        Ok(wait_ctx) => wait_ctx,
+        Err(e) => {
+            error!("failed creating WaitContext: {}", e);
+            return;
+        }
+    };
+
+    loop {
+        let events = match wait_ctx.wait() {
+            Ok(events) => events,
+            Err(e) => {
+                error!("failed polling for events: {}", e);
+                return;
+            }
+        };
+        for event in events.iter() {
+            if event.token != WorkerToken::GpuControl || !event.is_readable {
+                return;
+            }
+            let served = serve_gpu_control(gpu_control_tube, |_| {
+                GpuControlResult::DisplayBackendUnavailable {
+                    reason: "the gpu device failed to initialize, see the crosvm log".to_string(),
+                }
+            });
+            if let GpuControlServed::RecvFailed(e) = served {
+                error!("gpu control socket failed recv: {}", e);
+                return;
+            }
+        }
+    }
+}
+
 /// Create a handler that writes into the completed fence queue
 pub fn create_fence_handler<Q>(
     mem: GuestMemory,
@@ -758,6 +812,8 @@ enum WorkerToken {
     CtrlQueue,
     CursorQueue,
     Display,
+    DisplayChanges,
+    EdidReread,
     GpuControl,
     InterruptResample,
     Kill,
@@ -819,6 +875,7 @@ struct Worker {
     resource_bridges: ResourceBridges,
     kill_evt: Event,
     state: Frontend,
+    edid_reread_timeout: Option<Duration>,
 }
 
 impl Worker {
@@ -833,10 +890,42 @@ impl Worker {
                 }
             };
 
+        let mut display_changes = match DisplayChanges::new(DISPLAY_CHANGE_WINDOW) {
+            Ok(v) => v,
+            Err(e) => {
+                error!("failed creating display change timer: {}", e);
+                return;
+            }
+        };
+        let display_changes_desc = match SafeDescriptor::try_from(display_changes.timer()) {
+            Ok(v) => v,
+            Err(e) => {
+                error!("failed getting descriptor for display change timer: {}", e);
+                return;
+            }
+        };
+
+        let mut edid_reread = match EdidReread::new(self.edid_reread_timeout) {
+            Ok(v) => v,
+            Err(e) => {
+                error!("failed creating EDID re-read timer: {}", e);
+                return;
+            }
+        };
+        let edid_reread_desc = match SafeDescriptor::try_from(edid_reread.timer()) {
+            Ok(v) => v,
+            Err(e) => {
+                error!("failed getting descriptor for EDID re-read timer: {}", e);
+                return;
+            }
+        };
+
         let mut event_manager = match EventManager::build_with(&[
             (&self.ctrl_evt, WorkerToken::CtrlQueue),
             (&self.cursor_evt, WorkerToken::CursorQueue),
             (&display_desc, WorkerToken::Display),
+            (&display_changes_desc, WorkerToken::DisplayChanges),
+            (&edid_reread_desc, WorkerToken::EdidReread),
             (&self.gpu_control_tube, WorkerToken::GpuControl),
             (&self.kill_evt, WorkerToken::Kill),
         ]) {
@@ -917,27 +1006,67 @@ impl Worker {
                     WorkerToken::Display => {
                         let close_requested = self.state.process_display();
                         if close_requested {
-                            let _ = self.exit_evt_wrtube.send::<VmEventType>(&VmEventType::Exit);
+                            let _ = self
+                                .exit_evt_wrtube
+                                .send::<VmExitReason>(&VmExitReason::GuestShutdown);
                         }
                     }
-                    WorkerToken::GpuControl => {
-                        let req = match self.gpu_control_tube.recv() {
-                            Ok(req) => req,
-                            Err(e) => {
-                                error!("gpu control socket failed recv: {:?}", e);
-                                break 'wait;
+                    WorkerToken::DisplayChanges => {
+                        if display_changes.timer_fired() {
+                            needs_config_interrupt = true;
+                            if let Err(e) = edid_reread.notified() {
+                                error!("failed arming EDID re-read timer: {}", e);
                             }
-                        };
-
-                        let resp = self.state.process_gpu_control_command(req);
-
-                        if let GpuControlResult::DisplaysUpdated = resp {
+                        }
+                    }
+                    WorkerToken::EdidReread => match edid_reread.timer_fired() {
+                        Some(EdidRereadStep::DisconnectStale) => {
+                            let stale = self.state.virtio_gpu.stale_edids();
+                            if !stale.is_empty() {
+                                warn!(
+                                    "guest didn't read the new EDID of displays {:?}, \
+                                     simulating a hotplug",
+                                    stale
+                                );
+                                self.state.virtio_gpu.disconnect_scanouts(&stale);
+                                needs_config_interrupt = true;
+                                if let Err(e) = edid_reread.disconnected() {
+                                    error!("failed arming display reconnect timer: {}", e);
+                                    self.state.virtio_gpu.reconnect_scanouts();
+                                }
+                            }
+                        }
+                        Some(EdidRereadStep::Reconnect) => {
+                            self.state.virtio_gpu.reconnect_scanouts();
                             needs_config_interrupt = true;
                         }
-
-                        if let Err(e) = self.gpu_control_tube.send(&resp) {
-                            error!("display control socket failed send: {}", e);
-                            break 'wait;
+                        None => {}
+                    },
+                    WorkerToken::GpuControl => {
+                        let state = &mut self.state;
+                        let served = serve_gpu_control(&self.gpu_control_tube, |cmd| {
+                            let resp = state.process_gpu_control_command(cmd);
+                            // The guest is notified once the burst of changes this may be part
+                            // of is over, the reply is sent right away.
+                            if let GpuControlResult::BatchApplied { .. }
+                            | GpuControlResult::DisplaysUpdated
+                            | GpuControlResult::DisplayModeSet { .. } = resp
+                            {
+                                if let Err(e) = display_changes.changed() {
+                                    error!("failed arming display change timer: {}", e);
+                                    needs_config_interrupt = true;
+                                }
+                            }
+                            resp
+                        });
+                        if let GpuControlServed::RecvFailed(e) = served {
+                            // The device keeps running for the guest without the control
+                            // socket rather than spinning on a tube that stays readable.
+                            error!(
+                                "gpu control socket failed recv, no longer serving it: {}",
+                                e
+                            );
+                            event_manager.delete(WorkerToken::GpuControl);
                         }
                     }
                     WorkerToken::ResourceBridge { index } => {
@@ -1058,7 +1187,9 @@ pub struct Gpu {
     render_server_fd: Option<SafeDescriptor>,
     #[cfg(feature = "kiwi")]
     gpu_device_service_tube: Option<Tube>,
+    // Capsets advertised to the guest, those the host can back.
     context_mask: u64,
+    edid_reread_timeout: Option<Duration>,
 }
 
 impl Gpu {
@@ -1125,6 +1256,24 @@ impl Gpu {
             .set_use_external_blob(external_blob)
             .set_use_render_server(use_render_server);
 
+        // Only advertise the capsets the host can back, guests fail in confusing ways later on
+        // when they use the others.
+        let context_mask = rutabaga_builder.probe_context_mask();
+        let unavailable = gpu_parameters.context_mask & !context_mask;
+        if unavailable != 0 {
+            warn!(
+                "context types not advertised to the guest: {}",
+                calculate_context_types(unavailable).join(":")
+            );
+        }
+        // Without context types the builder picks the capsets, and with none of the requested
+        // ones usable it fails with the reason when it is built.
+        let rutabaga_builder = if context_mask != 0 && gpu_parameters.context_mask != 0 {
+            rutabaga_builder.set_context_mask(context_mask)
+        } else {
+            rutabaga_builder
+        };
+
         Gpu {
             exit_evt_wrtube,
             gpu_control_tube: Some(gpu_control_tube),
@@ -1148,7 +1297,11 @@ impl Gpu {
             render_server_fd,
             #[cfg(feature = "kiwi")]
             gpu_device_service_tube,
-            context_mask: gpu_parameters.context_mask,
+            context_mask,
+            edid_reread_timeout: match gpu_parameters.edid_reread_timeout_ms {
+                0 => None,
+                ms => Some(Duration::from_millis(ms)),
+            },
         }
     }
 
@@ -1193,32 +1346,7 @@ impl Gpu {
             events_read |= VIRTIO_GPU_EVENT_DISPLAY;
         }
 
-        let num_capsets = match self.context_mask {
-            0 => {
-                match self.rutabaga_component {
-                    RutabagaComponentType::Rutabaga2D => 0,
-                    _ => {
-                        #[allow(unused_mut)]
-                        let mut num_capsets = 0;
-
-                        // Three capsets for virgl_renderer
-                        #[cfg(feature = "virgl_renderer")]
-                        {
-                            num_capsets += 3;
-                        }
-
-                        // One capset for gfxstream
-                        #[cfg(feature = "gfxstream")]
-                        {
-                            num_capsets += 1;
-                        }
-
-                        num_capsets
-                    }
-                }
-            }
-            _ => self.context_mask.count_ones(),
-        };
+        let num_capsets = self.context_mask.count_ones();
 
         virtio_gpu_config {
             events_read: Le32::from(events_read),
@@ -1379,6 +1507,7 @@ impl VirtioDevice for Gpu {
         let event_devices = self.event_devices.split_off(0);
         let external_blob = self.external_blob;
         let udmabuf = self.udmabuf;
+        let edid_reread_timeout = self.edid_reread_timeout;
         let fence_state = Arc::new(Mutex::new(Default::default()));
         #[cfg(feature = "virgl_renderer_next")]
         let render_server_fd = self.render_server_fd.take();
@@ -1415,7 +1544,10 @@ impl VirtioDevice for Gpu {
                             render_server_fd,
                         ) {
                             Some(backend) => backend,
-                            None => return,
+                            None => {
+                                serve_gpu_control_unavailable(&gpu_control_tube, &kill_evt);
+                                return;
+                            }
                         };
 
                         Worker {
@@ -1430,6 +1562,7 @@ impl VirtioDevice for Gpu {
                             resource_bridges,
                             kill_evt,
                             state: Frontend::new(virtio_gpu, fence_state),
+                            edid_reread_timeout,
                         }
                         .run()
                     });
@@ -70,6 +70,12 @@ pub struct GpuParameters {
     pub pci_bar_size: u64,
     #[serde(rename = "context-types", with = "serde_context_mask")]
     pub context_mask: u64,
+    // Absolute pointer devices created ahead of time for the displays added with `input=per-display`
+    // while the VM runs, since virtio-input devices can't be hotplugged.
+    pub hotplug_display_inputs: u32,
+    // How long the guest has to read a changed EDID before its display is unplugged and plugged
+    // back in to force it. 0 never does it.
+    pub edid_reread_timeout_ms: u64,
 }
 
 impl Default for GpuParameters {
@@ -96,6 +102,8 @@ impl Default for GpuParameters {
             pci_bar_size: (1 << 33),
             udmabuf: false,
             context_mask: 0,
+            hotplug_display_inputs: 0,
+            edid_reread_timeout_ms: 1000,
         }
     }
 }
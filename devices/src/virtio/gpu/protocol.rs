@@ -864,6 +864,7 @@ impl Display for GpuResponse {
             ErrDisplay(e) => write!(f, "display error: {}", e),
             ErrScanout { num_scanouts } => write!(f, "non-zero scanout: {}", num_scanouts),
             ErrUdmabuf(e) => write!(f, "udmabuf error: {}", e),
+            ErrEdid(e) => write!(f, "edid error: {}", e),
             _ => Ok(()),
         }
     }
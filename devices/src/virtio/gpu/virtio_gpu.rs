@@ -13,10 +13,12 @@ use std::sync::atomic::Ordering;
 use std::sync::Arc;
 
 use base::error;
+use base::warn;
 use base::Protection;
 use base::SafeDescriptor;
 use data_model::VolatileSlice;
 use gpu_display::*;
+use hypervisor::MemCacheType;
 use libc::c_void;
 use rutabaga_gfx::ResourceCreate3D;
 use rutabaga_gfx::ResourceCreateBlob;
@@ -27,11 +29,18 @@ use rutabaga_gfx::RutabagaFenceHandler;
 use rutabaga_gfx::RutabagaHandle;
 use rutabaga_gfx::RutabagaIovec;
 use rutabaga_gfx::Transfer3D;
+use rutabaga_gfx::RUTABAGA_MAP_CACHE_MASK;
+use rutabaga_gfx::RUTABAGA_MAP_CACHE_UNCACHED;
+use rutabaga_gfx::RUTABAGA_MAP_CACHE_WC;
 use rutabaga_gfx::RUTABAGA_MEM_HANDLE_TYPE_DMABUF;
 use rutabaga_gfx::RUTABAGA_MEM_HANDLE_TYPE_OPAQUE_FD;
+use vm_control::gpu::DisplayInput;
 use vm_control::gpu::DisplayParameters;
+use vm_control::gpu::DisplayTraceEvent;
 use vm_control::gpu::GpuControlCommand;
 use vm_control::gpu::GpuControlResult;
+use vm_control::gpu::ResizePolicy;
+use vm_control::gpu::ScanoutState;
 use vm_control::VmMemorySource;
 use vm_memory::udmabuf::UdmabufDriver;
 use vm_memory::udmabuf::UdmabufDriverTrait;
@@ -45,9 +54,14 @@ use super::protocol::VirtioGpuResult;
 use super::protocol::VIRTIO_GPU_BLOB_FLAG_CREATE_GUEST_HANDLE;
 use super::protocol::VIRTIO_GPU_BLOB_MEM_HOST3D;
 use super::VirtioScanoutBlobData;
-use crate::virtio::gpu::edid::DisplayInfo;
-use crate::virtio::gpu::edid::EdidBytes;
+use crate::virtio::gpu::display_batch::DisplayModel;
+use crate::virtio::gpu::display_params_edid;
+use crate::virtio::gpu::display_state::DisplayState;
+use crate::virtio::gpu::display_trace::DisplayTrace;
+use crate::virtio::gpu::display_trace::RequestedModes;
+use crate::virtio::gpu::edid::EdidIdentifiers;
 use crate::virtio::gpu::GpuDisplayParameters;
+use crate::virtio::gpu::DEFAULT_REFRESH_RATE;
 use crate::virtio::gpu::VIRTIO_GPU_MAX_SCANOUTS;
 use crate::virtio::resource_bridge::BufferInfo;
 use crate::virtio::resource_bridge::PlaneInfo;
@@ -84,8 +98,9 @@ impl VirtioGpuResource {
 }
 
 struct VirtioGpuScanout {
-    width: u32,
-    height: u32,
+    // Mode reported to the guest, the size of which is also the size of the surface presenting
+    // the scanout.
+    state: DisplayState,
     surface_id: Option<u32>,
     resource_id: Option<NonZeroU32>,
     scanout_type: SurfaceType,
@@ -95,20 +110,38 @@ struct VirtioGpuScanout {
     display_params: Option<GpuDisplayParameters>,
     // If this scanout is a cursor scanout, the scanout that this is cursor is overlayed onto.
     parent_surface_id: Option<u32>,
+    // Sizes of the resources the guest set on this scanout.
+    requested_modes: RequestedModes,
+    // Generation of the mode the last EDID read by the guest was generated with.
+    edid_read_generation: Option<u64>,
+    // Whether the display info reports the scanout enabled, which is only false while simulating
+    // a hotplug.
+    connected: bool,
+    // Size of the resource the guest set on this scanout, while one is set.
+    guest_mode: Option<(u32, u32)>,
+    // Number of flushes presented on the surface.
+    frames_presented: u64,
 }
 
 impl VirtioGpuScanout {
     fn new_primary(scanout_id: u32, params: GpuDisplayParameters) -> VirtioGpuScanout {
         let (width, height) = params.get_virtual_display_size();
+        // The manufacturer was checked when the parameters were parsed.
+        let identifiers = EdidIdentifiers::new(&params, scanout_id).unwrap_or_default();
         VirtioGpuScanout {
-            width,
-            height,
+            state: DisplayState::new(width, height, params.refresh_rate)
+                .with_identifiers(identifiers),
             scanout_type: SurfaceType::Scanout,
             scanout_id: Some(scanout_id),
             display_params: Some(params),
             surface_id: None,
             resource_id: None,
             parent_surface_id: None,
+            requested_modes: Default::default(),
+            edid_read_generation: None,
+            connected: true,
+            guest_mode: None,
+            frames_presented: 0,
         }
     }
 
@@ -116,14 +149,18 @@ impl VirtioGpuScanout {
         // Per virtio spec: "The mouse cursor image is a normal resource, except that it must be
         // 64x64 in size."
         VirtioGpuScanout {
-            width: 64,
-            height: 64,
+            state: DisplayState::new(64, 64, DEFAULT_REFRESH_RATE),
             scanout_type: SurfaceType::Cursor,
             scanout_id: None,
             display_params: None,
             surface_id: None,
             resource_id: None,
             parent_surface_id: None,
+            requested_modes: Default::default(),
+            edid_read_generation: None,
+            connected: true,
+            guest_mode: None,
+            frames_presented: 0,
         }
     }
 
@@ -153,8 +190,8 @@ impl VirtioGpuScanout {
 
         let surface_id = display.create_surface(
             self.parent_surface_id,
-            self.width,
-            self.height,
+            self.state.width(),
+            self.state.height(),
             self.scanout_type,
         )?;
 
@@ -175,6 +212,14 @@ impl VirtioGpuScanout {
         self.surface_id = None;
     }
 
+    /// Changes the size of the scanout. Its surface is created again with the new size when the
+    /// guest next sets a resource on the scanout.
+    fn resize(&mut self, display: &Rc<RefCell<GpuDisplay>>, width: u32, height: u32) {
+        if self.state.set_size(width, height) {
+            self.release_surface(display);
+        }
+    }
+
     fn set_position(&self, display: &Rc<RefCell<GpuDisplay>>, x: u32, y: u32) -> VirtioGpuResult {
         if let Some(surface_id) = self.surface_id {
             display.borrow_mut().set_position(surface_id, x, y)?;
@@ -204,6 +249,7 @@ impl VirtioGpuScanout {
             VirtioGpuScanout::import_resource_to_display(display, resource, rutabaga)
         {
             display.borrow_mut().flip_to(surface_id, import_id)?;
+            self.frames_presented += 1;
             return Ok(OkNoData);
         }
 
@@ -215,11 +261,12 @@ impl VirtioGpuScanout {
             return Ok(OkNoData);
         }
 
+        let (width, height) = self.state.size();
         let fb = display
-            .framebuffer_region(surface_id, 0, 0, self.width, self.height)
+            .framebuffer_region(surface_id, 0, 0, width, height)
             .ok_or(ErrUnspec)?;
 
-        let mut transfer = Transfer3D::new_2d(0, 0, self.width, self.height);
+        let mut transfer = Transfer3D::new_2d(0, 0, width, height);
         transfer.stride = fb.stride();
         rutabaga.transfer_read(
             0,
@@ -229,6 +276,7 @@ impl VirtioGpuScanout {
         )?;
 
         display.flip(surface_id);
+        self.frames_presented += 1;
         Ok(OkNoData)
     }
 
@@ -278,20 +326,30 @@ impl VirtioGpuScanout {
     }
 }
 
+/// Returns the cache type the guest should map a blob with, given the `map_info` reported for it
+/// by rutabaga.
+fn map_info_cache_type(map_info: u32) -> MemCacheType {
+    match map_info & RUTABAGA_MAP_CACHE_MASK {
+        RUTABAGA_MAP_CACHE_WC => MemCacheType::WriteCombine,
+        RUTABAGA_MAP_CACHE_UNCACHED => MemCacheType::Uncached,
+        _ => MemCacheType::Cached,
+    }
+}
+
 /// Handles functionality related to displays, input events and hypervisor memory management.
 pub struct VirtioGpu {
     display: Rc<RefCell<GpuDisplay>>,
     scanouts: Map<u32, VirtioGpuScanout>,
     scanouts_updated: Arc<AtomicBool>,
     cursor_scanout: VirtioGpuScanout,
-    // Maps event devices to scanout number.
-    event_devices: Map<u32, u32>,
+    // Maps event devices to the displays they receive events from.
+    event_devices: Map<u32, EventDeviceDisplay>,
     mapper: Box<dyn SharedMemoryMapper>,
     rutabaga: Rutabaga,
     resources: Map<u32, VirtioGpuResource>,
     external_blob: bool,
-    refresh_rate: u32,
     udmabuf_driver: Option<UdmabufDriver>,
+    display_trace: DisplayTrace,
     #[cfg(feature = "kiwi")]
     gpu_device_service_tube: Tube,
 }
@@ -373,15 +431,15 @@ impl VirtioGpu {
             rutabaga,
             resources: Default::default(),
             external_blob,
-            refresh_rate: display_params[0].refresh_rate,
             udmabuf_driver,
+            display_trace: DisplayTrace::new(),
             #[cfg(feature = "kiwi")]
             gpu_device_service_tube,
         };
 
         for event_device in event_devices {
             virtio_gpu
-                .import_event_device(event_device, 0)
+                .import_event_device(event_device)
                 .map_err(|e| error!("failed to import event device {}", e))
                 .ok()?;
         }
@@ -390,17 +448,56 @@ impl VirtioGpu {
     }
 
     /// Imports the event device
-    pub fn import_event_device(
-        &mut self,
-        event_device: EventDevice,
-        scanout_id: u32,
-    ) -> VirtioGpuResult {
+    ///
+    /// Devices with `EventDeviceDisplay::Unassigned` are kept for the displays added with
+    /// `input=per-display` while the VM runs.
+    pub fn import_event_device(&mut self, event_device: EventDevice) -> VirtioGpuResult {
         let mut display = self.display.borrow_mut();
+        let event_device_display = event_device.display();
         let event_device_id = display.import_event_device(event_device)?;
-        self.event_devices.insert(event_device_id, scanout_id);
+        self.event_devices
+            .insert(event_device_id, event_device_display);
         Ok(OkNoData)
     }
 
+    // Binds a spare event device to the display `scanout_id`, returning false if there is none.
+    fn assign_event_device(&mut self, scanout_id: u32) -> bool {
+        let event_device_id = match self
+            .event_devices
+            .iter()
+            .find(|(_, display)| **display == EventDeviceDisplay::Unassigned)
+        {
+            Some((&id, _)) => id,
+            None => return false,
+        };
+        let display = EventDeviceDisplay::Display(scanout_id);
+        if let Err(e) = self
+            .display
+            .borrow_mut()
+            .set_event_device_display(event_device_id, display)
+        {
+            error!("failed to assign event device {}: {}", event_device_id, e);
+            return false;
+        }
+        self.event_devices.insert(event_device_id, display);
+        true
+    }
+
+    // Returns the event devices of the display `scanout_id` to the spares.
+    fn unassign_event_devices(&mut self, scanout_id: u32) {
+        let mut display = self.display.borrow_mut();
+        for (event_device_id, event_device_display) in self.event_devices.iter_mut() {
+            if *event_device_display != EventDeviceDisplay::Display(scanout_id) {
+                continue;
+            }
+            match display.set_event_device_display(*event_device_id, EventDeviceDisplay::Unassigned)
+            {
+                Ok(()) => *event_device_display = EventDeviceDisplay::Unassigned,
+                Err(e) => error!("failed to unassign event device {}: {}", event_device_id, e),
+            }
+        }
+    }
+
     /// Gets a reference to the display passed into `new`.
     pub fn display(&mut self) -> &Rc<RefCell<GpuDisplay>> {
         &self.display
@@ -414,7 +511,8 @@ impl VirtioGpu {
                 self.scanouts
                     .get(&scanout_id)
                     .map_or((0, 0, false), |scanout| {
-                        (scanout.width, scanout.height, true)
+                        let (width, height, enabled) = scanout.state.display_info();
+                        (width, height, enabled && scanout.connected)
                     })
             })
             .collect::<Vec<_>>()
@@ -434,10 +532,47 @@ impl VirtioGpu {
             available_scanout_ids.remove(scanout_id);
         });
 
+        // Reject displays the guest couldn't be given a sane EDID for before touching any
+        // scanout, so a bad request doesn't leave some of its displays attached.
+        for (display_params, &display_id) in displays.iter().zip(&available_scanout_ids) {
+            if let Err(reason) = display_params_edid(display_params) {
+                return GpuControlResult::EdidGenerationFailed { display_id, reason };
+            }
+        }
+        let spare_event_devices = self
+            .event_devices
+            .values()
+            .filter(|display| **display == EventDeviceDisplay::Unassigned)
+            .count();
+        let per_display_inputs = displays
+            .iter()
+            .filter(|display_params| display_params.input == DisplayInput::PerDisplay)
+            .count();
+        if per_display_inputs > spare_event_devices {
+            return GpuControlResult::InvalidDisplay {
+                reason: format!(
+                    "{} displays with input=per-display but only {} spare input devices, see \
+                     `--gpu hotplug-display-inputs`",
+                    per_display_inputs, spare_event_devices
+                ),
+            };
+        }
+
         for display_params in displays.into_iter() {
-            let new_scanout_id = *available_scanout_ids.iter().next().unwrap();
+            // Can't run out, the number of displays was checked above.
+            let new_scanout_id = match available_scanout_ids.iter().next() {
+                Some(&id) => id,
+                None => break,
+            };
             available_scanout_ids.remove(&new_scanout_id);
 
+            // There are enough spare event devices, checked above.
+            if display_params.input == DisplayInput::PerDisplay
+                && !self.assign_event_device(new_scanout_id)
+            {
+                warn!("display {} has no input device of its own", new_scanout_id);
+            }
+
             self.scanouts.insert(
                 new_scanout_id,
                 VirtioGpuScanout::new_primary(new_scanout_id, display_params),
@@ -449,6 +584,38 @@ impl VirtioGpu {
         GpuControlResult::DisplaysUpdated
     }
 
+    /// Applies the display changes of a batch if all of them can be applied.
+    fn apply_batch(&mut self, commands: Vec<GpuControlCommand>) -> GpuControlResult {
+        let displays = self
+            .scanouts
+            .iter()
+            .filter_map(|(scanout_id, scanout)| {
+                scanout
+                    .display_params
+                    .clone()
+                    .map(|display_params| (*scanout_id, display_params))
+            })
+            .collect();
+        let spare_event_devices = self
+            .event_devices
+            .values()
+            .filter(|display| **display == EventDeviceDisplay::Unassigned)
+            .count();
+        let mut model = DisplayModel::new(displays, VIRTIO_GPU_MAX_SCANOUTS, spare_event_devices);
+        if let Err((index, result)) = model.check_batch(&commands) {
+            return GpuControlResult::BatchRejected {
+                index,
+                result: Box::new(result),
+            };
+        }
+
+        let results = commands
+            .into_iter()
+            .map(|cmd| self.process_gpu_control_command(cmd))
+            .collect();
+        GpuControlResult::BatchApplied { results }
+    }
+
     /// Returns the list of displays currently connected to the device.
     fn list_displays(&self) -> GpuControlResult {
         GpuControlResult::DisplayList {
@@ -463,42 +630,194 @@ impl VirtioGpu {
                         .map(|display_params| (*scanout_id, display_params))
                 })
                 .collect(),
+            guest_requested: self
+                .scanouts
+                .iter()
+                .filter(|(_, scanout)| !scanout.requested_modes.is_empty())
+                .map(|(scanout_id, scanout)| {
+                    (
+                        *scanout_id,
+                        scanout.requested_modes.get(scanout.state.size()),
+                    )
+                })
+                .collect(),
+            presented: self
+                .scanouts
+                .iter()
+                .filter(|(_, scanout)| scanout.display_params.is_some())
+                .map(|(scanout_id, scanout)| (*scanout_id, scanout.state.size()))
+                .collect(),
+        }
+    }
+
+    /// Returns the state of the scanouts of the displays as the guest set them up.
+    fn display_state(&self) -> GpuControlResult {
+        GpuControlResult::DisplayState {
+            displays: self
+                .scanouts
+                .iter()
+                .filter(|(_, scanout)| scanout.display_params.is_some())
+                .map(|(scanout_id, scanout)| {
+                    (
+                        *scanout_id,
+                        ScanoutState {
+                            guest_enabled: scanout.resource_id.is_some(),
+                            current_mode: scanout.guest_mode,
+                            framebuffer_attached: scanout.surface_id.is_some(),
+                            frames_presented: scanout.frames_presented,
+                        },
+                    )
+                })
+                .collect(),
         }
     }
 
     /// Removes the specified displays from the device.
     fn remove_displays(&mut self, display_ids: Vec<u32>) -> GpuControlResult {
-        let display_ids_to_remove = Set::from_iter(display_ids.iter());
-        display_ids_to_remove
-            .into_iter()
-            .try_for_each(|display_id| {
-                self.scanouts
-                    .get_mut(display_id)
-                    .ok_or(GpuControlResult::NoSuchDisplay {
-                        display_id: *display_id,
-                    })
-                    .map(|scanout| {
-                        scanout.release_surface(&self.display);
-                        scanout
-                    })?;
+        let display_ids_to_remove = Set::from_iter(display_ids.into_iter());
 
-                self.scanouts.remove(display_id);
+        // Check all the displays before removing any, so a request is applied entirely or not at
+        // all.
+        if let Some(&display_id) = display_ids_to_remove
+            .iter()
+            .find(|display_id| !self.scanouts.contains_key(display_id))
+        {
+            return GpuControlResult::NoSuchDisplay { display_id };
+        }
 
-                Ok(())
-            })
-            .err()
-            .unwrap_or_else(|| {
-                self.scanouts_updated.store(true, Ordering::Relaxed);
-                GpuControlResult::DisplaysUpdated
+        for display_id in display_ids_to_remove {
+            if let Some(mut scanout) = self.scanouts.remove(&display_id) {
+                scanout.release_surface(&self.display);
+            }
+            self.unassign_event_devices(display_id);
+        }
+
+        self.scanouts_updated.store(true, Ordering::Relaxed);
+        GpuControlResult::DisplaysUpdated
+    }
+
+    /// Changes the refresh rate of a display. The guest is notified like when displays are added,
+    /// and reads the new EDID without a modeset since the size doesn't change.
+    fn set_refresh_rate(&mut self, display_id: u32, refresh_rate: u32) -> GpuControlResult {
+        let scanout = match self.scanouts.get_mut(&display_id) {
+            Some(scanout) if scanout.display_params.is_some() => scanout,
+            _ => return GpuControlResult::NoSuchDisplay { display_id },
+        };
+        let mut params = scanout.display_params.clone().unwrap();
+        params.refresh_rate = refresh_rate;
+        if let Err(reason) = display_params_edid(&params) {
+            return GpuControlResult::EdidGenerationFailed { display_id, reason };
+        }
+        scanout.display_params = Some(params);
+        if scanout.state.set_refresh_rate(refresh_rate) {
+            self.scanouts_updated.store(true, Ordering::Relaxed);
+        }
+        GpuControlResult::DisplaysUpdated
+    }
+
+    /// Replaces the parameters of a display. The guest is notified like when displays are added,
+    /// and finds the new mode on the same scanout.
+    fn set_display_mode(&mut self, display_id: u32, params: DisplayParameters) -> GpuControlResult {
+        let scanout = match self.scanouts.get_mut(&display_id) {
+            Some(scanout) if scanout.display_params.is_some() => scanout,
+            _ => return GpuControlResult::NoSuchDisplay { display_id },
+        };
+        if let Err(reason) = display_params_edid(&params) {
+            return GpuControlResult::EdidGenerationFailed { display_id, reason };
+        }
+        let identifiers = match EdidIdentifiers::new(&params, display_id) {
+            Ok(identifiers) => identifiers,
+            Err(reason) => return GpuControlResult::EdidGenerationFailed { display_id, reason },
+        };
+        // Input devices are bound to displays when they are added.
+        if scanout.display_params.as_ref().map(|p| p.input) != Some(params.input) {
+            return GpuControlResult::InvalidParameters {
+                field: "input".to_string(),
+                reason: format!("the input of display {} can't be changed", display_id),
+            };
+        }
+
+        let (width, height) = params.get_virtual_display_size();
+        let resized = (width, height) != scanout.state.size();
+        if resized {
+            // The cursor is drawn on the surface about to be released.
+            if scanout.surface_id.is_some()
+                && self.cursor_scanout.parent_surface_id == scanout.surface_id
+            {
+                self.cursor_scanout.release_surface(&self.display);
+            }
+            scanout.resize(&self.display, width, height);
+        }
+        let refresh_rate_changed = scanout.state.set_refresh_rate(params.refresh_rate);
+        let identifiers_changed = scanout.state.set_identifiers(identifiers);
+        scanout.display_params = Some(params);
+        if resized || refresh_rate_changed || identifiers_changed {
+            self.scanouts_updated.store(true, Ordering::Relaxed);
+        }
+        GpuControlResult::DisplayModeSet { display_id }
+    }
+
+    /// Returns the scanouts whose EDID changed since the guest last read it. Scanouts whose EDID
+    /// the guest never read are left out, as it doesn't use EDIDs for them.
+    pub fn stale_edids(&self) -> Vec<u32> {
+        self.scanouts
+            .iter()
+            .filter(|(_, scanout)| {
+                scanout
+                    .edid_read_generation
+                    .map_or(false, |generation| generation != scanout.state.generation())
             })
+            .map(|(scanout_id, _)| *scanout_id)
+            .collect()
+    }
+
+    /// Reports the given scanouts as disconnected in the display info, to simulate a hotplug.
+    pub fn disconnect_scanouts(&mut self, scanout_ids: &[u32]) {
+        for scanout_id in scanout_ids {
+            if let Some(scanout) = self.scanouts.get_mut(scanout_id) {
+                scanout.connected = false;
+                self.display_trace
+                    .record(DisplayTraceEvent::SimulatedHotplug {
+                        scanout_id: *scanout_id,
+                        connected: false,
+                    });
+            }
+        }
+        self.scanouts_updated.store(true, Ordering::Relaxed);
+    }
+
+    /// Reports the scanouts disconnected by `disconnect_scanouts` as connected again.
+    pub fn reconnect_scanouts(&mut self) {
+        for (scanout_id, scanout) in self.scanouts.iter_mut() {
+            if !scanout.connected {
+                scanout.connected = true;
+                self.display_trace
+                    .record(DisplayTraceEvent::SimulatedHotplug {
+                        scanout_id: *scanout_id,
+                        connected: true,
+                    });
+            }
+        }
+        self.scanouts_updated.store(true, Ordering::Relaxed);
     }
 
     /// Performs the given command to interact with or modify the device.
     pub fn process_gpu_control_command(&mut self, cmd: GpuControlCommand) -> GpuControlResult {
         match cmd {
             GpuControlCommand::AddDisplays { displays } => self.add_displays(displays),
+            GpuControlCommand::Batch { commands } => self.apply_batch(commands),
+            GpuControlCommand::DisplayState => self.display_state(),
+            GpuControlCommand::GetDisplayTrace => self.display_trace.get(),
             GpuControlCommand::ListDisplays => self.list_displays(),
             GpuControlCommand::RemoveDisplays { display_ids } => self.remove_displays(display_ids),
+            GpuControlCommand::SetDisplayMode {
+                display_id,
+                parameters,
+            } => self.set_display_mode(display_id, parameters),
+            GpuControlCommand::SetRefreshRate {
+                display_id,
+                refresh_rate,
+            } => self.set_refresh_rate(display_id, refresh_rate),
         }
     }
 
@@ -532,7 +851,89 @@ impl VirtioGpu {
         resource_id: u32,
         scanout_data: Option<VirtioScanoutBlobData>,
     ) -> VirtioGpuResult {
-        self.update_scanout_resource(SurfaceType::Scanout, scanout_id, scanout_data, resource_id)
+        let blob_size = scanout_data.as_ref().map(|data| (data.width, data.height));
+        let requested_size = blob_size.or_else(|| {
+            self.resources
+                .get(&resource_id)
+                .map(|resource| (resource.width, resource.height))
+        });
+        let resize_result = match requested_size {
+            Some(size) => self.apply_resize_policy(scanout_id, size),
+            None => Ok(OkNoData),
+        };
+        let rejected = resize_result.is_err();
+        let result = resize_result.and_then(|_| {
+            self.update_scanout_resource(
+                SurfaceType::Scanout,
+                scanout_id,
+                scanout_data,
+                resource_id,
+            )
+        });
+        if result.is_ok() {
+            if let Some(scanout) = self.scanouts.get_mut(&scanout_id) {
+                scanout.guest_mode = scanout.resource_id.and(requested_size);
+            }
+        }
+        // Rejected sizes are recorded as well, as mismatches.
+        if result.is_ok() || rejected {
+            if let (Some(requested_size), Some(scanout)) =
+                (requested_size, self.scanouts.get_mut(&scanout_id))
+            {
+                let granted = scanout.state.size();
+                scanout
+                    .requested_modes
+                    .record(scanout_id, requested_size, granted);
+            }
+        }
+        self.display_trace.record(DisplayTraceEvent::SetScanout {
+            scanout_id,
+            resource_id,
+            blob_size,
+            error: result.as_ref().err().map(|e| e.to_string()),
+        });
+        result
+    }
+
+    /// Applies the resize policy of the display of `scanout_id` to the guest setting a resource of
+    /// the given `size` on it.
+    fn apply_resize_policy(
+        &mut self,
+        scanout_id: u32,
+        (width, height): (u32, u32),
+    ) -> VirtioGpuResult {
+        let scanout = match self.scanouts.get_mut(&scanout_id) {
+            Some(scanout) => scanout,
+            // Reported by `update_scanout_resource`.
+            None => return Ok(OkNoData),
+        };
+        let policy = match &scanout.display_params {
+            Some(params) => params.resize_policy,
+            None => return Ok(OkNoData),
+        };
+        if (width, height) == scanout.state.size() || width == 0 || height == 0 {
+            return Ok(OkNoData);
+        }
+
+        match policy {
+            ResizePolicy::Scale => Ok(OkNoData),
+            ResizePolicy::ResizeHostWindow => {
+                // The cursor is drawn on the surface about to be released.
+                if scanout.surface_id.is_some()
+                    && self.cursor_scanout.parent_surface_id == scanout.surface_id
+                {
+                    self.cursor_scanout.release_surface(&self.display);
+                }
+                scanout.resize(&self.display, width, height);
+                Ok(OkNoData)
+            }
+            ResizePolicy::Reject => {
+                // Have the guest read the display info again, which still has the size of the
+                // display.
+                self.scanouts_updated.store(true, Ordering::Relaxed);
+                Err(ErrInvalidParameter)
+            }
+        }
     }
 
     /// If the resource is the scanout resource, flush it to the display.
@@ -870,7 +1271,12 @@ impl VirtioGpu {
         };
 
         self.mapper
-            .add_mapping(source.unwrap(), offset, Protection::read_write())
+            .add_mapping(
+                source.unwrap(),
+                offset,
+                Protection::read_write(),
+                map_info_cache_type(map_info),
+            )
             .map_err(|_| ErrUnspec)?;
 
         resource.shmem_offset = Some(offset);
@@ -899,17 +1305,42 @@ impl VirtioGpu {
     }
 
     /// Gets the EDID for the specified scanout ID.
-    pub fn get_edid(&self, scanout_id: u32) -> VirtioGpuResult {
-        let scanout = self
+    pub fn get_edid(&mut self, scanout_id: u32) -> VirtioGpuResult {
+        self.display_trace
+            .record(DisplayTraceEvent::GetEdid { scanout_id });
+
+        let result = self
             .scanouts
             .get(&scanout_id)
-            .ok_or(ErrEdid(format!("Invalid scanout id: {}", scanout_id)))?;
+            .ok_or(ErrEdid(format!("Invalid scanout id: {}", scanout_id)))
+            .and_then(|scanout| scanout.state.edid().map(|resp| (resp, &scanout.state)));
 
-        EdidBytes::new(&DisplayInfo::new(
-            scanout.width,
-            scanout.height,
-            self.refresh_rate,
-        ))
+        match result {
+            Ok((resp, state)) => {
+                let generation = state.generation();
+                if let OkEdid(edid) = &resp {
+                    self.display_trace.record(DisplayTraceEvent::EdidGenerated {
+                        scanout_id,
+                        width: state.width(),
+                        height: state.height(),
+                        refresh_rate: state.refresh_rate(),
+                        generation,
+                        edid: edid.as_bytes().to_vec(),
+                    });
+                }
+                if let Some(scanout) = self.scanouts.get_mut(&scanout_id) {
+                    scanout.edid_read_generation = Some(generation);
+                }
+                Ok(resp)
+            }
+            Err(e) => {
+                self.display_trace.record(DisplayTraceEvent::EdidFailed {
+                    scanout_id,
+                    error: e.to_string(),
+                });
+                Err(e)
+            }
+        }
     }
 
     /// Creates a rutabaga context.
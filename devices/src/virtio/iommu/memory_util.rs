@@ -6,6 +6,7 @@
 
 use anyhow::Context;
 use data_model::DataInit;
+use vm_memory::AccessContext;
 use vm_memory::GuestAddress;
 use vm_memory::GuestMemory;
 
@@ -16,11 +17,18 @@ pub fn read_obj_from_addr_wrapper<T: DataInit>(
     mem: &GuestMemory,
     exported_region: &Option<ExportedRegion>,
     addr: GuestAddress,
+    context: &AccessContext,
 ) -> anyhow::Result<T> {
     if let Some(exported_region) = exported_region {
-        exported_region.read_obj_from_addr::<T>(mem, addr.offset())
+        exported_region
+            .read_obj_from_addr::<T>(mem, addr.offset())
+            .map_err(|e| {
+                context.record_fault();
+                e.context(context.to_string())
+            })
     } else {
-        mem.read_obj_from_addr::<T>(addr)
+        context
+            .attach(mem.read_obj_from_addr::<T>(addr))
             .context("read_obj_from_addr failed")
     }
 }
@@ -31,11 +39,18 @@ pub fn write_obj_at_addr_wrapper<T: DataInit>(
     exported_region: &Option<ExportedRegion>,
     val: T,
     addr: GuestAddress,
+    context: &AccessContext,
 ) -> anyhow::Result<()> {
     if let Some(exported_region) = exported_region {
-        exported_region.write_obj_at_addr(mem, val, addr.offset())
+        exported_region
+            .write_obj_at_addr(mem, val, addr.offset())
+            .map_err(|e| {
+                context.record_fault();
+                e.context(context.to_string())
+            })
     } else {
-        mem.write_obj_at_addr(val, addr)
+        context
+            .attach(mem.write_obj_at_addr(val, addr))
             .context("write_obj_at_addr failed")
     }
 }
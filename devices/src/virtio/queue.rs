@@ -24,6 +24,7 @@ use smallvec::smallvec;
 use smallvec::SmallVec;
 use sync::Mutex;
 use virtio_sys::virtio_ring::VIRTIO_RING_F_EVENT_IDX;
+use vm_memory::AccessContext;
 use vm_memory::GuestAddress;
 use vm_memory::GuestMemory;
 
@@ -113,6 +114,9 @@ pub struct DescriptorChain {
     /// The exported iommu region of the current descriptor. Present iff
     /// iommu is present.
     exported_region: Option<ExportedRegion>,
+
+    /// The device and queue this chain belongs to, used to attribute guest memory faults.
+    access_context: AccessContext,
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -135,6 +139,7 @@ impl DescriptorChain {
         required_flags: u16,
         iommu: Option<Arc<Mutex<IpcMemoryMapper>>>,
         exported_desc_table: Option<ExportedRegion>,
+        access_context: AccessContext,
     ) -> Result<DescriptorChain> {
         if index >= queue_size {
             bail!("index ({}) >= queue_size ({})", index, queue_size);
@@ -143,8 +148,9 @@ impl DescriptorChain {
         let desc_head = desc_table
             .checked_add((index as u64) * 16)
             .context("integer overflow")?;
-        let desc: Desc = read_obj_from_addr_wrapper(mem, &exported_desc_table, desc_head)
-            .with_context(|| format!("failed to read desc {:x}", desc_head.offset()))?;
+        let desc: Desc =
+            read_obj_from_addr_wrapper(mem, &exported_desc_table, desc_head, &access_context)
+                .with_context(|| format!("failed to read desc {:x}", desc_head.offset()))?;
 
         let addr = GuestAddress(desc.addr.into());
         let len = desc.len.to_native();
@@ -195,6 +201,7 @@ impl DescriptorChain {
             regions,
             exported_region,
             exported_desc_table,
+            access_context,
         };
 
         if chain.is_valid() && chain.flags & required_flags == required_flags {
@@ -261,6 +268,7 @@ impl DescriptorChain {
                 required_flags,
                 iommu,
                 self.exported_desc_table.clone(),
+                self.access_context.clone(),
             ) {
                 Ok(mut c) => {
                     c.ttl = self.ttl - 1;
@@ -348,6 +356,9 @@ pub struct Queue {
     exported_desc_table: Option<ExportedRegion>,
     exported_avail_ring: Option<ExportedRegion>,
     exported_used_ring: Option<ExportedRegion>,
+
+    // The device and queue index guest memory accesses are attributed to.
+    access_context: AccessContext,
 }
 
 macro_rules! accessors {
@@ -387,6 +398,7 @@ impl Queue {
             exported_desc_table: None,
             exported_avail_ring: None,
             exported_used_ring: None,
+            access_context: AccessContext::new("virtio"),
         }
     }
 
@@ -526,8 +538,13 @@ impl Queue {
         fence(Ordering::SeqCst);
 
         let avail_index_addr = self.avail_ring.unchecked_add(2);
-        let avail_index: u16 =
-            read_obj_from_addr_wrapper(mem, &self.exported_avail_ring, avail_index_addr).unwrap();
+        let avail_index: u16 = read_obj_from_addr_wrapper(
+            mem,
+            &self.exported_avail_ring,
+            avail_index_addr,
+            &self.access_context,
+        )
+        .unwrap();
 
         Wrapping(avail_index)
     }
@@ -549,6 +566,7 @@ impl Queue {
             &self.exported_used_ring,
             avail_index.0,
             avail_event_addr,
+            &self.access_context,
         )
         .unwrap();
     }
@@ -559,8 +577,13 @@ impl Queue {
     fn get_avail_flag(&self, mem: &GuestMemory, flag: u16) -> bool {
         fence(Ordering::SeqCst);
 
-        let avail_flags: u16 =
-            read_obj_from_addr_wrapper(mem, &self.exported_avail_ring, self.avail_ring).unwrap();
+        let avail_flags: u16 = read_obj_from_addr_wrapper(
+            mem,
+            &self.exported_avail_ring,
+            self.avail_ring,
+            &self.access_context,
+        )
+        .unwrap();
 
         avail_flags & flag == flag
     }
@@ -578,8 +601,13 @@ impl Queue {
         let used_event_addr = self
             .avail_ring
             .unchecked_add(4 + 2 * u64::from(self.actual_size()));
-        let used_event: u16 =
-            read_obj_from_addr_wrapper(mem, &self.exported_avail_ring, used_event_addr).unwrap();
+        let used_event: u16 = read_obj_from_addr_wrapper(
+            mem,
+            &self.exported_avail_ring,
+            used_event_addr,
+            &self.access_context,
+        )
+        .unwrap();
 
         Wrapping(used_event)
     }
@@ -592,8 +620,14 @@ impl Queue {
         fence(Ordering::SeqCst);
 
         let used_index_addr = self.used_ring.unchecked_add(2);
-        write_obj_at_addr_wrapper(mem, &self.exported_used_ring, used_index.0, used_index_addr)
-            .unwrap();
+        write_obj_at_addr_wrapper(
+            mem,
+            &self.exported_used_ring,
+            used_index.0,
+            used_index_addr,
+            &self.access_context,
+        )
+        .unwrap();
     }
 
     // Set a single-bit flag in the used ring.
@@ -602,15 +636,26 @@ impl Queue {
     fn set_used_flag(&mut self, mem: &GuestMemory, flag: u16, value: bool) {
         fence(Ordering::SeqCst);
 
-        let mut used_flags: u16 =
-            read_obj_from_addr_wrapper(mem, &self.exported_used_ring, self.used_ring).unwrap();
+        let mut used_flags: u16 = read_obj_from_addr_wrapper(
+            mem,
+            &self.exported_used_ring,
+            self.used_ring,
+            &self.access_context,
+        )
+        .unwrap();
         if value {
             used_flags |= flag;
         } else {
             used_flags &= !flag;
         }
-        write_obj_at_addr_wrapper(mem, &self.exported_used_ring, used_flags, self.used_ring)
-            .unwrap();
+        write_obj_at_addr_wrapper(
+            mem,
+            &self.exported_used_ring,
+            used_flags,
+            self.used_ring,
+            &self.access_context,
+        )
+        .unwrap();
     }
 
     /// Get the first available descriptor chain without removing it from the queue.
@@ -632,8 +677,13 @@ impl Queue {
         let desc_idx_addr = self.avail_ring.checked_add(desc_idx_addr_offset)?;
 
         // This index is checked below in checked_new.
-        let descriptor_index: u16 =
-            read_obj_from_addr_wrapper(mem, &self.exported_avail_ring, desc_idx_addr).unwrap();
+        let descriptor_index: u16 = read_obj_from_addr_wrapper(
+            mem,
+            &self.exported_avail_ring,
+            desc_idx_addr,
+            &self.access_context,
+        )
+        .unwrap();
 
         let iommu = self.iommu.as_ref().map(Arc::clone);
         DescriptorChain::checked_new(
@@ -644,6 +694,7 @@ impl Queue {
             0,
             iommu,
             self.exported_desc_table.clone(),
+            self.access_context.clone(),
         )
         .map_err(|e| {
             error!("{:#}", e);
@@ -706,13 +757,20 @@ impl Queue {
         let used_elem = used_ring.unchecked_add((4 + next_used * 8) as u64);
 
         // These writes can't fail as we are guaranteed to be within the descriptor ring.
-        write_obj_at_addr_wrapper(mem, &self.exported_used_ring, desc_index as u32, used_elem)
-            .unwrap();
+        write_obj_at_addr_wrapper(
+            mem,
+            &self.exported_used_ring,
+            desc_index as u32,
+            used_elem,
+            &self.access_context,
+        )
+        .unwrap();
         write_obj_at_addr_wrapper(
             mem,
             &self.exported_used_ring,
             len as u32,
             used_elem.unchecked_add(4),
+            &self.access_context,
         )
         .unwrap();
 
@@ -895,6 +953,15 @@ impl Queue {
     pub fn set_iommu(&mut self, iommu: Arc<Mutex<IpcMemoryMapper>>) {
         self.iommu = Some(iommu);
     }
+
+    /// Attributes guest memory faults of this queue to `access_context`.
+    pub fn set_access_context(&mut self, access_context: AccessContext) {
+        self.access_context = access_context;
+    }
+
+    pub fn access_context(&self) -> &AccessContext {
+        &self.access_context
+    }
 }
 
 #[cfg(test)]
@@ -1155,4 +1222,27 @@ mod tests {
         // should inject interrupt again.
         assert_eq!(queue.trigger_interrupt(&mem, &interrupt), true);
     }
+
+    #[test]
+    fn descriptor_fault_names_device() {
+        let mem = GuestMemory::new(&[(GuestAddress(0), GUEST_MEMORY_SIZE)]).unwrap();
+        let mut queue = Queue::new(QUEUE_SIZE.try_into().unwrap());
+        queue.set_access_context(AccessContext::new("test-queue-device").with_queue(1));
+
+        // A descriptor table past the end of guest memory cannot be read.
+        let err = match DescriptorChain::checked_new(
+            &mem,
+            GuestAddress(GUEST_MEMORY_SIZE),
+            QUEUE_SIZE as u16,
+            0,
+            0,
+            None,
+            None,
+            queue.access_context().clone(),
+        ) {
+            Ok(_) => panic!("descriptor outside guest memory was read"),
+            Err(e) => format!("{:#}", e),
+        };
+        assert!(err.contains("device test-queue-device queue 1"), "{}", err);
+    }
 }
@@ -42,6 +42,8 @@ pub enum Error {
     /// Creating kill event failed.
     #[error("failed to create kill event: {0}")]
     CreateKillEvent(SysError),
+    #[error("failed to create memory layout event: {0}")]
+    CreateMemoryLayoutEvent(SysError),
     /// Creating tube failed.
     #[error("failed to create tube: {0}")]
     CreateTube(TubeError),
@@ -67,6 +67,7 @@ use base::IntoRawDescriptor;
 use base::Protection;
 use base::SafeDescriptor;
 use base::SharedMemory;
+use hypervisor::MemCacheType;
 use sys::Doorbell;
 use vm_control::VmMemorySource;
 use vm_memory::GuestAddress;
@@ -754,6 +755,9 @@ impl SharedMemoryMapper for VhostShmemMapper {
         source: VmMemorySource,
         offset: u64,
         prot: Protection,
+        // The vhost-user protocol has no way to pass the cache type along, so the frontend maps
+        // everything as cached.
+        _cache: MemCacheType,
     ) -> anyhow::Result<()> {
         // True if we should send gpu_map instead of shmem_map.
         let is_gpu = matches!(&source, &VmMemorySource::Vulkan { .. });
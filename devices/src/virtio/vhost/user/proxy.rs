@@ -42,6 +42,7 @@ use base::WaitContext;
 use data_model::DataInit;
 use data_model::Le32;
 use hypervisor::Datamatch;
+use hypervisor::MemCacheType;
 use libc::recv;
 use libc::MSG_DONTWAIT;
 use libc::MSG_PEEK;
@@ -840,6 +841,7 @@ impl Worker {
             source,
             dest,
             prot: Protection::read_write(),
+            cache: MemCacheType::Cached,
         };
         self.send_memory_request(&request)?;
         Ok(())
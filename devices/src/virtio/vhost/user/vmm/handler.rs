@@ -5,6 +5,7 @@
 mod sys;
 mod worker;
 
+use std::sync::Arc;
 use std::sync::Mutex;
 use std::thread;
 
@@ -14,9 +15,12 @@ use base::AsRawDescriptor;
 use base::Event;
 use base::Protection;
 use base::SafeDescriptor;
+use hypervisor::MemCacheType;
 use rutabaga_gfx::DeviceId;
 use vm_control::VmMemorySource;
+use vm_memory::GuestAddress;
 use vm_memory::GuestMemory;
+use vm_memory::MemoryObserver;
 use vmm_vhost::message::VhostUserConfigFlags;
 use vmm_vhost::message::VhostUserGpuMapMsg;
 use vmm_vhost::message::VhostUserProtocolFeatures;
@@ -49,6 +53,96 @@ fn set_features(vu: &mut SocketMaster, avail_features: u64, ack_features: u64) -
     Ok(features)
 }
 
+fn set_mem_table(vu: &SocketMaster, mem: &GuestMemory) -> Result<()> {
+    let mut regions: Vec<VhostUserMemoryRegionInfo> = Vec::new();
+    mem.with_regions::<_, ()>(
+        |_idx, guest_phys_addr, memory_size, userspace_addr, mmap, mmap_offset| {
+            let region = VhostUserMemoryRegionInfo {
+                guest_phys_addr: guest_phys_addr.0,
+                memory_size: memory_size as u64,
+                userspace_addr: userspace_addr as u64,
+                mmap_offset,
+                mmap_handle: mmap.as_raw_descriptor(),
+            };
+            regions.push(region);
+            Ok(())
+        },
+    )
+    .unwrap(); // never fail
+
+    vu.set_mem_table(regions.as_slice())
+        .map_err(Error::SetMemTable)
+}
+
+/// Guest addresses of an activated vring, kept so that they can be translated again after the
+/// guest memory layout changes.
+#[derive(Clone, Copy)]
+struct VringGuestAddrs {
+    queue_index: usize,
+    queue_max_size: u16,
+    queue_size: u16,
+    desc_table: GuestAddress,
+    used_ring: GuestAddress,
+    avail_ring: GuestAddress,
+}
+
+impl VringGuestAddrs {
+    fn new(queue_index: usize, queue: &Queue) -> Self {
+        VringGuestAddrs {
+            queue_index,
+            queue_max_size: queue.max_size,
+            queue_size: queue.actual_size(),
+            desc_table: queue.desc_table(),
+            used_ring: queue.used_ring(),
+            avail_ring: queue.avail_ring(),
+        }
+    }
+
+    fn set_vring_addr(&self, vu: &SocketMaster, mem: &GuestMemory) -> Result<()> {
+        let config_data = VringConfigData {
+            queue_max_size: self.queue_max_size,
+            queue_size: self.queue_size,
+            flags: 0u32,
+            desc_table_addr: mem
+                .get_host_address(self.desc_table)
+                .map_err(Error::GetHostAddress)? as u64,
+            used_ring_addr: mem
+                .get_host_address(self.used_ring)
+                .map_err(Error::GetHostAddress)? as u64,
+            avail_ring_addr: mem
+                .get_host_address(self.avail_ring)
+                .map_err(Error::GetHostAddress)? as u64,
+            log_addr: None,
+        };
+        vu.set_vring_addr(self.queue_index, &config_data)
+            .map_err(Error::SetVringAddr)
+    }
+}
+
+/// Sends the new memory table and vring addresses to the backend when the guest memory layout
+/// changes, since both carry host addresses derived from the old layout.
+struct MemTableObserver {
+    vu: SocketMaster,
+    vrings: Vec<VringGuestAddrs>,
+}
+
+impl MemoryObserver for MemTableObserver {
+    fn memory_layout_changed(&self, mem: &GuestMemory) {
+        if let Err(e) = set_mem_table(&self.vu, mem) {
+            error!("failed to update vhost-user memory table: {}", e);
+            return;
+        }
+        for vring in &self.vrings {
+            if let Err(e) = vring.set_vring_addr(&self.vu, mem) {
+                error!(
+                    "failed to update vhost-user vring {} address: {}",
+                    vring.queue_index, e
+                );
+            }
+        }
+    }
+}
+
 pub struct VhostUserHandler {
     vu: SocketMaster,
     pub avail_features: u64,
@@ -57,6 +151,8 @@ pub struct VhostUserHandler {
     backend_req_handler: Option<BackendReqHandler>,
     // Shared memory region info. IPC result from backend is saved with outer Option.
     shmem_region: Option<Option<SharedMemoryRegion>>,
+    // Keeps the backend's memory table in sync with the guest memory layout while activated.
+    mem_observer: Option<Arc<dyn MemoryObserver>>,
     // On Windows, we need a backend pid to support backend requests.
     #[cfg(windows)]
     backend_pid: Option<u32>,
@@ -111,6 +207,7 @@ impl VhostUserHandler {
             protocol_features,
             backend_req_handler,
             shmem_region: None,
+            mem_observer: None,
             #[cfg(windows)]
             backend_pid,
         })
@@ -174,27 +271,7 @@ impl VhostUserHandler {
 
     /// Sets the memory map regions so it can translate the vring addresses.
     pub fn set_mem_table(&mut self, mem: &GuestMemory) -> Result<()> {
-        let mut regions: Vec<VhostUserMemoryRegionInfo> = Vec::new();
-        mem.with_regions::<_, ()>(
-            |_idx, guest_phys_addr, memory_size, userspace_addr, mmap, mmap_offset| {
-                let region = VhostUserMemoryRegionInfo {
-                    guest_phys_addr: guest_phys_addr.0,
-                    memory_size: memory_size as u64,
-                    userspace_addr: userspace_addr as u64,
-                    mmap_offset,
-                    mmap_handle: mmap.as_raw_descriptor(),
-                };
-                regions.push(region);
-                Ok(())
-            },
-        )
-        .unwrap(); // never fail
-
-        self.vu
-            .set_mem_table(regions.as_slice())
-            .map_err(Error::SetMemTable)?;
-
-        Ok(())
+        set_mem_table(&self.vu, mem)
     }
 
     /// Activates a vring for the given `queue`.
@@ -210,24 +287,7 @@ impl VhostUserHandler {
             .set_vring_num(queue_index, queue.actual_size())
             .map_err(Error::SetVringNum)?;
 
-        let config_data = VringConfigData {
-            queue_max_size: queue.max_size,
-            queue_size: queue.actual_size(),
-            flags: 0u32,
-            desc_table_addr: mem
-                .get_host_address(queue.desc_table())
-                .map_err(Error::GetHostAddress)? as u64,
-            used_ring_addr: mem
-                .get_host_address(queue.used_ring())
-                .map_err(Error::GetHostAddress)? as u64,
-            avail_ring_addr: mem
-                .get_host_address(queue.avail_ring())
-                .map_err(Error::GetHostAddress)? as u64,
-            log_addr: None,
-        };
-        self.vu
-            .set_vring_addr(queue_index, &config_data)
-            .map_err(Error::SetVringAddr)?;
+        VringGuestAddrs::new(queue_index, queue).set_vring_addr(&self.vu, mem)?;
 
         self.vu
             .set_vring_base(queue_index, 0)
@@ -273,6 +333,17 @@ impl VhostUserHandler {
 
         drop(msix_config);
 
+        let mem_observer: Arc<dyn MemoryObserver> = Arc::new(MemTableObserver {
+            vu: self.vu.clone(),
+            vrings: queues
+                .iter()
+                .enumerate()
+                .map(|(queue_index, queue)| VringGuestAddrs::new(queue_index, queue))
+                .collect(),
+        });
+        mem.add_observer(&mem_observer);
+        self.mem_observer = Some(mem_observer);
+
         let label = format!("vhost_user_virtio_{}", label);
         let kill_evt = Event::new().map_err(Error::CreateEvent)?;
         let self_kill_evt = kill_evt.try_clone().map_err(Error::CreateEvent)?;
@@ -307,6 +378,7 @@ impl VhostUserHandler {
 
     /// Deactivates all vrings.
     pub fn reset(&mut self, queues_num: usize) -> Result<()> {
+        self.mem_observer = None;
         for queue_index in 0..queues_num {
             self.vu
                 .set_vring_enable(queue_index, false)
@@ -412,6 +484,7 @@ impl VhostUserMasterReqHandlerMut for BackendReqHandlerImpl {
             },
             req.shm_offset,
             Protection::from(req.flags),
+            MemCacheType::Cached,
         ) {
             Ok(()) => Ok(0),
             Err(e) => {
@@ -472,6 +545,7 @@ impl VhostUserMasterReqHandlerMut for BackendReqHandlerImpl {
             },
             req.shm_offset,
             Protection::read_write(),
+            MemCacheType::Cached,
         ) {
             Ok(()) => Ok(0),
             Err(e) => {
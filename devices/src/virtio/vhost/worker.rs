@@ -2,6 +2,8 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
+use std::sync::Arc;
+
 use base::error;
 use base::Error as SysError;
 use base::Event;
@@ -11,6 +13,8 @@ use base::WaitContext;
 use libc::EIO;
 use vhost::Vhost;
 use vm_memory::GuestMemory;
+use vm_memory::MemoryLayoutEvent;
+use vm_memory::MemoryObserver;
 
 use super::control_socket::VhostDevRequest;
 use super::control_socket::VhostDevResponse;
@@ -31,6 +35,8 @@ pub struct Worker<T: Vhost> {
     pub kill_evt: Event,
     pub response_tube: Option<Tube>,
     uses_viommu: bool,
+    queue_sizes: Vec<u16>,
+    mem_layout: Option<Arc<MemoryLayoutEvent>>,
 }
 
 impl<T: Vhost> Worker<T> {
@@ -53,6 +59,8 @@ impl<T: Vhost> Worker<T> {
             kill_evt,
             response_tube,
             uses_viommu,
+            queue_sizes: Vec::new(),
+            mem_layout: None,
         }
     }
 
@@ -92,24 +100,13 @@ impl<T: Vhost> Worker<T> {
             .set_mem_table(&mem)
             .map_err(Error::VhostSetMemTable)?;
 
+        self.queue_sizes = queue_sizes.to_vec();
         for (queue_index, queue) in self.queues.iter().enumerate() {
             self.vhost_handle
                 .set_vring_num(queue_index, queue.max_size)
                 .map_err(Error::VhostSetVringNum)?;
 
-            self.vhost_handle
-                .set_vring_addr(
-                    &mem,
-                    queue_sizes[queue_index],
-                    queue.actual_size(),
-                    queue_index,
-                    0,
-                    queue.desc_table(),
-                    queue.used_ring(),
-                    queue.avail_ring(),
-                    None,
-                )
-                .map_err(Error::VhostSetVringAddr)?;
+            self.set_vring_addr(&mem, queue_index, queue)?;
             self.vhost_handle
                 .set_vring_base(queue_index, 0)
                 .map_err(Error::VhostSetVringBase)?;
@@ -120,6 +117,42 @@ impl<T: Vhost> Worker<T> {
         }
 
         activate_vqs(&self.vhost_handle)?;
+
+        // The vhost driver translates guest addresses with the table set above, so it has to be
+        // replaced whenever the guest memory layout changes.
+        let mem_layout =
+            Arc::new(MemoryLayoutEvent::new().map_err(Error::CreateMemoryLayoutEvent)?);
+        let observer: Arc<dyn MemoryObserver> = mem_layout.clone();
+        mem.add_observer(&observer);
+        self.mem_layout = Some(mem_layout);
+
+        Ok(())
+    }
+
+    fn set_vring_addr(&self, mem: &GuestMemory, queue_index: usize, queue: &Queue) -> Result<()> {
+        self.vhost_handle
+            .set_vring_addr(
+                mem,
+                self.queue_sizes[queue_index],
+                queue.actual_size(),
+                queue_index,
+                0,
+                queue.desc_table(),
+                queue.used_ring(),
+                queue.avail_ring(),
+                None,
+            )
+            .map_err(Error::VhostSetVringAddr)
+    }
+
+    /// Re-registers guest memory with the vhost driver after a layout change.
+    fn update_mem_table(&self, mem: &GuestMemory) -> Result<()> {
+        self.vhost_handle
+            .set_mem_table(mem)
+            .map_err(Error::VhostSetMemTable)?;
+        for (queue_index, queue) in self.queues.iter().enumerate() {
+            self.set_vring_addr(mem, queue_index, queue)?;
+        }
         Ok(())
     }
 
@@ -133,6 +166,7 @@ impl<T: Vhost> Worker<T> {
             InterruptResample,
             Kill,
             ControlNotify,
+            MemoryLayoutChanged,
         }
 
         let wait_ctx: WaitContext<Token> =
@@ -154,6 +188,11 @@ impl<T: Vhost> Worker<T> {
                 .add(resample_evt, Token::InterruptResample)
                 .map_err(Error::CreateWaitContext)?;
         }
+        if let Some(mem_layout) = &self.mem_layout {
+            wait_ctx
+                .add(mem_layout.event(), Token::MemoryLayoutChanged)
+                .map_err(Error::CreateWaitContext)?;
+        }
 
         'wait: loop {
             let events = wait_ctx.wait().map_err(Error::WaitError)?;
@@ -174,6 +213,12 @@ impl<T: Vhost> Worker<T> {
                         let _ = self.kill_evt.read();
                         break 'wait;
                     }
+                    Token::MemoryLayoutChanged => {
+                        let mem = self.mem_layout.as_ref().and_then(|l| l.take());
+                        if let Some(mem) = mem {
+                            self.update_mem_table(&mem)?;
+                        }
+                    }
                     Token::ControlNotify => {
                         if let Some(socket) = &self.response_tube {
                             match socket.recv() {
@@ -28,6 +28,7 @@ use virtio_sys::virtio_config::VIRTIO_CONFIG_S_DRIVER_OK;
 use virtio_sys::virtio_config::VIRTIO_CONFIG_S_FAILED;
 use virtio_sys::virtio_config::VIRTIO_CONFIG_S_FEATURES_OK;
 use virtio_sys::virtio_mmio::*;
+use vm_memory::AccessContext;
 use vm_memory::GuestMemory;
 
 use super::*;
@@ -74,10 +75,16 @@ impl VirtioMmioDevice {
         for _ in device.queue_max_sizes() {
             queue_evts.push(Event::new()?)
         }
+        let access_context = AccessContext::new(&device.debug_label());
         let queues: Vec<Queue> = device
             .queue_max_sizes()
             .iter()
-            .map(|&s| Queue::new(s))
+            .enumerate()
+            .map(|(i, &s)| {
+                let mut queue = Queue::new(s);
+                queue.set_access_context(access_context.with_queue(i));
+                queue
+            })
             .collect();
 
         Ok(VirtioMmioDevice {
@@ -21,6 +21,7 @@ use base::Tube;
 use data_model::DataInit;
 use data_model::Le32;
 use hypervisor::Datamatch;
+use hypervisor::MemCacheType;
 use libc::ERANGE;
 use resources::Alloc;
 use resources::AllocOptions;
@@ -36,6 +37,7 @@ use vm_control::VmMemoryDestination;
 use vm_control::VmMemoryRequest;
 use vm_control::VmMemoryResponse;
 use vm_control::VmMemorySource;
+use vm_memory::AccessContext;
 use vm_memory::GuestAddress;
 use vm_memory::GuestMemory;
 
@@ -315,10 +317,16 @@ impl VirtioPciDevice {
         for _ in device.queue_max_sizes() {
             queue_evts.push(Event::new()?)
         }
+        let access_context = AccessContext::new(&device.debug_label());
         let queues: Vec<Queue> = device
             .queue_max_sizes()
             .iter()
-            .map(|&s| Queue::new(s))
+            .enumerate()
+            .map(|(i, &s)| {
+                let mut queue = Queue::new(s);
+                queue.set_access_context(access_context.with_queue(i));
+                queue
+            })
             .collect();
 
         let pci_device_id = VIRTIO_PCI_DEVICE_ID_BASE + device.device_type() as u16;
@@ -701,7 +709,8 @@ impl PciDevice for VirtioPciDevice {
                     format!("virtio-{}-custom_bar", self.device.device_type()),
                     AllocOptions::new()
                         .prefetchable(config.is_prefetchable())
-                        .align(config.size()),
+                        .align(config.size())
+                        .best_fit(true),
                 )
                 .map_err(|e| PciDeviceError::IoAllocationFailed(config.size(), e))?;
             let config = config.set_address(device_addr);
@@ -955,6 +964,7 @@ impl SharedMemoryMapper for VmRequester {
         source: VmMemorySource,
         offset: u64,
         prot: Protection,
+        cache: MemCacheType,
     ) -> anyhow::Result<()> {
         let request = VmMemoryRequest::RegisterMemory {
             source,
@@ -963,6 +973,7 @@ impl SharedMemoryMapper for VmRequester {
                 offset,
             },
             prot,
+            cache,
         };
         self.tube.send(&request).context("failed to send request")?;
         match self
@@ -66,6 +66,7 @@ use base::pagesize;
 use base::pipe;
 use base::round_up_to_page_size;
 use base::warn;
+use base::AccessMode;
 use base::AsRawDescriptor;
 use base::Error;
 use base::Event;
@@ -86,6 +87,7 @@ use base::Tube;
 use base::TubeError;
 use base::WaitContext;
 use data_model::*;
+use hypervisor::MemCacheType;
 #[cfg(feature = "minigbm")]
 use libc::EBADF;
 #[cfg(feature = "minigbm")]
@@ -490,6 +492,7 @@ impl VmRequester {
             // neccesary. In practice, linear buffers for commonly used formats
             // will also support scanout and texturing.
             flags: RutabagaGrallocFlags::empty().use_linear(true),
+            ..Default::default()
         };
 
         let reqs = state
@@ -498,7 +501,7 @@ impl VmRequester {
             .map_err(WlError::GrallocError)?;
         let handle = state
             .gralloc
-            .allocate_memory(reqs)
+            .allocate_memory(reqs.clone())
             .map_err(WlError::GrallocError)?;
         drop(state);
 
@@ -538,10 +541,12 @@ impl VmRequester {
             .context("failed to allocate offset")
             .map_err(WlError::ShmemMapperError)?;
 
-        match state
-            .mapper
-            .add_mapping(source, offset, Protection::read_write())
-        {
+        match state.mapper.add_mapping(
+            source,
+            offset,
+            Protection::read_write(),
+            MemCacheType::Cached,
+        ) {
             Ok(()) => {
                 state.allocs.insert(offset, alloc);
                 Ok(offset)
@@ -896,10 +901,10 @@ impl WlVfd {
             vfd.fence = Some(descriptor);
             Ok(vfd)
         } else {
-            let flags = match FileFlags::from_file(&descriptor) {
-                Ok(FileFlags::Read) => VIRTIO_WL_VFD_READ,
-                Ok(FileFlags::Write) => VIRTIO_WL_VFD_WRITE,
-                Ok(FileFlags::ReadWrite) => VIRTIO_WL_VFD_READ | VIRTIO_WL_VFD_WRITE,
+            let flags = match FileFlags::from_file(&descriptor).map(|f| f.access_mode()) {
+                Ok(AccessMode::Read) => VIRTIO_WL_VFD_READ,
+                Ok(AccessMode::Write) => VIRTIO_WL_VFD_WRITE,
+                Ok(AccessMode::ReadWrite) => VIRTIO_WL_VFD_READ | VIRTIO_WL_VFD_WRITE,
                 _ => 0,
             };
             let mut vfd = WlVfd::default();
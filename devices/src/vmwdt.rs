@@ -13,6 +13,8 @@ use std::process;
 use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
 
 use base::debug;
 use base::error;
@@ -23,17 +25,24 @@ use base::Descriptor;
 use base::Error as SysError;
 use base::Event;
 use base::EventToken;
+use base::ReadNotifier;
 use base::SendTube;
 use base::Timer;
-use base::VmEventType;
+use base::Tube;
+use base::TubeError;
+use base::VmExitReason;
 use base::WaitContext;
 use remain::sorted;
 use sync::Mutex;
 use thiserror::Error;
+use vm_control::VmwdtCommand;
+use vm_control::VmwdtControlResult;
+use vm_control::VmwdtVcpuStatus;
 
 use crate::pci::CrosvmDeviceId;
 use crate::BusAccessInfo;
 use crate::BusDevice;
+use crate::BusResumeDevice;
 use crate::DeviceId;
 
 // Registers offsets
@@ -85,6 +94,40 @@ pub struct VmwdtPerCpu {
     // The pre-programmed one-shot expiration interval. If the guest runs in this
     // interval but we don't receive a periodic event, the guest is stalled.
     next_expiration_interval_ms: i64,
+    // Wall clock time of the last write to VMWDT_REG_LOAD_CNT
+    last_pet: Option<SystemTime>,
+    // Expiration interval set by the host with `VmwdtCommand::SetTimeout`, which replaces the
+    // one programmed by the guest
+    timeout_override_ms: Option<u64>,
+}
+
+impl VmwdtPerCpu {
+    fn status(&self, vcpu_id: usize, paused: bool) -> VmwdtVcpuStatus {
+        let remaining_ms = if !self.is_enabled || self.pid == 0 {
+            None
+        } else if paused {
+            // The countdown restarts from the current guest time on resume.
+            Some(self.next_expiration_interval_ms)
+        } else {
+            let current_guest_time_ms = Vmwdt::get_guest_time_ms(self.ppid, self.pid);
+            Some(
+                self.next_expiration_interval_ms
+                    - (current_guest_time_ms - self.last_guest_time_ms),
+            )
+        };
+
+        VmwdtVcpuStatus {
+            vcpu_id,
+            enabled: self.is_enabled,
+            last_pet_unix_ms: self.last_pet.map(|last_pet| {
+                last_pet
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_millis() as u64
+            }),
+            remaining_ms: remaining_ms.map(|remaining_ms| remaining_ms.max(0) as u64),
+        }
+    }
 }
 
 pub struct Vmwdt {
@@ -96,10 +139,19 @@ pub struct Vmwdt {
     // TODO: @sebastianene add separate reset event for the watchdog
     // Reset source if the device is not responding
     reset_evt_wrtube: SendTube,
+    // Tube serving `VmwdtCommand`s from the host, moved to the worker thread once it starts
+    control_tube: Option<Tube>,
 }
 
 impl Vmwdt {
-    pub fn new(cpu_count: usize, reset_evt_wrtube: SendTube) -> VmwdtResult<Vmwdt> {
+    /// Creates the watchdogs of `cpu_count` vcpus, which send a `VmExitReason::WatchdogBite` to
+    /// `reset_evt_wrtube` when they expire. If `control_tube` is given, the worker thread starts
+    /// right away to answer the `VmwdtCommand`s received on it.
+    pub fn new(
+        cpu_count: usize,
+        reset_evt_wrtube: SendTube,
+        control_tube: Option<Tube>,
+    ) -> VmwdtResult<Vmwdt> {
         let mut vec = Vec::new();
         for _ in 0..cpu_count {
             vec.push(VmwdtPerCpu {
@@ -110,33 +162,51 @@ impl Vmwdt {
                 timer: Timer::new().unwrap(),
                 timer_freq_hz: 0,
                 next_expiration_interval_ms: 0,
+                last_pet: None,
+                timeout_override_ms: None,
             });
         }
         let vm_wdts = Arc::new(Mutex::new(vec));
 
         // Create a new event that will be used to notify the bg thread for exit
         let kill_evt = Event::new().unwrap();
-        Ok(Vmwdt {
+        let mut vmwdt = Vmwdt {
             vm_wdts,
             worker_thread: None,
             kill_evt,
             reset_evt_wrtube,
-        })
+            control_tube,
+        };
+        if vmwdt.control_tube.is_some() {
+            vmwdt.start();
+        }
+        Ok(vmwdt)
     }
 
     pub fn vmwdt_worker_thread(
         vm_wdts: Arc<Mutex<Vec<VmwdtPerCpu>>>,
         kill_evt: Event,
         reset_evt_wrtube: SendTube,
+        control_tube: Option<Tube>,
     ) {
         #[derive(EventToken)]
         enum Token {
             Kill,
             Timer(usize),
+            Control,
         }
 
         let wait_ctx: WaitContext<Token> = WaitContext::new().unwrap();
         wait_ctx.add(&kill_evt, Token::Kill).unwrap();
+        if let Some(control_tube) = &control_tube {
+            wait_ctx
+                .add(control_tube.get_read_notifier(), Token::Control)
+                .unwrap();
+        }
+
+        // Set by `VmwdtCommand::Pause`. The timers keep firing while paused, but they neither
+        // bite nor get re-armed until `VmwdtCommand::Resume`.
+        let mut paused = false;
 
         let len = vm_wdts.lock().len();
         for clock_id in 0..len {
@@ -159,6 +229,9 @@ impl Vmwdt {
                         if let Err(_e) = watchdog.timer.wait() {
                             error!("error waiting for timer event on vcpu {}", cpu_id);
                         }
+                        if paused {
+                            continue;
+                        }
 
                         let current_guest_time_ms =
                             Vmwdt::get_guest_time_ms(watchdog.ppid, watchdog.pid);
@@ -175,27 +248,119 @@ impl Vmwdt {
                             }
                         } else {
                             // The guest ran but it did not send the periodic event
-                            if let Err(_e) =
-                                reset_evt_wrtube.send::<VmEventType>(&VmEventType::WatchdogReset)
+                            if let Err(_e) = reset_evt_wrtube
+                                .send::<VmExitReason>(&VmExitReason::WatchdogBite { vcpu: cpu_id })
                             {
                                 error!("failed to send reset event from vcpu {}", cpu_id)
                             }
                         }
                     }
+                    Token::Control => {
+                        let control_tube = match &control_tube {
+                            Some(control_tube) => control_tube,
+                            None => continue,
+                        };
+                        let command = match control_tube.recv::<VmwdtCommand>() {
+                            Ok(command) => command,
+                            Err(TubeError::Disconnected) => {
+                                if let Err(e) = wait_ctx.delete(control_tube.get_read_notifier()) {
+                                    error!("failed to remove vmwdt control tube: {}", e);
+                                }
+                                continue;
+                            }
+                            Err(e) => {
+                                error!("failed to receive vmwdt command: {}", e);
+                                continue;
+                            }
+                        };
+                        let result =
+                            Vmwdt::handle_command(&mut vm_wdts.lock(), &mut paused, command);
+                        if let Err(e) = control_tube.send(&result) {
+                            error!("failed to send vmwdt command result: {}", e);
+                        }
+                    }
                 }
             }
         }
     }
 
+    /// Runs a `VmwdtCommand` from the host. The caller holds the lock of the watchdogs, so the
+    /// command doesn't race with the MMIO writes of the vcpus.
+    fn handle_command(
+        watchdogs: &mut [VmwdtPerCpu],
+        paused: &mut bool,
+        command: VmwdtCommand,
+    ) -> VmwdtControlResult {
+        match command {
+            VmwdtCommand::Status => VmwdtControlResult::Status {
+                paused: *paused,
+                vcpus: watchdogs
+                    .iter()
+                    .enumerate()
+                    .map(|(vcpu_id, watchdog)| watchdog.status(vcpu_id, *paused))
+                    .collect(),
+            },
+            VmwdtCommand::SetTimeout { secs } => {
+                let timeout_override_ms = if secs == 0 {
+                    None
+                } else {
+                    Some(secs as u64 * 1000)
+                };
+                for watchdog in watchdogs.iter_mut() {
+                    watchdog.timeout_override_ms = timeout_override_ms;
+                    if let Some(timeout_ms) = timeout_override_ms {
+                        watchdog.next_expiration_interval_ms = timeout_ms as i64;
+                    }
+                }
+                // Restart the countdown of the enabled watchdogs with the new timeout. A paused
+                // watchdog picks it up on resume.
+                if timeout_override_ms.is_some() && !*paused {
+                    Vmwdt::rebaseline(watchdogs);
+                }
+                VmwdtControlResult::Ok
+            }
+            VmwdtCommand::Pause => {
+                *paused = true;
+                VmwdtControlResult::Ok
+            }
+            VmwdtCommand::Resume => {
+                if *paused {
+                    *paused = false;
+                    Vmwdt::rebaseline(watchdogs);
+                }
+                VmwdtControlResult::Ok
+            }
+        }
+    }
+
+    /// Restarts the countdown of the enabled watchdogs from the current guest time.
+    fn rebaseline(watchdogs: &mut [VmwdtPerCpu]) {
+        for (cpu_id, watchdog) in watchdogs.iter_mut().enumerate() {
+            if !watchdog.is_enabled || watchdog.pid == 0 {
+                continue;
+            }
+            watchdog.last_guest_time_ms = Vmwdt::get_guest_time_ms(watchdog.ppid, watchdog.pid);
+            if let Err(_e) = watchdog.timer.reset(
+                Duration::from_millis(watchdog.next_expiration_interval_ms.max(0) as u64),
+                None,
+            ) {
+                error!("failed to reset one-shot vcpu time {}", cpu_id);
+            }
+        }
+    }
+
     fn start(&mut self) {
         let vm_wdts = self.vm_wdts.clone();
         let kill_evt = self.kill_evt.try_clone().unwrap();
         let reset_evt_wrtube = self.reset_evt_wrtube.try_clone().unwrap();
+        let control_tube = self.control_tube.take();
 
         self.worker_thread = Some(
             thread::Builder::new()
                 .name("vmwdt worker".into())
-                .spawn(|| Vmwdt::vmwdt_worker_thread(vm_wdts, kill_evt, reset_evt_wrtube))
+                .spawn(|| {
+                    Vmwdt::vmwdt_worker_thread(vm_wdts, kill_evt, reset_evt_wrtube, control_tube)
+                })
                 .map_err(VmwdtError::SpawnThread)
                 .unwrap(),
         );
@@ -305,12 +470,14 @@ impl BusDevice for Vmwdt {
                 let guest_time_ms = Vmwdt::get_guest_time_ms(ppid, pid as u32);
                 let mut wdts_locked = self.vm_wdts.lock();
                 let mut cpu_watchdog = &mut wdts_locked[cpu_index];
-                let next_expiration_interval_ms =
-                    reg_val as u64 * 1000 / cpu_watchdog.timer_freq_hz;
+                let next_expiration_interval_ms = cpu_watchdog
+                    .timeout_override_ms
+                    .unwrap_or(reg_val as u64 * 1000 / cpu_watchdog.timer_freq_hz);
 
                 cpu_watchdog.pid = pid as u32;
                 cpu_watchdog.ppid = ppid;
                 cpu_watchdog.last_guest_time_ms = guest_time_ms;
+                cpu_watchdog.last_pet = Some(SystemTime::now());
                 cpu_watchdog.next_expiration_interval_ms = next_expiration_interval_ms as i64;
 
                 if cpu_watchdog.is_enabled {
@@ -339,12 +506,19 @@ impl BusDevice for Vmwdt {
         }
     }
 }
+
+impl BusResumeDevice for Vmwdt {
+    /// Restarts the countdown of the enabled watchdogs from the current guest time, so the time
+    /// spent suspended doesn't count against the guest.
+    fn resume_imminent(&mut self) {
+        Vmwdt::rebaseline(&mut self.vm_wdts.lock());
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::thread::sleep;
 
-    use base::Tube;
-
     use super::*;
 
     const AARCH64_VMWDT_ADDR: u64 = 0x3000;
@@ -361,7 +535,7 @@ mod tests {
     #[test]
     fn test_watchdog_internal_timer() {
         let (vm_evt_wrtube, _vm_evt_rdtube) = Tube::directional_pair().unwrap();
-        let mut device = Vmwdt::new(TEST_VMWDT_CPU_NO, vm_evt_wrtube).unwrap();
+        let mut device = Vmwdt::new(TEST_VMWDT_CPU_NO, vm_evt_wrtube, None).unwrap();
 
         // Configure the watchdog device, 2Hz internal clock
         device.write(
@@ -391,7 +565,7 @@ mod tests {
     #[test]
     fn test_watchdog_expiration() {
         let (vm_evt_wrtube, vm_evt_rdtube) = Tube::directional_pair().unwrap();
-        let mut device = Vmwdt::new(TEST_VMWDT_CPU_NO, vm_evt_wrtube).unwrap();
+        let mut device = Vmwdt::new(TEST_VMWDT_CPU_NO, vm_evt_wrtube, None).unwrap();
 
         // Configure the watchdog device, 2Hz internal clock
         device.write(
@@ -407,13 +581,116 @@ mod tests {
         sleep(Duration::from_secs(1));
 
         // Verify that our timer expired and the next_expiration_interval_ms changed
-        match vm_evt_rdtube.recv::<VmEventType>() {
+        match vm_evt_rdtube.recv::<VmExitReason>() {
             Ok(vm_event) => {
-                assert!(vm_event == VmEventType::WatchdogReset);
+                assert_eq!(vm_event, VmExitReason::WatchdogBite { vcpu: 0 });
             }
             Err(_e) => {
                 panic!();
             }
         };
     }
+
+    #[test]
+    fn test_watchdog_resume_rebaselines() {
+        let (vm_evt_wrtube, _vm_evt_rdtube) = Tube::directional_pair().unwrap();
+        let mut device = Vmwdt::new(TEST_VMWDT_CPU_NO, vm_evt_wrtube, None).unwrap();
+
+        device.write(
+            vmwdt_bus_address(VMWDT_REG_CLOCK_FREQ_HZ as u64),
+            &[10, 0, 0, 0],
+        );
+        device.write(vmwdt_bus_address(VMWDT_REG_LOAD_CNT as u64), &[1, 0, 0, 0]);
+        device.write(vmwdt_bus_address(VMWDT_REG_STATUS as u64), &[1, 0, 0, 0]);
+        // A baseline taken long before the VM was suspended.
+        device.vm_wdts.lock()[0].last_guest_time_ms = -1000;
+
+        device.resume_imminent();
+
+        let watchdog = &device.vm_wdts.lock()[0];
+        assert_eq!(
+            watchdog.last_guest_time_ms,
+            Vmwdt::get_guest_time_ms(watchdog.ppid, watchdog.pid)
+        );
+    }
+
+    #[test]
+    fn test_watchdog_pause_prevents_expiration() {
+        #[derive(EventToken)]
+        enum Token {
+            Reset,
+        }
+
+        let (vm_evt_wrtube, vm_evt_rdtube) = Tube::directional_pair().unwrap();
+        let (host_tube, device_tube) = Tube::pair().unwrap();
+        let mut device = Vmwdt::new(TEST_VMWDT_CPU_NO, vm_evt_wrtube, Some(device_tube)).unwrap();
+
+        host_tube.send(&VmwdtCommand::Pause).unwrap();
+        assert_eq!(
+            host_tube.recv::<VmwdtControlResult>().unwrap(),
+            VmwdtControlResult::Ok
+        );
+
+        device.write(
+            vmwdt_bus_address(VMWDT_REG_CLOCK_FREQ_HZ as u64),
+            &[10, 0, 0, 0],
+        );
+        device.write(vmwdt_bus_address(VMWDT_REG_LOAD_CNT as u64), &[1, 0, 0, 0]);
+        device.write(vmwdt_bus_address(VMWDT_REG_STATUS as u64), &[1, 0, 0, 0]);
+        // Would bite on the first timer event if the watchdog was running.
+        device.vm_wdts.lock()[0].last_guest_time_ms = -1000;
+
+        sleep(Duration::from_secs(1));
+
+        let wait_ctx =
+            WaitContext::build_with(&[(vm_evt_rdtube.get_read_notifier(), Token::Reset)]).unwrap();
+        assert_eq!(wait_ctx.wait_timeout(Duration::ZERO).unwrap().len(), 0);
+    }
+
+    #[test]
+    fn test_watchdog_status_after_pet() {
+        let (vm_evt_wrtube, _vm_evt_rdtube) = Tube::directional_pair().unwrap();
+        let (host_tube, device_tube) = Tube::pair().unwrap();
+        let mut device = Vmwdt::new(TEST_VMWDT_CPU_NO, vm_evt_wrtube, Some(device_tube)).unwrap();
+
+        host_tube.send(&VmwdtCommand::Status).unwrap();
+        assert_eq!(
+            host_tube.recv::<VmwdtControlResult>().unwrap(),
+            VmwdtControlResult::Status {
+                paused: false,
+                vcpus: vec![VmwdtVcpuStatus {
+                    vcpu_id: 0,
+                    enabled: false,
+                    last_pet_unix_ms: None,
+                    remaining_ms: None,
+                }],
+            }
+        );
+
+        // 10Hz internal clock and a timeout of 50 ticks.
+        device.write(
+            vmwdt_bus_address(VMWDT_REG_CLOCK_FREQ_HZ as u64),
+            &[10, 0, 0, 0],
+        );
+        device.write(vmwdt_bus_address(VMWDT_REG_STATUS as u64), &[1, 0, 0, 0]);
+        let before_pet = SystemTime::now();
+        device.write(vmwdt_bus_address(VMWDT_REG_LOAD_CNT as u64), &[50, 0, 0, 0]);
+
+        host_tube.send(&VmwdtCommand::Status).unwrap();
+        let vcpus = match host_tube.recv::<VmwdtControlResult>().unwrap() {
+            VmwdtControlResult::Status { paused, vcpus } => {
+                assert!(!paused);
+                vcpus
+            }
+            result => panic!("unexpected result {:?}", result),
+        };
+        assert_eq!(vcpus.len(), 1);
+        assert!(vcpus[0].enabled);
+        let last_pet_unix_ms = vcpus[0].last_pet_unix_ms.unwrap();
+        assert!(
+            last_pet_unix_ms >= before_pet.duration_since(UNIX_EPOCH).unwrap().as_millis() as u64
+        );
+        // The test thread doesn't run a guest, so no guest time passed since the pet.
+        assert_eq!(vcpus[0].remaining_ms, Some(5000));
+    }
 }
@@ -45,9 +45,24 @@ pub enum EventDeviceKind {
     Keyboard,
 }
 
+/// The displays whose window events an event device receives.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum EventDeviceDisplay {
+    /// Events from every display that has no event device of the same kind of its own.
+    Shared,
+    /// Events from the display with the given scanout id only.
+    Display(u32),
+    /// No events, until the device is given to a display added while the VM runs.
+    Unassigned,
+}
+
 /// Encapsulates a virtual event device, such as a mouse or keyboard
 pub struct EventDevice {
     kind: EventDeviceKind,
+    display: EventDeviceDisplay,
+    // Range of the absolute axes of the device, to which the coordinates of window events are
+    // scaled if the window has a different size.
+    abs_size: Option<(u32, u32)>,
     event_buffer: VecDeque<u8>,
     event_socket: StreamChannel,
 }
@@ -57,11 +72,26 @@ impl EventDevice {
         let _ = event_socket.set_nonblocking(true);
         EventDevice {
             kind,
+            display: EventDeviceDisplay::Shared,
+            abs_size: None,
             event_buffer: Default::default(),
             event_socket,
         }
     }
 
+    /// Sets the displays whose events the device receives, which are all of them by default.
+    pub fn with_display(mut self, display: EventDeviceDisplay) -> EventDevice {
+        self.display = display;
+        self
+    }
+
+    /// Scales the absolute coordinates of the events sent to the device from the size of the
+    /// window they come from to `width`x`height`, the range of the device's absolute axes.
+    pub fn with_abs_size(mut self, width: u32, height: u32) -> EventDevice {
+        self.abs_size = Some((width, height));
+        self
+    }
+
     #[inline]
     pub fn mouse(event_socket: StreamChannel) -> EventDevice {
         Self::new(EventDeviceKind::Mouse, event_socket)
@@ -82,6 +112,20 @@ impl EventDevice {
         self.kind
     }
 
+    #[inline]
+    pub fn display(&self) -> EventDeviceDisplay {
+        self.display
+    }
+
+    pub fn set_display(&mut self, display: EventDeviceDisplay) {
+        self.display = display;
+    }
+
+    #[inline]
+    pub fn abs_size(&self) -> Option<(u32, u32)> {
+        self.abs_size
+    }
+
     /// Flushes the buffered events that did not fit into the underlying transport, if any.
     ///
     /// Returns `Ok(true)` if, after this function returns, there all the buffer of events is
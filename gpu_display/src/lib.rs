@@ -31,10 +31,16 @@ mod keycode_converter;
 mod sys;
 
 pub use event_device::EventDevice;
+pub use event_device::EventDeviceDisplay;
 pub use event_device::EventDeviceKind;
 #[cfg(windows)]
 pub use gpu_display_win::DisplayProperties as WinDisplayProperties;
 use linux_input_sys::virtio_input_event;
+use linux_input_sys::ABS_MT_POSITION_X;
+use linux_input_sys::ABS_MT_POSITION_Y;
+use linux_input_sys::ABS_X;
+use linux_input_sys::ABS_Y;
+use linux_input_sys::EV_ABS;
 use sys::SysDisplayT;
 pub use sys::SysGpuDisplayExt;
 #[cfg(windows)]
@@ -65,6 +71,9 @@ pub enum GpuDisplayError {
     /// Failed to import a buffer to the compositor.
     #[error("failed to import a buffer to the compositor")]
     FailedImport,
+    /// The event device ID is invalid.
+    #[error("invalid event device ID")]
+    InvalidEventDeviceId,
     /// The import ID is invalid.
     #[error("invalid import ID")]
     InvalidImportId,
@@ -246,6 +255,37 @@ struct GpuDisplayEvents {
     device_type: EventDeviceKind,
 }
 
+/// What `GpuDisplay` knows about a surface to route its input events.
+#[derive(Clone, Copy, Debug, Default)]
+struct SurfaceInfo {
+    scanout_id: Option<u32>,
+    width: u32,
+    height: u32,
+}
+
+/// Scales the absolute coordinates of `event` from the size of the window it comes from, `from`,
+/// to the range of the axes of the device it is sent to, `to`.
+fn scale_abs_event(
+    mut event: virtio_input_event,
+    from: (u32, u32),
+    to: (u32, u32),
+) -> virtio_input_event {
+    if event.type_.to_native() != EV_ABS {
+        return event;
+    }
+    let (from, to) = match event.code.to_native() {
+        ABS_X | ABS_MT_POSITION_X => (from.0, to.0),
+        ABS_Y | ABS_MT_POSITION_Y => (from.1, to.1),
+        _ => return event,
+    };
+    if from == 0 || from == to {
+        return event;
+    }
+    let value = i64::from(event.value.to_native()) * i64::from(to) / i64::from(from);
+    event.value = (value.clamp(i32::MIN.into(), i32::MAX.into()) as i32).into();
+    event
+}
+
 trait DisplayT: AsRawDescriptor {
     /// Returns true if there are events that are on the queue.
     fn pending_events(&self) -> bool {
@@ -313,6 +353,7 @@ pub struct GpuDisplay {
     event_devices: BTreeMap<u32, EventDevice>,
     surfaces: BTreeMap<u32, Box<dyn GpuDisplaySurface>>,
     imports: BTreeMap<u32, Box<dyn GpuDisplayImport>>,
+    surface_info: BTreeMap<u32, SurfaceInfo>,
     // `inner` must be after `imports` and `surfaces` to ensure those objects are dropped before
     // the display context. The drop order for fields inside a struct is the order in which they
     // are declared [Rust RFC 1857].
@@ -341,6 +382,8 @@ impl GpuDisplay {
                 event_devices: Default::default(),
                 surfaces: Default::default(),
                 imports: Default::default(),
+                surface_info: Default::default(),
+                surface_info: Default::default(),
                 wait_ctx,
                 is_x: true,
             })
@@ -360,6 +403,7 @@ impl GpuDisplay {
             event_devices: Default::default(),
             surfaces: Default::default(),
             imports: Default::default(),
+            surface_info: Default::default(),
             wait_ctx,
             is_x: false,
         })
@@ -382,19 +426,14 @@ impl GpuDisplay {
         while self.inner.pending_events() {
             let surface_descriptor = self.inner.next_event()?;
 
-            for surface in self.surfaces.values_mut() {
+            for (surface_id, surface) in self.surfaces.iter_mut() {
                 if surface_descriptor != surface.surface_descriptor() {
                     continue;
                 }
 
                 if let Some(gpu_display_events) = self.inner.handle_next_event(surface) {
-                    for event_device in self.event_devices.values_mut() {
-                        if event_device.kind() != gpu_display_events.device_type {
-                            continue;
-                        }
-
-                        event_device.send_report(gpu_display_events.events.iter().cloned())?;
-                    }
+                    let info = self.surface_info.get(surface_id).copied();
+                    route_display_events(&mut self.event_devices, info, gpu_display_events)?;
                 }
             }
         }
@@ -402,6 +441,19 @@ impl GpuDisplay {
         Ok(())
     }
 
+    /// Sets the displays whose window events the event device `event_device_id` receives.
+    pub fn set_event_device_display(
+        &mut self,
+        event_device_id: u32,
+        display: EventDeviceDisplay,
+    ) -> GpuDisplayResult<()> {
+        self.event_devices
+            .get_mut(&event_device_id)
+            .ok_or(GpuDisplayError::InvalidEventDeviceId)?
+            .set_display(display);
+        Ok(())
+    }
+
     /// Dispatches internal events that were received from the compositor since the last call to
     /// `dispatch_events`.
     pub fn dispatch_events(&mut self) -> GpuDisplayResult<()> {
@@ -459,12 +511,21 @@ impl GpuDisplay {
 
         self.next_id += 1;
         self.surfaces.insert(new_surface_id, new_surface);
+        self.surface_info.insert(
+            new_surface_id,
+            SurfaceInfo {
+                scanout_id: None,
+                width,
+                height,
+            },
+        );
         Ok(new_surface_id)
     }
 
     /// Releases a previously created surface identified by the given handle.
     pub fn release_surface(&mut self, surface_id: u32) {
         self.surfaces.remove(&surface_id);
+        self.surface_info.remove(&surface_id);
     }
 
     /// Gets a reference to an unused framebuffer for the identified surface.
@@ -590,6 +651,166 @@ impl GpuDisplay {
             .ok_or(GpuDisplayError::InvalidSurfaceId)?;
 
         surface.set_scanout_id(scanout_id);
+        if let Some(info) = self.surface_info.get_mut(&surface_id) {
+            info.scanout_id = Some(scanout_id);
+        }
         Ok(())
     }
 }
+
+/// Sends the input events of a window to the event devices of the display it shows, or to the
+/// shared event devices if the display has none of the kind of the events.
+fn route_display_events(
+    event_devices: &mut BTreeMap<u32, EventDevice>,
+    surface: Option<SurfaceInfo>,
+    gpu_display_events: GpuDisplayEvents,
+) -> GpuDisplayResult<()> {
+    let scanout_id = surface.and_then(|s| s.scanout_id);
+    let has_own_device = scanout_id.map_or(false, |scanout_id| {
+        event_devices.values().any(|d| {
+            d.kind() == gpu_display_events.device_type
+                && d.display() == EventDeviceDisplay::Display(scanout_id)
+        })
+    });
+
+    for event_device in event_devices.values_mut() {
+        if event_device.kind() != gpu_display_events.device_type {
+            continue;
+        }
+        let receives = match event_device.display() {
+            EventDeviceDisplay::Shared => !has_own_device,
+            EventDeviceDisplay::Display(id) => Some(id) == scanout_id,
+            EventDeviceDisplay::Unassigned => false,
+        };
+        if !receives {
+            continue;
+        }
+
+        match (surface, event_device.abs_size()) {
+            (Some(surface), Some(abs_size)) => {
+                let size = (surface.width, surface.height);
+                event_device.send_report(
+                    gpu_display_events
+                        .events
+                        .iter()
+                        .map(|&e| scale_abs_event(e, size, abs_size)),
+                )?;
+            }
+            _ => {
+                event_device.send_report(gpu_display_events.events.iter().cloned())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::io::Read;
+
+    use base::BlockingMode;
+    use base::FramingMode;
+    use base::StreamChannel;
+    use data_model::DataInit;
+    use linux_input_sys::InputEventDecoder;
+
+    use super::*;
+
+    fn touchscreen(
+        display: EventDeviceDisplay,
+        abs_size: (u32, u32),
+    ) -> (EventDevice, StreamChannel) {
+        let (device_socket, guest_socket) =
+            StreamChannel::pair(BlockingMode::Nonblocking, FramingMode::Byte).unwrap();
+        let device = EventDevice::touchscreen(device_socket)
+            .with_display(display)
+            .with_abs_size(abs_size.0, abs_size.1);
+        (device, guest_socket)
+    }
+
+    // Reads the (code, value) pairs of the absolute events sent to `socket`.
+    fn read_abs_events(socket: &mut StreamChannel) -> Vec<(u16, i32)> {
+        let mut buf = [0u8; 64 * virtio_input_event::SIZE];
+        let len = match socket.read(&mut buf) {
+            Ok(len) => len,
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => 0,
+            Err(e) => panic!("failed to read events: {}", e),
+        };
+        buf[..len]
+            .chunks(virtio_input_event::SIZE)
+            .map(|chunk| *virtio_input_event::from_slice(chunk).unwrap())
+            .filter(|e| e.type_.to_native() == EV_ABS)
+            .map(|e| (e.code.to_native(), e.value.to_native()))
+            .collect()
+    }
+
+    fn touch(x: i32, y: i32) -> GpuDisplayEvents {
+        GpuDisplayEvents {
+            events: vec![
+                virtio_input_event::absolute_x(x),
+                virtio_input_event::absolute_y(y),
+            ],
+            device_type: EventDeviceKind::Touchscreen,
+        }
+    }
+
+    fn surface(scanout_id: u32, width: u32, height: u32) -> Option<SurfaceInfo> {
+        Some(SurfaceInfo {
+            scanout_id: Some(scanout_id),
+            width,
+            height,
+        })
+    }
+
+    #[test]
+    fn per_display_routing() {
+        let mut devices = BTreeMap::new();
+        let (device, mut guest0) = touchscreen(EventDeviceDisplay::Display(0), (1280, 720));
+        devices.insert(0, device);
+        let (device, mut guest1) = touchscreen(EventDeviceDisplay::Display(1), (800, 600));
+        devices.insert(1, device);
+        let (device, mut spare) = touchscreen(EventDeviceDisplay::Unassigned, (1280, 1024));
+        devices.insert(2, device);
+
+        // The windows are twice the size of the displays' input ranges.
+        route_display_events(&mut devices, surface(0, 2560, 1440), touch(2000, 1000)).unwrap();
+        route_display_events(&mut devices, surface(1, 1600, 1200), touch(1600, 1200)).unwrap();
+
+        assert_eq!(
+            read_abs_events(&mut guest0),
+            vec![(ABS_X, 1000), (ABS_Y, 500)]
+        );
+        assert_eq!(
+            read_abs_events(&mut guest1),
+            vec![(ABS_X, 800), (ABS_Y, 600)]
+        );
+        assert_eq!(read_abs_events(&mut spare), vec![]);
+    }
+
+    #[test]
+    fn shared_fallback() {
+        let mut devices = BTreeMap::new();
+        let (device, mut own) = touchscreen(EventDeviceDisplay::Display(0), (100, 100));
+        devices.insert(0, device);
+        let (device, mut shared) = touchscreen(EventDeviceDisplay::Shared, (100, 100));
+        devices.insert(1, device);
+
+        // Display 0 has its own device, display 1 falls back to the shared one.
+        route_display_events(&mut devices, surface(0, 100, 100), touch(10, 20)).unwrap();
+        route_display_events(&mut devices, surface(1, 100, 100), touch(30, 40)).unwrap();
+
+        assert_eq!(read_abs_events(&mut own), vec![(ABS_X, 10), (ABS_Y, 20)]);
+        assert_eq!(read_abs_events(&mut shared), vec![(ABS_X, 30), (ABS_Y, 40)]);
+    }
+
+    #[test]
+    fn scale_abs_event_other_events() {
+        let key = virtio_input_event::key(1, true);
+        let scaled = scale_abs_event(key, (100, 100), (200, 200));
+        assert_eq!(scaled.value.to_native(), key.value.to_native());
+
+        // Empty windows are not scaled.
+        let x = virtio_input_event::absolute_x(5);
+        assert_eq!(scale_abs_event(x, (0, 0), (200, 200)).value.to_native(), 5);
+    }
+}
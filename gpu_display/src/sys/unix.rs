@@ -56,6 +56,7 @@ impl UnixGpuDisplayExt for GpuDisplay {
             event_devices: Default::default(),
             surfaces: Default::default(),
             imports: Default::default(),
+            surface_info: Default::default(),
             wait_ctx,
             is_x: false,
         })
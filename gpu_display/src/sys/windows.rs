@@ -66,6 +66,7 @@ impl WinGpuDisplayExt for GpuDisplay {
             event_devices: Default::default(),
             surfaces: Default::default(),
             imports: Default::default(),
+            surface_info: Default::default(),
             wait_ctx,
             is_x: false,
         })
@@ -49,13 +49,26 @@ impl TryFrom<u32> for PsciVersion {
 pub const PSCI_0_2: PsciVersion = PsciVersion { major: 0, minor: 2 };
 pub const PSCI_1_0: PsciVersion = PsciVersion { major: 1, minor: 0 };
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum VcpuRegAArch64 {
     X(u8),
     Sp,
     Pc,
     Pstate,
+    /// System register, encoded as returned by `sysreg`.
+    System(u16),
+}
+
+/// Encodes a system register by its (Op0, Op1, CRn, CRm, Op2) operands, as used by `MRS`/`MSR`.
+pub const fn sysreg(op0: u16, op1: u16, crn: u16, crm: u16, op2: u16) -> u16 {
+    (op0 << 14) | (op1 << 11) | (crn << 7) | (crm << 3) | op2
 }
 
+/// Main ID Register.
+pub const MIDR_EL1: u16 = sysreg(3, 0, 0, 0, 0);
+/// Revision ID Register.
+pub const REVIDR_EL1: u16 = sysreg(3, 0, 0, 0, 6);
+
 /// A wrapper for using a VM on aarch64 and getting/setting its state.
 pub trait VmAArch64: Vm {
     /// Gets the `Hypervisor` that created this VM.
@@ -98,6 +111,17 @@ pub trait VcpuAArch64: Vcpu {
     /// Gets the current PSCI version.
     fn get_psci_version(&self) -> Result<PsciVersion>;
 
+    /// Gets the value of the virtual counter (`CNTVCT_EL0`) as currently seen by the guest.
+    fn get_virtual_counter(&self) -> Result<u64>;
+
+    /// Makes the guest see `value` as the current value of the virtual counter, by changing the
+    /// virtual counter offset (`CNTVOFF_EL2`). The offset is shared by all the VCPUs of the VM.
+    fn set_virtual_counter(&self, value: u64) -> Result<()>;
+
+    /// Injects an external data abort into this VCPU, to complete the MMIO access that caused the
+    /// last exit instead of emulating it.
+    fn inject_external_data_abort(&self) -> Result<()>;
+
     #[cfg(feature = "gdb")]
     /// Sets up debug registers and configure vcpu for handling guest debug events.
     fn set_guest_debug(&self, addrs: &[GuestAddress], enable_singlestep: bool) -> Result<()>;
@@ -129,8 +153,18 @@ impl_downcast!(VcpuAArch64);
 #[derive(Clone, Default)]
 pub struct VcpuInitAArch64 {}
 
-#[derive(Clone, Debug, PartialEq, Eq)]
-pub struct CpuConfigAArch64 {}
+/// Per-VCPU configuration for AArch64 VCPUs.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CpuConfigAArch64 {
+    /// Value reported to the guest in MIDR_EL1 instead of the host's.
+    ///
+    /// Only reads from EL1 (the guest kernel, or EL0 through its emulation) observe the override;
+    /// firmware running at EL2 and the host are unaffected.
+    pub midr: Option<u32>,
+    /// Value reported to the guest in REVIDR_EL1 instead of the host's, with the same caveats as
+    /// `midr`.
+    pub revidr: Option<u32>,
+}
 
 // Convenience constructors for IrqRoutes
 impl IrqRoute {
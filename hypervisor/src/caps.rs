@@ -39,4 +39,6 @@ pub enum VmCap {
     Protected,
     /// VM completes initialization of CPUID at creation time, not required after.
     EarlyInitCpuid,
+    /// Memory regions can be mapped into the guest with a `MemCacheType` other than `Cached`.
+    MemCacheAttributes,
 }
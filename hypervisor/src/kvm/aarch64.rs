@@ -37,6 +37,7 @@ use super::Kvm;
 use super::KvmCap;
 use super::KvmVcpu;
 use super::KvmVm;
+use crate::sysreg;
 use crate::ClockState;
 use crate::DeviceKind;
 use crate::Hypervisor;
@@ -357,6 +358,10 @@ impl KvmVcpuRegister {
     pub const SMCCC_ARCH_WORKAROUND_1: Self = Self::Firmware(1);
     pub const SMCCC_ARCH_WORKAROUND_2: Self = Self::Firmware(2);
     pub const SMCCC_ARCH_WORKAROUND_3: Self = Self::Firmware(3);
+
+    // The KVM ABI swaps the encodings of CNTVCT_EL0 and CNTV_CVAL_EL0: this is the virtual count,
+    // and writing it changes the virtual counter offset of the whole VM.
+    pub const TIMER_CNT: Self = Self::System(sysreg(3, 3, 14, 3, 2));
 }
 
 /// Gives the `u64` register ID expected by the `GET_ONE_REG`/`SET_ONE_REG` ioctl API.
@@ -511,6 +516,7 @@ impl From<VcpuRegAArch64> for KvmVcpuRegister {
             VcpuRegAArch64::Sp => Self::Sp,
             VcpuRegAArch64::Pc => Self::Pc,
             VcpuRegAArch64::Pstate => Self::Pstate,
+            VcpuRegAArch64::System(n) => Self::System(n),
         }
     }
 }
@@ -649,6 +655,14 @@ impl VcpuAArch64 for KvmVcpu {
         self.get_one_kvm_reg_u64(KvmVcpuRegister::from(reg_id))
     }
 
+    fn get_virtual_counter(&self) -> Result<u64> {
+        self.get_one_kvm_reg_u64(KvmVcpuRegister::TIMER_CNT)
+    }
+
+    fn set_virtual_counter(&self, value: u64) -> Result<()> {
+        self.set_one_kvm_reg_u64(KvmVcpuRegister::TIMER_CNT, value)
+    }
+
     fn get_psci_version(&self) -> Result<PsciVersion> {
         let version = if let Ok(v) = self.get_one_kvm_reg_u64(KvmVcpuRegister::PSCI_VERSION) {
             let v = u32::try_from(v).map_err(|_| Error::new(EINVAL))?;
@@ -667,6 +681,19 @@ impl VcpuAArch64 for KvmVcpu {
         }
     }
 
+    fn inject_external_data_abort(&self) -> Result<()> {
+        let mut events = kvm_vcpu_events::default();
+        events.exception.ext_dabt_pending = 1;
+        // Safe because we allocated the struct and we know the kernel will read exactly the size of
+        // the struct.
+        let ret = unsafe { ioctl_with_ref(self, KVM_SET_VCPU_EVENTS(), &events) };
+        if ret == 0 {
+            Ok(())
+        } else {
+            errno_result()
+        }
+    }
+
     #[cfg(feature = "gdb")]
     fn get_max_hw_bps(&self) -> Result<usize> {
         // Safe because the kernel will only return the result of the ioctl.
@@ -61,6 +61,7 @@ use libc::EINVAL;
 use libc::EIO;
 use libc::ENOENT;
 use libc::ENOSPC;
+use libc::ENOTSUP;
 use libc::EOVERFLOW;
 use libc::O_CLOEXEC;
 use libc::O_RDWR;
@@ -83,6 +84,7 @@ use crate::IoParams;
 use crate::IrqRoute;
 use crate::IrqSource;
 use crate::MPState;
+use crate::MemCacheType;
 use crate::MemSlot;
 use crate::Vcpu;
 use crate::VcpuExit;
@@ -229,7 +231,7 @@ impl KvmVm {
                 set_user_memory_region(
                     &vm_descriptor,
                     index as MemSlot,
-                    false,
+                    guest_mem.is_read_only(guest_addr),
                     false,
                     guest_addr.offset(),
                     size as u64,
@@ -494,6 +496,8 @@ impl Vm for KvmVm {
             VmCap::PvClockSuspend => self.check_raw_capability(KvmCap::KvmclockCtrl),
             VmCap::Protected => self.check_raw_capability(KvmCap::ArmProtectedVm),
             VmCap::EarlyInitCpuid => false,
+            // KVM has no uAPI for choosing the memory type of a user memory region.
+            VmCap::MemCacheAttributes => false,
         }
     }
 
@@ -511,7 +515,11 @@ impl Vm for KvmVm {
         mem: Box<dyn MappedRegion>,
         read_only: bool,
         log_dirty_pages: bool,
+        cache: MemCacheType,
     ) -> Result<MemSlot> {
+        if cache != MemCacheType::Cached {
+            return Err(Error::new(ENOTSUP));
+        }
         let pgsz = pagesize() as u64;
         // KVM require to set the user memory region with page size aligned size. Safe to extend
         // the mem.size() to be page size aligned because the mmap will round up the size to be
@@ -1386,11 +1394,23 @@ mod tests {
         let mut vm = KvmVm::new(&kvm, gm, Default::default()).unwrap();
         let mem_size = 0x1000;
         let mem = MemoryMappingBuilder::new(mem_size).build().unwrap();
-        vm.add_memory_region(GuestAddress(0x1000), Box::new(mem), false, false)
-            .unwrap();
+        vm.add_memory_region(
+            GuestAddress(0x1000),
+            Box::new(mem),
+            false,
+            false,
+            MemCacheType::Cached,
+        )
+        .unwrap();
         let mem = MemoryMappingBuilder::new(mem_size).build().unwrap();
-        vm.add_memory_region(GuestAddress(0x10000), Box::new(mem), false, false)
-            .unwrap();
+        vm.add_memory_region(
+            GuestAddress(0x10000),
+            Box::new(mem),
+            false,
+            false,
+            MemCacheType::Cached,
+        )
+        .unwrap();
     }
 
     #[test]
@@ -1400,8 +1420,29 @@ mod tests {
         let mut vm = KvmVm::new(&kvm, gm, Default::default()).unwrap();
         let mem_size = 0x1000;
         let mem = MemoryMappingBuilder::new(mem_size).build().unwrap();
-        vm.add_memory_region(GuestAddress(0x1000), Box::new(mem), true, false)
-            .unwrap();
+        vm.add_memory_region(
+            GuestAddress(0x1000),
+            Box::new(mem),
+            true,
+            false,
+            MemCacheType::Cached,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn add_memory_uncached() {
+        let kvm = Kvm::new().unwrap();
+        let gm = GuestMemory::new(&[(GuestAddress(0), 0x1000)]).unwrap();
+        let mut vm = KvmVm::new(&kvm, gm, Default::default()).unwrap();
+        assert!(!vm.check_capability(VmCap::MemCacheAttributes));
+        for cache in [MemCacheType::WriteCombine, MemCacheType::Uncached] {
+            let mem = MemoryMappingBuilder::new(0x1000).build().unwrap();
+            let err = vm
+                .add_memory_region(GuestAddress(0x1000), Box::new(mem), false, false, cache)
+                .unwrap_err();
+            assert_eq!(err.errno(), ENOTSUP);
+        }
     }
 
     #[test]
@@ -1413,7 +1454,13 @@ mod tests {
         let mem = MemoryMappingBuilder::new(mem_size).build().unwrap();
         let mem_ptr = mem.as_ptr();
         let slot = vm
-            .add_memory_region(GuestAddress(0x1000), Box::new(mem), false, false)
+            .add_memory_region(
+                GuestAddress(0x1000),
+                Box::new(mem),
+                false,
+                false,
+                MemCacheType::Cached,
+            )
             .unwrap();
         let removed_mem = vm.remove_memory_region(slot).unwrap();
         assert_eq!(removed_mem.size(), mem_size);
@@ -1436,7 +1483,13 @@ mod tests {
         let mem_size = 0x2000;
         let mem = MemoryMappingBuilder::new(mem_size).build().unwrap();
         assert!(vm
-            .add_memory_region(GuestAddress(0x2000), Box::new(mem), false, false)
+            .add_memory_region(
+                GuestAddress(0x2000),
+                Box::new(mem),
+                false,
+                false,
+                MemCacheType::Cached
+            )
             .is_err());
     }
 
@@ -1449,7 +1502,13 @@ mod tests {
         let mem_size = 0x1000;
         let mem = MemoryMappingArena::new(mem_size).unwrap();
         let slot = vm
-            .add_memory_region(GuestAddress(0x1000), Box::new(mem), false, false)
+            .add_memory_region(
+                GuestAddress(0x1000),
+                Box::new(mem),
+                false,
+                false,
+                MemCacheType::Cached,
+            )
             .unwrap();
         vm.msync_memory_region(slot, mem_size, 0).unwrap();
         assert!(vm.msync_memory_region(slot, mem_size + 1, 0).is_err());
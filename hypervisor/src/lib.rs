@@ -38,6 +38,23 @@ pub use crate::x86_64::*;
 /// An index in the list of guest-mapped memory regions.
 pub type MemSlot = u32;
 
+/// The caching attributes used for a memory region mapped into the guest.
+///
+/// Anything other than `Cached` is only honored by hypervisors reporting
+/// `VmCap::MemCacheAttributes`. The host's own mapping of the region keeps the attributes chosen
+/// by whoever exported it (e.g. the graphics driver for a dma-buf), since there is no userspace
+/// interface for changing them after the fact.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MemCacheType {
+    /// Normal write-back cacheable memory.
+    #[default]
+    Cached,
+    /// Uncached memory whose writes may be combined, typically used for framebuffers.
+    WriteCombine,
+    /// Uncached memory, typically used for device registers.
+    Uncached,
+}
+
 /// A trait for checking hypervisor capabilities.
 pub trait Hypervisor: Send {
     /// Makes a shallow clone of this `Hypervisor`.
@@ -83,12 +100,17 @@ pub trait Vm: Send {
     ///
     /// If `log_dirty_pages` is true, the slot number can be used to retrieve the pages written to
     /// by the guest with `get_dirty_log`.
+    ///
+    /// `cache` selects the caching attributes the guest sees for the region. Hypervisors that do
+    /// not report `VmCap::MemCacheAttributes` fail with `ENOTSUP` for anything but
+    /// `MemCacheType::Cached`.
     fn add_memory_region(
         &mut self,
         guest_addr: GuestAddress,
         mem_region: Box<dyn MappedRegion>,
         read_only: bool,
         log_dirty_pages: bool,
+        cache: MemCacheType,
     ) -> Result<MemSlot>;
 
     /// Does a synchronous msync of the memory mapped at `slot`, syncing `size` bytes starting at
@@ -52,6 +52,7 @@ use crate::DestinationMode;
 use crate::DeviceKind;
 use crate::IoEventAddress;
 use crate::LapicState;
+use crate::MemCacheType;
 use crate::MemSlot;
 use crate::TriggerMode;
 use crate::VcpuX86_64;
@@ -408,6 +409,7 @@ impl Vm for WhpxVm {
             VmCap::Protected => false,
             // whpx initializes cpuid early during VM creation.
             VmCap::EarlyInitCpuid => true,
+            VmCap::MemCacheAttributes => false,
         }
     }
 
@@ -421,7 +423,11 @@ impl Vm for WhpxVm {
         mem: Box<dyn MappedRegion>,
         read_only: bool,
         log_dirty_pages: bool,
+        cache: MemCacheType,
     ) -> Result<MemSlot> {
+        if cache != MemCacheType::Cached {
+            return Err(Error::new(ENOTSUP));
+        }
         let size = mem.size() as u64;
         let end_addr = guest_addr.checked_add(size).ok_or(Error::new(EOVERFLOW))?;
         if self.guest_mem.range_overlap(guest_addr, end_addr) {
@@ -977,8 +983,14 @@ mod tests {
             .from_shared_memory(&shm)
             .build()
             .unwrap();
-        vm.add_memory_region(GuestAddress(0x1000), Box::new(mem), true, false)
-            .unwrap();
+        vm.add_memory_region(
+            GuestAddress(0x1000),
+            Box::new(mem),
+            true,
+            false,
+            MemCacheType::Cached,
+        )
+        .unwrap();
     }
 
     #[test]
@@ -998,7 +1010,13 @@ mod tests {
             .unwrap();
         let mem_ptr = mem.as_ptr();
         let slot = vm
-            .add_memory_region(GuestAddress(0x1000), Box::new(mem), false, false)
+            .add_memory_region(
+                GuestAddress(0x1000),
+                Box::new(mem),
+                false,
+                false,
+                MemCacheType::Cached,
+            )
             .unwrap();
         let removed_mem = vm.remove_memory_region(slot).unwrap();
         assert_eq!(removed_mem.size(), mem_size);
@@ -1033,7 +1051,13 @@ mod tests {
             .build()
             .unwrap();
         assert!(vm
-            .add_memory_region(GuestAddress(0x2000), Box::new(mem), false, false)
+            .add_memory_region(
+                GuestAddress(0x2000),
+                Box::new(mem),
+                false,
+                false,
+                MemCacheType::Cached
+            )
             .is_err());
     }
 
@@ -1053,7 +1077,13 @@ mod tests {
             .build()
             .unwrap();
         let slot = vm
-            .add_memory_region(GuestAddress(0x10000), Box::new(mem), false, false)
+            .add_memory_region(
+                GuestAddress(0x10000),
+                Box::new(mem),
+                false,
+                false,
+                MemCacheType::Cached,
+            )
             .unwrap();
         vm.msync_memory_region(slot, mem_size - 1, 0).unwrap();
         vm.msync_memory_region(slot, 0, mem_size).unwrap();
@@ -116,6 +116,7 @@ where
             ),
             false,
             true,
+            MemCacheType::Cached,
         )
         .expect("failed to register memory");
 
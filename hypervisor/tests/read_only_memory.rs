@@ -125,6 +125,7 @@ where
         ),
         false,
         false,
+        MemCacheType::Cached,
     )
     .expect("failed to register memory");
 
@@ -149,6 +150,7 @@ where
         ),
         true,
         false,
+        MemCacheType::Cached,
     )
     .expect("failed to register memory");
 
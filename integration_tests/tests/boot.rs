@@ -3,6 +3,10 @@
 // found in the LICENSE file.
 
 pub mod fixture;
+use std::fs;
+use std::time::Duration;
+use std::time::Instant;
+
 use fixture::Config;
 use fixture::TestVm;
 
@@ -27,3 +31,154 @@ fn boot_test_suspend_resume() {
     vm.resume().unwrap();
     assert_eq!(vm.exec_in_guest("echo 42").unwrap().trim(), "42");
 }
+
+#[test]
+fn boot_test_suspend_resume_during_command() {
+    let mut vm = TestVm::new(Config::new()).unwrap();
+    let command = vm
+        .exec_in_guest_async("sleep 1; echo done", Duration::from_secs(10))
+        .unwrap();
+    vm.suspend().unwrap();
+    vm.resume().unwrap();
+    assert_eq!(command.join().unwrap(), "done");
+    assert_eq!(vm.exec_in_guest("echo 42").unwrap().trim(), "42");
+}
+
+#[test]
+fn boot_test_exec_in_guest_timeout() {
+    let mut vm = TestVm::new(Config::new()).unwrap();
+    assert_eq!(
+        vm.exec_in_guest_timeout("echo 42", Duration::from_secs(10))
+            .unwrap(),
+        "42"
+    );
+    let err = vm
+        .exec_in_guest_timeout("echo partial; sleep 5", Duration::from_millis(500))
+        .unwrap_err();
+    assert!(err.to_string().contains("partial"), "{}", err);
+}
+
+#[test]
+fn boot_test_suspend_resume_stress() {
+    let mut vm = TestVm::new(Config::new()).unwrap();
+    vm.suspend_resume_stress(20).unwrap();
+    assert_eq!(vm.exec_in_guest("echo 42").unwrap().trim(), "42");
+}
+
+#[test]
+fn boot_test_boot_times() {
+    let mut vm = TestVm::new(Config::new()).unwrap();
+    assert_eq!(vm.exec_in_guest("echo 42").unwrap().trim(), "42");
+    let times = vm.boot_times().unwrap();
+    assert!(!times.contains("\"first_vcpu_run\": null"), "{}", times);
+    assert!(
+        !times.contains("\"first_serial_output\": null"),
+        "{}",
+        times
+    );
+}
+
+#[test]
+fn boot_test_vm_uuid() {
+    const UUID: &str = "23546c3d-962d-4ebc-94d9-4acf50996944";
+    let mut vm =
+        TestVm::new(Config::new().extra_args(vec!["--vm-uuid".to_owned(), UUID.to_owned()]))
+            .unwrap();
+
+    let info = vm.vm_info().unwrap();
+    assert!(
+        info.contains(&format!("\"uuid\": \"{}\"", UUID)),
+        "{}",
+        info
+    );
+
+    let guest_uuid = if cfg!(target_arch = "aarch64") {
+        vm.exec_in_guest("cat /proc/device-tree/chosen/crosvm,vm-uuid")
+    } else {
+        vm.exec_in_guest("cat /sys/class/dmi/id/product_uuid")
+    }
+    .unwrap();
+    assert_eq!(
+        guest_uuid.trim_matches(|c: char| c.is_whitespace() || c == '\0'),
+        UUID
+    );
+}
+
+#[test]
+fn boot_test_set_kernel_cmdline() {
+    let mut vm = TestVm::new(Config::new().start_paused()).unwrap();
+    // The new command line replaces the one crosvm generated, so it must still boot the delegate.
+    vm.set_kernel_cmdline(
+        "panic=-1 console=ttyS0 root=/dev/vda ro init=/bin/delegate crosvm_test.cmdline=1",
+    )
+    .unwrap();
+    vm.resume_and_wait_ready().unwrap();
+    let cmdline = vm.exec_in_guest("cat /proc/cmdline").unwrap();
+    assert!(cmdline.contains("crosvm_test.cmdline=1"), "{}", cmdline);
+
+    // The guest already read its command line.
+    assert!(vm.set_kernel_cmdline("panic=-1").is_err());
+}
+
+#[test]
+fn boot_test_exit_status_poweroff() {
+    let mut vm = TestVm::new(Config::new().extended_status()).unwrap();
+    vm.exec_in_guest_no_wait("poweroff -f").unwrap();
+    assert_eq!(vm.wait_for_exit().unwrap().code(), Some(0));
+}
+
+#[test]
+fn boot_test_exit_status_reboot() {
+    let mut vm = TestVm::new(Config::new().extended_status()).unwrap();
+    vm.exec_in_guest_no_wait("reboot -f").unwrap();
+    assert_eq!(vm.wait_for_exit().unwrap().code(), Some(32));
+}
+
+#[cfg(target_arch = "aarch64")]
+#[test]
+fn boot_test_exit_status_watchdog_bite() {
+    let mut vm = TestVm::new(Config::new().extended_status()).unwrap();
+    // Arm the watchdog of vcpu 0 to bite after 1 second of guest time at 2Hz, then keep the vcpu
+    // busy without ever petting it.
+    vm.exec_in_guest_no_wait(
+        "taskset 1 sh -c 'devmem 0x300c 32 2; devmem 0x3004 32 2; devmem 0x3000 32 1; \
+         while true; do :; done'",
+    )
+    .unwrap();
+    assert_eq!(vm.wait_for_exit().unwrap().code(), Some(36));
+}
+
+#[test]
+fn boot_test_console_history() {
+    let mut vm = TestVm::new(Config::new()).unwrap();
+    // The guest is ready, so the kernel banner was printed long ago.
+    assert!(vm.console_contains(r"Linux version \d+\.\d+").unwrap());
+    vm.wait_for_console(r"Linux version", Duration::from_secs(1))
+        .unwrap();
+}
+
+#[test]
+fn boot_test_console_history_timeout() {
+    let mut vm = TestVm::new(Config::new()).unwrap();
+    assert!(!vm.console_contains("^never printed by the guest$").unwrap());
+    let start = Instant::now();
+    assert!(vm
+        .wait_for_console("^never printed by the guest$", Duration::from_millis(500))
+        .is_err());
+    assert!(start.elapsed() >= Duration::from_millis(500));
+}
+
+#[test]
+fn boot_test_rotate_console() {
+    let mut vm = TestVm::new(Config::new()).unwrap();
+    let rotated = vm.rotate_console().unwrap();
+    vm.exec_in_guest("echo crosvm_test_rotated > /dev/ttyS0")
+        .unwrap();
+    vm.wait_for_console("^crosvm_test_rotated", Duration::from_secs(10))
+        .unwrap();
+
+    // The boot log stays in the rotated file, the output that followed went to the new one.
+    let before = String::from_utf8_lossy(&fs::read(rotated).unwrap()).into_owned();
+    assert!(before.contains("Linux version"), "{}", before);
+    assert!(!before.contains("crosvm_test_rotated"), "{}", before);
+}
@@ -4,29 +4,43 @@
 
 use std::env;
 use std::ffi::CString;
+use std::fs;
 use std::fs::File;
 use std::fs::OpenOptions;
 use std::io;
-use std::io::BufRead;
-use std::io::BufReader;
+use std::io::Read;
 use std::io::Write;
 use std::os::unix::fs::OpenOptionsExt;
 use std::path::Path;
 use std::path::PathBuf;
 use std::process::Child;
 use std::process::Command;
+use std::process::ExitStatus;
+use std::process::Output;
 use std::process::Stdio;
 use std::str::from_utf8;
+use std::sync::mpsc::channel;
 use std::sync::mpsc::sync_channel;
+use std::sync::mpsc::Receiver;
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::Arc;
+use std::sync::Mutex;
 use std::sync::Once;
 use std::thread;
+use std::thread::JoinHandle;
 use std::time::Duration;
+use std::time::Instant;
 
 use anyhow::anyhow;
 use anyhow::Result;
 use base::syslog;
 use libc::O_DIRECT;
+use regex::bytes::Regex;
+use regex::bytes::RegexBuilder;
 use tempfile::TempDir;
+use vm_control::client::VmClient;
+use vm_control::VmRequest;
+use vm_control::VmResponse;
 
 const PREBUILT_URL: &str = "https://storage.googleapis.com/chromeos-localmirror/distfiles";
 
@@ -41,6 +55,9 @@ const ARCH: &str = "aarch64";
 /// do not block the tests.
 const VM_COMMUNICATION_TIMEOUT: Duration = Duration::from_secs(10);
 
+/// How much of the guest console output `TestVm` keeps in memory.
+const CONSOLE_HISTORY_BYTES: usize = 1 << 20;
+
 fn prebuilt_version() -> &'static str {
     include_str!("../../guest_under_test/PREBUILT_VERSION").trim()
 }
@@ -170,8 +187,14 @@ pub struct Config {
     /// Extra arguments for the `run` subcommand.
     extra_args: Vec<String>,
 
+    /// Run crosvm with `--extended-status`.
+    extended_status: bool,
+
     /// Use `O_DIRECT` for the rootfs.
     o_direct: bool,
+
+    /// Start the VM with its vcpus suspended.
+    start_paused: bool,
 }
 
 #[cfg(test)]
@@ -188,11 +211,206 @@ impl Config {
         self
     }
 
+    /// Runs crosvm with `--extended-status`, so that its exit code tells why the VM stopped.
+    #[allow(dead_code)]
+    pub fn extended_status(mut self) -> Self {
+        self.extended_status = true;
+        self
+    }
+
     /// Uses `O_DIRECT` for the rootfs.
     pub fn o_direct(mut self) -> Self {
         self.o_direct = true;
         self
     }
+
+    /// Starts the VM with `--start-paused`. `TestVm::new` then returns without waiting for the
+    /// guest, which boots after `TestVm::resume_and_wait_ready`.
+    #[allow(dead_code)]
+    pub fn start_paused(mut self) -> Self {
+        self.start_paused = true;
+        self
+    }
+}
+
+/// Output of the guest console, which crosvm writes to a file. Only the last
+/// `CONSOLE_HISTORY_BYTES` are kept in memory.
+struct ConsoleHistory {
+    path: PathBuf,
+    /// Opened once crosvm created the file, and read up to where the history ends.
+    file: Option<File>,
+    history: Vec<u8>,
+}
+
+impl ConsoleHistory {
+    fn new(path: PathBuf) -> Self {
+        ConsoleHistory {
+            path,
+            file: None,
+            history: Vec::new(),
+        }
+    }
+
+    /// Appends what the guest printed since the last call to the history.
+    fn update(&mut self) -> Result<()> {
+        if self.file.is_none() {
+            match File::open(&self.path) {
+                Ok(file) => self.file = Some(file),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        self.file.as_mut().unwrap().read_to_end(&mut self.history)?;
+        if self.history.len() > CONSOLE_HISTORY_BYTES {
+            self.history
+                .drain(..self.history.len() - CONSOLE_HISTORY_BYTES);
+        }
+        Ok(())
+    }
+
+    fn contains(&mut self, regex: &Regex) -> Result<bool> {
+        self.update()?;
+        Ok(regex.is_match(&self.history))
+    }
+
+    /// Renames the console file to `rotated`, keeping the history read so far. The file created
+    /// at the path afterwards is read from then on.
+    fn rotate(&mut self, rotated: &Path) -> Result<()> {
+        self.update()?;
+        fs::rename(&self.path, rotated)?;
+        self.file = None;
+        Ok(())
+    }
+}
+
+/// Output of the delegate binary in the guest, read from the pipe by a background thread so that
+/// waiting for it can time out.
+struct GuestOutput {
+    chunks: Receiver<Vec<u8>>,
+    /// Received bytes that don't make a complete line yet.
+    pending: Vec<u8>,
+}
+
+impl GuestOutput {
+    fn new(mut pipe: File) -> Self {
+        let (tx, rx) = channel();
+        // Stops when crosvm closes the pipe.
+        thread::spawn(move || {
+            let mut buf = [0u8; 4096];
+            loop {
+                match pipe.read(&mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => {
+                        if tx.send(buf[..n].to_vec()).is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+        });
+        GuestOutput {
+            chunks: rx,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Returns the next line, or `None` if it isn't complete by `deadline`. Without a deadline,
+    /// waits for as long as it takes.
+    fn read_line(&mut self, deadline: Option<Instant>) -> Result<Option<String>> {
+        loop {
+            if let Some(end) = self.pending.iter().position(|&b| b == b'\n') {
+                let line = self.pending.drain(..=end).collect::<Vec<u8>>();
+                return Ok(Some(String::from_utf8(line)?));
+            }
+            let chunk = match deadline {
+                Some(deadline) => {
+                    match self
+                        .chunks
+                        .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                    {
+                        Ok(chunk) => chunk,
+                        Err(RecvTimeoutError::Timeout) => return Ok(None),
+                        Err(RecvTimeoutError::Disconnected) => {
+                            return Err(anyhow!("guest output pipe closed"))
+                        }
+                    }
+                }
+                None => self
+                    .chunks
+                    .recv()
+                    .map_err(|_| anyhow!("guest output pipe closed"))?,
+            };
+            self.pending.extend_from_slice(&chunk);
+        }
+    }
+
+    /// The start of the line being received.
+    fn partial_line(&self) -> String {
+        String::from_utf8_lossy(&self.pending).into_owned()
+    }
+
+    /// Reads the output of the shell command `command` until the magic line printed after it,
+    /// giving up at `deadline` if there is one.
+    fn read_command_output(&mut self, command: &str, deadline: Option<Instant>) -> Result<String> {
+        let timeout_error = |output: &str, partial_line: String| {
+            anyhow!(
+                "Timeout waiting for the output of {:?}, received so far: {:?}",
+                command,
+                output.to_string() + &partial_line
+            )
+        };
+
+        // We will receive an echo of what we have written on the pipe.
+        let echo = match self.read_line(deadline)? {
+            Some(echo) => echo,
+            None => return Err(timeout_error("", self.partial_line())),
+        };
+        assert_eq!(echo.trim(), command);
+
+        // Return all remaining lines until we receive the MAGIC_LINE
+        let mut output = String::new();
+        loop {
+            let line = match self.read_line(deadline)? {
+                Some(line) => line,
+                None => return Err(timeout_error(&output, self.partial_line())),
+            };
+            if line.trim() == TestVm::MAGIC_LINE {
+                break;
+            }
+            output.push_str(&line);
+        }
+        let trimmed = output.trim();
+        println!("<- {:?}", trimmed);
+
+        Ok(trimmed.to_string())
+    }
+}
+
+/// A shell command started in the guest by `TestVm::exec_in_guest_async`.
+#[allow(dead_code)]
+pub struct GuestCommand {
+    thread: JoinHandle<Result<String>>,
+}
+
+#[allow(dead_code)]
+impl GuestCommand {
+    /// Returns whether the command completed or timed out, in which case `join` doesn't block.
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+
+    /// Waits for the command and returns its stdout, like `TestVm::exec_in_guest_timeout`.
+    pub fn join(self) -> Result<String> {
+        self.thread
+            .join()
+            .unwrap_or_else(|e| std::panic::resume_unwind(e))
+    }
+}
+
+/// Compiles `regex` to match against the console history, with `^` and `$` matching at the start
+/// and end of lines.
+fn console_regex(regex: &str) -> Result<Regex> {
+    Ok(RegexBuilder::new(regex).multi_line(true).build()?)
 }
 
 /// Test fixture to spin up a VM running a guest that can be communicated with.
@@ -204,9 +422,11 @@ pub struct TestVm {
     /// Maintain ownership of test_dir until the vm is destroyed.
     #[allow(dead_code)]
     test_dir: TempDir,
-    from_guest_reader: BufReader<File>,
+    // Shared with the commands started by `exec_in_guest_async`.
+    from_guest: Arc<Mutex<GuestOutput>>,
     to_guest: File,
     control_socket_path: PathBuf,
+    console: ConsoleHistory,
     process: Option<Child>, // Use `Option` to allow taking the ownership in `Drop::drop()`.
 }
 
@@ -267,14 +487,16 @@ impl TestVm {
 
     // Adds 2 serial devices:
     // - ttyS0: Console device which prints kernel log / debug output of the
-    //          delegate binary.
+    //          delegate binary to `console_file`.
     // - ttyS1: Serial device attached to the named pipes.
     fn configure_serial_devices(
         command: &mut Command,
+        console_file: &Path,
         from_guest_pipe: &Path,
         to_guest_pipe: &Path,
     ) {
-        command.args(&["--serial", "type=syslog"]);
+        let console_params = format!("type=file,path={},num=1", console_file.display());
+        command.args(&["--serial", &console_params]);
 
         // Setup channel for communication with the delegate.
         let serial_params = format!(
@@ -311,12 +533,24 @@ impl TestVm {
         mkfifo(&to_guest_pipe)?;
 
         let control_socket_path = test_dir.path().join("control");
+        let console_file = test_dir.path().join("console");
 
         let mut command = Command::new(find_crosvm_binary());
+        if cfg.extended_status {
+            command.arg("--extended-status");
+        }
         command.args(&["run"]);
-        TestVm::configure_serial_devices(&mut command, &from_guest_pipe, &to_guest_pipe);
+        TestVm::configure_serial_devices(
+            &mut command,
+            &console_file,
+            &from_guest_pipe,
+            &to_guest_pipe,
+        );
         command.args(&["--socket", control_socket_path.to_str().unwrap()]);
         TestVm::configure_rootfs(&mut command, cfg.o_direct);
+        if cfg.start_paused {
+            command.arg("--start-paused");
+        }
         command.args(cfg.extra_args);
         // Set kernel as the last argument.
         command.arg(kernel_path());
@@ -350,49 +584,162 @@ impl TestVm {
             },
         );
 
-        // Wait for magic line to be received, indicating the delegate is ready.
-        let mut from_guest_reader = BufReader::new(from_guest?);
-        let mut magic_line = String::new();
-        from_guest_reader.read_line(&mut magic_line)?;
-        assert_eq!(magic_line.trim(), TestVm::MAGIC_LINE);
-
-        Ok(TestVm {
+        let mut vm = TestVm {
             test_dir,
-            from_guest_reader,
+            from_guest: Arc::new(Mutex::new(GuestOutput::new(from_guest?))),
             to_guest: to_guest?,
             control_socket_path,
+            console: ConsoleHistory::new(console_file),
             process,
-        })
+        };
+        if cfg.start_paused {
+            vm.wait_for_control_socket()?;
+        } else {
+            vm.wait_ready()?;
+        }
+        Ok(vm)
+    }
+
+    /// Waits for the magic line to be received, indicating the delegate is ready.
+    fn wait_ready(&mut self) -> Result<()> {
+        let magic_line = self.from_guest.lock().unwrap().read_line(None)?;
+        assert_eq!(magic_line.unwrap_or_default().trim(), TestVm::MAGIC_LINE);
+        Ok(())
+    }
+
+    /// Waits for crosvm to listen on the control socket, as nothing comes from the guest of a
+    /// paused VM.
+    fn wait_for_control_socket(&self) -> Result<()> {
+        let start = Instant::now();
+        while !self.control_socket_path.exists() {
+            if start.elapsed() > VM_COMMUNICATION_TIMEOUT {
+                return Err(anyhow!("Timeout waiting for the control socket"));
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        Ok(())
     }
 
     /// Executes the shell command `command` and returns the programs stdout.
     pub fn exec_in_guest(&mut self, command: &str) -> Result<String> {
         // Write command to serial port.
         writeln!(&mut self.to_guest, "{}", command)?;
+        self.from_guest
+            .lock()
+            .unwrap()
+            .read_command_output(command, None)
+    }
 
-        // We will receive an echo of what we have written on the pipe.
-        let mut echo = String::new();
-        self.from_guest_reader.read_line(&mut echo)?;
-        assert_eq!(echo.trim(), command);
+    /// Like `exec_in_guest`, but fails if the command doesn't complete within `timeout`, with an
+    /// error containing the output received until then. The remaining output of the command is
+    /// taken for the output of the next one, so the guest can't be used after a timeout.
+    #[allow(dead_code)]
+    pub fn exec_in_guest_timeout(&mut self, command: &str, timeout: Duration) -> Result<String> {
+        writeln!(&mut self.to_guest, "{}", command)?;
+        self.from_guest
+            .lock()
+            .unwrap()
+            .read_command_output(command, Some(Instant::now() + timeout))
+    }
 
-        // Return all remaining lines until we receive the MAGIC_LINE
-        let mut output = String::new();
-        loop {
-            let mut line = String::new();
-            self.from_guest_reader.read_line(&mut line)?;
-            if line.trim() == TestVm::MAGIC_LINE {
-                break;
+    /// Starts the shell command `command` in the guest and returns right away, so the VM can be
+    /// controlled while it runs. Its output is collected like by `exec_in_guest_timeout`, and other
+    /// commands wait for it to complete.
+    #[allow(dead_code)]
+    pub fn exec_in_guest_async(
+        &mut self,
+        command: &str,
+        timeout: Duration,
+    ) -> Result<GuestCommand> {
+        writeln!(&mut self.to_guest, "{}", command)?;
+        let from_guest = self.from_guest.clone();
+        let sent_command = command.to_string();
+        let deadline = Instant::now() + timeout;
+        let (locked_tx, locked_rx) = sync_channel(1);
+        let thread = thread::spawn(move || {
+            let mut from_guest = from_guest.lock().unwrap();
+            let _ = locked_tx.send(());
+            from_guest.read_command_output(&sent_command, Some(deadline))
+        });
+        // Don't let a command sent after this one read its output first.
+        locked_rx
+            .recv()
+            .map_err(|_| anyhow!("failed to wait for the output of {:?}", command))?;
+        Ok(GuestCommand { thread })
+    }
+
+    /// Returns whether a line of the guest console output, from boot onwards, matches `regex`.
+    /// Only the last `CONSOLE_HISTORY_BYTES` of the output are searched.
+    #[allow(dead_code)]
+    pub fn console_contains(&mut self, regex: &str) -> Result<bool> {
+        self.console.contains(&console_regex(regex)?)
+    }
+
+    /// Waits up to `timeout` for a line of the guest console output to match `regex`.
+    #[allow(dead_code)]
+    pub fn wait_for_console(&mut self, regex: &str, timeout: Duration) -> Result<()> {
+        let regex = console_regex(regex)?;
+        let start = Instant::now();
+        while !self.console.contains(&regex)? {
+            if start.elapsed() > timeout {
+                return Err(anyhow!(
+                    "Timeout waiting for {:?} on the console",
+                    regex.as_str()
+                ));
             }
-            output.push_str(&line);
+            thread::sleep(Duration::from_millis(10));
         }
-        let trimmed = output.trim();
-        println!("<- {:?}", trimmed);
+        Ok(())
+    }
 
-        Ok(trimmed.to_string())
+    /// Rotates the console output file like a log rotation tool would, by renaming it and having
+    /// crosvm reopen it. Returns the path the previous output was moved to.
+    #[allow(dead_code)]
+    pub fn rotate_console(&mut self) -> Result<PathBuf> {
+        let rotated = self.console.path.with_extension("1");
+        self.console.rotate(&rotated)?;
+        self.crosvm_command_output("serial_reopen_output", &["--num", "1"])?;
+        Ok(rotated)
+    }
+
+    /// Sends the shell command `command` to the guest without waiting for its output, for
+    /// commands that stop the VM.
+    #[allow(dead_code)]
+    pub fn exec_in_guest_no_wait(&mut self, command: &str) -> Result<()> {
+        writeln!(&mut self.to_guest, "{}", command)?;
+        Ok(())
+    }
+
+    /// Waits for crosvm to exit on its own and returns its exit status. The VM is then no longer
+    /// stopped when this instance is dropped.
+    #[allow(dead_code)]
+    pub fn wait_for_exit(&mut self) -> Result<ExitStatus> {
+        let start = Instant::now();
+        let process = self
+            .process
+            .as_mut()
+            .ok_or_else(|| anyhow!("crosvm already exited"))?;
+        while process.try_wait()?.is_none() {
+            if start.elapsed() > VM_COMMUNICATION_TIMEOUT {
+                return Err(anyhow!("Timeout waiting for crosvm to exit"));
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        let output = self.process.take().unwrap().wait_with_output()?;
+        print_output(&output);
+        Ok(output.status)
     }
 
-    fn crosvm_command(&self, command: &str) -> Result<()> {
-        let args = [self.control_socket_path.to_str().unwrap()];
+    /// Returns a client for the control socket of this VM that gives up on a VM that stopped
+    /// answering.
+    fn control_client(&self) -> VmClient {
+        VmClient::with_timeout(&self.control_socket_path, VM_COMMUNICATION_TIMEOUT)
+    }
+
+    /// Runs a crosvm control command with `args` against this VM and returns its stdout.
+    fn crosvm_command_output(&self, command: &str, args: &[&str]) -> Result<String> {
+        let mut args = args.to_vec();
+        args.push(self.control_socket_path.to_str().unwrap());
         println!("$ crosvm {} {:?}", command, &args.join(" "));
 
         let mut cmd = Command::new(find_crosvm_binary());
@@ -417,38 +764,134 @@ impl TestVm {
         if !output.status.success() {
             Err(anyhow!("Command failed with exit code {}", output.status))
         } else {
-            Ok(())
+            Ok(from_utf8(&output.stdout)?.to_string())
         }
     }
 
     pub fn stop(&self) -> Result<()> {
-        self.crosvm_command("stop")
+        Ok(self.control_client().stop()?)
     }
 
     pub fn suspend(&self) -> Result<()> {
-        self.crosvm_command("suspend")
+        self.control_client().suspend()?;
+        Ok(())
     }
 
     pub fn resume(&self) -> Result<()> {
-        self.crosvm_command("resume")
+        self.control_client().resume()?;
+        Ok(())
+    }
+
+    /// Suspends and resumes the VM `cycles` times back to back, then checks that the guest clock
+    /// still advances and that a workload started beforehand still makes progress.
+    #[allow(dead_code)]
+    pub fn suspend_resume_stress(&mut self, cycles: usize) -> Result<()> {
+        self.exec_in_guest(
+            "(while true; do cat /proc/uptime > /tmp/stress; sleep 0.1; done) & \
+             echo $! > /tmp/stress.pid",
+        )?;
+        let uptime_before = self.guest_uptime("cat /proc/uptime")?;
+        for cycle in 0..cycles {
+            self.suspend()
+                .map_err(|e| anyhow!("suspend {} failed: {}", cycle, e))?;
+            self.resume()
+                .map_err(|e| anyhow!("resume {} failed: {}", cycle, e))?;
+        }
+
+        // Give the workload some time to run again.
+        thread::sleep(Duration::from_millis(500));
+        let uptime_after = self.guest_uptime("cat /proc/uptime")?;
+        if uptime_after <= uptime_before {
+            return Err(anyhow!(
+                "guest clock stuck at {} after {} suspend/resume cycles",
+                uptime_before,
+                cycles
+            ));
+        }
+        let workload = self.guest_uptime("cat /tmp/stress")?;
+        if workload <= uptime_before {
+            return Err(anyhow!(
+                "guest workload stopped at {} after {} suspend/resume cycles",
+                workload,
+                cycles
+            ));
+        }
+        self.exec_in_guest("kill $(cat /tmp/stress.pid)")?;
+        Ok(())
+    }
+
+    /// Runs `command` in the guest and parses the first field of its output, in the format of
+    /// /proc/uptime.
+    fn guest_uptime(&mut self, command: &str) -> Result<f64> {
+        let output = self.exec_in_guest(command)?;
+        output
+            .split_whitespace()
+            .next()
+            .and_then(|uptime| uptime.parse().ok())
+            .ok_or_else(|| anyhow!("unexpected uptime {:?}", output))
+    }
+
+    /// Resumes a VM started with `Config::start_paused` and waits for the guest to be ready.
+    #[allow(dead_code)]
+    pub fn resume_and_wait_ready(&mut self) -> Result<()> {
+        self.resume()?;
+        self.wait_ready()
+    }
+
+    /// Replaces the kernel command line of a VM started with `Config::start_paused`.
+    #[allow(dead_code)]
+    pub fn set_kernel_cmdline(&self, cmdline: &str) -> Result<()> {
+        match self
+            .control_client()
+            .request(&VmRequest::SetKernelCmdline(cmdline.to_owned()))?
+        {
+            VmResponse::Ok => Ok(()),
+            r => Err(anyhow!("failed to set the kernel command line: {}", r)),
+        }
+    }
+
+    /// Returns the boot milestones reported by `crosvm boot_times` as JSON.
+    pub fn boot_times(&self) -> Result<String> {
+        self.crosvm_command_output("boot_times", &[])
+    }
+
+    /// Returns the identity of the VM reported by `crosvm vm_info` as JSON.
+    #[allow(dead_code)]
+    pub fn vm_info(&self) -> Result<String> {
+        self.crosvm_command_output("vm_info", &[])
     }
 }
 
+/// Prints both the crosvm's stdout/stderr to stdout so that they'll be shown when the test failed.
+fn print_output(output: &Output) {
+    println!(
+        "TestVm stdout:\n{}",
+        std::str::from_utf8(&output.stdout).unwrap()
+    );
+    println!(
+        "TestVm stderr:\n{}",
+        std::str::from_utf8(&output.stderr).unwrap()
+    );
+}
+
 impl Drop for TestVm {
     fn drop(&mut self) {
-        self.stop().unwrap();
-        let output = self.process.take().unwrap().wait_with_output().unwrap();
+        // The console output no longer goes to crosvm's stdout, so show it here for failed tests.
+        if self.console.update().is_ok() {
+            println!(
+                "TestVm console:\n{}",
+                String::from_utf8_lossy(&self.console.history)
+            );
+        }
 
-        // Print both the crosvm's stdout/stderr to stdout so that they'll be shown when the test
-        // failed.
-        println!(
-            "TestVm stdout:\n{}",
-            std::str::from_utf8(&output.stdout).unwrap()
-        );
-        println!(
-            "TestVm stderr:\n{}",
-            std::str::from_utf8(&output.stderr).unwrap()
-        );
+        // Already waited for by `wait_for_exit`.
+        let process = match self.process.take() {
+            Some(process) => process,
+            None => return,
+        };
+        self.stop().unwrap();
+        let output = process.wait_with_output().unwrap();
+        print_output(&output);
 
         if !output.status.success() {
             panic!("VM exited illegally: {}", output.status);
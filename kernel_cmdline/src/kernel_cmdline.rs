@@ -16,6 +16,9 @@ pub enum Error {
     /// Key/Value Operation would have had an equals sign in it.
     #[error("string contains an equals sign")]
     HasEquals,
+    /// Operation would have had a double quote in it.
+    #[error("string contains a double quote")]
+    HasQuote,
     /// Key/Value Operation would have had a space in it.
     #[error("string contains a space")]
     HasSpace,
@@ -54,6 +57,34 @@ fn valid_element(s: &str) -> Result<()> {
     }
 }
 
+/// Returns the key of a parameter, which is the whole parameter for a bare flag.
+fn param_key(param: &str) -> &str {
+    param.split_once('=').map_or(param, |(key, _)| key)
+}
+
+/// Splits a command line into parameters like the kernel does, on spaces outside double quotes.
+fn split_params(line: &str) -> Vec<&str> {
+    let mut params = Vec::new();
+    let mut in_quotes = false;
+    let mut start = 0;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            ' ' if !in_quotes => {
+                if i > start {
+                    params.push(&line[start..i]);
+                }
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if line.len() > start {
+        params.push(&line[start..]);
+    }
+    params
+}
+
 /// A builder for a kernel command line string that validates the string as its being built. A
 /// `CString` can be constructed from this directly using `CString::new`.
 pub struct Cmdline {
@@ -111,6 +142,37 @@ impl Cmdline {
         Ok(())
     }
 
+    /// Validates and inserts a key value pair into this command line, quoting the value if it
+    /// contains spaces. The value can't contain double quotes as the kernel has no way to escape
+    /// them.
+    pub fn insert_quoted<T: AsRef<str>>(&mut self, key: T, val: T) -> Result<()> {
+        let k = key.as_ref();
+        let v = val.as_ref();
+
+        valid_element(k)?;
+        valid_str(v)?;
+        if v.contains('"') {
+            return Err(Error::HasQuote);
+        }
+        let quote = v.contains(' ');
+        let quotes_len = if quote { 2 } else { 0 };
+        self.has_capacity(k.len() + v.len() + 1 + quotes_len)?;
+
+        self.start_push();
+        self.line.push_str(k);
+        self.line.push('=');
+        if quote {
+            self.line.push('"');
+            self.line.push_str(v);
+            self.line.push('"');
+        } else {
+            self.line.push_str(v);
+        }
+        self.end_push();
+
+        Ok(())
+    }
+
     /// Validates and inserts a string to the end of the current command line
     pub fn insert_str<T: AsRef<str>>(&mut self, slug: T) -> Result<()> {
         let s = slug.as_ref();
@@ -125,6 +187,53 @@ impl Cmdline {
         Ok(())
     }
 
+    /// Removes every parameter named `key`, both bare flags and `key=value` pairs, keeping the
+    /// order of the other parameters. Returns whether any parameter was removed.
+    pub fn remove(&mut self, key: &str) -> bool {
+        let (removed, kept): (Vec<&str>, Vec<&str>) = split_params(&self.line)
+            .into_iter()
+            .partition(|param| param_key(param) == key);
+        if removed.is_empty() {
+            return false;
+        }
+        self.line = kept.join(" ");
+        true
+    }
+
+    /// Validates and inserts a key value pair in place of the first parameter named `key`, be it a
+    /// bare flag or a `key=value` pair, removing any other. The pair is inserted at the end of the
+    /// command line if there is no such parameter.
+    pub fn insert_or_replace<T: AsRef<str>>(&mut self, key: T, val: T) -> Result<()> {
+        let k = key.as_ref();
+        let v = val.as_ref();
+
+        valid_element(k)?;
+        valid_element(v)?;
+
+        let new_param = format!("{}={}", k, v);
+        let mut replaced = false;
+        let mut params = Vec::new();
+        for param in split_params(&self.line) {
+            if param_key(param) != k {
+                params.push(param);
+            } else if !replaced {
+                params.push(&new_param);
+                replaced = true;
+            }
+        }
+        if !replaced {
+            params.push(&new_param);
+        }
+
+        let line = params.join(" ");
+        if line.len() >= self.capacity {
+            return Err(Error::TooLarge);
+        }
+        self.line = line;
+
+        Ok(())
+    }
+
     /// Returns the cmdline in progress without nul termination
     pub fn as_str(&self) -> &str {
         self.line.as_str()
@@ -217,4 +326,121 @@ mod tests {
         assert_eq!(cl.insert("c", "da"), Err(Error::TooLarge)); // adds 5 (including space) length
         assert!(cl.insert("c", "d").is_ok()); // adds 4 (including space) length
     }
+
+    #[test]
+    fn remove() {
+        let mut cl = Cmdline::new(100);
+        cl.insert_str("quiet panic=-1 console=ttyS0 quiet").unwrap();
+        assert!(cl.remove("quiet"));
+        assert_eq!(cl.as_str(), "panic=-1 console=ttyS0");
+        assert!(cl.remove("panic"));
+        assert_eq!(cl.as_str(), "console=ttyS0");
+        assert!(!cl.remove("console=ttyS0"));
+        assert!(!cl.remove("cons"));
+        assert!(cl.remove("console"));
+        assert_eq!(cl.as_str(), "");
+        assert!(!cl.remove("console"));
+    }
+
+    #[test]
+    fn replace_first() {
+        let mut cl = Cmdline::new(100);
+        cl.insert_str("panic=-1 console=ttyS0 quiet").unwrap();
+        assert!(cl.insert_or_replace("panic", "0").is_ok());
+        assert_eq!(cl.as_str(), "panic=0 console=ttyS0 quiet");
+    }
+
+    #[test]
+    fn replace_middle() {
+        let mut cl = Cmdline::new(100);
+        cl.insert_str("panic=-1 console=ttyS0 quiet console=hvc0")
+            .unwrap();
+        assert!(cl.insert_or_replace("console", "ttyAMA0").is_ok());
+        assert_eq!(cl.as_str(), "panic=-1 console=ttyAMA0 quiet");
+    }
+
+    #[test]
+    fn replace_last_flag() {
+        let mut cl = Cmdline::new(100);
+        cl.insert_str("panic=-1 console=ttyS0 quiet").unwrap();
+        assert!(cl.insert_or_replace("quiet", "1").is_ok());
+        assert_eq!(cl.as_str(), "panic=-1 console=ttyS0 quiet=1");
+    }
+
+    #[test]
+    fn replace_missing() {
+        let mut cl = Cmdline::new(100);
+        assert!(cl.insert_or_replace("panic", "-1").is_ok());
+        assert_eq!(cl.as_str(), "panic=-1");
+        assert!(cl.insert_or_replace("quiet", "1").is_ok());
+        assert_eq!(cl.as_str(), "panic=-1 quiet=1");
+    }
+
+    #[test]
+    fn replace_invalid() {
+        let mut cl = Cmdline::new(100);
+        cl.insert_str("panic=-1").unwrap();
+        assert_eq!(cl.insert_or_replace("panic", "a b"), Err(Error::HasSpace));
+        assert_eq!(cl.insert_or_replace("pan=ic", "0"), Err(Error::HasEquals));
+        assert_eq!(cl.as_str(), "panic=-1");
+    }
+
+    #[test]
+    fn replace_too_large() {
+        let mut cl = Cmdline::new(12);
+        cl.insert_str("a=b panic=1").unwrap();
+        assert_eq!(cl.insert_or_replace("panic", "-1"), Err(Error::TooLarge));
+        assert_eq!(cl.as_str(), "a=b panic=1");
+        assert!(cl.insert_or_replace("panic", "0").is_ok());
+        assert_eq!(cl.as_str(), "a=b panic=0");
+    }
+
+    #[test]
+    fn insert_quoted() {
+        let mut cl = Cmdline::new(100);
+        assert!(cl.insert_quoted("dyndbg", "file drivers/* +p").is_ok());
+        assert!(cl.insert_quoted("init", "/bin/sh").is_ok());
+        assert!(cl.insert_quoted("opts", "a=b c").is_ok());
+        assert_eq!(
+            cl.as_str(),
+            "dyndbg=\"file drivers/* +p\" init=/bin/sh opts=\"a=b c\""
+        );
+
+        let s = CString::new(cl).unwrap();
+        assert_eq!(
+            s,
+            CString::new("dyndbg=\"file drivers/* +p\" init=/bin/sh opts=\"a=b c\"").unwrap()
+        );
+    }
+
+    #[test]
+    fn insert_quoted_invalid() {
+        let mut cl = Cmdline::new(100);
+        assert_eq!(cl.insert_quoted("a", "say \"hi\""), Err(Error::HasQuote));
+        assert_eq!(cl.insert_quoted("a", "\""), Err(Error::HasQuote));
+        assert_eq!(cl.insert_quoted("a b", "c"), Err(Error::HasSpace));
+        assert_eq!(cl.insert_quoted("a", "💖 x"), Err(Error::InvalidAscii));
+        assert_eq!(cl.as_str(), "");
+    }
+
+    #[test]
+    fn insert_quoted_too_large() {
+        // "a=\"b c\"" is 7 characters, plus the nul terminator.
+        let mut cl = Cmdline::new(7);
+        assert_eq!(cl.insert_quoted("a", "b c"), Err(Error::TooLarge));
+        let mut cl = Cmdline::new(8);
+        assert!(cl.insert_quoted("a", "b c").is_ok());
+        assert_eq!(cl.as_str(), "a=\"b c\"");
+    }
+
+    #[test]
+    fn replace_quoted() {
+        let mut cl = Cmdline::new(100);
+        cl.insert_quoted("dyndbg", "file drivers/* +p").unwrap();
+        cl.insert_str("quiet").unwrap();
+        assert!(cl.insert_or_replace("quiet", "1").is_ok());
+        assert_eq!(cl.as_str(), "dyndbg=\"file drivers/* +p\" quiet=1");
+        assert!(cl.remove("dyndbg"));
+        assert_eq!(cl.as_str(), "quiet=1");
+    }
 }
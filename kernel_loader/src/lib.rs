@@ -200,7 +200,7 @@ where
             .map_err(|_| Error::SeekKernelStart)?;
 
         guest_mem
-            .read_to_memory(
+            .read_to_memory_exact(
                 GuestAddress(phdr.p_paddr),
                 kernel_image,
                 phdr.p_filesz as usize,
@@ -386,6 +386,8 @@ mod test {
     use std::fs::File;
     use std::io::Write;
 
+    use base::SafeDescriptor;
+    use base::SharedMemory;
     use tempfile::tempfile;
     use vm_memory::GuestAddress;
     use vm_memory::GuestMemory;
@@ -460,6 +462,15 @@ mod test {
         file
     }
 
+    // The same image held in a memfd, as passed by launchers that don't keep kernels in files.
+    fn make_elf64_memfd() -> File {
+        let shm = SharedMemory::new("test_elf64", 0).expect("failed to create shared memory");
+        let mut file = File::from(SafeDescriptor::from(shm));
+        file.write_all(include_bytes!("test_elf64.bin"))
+            .expect("failed to write elf to shared memory");
+        file
+    }
+
     fn mutate_elf_bin(mut f: &File, offset: u64, val: u8) {
         f.seek(SeekFrom::Start(offset))
             .expect("failed to seek file");
@@ -491,6 +502,18 @@ mod test {
         assert_eq!(kernel.entry, GuestAddress(0x20_000e));
     }
 
+    #[test]
+    fn load_elf64_from_memfd() {
+        let gm = create_guest_mem();
+        let kernel_addr = GuestAddress(0x0);
+        let mut image = make_elf64_memfd();
+        let kernel = load_elf64(&gm, kernel_addr, &mut image).expect("failed to load ELF");
+        assert_eq!(kernel.address_range.start, 0x20_0000);
+        assert_eq!(kernel.address_range.end, 0x20_0035);
+        assert_eq!(kernel.size, 0x35);
+        assert_eq!(kernel.entry, GuestAddress(0x20_000e));
+    }
+
     #[test]
     fn bad_magic() {
         let gm = create_guest_mem();
@@ -7,11 +7,46 @@ use std::collections::BTreeSet;
 use std::collections::HashMap;
 use std::ops::Bound;
 
+use serde::Deserialize;
+use serde::Serialize;
+
 use crate::AddressRange;
 use crate::Alloc;
 use crate::Error;
 use crate::Result;
 
+/// Policy used to pick the free region an allocation is carved out of.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum AllocStrategy {
+    /// Lowest addressed free region that fits.
+    FirstFit,
+    /// Highest addressed free region that fits, allocating from its top.
+    LastFit,
+    /// Smallest free region that fits.
+    BestFit,
+}
+
+/// An allocation made from an `AddressAllocator`.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct AllocationInfo {
+    pub alloc: Alloc,
+    pub range: AddressRange,
+    pub tag: String,
+}
+
+/// Utilization of the pools of an `AddressAllocator`.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct AllocatorStats {
+    /// Total size of the pools, in bytes.
+    pub size: u64,
+    /// Bytes allocated from the pools.
+    pub allocated: u64,
+    /// Size of the largest free range, i.e. of the largest allocation that can still succeed.
+    pub largest_free: u64,
+    /// Allocations sorted by address.
+    pub allocations: Vec<AllocationInfo>,
+}
+
 /// Manages allocating address ranges.
 /// Use `AddressAllocator` whenever an address range needs to be allocated to different users.
 /// Allocations must be uniquely tagged with an Alloc enum, which can be used for lookup.
@@ -96,6 +131,20 @@ impl AddressAllocator {
         &self.pools
     }
 
+    /// Adds `pool` to the ranges managed by the allocator. `pool` must not overlap any range that
+    /// is already managed; it is coalesced with adjacent free ranges.
+    pub fn add_pool(&mut self, pool: AddressRange) -> Result<()> {
+        if pool.is_empty() {
+            return Err(Error::AllocSizeZero);
+        }
+        if self.pools.iter().any(|p| p.overlaps(pool)) {
+            return Err(Error::RegionOverlap(pool));
+        }
+        self.insert_at(pool)?;
+        self.pools.push(pool);
+        Ok(())
+    }
+
     fn internal_allocate_from_slot(
         &mut self,
         slot: AddressRange,
@@ -119,13 +168,56 @@ impl AddressAllocator {
         Ok(range.start)
     }
 
+    // Finds the free region that an allocation of `size` bytes at `alignment` would be carved out
+    // of under `strategy`. `alignment` must be a power of two and `size` must be nonzero.
+    fn find_region(
+        &self,
+        size: u64,
+        alignment: u64,
+        strategy: AllocStrategy,
+    ) -> Option<AddressRange> {
+        // Returns true if an aligned allocation of `size` fits at the start of `range`.
+        let fits_from_start = |range: &&AddressRange| {
+            match range.start % alignment {
+                0 => range.start.checked_add(size - 1),
+                r => range.start.checked_add(size - 1 + alignment - r),
+            }
+            .map_or(false, |end| end <= range.end)
+        };
+
+        match strategy {
+            // finds first region matching alignment and size.
+            AllocStrategy::FirstFit => self.regions.iter().find(fits_from_start).cloned(),
+            // finds last region matching alignment and size.
+            AllocStrategy::LastFit => self
+                .regions
+                .iter()
+                .rev()
+                .find(|range| {
+                    range
+                        .end
+                        .checked_sub(size - 1)
+                        .map_or(false, |start| start & !(alignment - 1) >= range.start)
+                })
+                .cloned(),
+            // finds the smallest region matching alignment and size, preferring lower addresses
+            // when several regions have the same size.
+            AllocStrategy::BestFit => self
+                .regions
+                .iter()
+                .filter(fits_from_start)
+                .min_by_key(|range| range.end - range.start)
+                .cloned(),
+        }
+    }
+
     fn internal_allocate_with_align(
         &mut self,
         size: u64,
         alloc: Alloc,
         tag: String,
         alignment: u64,
-        reverse: bool,
+        strategy: AllocStrategy,
     ) -> Result<u64> {
         let alignment = cmp::max(self.min_align, alignment);
 
@@ -139,41 +231,16 @@ impl AddressAllocator {
             return Err(Error::BadAlignment);
         }
 
-        let region = if !reverse {
-            // finds first region matching alignment and size.
-            self.regions
-                .iter()
-                .find(|range| {
-                    match range.start % alignment {
-                        0 => range.start.checked_add(size - 1),
-                        r => range.start.checked_add(size - 1 + alignment - r),
-                    }
-                    .map_or(false, |end| end <= range.end)
-                })
-                .cloned()
-        } else {
-            // finds last region matching alignment and size.
-            self.regions
-                .iter()
-                .rev()
-                .find(|range| {
-                    range
-                        .end
-                        .checked_sub(size - 1)
-                        .map_or(false, |start| start & !(alignment - 1) >= range.start)
-                })
-                .cloned()
-        };
-
-        match region {
+        match self.find_region(size, alignment, strategy) {
             Some(slot) => {
-                let start = if !reverse {
-                    match slot.start % alignment {
-                        0 => slot.start,
-                        r => slot.start + alignment - r,
+                let start = match strategy {
+                    AllocStrategy::FirstFit | AllocStrategy::BestFit => {
+                        match slot.start % alignment {
+                            0 => slot.start,
+                            r => slot.start + alignment - r,
+                        }
                     }
-                } else {
-                    (slot.end - (size - 1)) & !(alignment - 1)
+                    AllocStrategy::LastFit => (slot.end - (size - 1)) & !(alignment - 1),
                 };
                 let end = start + size - 1;
                 let range = AddressRange { start, end };
@@ -194,7 +261,7 @@ impl AddressAllocator {
         tag: String,
         alignment: u64,
     ) -> Result<u64> {
-        self.internal_allocate_with_align(size, alloc, tag, alignment, true)
+        self.internal_allocate_with_align(size, alloc, tag, alignment, AllocStrategy::LastFit)
     }
 
     /// Allocates a range of addresses, preferring to allocate from high rather than low addresses.
@@ -217,7 +284,7 @@ impl AddressAllocator {
         tag: String,
         alignment: u64,
     ) -> Result<u64> {
-        self.internal_allocate_with_align(size, alloc, tag, alignment, false)
+        self.internal_allocate_with_align(size, alloc, tag, alignment, AllocStrategy::FirstFit)
     }
 
     pub fn allocate(&mut self, size: u64, alloc: Alloc, tag: String) -> Result<u64> {
@@ -229,6 +296,20 @@ impl AddressAllocator {
         self.allocate_with_align(size, alloc, tag, self.min_align)
     }
 
+    /// Allocates a range of addresses from the smallest free region that can hold `size` bytes at
+    /// the requested alignment. Compared to `allocate_with_align`, this keeps large free regions
+    /// intact for later large allocations (such as naturally aligned PCI BARs) at the cost of a
+    /// scan over all free regions.
+    pub fn allocate_best_fit_with_align(
+        &mut self,
+        size: u64,
+        alloc: Alloc,
+        tag: String,
+        alignment: u64,
+    ) -> Result<u64> {
+        self.internal_allocate_with_align(size, alloc, tag, alignment, AllocStrategy::BestFit)
+    }
+
     /// Allocates a range of addresses from the managed region with an optional tag
     /// and required location. Allocation alignment is not enforced.
     /// Returns OutOfSpace if requested range is not available or ExistingAlloc if the requested
@@ -307,6 +388,35 @@ impl AddressAllocator {
         self.allocs.get(alloc)
     }
 
+    /// Returns how much of the pools is allocated, and to what.
+    pub fn stats(&self) -> AllocatorStats {
+        // A range covering all of u64 has no representable length.
+        let len = |range: &AddressRange| range.len().unwrap_or(u64::MAX);
+        let mut allocations: Vec<AllocationInfo> = self
+            .allocs
+            .iter()
+            .map(|(alloc, (range, tag))| AllocationInfo {
+                alloc: *alloc,
+                range: *range,
+                tag: tag.clone(),
+            })
+            .collect();
+        allocations.sort_by_key(|a| a.range);
+        AllocatorStats {
+            size: self
+                .pools
+                .iter()
+                .map(len)
+                .fold(0, |sum, len| sum.saturating_add(len)),
+            allocated: allocations
+                .iter()
+                .map(|a| len(&a.range))
+                .fold(0, |sum, len| sum.saturating_add(len)),
+            largest_free: self.regions.iter().map(len).max().unwrap_or(0),
+            allocations,
+        }
+    }
+
     /// Insert range of addresses into the pool, coalescing neighboring regions.
     fn insert_at(&mut self, mut slot: AddressRange) -> Result<()> {
         if slot.is_empty() {
@@ -424,6 +534,42 @@ impl<'a> AddressAllocatorSet<'a> {
         last_res
     }
 
+    pub fn allocate_best_fit_with_align(
+        &mut self,
+        size: u64,
+        alloc: Alloc,
+        tag: String,
+        alignment: u64,
+    ) -> Result<u64> {
+        // Pick the allocator whose best fitting free region is the smallest overall.
+        let best = self
+            .allocators
+            .iter()
+            .enumerate()
+            .filter_map(|(i, allocator)| {
+                let alignment = cmp::max(allocator.min_align, alignment);
+                if size == 0 || !alignment.is_power_of_two() {
+                    return None;
+                }
+                allocator
+                    .find_region(size, alignment, AllocStrategy::BestFit)
+                    .map(|region| (i, region.end - region.start))
+            })
+            .min_by_key(|&(_, region_size)| region_size)
+            .map(|(i, _)| i);
+
+        match best {
+            Some(i) => self.allocators[i].allocate_best_fit_with_align(size, alloc, tag, alignment),
+            // Let the first allocator report the appropriate error.
+            None => match self.allocators.first_mut() {
+                Some(allocator) => {
+                    allocator.allocate_best_fit_with_align(size, alloc, tag, alignment)
+                }
+                None => Err(Error::OutOfSpace),
+            },
+        }
+    }
+
     pub fn allocate(&mut self, size: u64, alloc: Alloc, tag: String) -> Result<u64> {
         let mut last_res = Err(Error::OutOfSpace);
         for allocator in self.allocators.iter_mut() {
@@ -822,6 +968,143 @@ mod tests {
         );
     }
 
+    #[test]
+    fn allocate_best_fit_picks_smallest_region() {
+        let mut pool = AddressAllocator::new_from_list(
+            vec![
+                AddressRange {
+                    start: 0x1000_0000,
+                    end: 0x1fff_ffff,
+                },
+                AddressRange {
+                    start: 0x3000_0000,
+                    end: 0x3000_ffff,
+                },
+            ],
+            Some(0x1000),
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            pool.allocate_best_fit_with_align(
+                0x1000,
+                Alloc::Anon(0),
+                String::from("small"),
+                0x1000
+            ),
+            Ok(0x3000_0000)
+        );
+        // The naturally aligned 256MB region is still intact.
+        assert_eq!(
+            pool.allocate_best_fit_with_align(
+                0x1000_0000,
+                Alloc::Anon(1),
+                String::from("large"),
+                0x1000_0000
+            ),
+            Ok(0x1000_0000)
+        );
+    }
+
+    #[test]
+    fn allocate_first_fit_fragments_large_region() {
+        // Same layout as `allocate_best_fit_picks_smallest_region`, but first-fit places the small
+        // allocation in the large region, so the naturally aligned allocation no longer fits.
+        let mut pool = AddressAllocator::new_from_list(
+            vec![
+                AddressRange {
+                    start: 0x1000_0000,
+                    end: 0x1fff_ffff,
+                },
+                AddressRange {
+                    start: 0x3000_0000,
+                    end: 0x3000_ffff,
+                },
+            ],
+            Some(0x1000),
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            pool.allocate_with_align(0x1000, Alloc::Anon(0), String::from("small"), 0x1000),
+            Ok(0x1000_0000)
+        );
+        assert_eq!(
+            pool.allocate_with_align(
+                0x1000_0000,
+                Alloc::Anon(1),
+                String::from("large"),
+                0x1000_0000
+            ),
+            Err(Error::OutOfSpace)
+        );
+    }
+
+    #[test]
+    fn allocate_best_fit_respects_alignment() {
+        let mut pool = AddressAllocator::new_from_list(
+            vec![
+                AddressRange {
+                    start: 0x1000,
+                    end: 0x17ff,
+                },
+                AddressRange {
+                    start: 0x10800,
+                    end: 0x11fff,
+                },
+                AddressRange {
+                    start: 0x20000,
+                    end: 0x2ffff,
+                },
+            ],
+            Some(0x100),
+            None,
+        )
+        .unwrap();
+        // The smallest region cannot hold 0x1000 bytes; the next one can once its start is aligned
+        // up. What remains of it afterwards is too small for the second allocation.
+        assert_eq!(
+            pool.allocate_best_fit_with_align(0x1000, Alloc::Anon(0), String::from("bar"), 0x1000),
+            Ok(0x11000)
+        );
+        assert_eq!(
+            pool.allocate_best_fit_with_align(0x1000, Alloc::Anon(1), String::from("bar"), 0x2000),
+            Ok(0x20000)
+        );
+    }
+
+    #[test]
+    fn add_pool_coalesces() {
+        let mut pool = AddressAllocator::new(
+            AddressRange {
+                start: 0x1000,
+                end: 0x1fff,
+            },
+            Some(0x1000),
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            pool.add_pool(AddressRange {
+                start: 0x1800,
+                end: 0x2fff,
+            }),
+            Err(Error::RegionOverlap(AddressRange {
+                start: 0x1800,
+                end: 0x2fff,
+            }))
+        );
+        pool.add_pool(AddressRange {
+            start: 0x2000,
+            end: 0x2fff,
+        })
+        .unwrap();
+        assert_eq!(
+            pool.allocate(0x2000, Alloc::Anon(0), String::from("spans")),
+            Ok(0x1000)
+        );
+    }
+
     #[test]
     fn allocate_with_alignment_no_allocator_alignment() {
         let mut pool = AddressAllocator::new(
@@ -1103,4 +1386,58 @@ mod tests {
 
         assert_eq!(pool.get_max_addr(), 0xFFFFF);
     }
+
+    #[test]
+    fn stats() {
+        let mut pool = AddressAllocator::new_from_list(
+            vec![
+                AddressRange::from_start_and_end(0x1000, 0x1fff),
+                AddressRange::from_start_and_end(0x4000, 0x7fff),
+            ],
+            Some(0x100),
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            pool.stats(),
+            AllocatorStats {
+                size: 0x5000,
+                allocated: 0,
+                largest_free: 0x4000,
+                allocations: Vec::new(),
+            }
+        );
+
+        pool.allocate_at(
+            AddressRange::from_start_and_end(0x5000, 0x5fff),
+            Alloc::Anon(0),
+            "middle".to_string(),
+        )
+        .unwrap();
+        pool.allocate(0x200, Alloc::Anon(1), "first".to_string())
+            .unwrap();
+        assert_eq!(
+            pool.stats(),
+            AllocatorStats {
+                size: 0x5000,
+                allocated: 0x1200,
+                largest_free: 0x2000,
+                allocations: vec![
+                    AllocationInfo {
+                        alloc: Alloc::Anon(1),
+                        range: AddressRange::from_start_and_end(0x1000, 0x11ff),
+                        tag: "first".to_string(),
+                    },
+                    AllocationInfo {
+                        alloc: Alloc::Anon(0),
+                        range: AddressRange::from_start_and_end(0x5000, 0x5fff),
+                        tag: "middle".to_string(),
+                    },
+                ],
+            }
+        );
+
+        pool.release(Alloc::Anon(0)).unwrap();
+        assert_eq!(pool.stats().largest_free, 0x4000);
+    }
 }
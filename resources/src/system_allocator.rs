@@ -8,6 +8,7 @@ use base::pagesize;
 
 use crate::address_allocator::AddressAllocator;
 use crate::address_allocator::AddressAllocatorSet;
+use crate::address_allocator::AllocatorStats;
 use crate::AddressRange;
 use crate::Alloc;
 use crate::Error;
@@ -31,6 +32,7 @@ pub struct AllocOptions {
     max_address: u64,
     alignment: Option<u64>,
     top_down: bool,
+    best_fit: bool,
 }
 
 impl Default for AllocOptions {
@@ -46,6 +48,7 @@ impl AllocOptions {
             max_address: u64::MAX,
             alignment: None,
             top_down: false,
+            best_fit: false,
         }
     }
 
@@ -80,6 +83,15 @@ impl AllocOptions {
         self.top_down = top_down;
         self
     }
+
+    /// If `true`, allocate from the smallest free range that fits instead of the first one. This
+    /// reduces fragmentation when many small allocations are mixed with a few large, naturally
+    /// aligned ones. Takes precedence over `top_down`.
+    /// Default: `false`
+    pub fn best_fit(&mut self, best_fit: bool) -> &mut Self {
+        self.best_fit = best_fit;
+        self
+    }
 }
 
 pub struct SystemAllocatorConfig {
@@ -370,6 +382,10 @@ impl SystemAllocator {
         mmio_type: MmioType,
     ) -> Result<u64> {
         let allocator = &mut self.mmio_address_spaces[mmio_type as usize];
+        if opts.best_fit {
+            let alignment = opts.alignment.unwrap_or(1);
+            return allocator.allocate_best_fit_with_align(size, alloc, tag, alignment);
+        }
         match (opts.alignment, opts.top_down) {
             (Some(align), true) => allocator.reverse_allocate_with_align(size, alloc, tag, align),
             (Some(align), false) => allocator.allocate_with_align(size, alloc, tag, align),
@@ -378,6 +394,25 @@ impl SystemAllocator {
         }
     }
 
+    /// Allocate `size` bytes of MMIO space aligned to `align`, which must be a power of two.
+    ///
+    /// The allocation is placed in the smallest free range of the low or high MMIO pools that can
+    /// hold it, so that large naturally aligned regions (such as big prefetchable PCI BARs) are
+    /// not fragmented by earlier small allocations.
+    pub fn allocate_with_align(
+        &mut self,
+        size: u64,
+        align: u64,
+        alloc: Alloc,
+        tag: String,
+    ) -> Result<u64> {
+        if !align.is_power_of_two() {
+            return Err(Error::BadAlignment);
+        }
+        self.mmio_allocator_any()
+            .allocate_best_fit_with_align(size, alloc, tag, align)
+    }
+
     /// Reserve specified range from pci mmio, get the overlap of specified
     /// range with mmio pools, exclude the overlap from mmio allocator.
     ///
@@ -407,6 +442,62 @@ impl SystemAllocator {
         Ok(())
     }
 
+    /// Grows the platform MMIO space to `window`, which must start at the same address as the
+    /// current platform MMIO space and cover all of it.
+    ///
+    /// Any part of the added range that falls within the low or high MMIO pools is reserved there
+    /// so it will not be handed out twice. Fails if the added range overlaps the reserved region
+    /// or has already been allocated from another pool.
+    pub fn expand_platform_mmio(&mut self, window: AddressRange) -> Result<()> {
+        let platform = self
+            .mmio_platform_address_spaces
+            .as_ref()
+            .ok_or(Error::MissingPlatformMMIOAddresses)?;
+        let start = platform.pools().iter().map(|p| p.start).min();
+        let end = platform.pools().iter().map(|p| p.end).max();
+        let (start, end) = match (start, end) {
+            (Some(start), Some(end)) => (start, end),
+            _ => return Err(Error::MissingPlatformMMIOAddresses),
+        };
+        if window.start != start || window.end < end {
+            return Err(Error::OutOfBounds);
+        }
+        if window.end == end {
+            return Ok(());
+        }
+
+        let added = AddressRange {
+            start: end + 1,
+            end: window.end,
+        };
+        if let Some(reserved) = self.reserved_region {
+            if reserved.overlaps(added) {
+                return Err(Error::RegionOverlap(reserved));
+            }
+        }
+        for mmio_type in [MmioType::Low, MmioType::High] {
+            let overlaps: Vec<AddressRange> = self.mmio_address_spaces[mmio_type as usize]
+                .pools()
+                .iter()
+                .map(|pool| pool.intersect(added))
+                .filter(|overlap| !overlap.is_empty())
+                .collect();
+            for overlap in overlaps {
+                let id = self.get_anon_alloc();
+                self.mmio_address_spaces[mmio_type as usize].allocate_at(
+                    overlap,
+                    id,
+                    "platform mmio expansion".to_string(),
+                )?;
+            }
+        }
+
+        self.mmio_platform_address_spaces
+            .as_mut()
+            .ok_or(Error::MissingPlatformMMIOAddresses)?
+            .add_pool(added)
+    }
+
     /// Gets an allocator to be used for platform device MMIO allocation.
     pub fn mmio_platform_allocator(&mut self) -> Option<&mut AddressAllocator> {
         self.mmio_platform_address_spaces.as_mut()
@@ -438,6 +529,27 @@ impl SystemAllocator {
             .collect()
     }
 
+    /// Gets the utilization of each address space, keyed by the name of the
+    /// `SystemAllocatorConfig` field it was created from. Absent address spaces are left out.
+    pub fn pool_stats(&self) -> BTreeMap<String, AllocatorStats> {
+        let mut stats = BTreeMap::new();
+        if let Some(io) = &self.io_address_space {
+            stats.insert("io".to_string(), io.stats());
+        }
+        stats.insert(
+            "low_mmio".to_string(),
+            self.mmio_address_spaces[MmioType::Low as usize].stats(),
+        );
+        stats.insert(
+            "high_mmio".to_string(),
+            self.mmio_address_spaces[MmioType::High as usize].stats(),
+        );
+        if let Some(platform) = &self.mmio_platform_address_spaces {
+            stats.insert("platform_mmio".to_string(), platform.stats());
+        }
+        stats
+    }
+
     /// Gets the reserved address space region.
     pub fn reserved_region(&self) -> Option<AddressRange> {
         self.reserved_region
@@ -549,4 +661,241 @@ mod tests {
             true
         );
     }
+
+    #[test]
+    fn allocate_with_align_avoids_fragmentation() {
+        let mut a = SystemAllocator::new(
+            SystemAllocatorConfig {
+                io: None,
+                low_mmio: AddressRange {
+                    start: 0x2000_0000,
+                    end: 0x2fff_ffff,
+                },
+                high_mmio: AddressRange {
+                    start: 0x1_0000_0000,
+                    end: 0x1_0000_ffff,
+                },
+                platform_mmio: None,
+                first_irq: 5,
+            },
+            None,
+            &[],
+        )
+        .unwrap();
+
+        // Many small BARs land in the smaller high pool instead of splitting the low pool.
+        for i in 0..8 {
+            let alloc = a.get_anon_alloc();
+            let addr = a
+                .allocate_with_align(0x1000, 0x1000, alloc, format!("small{}", i))
+                .unwrap();
+            assert!(addr >= 0x1_0000_0000);
+        }
+
+        // A 256MB BAR still fits at its natural alignment.
+        let alloc = a.get_anon_alloc();
+        assert_eq!(
+            a.allocate_with_align(0x1000_0000, 0x1000_0000, alloc, "large".to_string()),
+            Ok(0x2000_0000)
+        );
+
+        let alloc = a.get_anon_alloc();
+        assert_eq!(
+            a.allocate_with_align(0x1000, 0x3000, alloc, "bad".to_string()),
+            Err(Error::BadAlignment)
+        );
+    }
+
+    #[test]
+    fn allocate_mmio_best_fit() {
+        let mut a = SystemAllocator::new(
+            SystemAllocatorConfig {
+                io: None,
+                low_mmio: AddressRange {
+                    start: 0x2000_0000,
+                    end: 0x2fff_ffff,
+                },
+                high_mmio: AddressRange {
+                    start: 0x1_0000_0000,
+                    end: 0x1_ffff_ffff,
+                },
+                platform_mmio: None,
+                first_irq: 5,
+            },
+            None,
+            &[],
+        )
+        .unwrap();
+
+        // Leave a 128MB free range at the bottom and a 4KB one at the top of the low pool.
+        let id = a.get_anon_alloc();
+        a.mmio_allocator(MmioType::Low)
+            .allocate_at(
+                AddressRange {
+                    start: 0x2800_0000,
+                    end: 0x2fff_efff,
+                },
+                id,
+                "reserved".to_string(),
+            )
+            .unwrap();
+
+        let id = a.get_anon_alloc();
+        assert_eq!(
+            a.allocate_mmio(
+                0x1000,
+                id,
+                "small".to_string(),
+                AllocOptions::new().align(0x1000).best_fit(true),
+            ),
+            Ok(0x2fff_f000)
+        );
+        let id = a.get_anon_alloc();
+        assert_eq!(
+            a.allocate_mmio(
+                0x1000,
+                id,
+                "small".to_string(),
+                AllocOptions::new().align(0x1000),
+            ),
+            Ok(0x2000_0000)
+        );
+    }
+
+    #[test]
+    fn expand_platform_mmio() {
+        let mut a = SystemAllocator::new(
+            SystemAllocatorConfig {
+                io: None,
+                low_mmio: AddressRange {
+                    start: 0x2000_0000,
+                    end: 0x2fff_ffff,
+                },
+                high_mmio: AddressRange {
+                    start: 0x1_0010_0000,
+                    end: 0x1_ffff_ffff,
+                },
+                platform_mmio: Some(AddressRange {
+                    start: 0x1_0000_0000,
+                    end: 0x1_000f_ffff,
+                }),
+                first_irq: 5,
+            },
+            None,
+            &[],
+        )
+        .unwrap();
+
+        let id = a.get_anon_alloc();
+        assert_eq!(
+            a.mmio_platform_allocator()
+                .unwrap()
+                .allocate(0x20_0000, id, "too big".to_string()),
+            Err(Error::OutOfSpace)
+        );
+
+        // The window must keep its base.
+        assert_eq!(
+            a.expand_platform_mmio(AddressRange {
+                start: 0x1_0000_1000,
+                end: 0x1_002f_ffff,
+            }),
+            Err(Error::OutOfBounds)
+        );
+        assert_eq!(
+            a.expand_platform_mmio(AddressRange {
+                start: 0x1_0000_0000,
+                end: 0x1_002f_ffff,
+            }),
+            Ok(())
+        );
+        assert_eq!(
+            a.mmio_platform_allocator()
+                .unwrap()
+                .allocate(0x20_0000, id, "big".to_string()),
+            Ok(0x1_0000_0000)
+        );
+
+        // The added range is no longer available from the high MMIO pool.
+        let id = a.get_anon_alloc();
+        assert_eq!(
+            a.mmio_allocator(MmioType::High)
+                .allocate(0x1000, id, "bar".to_string()),
+            Ok(0x1_0030_0000)
+        );
+    }
+
+    #[test]
+    fn pool_stats() {
+        let mut a = SystemAllocator::new(
+            SystemAllocatorConfig {
+                io: Some(AddressRange {
+                    start: 0x1000,
+                    end: 0xffff,
+                }),
+                low_mmio: AddressRange {
+                    start: 0x3000_0000,
+                    end: 0x3fff_ffff,
+                },
+                high_mmio: AddressRange {
+                    start: 0x1_0000_0000,
+                    end: 0x1_ffff_ffff,
+                },
+                platform_mmio: None,
+                first_irq: 5,
+            },
+            None,
+            &[],
+        )
+        .unwrap();
+        let bar = Alloc::PciBar {
+            bus: 0,
+            dev: 1,
+            func: 0,
+            bar: 0,
+        };
+        a.mmio_allocator(MmioType::Low)
+            .allocate(0x1000_0000, bar, "bar0".to_string())
+            .unwrap();
+        a.mmio_allocator(MmioType::High)
+            .allocate_at(
+                AddressRange {
+                    start: 0x1_8000_0000,
+                    end: 0x1_8000_ffff,
+                },
+                Alloc::Pstore,
+                "pstore".to_string(),
+            )
+            .unwrap();
+
+        let stats = a.pool_stats();
+        assert_eq!(
+            stats.keys().collect::<Vec<_>>(),
+            ["high_mmio", "io", "low_mmio"]
+        );
+
+        assert_eq!(stats["io"].size, 0xf000);
+        assert_eq!(stats["io"].allocated, 0);
+        assert_eq!(stats["io"].largest_free, 0xf000);
+
+        let low = &stats["low_mmio"];
+        assert_eq!(low.size, 0x1000_0000);
+        assert_eq!(low.allocated, 0x1000_0000);
+        assert_eq!(low.largest_free, 0);
+        assert_eq!(low.allocations.len(), 1);
+        assert_eq!(low.allocations[0].alloc, bar);
+        assert_eq!(low.allocations[0].tag, "bar0");
+
+        let high = &stats["high_mmio"];
+        assert_eq!(high.size, 0x1_0000_0000);
+        assert_eq!(high.allocated, 0x1_0000);
+        assert_eq!(high.largest_free, 0x8000_0000);
+        assert_eq!(
+            high.allocations[0].range,
+            AddressRange {
+                start: 0x1_8000_0000,
+                end: 0x1_8000_ffff,
+            }
+        );
+    }
 }
@@ -74,6 +74,7 @@ extern "C" {
 #define RUTABAGA_FENCE_HANDLE_TYPE_OPAQUE_FD 0x10
 #define RUTABAGA_FENCE_HANDLE_TYPE_SYNC_FD 0x11
 #define RUTABAGA_FENCE_HANDLE_TYPE_OPAQUE_WIN32 0x12
+#define RUTABAGA_FENCE_HANDLE_TYPE_EVENT_FD 0x13
 
 struct rutabaga;
 
@@ -424,6 +424,12 @@ impl CrossDomainWorker {
 }
 
 impl CrossDomain {
+    /// Checks that the gralloc the cross-domain component allocates with can be initialized.
+    pub fn probe() -> RutabagaResult<()> {
+        RutabagaGralloc::new()?;
+        Ok(())
+    }
+
     /// Initializes the cross-domain component by taking the the rutabaga channels (if any) and
     /// initializing rutabaga gralloc.
     pub fn init(
@@ -511,6 +517,7 @@ impl CrossDomainContext {
             height: cmd_get_reqs.height,
             drm_format: DrmFormat::from(cmd_get_reqs.drm_format),
             flags: RutabagaGrallocFlags::new(cmd_get_reqs.flags),
+            ..Default::default()
         };
 
         let reqs = self.gralloc.lock().get_image_memory_requirements(info)?;
@@ -633,7 +640,7 @@ impl RutabagaContext for CrossDomainContext {
                     // cross-domain use case, so whatever.
                     let hnd = match handle_opt {
                         Some(handle) => handle,
-                        None => self.gralloc.lock().allocate_memory(*reqs)?,
+                        None => self.gralloc.lock().allocate_memory(reqs.clone())?,
                     };
 
                     let info_3d = Resource3DInfo {
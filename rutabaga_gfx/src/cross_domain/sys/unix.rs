@@ -8,6 +8,7 @@ use std::io::Seek;
 use std::io::SeekFrom;
 
 use base::pipe;
+use base::AccessMode;
 use base::AsRawDescriptor;
 use base::FileFlags;
 use base::FromRawDescriptor;
@@ -46,8 +47,8 @@ pub(crate) fn descriptor_analysis(
             Ok(())
         }
         _ => {
-            *descriptor_type = match FileFlags::from_file(descriptor) {
-                Ok(FileFlags::Write) => CROSS_DOMAIN_ID_TYPE_WRITE_PIPE,
+            *descriptor_type = match FileFlags::from_file(descriptor).map(|f| f.access_mode()) {
+                Ok(AccessMode::Write) => CROSS_DOMAIN_ID_TYPE_WRITE_PIPE,
                 _ => return Err(RutabagaError::InvalidCrossDomainItemType),
             };
             Ok(())
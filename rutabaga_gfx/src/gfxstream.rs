@@ -193,7 +193,9 @@ extern "C" {
 }
 
 /// The virtio-gpu backend state tracker which supports accelerated rendering.
-pub struct Gfxstream {}
+pub struct Gfxstream {
+    exported_fences: ExportedFences,
+}
 
 struct GfxstreamContext {
     ctx_id: u32,
@@ -273,15 +275,26 @@ const GFXSTREAM_RENDERER_CALLBACKS: &VirglRendererCallbacks = &VirglRendererCall
 };
 
 impl Gfxstream {
+    /// Checks that the host can back gfxstream, without initializing it.
+    pub fn probe() -> RutabagaResult<()> {
+        // gfxstream renders through the host's graphics APIs on Windows, which don't need a
+        // render node.
+        #[cfg(unix)]
+        probe_render_node()?;
+        Ok(())
+    }
+
     pub fn init(
         display_width: u32,
         display_height: u32,
         gfxstream_flags: GfxstreamFlags,
         fence_handler: RutabagaFenceHandler,
     ) -> RutabagaResult<Box<dyn RutabagaComponent>> {
+        // gfxstream has no fence export API, so fences are exported from its fence callback.
+        let exported_fences = ExportedFences::default();
         let cookie: *mut VirglCookie = Box::into_raw(Box::new(VirglCookie {
             render_server_fd: None,
-            fence_handler: Some(fence_handler.clone()),
+            fence_handler: Some(exported_fences.wrap_handler(fence_handler)),
         }));
 
         unsafe {
@@ -296,7 +309,7 @@ impl Gfxstream {
             );
         }
 
-        Ok(Box::new(Gfxstream {}))
+        Ok(Box::new(Gfxstream { exported_fences }))
     }
 
     fn map_info(&self, resource_id: u32) -> RutabagaResult<u32> {
@@ -599,6 +612,14 @@ impl RutabagaComponent for Gfxstream {
         ret_to_res(ret)
     }
 
+    fn supports_fence_export(&self) -> bool {
+        true
+    }
+
+    fn export_fence(&self, fence_id: u32) -> RutabagaResult<RutabagaHandle> {
+        self.exported_fences.export(fence_id.into())
+    }
+
     fn create_context(
         &self,
         ctx_id: u32,
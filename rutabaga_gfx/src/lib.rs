@@ -27,4 +27,6 @@ pub use crate::rutabaga_gralloc::ImageAllocationInfo;
 pub use crate::rutabaga_gralloc::ImageMemoryRequirements;
 pub use crate::rutabaga_gralloc::RutabagaGralloc;
 pub use crate::rutabaga_gralloc::RutabagaGrallocFlags;
+pub use crate::rutabaga_gralloc::DRM_FORMAT_MOD_INVALID;
+pub use crate::rutabaga_gralloc::DRM_FORMAT_MOD_LINEAR;
 pub use crate::rutabaga_utils::*;
@@ -4,18 +4,38 @@
 
 //! renderer_utils: Utility functions and structs used by virgl_renderer and gfxstream.
 
+#[cfg(feature = "gfxstream")]
+use std::collections::BTreeMap as Map;
+#[cfg(unix)]
+use std::fs::OpenOptions;
+#[cfg(unix)]
+use std::io::ErrorKind;
 use std::os::raw::c_int;
 use std::os::raw::c_void;
 use std::panic::catch_unwind;
 use std::process::abort;
+#[cfg(feature = "gfxstream")]
+use std::sync::Arc;
 
+#[cfg(feature = "gfxstream")]
+use base::error;
+#[cfg(feature = "gfxstream")]
+use base::Event;
 use base::IntoRawDescriptor;
 use base::SafeDescriptor;
+#[cfg(feature = "gfxstream")]
+use sync::Mutex;
 
 use crate::rutabaga_utils::RutabagaError;
 use crate::rutabaga_utils::RutabagaFence;
+#[cfg(feature = "gfxstream")]
+use crate::rutabaga_utils::RutabagaFenceClosure;
 use crate::rutabaga_utils::RutabagaFenceHandler;
+#[cfg(feature = "gfxstream")]
+use crate::rutabaga_utils::RutabagaHandle;
 use crate::rutabaga_utils::RutabagaResult;
+#[cfg(feature = "gfxstream")]
+use crate::rutabaga_utils::RUTABAGA_FENCE_HANDLE_TYPE_EVENT_FD;
 use crate::rutabaga_utils::RUTABAGA_FLAG_FENCE;
 #[cfg(feature = "gfxstream")]
 use crate::rutabaga_utils::RUTABAGA_FLAG_INFO_RING_IDX;
@@ -31,6 +51,29 @@ pub struct VirglBox {
     pub d: u32,
 }
 
+/// Checks that a DRM render node can be opened, which the renderers need to render on a GPU.
+#[cfg(unix)]
+pub fn probe_render_node() -> RutabagaResult<()> {
+    const DRM_DIR_NAME: &str = "/dev/dri";
+    const DRM_MAX_MINOR: u32 = 15;
+    const RENDER_NODE_START: u32 = 128;
+
+    let mut open_error = None;
+    for n in RENDER_NODE_START..=RENDER_NODE_START + DRM_MAX_MINOR {
+        let path = format!("{}/renderD{}", DRM_DIR_NAME, n);
+        match OpenOptions::new().read(true).write(true).open(path) {
+            Ok(_) => return Ok(()),
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            // Report why an existing render node couldn't be opened, e.g. missing permissions.
+            Err(e) => open_error = Some(e),
+        }
+    }
+
+    Err(open_error
+        .map(RutabagaError::IoError)
+        .unwrap_or(RutabagaError::SpecViolation("no DRM rendernode found")))
+}
+
 pub fn ret_to_res(ret: i32) -> RutabagaResult<()> {
     match ret {
         0 => Ok(()),
@@ -38,6 +81,76 @@ pub fn ret_to_res(ret: i32) -> RutabagaResult<()> {
     }
 }
 
+#[cfg(feature = "gfxstream")]
+#[derive(Default)]
+struct ExportedFencesState {
+    pending: Map<u64, Vec<Event>>,
+    last_signaled: Option<u64>,
+}
+
+/// Exports fences as events for renderers that only report fence completion through the fence
+/// callback.
+///
+/// Like `virgl_renderer_export_fence`, only the fences of the global timeline, i.e. the ones
+/// without a ring index, can be exported. These are signaled in order, so a fence exported after
+/// a later one was signaled is exported as already signaled.
+#[cfg(feature = "gfxstream")]
+#[derive(Clone, Default)]
+pub struct ExportedFences {
+    state: Arc<Mutex<ExportedFencesState>>,
+}
+
+#[cfg(feature = "gfxstream")]
+impl ExportedFences {
+    /// Returns an event that becomes readable once the fence `fence_id` is signaled.
+    pub fn export(&self, fence_id: u64) -> RutabagaResult<RutabagaHandle> {
+        let event = Event::new()?;
+        let mut state = self.state.lock();
+        if state.last_signaled.map_or(false, |last| fence_id <= last) {
+            event.write(1)?;
+        } else {
+            state
+                .pending
+                .entry(fence_id)
+                .or_default()
+                .push(event.try_clone()?);
+        }
+
+        Ok(RutabagaHandle {
+            os_handle: event.into(),
+            handle_type: RUTABAGA_FENCE_HANDLE_TYPE_EVENT_FD,
+        })
+    }
+
+    /// Signals the events of the exported fences up to `fence_id`.
+    pub fn signal(&self, fence_id: u64) {
+        let mut state = self.state.lock();
+        let pending = match fence_id.checked_add(1) {
+            Some(next) => state.pending.split_off(&next),
+            None => Map::new(),
+        };
+        let signaled = std::mem::replace(&mut state.pending, pending);
+        state.last_signaled = state.last_signaled.max(Some(fence_id));
+
+        for event in signaled.into_values().flatten() {
+            if let Err(e) = event.write(1) {
+                error!("failed to signal exported fence: {}", e);
+            }
+        }
+    }
+
+    /// Returns a fence handler that signals the exported fences before calling `fence_handler`.
+    pub fn wrap_handler(&self, fence_handler: RutabagaFenceHandler) -> RutabagaFenceHandler {
+        let exported_fences = self.clone();
+        RutabagaFenceClosure::new(move |fence: RutabagaFence| {
+            if fence.flags & RUTABAGA_FLAG_INFO_RING_IDX == 0 {
+                exported_fences.signal(fence.fence_id);
+            }
+            fence_handler.call(fence);
+        })
+    }
+}
+
 pub struct VirglCookie {
     pub render_server_fd: Option<SafeDescriptor>,
     pub fence_handler: Option<RutabagaFenceHandler>,
@@ -102,3 +215,61 @@ pub unsafe extern "C" fn get_server_fd(cookie: *mut c_void, version: u32) -> c_i
     })
     .unwrap_or_else(|_| abort())
 }
+
+#[cfg(all(test, feature = "gfxstream"))]
+mod tests {
+    use std::time::Duration;
+
+    use base::EventWaitResult;
+    use base::FromRawDescriptor;
+
+    use super::*;
+
+    fn is_signaled(handle: RutabagaHandle) -> bool {
+        assert_eq!(handle.handle_type, RUTABAGA_FENCE_HANDLE_TYPE_EVENT_FD);
+        // Safe because the handle of an exported fence is an event that we own.
+        let event = unsafe { Event::from_raw_descriptor(handle.os_handle.into_raw_descriptor()) };
+        event.wait_timeout(Duration::ZERO).unwrap() == EventWaitResult::Signaled
+    }
+
+    #[test]
+    fn exported_fences_signal_in_order() {
+        let exported_fences = ExportedFences::default();
+        let first = exported_fences.export(1).unwrap();
+        let second = exported_fences.export(2).unwrap();
+
+        exported_fences.signal(1);
+        assert!(is_signaled(first));
+        assert!(!is_signaled(second.try_clone().unwrap()));
+
+        exported_fences.signal(2);
+        assert!(is_signaled(second));
+
+        // Fences that were already signaled are exported as such.
+        assert!(is_signaled(exported_fences.export(2).unwrap()));
+        assert!(!is_signaled(exported_fences.export(3).unwrap()));
+    }
+
+    #[test]
+    fn exported_fences_ignore_ring_fences() {
+        let exported_fences = ExportedFences::default();
+        let fence = exported_fences.export(1).unwrap();
+        let handler = exported_fences.wrap_handler(RutabagaFenceClosure::new(|_| {}));
+
+        handler.call(RutabagaFence {
+            flags: RUTABAGA_FLAG_FENCE | RUTABAGA_FLAG_INFO_RING_IDX,
+            fence_id: 1,
+            ctx_id: 1,
+            ring_idx: 0,
+        });
+        assert!(!is_signaled(fence.try_clone().unwrap()));
+
+        handler.call(RutabagaFence {
+            flags: RUTABAGA_FLAG_FENCE,
+            fence_id: 1,
+            ctx_id: 0,
+            ring_idx: 0,
+        });
+        assert!(is_signaled(fence));
+    }
+}
@@ -7,6 +7,7 @@
 use std::collections::BTreeMap as Map;
 use std::sync::Arc;
 
+use base::warn;
 use base::SafeDescriptor;
 use data_model::VolatileSlice;
 
@@ -159,6 +160,11 @@ pub trait RutabagaComponent {
         Err(RutabagaError::Unsupported)
     }
 
+    /// Implementations must return true when `export_fence` is supported.
+    fn supports_fence_export(&self) -> bool {
+        false
+    }
+
     /// Implementations must return a RutabagaHandle of the fence on success.
     fn export_fence(&self, _fence_id: u32) -> RutabagaResult<RutabagaHandle> {
         Err(RutabagaError::Unsupported)
@@ -259,6 +265,33 @@ pub fn calculate_context_mask(context_names: Vec<String>) -> u64 {
     context_mask
 }
 
+fn capset_mask<'a>(capsets: impl Iterator<Item = &'a RutabagaCapsetInfo>) -> u64 {
+    capsets.fold(0, |mask, capset| mask | 1 << capset.capset_id)
+}
+
+// Returns the component `RutabagaBuilder::build` initializes besides cross-domain, which the
+// context types choose if there are any.
+fn initialized_component(
+    default_component: RutabagaComponentType,
+    context_mask: u64,
+) -> RutabagaComponentType {
+    if context_mask == 0 {
+        return default_component;
+    }
+
+    let capset_enabled = |capset_id: u32| (context_mask & (1 << capset_id)) != 0;
+    if capset_enabled(RUTABAGA_CAPSET_GFXSTREAM) {
+        RutabagaComponentType::Gfxstream
+    } else if capset_enabled(RUTABAGA_CAPSET_VIRGL2)
+        || capset_enabled(RUTABAGA_CAPSET_VENUS)
+        || capset_enabled(RUTABAGA_CAPSET_DRM)
+    {
+        RutabagaComponentType::VirglRenderer
+    } else {
+        RutabagaComponentType::CrossDomain
+    }
+}
+
 pub fn calculate_context_types(context_mask: u64) -> Vec<String> {
     RUTABAGA_CAPSETS
         .iter()
@@ -651,6 +684,14 @@ impl Rutabaga {
         }
     }
 
+    /// Returns whether fences can be exported with `export_fence`, so that their completion can be
+    /// waited on through the returned handle.
+    pub fn supports_fence_export(&self) -> bool {
+        self.components
+            .get(&self.default_component)
+            .map_or(false, |component| component.supports_fence_export())
+    }
+
     /// Exports the given fence for import into other processes.
     pub fn export_fence(&self, fence_id: u32) -> RutabagaResult<RutabagaHandle> {
         let component = self
@@ -776,6 +817,13 @@ impl RutabagaBuilder {
         }
     }
 
+    /// Replaces the context types given to `RutabagaBuilder::new`, e.g. with the ones
+    /// `probe_context_mask` found usable.
+    pub fn set_context_mask(mut self, context_mask: u64) -> RutabagaBuilder {
+        self.context_mask = context_mask;
+        self
+    }
+
     /// Set display width for the RutabagaBuilder
     pub fn set_display_width(mut self, display_width: u32) -> RutabagaBuilder {
         self.display_width = Some(display_width);
@@ -862,6 +910,63 @@ impl RutabagaBuilder {
         self
     }
 
+    /// Returns the components built into rutabaga that the host can back, without initializing
+    /// them.
+    ///
+    /// Each component only checks what it needs from the host, like a render node for the 3D
+    /// renderers, so this can be called before `build`. The components that can't be used are
+    /// logged along with the reason.
+    pub fn probe_available_components(&self) -> Vec<RutabagaComponentType> {
+        let mut probes = vec![(RutabagaComponentType::Rutabaga2D, Ok(()))];
+        #[cfg(feature = "virgl_renderer")]
+        probes.push((RutabagaComponentType::VirglRenderer, VirglRenderer::probe()));
+        #[cfg(feature = "gfxstream")]
+        probes.push((RutabagaComponentType::Gfxstream, Gfxstream::probe()));
+        probes.push((RutabagaComponentType::CrossDomain, CrossDomain::probe()));
+
+        probes
+            .into_iter()
+            .filter_map(|(component, result)| match result {
+                Ok(()) => Some(component),
+                Err(e) => {
+                    warn!("rutabaga component {:?} is unavailable: {}", component, e);
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Returns the mask of the capsets `build` would report, leaving out the ones of the
+    /// components `probe_available_components` finds unusable.
+    ///
+    /// The result is 0 in 2D mode, or if none of the context types given to the builder can be
+    /// used.
+    pub fn probe_context_mask(&self) -> u64 {
+        let available = self.probe_available_components();
+        let usable_capsets = || {
+            RUTABAGA_CAPSETS
+                .iter()
+                .filter(|capset| available.contains(&capset.component))
+        };
+
+        let context_mask = capset_mask(
+            usable_capsets().filter(|capset| self.context_mask & (1 << capset.capset_id) != 0),
+        );
+        if self.context_mask != 0 && context_mask == 0 {
+            return 0;
+        }
+
+        let component = initialized_component(self.default_component, context_mask);
+        if component == RutabagaComponentType::Rutabaga2D {
+            return 0;
+        }
+        capset_mask(usable_capsets().filter(|capset| {
+            (capset.component == component
+                || capset.component == RutabagaComponentType::CrossDomain)
+                && (context_mask == 0 || context_mask & (1 << capset.capset_id) != 0)
+        }))
+    }
+
     /// Builds Rutabaga and returns a handle to it.
     ///
     /// This should be only called once per every virtual machine instance.  Rutabaga tries to
@@ -899,18 +1004,8 @@ impl RutabagaBuilder {
         };
 
         if self.context_mask != 0 {
-            let supports_gfxstream = capset_enabled(RUTABAGA_CAPSET_GFXSTREAM);
-            let supports_virglrenderer = capset_enabled(RUTABAGA_CAPSET_VIRGL2)
-                | capset_enabled(RUTABAGA_CAPSET_VENUS)
-                | capset_enabled(RUTABAGA_CAPSET_DRM);
-
-            if supports_gfxstream {
-                self.default_component = RutabagaComponentType::Gfxstream;
-            } else if supports_virglrenderer {
-                self.default_component = RutabagaComponentType::VirglRenderer;
-            } else {
-                self.default_component = RutabagaComponentType::CrossDomain;
-            }
+            self.default_component =
+                initialized_component(self.default_component, self.context_mask);
 
             self.virglrenderer_flags = self
                 .virglrenderer_flags
@@ -990,3 +1085,81 @@ impl RutabagaBuilder {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn initialized_component_from_context_types() {
+        let mask = |context_types: &[&str]| {
+            calculate_context_mask(context_types.iter().map(|t| t.to_string()).collect())
+        };
+        let component = |context_types: &[&str]| {
+            initialized_component(RutabagaComponentType::Rutabaga2D, mask(context_types))
+        };
+
+        assert_eq!(component(&[]), RutabagaComponentType::Rutabaga2D);
+        assert_eq!(
+            component(&["gfxstream", "virgl2", "cross-domain"]),
+            RutabagaComponentType::Gfxstream
+        );
+        assert_eq!(
+            component(&["venus", "cross-domain"]),
+            RutabagaComponentType::VirglRenderer
+        );
+        assert_eq!(
+            component(&["cross-domain"]),
+            RutabagaComponentType::CrossDomain
+        );
+    }
+
+    #[test]
+    #[cfg(not(any(feature = "gfxstream", feature = "virgl_renderer")))]
+    fn probe_2d_only() {
+        let all_context_types = capset_mask(RUTABAGA_CAPSETS.iter());
+
+        let builder = RutabagaBuilder::new(RutabagaComponentType::Rutabaga2D, 0);
+        assert_eq!(
+            builder.probe_available_components(),
+            [
+                RutabagaComponentType::Rutabaga2D,
+                RutabagaComponentType::CrossDomain
+            ]
+        );
+        // There are no capsets in 2D mode.
+        assert_eq!(builder.probe_context_mask(), 0);
+
+        // Only cross-domain is left of the requested context types.
+        let builder = RutabagaBuilder::new(RutabagaComponentType::Rutabaga2D, all_context_types);
+        assert_eq!(
+            calculate_context_types(builder.probe_context_mask()),
+            ["cross-domain"]
+        );
+        let builder = RutabagaBuilder::new(
+            RutabagaComponentType::Rutabaga2D,
+            calculate_context_mask(vec!["gfxstream".to_string(), "virgl2".to_string()]),
+        );
+        assert_eq!(builder.probe_context_mask(), 0);
+
+        // The default 3D component isn't built in, only cross-domain is reported along with it.
+        let builder = RutabagaBuilder::new(RutabagaComponentType::VirglRenderer, 0);
+        assert_eq!(
+            calculate_context_types(builder.probe_context_mask()),
+            ["cross-domain"]
+        );
+    }
+
+    #[test]
+    #[cfg(not(feature = "virgl_renderer_next"))]
+    fn export_fence_2d() {
+        let rutabaga = RutabagaBuilder::new(RutabagaComponentType::Rutabaga2D, 0)
+            .build(RutabagaFenceClosure::new(|_| {}))
+            .unwrap();
+        assert!(!rutabaga.supports_fence_export());
+        assert!(matches!(
+            rutabaga.export_fence(1),
+            Err(RutabagaError::Unsupported)
+        ));
+    }
+}
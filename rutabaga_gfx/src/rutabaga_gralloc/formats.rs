@@ -284,9 +284,10 @@ mod tests {
             height: 10,
             drm_format: DrmFormat::new(b'R', b'8', b' ', b' '),
             flags: RutabagaGrallocFlags::empty(),
+            ..Default::default()
         };
 
-        let r8_reqs = canonical_image_requirements(info).unwrap();
+        let r8_reqs = canonical_image_requirements(info.clone()).unwrap();
 
         assert_eq!(r8_reqs.info.width, 10);
         assert_eq!(r8_reqs.info.height, 10);
@@ -323,9 +324,10 @@ mod tests {
             height: 10,
             drm_format: DrmFormat::new(b'N', b'V', b'1', b'2'),
             flags: RutabagaGrallocFlags::empty(),
+            ..Default::default()
         };
 
-        let nv12_reqs = canonical_image_requirements(info).unwrap();
+        let nv12_reqs = canonical_image_requirements(info.clone()).unwrap();
 
         assert_eq!(nv12_reqs.info.width, 10);
         assert_eq!(nv12_reqs.info.height, 10);
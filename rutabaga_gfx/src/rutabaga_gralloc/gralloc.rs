@@ -40,6 +40,11 @@ const RUTABAGA_GRALLOC_VIDEO_DECODER: u32 = 1 << 13;
 #[allow(dead_code)]
 const RUTABAGA_GRALLOC_VIDEO_ENCODER: u32 = 1 << 14;
 
+/// The buffer is laid out in memory row by row, without tiling or compression.
+pub const DRM_FORMAT_MOD_LINEAR: u64 = 0;
+/// Reserved by the kernel to mean "no modifier", never a valid layout.
+pub const DRM_FORMAT_MOD_INVALID: u64 = 0x00ff_ffff_ffff_ffff;
+
 /// Usage flags for constructing a buffer object.
 #[derive(Copy, Clone, Eq, PartialEq, Default)]
 pub struct RutabagaGrallocFlags(pub u32);
@@ -108,6 +113,12 @@ impl RutabagaGrallocFlags {
         }
     }
 
+    /// Returns true if the linear flag is set.
+    #[inline(always)]
+    pub fn uses_linear(self) -> bool {
+        self.0 & RUTABAGA_GRALLOC_USE_LINEAR != 0
+    }
+
     /// Returns true if the texturing flag is set.
     #[inline(always)]
     pub fn uses_texturing(self) -> bool {
@@ -140,16 +151,45 @@ impl RutabagaGrallocFlags {
 }
 
 /// Information required to allocate a swapchain image.
-#[derive(Copy, Clone, Default)]
+#[derive(Clone, Default)]
 pub struct ImageAllocationInfo {
     pub width: u32,
     pub height: u32,
     pub drm_format: DrmFormat,
     pub flags: RutabagaGrallocFlags,
+    /// DRM format modifiers the image may be allocated with.  An empty list lets the backend
+    /// choose any layout.
+    pub modifiers: Vec<u64>,
+}
+
+impl ImageAllocationInfo {
+    /// Returns an error if the requested modifiers can never be satisfied, whatever the backend.
+    pub fn validate(&self) -> RutabagaResult<()> {
+        if self.modifiers.is_empty() {
+            return Ok(());
+        }
+
+        if self.modifiers.contains(&DRM_FORMAT_MOD_INVALID) {
+            return Err(RutabagaError::InvalidGrallocModifiers);
+        }
+
+        // A linear image can only have the linear layout.
+        if self.flags.uses_linear() && !self.modifiers.contains(&DRM_FORMAT_MOD_LINEAR) {
+            return Err(RutabagaError::InvalidGrallocModifiers);
+        }
+
+        Ok(())
+    }
+
+    /// Returns true if `modifier` is one of the requested modifiers, or if any modifier is
+    /// acceptable.
+    pub fn allows_modifier(&self, modifier: u64) -> bool {
+        self.modifiers.is_empty() || self.modifiers.contains(&modifier)
+    }
 }
 
 /// The memory requirements, compression and layout of a swapchain image.
-#[derive(Copy, Clone, Default)]
+#[derive(Clone, Default)]
 pub struct ImageMemoryRequirements {
     pub info: ImageAllocationInfo,
     pub map_info: u32,
@@ -177,7 +217,8 @@ pub trait Gralloc: Send {
     fn supports_dmabuf(&self) -> bool;
 
     /// Implementations must return the resource layout, compression, and caching properties of
-    /// an allocation request.
+    /// an allocation request.  The reported modifier must be one of `info.modifiers`, unless the
+    /// list is empty.
     fn get_image_memory_requirements(
         &mut self,
         info: ImageAllocationInfo,
@@ -267,7 +308,7 @@ impl RutabagaGralloc {
     }
 
     /// Returns the best allocation backend to service a particular request.
-    fn determine_optimal_backend(&self, _info: ImageAllocationInfo) -> GrallocBackend {
+    fn determine_optimal_backend(&self, _info: &ImageAllocationInfo) -> GrallocBackend {
         // This function could be more sophisticated and consider the allocation info.  For example,
         // nobody has ever tried Mali allocated memory + a mediatek/rockchip display and as such it
         // probably doesn't work.  In addition, YUV calculations in minigbm have yet to make it
@@ -298,7 +339,8 @@ impl RutabagaGralloc {
         &mut self,
         info: ImageAllocationInfo,
     ) -> RutabagaResult<ImageMemoryRequirements> {
-        let backend = self.determine_optimal_backend(info);
+        info.validate()?;
+        let backend = self.determine_optimal_backend(&info);
 
         let gralloc = self
             .grallocs
@@ -306,6 +348,10 @@ impl RutabagaGralloc {
             .ok_or(RutabagaError::InvalidGrallocBackend)?;
 
         let mut reqs = gralloc.get_image_memory_requirements(info)?;
+        if !reqs.info.allows_modifier(reqs.modifier) {
+            return Err(RutabagaError::UnsupportedGrallocModifiers);
+        }
+
         reqs.size = round_up_to_page_size(reqs.size as usize) as u64;
         Ok(reqs)
     }
@@ -315,7 +361,7 @@ impl RutabagaGralloc {
         &mut self,
         reqs: ImageMemoryRequirements,
     ) -> RutabagaResult<RutabagaHandle> {
-        let backend = self.determine_optimal_backend(reqs.info);
+        let backend = self.determine_optimal_backend(&reqs.info);
 
         let gralloc = self
             .grallocs
@@ -346,6 +392,40 @@ impl RutabagaGralloc {
 mod tests {
     use super::*;
 
+    #[test]
+    fn validate_modifiers() {
+        let mut info = ImageAllocationInfo {
+            width: 512,
+            height: 512,
+            drm_format: DrmFormat::new(b'X', b'R', b'2', b'4'),
+            flags: RutabagaGrallocFlags::empty().use_linear(true),
+            modifiers: Vec::new(),
+        };
+        assert!(info.validate().is_ok());
+        assert!(info.allows_modifier(DRM_FORMAT_MOD_LINEAR));
+
+        info.modifiers = vec![DRM_FORMAT_MOD_LINEAR];
+        assert!(info.validate().is_ok());
+
+        // A linear image can't use any other layout.
+        info.modifiers = vec![0x0100_0000_0000_0001];
+        assert!(matches!(
+            info.validate(),
+            Err(RutabagaError::InvalidGrallocModifiers)
+        ));
+        assert!(!info.allows_modifier(DRM_FORMAT_MOD_LINEAR));
+
+        info.flags = RutabagaGrallocFlags::empty();
+        assert!(info.validate().is_ok());
+        assert!(info.allows_modifier(0x0100_0000_0000_0001));
+
+        info.modifiers.push(DRM_FORMAT_MOD_INVALID);
+        assert!(matches!(
+            info.validate(),
+            Err(RutabagaError::InvalidGrallocModifiers)
+        ));
+    }
+
     #[test]
     fn create_render_target() {
         let gralloc_result = RutabagaGralloc::new();
@@ -360,15 +440,16 @@ mod tests {
             height: 1024,
             drm_format: DrmFormat::new(b'X', b'R', b'2', b'4'),
             flags: RutabagaGrallocFlags::empty().use_scanout(true),
+            ..Default::default()
         };
 
-        let reqs = gralloc.get_image_memory_requirements(info).unwrap();
+        let reqs = gralloc.get_image_memory_requirements(info.clone()).unwrap();
         let min_reqs = canonical_image_requirements(info).unwrap();
 
         assert!(reqs.strides[0] >= min_reqs.strides[0]);
         assert!(reqs.size >= min_reqs.size);
 
-        let _handle = gralloc.allocate_memory(reqs).unwrap();
+        let _handle = gralloc.allocate_memory(reqs.clone()).unwrap();
 
         // Reallocate with same requirements
         let _handle2 = gralloc.allocate_memory(reqs).unwrap();
@@ -388,9 +469,10 @@ mod tests {
             height: 1024,
             drm_format: DrmFormat::new(b'N', b'V', b'1', b'2'),
             flags: RutabagaGrallocFlags::empty().use_linear(true),
+            ..Default::default()
         };
 
-        let reqs = gralloc.get_image_memory_requirements(info).unwrap();
+        let reqs = gralloc.get_image_memory_requirements(info.clone()).unwrap();
         let min_reqs = canonical_image_requirements(info).unwrap();
 
         assert!(reqs.strides[0] >= min_reqs.strides[0]);
@@ -405,7 +487,7 @@ mod tests {
 
         assert!(reqs.size >= min_reqs.size);
 
-        let _handle = gralloc.allocate_memory(reqs).unwrap();
+        let _handle = gralloc.allocate_memory(reqs.clone()).unwrap();
 
         // Reallocate with same requirements
         let _handle2 = gralloc.allocate_memory(reqs).unwrap();
@@ -428,6 +510,7 @@ mod tests {
                 .use_linear(true)
                 .use_sw_write(true)
                 .use_sw_read(true),
+            ..Default::default()
         };
 
         let mut reqs = gralloc.get_image_memory_requirements(info).unwrap();
@@ -437,7 +520,7 @@ mod tests {
             return;
         }
 
-        let handle = gralloc.allocate_memory(reqs).unwrap();
+        let handle = gralloc.allocate_memory(reqs.clone()).unwrap();
         let vulkan_info = reqs.vulkan_info.take().unwrap();
 
         let mapping = gralloc
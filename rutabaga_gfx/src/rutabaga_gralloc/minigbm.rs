@@ -13,6 +13,7 @@ use std::fs::File;
 use std::io::Seek;
 use std::io::SeekFrom;
 use std::os::raw::c_char;
+use std::os::raw::c_uint;
 use std::sync::Arc;
 
 use base::AsRawDescriptor;
@@ -79,6 +80,47 @@ impl MinigbmDevice {
             device_name,
         }))
     }
+
+    /// Creates a buffer object for `info`, restricted to the requested modifiers if there are any.
+    fn create_bo(&self, info: &ImageAllocationInfo) -> RutabagaResult<MinigbmBuffer> {
+        let bo = if info.modifiers.is_empty() {
+            // Safe because the minigbm device is valid and the returned bo is checked below.
+            unsafe {
+                gbm_bo_create(
+                    self.minigbm_device.gbm,
+                    info.width,
+                    info.height,
+                    info.drm_format.0,
+                    info.flags.0,
+                )
+            }
+        } else {
+            // gbm_bo_create_with_modifiers doesn't take usage flags: the modifiers already
+            // describe the layout.  Safe because the modifier pointer and count describe a valid
+            // slice, which minigbm only reads during the call.
+            let count = c_uint::try_from(info.modifiers.len())?;
+            let bo = unsafe {
+                gbm_bo_create_with_modifiers(
+                    self.minigbm_device.gbm,
+                    info.width,
+                    info.height,
+                    info.drm_format.0,
+                    info.modifiers.as_ptr(),
+                    count,
+                )
+            };
+            if bo.is_null() {
+                return Err(RutabagaError::UnsupportedGrallocModifiers);
+            }
+            bo
+        };
+
+        if bo.is_null() {
+            return Err(RutabagaError::BaseError(BaseError::last()));
+        }
+
+        Ok(MinigbmBuffer(bo, self.clone()))
+    }
 }
 
 impl Gralloc for MinigbmDevice {
@@ -94,21 +136,8 @@ impl Gralloc for MinigbmDevice {
         &mut self,
         info: ImageAllocationInfo,
     ) -> RutabagaResult<ImageMemoryRequirements> {
-        let bo = unsafe {
-            gbm_bo_create(
-                self.minigbm_device.gbm,
-                info.width,
-                info.height,
-                info.drm_format.0,
-                info.flags.0,
-            )
-        };
-        if bo.is_null() {
-            return Err(RutabagaError::BaseError(BaseError::last()));
-        }
-
+        let gbm_buffer = self.create_bo(&info)?;
         let mut reqs: ImageMemoryRequirements = Default::default();
-        let gbm_buffer = MinigbmBuffer(bo, self.clone());
 
         // Intel GPUs typically only use cached memory buffers.  This will change with dGPUs, but
         // perhaps minigbm will be deprecated by then.  Other display drivers (rockchip, mediatek,
@@ -121,6 +150,10 @@ impl Gralloc for MinigbmDevice {
         }
 
         reqs.modifier = gbm_buffer.format_modifier();
+        if !info.allows_modifier(reqs.modifier) {
+            return Err(RutabagaError::UnsupportedGrallocModifiers);
+        }
+
         for plane in 0..gbm_buffer.num_planes() {
             reqs.strides[plane] = gbm_buffer.plane_stride(plane);
             reqs.offsets[plane] = gbm_buffer.plane_offset(plane);
@@ -148,6 +181,7 @@ impl Gralloc for MinigbmDevice {
             if gbm_buffer.width() != reqs.info.width
                 || gbm_buffer.height() != reqs.info.height
                 || gbm_buffer.format() != reqs.info.drm_format
+                || gbm_buffer.format_modifier() != reqs.modifier
             {
                 return Err(RutabagaError::InvalidGrallocDimensions);
             }
@@ -159,21 +193,11 @@ impl Gralloc for MinigbmDevice {
             });
         }
 
-        let bo = unsafe {
-            gbm_bo_create(
-                self.minigbm_device.gbm,
-                reqs.info.width,
-                reqs.info.height,
-                reqs.info.drm_format.0,
-                reqs.info.flags.0,
-            )
-        };
-
-        if bo.is_null() {
-            return Err(RutabagaError::BaseError(BaseError::last()));
+        let gbm_buffer = self.create_bo(&reqs.info)?;
+        if !reqs.info.allows_modifier(gbm_buffer.format_modifier()) {
+            return Err(RutabagaError::UnsupportedGrallocModifiers);
         }
 
-        let gbm_buffer = MinigbmBuffer(bo, self.clone());
         let dmabuf = gbm_buffer.export()?.into();
         Ok(RutabagaHandle {
             os_handle: dmabuf,
@@ -251,3 +275,39 @@ impl Drop for MinigbmBuffer {
         unsafe { gbm_bo_destroy(self.0) }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rutabaga_gralloc::gralloc::RutabagaGrallocFlags;
+    use crate::rutabaga_gralloc::gralloc::DRM_FORMAT_MOD_LINEAR;
+
+    #[test]
+    fn allocate_with_modifiers() {
+        // Hosts without a render node can't run this test.
+        let mut gralloc = match MinigbmDevice::init() {
+            Ok(gralloc) => gralloc,
+            Err(_) => return,
+        };
+
+        let mut info = ImageAllocationInfo {
+            width: 512,
+            height: 512,
+            drm_format: DrmFormat::new(b'X', b'R', b'2', b'4'),
+            flags: RutabagaGrallocFlags::empty().use_linear(true),
+            modifiers: vec![DRM_FORMAT_MOD_LINEAR],
+        };
+
+        let reqs = gralloc.get_image_memory_requirements(info.clone()).unwrap();
+        assert_eq!(reqs.modifier, DRM_FORMAT_MOD_LINEAR);
+        gralloc.allocate_memory(reqs).unwrap();
+
+        // No driver uses this vendor and layout.
+        info.flags = RutabagaGrallocFlags::empty();
+        info.modifiers = vec![0x7f00_0000_dead_beef];
+        assert!(matches!(
+            gralloc.get_image_memory_requirements(info),
+            Err(RutabagaError::UnsupportedGrallocModifiers)
+        ));
+    }
+}
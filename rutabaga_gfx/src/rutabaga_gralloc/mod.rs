@@ -21,3 +21,5 @@ pub use gralloc::ImageAllocationInfo;
 pub use gralloc::ImageMemoryRequirements;
 pub use gralloc::RutabagaGralloc;
 pub use gralloc::RutabagaGrallocFlags;
+pub use gralloc::DRM_FORMAT_MOD_INVALID;
+pub use gralloc::DRM_FORMAT_MOD_LINEAR;
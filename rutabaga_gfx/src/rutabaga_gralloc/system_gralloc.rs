@@ -11,6 +11,7 @@ use crate::rutabaga_gralloc::formats::canonical_image_requirements;
 use crate::rutabaga_gralloc::gralloc::Gralloc;
 use crate::rutabaga_gralloc::gralloc::ImageAllocationInfo;
 use crate::rutabaga_gralloc::gralloc::ImageMemoryRequirements;
+use crate::rutabaga_gralloc::gralloc::DRM_FORMAT_MOD_LINEAR;
 use crate::rutabaga_utils::*;
 
 /// A gralloc implementation capable of allocation from system memory.
@@ -40,6 +41,11 @@ impl Gralloc for SystemGralloc {
         &mut self,
         info: ImageAllocationInfo,
     ) -> RutabagaResult<ImageMemoryRequirements> {
+        // System memory images are always linear.
+        if !info.allows_modifier(DRM_FORMAT_MOD_LINEAR) {
+            return Err(RutabagaError::UnsupportedGrallocModifiers);
+        }
+
         let mut reqs = canonical_image_requirements(info)?;
         reqs.map_info = RUTABAGA_MAP_CACHE_CACHED;
         Ok(reqs)
@@ -24,6 +24,7 @@ use vulkano::device::QueueCreateInfo;
 use vulkano::image;
 use vulkano::image::ImageCreationError;
 use vulkano::image::ImageDimensions;
+use vulkano::image::ImageTiling;
 use vulkano::image::ImageUsage;
 use vulkano::image::SampleCount;
 use vulkano::instance::Instance;
@@ -50,6 +51,7 @@ use vulkano::VulkanLibrary;
 use crate::rutabaga_gralloc::gralloc::Gralloc;
 use crate::rutabaga_gralloc::gralloc::ImageAllocationInfo;
 use crate::rutabaga_gralloc::gralloc::ImageMemoryRequirements;
+use crate::rutabaga_gralloc::gralloc::DRM_FORMAT_MOD_LINEAR;
 use crate::rutabaga_utils::*;
 
 /// A gralloc implementation capable of allocation `VkDeviceMemory`.
@@ -197,7 +199,7 @@ impl VulkanoGralloc {
     // TODO(tutankhamen): Do we still need a separate MemoryRequirements?
     unsafe fn create_image(
         &mut self,
-        info: ImageAllocationInfo,
+        info: &ImageAllocationInfo,
     ) -> RutabagaResult<(Arc<image::sys::UnsafeImage>, MemoryRequirements)> {
         let device = if self.has_integrated_gpu {
             self.devices
@@ -230,6 +232,16 @@ impl VulkanoGralloc {
             return Err(RutabagaError::InvalidGrallocDimensions);
         }
 
+        // Vulkano doesn't expose VK_EXT_image_drm_format_modifier, so the only layout that can be
+        // requested explicitly is the linear one.
+        let tiling = if info.modifiers.is_empty() {
+            ImageTiling::Optimal
+        } else if info.modifiers.contains(&DRM_FORMAT_MOD_LINEAR) {
+            ImageTiling::Linear
+        } else {
+            return Err(RutabagaError::UnsupportedGrallocModifiers);
+        };
+
         let vulkan_format = info.drm_format.vulkan_format()?;
         let unsafe_image = image::sys::UnsafeImage::new(
             device.clone(),
@@ -244,6 +256,7 @@ impl VulkanoGralloc {
                 usage,
                 mip_levels: 1,
                 sharing: Sharing::Exclusive,
+                tiling,
                 ..Default::default()
             },
         )?;
@@ -281,7 +294,7 @@ impl Gralloc for VulkanoGralloc {
     ) -> RutabagaResult<ImageMemoryRequirements> {
         let mut reqs: ImageMemoryRequirements = Default::default();
 
-        let (unsafe_image, memory_requirements) = unsafe { self.create_image(info)? };
+        let (unsafe_image, memory_requirements) = unsafe { self.create_image(&info)? };
 
         let device_type = if self.has_integrated_gpu {
             &PhysicalDeviceType::IntegratedGpu
@@ -378,7 +391,7 @@ impl Gralloc for VulkanoGralloc {
     }
 
     fn allocate_memory(&mut self, reqs: ImageMemoryRequirements) -> RutabagaResult<RutabagaHandle> {
-        let (unsafe_image, memory_requirements) = unsafe { self.create_image(reqs.info)? };
+        let (unsafe_image, memory_requirements) = unsafe { self.create_image(&reqs.info)? };
 
         let vulkan_info = reqs.vulkan_info.ok_or(RutabagaError::InvalidVulkanInfo)?;
 
@@ -131,6 +131,7 @@ pub struct RutabagaFence {
 }
 
 /// Mapped memory caching flags (see virtio_gpu spec)
+pub const RUTABAGA_MAP_CACHE_MASK: u32 = 0x0f;
 pub const RUTABAGA_MAP_CACHE_CACHED: u32 = 0x01;
 pub const RUTABAGA_MAP_CACHE_UNCACHED: u32 = 0x02;
 pub const RUTABAGA_MAP_CACHE_WC: u32 = 0x03;
@@ -212,6 +213,9 @@ pub enum RutabagaError {
     /// Invalid GPU type.
     #[error("invalid GPU type for gralloc")]
     InvalidGrallocGpuType,
+    /// The requested DRM format modifiers can't describe a valid image.
+    #[error("invalid gralloc DRM format modifiers")]
+    InvalidGrallocModifiers,
     /// Invalid number of YUV planes.
     #[error("invalid number of YUV planes")]
     InvalidGrallocNumberOfPlanes,
@@ -245,6 +249,9 @@ pub enum RutabagaError {
     /// The command is unsupported.
     #[error("the requested function is not implemented")]
     Unsupported,
+    /// None of the requested DRM format modifiers can be allocated.
+    #[error("none of the requested DRM format modifiers are supported")]
+    UnsupportedGrallocModifiers,
     /// Utf8 error.
     #[error("an utf8 error occured: {0}")]
     Utf8Error(Utf8Error),
@@ -577,7 +584,7 @@ pub struct RutabagaChannel {
 }
 
 /// Enumeration of possible rutabaga components.
-#[derive(Copy, Clone, Eq, PartialEq, PartialOrd, Ord)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
 pub enum RutabagaComponentType {
     Rutabaga2D,
     VirglRenderer,
@@ -593,6 +600,7 @@ pub const RUTABAGA_MEM_HANDLE_TYPE_SHM: u32 = 0x0004;
 pub const RUTABAGA_FENCE_HANDLE_TYPE_OPAQUE_FD: u32 = 0x0010;
 pub const RUTABAGA_FENCE_HANDLE_TYPE_SYNC_FD: u32 = 0x0011;
 pub const RUTABAGA_FENCE_HANDLE_TYPE_OPAQUE_WIN32: u32 = 0x0012;
+pub const RUTABAGA_FENCE_HANDLE_TYPE_EVENT_FD: u32 = 0x0013;
 
 /// Handle to OS-specific memory or synchronization objects.
 pub struct RutabagaHandle {
@@ -230,6 +230,11 @@ fn export_query(resource_id: u32) -> RutabagaResult<Query> {
 }
 
 impl VirglRenderer {
+    /// Checks that the host can back virglrenderer, without initializing it.
+    pub fn probe() -> RutabagaResult<()> {
+        probe_render_node()
+    }
+
     pub fn init(
         virglrenderer_flags: VirglRendererFlags,
         fence_handler: RutabagaFenceHandler,
@@ -646,6 +651,10 @@ impl RutabagaComponent for VirglRenderer {
     }
 
     #[allow(unused_variables)]
+    fn supports_fence_export(&self) -> bool {
+        cfg!(feature = "virgl_renderer_next")
+    }
+
     fn export_fence(&self, fence_id: u32) -> RutabagaResult<RutabagaHandle> {
         #[cfg(feature = "virgl_renderer_next")]
         {
@@ -24,6 +24,10 @@ cfg_if::cfg_if! {
 use std::collections::BTreeMap;
 use std::path::PathBuf;
 
+use arch::cpu_cache::CpuCache;
+use arch::ClusterAffinity;
+#[cfg(target_arch = "aarch64")]
+use arch::FdtPosition;
 #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
 use arch::MsrConfig;
 use arch::Pstore;
@@ -43,8 +47,11 @@ use devices::PflashParameters;
 use devices::SerialHardware;
 use devices::SerialParameters;
 use devices::StubPciParameters;
+use devices::UnmappedAccessPolicy;
 use hypervisor::ProtectionType;
 use resources::AddressRange;
+use uuid::Uuid;
+use vm_memory::BackingObjectLimits;
 
 #[cfg(feature = "gpu")]
 use super::sys::config::parse_gpu_options;
@@ -52,6 +59,7 @@ use super::sys::config::parse_gpu_options;
 use super::sys::config::parse_gpu_render_server_options;
 #[cfg(all(feature = "gpu", feature = "virgl_renderer_next"))]
 use super::sys::GpuRenderServerParameters;
+use crate::crosvm::config::from_key_values;
 use crate::crosvm::config::numbered_disk_option;
 #[cfg(feature = "audio")]
 use crate::crosvm::config::parse_ac97_options;
@@ -61,6 +69,8 @@ use crate::crosvm::config::parse_cpu_capacity;
 use crate::crosvm::config::parse_cpu_set;
 #[cfg(feature = "direct")]
 use crate::crosvm::config::parse_direct_io_options;
+#[cfg(target_arch = "aarch64")]
+use crate::crosvm::config::parse_fdt_position;
 #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
 use crate::crosvm::config::parse_memory_region;
 use crate::crosvm::config::parse_mmio_address_range;
@@ -72,11 +82,15 @@ use crate::crosvm::config::parse_plugin_mount_option;
 use crate::crosvm::config::parse_pstore;
 use crate::crosvm::config::parse_serial_options;
 use crate::crosvm::config::parse_stub_pci_parameters;
+use crate::crosvm::config::parse_unmapped_mmio;
 #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
 use crate::crosvm::config::parse_userspace_msr_options;
 use crate::crosvm::config::BatteryConfig;
 #[cfg(feature = "plugin")]
 use crate::crosvm::config::BindMount;
+use crate::crosvm::config::ClusterAffinityOption;
+#[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
+use crate::crosvm::config::CpuIdConfig;
 #[cfg(feature = "direct")]
 use crate::crosvm::config::DirectIoOption;
 use crate::crosvm::config::Executable;
@@ -86,6 +100,8 @@ use crate::crosvm::config::GidMap;
 #[cfg(feature = "direct")]
 use crate::crosvm::config::HostPcieRootPortParameters;
 use crate::crosvm::config::HypervisorKind;
+#[cfg(target_arch = "aarch64")]
+use crate::crosvm::config::PmemRegionParameters;
 use crate::crosvm::config::TouchDeviceOption;
 use crate::crosvm::config::VhostUserFsOption;
 use crate::crosvm::config::VhostUserOption;
@@ -123,6 +139,7 @@ pub enum CrossPlatformCommands {
     #[cfg(feature = "balloon")]
     BalloonStats(BalloonStatsCommand),
     Battery(BatteryCommand),
+    BootTimes(BootTimesCommand),
     #[cfg(feature = "composite-disk")]
     CreateComposite(CreateCompositeCommand),
     #[cfg(feature = "qcow")]
@@ -131,9 +148,18 @@ pub enum CrossPlatformCommands {
     Disk(DiskCommand),
     #[cfg(feature = "gpu")]
     Gpu(GpuCommand),
+    ListDevices(ListDevicesCommand),
     MakeRT(MakeRTCommand),
+    PvtimeStats(PvtimeStatsCommand),
+    Resources(ResourcesCommand),
     Resume(ResumeCommand),
     Run(RunCommand),
+    SerialBuffer(SerialBufferCommand),
+    SerialInput(SerialInputCommand),
+    SerialReopenOutput(SerialReopenOutputCommand),
+    SetKernelCmdline(SetKernelCmdlineCommand),
+    SetMetric(SetMetricCommand),
+    Snd(SndCommand),
     Stop(StopCommand),
     Suspend(SuspendCommand),
     Powerbtn(PowerbtnCommand),
@@ -142,6 +168,8 @@ pub enum CrossPlatformCommands {
     Usb(UsbCommand),
     Version(VersionCommand),
     Vfio(VfioCrosvmCommand),
+    VmInfo(VmInfoCommand),
+    Vmwdt(VmwdtCommand),
 }
 
 #[allow(clippy::large_enum_variant)]
@@ -172,6 +200,15 @@ pub struct BalloonStatsCommand {
     pub socket_path: String,
 }
 
+#[derive(FromArgs)]
+#[argh(subcommand, name = "boot_times")]
+/// Prints the time taken to reach each guest boot milestone for a `VM_SOCKET`
+pub struct BootTimesCommand {
+    #[argh(positional, arg_name = "VM_SOCKET")]
+    /// VM Socket path
+    pub socket_path: String,
+}
+
 #[derive(FromArgs)]
 #[argh(subcommand, name = "battery")]
 /// Modify battery
@@ -179,13 +216,18 @@ pub struct BatteryCommand {
     #[argh(positional, arg_name = "BATTERY_TYPE")]
     /// battery type
     pub battery_type: String,
+    #[argh(option, default = "0")]
+    /// index of the battery among the batteries of BATTERY_TYPE, in the order of their
+    /// --battery options (default: 0)
+    pub index: usize,
     #[argh(positional)]
     /// battery property
-    /// status | present | health | capacity | aconline
+    /// status | present | health | capacity | aconline | temperature | cyclecount
     pub property: String,
     #[argh(positional)]
     /// battery property target
-    /// STATUS | PRESENT | HEALTH | CAPACITY | ACONLINE
+    /// STATUS | PRESENT | HEALTH | CAPACITY | ACONLINE | TEMPERATURE | CYCLECOUNT
+    /// (temperature is in tenths of a degree Celsius)
     pub target: String,
     #[argh(positional, arg_name = "VM_SOCKET")]
     /// VM Socket path
@@ -251,6 +293,15 @@ pub struct DiskCommand {
     pub command: DiskSubcommand,
 }
 
+#[derive(FromArgs)]
+#[argh(subcommand, name = "list_devices")]
+/// Prints the devices the VM at a `VM_SOCKET` has at fixed MMIO addresses
+pub struct ListDevicesCommand {
+    #[argh(positional, arg_name = "VM_SOCKET")]
+    /// VM Socket path
+    pub socket_path: String,
+}
+
 #[derive(FromArgs)]
 #[argh(subcommand, name = "make_rt")]
 /// Enables real-time vcpu priority for crosvm instances started with `--delay-rt`
@@ -260,6 +311,32 @@ pub struct MakeRTCommand {
     pub socket_path: String,
 }
 
+#[derive(FromArgs)]
+#[argh(subcommand, name = "pvtime_stats")]
+/// Prints how long the vcpus of the VM at a `VM_SOCKET` were ready to run but the host ran
+/// something else, as published to an aarch64 guest with pvtime
+pub struct PvtimeStatsCommand {
+    #[argh(option, arg_name = "VCPU")]
+    /// only print the stolen time of this vcpu
+    pub vcpu: Option<usize>,
+    #[argh(positional, arg_name = "VM_SOCKET")]
+    /// VM Socket path
+    pub socket_path: String,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "resources")]
+/// Prints the size, allocated bytes, largest free range and allocations of each address space of
+/// the VM at a `VM_SOCKET`
+pub struct ResourcesCommand {
+    #[argh(switch)]
+    /// print JSON instead of a table
+    pub json: bool,
+    #[argh(positional, arg_name = "VM_SOCKET")]
+    /// VM Socket path
+    pub socket_path: String,
+}
+
 #[derive(FromArgs)]
 #[argh(subcommand, name = "resume")]
 /// Resumes the crosvm instance
@@ -269,6 +346,124 @@ pub struct ResumeCommand {
     pub socket_path: String,
 }
 
+#[derive(FromArgs)]
+#[argh(subcommand, name = "serial_buffer")]
+/// Prints the recent output of a serial port of the VM at a `VM_SOCKET`, which was given a
+/// `console_buffer`
+pub struct SerialBufferCommand {
+    #[argh(option, default = "String::from(\"serial\")", arg_name = "HARDWARE")]
+    /// type of the serial port, as given to --serial (default: serial)
+    pub hardware: String,
+    #[argh(option, arg_name = "NUM")]
+    /// number of the serial port, as given to --serial
+    pub num: u8,
+    #[argh(positional, arg_name = "VM_SOCKET")]
+    /// VM Socket path
+    pub socket_path: String,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "serial_input")]
+/// Sends input to a serial port of the VM at a `VM_SOCKET`, as if it had been typed on the input
+/// of the port
+pub struct SerialInputCommand {
+    #[argh(option, default = "String::from(\"serial\")", arg_name = "HARDWARE")]
+    /// type of the serial port, as given to --serial (default: serial)
+    pub hardware: String,
+    #[argh(option, arg_name = "NUM")]
+    /// number of the serial port, as given to --serial
+    pub num: u8,
+    #[argh(positional, arg_name = "DATA")]
+    /// text given to the guest
+    pub data: String,
+    #[argh(positional, arg_name = "VM_SOCKET")]
+    /// VM Socket path
+    pub socket_path: String,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "serial_reopen_output")]
+/// Reopens the output file of a serial port of the VM at a `VM_SOCKET`, e.g. after it was renamed
+/// to rotate it
+pub struct SerialReopenOutputCommand {
+    #[argh(option, default = "String::from(\"serial\")", arg_name = "HARDWARE")]
+    /// type of the serial port, as given to --serial (default: serial)
+    pub hardware: String,
+    #[argh(option, arg_name = "NUM")]
+    /// number of the serial port, as given to --serial
+    pub num: u8,
+    #[argh(positional, arg_name = "VM_SOCKET")]
+    /// VM Socket path
+    pub socket_path: String,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "set_kernel_cmdline")]
+/// Replaces the kernel command line of a crosvm instance started with `--start-paused`, before
+/// it is resumed
+pub struct SetKernelCmdlineCommand {
+    #[argh(positional, arg_name = "CMDLINE")]
+    /// new kernel command line, replacing the whole command line
+    pub cmdline: String,
+    #[argh(positional, arg_name = "VM_SOCKET")]
+    /// VM Socket path
+    pub socket_path: String,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "set_metric")]
+/// Adds or updates a record in the metrics page of a crosvm instance started with
+/// `--metrics-page`
+pub struct SetMetricCommand {
+    #[argh(positional, arg_name = "ID")]
+    /// record id, must not be 0
+    pub id: u32,
+    #[argh(positional, arg_name = "VALUE")]
+    /// new value of the record
+    pub value: u64,
+    #[argh(positional, arg_name = "VM_SOCKET")]
+    /// VM Socket path
+    pub socket_path: String,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+pub enum SndSubcommand {
+    Capture(SndCaptureSubcommand),
+    Info(SndInfoSubcommand),
+}
+
+#[derive(FromArgs)]
+/// print the last frames rendered to the host audio engine as JSON, along with their format.
+/// Requires the VM to be started with loopback capture enabled
+#[argh(subcommand, name = "capture")]
+pub struct SndCaptureSubcommand {
+    #[argh(option, arg_name = "N")]
+    /// number of frames to print
+    pub frames: usize,
+    #[argh(positional, arg_name = "VM_SOCKET")]
+    /// VM Socket path
+    pub socket_path: String,
+}
+
+#[derive(FromArgs)]
+/// print the formats negotiated with the host audio engine and the number of renegotiations and
+/// underruns of each playback stream
+#[argh(subcommand, name = "info")]
+pub struct SndInfoSubcommand {
+    #[argh(positional, arg_name = "VM_SOCKET")]
+    /// VM Socket path
+    pub socket_path: String,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "snd")]
+/// Inspect the audio streams of a crosvm instance
+pub struct SndCommand {
+    #[argh(subcommand)]
+    pub command: SndSubcommand,
+}
+
 #[derive(FromArgs)]
 #[argh(subcommand, name = "stop")]
 /// Stops crosvm instances via their control sockets
@@ -378,6 +573,73 @@ pub struct VfioCrosvmCommand {
     pub command: VfioSubCommand,
 }
 
+#[derive(FromArgs)]
+#[argh(subcommand, name = "vm_info")]
+/// Prints the UUID, vcpu count, memory size and architecture of the VM at a `VM_SOCKET`
+pub struct VmInfoCommand {
+    #[argh(positional, arg_name = "VM_SOCKET")]
+    /// VM Socket path
+    pub socket_path: String,
+}
+
+#[derive(FromArgs)]
+/// print the state of the watchdog of each vcpu: when the guest last petted it and how much guest
+/// run time is left before it bites
+#[argh(subcommand, name = "status")]
+pub struct VmwdtStatusSubcommand {
+    #[argh(positional, arg_name = "VM_SOCKET")]
+    /// VM Socket path
+    pub socket_path: String,
+}
+
+#[derive(FromArgs)]
+/// use a timeout of SECS seconds for the next pets of all the vcpus instead of the one programmed
+/// by the guest, or go back to the guest's timeout if SECS is 0
+#[argh(subcommand, name = "set_timeout")]
+pub struct VmwdtSetTimeoutSubcommand {
+    #[argh(positional, arg_name = "SECS")]
+    /// timeout in seconds
+    pub secs: u32,
+    #[argh(positional, arg_name = "VM_SOCKET")]
+    /// VM Socket path
+    pub socket_path: String,
+}
+
+#[derive(FromArgs)]
+/// stop the countdown of the watchdogs, e.g. while a debugger holds the vcpus
+#[argh(subcommand, name = "pause")]
+pub struct VmwdtPauseSubcommand {
+    #[argh(positional, arg_name = "VM_SOCKET")]
+    /// VM Socket path
+    pub socket_path: String,
+}
+
+#[derive(FromArgs)]
+/// restart the countdown of the watchdogs stopped with `pause` from the current guest time
+#[argh(subcommand, name = "resume")]
+pub struct VmwdtResumeSubcommand {
+    #[argh(positional, arg_name = "VM_SOCKET")]
+    /// VM Socket path
+    pub socket_path: String,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+pub enum VmwdtSubcommand {
+    Pause(VmwdtPauseSubcommand),
+    Resume(VmwdtResumeSubcommand),
+    SetTimeout(VmwdtSetTimeoutSubcommand),
+    Status(VmwdtStatusSubcommand),
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "vmwdt")]
+/// Inspect and control the virtual watchdog of the vcpus of a crosvm instance
+pub struct VmwdtCommand {
+    #[argh(subcommand)]
+    pub command: VmwdtSubcommand,
+}
+
 #[derive(FromArgs)]
 #[argh(subcommand, name = "device")]
 /// Start a device process
@@ -406,8 +668,12 @@ pub enum DeviceSubcommand {
 #[argh(subcommand)]
 pub enum GpuSubCommand {
     AddDisplays(GpuAddDisplaysCommand),
+    DisplayState(GpuDisplayStateCommand),
     ListDisplays(GpuListDisplaysCommand),
     RemoveDisplays(GpuRemoveDisplaysCommand),
+    SetMode(GpuSetModeCommand),
+    SetRefreshRate(GpuSetRefreshRateCommand),
+    TraceDisplays(GpuTraceDisplaysCommand),
 }
 
 #[cfg(feature = "gpu")]
@@ -424,6 +690,17 @@ pub struct GpuAddDisplaysCommand {
     pub socket_path: String,
 }
 
+#[cfg(feature = "gpu")]
+#[derive(FromArgs)]
+/// Print whether the guest enabled the scanout of each display, its size and the number of frames
+/// presented.
+#[argh(subcommand, name = "display-state")]
+pub struct GpuDisplayStateCommand {
+    #[argh(positional, arg_name = "VM_SOCKET")]
+    /// VM Socket path
+    pub socket_path: String,
+}
+
 #[cfg(feature = "gpu")]
 #[derive(FromArgs)]
 /// List the displays currently attached to the GPU device.
@@ -447,6 +724,48 @@ pub struct GpuRemoveDisplaysCommand {
     pub socket_path: String,
 }
 
+#[cfg(feature = "gpu")]
+#[derive(FromArgs)]
+/// Change the size and parameters of an existing display without detaching it.
+#[argh(subcommand, name = "set-mode")]
+pub struct GpuSetModeCommand {
+    #[argh(option)]
+    /// display id
+    pub display_id: u32,
+    #[argh(option)]
+    /// new parameters of the display
+    pub gpu_display: vm_control::gpu::DisplayParameters,
+    #[argh(positional, arg_name = "VM_SOCKET")]
+    /// VM Socket path
+    pub socket_path: String,
+}
+
+#[cfg(feature = "gpu")]
+#[derive(FromArgs)]
+/// Change the refresh rate of an existing display without resizing it.
+#[argh(subcommand, name = "set-refresh-rate")]
+pub struct GpuSetRefreshRateCommand {
+    #[argh(option)]
+    /// display id
+    pub display_id: u32,
+    #[argh(option)]
+    /// refresh rate in Hz
+    pub refresh_rate: u32,
+    #[argh(positional, arg_name = "VM_SOCKET")]
+    /// VM Socket path
+    pub socket_path: String,
+}
+
+#[cfg(feature = "gpu")]
+#[derive(FromArgs)]
+/// Print the recent EDID and scanout requests handled by the GPU device.
+#[argh(subcommand, name = "trace-displays")]
+pub struct GpuTraceDisplaysCommand {
+    #[argh(positional, arg_name = "VM_SOCKET")]
+    /// VM Socket path
+    pub socket_path: String,
+}
+
 #[derive(FromArgs)]
 #[argh(subcommand)]
 pub enum UsbSubCommand {
@@ -517,6 +836,10 @@ pub struct RunCommand {
     ///         recording. The only supported effect value now is
     ///         EchoCancellation or aec.
     ///     client_type - Set specific client type for cras backend.
+    ///     loopback - Keep the last frames rendered by the
+    ///         win_audio backend for `crosvm snd capture`.
+    ///     loopback_wav=PATH - Also write the frames rendered by
+    ///         the win_audio backend to a WAV file. Implies loopback.
     ///     socket_type - Set specific socket type for cras backend.
     pub ac97: Vec<Ac97Parameters>,
     #[argh(option, long = "acpi-table", arg_name = "PATH")]
@@ -536,17 +859,33 @@ pub struct RunCommand {
     pub balloon_page_reporting: bool,
     #[argh(option)]
     /// comma separated key=value pairs for setting up battery
-    /// device
+    /// device. Can be given more than once to add several
+    /// batteries.
     /// Possible key values:
     ///     type=goldfish - type of battery emulation, defaults to
     ///     goldfish
-    pub battery: Option<BatteryConfig>,
+    pub battery: Vec<BatteryConfig>,
     #[argh(option)]
     /// path to BIOS/firmware ROM
     pub bios: Option<PathBuf>,
     #[argh(option, arg_name = "CID")]
     /// context ID for virtual sockets.
     pub cid: Option<u64>,
+    #[argh(
+        option,
+        long = "cluster-affinity",
+        arg_name = "cluster=N,host-cpus=[CPU,...][,rt]",
+        from_str_fn(from_key_values)
+    )]
+    /// pin the vCPUs of a cluster to the given host CPUs (aarch64).
+    /// Possible key values:
+    ///     cluster=N - index of the cluster, in the order of the
+    ///        --cpu-cluster options.
+    ///     host-cpus=[CPU,...] - the host CPUs the vCPUs of the
+    ///        cluster run on.
+    ///     rt - run the vCPUs of the cluster with real-time
+    ///        priority.
+    pub cluster_affinity: Vec<ClusterAffinityOption>,
     #[cfg(unix)]
     #[argh(
         option,
@@ -563,6 +902,26 @@ pub struct RunCommand {
     ///        pinned page must be busy for to be aged into the
     ///        older which is less frequently checked generation.
     pub coiommu: Option<devices::CoIommuParameters>,
+    #[argh(
+        option,
+        long = "cpu-cache",
+        arg_name = "level=N,type=TYPE,cpus=[CPU,...][,size=N][,line-size=N][,sets=N]",
+        from_str_fn(from_key_values)
+    )]
+    /// describe a cache shared by the given CPUs to the guest
+    /// (default: the host caches with host-cpu-topology, else
+    /// none). Can be given once per cache.
+    /// Possible key values:
+    ///     level=N - level of the cache, 1 for the closest to
+    ///        the CPUs.
+    ///     type=(data,instruction,unified) - only level 1 caches
+    ///        can be data or instruction caches.
+    ///     cpus=[CPU,...] - the CPUs sharing the cache.
+    ///     size=N - size of the cache in bytes. Caches without
+    ///        a size are not described to the guest.
+    ///     line-size=N - size of a cache line in bytes.
+    ///     sets=N - number of sets of the cache.
+    pub cpu_caches: Vec<CpuCache>,
     #[argh(
         option,
         arg_name = "CPU=CAP[,CPU=CAP[,...]]",
@@ -578,6 +937,14 @@ pub struct RunCommand {
     )]
     /// group the given CPUs into a cluster (default: no clusters)
     pub cpu_clusters: Vec<Vec<usize>>,
+    #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
+    #[argh(option, long = "cpu-id", arg_name = "midr=VALUE[,revidr=VALUE]")]
+    /// override the CPU identification registers read by the
+    /// guest kernel. Only EL1-visible reads are affected.
+    /// Possible key values:
+    ///     midr=VALUE - 32-bit MIDR_EL1 value (e.g. 0x410fd083).
+    ///     revidr=VALUE - 32-bit REVIDR_EL1 value.
+    pub cpu_id: Option<CpuIdConfig>,
     #[cfg(feature = "crash-report")]
     #[argh(option, long = "crash-pipe-name", arg_name = "\\\\.\\pipe\\PIPE_NAME")]
     /// the crash handler ipc pipe name.
@@ -585,6 +952,13 @@ pub struct RunCommand {
     #[argh(switch)]
     /// don't set VCPUs real-time until make-rt command is run
     pub delay_rt: bool,
+    #[cfg(target_arch = "aarch64")]
+    #[argh(option, arg_name = "PATH")]
+    /// path to a device tree overlay (DTBO) to apply to the device
+    /// tree generated for the guest. Its fragments must name
+    /// their target with `target-path` and may not modify /chosen
+    /// or /memory.
+    pub device_tree_overlay: Option<PathBuf>,
     #[cfg(feature = "direct")]
     #[argh(option, arg_name = "irq")]
     /// enable interrupt passthrough
@@ -661,12 +1035,25 @@ pub struct RunCommand {
     /// expose Power and Perfomance (PnP) data to guest and guest can show these PnP data
     pub enable_pnp_data: bool,
     #[argh(positional, arg_name = "KERNEL")]
-    /// bzImage of kernel to run
+    /// bzImage of kernel to run. A /proc/self/fd/N path must be
+    /// an inherited memfd.
     pub executable_path: Option<PathBuf>,
     #[cfg(windows)]
     #[argh(switch, long = "exit-stats")]
     /// gather and display statistics on Vm Exits and Bus Reads/Writes.
     pub exit_stats: bool,
+    #[cfg(target_arch = "aarch64")]
+    #[argh(
+        option,
+        arg_name = "end|after-kernel|addr=ADDR",
+        from_str_fn(parse_fdt_position)
+    )]
+    /// where to place the device tree in guest memory:
+    ///     end - near the end of RAM (default)
+    ///     after-kernel - right after the kernel, followed by the
+    ///        initrd
+    ///     addr=ADDR - at guest physical address ADDR
+    pub fdt_position: Option<FdtPosition>,
     #[argh(
         option,
         long = "file-backed-mapping",
@@ -708,6 +1095,16 @@ pub struct RunCommand {
     ///        initially hidden (default: false).
     ///     refresh-rate=INT - Force a specific vsync generation
     ///        rate in hertz on the guest (default: 60)
+    ///     resize-policy=(scale|resize-host-window|reject) - What
+    ///        to do when the guest sets a size other than the
+    ///        display's: scale the image to the window, resize
+    ///        the window to the new size, or refuse the size
+    ///        (default: scale)
+    ///     input=(shared|per-display) - Send the pointer events of
+    ///        the display's window to the touchscreen shared by
+    ///        all displays, or to an absolute pointer device of
+    ///        its own with the size of the display
+    ///        (default: shared)
     #[cfg(unix)]
     pub gpu_display: Vec<GpuDisplayParameters>,
     #[cfg(feature = "gpu")]
@@ -741,6 +1138,13 @@ pub struct RunCommand {
     ///     cache-size=SIZE - The maximum size of the shader cache.
     ///     pci-bar-size=SIZE - The size for the PCI BAR in bytes
     ///        (default 8gb).
+    ///     hotplug-display-inputs=INT - The number of absolute
+    ///        pointer devices to set aside for displays added with
+    ///        input=per-display while the VM runs (default: 0).
+    ///     edid-reread-timeout-ms=INT - How long the guest has to
+    ///        read a changed EDID before its display is
+    ///        disconnected and connected again, 0 to never do it
+    ///        (default: 1000).
     pub gpu_params: Option<devices::virtio::GpuParameters>,
     #[cfg(all(unix, feature = "gpu", feature = "virgl_renderer_next"))]
     #[argh(option, from_str_fn(parse_gpu_render_server_options))]
@@ -773,7 +1177,8 @@ pub struct RunCommand {
     /// amount of guest memory outside the balloon at boot in MiB. (default: --mem)
     pub init_memory: Option<u64>,
     #[argh(option, short = 'i', long = "initrd", arg_name = "PATH")]
-    /// initial ramdisk to load
+    /// initial ramdisk to load. A /proc/self/fd/N path must be an
+    /// inherited memfd.
     pub initrd_path: Option<PathBuf>,
     #[cfg(windows)]
     #[argh(option, long = "irqchip", arg_name = "kernel|split|userspace")]
@@ -806,9 +1211,31 @@ pub struct RunCommand {
     #[argh(option, arg_name = "MAC", long = "mac")]
     /// MAC address for VM
     pub mac_address: Option<net_util::MacAddress>,
+    #[cfg(unix)]
+    #[argh(option, long = "memfd-fallback-dir", arg_name = "PATH")]
+    /// directory, ideally on tmpfs, to create guest memory in if the kernel lacks memfd support.
+    /// (default: /dev/shm)
+    pub memfd_fallback_dir: Option<PathBuf>,
     #[argh(option, long = "mem", short = 'm', arg_name = "N")]
     /// amount of guest memory in MiB. (default: 256)
     pub memory: Option<u64>,
+    #[argh(
+        option,
+        long = "memory-backing-limits",
+        arg_name = "[soft=N][,hard=N]",
+        from_str_fn(from_key_values)
+    )]
+    /// limit the number of files and shared memory objects backing
+    /// guest memory, each of which keeps a descriptor open.
+    /// Possible key values:
+    ///     soft=N - log a warning above N backing objects.
+    ///     hard=N - refuse to add memory regions above N backing
+    ///        objects.
+    pub memory_backing_limits: Option<BackingObjectLimits>,
+    #[cfg(target_arch = "aarch64")]
+    #[argh(switch)]
+    /// publish metrics records to the guest in a page of guest memory
+    pub metrics_page: bool,
     #[argh(
         option,
         long = "mmio-address-range",
@@ -831,10 +1258,20 @@ pub struct RunCommand {
     #[argh(switch)]
     /// don't use virtio-balloon device in the guest
     pub no_balloon: bool,
+    #[cfg(target_arch = "aarch64")]
+    #[argh(switch)]
+    /// don't let the guest's virtual counter advance while the VM
+    /// is suspended, e.g. across host suspend
+    pub no_host_suspend_time: bool,
     #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
     #[argh(switch)]
     /// don't use legacy KBD devices emulation
     pub no_i8042: bool,
+    #[cfg(target_arch = "aarch64")]
+    #[argh(switch)]
+    /// don't expose a PMU to the guest, even if the hypervisor
+    /// supports it
+    pub no_pmu: bool,
     #[argh(switch)]
     /// don't create RNG device in the guest
     pub no_rng: bool,
@@ -921,6 +1358,27 @@ pub struct RunCommand {
     #[argh(option, long = "pmem-device", arg_name = "PATH")]
     /// path to a disk image
     pub pmem_devices: Vec<DiskOption>,
+    #[cfg(target_arch = "aarch64")]
+    #[argh(
+        option,
+        long = "pmem-region",
+        arg_name = "path=PATH,size=SIZE[,reserved-memory]"
+    )]
+    /// map a file as persistent memory above the guest RAM.
+    /// Parameters (path and size are required):
+    ///     path=PATH - path to the backing file, which must be
+    ///        writable and at least SIZE bytes long
+    ///     size=SIZE - size of the region, a multiple of 2 MiB
+    ///     reserved-memory - describe the region to the guest
+    ///        as reserved memory in the device tree instead of
+    ///        with a virtio-pmem device
+    pub pmem_regions: Vec<PmemRegionParameters>,
+    #[cfg(target_arch = "aarch64")]
+    #[argh(switch)]
+    /// expose a PMU to the guest, and fail to start if the
+    /// hypervisor doesn't support it. By default the PMU is
+    /// exposed if the hypervisor supports it.
+    pub pmu: bool,
     #[argh(switch)]
     /// grant this Guest VM certain privileges to manage Host resources, such as power management
     pub privileged_vm: bool,
@@ -1050,6 +1508,9 @@ pub struct RunCommand {
     ///        type=file
     ///     input=PATH - The path to the file to read from when not
     ///        stdin
+    ///     input_rate=BYTES - Most bytes of input passed to the
+    ///        guest per second, as a UART at a given line rate
+    ///        would. Unlimited if not given.
     ///     console - Use this serial device as the guest console.
     ///        Can only be given once. Will default to first
     ///        serial port if not provided.
@@ -1058,6 +1519,19 @@ pub struct RunCommand {
     ///     stdin - Direct standard input to this serial device.
     ///        Can only be given once. Will default to first serial
     ///        port if not provided.
+    ///     output_policy=(drop,flow-control) - What a serial
+    ///        (8250 UART) device does with guest output when the
+    ///        output is written slower than the guest produces it.
+    ///        drop discards it, flow-control reports the
+    ///        transmitter as busy so the guest waits. Defaults to
+    ///        drop.
+    ///     console_buffer=BYTES - Keep the last BYTES of output of a
+    ///        serial (8250 UART) device in memory, to be printed
+    ///        with `crosvm serial_buffer`. Disabled by default.
+    ///     watch_resize - Report the size of the terminal the
+    ///        output goes to, e.g. a PTY given as stdout or file,
+    ///        to the guest and follow its resizes. Only supported
+    ///        by virtio-console devices.
     pub serial_parameters: Vec<SerialParameters>,
     #[cfg(feature = "kiwi")]
     #[argh(option, long = "service-pipe-name", arg_name = "PIPE_NAME")]
@@ -1136,8 +1610,18 @@ pub struct RunCommand {
     /// (EXPERIMENTAL) enable split-irqchip support
     pub split_irqchip: bool,
     #[argh(switch)]
+    /// start the VM with its vcpus suspended, e.g. to change the
+    /// kernel command line with `crosvm set_kernel_cmdline`, until
+    /// `crosvm resume` is called
+    pub start_paused: bool,
+    #[argh(switch)]
     /// don't allow guest to use pages from the balloon
     pub strict_balloon: bool,
+    #[argh(switch)]
+    /// fail to start the VM if the interrupt of an optional
+    /// device (battery, RTC, serial ports) can't be set up, instead
+    /// of running the device without an interrupt
+    pub strict_irqs: bool,
     #[argh(
         option,
         long = "stub-pci-device",
@@ -1174,6 +1658,18 @@ pub struct RunCommand {
     #[argh(option, arg_name = "NAME[,...]")]
     /// comma-separated names of the task profiles to apply to all threads in crosvm including the vCPU threads
     pub task_profiles: Vec<String>,
+    #[argh(
+        option,
+        arg_name = "ignore|log|fault",
+        from_str_fn(parse_unmapped_mmio)
+    )]
+    /// what to do when the guest accesses an MMIO address without a
+    /// device:
+    ///     ignore - reads return zero and writes are dropped (default)
+    ///     log - like ignore, and log the access (rate limited)
+    ///     fault - like log, and inject an external abort into the
+    ///        guest (aarch64 only)
+    pub unmapped_mmio: Option<UnmappedAccessPolicy>,
     // Must be `Some` iff `protection_type == ProtectionType::UnprotectedWithFirmware`.
     #[argh(option, long = "unprotected-vm-with-firmware", arg_name = "PATH")]
     /// (EXPERIMENTAL/FOR DEBUGGING) Use VM firmware, but allow host access to guest memory
@@ -1333,6 +1829,10 @@ pub struct RunCommand {
     #[argh(option, long = "trackpad", arg_name = "PATH:WIDTH:HEIGHT")]
     /// path to a socket from where to read trackpad input events and write status updates to, optionally followed by screen width and height (defaults to 800x1280)
     pub virtio_trackpad: Vec<TouchDeviceOption>,
+    #[argh(option, arg_name = "UUID")]
+    /// UUID identifying the VM to the guest and to clients of the
+    /// control socket. (default: randomly generated)
+    pub vm_uuid: Option<Uuid>,
     #[cfg(all(feature = "vtpm", target_arch = "x86_64"))]
     #[argh(switch)]
     /// enable the virtio-tpm connection to vtpm daemon
@@ -1396,9 +1896,37 @@ impl TryFrom<RunCommand> for super::config::Config {
 
         cfg.vcpu_count = cmd.vcpu_count;
 
+        if let Some(vm_uuid) = cmd.vm_uuid {
+            cfg.vm_uuid = vm_uuid;
+        }
+
         cfg.vcpu_affinity = cmd.vcpu_affinity;
 
         cfg.cpu_clusters = cmd.cpu_clusters;
+        for affinity in cmd.cluster_affinity {
+            let cluster = affinity.cluster;
+            if cfg
+                .cluster_affinity
+                .insert(
+                    cluster,
+                    ClusterAffinity {
+                        host_cpus: affinity.host_cpus,
+                        rt: affinity.rt,
+                    },
+                )
+                .is_some()
+            {
+                return Err(format!(
+                    "`cluster-affinity` given twice for cluster {}",
+                    cluster
+                ));
+            }
+        }
+        cfg.cpu_caches = cmd.cpu_caches;
+        #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
+        if let Some(cpu_id) = cmd.cpu_id {
+            cfg.cpu_id = cpu_id;
+        }
 
         if let Some(capacity) = cmd.cpu_capacity {
             cfg.cpu_capacity = capacity;
@@ -1415,6 +1943,9 @@ impl TryFrom<RunCommand> for super::config::Config {
         cfg.delay_rt = cmd.delay_rt;
 
         cfg.memory = cmd.memory;
+        if let Some(limits) = cmd.memory_backing_limits {
+            cfg.memory_backing_limits = limits;
+        }
 
         #[cfg(target_arch = "aarch64")]
         {
@@ -1424,7 +1955,19 @@ impl TryFrom<RunCommand> for super::config::Config {
                         .to_string(),
                 );
             }
+            cfg.device_tree_overlay = cmd.device_tree_overlay;
+            cfg.fdt_position = cmd.fdt_position.unwrap_or_default();
+            cfg.metrics_page = cmd.metrics_page;
             cfg.mte = cmd.mte;
+            cfg.no_host_suspend_time = cmd.no_host_suspend_time;
+            cfg.pmu = match (cmd.pmu, cmd.no_pmu) {
+                (true, true) => {
+                    return Err("`pmu` and `no-pmu` can't be given together".to_string())
+                }
+                (true, false) => Some(true),
+                (false, true) => Some(false),
+                (false, false) => None,
+            };
             cfg.swiotlb = cmd.swiotlb;
         }
 
@@ -1435,6 +1978,7 @@ impl TryFrom<RunCommand> for super::config::Config {
         #[cfg(unix)]
         {
             cfg.lock_guest_memory = cmd.lock_guest_memory;
+            cfg.memfd_fallback_dir = cmd.memfd_fallback_dir;
         }
 
         #[cfg(feature = "audio")]
@@ -1897,9 +2441,17 @@ impl TryFrom<RunCommand> for super::config::Config {
 
         cfg.file_backed_mappings = cmd.file_backed_mappings;
 
+        #[cfg(target_arch = "aarch64")]
+        {
+            cfg.pmem_regions = cmd.pmem_regions;
+        }
+
         cfg.init_memory = cmd.init_memory;
 
+        cfg.start_paused = cmd.start_paused;
         cfg.strict_balloon = cmd.strict_balloon;
+        cfg.strict_irqs = cmd.strict_irqs;
+        cfg.unmapped_mmio = cmd.unmapped_mmio.unwrap_or_default();
 
         #[cfg(target_os = "android")]
         {
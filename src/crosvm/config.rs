@@ -10,7 +10,11 @@ use std::str::FromStr;
 use std::sync::atomic::AtomicUsize;
 use std::sync::atomic::Ordering;
 
+use arch::cpu_cache::check_cpu_caches;
+use arch::cpu_cache::CpuCache;
 use arch::set_default_serial_parameters;
+use arch::ClusterAffinity;
+use arch::FdtPosition;
 use arch::MsrAction;
 use arch::MsrConfig;
 use arch::MsrFilter;
@@ -39,6 +43,7 @@ use devices::PciAddress;
 use devices::PciClassCode;
 use devices::PflashParameters;
 use devices::StubPciParameters;
+use devices::UnmappedAccessPolicy;
 use hypervisor::ProtectionType;
 use resources::AddressRange;
 use serde::Deserialize;
@@ -46,6 +51,7 @@ use serde::Serialize;
 use serde_keyvalue::FromKeyValues;
 use uuid::Uuid;
 use vm_control::BatteryType;
+use vm_memory::BackingObjectLimits;
 #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
 use x86_64::set_enable_pnp_data_msr_config;
 
@@ -509,6 +515,17 @@ pub struct FileBackedMappingParameters {
     pub align: bool,
 }
 
+/// A file mapped as persistent memory at an address chosen by the guest memory layout.
+#[cfg(target_arch = "aarch64")]
+#[derive(Debug, Serialize, Deserialize, FromKeyValues)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct PmemRegionParameters {
+    pub path: PathBuf,
+    pub size: u64,
+    #[serde(default)]
+    pub reserved_memory: bool,
+}
+
 #[derive(Clone, Deserialize, Serialize)]
 pub struct HostPcieRootPortParameters {
     pub host_path: PathBuf,
@@ -609,6 +626,35 @@ pub fn parse_pstore(value: &str) -> Result<Pstore, String> {
     })
 }
 
+#[cfg(target_arch = "aarch64")]
+pub fn parse_fdt_position(value: &str) -> Result<FdtPosition, String> {
+    match value {
+        "end" => Ok(FdtPosition::End),
+        "after-kernel" => Ok(FdtPosition::AfterKernel),
+        _ => match value.strip_prefix("addr=") {
+            Some(addr) => parse_hex_or_decimal(addr)
+                .map(FdtPosition::Address)
+                .map_err(|_| invalid_value_err(addr, "expected u64 value")),
+            None => Err(invalid_value_err(
+                value,
+                "expected `end`, `after-kernel` or `addr=ADDR`",
+            )),
+        },
+    }
+}
+
+pub fn parse_unmapped_mmio(value: &str) -> Result<UnmappedAccessPolicy, String> {
+    match value {
+        "ignore" => Ok(UnmappedAccessPolicy::Ignore),
+        "log" => Ok(UnmappedAccessPolicy::Log),
+        "fault" => Ok(UnmappedAccessPolicy::Fault),
+        _ => Err(invalid_value_err(
+            value,
+            "expected `ignore`, `log` or `fault`",
+        )),
+    }
+}
+
 #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
 pub fn parse_userspace_msr_options(value: &str) -> Result<(u32, MsrConfig), String> {
     let mut rw_type: Option<MsrRWType> = None;
@@ -905,6 +951,28 @@ pub struct BatteryConfig {
     pub type_: BatteryType,
 }
 
+/// Placement of the vcpus of a `--cpu-cluster` on host CPUs.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, FromKeyValues)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ClusterAffinityOption {
+    /// Index of the cluster, in the order of the `--cpu-cluster` options.
+    pub cluster: usize,
+    pub host_cpus: Vec<usize>,
+    #[serde(default)]
+    pub rt: bool,
+}
+
+/// Identification register values reported to an AArch64 guest instead of the host's.
+#[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq, FromKeyValues)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct CpuIdConfig {
+    #[serde(default)]
+    pub midr: Option<u32>,
+    #[serde(default)]
+    pub revidr: Option<u32>,
+}
+
 pub fn parse_cpu_capacity(s: &str) -> Result<BTreeMap<usize, u32>, String> {
     let mut cpu_capacity: BTreeMap<usize, u32> = BTreeMap::default();
     for cpu_pair in s.split(',') {
@@ -1150,7 +1218,7 @@ pub struct Config {
     pub balloon_bias: i64,
     pub balloon_control: Option<PathBuf>,
     pub balloon_page_reporting: bool,
-    pub battery_config: Option<BatteryConfig>,
+    pub battery_config: Vec<BatteryConfig>,
     #[cfg(windows)]
     pub block_control_tube: Vec<Tube>,
     #[cfg(windows)]
@@ -1158,15 +1226,20 @@ pub struct Config {
     #[cfg(windows)]
     pub broker_shutdown_event: Option<Event>,
     pub cid: Option<u64>,
+    pub cluster_affinity: BTreeMap<usize, ClusterAffinity>,
     #[cfg(unix)]
     pub coiommu_param: Option<devices::CoIommuParameters>,
+    pub cpu_caches: Vec<CpuCache>,
     pub cpu_capacity: BTreeMap<usize, u32>, // CPU index -> capacity
     pub cpu_clusters: Vec<Vec<usize>>,
+    #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
+    pub cpu_id: CpuIdConfig,
     #[cfg(feature = "crash-report")]
     pub crash_pipe_name: Option<String>,
     #[cfg(feature = "crash-report")]
     pub crash_report_uuid: Option<String>,
     pub delay_rt: bool,
+    pub device_tree_overlay: Option<PathBuf>,
     #[cfg(feature = "direct")]
     pub direct_edge_irq: Vec<u32>,
     #[cfg(feature = "direct")]
@@ -1189,6 +1262,7 @@ pub struct Config {
     pub executable_path: Option<Executable>,
     #[cfg(windows)]
     pub exit_stats: bool,
+    pub fdt_position: FdtPosition,
     pub file_backed_mappings: Vec<FileBackedMappingParameters>,
     pub force_calibrated_tsc_leaf: bool,
     pub force_s2idle: bool,
@@ -1221,8 +1295,12 @@ pub struct Config {
     #[cfg(windows)]
     pub logs_directory: Option<String>,
     pub mac_address: Option<net_util::MacAddress>,
+    #[cfg(unix)]
+    pub memfd_fallback_dir: Option<PathBuf>,
     pub memory: Option<u64>,
+    pub memory_backing_limits: BackingObjectLimits,
     pub memory_file: Option<PathBuf>,
+    pub metrics_page: bool,
     pub mmio_address_ranges: Vec<AddressRange>,
     #[cfg(target_arch = "aarch64")]
     pub mte: bool,
@@ -1230,6 +1308,8 @@ pub struct Config {
     pub net_vhost_user_tube: Option<Tube>,
     pub net_vq_pairs: Option<u16>,
     pub netmask: Option<net::Ipv4Addr>,
+    #[cfg(target_arch = "aarch64")]
+    pub no_host_suspend_time: bool,
     pub no_i8042: bool,
     pub no_rtc: bool,
     pub no_smt: bool,
@@ -1249,6 +1329,10 @@ pub struct Config {
     pub plugin_mounts: Vec<BindMount>,
     pub plugin_root: Option<PathBuf>,
     pub pmem_devices: Vec<DiskOption>,
+    #[cfg(target_arch = "aarch64")]
+    pub pmem_regions: Vec<PmemRegionParameters>,
+    #[cfg(target_arch = "aarch64")]
+    pub pmu: Option<bool>,
     pub privileged_vm: bool,
     #[cfg(feature = "process-invariants")]
     pub process_invariants_data_handle: Option<u64>,
@@ -1283,7 +1367,9 @@ pub struct Config {
     #[cfg(feature = "audio")]
     pub sound: Option<PathBuf>,
     pub split_irqchip: bool,
+    pub start_paused: bool,
     pub strict_balloon: bool,
+    pub strict_irqs: bool,
     pub stub_pci_devices: Vec<StubPciParameters>,
     pub swiotlb: Option<u64>,
     #[cfg(windows)]
@@ -1293,6 +1379,7 @@ pub struct Config {
     pub tap_name: Vec<String>,
     #[cfg(target_os = "android")]
     pub task_profiles: Vec<String>,
+    pub unmapped_mmio: UnmappedAccessPolicy,
     pub usb: bool,
     pub userspace_msr: BTreeMap<u32, MsrConfig>,
     pub vcpu_affinity: Option<VcpuAffinity>,
@@ -1331,6 +1418,7 @@ pub struct Config {
     pub virtio_snds: Vec<SndParameters>,
     pub virtio_switches: Vec<PathBuf>,
     pub virtio_trackpad: Vec<TouchDeviceOption>,
+    pub vm_uuid: Uuid,
     #[cfg(all(feature = "vtpm", target_arch = "x86_64"))]
     pub vtpm_proxy: bool,
     pub vvu_proxy: Vec<VvuOption>,
@@ -1349,7 +1437,7 @@ impl Default for Config {
             balloon_bias: 0,
             balloon_control: None,
             balloon_page_reporting: false,
-            battery_config: None,
+            battery_config: Vec::new(),
             #[cfg(windows)]
             block_control_tube: Vec::new(),
             #[cfg(windows)]
@@ -1357,15 +1445,20 @@ impl Default for Config {
             #[cfg(windows)]
             broker_shutdown_event: None,
             cid: None,
+            cluster_affinity: BTreeMap::new(),
             #[cfg(unix)]
             coiommu_param: None,
             #[cfg(feature = "crash-report")]
             crash_pipe_name: None,
             #[cfg(feature = "crash-report")]
             crash_report_uuid: None,
+            cpu_caches: Vec::new(),
             cpu_capacity: BTreeMap::new(),
             cpu_clusters: Vec::new(),
+            #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
+            cpu_id: Default::default(),
             delay_rt: false,
+            device_tree_overlay: None,
             #[cfg(feature = "direct")]
             direct_edge_irq: Vec::new(),
             #[cfg(feature = "direct")]
@@ -1388,6 +1481,7 @@ impl Default for Config {
             executable_path: None,
             #[cfg(windows)]
             exit_stats: false,
+            fdt_position: Default::default(),
             file_backed_mappings: Vec::new(),
             force_calibrated_tsc_leaf: false,
             force_s2idle: false,
@@ -1428,8 +1522,12 @@ impl Default for Config {
             #[cfg(windows)]
             logs_directory: None,
             mac_address: None,
+            #[cfg(unix)]
+            memfd_fallback_dir: None,
             memory: None,
+            memory_backing_limits: Default::default(),
             memory_file: None,
+            metrics_page: false,
             mmio_address_ranges: Vec::new(),
             #[cfg(target_arch = "aarch64")]
             mte: false,
@@ -1437,6 +1535,8 @@ impl Default for Config {
             net_vhost_user_tube: None,
             net_vq_pairs: None,
             netmask: None,
+            #[cfg(target_arch = "aarch64")]
+            no_host_suspend_time: false,
             no_i8042: false,
             no_rtc: false,
             no_smt: false,
@@ -1456,6 +1556,10 @@ impl Default for Config {
             plugin_mounts: Vec::new(),
             plugin_root: None,
             pmem_devices: Vec::new(),
+            #[cfg(target_arch = "aarch64")]
+            pmem_regions: Vec::new(),
+            #[cfg(target_arch = "aarch64")]
+            pmu: None,
             privileged_vm: false,
             #[cfg(feature = "process-invariants")]
             process_invariants_data_handle: None,
@@ -1483,7 +1587,9 @@ impl Default for Config {
             #[cfg(feature = "audio")]
             sound: None,
             split_irqchip: false,
+            start_paused: false,
             strict_balloon: false,
+            strict_irqs: false,
             stub_pci_devices: Vec::new(),
             swiotlb: None,
             #[cfg(windows)]
@@ -1493,6 +1599,7 @@ impl Default for Config {
             tap_name: Vec::new(),
             #[cfg(target_os = "android")]
             task_profiles: Vec::new(),
+            unmapped_mmio: Default::default(),
             usb: true,
             userspace_msr: BTreeMap::new(),
             vcpu_affinity: None,
@@ -1530,6 +1637,7 @@ impl Default for Config {
             virtio_snds: Vec::new(),
             virtio_switches: Vec::new(),
             virtio_trackpad: Vec::new(),
+            vm_uuid: Uuid::new_v4(),
             #[cfg(all(feature = "vtpm", target_arch = "x86_64"))]
             vtpm_proxy: false,
             vvu_proxy: Vec::new(),
@@ -1556,6 +1664,10 @@ pub fn validate_config(cfg: &mut Config) -> std::result::Result<(), String> {
     if cfg.gdb.is_some() && cfg.vcpu_count.unwrap_or(1) != 1 {
         return Err("`gdb` requires the number of vCPU to be 1".to_string());
     }
+    #[cfg(not(target_arch = "aarch64"))]
+    if cfg.unmapped_mmio == UnmappedAccessPolicy::Fault {
+        return Err("`unmapped-mmio=fault` is only supported on aarch64".to_string());
+    }
     if cfg.host_cpu_topology {
         if cfg.no_smt {
             return Err(
@@ -1664,6 +1776,13 @@ pub fn validate_config(cfg: &mut Config) -> std::result::Result<(), String> {
         }
     }
 
+    check_cpu_caches(
+        &cfg.cpu_caches,
+        cfg.vcpu_count.unwrap_or(1),
+        &cfg.cpu_clusters,
+    )
+    .map_err(|e| format!("invalid `cpu-cache`: {}", e))?;
+
     if !cfg.balloon && cfg.balloon_control.is_some() {
         return Err("'balloon-control' requires enabled balloon".to_string());
     }
@@ -1805,6 +1924,47 @@ mod tests {
     }
 
     #[cfg(feature = "audio_cras")]
+    #[cfg(target_arch = "aarch64")]
+    #[test]
+    fn parse_fdt_position_valid() {
+        assert_eq!(parse_fdt_position("end"), Ok(FdtPosition::End));
+        assert_eq!(
+            parse_fdt_position("after-kernel"),
+            Ok(FdtPosition::AfterKernel)
+        );
+        assert_eq!(
+            parse_fdt_position("addr=0x80400000"),
+            Ok(FdtPosition::Address(0x8040_0000))
+        );
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    #[test]
+    fn parse_fdt_position_invalid() {
+        assert!(parse_fdt_position("start").is_err());
+        assert!(parse_fdt_position("addr=").is_err());
+        assert!(parse_fdt_position("addr=foo").is_err());
+    }
+
+    #[test]
+    fn parse_unmapped_mmio_valid() {
+        assert_eq!(
+            parse_unmapped_mmio("ignore"),
+            Ok(UnmappedAccessPolicy::Ignore)
+        );
+        assert_eq!(parse_unmapped_mmio("log"), Ok(UnmappedAccessPolicy::Log));
+        assert_eq!(
+            parse_unmapped_mmio("fault"),
+            Ok(UnmappedAccessPolicy::Fault)
+        );
+    }
+
+    #[test]
+    fn parse_unmapped_mmio_invalid() {
+        assert!(parse_unmapped_mmio("abort").is_err());
+        assert!(parse_unmapped_mmio("").is_err());
+    }
+
     #[test]
     fn parse_ac97_vaild() {
         parse_ac97_options("backend=cras").expect("parse should have succeded");
@@ -1991,6 +2151,112 @@ mod tests {
         from_key_values::<BatteryConfig>("type=xxx").expect_err("parse should have failed");
     }
 
+    #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
+    #[test]
+    fn parse_cpu_id() {
+        let cpu_id: CpuIdConfig = from_key_values("midr=0x410fd083").unwrap();
+        assert_eq!(
+            cpu_id,
+            CpuIdConfig {
+                midr: Some(0x410fd083),
+                revidr: None,
+            }
+        );
+        let cpu_id: CpuIdConfig = from_key_values("midr=0x410fd083,revidr=0x1").unwrap();
+        assert_eq!(cpu_id.revidr, Some(1));
+        // Both registers are 32 bits wide.
+        from_key_values::<CpuIdConfig>("midr=0x1410fd083").expect_err("parse should have failed");
+        from_key_values::<CpuIdConfig>("mpidr=0x0").expect_err("parse should have failed");
+    }
+
+    #[test]
+    fn parse_memory_backing_limits() {
+        let limits: BackingObjectLimits = from_key_values("soft=256,hard=512").unwrap();
+        assert_eq!(limits.soft, Some(256));
+        assert_eq!(limits.hard, Some(512));
+        let limits: BackingObjectLimits = from_key_values("hard=512").unwrap();
+        assert_eq!(limits.soft, None);
+        from_key_values::<BackingObjectLimits>("max=512").expect_err("parse should have failed");
+    }
+
+    #[test]
+    fn parse_vm_uuid() {
+        let config: Config = crate::crosvm::cmdline::RunCommand::from_args(
+            &[],
+            &[
+                "--vm-uuid",
+                "23546c3d-962d-4ebc-94d9-4acf50996944",
+                "/dev/null",
+            ],
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+        assert_eq!(
+            config.vm_uuid,
+            Uuid::parse_str("23546c3d-962d-4ebc-94d9-4acf50996944").unwrap()
+        );
+
+        crate::crosvm::cmdline::RunCommand::from_args(&[], &["--vm-uuid", "vm0", "/dev/null"])
+            .expect_err("parse should have failed");
+    }
+
+    #[test]
+    fn parse_cpu_caches() {
+        let run = |caches: &[&str]| -> Result<Config, String> {
+            let mut args = vec![
+                "--cpus",
+                "4",
+                "--cpu-cluster",
+                "0,1",
+                "--cpu-cluster",
+                "2,3",
+            ];
+            for cache in caches {
+                args.extend(["--cpu-cache", *cache]);
+            }
+            args.push("/dev/null");
+            crate::crosvm::cmdline::RunCommand::from_args(&[], &args)
+                .unwrap()
+                .try_into()
+        };
+
+        let config: Config = run(&[
+            "level=1,type=data,size=32768,line-size=64,cpus=[0]",
+            "level=2,type=unified,size=1048576,sets=1024,cpus=[0,1]",
+        ])
+        .unwrap();
+        assert_eq!(
+            config.cpu_caches[1],
+            CpuCache {
+                level: 2,
+                cache_type: arch::cpu_cache::CpuCacheType::Unified,
+                size: Some(1048576),
+                line_size: None,
+                sets: Some(1024),
+                cpus: vec![0, 1],
+            }
+        );
+
+        run(&["level=2,type=unified,size=1048576,cpus=[1,2]"])
+            .expect_err("cache splitting clusters should have been rejected");
+        run(&["level=2,type=unified,cpus=[0,4]"])
+            .expect_err("cache of a missing vcpu should have been rejected");
+    }
+
+    #[test]
+    fn vm_uuid_generated() {
+        let run = || -> Config {
+            crate::crosvm::cmdline::RunCommand::from_args(&[], &["/dev/null"])
+                .unwrap()
+                .try_into()
+                .unwrap()
+        };
+        let uuid = run().vm_uuid;
+        assert!(!uuid.is_nil());
+        assert_ne!(uuid, run().vm_uuid);
+    }
+
     #[test]
     fn parse_stub_pci() {
         let params = parse_stub_pci_parameters("0000:01:02.3,vendor=0xfffe,device=0xfffd,class=0xffc1c2,subsystem_vendor=0xfffc,subsystem_device=0xfffb,revision=0xa").unwrap();
@@ -2053,6 +2319,42 @@ mod tests {
             .contains("invalid base range value"));
     }
 
+    #[cfg(target_arch = "aarch64")]
+    #[test]
+    fn parse_pmem_region() {
+        let params =
+            from_key_values::<PmemRegionParameters>("path=/tmp/pmem,size=0x200000").unwrap();
+        assert_eq!(params.path, PathBuf::from("/tmp/pmem"));
+        assert_eq!(params.size, 0x200000);
+        assert!(!params.reserved_memory);
+
+        let params =
+            from_key_values::<PmemRegionParameters>("path=/tmp/pmem,size=0x200000,reserved-memory")
+                .unwrap();
+        assert!(params.reserved_memory);
+
+        assert!(from_key_values::<PmemRegionParameters>("path=/tmp/pmem").is_err());
+        assert!(from_key_values::<PmemRegionParameters>("size=0x200000").is_err());
+        assert!(from_key_values::<PmemRegionParameters>(
+            "path=/tmp/pmem,size=0x200000,reserved_memory"
+        )
+        .is_err());
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    #[test]
+    fn parse_pmu() {
+        let run = |args: &[&str]| -> std::result::Result<Config, String> {
+            crate::crosvm::cmdline::RunCommand::from_args(&[], args)
+                .unwrap()
+                .try_into()
+        };
+        assert_eq!(run(&["/dev/null"]).unwrap().pmu, None);
+        assert_eq!(run(&["--pmu", "/dev/null"]).unwrap().pmu, Some(true));
+        assert_eq!(run(&["--no-pmu", "/dev/null"]).unwrap().pmu, Some(false));
+        assert!(run(&["--pmu", "--no-pmu", "/dev/null"]).is_err());
+    }
+
     #[test]
     fn parse_file_backed_mapping_valid() {
         let params = from_key_values::<FileBackedMappingParameters>(
@@ -147,7 +147,7 @@ impl GdbStub {
         let vm_tube = self.vm_tube.lock();
         vm_tube.send(&request).map_err(Error::VmRequest)?;
         match vm_tube.recv() {
-            Ok(VmResponse::Ok) => Ok(()),
+            Ok(VmResponse::Ok) | Ok(VmResponse::RunStateChanged { .. }) => Ok(()),
             Ok(r) => Err(Error::UnexpectedVmResponse(r)),
             Err(e) => Err(Error::VmResponse(e)),
         }
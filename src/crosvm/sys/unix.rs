@@ -11,6 +11,7 @@ mod device_helpers;
 pub(crate) mod gpu;
 pub(crate) mod jail_helpers;
 mod vcpu;
+mod vcpu_pause;
 
 use std::cmp::max;
 use std::cmp::Reverse;
@@ -33,8 +34,9 @@ use std::sync::Arc;
 use std::sync::Barrier;
 #[cfg(any(target_arch = "x86_64", feature = "gdb"))]
 use std::thread;
-#[cfg(feature = "balloon")]
+use std::thread::JoinHandle;
 use std::time::Duration;
+use std::time::Instant;
 
 #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
 use aarch64::AArch64 as Arch;
@@ -75,6 +77,8 @@ use devices::virtio::BalloonFeatures;
 use devices::virtio::BalloonMode;
 #[cfg(feature = "gpu")]
 use devices::virtio::EventDevice;
+#[cfg(feature = "gpu")]
+use devices::virtio::EventDeviceDisplay;
 use devices::virtio::VirtioTransportType;
 #[cfg(feature = "audio")]
 use devices::Ac97Dev;
@@ -87,6 +91,7 @@ use devices::HostHotPlugKey;
 #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
 use devices::HotPlugBus;
 use devices::IommuDevType;
+use devices::IrqChip;
 #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
 use devices::IrqChipAArch64 as IrqChipArch;
 #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
@@ -127,9 +132,12 @@ use gpu::*;
 use hypervisor::kvm::Kvm;
 use hypervisor::kvm::KvmVcpu;
 use hypervisor::kvm::KvmVm;
+#[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
+use hypervisor::CpuConfigAArch64;
 #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
 use hypervisor::CpuConfigX86_64;
 use hypervisor::HypervisorCap;
+use hypervisor::MemCacheType;
 use hypervisor::ProtectionType;
 #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
 use hypervisor::VcpuAArch64 as VcpuArch;
@@ -150,12 +158,23 @@ use resources::Alloc;
 use resources::Error as ResourceError;
 use resources::SystemAllocator;
 use rutabaga_gfx::RutabagaGralloc;
+use serde::Serialize;
 use sync::Condvar;
 use sync::Mutex;
+#[cfg(feature = "gpu")]
+use vm_control::gpu::DisplayInput;
+#[cfg(feature = "gpu")]
+use vm_control::gpu::DisplayTracker;
+#[cfg(feature = "gpu")]
+use vm_control::gpu::DEFAULT_DISPLAY_HEIGHT;
+#[cfg(feature = "gpu")]
+use vm_control::gpu::DEFAULT_DISPLAY_WIDTH;
 use vm_control::*;
 use vm_memory::GuestAddress;
 use vm_memory::GuestMemory;
 use vm_memory::MemoryPolicy;
+#[cfg(target_arch = "aarch64")]
+use vm_memory::MemoryRegion;
 #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
 use x86_64::msr::get_override_msr_list;
 #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
@@ -309,6 +328,49 @@ fn create_virtio_devices(
                 event_devices.push(EventDevice::keyboard(event_device_socket));
             }
 
+            // Absolute pointer devices of the displays with `input=per-display`, followed by the
+            // spares for such displays added while the VM runs. Their indices count down from
+            // below the one of the shared touchscreen.
+            let display_inputs = gpu_parameters
+                .display_params
+                .iter()
+                .enumerate()
+                .filter(|(_, display_param)| display_param.input == DisplayInput::PerDisplay)
+                .map(|(display_index, display_param)| {
+                    (
+                        EventDeviceDisplay::Display(display_index as u32),
+                        display_param.get_virtual_display_size(),
+                    )
+                })
+                .chain((0..gpu_parameters.hotplug_display_inputs).map(|_| {
+                    (
+                        EventDeviceDisplay::Unassigned,
+                        (DEFAULT_DISPLAY_WIDTH, DEFAULT_DISPLAY_HEIGHT),
+                    )
+                }));
+            for (input_index, (display, (width, height))) in display_inputs.enumerate() {
+                let (event_device_socket, virtio_dev_socket) =
+                    StreamChannel::pair(BlockingMode::Nonblocking, FramingMode::Byte)
+                        .context("failed to create socket")?;
+                let dev = virtio::new_multi_touch(
+                    u32::MAX - 1 - input_index as u32,
+                    virtio_dev_socket,
+                    width,
+                    height,
+                    virtio::base_features(cfg.protection_type),
+                )
+                .context("failed to set up display input device")?;
+                devs.push(VirtioDeviceStub {
+                    dev: Box::new(dev),
+                    jail: simple_jail(&cfg.jail_config, "input_device")?,
+                });
+                event_devices.push(
+                    EventDevice::touchscreen(event_device_socket)
+                        .with_display(display)
+                        .with_abs_size(width, height),
+                );
+            }
+
             devs.push(create_gpu_device(
                 cfg,
                 vm_evt_wrtube,
@@ -364,6 +426,26 @@ fn create_virtio_devices(
         )?);
     }
 
+    #[cfg(target_arch = "aarch64")]
+    {
+        let layout =
+            Arch::pmem_region_layout(&pmem_regions(cfg)).context("invalid pmem regions")?;
+        for (params, range) in cfg.pmem_regions.iter().zip(layout) {
+            if params.reserved_memory {
+                continue;
+            }
+            let pmem_device_tube = pmem_device_tubes.remove(0);
+            devs.push(create_pmem_region_device(
+                cfg.protection_type,
+                &cfg.jail_config,
+                vm,
+                params,
+                GuestAddress(range.start),
+                pmem_device_tube,
+            )?);
+        }
+    }
+
     if cfg.rng {
         devs.push(create_rng_device(cfg.protection_type, &cfg.jail_config)?);
     }
@@ -941,6 +1023,7 @@ fn create_file_backed_mappings(
             Box::new(memory_mapping),
             !mapping.writable,
             /* log_dirty_pages = */ false,
+            MemCacheType::Cached,
         )
         .context("failed to configure file-backed mapping")?;
     }
@@ -1079,12 +1162,23 @@ fn create_pcie_root_port(
     Ok(())
 }
 
+/// Opens the `name` image at `path`, e.g. the kernel, for loading into guest memory.
+///
+/// An image passed as an inherited descriptor with a `/proc/self/fd/N` path must be a memfd, as
+/// passed by launchers holding the image in memory, e.g. sealed for attestation.
+fn open_boot_image(path: &Path, name: &str) -> Result<File> {
+    let file = open_file(path, OpenOptions::new().read(true))
+        .with_context(|| format!("failed to open {} {}", name, path.display()))?;
+    if path.parent() == Some(Path::new("/proc/self/fd")) {
+        memfd_seals(&file)
+            .with_context(|| format!("{} {} is not a memfd", name, path.display()))?;
+    }
+    Ok(file)
+}
+
 fn setup_vm_components(cfg: &Config) -> Result<VmComponents> {
     let initrd_image = if let Some(initrd_path) = &cfg.initrd_path {
-        Some(
-            open_file(initrd_path, OpenOptions::new().read(true))
-                .with_context(|| format!("failed to open initrd {}", initrd_path.display()))?,
-        )
+        Some(open_boot_image(initrd_path, "initrd")?)
     } else {
         None
     };
@@ -1098,11 +1192,9 @@ fn setup_vm_components(cfg: &Config) -> Result<VmComponents> {
     };
 
     let vm_image = match cfg.executable_path {
-        Some(Executable::Kernel(ref kernel_path)) => VmImage::Kernel(
-            open_file(kernel_path, OpenOptions::new().read(true)).with_context(|| {
-                format!("failed to open kernel image {}", kernel_path.display())
-            })?,
-        ),
+        Some(Executable::Kernel(ref kernel_path)) => {
+            VmImage::Kernel(open_boot_image(kernel_path, "kernel image")?)
+        }
         Some(Executable::Bios(ref bios_path)) => VmImage::Bios(
             open_file(bios_path, OpenOptions::new().read(true))
                 .with_context(|| format!("failed to open bios {}", bios_path.display()))?,
@@ -1148,11 +1240,20 @@ fn setup_vm_components(cfg: &Config) -> Result<VmComponents> {
             .unwrap_or(256)
             .checked_mul(1024 * 1024)
             .ok_or_else(|| anyhow!("requested memory size too large"))?,
+        metrics_page: cfg.metrics_page,
+        fdt_position: cfg.fdt_position,
         swiotlb,
         vcpu_count: cfg.vcpu_count.unwrap_or(1),
         vcpu_affinity: cfg.vcpu_affinity.clone(),
         cpu_clusters: cfg.cpu_clusters.clone(),
+        cluster_affinity: cfg.cluster_affinity.clone(),
         cpu_capacity: cfg.cpu_capacity.clone(),
+        // Explicitly given caches take precedence over the host ones.
+        cpu_caches: if cfg.cpu_caches.is_empty() && cfg.host_cpu_topology {
+            arch::cpu_cache::host_cpu_caches(cfg.vcpu_count.unwrap_or(1), &cfg.cpu_clusters)
+        } else {
+            cfg.cpu_caches.clone()
+        },
         #[cfg(feature = "direct")]
         direct_gpe: cfg.direct_gpe.clone(),
         #[cfg(feature = "direct")]
@@ -1165,6 +1266,7 @@ fn setup_vm_components(cfg: &Config) -> Result<VmComponents> {
             protection_type: cfg.protection_type,
         },
         vm_image,
+        vm_uuid: cfg.vm_uuid,
         android_fstab: cfg
             .android_fstab
             .as_ref()
@@ -1173,6 +1275,7 @@ fn setup_vm_components(cfg: &Config) -> Result<VmComponents> {
                     .with_context(|| format!("failed to open android fstab file {}", x.display()))
             })
             .map_or(Ok(None), |v| v.map(Some))?,
+        boot_milestones: BootMilestones::new(),
         pstore: cfg.pstore.clone(),
         pflash_block_size,
         pflash_image,
@@ -1187,7 +1290,16 @@ fn setup_vm_components(cfg: &Config) -> Result<VmComponents> {
             })
             .collect::<Result<Vec<SDT>>>()?,
         rt_cpus: cfg.rt_cpus.clone(),
+        strict_irqs: cfg.strict_irqs,
         delay_rt: cfg.delay_rt,
+        device_tree_overlay: cfg
+            .device_tree_overlay
+            .as_ref()
+            .map(|x| {
+                File::open(x)
+                    .with_context(|| format!("failed to open device tree overlay {}", x.display()))
+            })
+            .map_or(Ok(None), |v| v.map(Some))?,
         #[cfg(all(any(target_arch = "x86_64", target_arch = "aarch64"), feature = "gdb"))]
         gdb: None,
         dmi_path: cfg.dmi_path.clone(),
@@ -1204,10 +1316,42 @@ fn setup_vm_components(cfg: &Config) -> Result<VmComponents> {
         pcie_ecam: cfg.pcie_ecam,
         #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
         pci_low_start: cfg.pci_low_start,
+        #[cfg(target_arch = "aarch64")]
+        pmem_regions: pmem_regions(cfg),
+        #[cfg(target_arch = "aarch64")]
+        pmu: cfg.pmu,
     })
 }
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg(target_arch = "aarch64")]
+fn pmem_regions(cfg: &Config) -> Vec<arch::PmemRegion> {
+    cfg.pmem_regions
+        .iter()
+        .map(|params| arch::PmemRegion {
+            size: params.size,
+            reserved_memory: params.reserved_memory,
+        })
+        .collect()
+}
+
+// Maps the pmem regions described to the guest as reserved memory, which are part of guest memory
+// instead of being mapped by a virtio-pmem device.
+#[cfg(target_arch = "aarch64")]
+fn create_pmem_memory_regions(cfg: &Config) -> Result<Vec<MemoryRegion>> {
+    let layout = Arch::pmem_region_layout(&pmem_regions(cfg)).context("invalid pmem regions")?;
+    cfg.pmem_regions
+        .iter()
+        .zip(layout)
+        .filter(|(params, _)| params.reserved_memory)
+        .map(|(params, range)| {
+            let file = open_pmem_region_file(params)?;
+            MemoryRegion::new_from_file(params.size, GuestAddress(range.start), 0, Arc::new(file))
+                .with_context(|| format!("failed to map pmem region {}", params.path.display()))
+        })
+        .collect()
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize)]
 pub enum ExitState {
     Reset,
     Stop,
@@ -1215,6 +1359,16 @@ pub enum ExitState {
     GuestPanic,
     WatchdogReset,
 }
+
+/// Final record logged by the main loop, so that the cause of an exit can be told apart from the
+/// log alone.
+#[derive(Serialize)]
+struct VmExitRecord {
+    /// The last reason reported over the vm_evt tube or found by the main loop, if any.
+    reason: Option<VmExitReason>,
+    exit_state: ExitState,
+}
+
 // Remove ranges in `guest_mem_layout` that overlap with ranges in `file_backed_mappings`.
 // Returns the updated guest memory layout.
 fn punch_holes_in_guest_mem_layout_for_mappings(
@@ -1352,7 +1506,22 @@ pub fn run_config(cfg: Config) -> Result<ExitState> {
     let guest_mem_layout =
         punch_holes_in_guest_mem_layout_for_mappings(guest_mem_layout, &cfg.file_backed_mappings);
 
-    let guest_mem = GuestMemory::new(&guest_mem_layout).context("failed to create guest memory")?;
+    if let Some(dir) = &cfg.memfd_fallback_dir {
+        vm_memory::set_memfd_fallback_dir(dir.clone());
+    }
+    #[cfg(target_arch = "aarch64")]
+    let file_regions = create_pmem_memory_regions(&cfg)?;
+    #[cfg(not(target_arch = "aarch64"))]
+    let file_regions = Vec::new();
+    let guest_mem = GuestMemory::new_with_access_policies(
+        &guest_mem_layout,
+        file_regions,
+        &Arch::guest_memory_access_policies(&components),
+    )
+    .context("failed to create guest memory")?;
+    guest_mem
+        .set_backing_object_limits(cfg.memory_backing_limits)
+        .context("guest memory exceeds --memory-backing-limits")?;
     let mut mem_policy = MemoryPolicy::empty();
     if components.hugepages {
         mem_policy |= MemoryPolicy::USE_HUGEPAGES;
@@ -1460,6 +1629,15 @@ where
     }
 
     let mut pmem_device_tubes = Vec::new();
+    // The pmem regions described as reserved memory have no virtio-pmem device.
+    #[cfg(target_arch = "aarch64")]
+    let pmem_count = cfg.pmem_devices.len()
+        + cfg
+            .pmem_regions
+            .iter()
+            .filter(|params| !params.reserved_memory)
+            .count();
+    #[cfg(not(target_arch = "aarch64"))]
     let pmem_count = cfg.pmem_devices.len();
     for _ in 0..pmem_count {
         let (pmem_host_tube, pmem_device_tube) = Tube::pair().context("failed to create tube")?;
@@ -1471,7 +1649,8 @@ where
         control_tubes.push(TaggedControlTube::VmIrq(ioapic_host_tube));
     }
 
-    let battery = if cfg.battery_config.is_some() {
+    let mut batteries = Vec::new();
+    for battery_config in &cfg.battery_config {
         #[cfg_attr(
             not(feature = "power-monitor-powerd"),
             allow(clippy::manual_map, clippy::needless_match)
@@ -1500,10 +1679,8 @@ where
             }
             None => None,
         };
-        (cfg.battery_config.as_ref().map(|c| c.type_), jail)
-    } else {
-        (cfg.battery_config.as_ref().map(|c| c.type_), None)
-    };
+        batteries.push((battery_config.type_, jail));
+    }
 
     let fs_count = cfg
         .shared_dirs
@@ -1760,7 +1937,7 @@ where
         &mut sys_allocator,
         &cfg.serial_parameters,
         simple_jail(&cfg.jail_config, "serial_device")?,
-        battery,
+        batteries,
         vm,
         ramoops_region,
         devices,
@@ -1772,6 +1949,8 @@ where
     )
     .context("the architecture failed to build the vm")?;
 
+    linux.mmio_bus.set_unmapped_access_policy(cfg.unmapped_mmio);
+
     #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
     let (hp_control_tube, hp_worker_tube) = mpsc::channel();
 
@@ -2210,6 +2389,200 @@ fn handle_hotplug_command<V: VmArch, Vcpu: VcpuArch>(
     }
 }
 
+fn set_kernel_cmdline<V: VmArch, Vcpu: VcpuArch>(
+    linux: &RunnableLinuxVm<V, Vcpu>,
+    vcpus_resumed: bool,
+    cmdline: &str,
+) -> VmResponse {
+    // A gdb client can also let the vcpus run, so check whether they did as well.
+    if vcpus_resumed || linux.boot_milestones.times().first_vcpu_run.is_some() {
+        return VmResponse::SetKernelCmdlineError(SetKernelCmdlineError::AlreadyBooted);
+    }
+    match Arch::set_kernel_cmdline(linux, cmdline) {
+        Ok(()) => {
+            info!("kernel command line set to \"{}\"", cmdline);
+            VmResponse::Ok
+        }
+        Err(e) => {
+            error!("failed to set kernel command line: {}", e);
+            VmResponse::SetKernelCmdlineError(e)
+        }
+    }
+}
+
+fn set_metrics_record<V: VmArch, Vcpu: VcpuArch>(
+    linux: &mut RunnableLinuxVm<V, Vcpu>,
+    id: u32,
+    value: u64,
+) -> VmResponse {
+    let metrics_page = match linux.metrics_page.as_mut() {
+        Some(metrics_page) => metrics_page,
+        None => return VmResponse::Err(base::Error::new(libc::ENOTSUP)),
+    };
+    match metrics_page.update(id, value) {
+        Ok(()) => VmResponse::Ok,
+        Err(e) => {
+            error!("failed to set metrics record {}: {}", id, e);
+            let errno = match e {
+                arch::metrics_page::Error::InvalidId => libc::EINVAL,
+                arch::metrics_page::Error::MemoryAccess(_) => libc::EIO,
+                arch::metrics_page::Error::PageFull(_) => libc::ENOSPC,
+            };
+            VmResponse::Err(base::Error::new(errno))
+        }
+    }
+}
+
+fn pvtime_stats<V: VmArch, Vcpu: VcpuArch>(
+    linux: &RunnableLinuxVm<V, Vcpu>,
+    vcpu_id: Option<usize>,
+) -> VmResponse {
+    let pvtime = match linux.pvtime.as_ref() {
+        Some(pvtime) => pvtime,
+        None => return VmResponse::Err(base::Error::new(libc::ENOTSUP)),
+    };
+    let vcpu_ids: Vec<usize> = match vcpu_id {
+        Some(vcpu_id) => vec![vcpu_id],
+        None => (0..linux.vcpu_count).collect(),
+    };
+    let stolen_times = vcpu_ids
+        .into_iter()
+        .map(|vcpu_id| {
+            pvtime
+                .stolen_time(vcpu_id)
+                .map(|stolen_time_ns| VcpuStolenTime {
+                    vcpu_id,
+                    stolen_time_ns,
+                })
+        })
+        .collect::<std::result::Result<Vec<_>, _>>();
+    match stolen_times {
+        Ok(stolen_times) => VmResponse::PvtimeStats(stolen_times),
+        Err(e) => {
+            error!("failed to get pvtime stats: {}", e);
+            let errno = match e {
+                arch::pvtime::Error::InvalidVcpu(_) => libc::EINVAL,
+                arch::pvtime::Error::ReadStolenTime(..) => libc::EIO,
+            };
+            VmResponse::Err(base::Error::new(errno))
+        }
+    }
+}
+
+fn serial_buffer<V: VmArch, Vcpu: VcpuArch>(
+    linux: &RunnableLinuxVm<V, Vcpu>,
+    hardware: &str,
+    index: u8,
+) -> VmResponse {
+    // Only the legacy UARTs record their output.
+    if hardware != SerialHardware::Serial.to_string() {
+        return VmResponse::Err(base::Error::new(libc::ENOTSUP));
+    }
+    match linux.serial_console_buffers.get(&index) {
+        Some(buffer) => VmResponse::SerialBuffer {
+            contents: buffer.contents(),
+            total: buffer.total(),
+        },
+        None => VmResponse::Err(base::Error::new(libc::ENODEV)),
+    }
+}
+
+fn serial_input<V: VmArch, Vcpu: VcpuArch>(
+    linux: &RunnableLinuxVm<V, Vcpu>,
+    hardware: &str,
+    index: u8,
+    data: Vec<u8>,
+) -> VmResponse {
+    // Only the legacy UARTs take injected input.
+    if hardware != SerialHardware::Serial.to_string() {
+        return VmResponse::Err(base::Error::new(libc::ENOTSUP));
+    }
+    match arch::send_serial_input(&linux.serial_inputs, index, data) {
+        Ok(()) => VmResponse::Ok,
+        Err(e) => VmResponse::Err(e),
+    }
+}
+
+fn serial_reopen_output<V: VmArch, Vcpu: VcpuArch>(
+    linux: &RunnableLinuxVm<V, Vcpu>,
+    hardware: &str,
+    index: u8,
+) -> VmResponse {
+    // Only the legacy UARTs can reopen their output file.
+    if hardware != SerialHardware::Serial.to_string() {
+        return VmResponse::Err(base::Error::new(libc::ENOTSUP));
+    }
+    match arch::reopen_serial_output(&linux.serial_output_files, index) {
+        Ok(()) => VmResponse::Ok,
+        Err(e) => VmResponse::Err(e),
+    }
+}
+
+fn vmwdt_command<V: VmArch, Vcpu: VcpuArch>(
+    linux: &RunnableLinuxVm<V, Vcpu>,
+    command: VmwdtCommand,
+) -> VmResponse {
+    let vmwdt_control = match linux.vmwdt_control.as_ref() {
+        Some(vmwdt_control) => vmwdt_control,
+        None => return VmResponse::Err(base::Error::new(libc::ENOTSUP)),
+    };
+    if let Err(e) = vmwdt_control.send(&command) {
+        error!("failed to send command to vmwdt: {}", e);
+        return VmResponse::Err(base::Error::new(libc::EIO));
+    }
+    match vmwdt_control.recv() {
+        Ok(result) => VmResponse::VmwdtResponse(result),
+        Err(e) => {
+            error!("failed to receive result from vmwdt: {}", e);
+            VmResponse::Err(base::Error::new(libc::EIO))
+        }
+    }
+}
+
+// Writes the pstore records back to their file, so that they are kept if the VM never resumes.
+fn sync_pstore<V: VmArch, Vcpu: VcpuArch>(linux: &mut RunnableLinuxVm<V, Vcpu>) {
+    if let Some(ramoops_region) = &linux.ramoops_region {
+        if let Err(e) = arch::pstore::sync(&mut linux.vm, ramoops_region) {
+            error!("{:#}", e);
+        }
+    }
+}
+
+/// How long `VmRequest::Suspend` and `VmRequest::Resume` wait for the vcpus to acknowledge.
+const VCPU_RUN_STATE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Sends `run_mode` to the vcpus in a new pause epoch and waits for all of them to acknowledge
+/// it, returning the response to the request that changed the run mode.
+fn change_vcpu_run_state(
+    vcpu_handles: &[(JoinHandle<()>, mpsc::Sender<VcpuControl>)],
+    irq_chip: &dyn IrqChip,
+    pause_state: &vcpu_pause::VcpuPauseState,
+    run_mode: VmRunMode,
+) -> VmResponse {
+    let start = Instant::now();
+    let epoch = pause_state.begin(run_mode == VmRunMode::Suspending);
+    vcpu::kick_all_vcpus(vcpu_handles, irq_chip, VcpuControl::RunState(run_mode));
+    let unresponsive = pause_state.wait(epoch, VCPU_RUN_STATE_TIMEOUT);
+    if unresponsive.is_empty() {
+        VmResponse::RunStateChanged {
+            epoch,
+            vcpus: vcpu_handles.len(),
+            elapsed_us: start.elapsed().as_micros() as u64,
+        }
+    } else {
+        error!(
+            "vcpus {:?} did not acknowledge pause epoch {}",
+            unresponsive, epoch
+        );
+        VmResponse::UnresponsiveVcpus(UnresponsiveVcpus {
+            epoch,
+            acked: vcpu_handles.len() - unresponsive.len(),
+            unresponsive,
+            timeout_ms: VCPU_RUN_STATE_TIMEOUT.as_millis() as u64,
+        })
+    }
+}
+
 fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
     mut linux: RunnableLinuxVm<V, Vcpu>,
     mut sys_allocator: SystemAllocator,
@@ -2235,6 +2608,7 @@ fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
         VmEvent,
         Suspend,
         ChildSignal,
+        MemoryFault,
         IrqFd { index: IrqEventIndex },
         VmControlServer,
         VmControl { index: usize },
@@ -2249,10 +2623,19 @@ fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
         .set_raw_mode()
         .expect("failed to set terminal raw mode");
 
+    // Signaled when an access to guest memory by this process raises SIGBUS.
+    let memory_fault_evt = Event::new().context("failed to create memory fault event")?;
+    vm_memory::set_memory_fault_event(
+        memory_fault_evt
+            .try_clone()
+            .context("failed to clone memory fault event")?,
+    );
+
     let wait_ctx = WaitContext::build_with(&[
         (&linux.suspend_evt, Token::Suspend),
         (&sigchld_fd, Token::ChildSignal),
         (&vm_evt_rdtube, Token::VmEvent),
+        (&memory_fault_evt, Token::MemoryFault),
     ])
     .context("failed to add descriptor to wait context")?;
 
@@ -2338,6 +2721,7 @@ fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
     android::set_process_profiles(&cfg.task_profiles)?;
 
     let guest_suspended_cvar = Arc::new((Mutex::new(false), Condvar::new()));
+    let pause_state = Arc::new(vcpu_pause::VcpuPauseState::new(linux.vcpu_count));
 
     // Architecture-specific code must supply a vcpu_init element for each VCPU.
     assert_eq!(vcpus.len(), linux.vcpu_init.len());
@@ -2362,7 +2746,10 @@ fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
         ));
 
         #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
-        let cpu_config = None;
+        let cpu_config = Some(CpuConfigAArch64 {
+            midr: cfg.cpu_id.midr,
+            revidr: cfg.cpu_id.revidr,
+        });
 
         let handle = vcpu::run_vcpu(
             cpu_id,
@@ -2379,6 +2766,7 @@ fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
             vcpu_affinity,
             linux.delay_rt,
             vcpu_thread_barrier.clone(),
+            cfg.start_paused,
             linux.has_bios,
             (*linux.io_bus).clone(),
             (*linux.mmio_bus).clone(),
@@ -2402,6 +2790,10 @@ fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
             },
             cfg.userspace_msr.clone(),
             guest_suspended_cvar.clone(),
+            linux.boot_milestones.clone(),
+            pause_state.clone(),
+            #[cfg(target_arch = "aarch64")]
+            cfg.no_host_suspend_time,
         )?;
         vcpu_handles.push((handle, to_vcpu_channel));
     }
@@ -2427,9 +2819,15 @@ fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
     vcpu_thread_barrier.wait();
 
     let mut exit_state = ExitState::Stop;
+    let mut last_exit_reason: Option<VmExitReason> = None;
     let mut pvpanic_code = PvPanicCode::Unknown;
     #[cfg(feature = "balloon")]
     let mut balloon_stats_id: u64 = 0;
+    // Whether the vcpus were allowed to run, after which the guest may have read its command line.
+    let mut vcpus_resumed = !cfg.start_paused;
+    let mut event_subscribers = VmEventSubscribers::new();
+    #[cfg(feature = "gpu")]
+    let mut display_tracker = DisplayTracker::new();
 
     'wait: loop {
         let events = {
@@ -2443,34 +2841,39 @@ fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
         };
 
         let mut vm_control_indices_to_remove = Vec::new();
+        // Connections that subscribed to events, to hand over once removed from `control_tubes`.
+        let mut vm_control_subscriptions = BTreeMap::new();
         for event in events.iter().filter(|e| e.is_readable) {
             match event.token {
                 Token::VmEvent => {
                     let mut break_to_wait: bool = true;
-                    match vm_evt_rdtube.recv::<VmEventType>() {
-                        Ok(vm_event) => match vm_event {
-                            VmEventType::Exit => {
-                                info!("vcpu requested shutdown");
-                                exit_state = ExitState::Stop;
-                            }
-                            VmEventType::Reset => {
-                                info!("vcpu requested reset");
-                                exit_state = ExitState::Reset;
-                            }
-                            VmEventType::Crash => {
-                                info!("vcpu crashed");
-                                exit_state = ExitState::Crash;
-                            }
-                            VmEventType::Panic(panic_code) => {
-                                pvpanic_code = PvPanicCode::from_u8(panic_code);
-                                info!("Guest reported panic [Code: {}]", pvpanic_code);
-                                break_to_wait = false;
-                            }
-                            VmEventType::WatchdogReset => {
-                                info!("vcpu stall detected");
-                                exit_state = ExitState::WatchdogReset;
+                    match vm_evt_rdtube.recv::<VmExitReason>() {
+                        Ok(reason) => {
+                            last_exit_reason = Some(reason);
+                            match reason {
+                                VmExitReason::GuestShutdown => {
+                                    info!("vcpu requested shutdown");
+                                    exit_state = ExitState::Stop;
+                                }
+                                VmExitReason::GuestReset => {
+                                    info!("vcpu requested reset");
+                                    exit_state = ExitState::Reset;
+                                }
+                                VmExitReason::Crash { .. } => {
+                                    info!("vcpu crashed");
+                                    exit_state = ExitState::Crash;
+                                }
+                                VmExitReason::Panic { info } => {
+                                    pvpanic_code = PvPanicCode::from_u8(info);
+                                    info!("Guest reported panic [Code: {}]", pvpanic_code);
+                                    break_to_wait = false;
+                                }
+                                VmExitReason::WatchdogBite { vcpu } => {
+                                    info!("vcpu {} stall detected", vcpu);
+                                    exit_state = ExitState::WatchdogReset;
+                                }
                             }
-                        },
+                        }
                         Err(e) => {
                             warn!("failed to recv VmEvent: {}", e);
                         }
@@ -2485,6 +2888,7 @@ fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
                 Token::Suspend => {
                     info!("VM requested suspend");
                     linux.suspend_evt.read().unwrap();
+                    pause_state.begin(true);
                     vcpu::kick_all_vcpus(
                         &vcpu_handles,
                         linux.irq_chip.as_irq_chip(),
@@ -2505,7 +2909,30 @@ fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
                             "child {} died: signo {}, status {}, code {}",
                             pid_label, siginfo.ssi_signo, siginfo.ssi_status, siginfo.ssi_code
                         );
+                        // A device process that exits on its own stops the VM as before, but one
+                        // killed by a signal is a crash.
+                        if siginfo.ssi_code == libc::CLD_KILLED
+                            || siginfo.ssi_code == libc::CLD_DUMPED
+                        {
+                            last_exit_reason = Some(VmExitReason::Crash {
+                                signal: Some(siginfo.ssi_status),
+                            });
+                            exit_state = ExitState::Crash;
+                        }
+                    }
+                    break 'wait;
+                }
+                Token::MemoryFault => {
+                    match vm_memory::memory_fault() {
+                        Some(fault) => error!(
+                            "shutting down after {} guest memory access faults, first: {}",
+                            vm_memory::memory_fault_count(),
+                            fault
+                        ),
+                        None => error!("shutting down after a guest memory access fault"),
                     }
+                    last_exit_reason = Some(VmExitReason::Crash { signal: None });
+                    exit_state = ExitState::Crash;
                     break 'wait;
                 }
                 Token::IrqFd { index } => {
@@ -2543,10 +2970,10 @@ fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
                     let mut add_tubes = Vec::new();
                     if let Some(socket) = control_tubes.get(index) {
                         match socket {
-                            TaggedControlTube::Vm(tube) => match tube.recv::<VmRequest>() {
-                                Ok(request) => {
+                            TaggedControlTube::Vm(tube) => match frame::recv_request(tube) {
+                                Ok((id, request)) => {
                                     let mut run_mode_opt = None;
-                                    let response = match request {
+                                    let mut response = match request {
                                         VmRequest::HotPlugCommand { device, add } => {
                                             #[cfg(any(
                                                 target_arch = "x86",
@@ -2571,6 +2998,71 @@ fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
                                             )))]
                                             VmResponse::Ok
                                         }
+                                        VmRequest::SetKernelCmdline(ref cmdline) => {
+                                            set_kernel_cmdline(&linux, vcpus_resumed, cmdline)
+                                        }
+                                        VmRequest::SetMetricsRecord { id, value } => {
+                                            set_metrics_record(&mut linux, id, value)
+                                        }
+                                        VmRequest::PvtimeStats { vcpu_id } => {
+                                            pvtime_stats(&linux, vcpu_id)
+                                        }
+                                        VmRequest::ListDevices => {
+                                            VmResponse::Devices(linux.static_mmio_map.clone())
+                                        }
+                                        VmRequest::SerialBuffer {
+                                            ref hardware,
+                                            index,
+                                        } => serial_buffer(&linux, hardware, index),
+                                        VmRequest::SerialInput {
+                                            ref hardware,
+                                            index,
+                                            data,
+                                        } => serial_input(&linux, hardware, index, data),
+                                        VmRequest::SerialReopenOutput {
+                                            ref hardware,
+                                            index,
+                                        } => serial_reopen_output(&linux, hardware, index),
+                                        VmRequest::Vmwdt(command) => vmwdt_command(&linux, command),
+                                        VmRequest::DegradedDevices => VmResponse::DegradedDevices {
+                                            devices: linux.degraded_devices.clone(),
+                                        },
+                                        VmRequest::GuestMemoryBackingObjects => {
+                                            VmResponse::GuestMemoryBackingObjects(
+                                                linux.vm.get_memory().backing_object_stats(),
+                                            )
+                                        }
+                                        VmRequest::GetVmInfo => VmResponse::VmInfo(VmInfo {
+                                            uuid: cfg.vm_uuid.to_string(),
+                                            vcpu_count: linux.vcpu_count,
+                                            memory_size: linux.vm.get_memory().memory_size(),
+                                            arch: std::env::consts::ARCH.to_owned(),
+                                            last_exit_reason,
+                                        }),
+                                        VmRequest::ResourceStats => {
+                                            VmResponse::ResourceStats(ResourceStats {
+                                                pools: sys_allocator.pool_stats(),
+                                            })
+                                        }
+                                        VmRequest::UnmappedMmioAccesses => {
+                                            let (accesses, total) =
+                                                linux.mmio_bus.unmapped_accesses();
+                                            VmResponse::UnmappedMmioAccesses { accesses, total }
+                                        }
+                                        #[cfg(feature = "gpu")]
+                                        VmRequest::GpuCommand(ref cmd) => {
+                                            let (response, events) =
+                                                display_tracker.execute(cmd, &gpu_control_tube);
+                                            for event in events {
+                                                event_subscribers.publish(event);
+                                            }
+                                            response
+                                        }
+                                        VmRequest::SubscribeEvents { ref kinds } => {
+                                            vm_control_subscriptions.insert(index, kinds.clone());
+                                            vm_control_indices_to_remove.push(index);
+                                            VmResponse::Ok
+                                        }
                                         _ => request.execute(
                                             &mut run_mode_opt,
                                             #[cfg(feature = "balloon")]
@@ -2585,36 +3077,48 @@ fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
                                             Some(&usb_control_tube),
                                             #[cfg(not(feature = "usb"))]
                                             None,
-                                            &mut linux.bat_control,
+                                            &linux.bat_control,
                                             &vcpu_handles,
                                             cfg.force_s2idle,
                                             guest_suspended_cvar.clone(),
+                                            &linux.boot_milestones,
                                         ),
                                     };
 
-                                    if let Err(e) = tube.send(&response) {
-                                        error!("failed to send VmResponse: {}", e);
-                                    }
+                                    let mut exiting = false;
                                     if let Some(run_mode) = run_mode_opt {
                                         info!("control socket changed run mode to {}", run_mode);
                                         match run_mode {
-                                            VmRunMode::Exiting => {
-                                                break 'wait;
-                                            }
+                                            VmRunMode::Exiting => exiting = true,
                                             other => {
                                                 if other == VmRunMode::Running {
+                                                    vcpus_resumed = true;
                                                     for dev in &linux.resume_notify_devices {
                                                         dev.lock().resume_imminent();
                                                     }
                                                 }
-                                                vcpu::kick_all_vcpus(
+                                                let changed = change_vcpu_run_state(
                                                     &vcpu_handles,
                                                     linux.irq_chip.as_irq_chip(),
-                                                    VcpuControl::RunState(other),
+                                                    &pause_state,
+                                                    other,
                                                 );
+                                                // Errors of the request itself take precedence.
+                                                if let VmResponse::Ok = response {
+                                                    response = changed;
+                                                }
+                                                if other == VmRunMode::Suspending {
+                                                    sync_pstore(&mut linux);
+                                                }
                                             }
                                         }
                                     }
+                                    if let Err(e) = frame::send_response(tube, id, &response) {
+                                        error!("failed to send VmResponse: {}", e);
+                                    }
+                                    if exiting {
+                                        break 'wait;
+                                    }
                                 }
                                 Err(e) => {
                                     if let TubeError::Disconnected = e {
@@ -2795,10 +3299,18 @@ fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
             }
 
             // This line implicitly drops the socket at `index` when it gets returned by
-            // `swap_remove`. After this line, the socket at `index` is not the one from
-            // `vm_control_indices_to_remove`. Because of this socket's change in index, we need to
-            // use `wait_ctx.modify` to change the associated index in its `Token::VmControl`.
-            control_tubes.swap_remove(index);
+            // `swap_remove`, unless it subscribed to events. After this line, the socket at `index`
+            // is not the one from `vm_control_indices_to_remove`. Because of this socket's change
+            // in index, we need to use `wait_ctx.modify` to change the associated index in its
+            // `Token::VmControl`.
+            let removed = control_tubes.swap_remove(index);
+            if let (Some(kinds), TaggedControlTube::Vm(tube)) =
+                (vm_control_subscriptions.remove(&index), removed)
+            {
+                if let Err(e) = event_subscribers.subscribe(tube, kinds) {
+                    error!("failed to subscribe to VM events: {}", e);
+                }
+            }
             if let Some(tube) = control_tubes.get(index) {
                 wait_ctx
                     .modify(tube, EventType::Read, Token::VmControl { index })
@@ -2822,6 +3334,8 @@ fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
     #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
     let _ = hp_control_tube.send(PciRootCommand::Kill);
 
+    sync_pstore(&mut linux);
+
     // Explicitly drop the VM structure here to allow the devices to clean up before the
     // control sockets are closed when this function exits.
     mem::drop(linux);
@@ -2830,6 +3344,15 @@ fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
         .set_canon_mode()
         .expect("failed to restore canonical mode for terminal");
 
+    let record = VmExitRecord {
+        reason: last_exit_reason,
+        exit_state,
+    };
+    match serde_json::to_string(&record) {
+        Ok(record) => info!("vm exit: {}", record),
+        Err(e) => error!("failed to serialize vm exit record: {}", e),
+    }
+
     Ok(exit_state)
 }
 
@@ -2917,9 +3440,7 @@ fn jail_and_start_vu_device<T: VirtioDeviceBuilder>(
 }
 
 fn process_vhost_user_control_request(tube: Tube, disk_host_tubes: &[Tube]) -> Result<()> {
-    let command = tube
-        .recv::<VmRequest>()
-        .context("failed to receive VmRequest")?;
+    let (id, command) = frame::recv_request(&tube).context("failed to receive VmRequest")?;
     let resp = match command {
         VmRequest::DiskCommand {
             disk_index,
@@ -2937,7 +3458,7 @@ fn process_vhost_user_control_request(tube: Tube, disk_host_tubes: &[Tube]) -> R
         }
     };
 
-    tube.send(&resp).context("failed to send VmResponse")?;
+    frame::send_response(&tube, id, &resp).context("failed to send VmResponse")?;
     Ok(())
 }
 
@@ -598,6 +598,15 @@ mod tests {
         assert_eq!(gpu_params.pci_bar_size, 0x100000);
     }
 
+    #[cfg(feature = "gpu")]
+    #[test]
+    fn parse_gpu_options_edid_reread_timeout() {
+        let gpu_params: GpuParameters = from_key_values("").unwrap();
+        assert_eq!(gpu_params.edid_reread_timeout_ms, 1000);
+        let gpu_params: GpuParameters = from_key_values("edid-reread-timeout-ms=0").unwrap();
+        assert_eq!(gpu_params.edid_reread_timeout_ms, 0);
+    }
+
     #[cfg(feature = "gpu")]
     #[test]
     fn parse_gpu_display_options_valid() {
@@ -5,6 +5,8 @@
 use std::cell::Cell;
 use std::collections::BTreeMap;
 use std::convert::TryFrom;
+#[cfg(target_arch = "aarch64")]
+use std::fs::File;
 use std::fs::OpenOptions;
 use std::net::Ipv4Addr;
 use std::ops::RangeInclusive;
@@ -56,6 +58,7 @@ use devices::VfioPciDevice;
 use devices::VfioPlatformDevice;
 #[cfg(all(feature = "vtpm", target_arch = "x86_64"))]
 use devices::VtpmProxy;
+use hypervisor::MemCacheType;
 use hypervisor::ProtectionType;
 use hypervisor::Vm;
 use minijail::Minijail;
@@ -69,6 +72,8 @@ use vm_memory::GuestAddress;
 
 use super::jail_helpers::*;
 use crate::crosvm::config::JailConfig;
+#[cfg(target_arch = "aarch64")]
+use crate::crosvm::config::PmemRegionParameters;
 use crate::crosvm::config::TouchDeviceOption;
 use crate::crosvm::config::VhostUserFsOption;
 use crate::crosvm::config::VhostUserOption;
@@ -1299,6 +1304,7 @@ pub fn create_pmem_device(
             Box::new(arena),
             /* read_only = */ disk.read_only,
             /* log_dirty_pages = */ false,
+            MemCacheType::Cached,
         )
         .context("failed to add pmem device memory")?;
 
@@ -1318,6 +1324,77 @@ pub fn create_pmem_device(
     })
 }
 
+/// Opens the backing file of the pmem region `params`, which must be at least as long as the
+/// region.
+#[cfg(target_arch = "aarch64")]
+pub fn open_pmem_region_file(params: &PmemRegionParameters) -> Result<File> {
+    let file = open_file(&params.path, OpenOptions::new().read(true).write(true))
+        .with_context(|| format!("failed to open pmem region {}", params.path.display()))?;
+    let len = file
+        .metadata()
+        .with_context(|| {
+            format!(
+                "failed to get pmem region {} metadata",
+                params.path.display()
+            )
+        })?
+        .len();
+    if len < params.size {
+        bail!(
+            "pmem region {} is {:#x} bytes long, smaller than its size {:#x}",
+            params.path.display(),
+            len,
+            params.size
+        );
+    }
+    Ok(file)
+}
+
+/// Creates the virtio-pmem device of the pmem region `params`, which the guest memory layout
+/// placed at `address`.
+#[cfg(target_arch = "aarch64")]
+pub fn create_pmem_region_device(
+    protection_type: ProtectionType,
+    jail_config: &Option<JailConfig>,
+    vm: &mut impl Vm,
+    params: &PmemRegionParameters,
+    address: GuestAddress,
+    pmem_device_tube: Tube,
+) -> DeviceResult {
+    let fd = open_pmem_region_file(params)?;
+    let size = usize::try_from(params.size).context("pmem region too big")?;
+
+    let mut arena = MemoryMappingArena::new(size).context("failed to reserve pmem memory")?;
+    arena
+        .add_fd_offset_protection(0, size, &fd, 0, Protection::read_write())
+        .context("failed to reserve pmem memory")?;
+
+    let slot = vm
+        .add_memory_region(
+            address,
+            Box::new(arena),
+            /* read_only = */ false,
+            /* log_dirty_pages = */ false,
+            MemCacheType::Cached,
+        )
+        .context("failed to add pmem region memory")?;
+
+    let dev = virtio::Pmem::new(
+        virtio::base_features(protection_type),
+        fd,
+        address,
+        slot,
+        params.size,
+        Some(pmem_device_tube),
+    )
+    .context("failed to create pmem device")?;
+
+    Ok(VirtioDeviceStub {
+        dev: Box::new(dev) as Box<dyn VirtioDevice>,
+        jail: simple_jail(jail_config, "pmem_device")?,
+    })
+}
+
 pub fn create_iommu_device(
     protection_type: ProtectionType,
     jail_config: &Option<JailConfig>,
@@ -1370,10 +1447,19 @@ impl VirtioDeviceBuilder for SerialParameters {
         let mut keep_rds = Vec::new();
         let evt = Event::new().context("failed to create event")?;
 
-        Ok(Box::new(
-            self.create_serial_device::<AsyncConsole>(protection_type, &evt, &mut keep_rds)
-                .context("failed to create console device")?,
-        ))
+        let mut console = self
+            .create_serial_device::<AsyncConsole>(protection_type, &evt, &mut keep_rds)
+            .context("failed to create console device")?;
+        if self.watch_resize {
+            let terminal = self
+                .open_output_terminal()
+                .context("watch_resize requires the output to be a terminal")?;
+            console
+                .watch_terminal_size(terminal)
+                .context("failed to read the terminal size")?;
+        }
+
+        Ok(Box::new(console))
     }
 
     fn create_vhost_user_device(
@@ -17,6 +17,8 @@ use std::thread::JoinHandle;
 use aarch64::AArch64 as Arch;
 #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
 use aarch64::MsrHandlers;
+#[cfg(target_arch = "aarch64")]
+use aarch64::SuspendedCounter;
 use anyhow::Context;
 use anyhow::Result;
 use arch::LinuxArch;
@@ -28,6 +30,8 @@ use devices::IrqChip;
 use devices::IrqChipAArch64 as IrqChipArch;
 #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
 use devices::IrqChipX86_64 as IrqChipArch;
+#[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
+use devices::UnmappedAccessPolicy;
 use devices::VcpuRunState;
 #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
 use hypervisor::CpuConfigAArch64 as CpuConfigArch;
@@ -43,6 +47,8 @@ use hypervisor::VcpuExit;
 use hypervisor::VcpuInitAArch64 as VcpuInitArch;
 #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
 use hypervisor::VcpuInitX86_64 as VcpuInitArch;
+#[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
+use hypervisor::VcpuRegAArch64;
 use hypervisor::VcpuRunHandle;
 #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
 use hypervisor::VcpuX86_64 as VcpuArch;
@@ -61,6 +67,7 @@ use x86_64::msr::MsrHandlers;
 #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
 use x86_64::X8664arch as Arch;
 
+use super::vcpu_pause::VcpuPauseState;
 use super::ExitState;
 
 pub fn setup_vcpu_signal_handler<T: Vcpu>(use_hypervisor_signals: bool) -> Result<()> {
@@ -84,21 +91,27 @@ pub fn setup_vcpu_signal_handler<T: Vcpu>(use_hypervisor_signals: bool) -> Resul
     Ok(())
 }
 
-fn bus_io_handler(bus: &Bus) -> impl FnMut(IoParams) -> Option<[u8; 8]> + '_ {
-    |IoParams {
-         address,
-         mut size,
-         operation: direction,
-     }| match direction {
+/// Returns a handler for the IO or MMIO exit of a vcpu, setting `unmapped` if the access didn't
+/// reach a device. `pc` returns the program counter of the access, if it can be known.
+fn bus_io_handler<'a>(
+    bus: &'a Bus,
+    pc: impl Fn() -> Option<u64> + 'a,
+    unmapped: &'a mut bool,
+) -> impl FnMut(IoParams) -> Option<[u8; 8]> + 'a {
+    move |IoParams {
+              address,
+              mut size,
+              operation: direction,
+          }| match direction {
         IoOperation::Read => {
             let mut data = [0u8; 8];
             if size > data.len() {
                 error!("unsupported Read size of {} bytes", size);
                 size = data.len();
             }
-            // Ignore the return value of `read()`. If no device exists on the bus at the given
-            // location, return the initial value of data, which is all zeroes.
-            let _ = bus.read(address, &mut data[..size]);
+            // If no device exists on the bus at the given location, return the initial value of
+            // data, which is all zeroes.
+            *unmapped = !bus.read_with_pc(address, &mut data[..size], &pc);
             Some(data)
         }
         IoOperation::Write { data } => {
@@ -107,12 +120,22 @@ fn bus_io_handler(bus: &Bus) -> impl FnMut(IoParams) -> Option<[u8; 8]> + '_ {
                 size = data.len()
             }
             let data = &data[..size];
-            bus.write(address, data);
+            *unmapped = !bus.write_with_pc(address, data, &pc);
             None
         }
     }
 }
 
+#[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
+fn vcpu_pc<V: VcpuArch>(vcpu: &V) -> Option<u64> {
+    vcpu.get_one_reg(VcpuRegAArch64::Pc).ok()
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn vcpu_pc<V: VcpuArch>(vcpu: &V) -> Option<u64> {
+    vcpu.get_regs().ok().map(|regs| regs.rip)
+}
+
 /// Set the VCPU thread affinity and other per-thread scheduler properties.
 /// This function will be called from each VCPU thread at startup.
 pub fn set_vcpu_thread_scheduling(
@@ -387,11 +410,22 @@ fn vcpu_loop<V>(
     #[cfg(feature = "gdb")] guest_mem: GuestMemory,
     msr_handlers: MsrHandlers,
     guest_suspended_cvar: Arc<(Mutex<bool>, Condvar)>,
+    boot_milestones: BootMilestones,
+    pause_state: Arc<VcpuPauseState>,
+    #[cfg(target_arch = "aarch64")] no_host_suspend_time: bool,
 ) -> ExitState
 where
     V: VcpuArch + 'static,
 {
     let mut interrupted_by_signal = false;
+    let mut first_run = true;
+    // The virtual counter offset is shared by all the VCPUs, so only the first one adjusts it.
+    #[cfg(target_arch = "aarch64")]
+    let mut suspended_counter = if no_host_suspend_time && cpu_id == 0 {
+        Some(SuspendedCounter::new())
+    } else {
+        None
+    };
 
     loop {
         // Start by checking for messages to process and the run state of the CPU.
@@ -432,8 +466,33 @@ where
                         VcpuControl::RunState(new_mode) => {
                             run_mode = new_mode;
                             match run_mode {
-                                VmRunMode::Running => break 'state_loop,
+                                VmRunMode::Running => {
+                                    #[cfg(target_arch = "aarch64")]
+                                    if let Some(counter) = suspended_counter.as_mut() {
+                                        match counter.resume(&vcpu) {
+                                            Ok(0) => {}
+                                            Ok(ticks) => info!(
+                                                "hid {} suspended ticks from the virtual counter",
+                                                ticks
+                                            ),
+                                            Err(e) => error!(
+                                                "failed to restore the virtual counter: {}",
+                                                e
+                                            ),
+                                        }
+                                    }
+                                    pause_state.ack(cpu_id, false);
+                                    // Keep going through the messages received along with this
+                                    // one, which may suspend the vcpu again. 'state_loop ends
+                                    // once there are no more messages.
+                                }
                                 VmRunMode::Suspending => {
+                                    #[cfg(target_arch = "aarch64")]
+                                    if let Some(counter) = suspended_counter.as_mut() {
+                                        if let Err(e) = counter.suspend(&vcpu) {
+                                            error!("failed to save the virtual counter: {}", e);
+                                        }
+                                    }
                                     // On KVM implementations that use a paravirtualized
                                     // clock (e.g. x86), a flag must be set to indicate to
                                     // the guest kernel that a vCPU was suspended. The guest
@@ -448,6 +507,7 @@ where
                                             );
                                         }
                                     }
+                                    pause_state.ack(cpu_id, true);
                                 }
                                 VmRunMode::Breakpoint => {}
                                 VmRunMode::Exiting => return ExitState::Stop,
@@ -503,16 +563,38 @@ where
         }
 
         if !interrupted_by_signal {
+            if first_run {
+                boot_milestones.record(BootMilestone::FirstVcpuRun);
+                first_run = false;
+            }
             match vcpu.run(&vcpu_run_handle) {
                 Ok(VcpuExit::Io) => {
-                    if let Err(e) = vcpu.handle_io(&mut bus_io_handler(&io_bus)) {
+                    let mut unmapped = false;
+                    if let Err(e) =
+                        vcpu.handle_io(&mut bus_io_handler(&io_bus, || None, &mut unmapped))
+                    {
                         error!("failed to handle io: {}", e)
                     }
                 }
                 Ok(VcpuExit::Mmio) => {
-                    if let Err(e) = vcpu.handle_mmio(&mut bus_io_handler(&mmio_bus)) {
+                    let mut unmapped = false;
+                    if let Err(e) = vcpu.handle_mmio(&mut bus_io_handler(
+                        &mmio_bus,
+                        || vcpu_pc(&vcpu),
+                        &mut unmapped,
+                    )) {
                         error!("failed to handle mmio: {}", e);
                     }
+                    #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
+                    if unmapped && mmio_bus.unmapped_access_policy() == UnmappedAccessPolicy::Fault
+                    {
+                        if let Err(e) = vcpu.inject_external_data_abort() {
+                            error!(
+                                "failed to inject external abort into vcpu {}: {}",
+                                cpu_id, e
+                            );
+                        }
+                    }
                 }
                 Ok(VcpuExit::RdMsr { index }) => {
                     if let Some(data) = msr_handlers.read(index) {
@@ -615,6 +697,7 @@ pub fn run_vcpu<V>(
     vcpu_affinity: Vec<usize>,
     delay_rt: bool,
     start_barrier: Arc<Barrier>,
+    start_paused: bool,
     has_bios: bool,
     mut io_bus: Bus,
     mut mmio_bus: Bus,
@@ -631,6 +714,9 @@ pub fn run_vcpu<V>(
     vcpu_cgroup_tasks_file: Option<File>,
     userspace_msr: BTreeMap<u32, MsrConfig>,
     guest_suspended_cvar: Arc<(Mutex<bool>, Condvar)>,
+    boot_milestones: BootMilestones,
+    pause_state: Arc<VcpuPauseState>,
+    #[cfg(target_arch = "aarch64")] no_host_suspend_time: bool,
 ) -> Result<JoinHandle<()>>
 where
     V: VcpuArch + 'static,
@@ -639,7 +725,7 @@ where
         .name(format!("crosvm_vcpu{}", cpu_id))
         .spawn(move || {
             // Having a closure returning ExitState guarentees that we
-            // send a VmEventType on all code paths after the closure
+            // send a VmExitReason on all code paths after the closure
             // returns.
             let vcpu_fn = || -> ExitState {
                 if let Err(e) = set_vcpu_thread_scheduling(
@@ -655,6 +741,9 @@ where
                 #[cfg(feature = "gdb")]
                 let guest_mem = vm.get_memory().clone();
 
+                #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
+                let id_overrides = cpu_config.clone().unwrap_or_default();
+
                 let runnable_vcpu = runnable_vcpu(
                     cpu_id,
                     vcpu_id,
@@ -670,6 +759,9 @@ where
 
                 // Add MSR handlers after CPU affinity setting.
                 // This avoids redundant MSR file fd creation.
+                #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
+                let mut msr_handlers = MsrHandlers::from_cpu_config(&id_overrides);
+                #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
                 let mut msr_handlers = MsrHandlers::new();
                 if !userspace_msr.is_empty() {
                     userspace_msr.iter().for_each(|(index, msr_config)| {
@@ -691,7 +783,12 @@ where
                 };
 
                 #[allow(unused_mut)]
-                let mut run_mode = VmRunMode::Running;
+                let mut run_mode = if start_paused {
+                    // Wait for `crosvm resume`.
+                    VmRunMode::Suspending
+                } else {
+                    VmRunMode::Running
+                };
                 #[cfg(all(any(target_arch = "x86_64", target_arch = "aarch64"), feature = "gdb"))]
                 if to_gdb_tube.is_some() {
                     // Wait until a GDB client attaches
@@ -724,18 +821,22 @@ where
                     guest_mem,
                     msr_handlers,
                     guest_suspended_cvar,
+                    boot_milestones,
+                    pause_state,
+                    #[cfg(target_arch = "aarch64")]
+                    no_host_suspend_time,
                 )
             };
 
             let final_event_data = match vcpu_fn() {
-                ExitState::Stop => VmEventType::Exit,
-                ExitState::Reset => VmEventType::Reset,
-                ExitState::Crash => VmEventType::Crash,
+                ExitState::Stop => VmExitReason::GuestShutdown,
+                ExitState::Reset => VmExitReason::GuestReset,
+                ExitState::Crash => VmExitReason::Crash { signal: None },
                 // vcpu_loop doesn't exit with GuestPanic.
                 ExitState::GuestPanic => unreachable!(),
-                ExitState::WatchdogReset => VmEventType::WatchdogReset,
+                ExitState::WatchdogReset => VmExitReason::WatchdogBite { vcpu: cpu_id },
             };
-            if let Err(e) = vm_evt_wrtube.send::<VmEventType>(&final_event_data) {
+            if let Err(e) = vm_evt_wrtube.send::<VmExitReason>(&final_event_data) {
                 error!(
                     "failed to send final event {:?} on vcpu {}: {}",
                     final_event_data, cpu_id, e
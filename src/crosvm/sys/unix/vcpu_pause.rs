@@ -0,0 +1,145 @@
+// Copyright 2022 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Accounting of the vcpus that applied a suspend or resume.
+//!
+//! Sending a new run mode to the vcpus only queues a message and kicks them out of the guest, so
+//! the main loop can't tell from that alone whether a vcpu actually parked or went back to
+//! running. Every suspend or resume starts a new pause epoch, and each vcpu acknowledges the epoch
+//! once it has applied its run mode. The main loop then waits for all the acknowledgments, and can
+//! name the vcpus that didn't respond instead of reporting success anyway.
+
+use std::time::Duration;
+
+use sync::Condvar;
+use sync::Mutex;
+
+struct Epochs {
+    /// The most recent epoch.
+    current: u64,
+    /// Whether the vcpus are suspended in the current epoch.
+    suspended: bool,
+    /// The last epoch acknowledged by each vcpu.
+    acked: Vec<u64>,
+}
+
+pub struct VcpuPauseState {
+    epochs: Mutex<Epochs>,
+    cvar: Condvar,
+}
+
+impl VcpuPauseState {
+    /// Creates the state of `vcpu_count` vcpus, which are in epoch 0 until the first suspend or
+    /// resume.
+    pub fn new(vcpu_count: usize) -> VcpuPauseState {
+        VcpuPauseState {
+            epochs: Mutex::new(Epochs {
+                current: 0,
+                suspended: false,
+                acked: vec![0; vcpu_count],
+            }),
+            cvar: Condvar::new(),
+        }
+    }
+
+    /// Starts an epoch in which the vcpus are suspended or running, returning its number. This
+    /// must be called before the new run mode is sent to the vcpus.
+    pub fn begin(&self, suspended: bool) -> u64 {
+        let mut epochs = self.epochs.lock();
+        epochs.current += 1;
+        epochs.suspended = suspended;
+        epochs.current
+    }
+
+    /// Records that vcpu `cpu_id` applied a run mode. Run modes that were superseded by a later
+    /// epoch before the vcpu got to them are not acknowledged.
+    pub fn ack(&self, cpu_id: usize, suspended: bool) {
+        let mut epochs = self.epochs.lock();
+        if epochs.suspended == suspended {
+            epochs.acked[cpu_id] = epochs.current;
+            self.cvar.notify_all();
+        }
+    }
+
+    /// Waits up to `timeout` for all the vcpus to acknowledge `epoch` or a later one. Returns the
+    /// ids of the vcpus that didn't, which is empty on success.
+    pub fn wait(&self, epoch: u64, timeout: Duration) -> Vec<usize> {
+        let epochs = self.epochs.lock();
+        let (epochs, _) = self.cvar.wait_timeout_while(epochs, timeout, |epochs| {
+            epochs.acked.iter().any(|&acked| acked < epoch)
+        });
+        epochs
+            .acked
+            .iter()
+            .enumerate()
+            .filter(|&(_, &acked)| acked < epoch)
+            .map(|(cpu_id, _)| cpu_id)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn all_vcpus_ack() {
+        let state = Arc::new(VcpuPauseState::new(4));
+        let epoch = state.begin(true);
+        assert_eq!(epoch, 1);
+
+        let vcpus: Vec<_> = (0..4)
+            .map(|cpu_id| {
+                let state = state.clone();
+                thread::spawn(move || state.ack(cpu_id, true))
+            })
+            .collect();
+        assert!(state.wait(epoch, Duration::from_secs(10)).is_empty());
+        for vcpu in vcpus {
+            vcpu.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn unresponsive_vcpus() {
+        let state = VcpuPauseState::new(4);
+        let epoch = state.begin(true);
+        state.ack(0, true);
+        state.ack(2, true);
+        assert_eq!(state.wait(epoch, Duration::from_millis(10)), [1, 3]);
+    }
+
+    #[test]
+    fn previous_epoch_not_counted() {
+        let state = VcpuPauseState::new(2);
+        let suspend = state.begin(true);
+        state.ack(0, true);
+        state.ack(1, true);
+        assert!(state.wait(suspend, Duration::from_millis(10)).is_empty());
+
+        let resume = state.begin(false);
+        state.ack(0, false);
+        assert_eq!(state.wait(resume, Duration::from_millis(10)), [1]);
+    }
+
+    #[test]
+    fn superseded_mode_not_acked() {
+        // Back to back suspend and resume, with vcpu 1 only getting to the suspend after the
+        // resume started.
+        let state = VcpuPauseState::new(2);
+        let suspend = state.begin(true);
+        state.ack(0, true);
+        let resume = state.begin(false);
+        assert!(resume > suspend);
+        state.ack(0, false);
+        state.ack(1, true);
+        assert_eq!(state.wait(resume, Duration::from_millis(10)), [1]);
+
+        state.ack(1, false);
+        assert!(state.wait(resume, Duration::from_millis(10)).is_empty());
+    }
+}
@@ -2,6 +2,8 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
+#[cfg(feature = "audio")]
+use std::path::PathBuf;
 use std::str::FromStr;
 
 #[cfg(feature = "gpu")]
@@ -34,11 +36,23 @@ use crate::crosvm::config::Config;
 
 #[cfg(feature = "audio")]
 pub fn parse_ac97_options(
-    _ac97_params: &mut Ac97Parameters,
+    ac97_params: &mut Ac97Parameters,
     key: &str,
     value: &str,
 ) -> Result<(), String> {
-    Err(format!("unknown ac97 parameter {} {}", key, value))
+    match key {
+        "loopback" => {
+            ac97_params.loopback = value
+                .parse::<bool>()
+                .map_err(|e| format!("invalid loopback option: {}", e))?;
+        }
+        "loopback_wav" => {
+            ac97_params.loopback = true;
+            ac97_params.loopback_wav = Some(PathBuf::from(value));
+        }
+        _ => return Err(format!("unknown ac97 parameter {} {}", key, value)),
+    }
+    Ok(())
 }
 
 #[cfg(feature = "gpu")]
@@ -575,6 +589,21 @@ mod tests {
             .expect("parse should have succeded");
     }
 
+    #[cfg(feature = "audio")]
+    #[test]
+    fn parse_ac97_loopback() {
+        let params = crate::crosvm::config::parse_ac97_options("backend=win_audio,loopback=true")
+            .expect("parse should have succeded");
+        assert!(params.loopback);
+        assert_eq!(params.loopback_wav, None);
+
+        let params =
+            crate::crosvm::config::parse_ac97_options("backend=win_audio,loopback_wav=out.wav")
+                .expect("parse should have succeded");
+        assert!(params.loopback);
+        assert_eq!(params.loopback_wav, Some(PathBuf::from("out.wav")));
+    }
+
     #[cfg(all(feature = "gpu"))]
     #[test]
     fn parse_gpu_options_default_vulkan_support() {
@@ -6,6 +6,7 @@
 
 #[cfg(any(feature = "composite-disk", feature = "qcow"))]
 use std::fs::OpenOptions;
+use std::io::Write;
 use std::path::Path;
 
 use anyhow::anyhow;
@@ -50,12 +51,21 @@ use vm_control::client::do_gpu_display_add;
 use vm_control::client::do_gpu_display_list;
 #[cfg(feature = "gpu")]
 use vm_control::client::do_gpu_display_remove;
+#[cfg(feature = "gpu")]
+use vm_control::client::do_gpu_display_set_mode;
+#[cfg(feature = "gpu")]
+use vm_control::client::do_gpu_display_set_refresh_rate;
+#[cfg(feature = "gpu")]
+use vm_control::client::do_gpu_display_state;
+#[cfg(feature = "gpu")]
+use vm_control::client::do_gpu_display_trace;
 use vm_control::client::do_modify_battery;
 use vm_control::client::do_usb_attach;
 use vm_control::client::do_usb_detach;
 use vm_control::client::do_usb_list;
 use vm_control::client::handle_request;
 use vm_control::client::vms_request;
+use vm_control::client::vms_run_state_request;
 #[cfg(feature = "gpu")]
 use vm_control::client::ModifyGpuResult;
 use vm_control::client::ModifyUsbResult;
@@ -68,6 +78,8 @@ use vm_control::UsbControlResult;
 use vm_control::VmRequest;
 #[cfg(feature = "balloon")]
 use vm_control::VmResponse;
+use vm_control::VmwdtCommand;
+use vm_control::VmwdtControlResult;
 
 use crate::sys::error_to_exit_code;
 use crate::sys::init_log;
@@ -136,7 +148,7 @@ fn to_command_status(result: Result<sys::ExitState>) -> Result<CommandStatus> {
     }
 }
 
-fn run_vm<F: 'static>(cmd: RunCommand, log_config: LogConfig<F>) -> Result<CommandStatus>
+fn run_vm<F: 'static>(cmd: RunCommand, mut log_config: LogConfig<F>) -> Result<CommandStatus>
 where
     F: Fn(&mut syslog::fmt::Formatter, &log::Record<'_>) -> std::io::Result<()> + Sync + Send,
 {
@@ -169,6 +181,7 @@ where
     #[cfg(windows)]
     metrics::setup_metrics_reporting()?;
 
+    log_config.vm_uuid = Some(cfg.vm_uuid.to_string());
     init_log(log_config, &cfg)?;
     let exit_state = crate::sys::run_config(cfg);
     to_command_status(exit_state)
@@ -179,11 +192,11 @@ fn stop_vms(cmd: cmdline::StopCommand) -> std::result::Result<(), ()> {
 }
 
 fn suspend_vms(cmd: cmdline::SuspendCommand) -> std::result::Result<(), ()> {
-    vms_request(&VmRequest::Suspend, cmd.socket_path)
+    vms_run_state_request(&VmRequest::Suspend, cmd.socket_path)
 }
 
 fn resume_vms(cmd: cmdline::ResumeCommand) -> std::result::Result<(), ()> {
-    vms_request(&VmRequest::Resume, cmd.socket_path)
+    vms_run_state_request(&VmRequest::Resume, cmd.socket_path)
 }
 
 fn powerbtn_vms(cmd: cmdline::PowerbtnCommand) -> std::result::Result<(), ()> {
@@ -224,10 +237,206 @@ fn balloon_stats(cmd: cmdline::BalloonStatsCommand) -> std::result::Result<(), (
     }
 }
 
+fn boot_times(cmd: cmdline::BootTimesCommand) -> std::result::Result<(), ()> {
+    match handle_request(&VmRequest::BootTimes, cmd.socket_path)? {
+        VmResponse::BootTimes(times) => {
+            println!("{}", times);
+            Ok(())
+        }
+        r => {
+            error!("unexpected boot_times response: {}", r);
+            Err(())
+        }
+    }
+}
+
+fn vm_info(cmd: cmdline::VmInfoCommand) -> std::result::Result<(), ()> {
+    match handle_request(&VmRequest::GetVmInfo, cmd.socket_path)? {
+        VmResponse::VmInfo(info) => {
+            println!("{}", info);
+            Ok(())
+        }
+        r => {
+            error!("unexpected vm_info response: {}", r);
+            Err(())
+        }
+    }
+}
+
+fn list_devices(cmd: cmdline::ListDevicesCommand) -> std::result::Result<(), ()> {
+    match handle_request(&VmRequest::ListDevices, cmd.socket_path)? {
+        r @ VmResponse::Devices(_) => {
+            print!("{}", r);
+            Ok(())
+        }
+        r => {
+            error!("unexpected list_devices response: {}", r);
+            Err(())
+        }
+    }
+}
+
+fn pvtime_stats(cmd: cmdline::PvtimeStatsCommand) -> std::result::Result<(), ()> {
+    match handle_request(
+        &VmRequest::PvtimeStats { vcpu_id: cmd.vcpu },
+        cmd.socket_path,
+    )? {
+        r @ VmResponse::PvtimeStats(_) => {
+            print!("{}", r);
+            Ok(())
+        }
+        r => {
+            error!("unexpected pvtime_stats response: {}", r);
+            Err(())
+        }
+    }
+}
+
+fn resources(cmd: cmdline::ResourcesCommand) -> std::result::Result<(), ()> {
+    match handle_request(&VmRequest::ResourceStats, cmd.socket_path)? {
+        VmResponse::ResourceStats(stats) => {
+            if cmd.json {
+                let json = serde_json::to_string_pretty(&stats).map_err(|e| {
+                    error!("failed to serialize resource stats: {}", e);
+                })?;
+                println!("{}", json);
+            } else {
+                print!("{}", stats);
+            }
+            Ok(())
+        }
+        r => {
+            error!("unexpected resources response: {}", r);
+            Err(())
+        }
+    }
+}
+
+fn serial_buffer(cmd: cmdline::SerialBufferCommand) -> std::result::Result<(), ()> {
+    let request = VmRequest::SerialBuffer {
+        hardware: cmd.hardware,
+        index: cmd.num,
+    };
+    match handle_request(&request, cmd.socket_path)? {
+        VmResponse::SerialBuffer { contents, .. } => {
+            // The output is written as is, it may not be UTF-8.
+            let mut stdout = std::io::stdout();
+            stdout
+                .write_all(&contents)
+                .and_then(|_| stdout.flush())
+                .map_err(|e| error!("failed to write the serial buffer: {}", e))
+        }
+        r => {
+            error!("unexpected serial_buffer response: {}", r);
+            Err(())
+        }
+    }
+}
+
+fn serial_input(cmd: cmdline::SerialInputCommand) -> std::result::Result<(), ()> {
+    vms_request(
+        &VmRequest::SerialInput {
+            hardware: cmd.hardware,
+            index: cmd.num,
+            data: cmd.data.into_bytes(),
+        },
+        cmd.socket_path,
+    )
+}
+
+fn serial_reopen_output(cmd: cmdline::SerialReopenOutputCommand) -> std::result::Result<(), ()> {
+    vms_request(
+        &VmRequest::SerialReopenOutput {
+            hardware: cmd.hardware,
+            index: cmd.num,
+        },
+        cmd.socket_path,
+    )
+}
+
+fn set_kernel_cmdline(cmd: cmdline::SetKernelCmdlineCommand) -> std::result::Result<(), ()> {
+    match handle_request(&VmRequest::SetKernelCmdline(cmd.cmdline), cmd.socket_path)? {
+        VmResponse::Ok => Ok(()),
+        VmResponse::SetKernelCmdlineError(e) => {
+            error!("failed to set the kernel command line: {}", e);
+            Err(())
+        }
+        r => {
+            error!("unexpected set_kernel_cmdline response: {}", r);
+            Err(())
+        }
+    }
+}
+
+fn set_metric(cmd: cmdline::SetMetricCommand) -> std::result::Result<(), ()> {
+    vms_request(
+        &VmRequest::SetMetricsRecord {
+            id: cmd.id,
+            value: cmd.value,
+        },
+        cmd.socket_path,
+    )
+}
+
+fn snd_cmd(cmd: cmdline::SndCommand) -> std::result::Result<(), ()> {
+    match cmd.command {
+        cmdline::SndSubcommand::Capture(cmd) => {
+            match handle_request(
+                &VmRequest::SndCapture { frames: cmd.frames },
+                cmd.socket_path,
+            )? {
+                VmResponse::SndCapture(capture) => {
+                    println!("{}", capture);
+                    Ok(())
+                }
+                r => {
+                    error!("unexpected snd capture response: {}", r);
+                    Err(())
+                }
+            }
+        }
+        cmdline::SndSubcommand::Info(cmd) => {
+            match handle_request(&VmRequest::SndInfo, cmd.socket_path)? {
+                VmResponse::SndInfo(info) => {
+                    println!("{}", info);
+                    Ok(())
+                }
+                r => {
+                    error!("unexpected snd info response: {}", r);
+                    Err(())
+                }
+            }
+        }
+    }
+}
+
+fn vmwdt_cmd(cmd: cmdline::VmwdtCommand) -> std::result::Result<(), ()> {
+    let (command, socket_path) = match cmd.command {
+        cmdline::VmwdtSubcommand::Pause(cmd) => (VmwdtCommand::Pause, cmd.socket_path),
+        cmdline::VmwdtSubcommand::Resume(cmd) => (VmwdtCommand::Resume, cmd.socket_path),
+        cmdline::VmwdtSubcommand::SetTimeout(cmd) => {
+            (VmwdtCommand::SetTimeout { secs: cmd.secs }, cmd.socket_path)
+        }
+        cmdline::VmwdtSubcommand::Status(cmd) => (VmwdtCommand::Status, cmd.socket_path),
+    };
+    match handle_request(&VmRequest::Vmwdt(command), socket_path)? {
+        VmResponse::VmwdtResponse(VmwdtControlResult::Ok) => Ok(()),
+        VmResponse::VmwdtResponse(result) => {
+            print!("{}", result);
+            Ok(())
+        }
+        r => {
+            error!("unexpected vmwdt response: {}", r);
+            Err(())
+        }
+    }
+}
+
 fn modify_battery(cmd: cmdline::BatteryCommand) -> std::result::Result<(), ()> {
     do_modify_battery(
         cmd.socket_path,
         &cmd.battery_type,
+        cmd.index,
         &cmd.property,
         &cmd.target,
     )
@@ -449,6 +658,11 @@ fn gpu_display_add(cmd: cmdline::GpuAddDisplaysCommand) -> ModifyGpuResult {
     do_gpu_display_add(cmd.socket_path, cmd.gpu_display)
 }
 
+#[cfg(feature = "gpu")]
+fn gpu_display_state(cmd: cmdline::GpuDisplayStateCommand) -> ModifyGpuResult {
+    do_gpu_display_state(cmd.socket_path)
+}
+
 #[cfg(feature = "gpu")]
 fn gpu_display_list(cmd: cmdline::GpuListDisplaysCommand) -> ModifyGpuResult {
     do_gpu_display_list(cmd.socket_path)
@@ -459,12 +673,31 @@ fn gpu_display_remove(cmd: cmdline::GpuRemoveDisplaysCommand) -> ModifyGpuResult
     do_gpu_display_remove(cmd.socket_path, cmd.display_id)
 }
 
+#[cfg(feature = "gpu")]
+fn gpu_display_set_mode(cmd: cmdline::GpuSetModeCommand) -> ModifyGpuResult {
+    do_gpu_display_set_mode(cmd.socket_path, cmd.display_id, cmd.gpu_display)
+}
+
+#[cfg(feature = "gpu")]
+fn gpu_display_set_refresh_rate(cmd: cmdline::GpuSetRefreshRateCommand) -> ModifyGpuResult {
+    do_gpu_display_set_refresh_rate(cmd.socket_path, cmd.display_id, cmd.refresh_rate)
+}
+
+#[cfg(feature = "gpu")]
+fn gpu_display_trace(cmd: cmdline::GpuTraceDisplaysCommand) -> ModifyGpuResult {
+    do_gpu_display_trace(cmd.socket_path)
+}
+
 #[cfg(feature = "gpu")]
 fn modify_gpu(cmd: cmdline::GpuCommand) -> std::result::Result<(), ()> {
     let result = match cmd.command {
         cmdline::GpuSubCommand::AddDisplays(cmd) => gpu_display_add(cmd),
+        cmdline::GpuSubCommand::DisplayState(cmd) => gpu_display_state(cmd),
         cmdline::GpuSubCommand::ListDisplays(cmd) => gpu_display_list(cmd),
         cmdline::GpuSubCommand::RemoveDisplays(cmd) => gpu_display_remove(cmd),
+        cmdline::GpuSubCommand::SetMode(cmd) => gpu_display_set_mode(cmd),
+        cmdline::GpuSubCommand::SetRefreshRate(cmd) => gpu_display_set_refresh_rate(cmd),
+        cmdline::GpuSubCommand::TraceDisplays(cmd) => gpu_display_trace(cmd),
     };
     match result {
         Ok(response) => {
@@ -635,6 +868,9 @@ fn crosvm_main() -> Result<CommandStatus> {
                     CrossPlatformCommands::Battery(cmd) => {
                         modify_battery(cmd).map_err(|_| anyhow!("battery subcommand failed"))
                     }
+                    CrossPlatformCommands::BootTimes(cmd) => {
+                        boot_times(cmd).map_err(|_| anyhow!("boot_times subcommand failed"))
+                    }
                     #[cfg(feature = "composite-disk")]
                     CrossPlatformCommands::CreateComposite(cmd) => create_composite(cmd)
                         .map_err(|_| anyhow!("create_composite subcommand failed")),
@@ -650,13 +886,36 @@ fn crosvm_main() -> Result<CommandStatus> {
                     CrossPlatformCommands::Gpu(cmd) => {
                         modify_gpu(cmd).map_err(|_| anyhow!("gpu subcommand failed"))
                     }
+                    CrossPlatformCommands::ListDevices(cmd) => {
+                        list_devices(cmd).map_err(|_| anyhow!("list_devices subcommand failed"))
+                    }
                     CrossPlatformCommands::MakeRT(cmd) => {
                         make_rt(cmd).map_err(|_| anyhow!("make_rt subcommand failed"))
                     }
+                    CrossPlatformCommands::PvtimeStats(cmd) => {
+                        pvtime_stats(cmd).map_err(|_| anyhow!("pvtime_stats subcommand failed"))
+                    }
+                    CrossPlatformCommands::Resources(cmd) => {
+                        resources(cmd).map_err(|_| anyhow!("resources subcommand failed"))
+                    }
                     CrossPlatformCommands::Resume(cmd) => {
                         resume_vms(cmd).map_err(|_| anyhow!("resume subcommand failed"))
                     }
                     CrossPlatformCommands::Run(_) => unreachable!(),
+                    CrossPlatformCommands::SerialBuffer(cmd) => serial_buffer(cmd)
+                        .map_err(|_| anyhow!("serial_buffer subcommand failed")),
+                    CrossPlatformCommands::SerialInput(cmd) => serial_input(cmd)
+                        .map_err(|_| anyhow!("serial_input subcommand failed")),
+                    CrossPlatformCommands::SerialReopenOutput(cmd) => serial_reopen_output(cmd)
+                        .map_err(|_| anyhow!("serial_reopen_output subcommand failed")),
+                    CrossPlatformCommands::SetKernelCmdline(cmd) => set_kernel_cmdline(cmd)
+                        .map_err(|_| anyhow!("set_kernel_cmdline subcommand failed")),
+                    CrossPlatformCommands::SetMetric(cmd) => {
+                        set_metric(cmd).map_err(|_| anyhow!("set_metric subcommand failed"))
+                    }
+                    CrossPlatformCommands::Snd(cmd) => {
+                        snd_cmd(cmd).map_err(|_| anyhow!("snd subcommand failed"))
+                    }
                     CrossPlatformCommands::Stop(cmd) => {
                         stop_vms(cmd).map_err(|_| anyhow!("stop subcommand failed"))
                     }
@@ -681,6 +940,12 @@ fn crosvm_main() -> Result<CommandStatus> {
                     CrossPlatformCommands::Vfio(cmd) => {
                         modify_vfio(cmd).map_err(|_| anyhow!("vfio subcommand failed"))
                     }
+                    CrossPlatformCommands::VmInfo(cmd) => {
+                        vm_info(cmd).map_err(|_| anyhow!("vm_info subcommand failed"))
+                    }
+                    CrossPlatformCommands::Vmwdt(cmd) => {
+                        vmwdt_cmd(cmd).map_err(|_| anyhow!("vmwdt subcommand failed"))
+                    }
                 }
                 .map(|_| CommandStatus::SuccessOrVmStop)
             }
@@ -68,7 +68,7 @@ use base::SendTube;
 use base::StreamChannel;
 use base::Tube;
 use base::TubeError;
-use base::VmEventType;
+use base::VmExitReason;
 use base::WaitContext;
 use broker_ipc::common_child_setup;
 use broker_ipc::CommonChildStartupArgs;
@@ -173,6 +173,7 @@ use tube_transporter::TubeTransporterReader;
 use vm_control::Ac97Control;
 #[cfg(feature = "kiwi")]
 use vm_control::BalloonControlCommand;
+use vm_control::BootMilestones;
 #[cfg(feature = "kiwi")]
 use vm_control::GpuSendToMain;
 #[cfg(feature = "kiwi")]
@@ -949,26 +950,26 @@ fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
         let mut vm_control_indices_to_remove = Vec::new();
         for event in events.iter().filter(|e| e.is_readable) {
             match event.token {
-                Token::VmEvent => match vm_evt_rdtube.recv::<VmEventType>() {
+                Token::VmEvent => match vm_evt_rdtube.recv::<VmExitReason>() {
                     Ok(vm_event) => {
                         match vm_event {
-                            VmEventType::Exit => {
+                            VmExitReason::GuestShutdown => {
                                 info!("vcpu requested shutdown");
                                 exit_state = ExitState::Stop;
                             }
-                            VmEventType::Reset => {
+                            VmExitReason::GuestReset => {
                                 info!("vcpu requested reset");
                                 exit_state = ExitState::Reset;
                             }
-                            VmEventType::Crash => {
+                            VmExitReason::Crash { .. } => {
                                 info!("vcpu crashed");
                                 exit_state = ExitState::Crash;
                             }
-                            VmEventType::Panic(_) => {
+                            VmExitReason::Panic { .. } => {
                                 error!("got pvpanic event. this event is not expected on Windows.");
                             }
-                            VmEventType::WatchdogReset => {
-                                info!("vcpu stall detected");
+                            VmExitReason::WatchdogBite { vcpu } => {
+                                info!("vcpu {} stall detected", vcpu);
                                 exit_state = ExitState::WatchdogReset;
                             }
                         }
@@ -1325,7 +1326,7 @@ fn run_control<V: VmArch + 'static, Vcpu: VcpuArch + 'static>(
     let _ = exit_evt.write(1);
     // Ensure any child threads have ended by sending the Exit vm event (possibly again) to ensure
     // their run loops are aborted.
-    let _ = vm_evt_wrtube.send::<VmEventType>(&VmEventType::Exit);
+    let _ = vm_evt_wrtube.send::<VmExitReason>(&VmExitReason::GuestShutdown);
     for (i, thread) in vcpu_threads.into_iter().enumerate() {
         // wait till all the threads exit, so that guest_os.vm arc memory count is down to 1.
         // otherwise, we will hit a memory leak if we force kill the thread with terminate.
@@ -1591,17 +1592,22 @@ fn setup_vm_components(cfg: &Config) -> Result<VmComponents> {
             .unwrap_or(256)
             .checked_mul(1024 * 1024)
             .ok_or_else(|| anyhow!("requested memory size too large"))?,
+        metrics_page: cfg.metrics_page,
+        fdt_position: cfg.fdt_position,
         swiotlb,
         vcpu_count: cfg.vcpu_count.unwrap_or(1),
         vcpu_affinity: cfg.vcpu_affinity.clone(),
         cpu_clusters: cfg.cpu_clusters.clone(),
+        cluster_affinity: cfg.cluster_affinity.clone(),
         cpu_capacity: cfg.cpu_capacity.clone(),
+        cpu_caches: cfg.cpu_caches.clone(),
         no_smt: cfg.no_smt,
         hugepages: cfg.hugepages,
         hv_cfg: hypervisor::Config {
             protection_type: cfg.protection_type,
         },
         vm_image,
+        vm_uuid: cfg.vm_uuid,
         android_fstab: cfg
             .android_fstab
             .as_ref()
@@ -1611,6 +1617,7 @@ fn setup_vm_components(cfg: &Config) -> Result<VmComponents> {
                 })
             })
             .map_or(Ok(None), |v| v.map(Some))?,
+        boot_milestones: BootMilestones::new(),
         pstore: cfg.pstore.clone(),
         pflash_block_size,
         pflash_image,
@@ -1626,7 +1633,9 @@ fn setup_vm_components(cfg: &Config) -> Result<VmComponents> {
             })
             .collect::<Result<Vec<SDT>>>()?,
         rt_cpus: cfg.rt_cpus.clone(),
+        strict_irqs: cfg.strict_irqs,
         delay_rt: cfg.delay_rt,
+        device_tree_overlay: None,
         dmi_path: cfg.dmi_path.clone(),
         no_i8042: cfg.no_i8042,
         no_rtc: cfg.no_rtc,
@@ -2110,7 +2119,10 @@ where
         &mut sys_allocator,
         &cfg.serial_parameters,
         None,
-        (cfg.battery_config.as_ref().map(|t| t.type_), None),
+        cfg.battery_config
+            .iter()
+            .map(|battery| (battery.type_, None))
+            .collect(),
         vm,
         ramoops_region,
         pci_devices,
@@ -2121,6 +2133,10 @@ where
     )
     .exit_context(Exit::BuildVm, "the architecture failed to build the vm")?;
 
+    windows
+        .mmio_bus
+        .set_unmapped_access_policy(cfg.unmapped_mmio);
+
     let _render_node_host = ();
 
     #[cfg(feature = "stats")]
@@ -34,7 +34,7 @@ use base::SafeMultimediaHandle;
 use base::SendTube;
 use base::Timer;
 use base::Tube;
-use base::VmEventType;
+use base::VmExitReason;
 use cros_async::select2;
 use cros_async::EventAsync;
 use cros_async::Executor;
@@ -290,7 +290,7 @@ impl VcpuRunThread {
             .name(format!("crosvm_vcpu{}", self.cpu_id))
             .spawn(move || {
                 // Having a closure returning ExitState guarentees that we
-                // send a VmEventType on all code paths after the closure
+                // send a VmExitReason on all code paths after the closure
                 // returns.
                 let vcpu_fn = || -> Result<ExitState> {
                     let runnable_vcpu = Self::runnable_vcpu(
@@ -384,11 +384,11 @@ impl VcpuRunThread {
                     );
                     ExitState::Stop
                 }) {
-                    ExitState::Stop => VmEventType::Exit,
+                    ExitState::Stop => VmExitReason::GuestShutdown,
                     _ => unreachable!(),
                 };
                 vm_evt_wrtube
-                    .send::<VmEventType>(&final_event_data)
+                    .send::<VmExitReason>(&final_event_data)
                     .unwrap_or_else(|e| {
                         error!(
                             "failed to send final event {:?} on vcpu {}: {}",
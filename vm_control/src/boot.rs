@@ -0,0 +1,165 @@
+// Copyright 2022 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Tracking of guest boot milestones observed by the VMM.
+
+use std::fmt;
+use std::fmt::Display;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+
+use base::info;
+use serde::Deserialize;
+use serde::Serialize;
+use sync::Mutex;
+
+/// A point in the guest boot process that the VMM can observe without guest cooperation, except
+/// for `GuestBootComplete` which is signaled by the guest through the boot doorbell device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BootMilestone {
+    /// A vcpu entered the guest for the first time.
+    FirstVcpuRun,
+    /// The guest wrote its first byte to a serial port.
+    FirstSerialOutput,
+    /// The guest wrote to the boot complete doorbell.
+    GuestBootComplete,
+}
+
+/// Timestamps of the boot milestones reached so far, relative to VM creation.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BootTimes {
+    /// Wall clock time at which the VM was created.
+    pub vm_created: SystemTime,
+    pub first_vcpu_run: Option<Duration>,
+    pub first_serial_output: Option<Duration>,
+    pub guest_boot_complete: Option<Duration>,
+    /// Time spent in each phase of building the VM, in order.
+    pub build_vm_phases: Vec<BuildPhase>,
+}
+
+impl Display for BootTimes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            serde_json::to_string_pretty(self).map_err(|_| fmt::Error)?
+        )
+    }
+}
+
+/// Wall time spent in a named phase of building the VM.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BuildPhase {
+    pub name: String,
+    pub duration: Duration,
+}
+
+/// Times consecutive phases of a process. Each call to `phase_done` ends a phase that started
+/// when the previous one ended, or when the timer was created for the first phase.
+pub struct PhaseTimer {
+    last: Instant,
+    phases: Vec<BuildPhase>,
+}
+
+impl PhaseTimer {
+    /// Starts timing the first phase.
+    pub fn new() -> PhaseTimer {
+        PhaseTimer {
+            last: Instant::now(),
+            phases: Vec::new(),
+        }
+    }
+
+    /// Ends the current phase, naming it `name`, and starts the next one.
+    pub fn phase_done(&mut self, name: &str) {
+        let now = Instant::now();
+        self.phases.push(BuildPhase {
+            name: name.to_owned(),
+            duration: now.saturating_duration_since(self.last),
+        });
+        self.last = now;
+    }
+
+    /// Returns the phases ended so far.
+    pub fn phases(&self) -> &[BuildPhase] {
+        &self.phases
+    }
+
+    /// Logs a one line summary of the phases of `what` and records them in `milestones`.
+    pub fn finish(self, what: &str, milestones: &BootMilestones) {
+        let total: Duration = self.phases.iter().map(|p| p.duration).sum();
+        let summary: Vec<String> = self
+            .phases
+            .iter()
+            .map(|p| format!("{} {:.1}ms", p.name, p.duration.as_secs_f64() * 1000.0))
+            .collect();
+        info!(
+            "{} took {:.1}ms: {}",
+            what,
+            total.as_secs_f64() * 1000.0,
+            summary.join(", ")
+        );
+        milestones.inner.lock().times.build_vm_phases = self.phases;
+    }
+}
+
+impl Default for PhaseTimer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct BootMilestonesInner {
+    created: Instant,
+    times: BootTimes,
+}
+
+/// Records when boot milestones are first reached. Cloned handles share the same record.
+#[derive(Clone)]
+pub struct BootMilestones {
+    inner: Arc<Mutex<BootMilestonesInner>>,
+}
+
+impl BootMilestones {
+    /// Starts tracking milestones for a VM created now.
+    pub fn new() -> BootMilestones {
+        BootMilestones {
+            inner: Arc::new(Mutex::new(BootMilestonesInner {
+                created: Instant::now(),
+                times: BootTimes {
+                    vm_created: SystemTime::now(),
+                    first_vcpu_run: None,
+                    first_serial_output: None,
+                    guest_boot_complete: None,
+                    build_vm_phases: Vec::new(),
+                },
+            })),
+        }
+    }
+
+    /// Records that `milestone` was reached. Only the first occurrence of each milestone is kept.
+    pub fn record(&self, milestone: BootMilestone) {
+        let mut inner = self.inner.lock();
+        let elapsed = inner.created.elapsed();
+        let time = match milestone {
+            BootMilestone::FirstVcpuRun => &mut inner.times.first_vcpu_run,
+            BootMilestone::FirstSerialOutput => &mut inner.times.first_serial_output,
+            BootMilestone::GuestBootComplete => &mut inner.times.guest_boot_complete,
+        };
+        time.get_or_insert(elapsed);
+    }
+
+    /// Returns the milestones reached so far.
+    pub fn times(&self) -> BootTimes {
+        self.inner.lock().times.clone()
+    }
+}
+
+impl Default for BootMilestones {
+    fn default() -> Self {
+        Self::new()
+    }
+}
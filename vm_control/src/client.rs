@@ -3,14 +3,25 @@
 // found in the LICENSE file.
 
 use std::fs::OpenOptions;
+#[cfg(unix)]
+use std::io;
 use std::path::Path;
 use std::path::PathBuf;
+#[cfg(unix)]
+use std::time::Duration;
 
+use base::error;
 use base::open_file;
+#[cfg(unix)]
+use base::TubeError;
 use remain::sorted;
 use thiserror::Error;
 
 pub use crate::sys::handle_request;
+#[cfg(unix)]
+pub use crate::sys::unix::connect_vm_control;
+#[cfg(unix)]
+pub use crate::sys::unix::connect_vm_control_with_timeout;
 pub use crate::*;
 
 #[cfg(feature = "gpu")]
@@ -48,6 +59,21 @@ pub fn vms_request<T: AsRef<Path> + std::fmt::Debug>(
     Ok(())
 }
 
+/// Sends a `VmRequest::Suspend` or `VmRequest::Resume`, which only succeeds once every vcpu
+/// acknowledged it.
+pub fn vms_run_state_request<T: AsRef<Path> + std::fmt::Debug>(
+    request: &VmRequest,
+    socket_path: T,
+) -> VmsRequestResult {
+    match handle_request(request, socket_path)? {
+        VmResponse::Ok | VmResponse::RunStateChanged { .. } => Ok(()),
+        r => {
+            error!("{:?} failed: {}", request, r);
+            Err(())
+        }
+    }
+}
+
 pub fn do_usb_attach<T: AsRef<Path> + std::fmt::Debug>(
     socket_path: T,
     dev_path: &Path,
@@ -98,13 +124,18 @@ pub type DoModifyBatteryResult = std::result::Result<(), ()>;
 pub fn do_modify_battery<T: AsRef<Path> + std::fmt::Debug>(
     socket_path: T,
     battery_type: &str,
+    index: usize,
     property: &str,
     target: &str,
 ) -> DoModifyBatteryResult {
     let response = match battery_type.parse::<BatteryType>() {
         Ok(type_) => match BatControlCommand::new(property.to_string(), target.to_string()) {
-            Ok(cmd) => {
-                let request = VmRequest::BatCommand(type_, cmd);
+            Ok(command) => {
+                let request = VmRequest::BatCommand {
+                    type_,
+                    index,
+                    command,
+                };
                 Ok(handle_request(&request, socket_path)?)
             }
             Err(e) => Err(ModifyBatError::BatControlErr(e)),
@@ -125,3 +156,196 @@ pub fn do_modify_battery<T: AsRef<Path> + std::fmt::Debug>(
 }
 
 pub type HandleRequestResult = std::result::Result<VmResponse, ()>;
+
+/// How long a `VmClient` waits by default to connect to the VM and for each response.
+#[cfg(unix)]
+pub const VM_CLIENT_DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[cfg(unix)]
+#[sorted]
+#[derive(Error, Debug)]
+pub enum VmClientError {
+    #[error("failed to connect to {0}: {1}")]
+    Connect(PathBuf, io::Error),
+    #[error("failed to exchange the request: {0}")]
+    Request(TubeError),
+    #[error("the VM failed the request: {0}")]
+    Response(base::Error),
+    #[error("the VM didn't answer within {0:?}")]
+    Timeout(Duration),
+    #[error("unexpected response: {0}")]
+    UnexpectedResponse(VmResponse),
+}
+
+#[cfg(unix)]
+pub type VmClientResult<T> = std::result::Result<T, VmClientError>;
+
+/// Typed client for the control socket of a VM.
+///
+/// Each request opens a new connection. Connecting and waiting for the response both time out,
+/// so a VM that died or hangs without closing its socket doesn't block the caller.
+#[cfg(unix)]
+#[derive(Clone, Debug)]
+pub struct VmClient {
+    socket_path: PathBuf,
+    timeout: Duration,
+}
+
+#[cfg(unix)]
+impl VmClient {
+    /// Creates a client for the control socket at `socket_path` that uses
+    /// `VM_CLIENT_DEFAULT_TIMEOUT`.
+    pub fn new<P: Into<PathBuf>>(socket_path: P) -> VmClient {
+        VmClient::with_timeout(socket_path, VM_CLIENT_DEFAULT_TIMEOUT)
+    }
+
+    /// Creates a client for the control socket at `socket_path` that gives up on connecting or on
+    /// a response after `timeout`.
+    pub fn with_timeout<P: Into<PathBuf>>(socket_path: P, timeout: Duration) -> VmClient {
+        VmClient {
+            socket_path: socket_path.into(),
+            timeout,
+        }
+    }
+
+    /// Sends `request` and returns the response, whatever it is.
+    pub fn request(&self, request: &VmRequest) -> VmClientResult<VmResponse> {
+        let connection = connect_vm_control_with_timeout(&self.socket_path, self.timeout).map_err(
+            |e| match e.raw_os_error() {
+                Some(libc::ETIMEDOUT) => VmClientError::Timeout(self.timeout),
+                _ => VmClientError::Connect(self.socket_path.clone(), e),
+            },
+        )?;
+        connection.request(request).map_err(|e| match e {
+            TubeError::Recv(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                VmClientError::Timeout(self.timeout)
+            }
+            TubeError::Send(ref e) if e.errno() == libc::EAGAIN => {
+                VmClientError::Timeout(self.timeout)
+            }
+            e => VmClientError::Request(e),
+        })
+    }
+
+    /// Sends `request`, which is only answered with `VmResponse::Ok` on success.
+    fn request_ok(&self, request: &VmRequest) -> VmClientResult<()> {
+        match self.request(request)? {
+            VmResponse::Ok => Ok(()),
+            VmResponse::Err(e) => Err(VmClientError::Response(e)),
+            r => Err(VmClientError::UnexpectedResponse(r)),
+        }
+    }
+
+    /// Sends `VmRequest::Suspend` or `VmRequest::Resume`, and returns the acknowledgement of the
+    /// vcpus if the VM sent one.
+    fn run_state_request(&self, request: &VmRequest) -> VmClientResult<VmResponse> {
+        match self.request(request)? {
+            r @ (VmResponse::Ok | VmResponse::RunStateChanged { .. }) => Ok(r),
+            VmResponse::Err(e) => Err(VmClientError::Response(e)),
+            r => Err(VmClientError::UnexpectedResponse(r)),
+        }
+    }
+
+    /// Stops the VM.
+    pub fn stop(&self) -> VmClientResult<()> {
+        self.request_ok(&VmRequest::Exit)
+    }
+
+    /// Suspends the vcpus of the VM.
+    pub fn suspend(&self) -> VmClientResult<VmResponse> {
+        self.run_state_request(&VmRequest::Suspend)
+    }
+
+    /// Resumes the vcpus of a suspended VM.
+    pub fn resume(&self) -> VmClientResult<VmResponse> {
+        self.run_state_request(&VmRequest::Resume)
+    }
+
+    /// Sets the size of the balloon of the VM to `num_bytes`.
+    pub fn balloon(&self, num_bytes: u64) -> VmClientResult<()> {
+        self.request_ok(&VmRequest::BalloonCommand(BalloonControlCommand::Adjust {
+            num_bytes,
+        }))
+    }
+
+    /// Sends `command` to the GPU device of the VM.
+    #[cfg(feature = "gpu")]
+    pub fn gpu(&self, command: GpuControlCommand) -> VmClientResult<GpuControlResult> {
+        match self.request(&VmRequest::GpuCommand(command))? {
+            VmResponse::GpuResponse(result) => Ok(result),
+            VmResponse::Err(e) => Err(VmClientError::Response(e)),
+            r => Err(VmClientError::UnexpectedResponse(r)),
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::thread;
+
+    use base::Tube;
+    use base::UnixSeqpacketListener;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::frame::recv_request;
+    use crate::frame::send_response;
+
+    // Listens on a control socket in a new temporary directory.
+    fn listen() -> (TempDir, PathBuf, UnixSeqpacketListener) {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("crosvm.sock");
+        let listener = UnixSeqpacketListener::bind(&path).unwrap();
+        (dir, path, listener)
+    }
+
+    #[test]
+    fn typed_responses() {
+        let (_dir, path, listener) = listen();
+        let server = thread::spawn(move || {
+            for response in [
+                VmResponse::RunStateChanged {
+                    epoch: 1,
+                    vcpus: 2,
+                    elapsed_us: 3,
+                },
+                VmResponse::Err(base::Error::new(libc::ENOTSUP)),
+                VmResponse::Ok,
+            ] {
+                let tube = Tube::new_from_unix_seqpacket(listener.accept().unwrap());
+                let (id, _request) = recv_request(&tube).unwrap();
+                send_response(&tube, id, &response).unwrap();
+            }
+        });
+
+        let client = VmClient::new(path);
+        match client.suspend().unwrap() {
+            VmResponse::RunStateChanged { epoch, .. } => assert_eq!(epoch, 1),
+            r => panic!("unexpected response {}", r),
+        }
+        match client.balloon(4096) {
+            Err(VmClientError::Response(e)) => assert_eq!(e.errno(), libc::ENOTSUP),
+            r => panic!("unexpected result {:?}", r),
+        }
+        client.stop().unwrap();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn response_timeout() {
+        let (_dir, path, listener) = listen();
+        let timeout = Duration::from_millis(50);
+        let server = thread::spawn(move || {
+            // Receive the request, but hang instead of answering until the client gave up.
+            let tube = Tube::new_from_unix_seqpacket(listener.accept().unwrap());
+            recv_request(&tube).unwrap();
+            thread::sleep(timeout * 4);
+        });
+
+        match VmClient::with_timeout(path, timeout).stop() {
+            Err(VmClientError::Timeout(t)) => assert_eq!(t, timeout),
+            r => panic!("unexpected result {:?}", r),
+        }
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,418 @@
+// Copyright 2022 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Notifications streamed to control socket clients that subscribed to VM events.
+//!
+//! A client subscribes by sending `VmRequest::SubscribeEvents` on a control connection. Once the
+//! VM acknowledges the subscription with `VmResponse::Ok`, that connection only carries `VmEvent`
+//! messages from the VM to the client, one per packet, until either side closes it. The VM no
+//! longer reads requests from a subscribed connection, so a subscriber that also wants to issue
+//! requests opens a separate control connection for them.
+//!
+//! Each subscriber has its own queue of at most `EVENT_QUEUE_SIZE` events, drained by a thread that
+//! writes them to the connection, so a subscriber that doesn't read its events never stalls the
+//! VM. When the queue is full, the oldest event is dropped to make room for the new one, and the
+//! subscriber receives a `VmEvent::Gap` with the number of dropped events before the events that
+//! followed them.
+
+use std::collections::BTreeSet;
+use std::collections::VecDeque;
+use std::io;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread;
+
+use base::error;
+use base::Tube;
+use base::TubeError;
+use libc::EPIPE;
+use serde::Deserialize;
+use serde::Serialize;
+use sync::Condvar;
+use sync::Mutex;
+
+#[cfg(feature = "gpu")]
+use crate::gpu::DisplayParameters;
+#[cfg(feature = "gpu")]
+use crate::gpu::GuestRequestedModes;
+
+/// Maximum number of events queued for a subscriber that doesn't keep up.
+pub const EVENT_QUEUE_SIZE: usize = 64;
+
+/// Kinds of events a client can subscribe to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum VmEventKind {
+    DisplayAdded,
+    DisplayRemoved,
+    DisplayModified,
+}
+
+/// An event sent to the subscribers of its kind.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum VmEvent {
+    /// A display was added to the gpu device.
+    #[cfg(feature = "gpu")]
+    DisplayAdded {
+        display_id: u32,
+        params: DisplayParameters,
+    },
+    /// A display was removed from the gpu device.
+    #[cfg(feature = "gpu")]
+    DisplayRemoved { display_id: u32 },
+    /// The parameters of a display, or the sizes the guest requested for it, changed.
+    #[cfg(feature = "gpu")]
+    DisplayModified {
+        display_id: u32,
+        params: DisplayParameters,
+        guest_requested: Option<GuestRequestedModes>,
+    },
+    /// `dropped` events were dropped because the subscriber didn't read them quickly enough. Sent
+    /// to every subscriber regardless of the kinds it subscribed to.
+    Gap { dropped: u64 },
+}
+
+impl VmEvent {
+    /// Returns the kind of the event, or `None` for `VmEvent::Gap`.
+    pub fn kind(&self) -> Option<VmEventKind> {
+        match self {
+            #[cfg(feature = "gpu")]
+            VmEvent::DisplayAdded { .. } => Some(VmEventKind::DisplayAdded),
+            #[cfg(feature = "gpu")]
+            VmEvent::DisplayRemoved { .. } => Some(VmEventKind::DisplayRemoved),
+            #[cfg(feature = "gpu")]
+            VmEvent::DisplayModified { .. } => Some(VmEventKind::DisplayModified),
+            VmEvent::Gap { .. } => None,
+        }
+    }
+}
+
+/// Events waiting to be sent to a subscriber.
+#[derive(Default)]
+struct EventQueue {
+    events: VecDeque<VmEvent>,
+    // Number of events dropped since the last one that was sent.
+    dropped: u64,
+    closed: bool,
+}
+
+impl EventQueue {
+    fn push(&mut self, event: VmEvent, capacity: usize) {
+        if self.events.len() >= capacity {
+            self.events.pop_front();
+            self.dropped += 1;
+        }
+        self.events.push_back(event);
+    }
+
+    fn pop(&mut self) -> Option<VmEvent> {
+        if self.dropped != 0 {
+            let dropped = self.dropped;
+            self.dropped = 0;
+            return Some(VmEvent::Gap { dropped });
+        }
+        self.events.pop_front()
+    }
+}
+
+struct Subscriber {
+    // All kinds if empty.
+    kinds: BTreeSet<VmEventKind>,
+    queue: Arc<(Mutex<EventQueue>, Condvar)>,
+    disconnected: Arc<AtomicBool>,
+}
+
+impl Subscriber {
+    fn wants(&self, kind: VmEventKind) -> bool {
+        self.kinds.is_empty() || self.kinds.contains(&kind)
+    }
+}
+
+/// The control connections that subscribed to VM events.
+#[derive(Default)]
+pub struct VmEventSubscribers {
+    subscribers: Vec<Subscriber>,
+}
+
+impl VmEventSubscribers {
+    pub fn new() -> VmEventSubscribers {
+        Default::default()
+    }
+
+    /// Starts sending the events of the given `kinds`, or of all kinds if `kinds` is empty, to
+    /// `tube`.
+    ///
+    /// The subscription must already have been acknowledged on `tube`, after which nothing but
+    /// events may be sent on it.
+    pub fn subscribe(&mut self, tube: Tube, kinds: BTreeSet<VmEventKind>) -> io::Result<()> {
+        let queue = Arc::new((Mutex::new(EventQueue::default()), Condvar::new()));
+        let disconnected = Arc::new(AtomicBool::new(false));
+        let worker_queue = queue.clone();
+        let worker_disconnected = disconnected.clone();
+        thread::Builder::new()
+            .name("vm_event_writer".to_string())
+            .spawn(move || write_events(tube, &worker_queue, &worker_disconnected))?;
+        self.subscribers.push(Subscriber {
+            kinds,
+            queue,
+            disconnected,
+        });
+        Ok(())
+    }
+
+    /// Queues `event` for every subscriber to its kind, without waiting for it to be sent.
+    pub fn publish(&mut self, event: VmEvent) {
+        self.subscribers
+            .retain(|s| !s.disconnected.load(Ordering::Acquire));
+        for subscriber in &self.subscribers {
+            if !event.kind().map_or(true, |kind| subscriber.wants(kind)) {
+                continue;
+            }
+            let (lock, cvar) = &*subscriber.queue;
+            lock.lock().push(event.clone(), EVENT_QUEUE_SIZE);
+            cvar.notify_one();
+        }
+    }
+
+    /// Returns the number of connected subscribers.
+    pub fn len(&self) -> usize {
+        self.subscribers
+            .iter()
+            .filter(|s| !s.disconnected.load(Ordering::Acquire))
+            .count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Drop for VmEventSubscribers {
+    fn drop(&mut self) {
+        // Let the writers send the events that are already queued and close their connection.
+        for subscriber in &self.subscribers {
+            let (lock, cvar) = &*subscriber.queue;
+            lock.lock().closed = true;
+            cvar.notify_one();
+        }
+    }
+}
+
+fn write_events(tube: Tube, queue: &(Mutex<EventQueue>, Condvar), disconnected: &AtomicBool) {
+    let (lock, cvar) = queue;
+    loop {
+        let event = {
+            let mut queue = lock.lock();
+            loop {
+                if let Some(event) = queue.pop() {
+                    break event;
+                }
+                if queue.closed {
+                    return;
+                }
+                queue = cvar.wait(queue);
+            }
+        };
+        if let Err(e) = tube.send(&event) {
+            // Most likely the subscriber closed the connection, which is how it unsubscribes.
+            if !matches!(&e, TubeError::Send(e) if e.errno() == EPIPE) {
+                error!("failed to send VM event to subscriber: {}", e);
+            }
+            disconnected.store(true, Ordering::Release);
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn queue_drops_oldest_with_gap() {
+        let mut queue = EventQueue::default();
+        // The other events depend on optional features, so gap markers stand in for them here.
+        for dropped in 0..5 {
+            queue.push(VmEvent::Gap { dropped }, 3);
+        }
+        assert_eq!(queue.pop(), Some(VmEvent::Gap { dropped: 2 }));
+        assert_eq!(queue.pop(), Some(VmEvent::Gap { dropped: 2 }));
+        assert_eq!(queue.pop(), Some(VmEvent::Gap { dropped: 3 }));
+        assert_eq!(queue.pop(), Some(VmEvent::Gap { dropped: 4 }));
+        assert_eq!(queue.pop(), None);
+    }
+
+    #[test]
+    fn subscriber_disconnect() {
+        let (client, server) = Tube::pair().unwrap();
+        let mut subscribers = VmEventSubscribers::new();
+        subscribers.subscribe(server, BTreeSet::new()).unwrap();
+        assert_eq!(subscribers.len(), 1);
+
+        subscribers.publish(VmEvent::Gap { dropped: 1 });
+        assert_eq!(
+            client.recv::<VmEvent>().unwrap(),
+            VmEvent::Gap { dropped: 1 }
+        );
+
+        drop(client);
+        // The writer notices the subscriber is gone when it fails to send it an event.
+        while !subscribers.is_empty() {
+            subscribers.publish(VmEvent::Gap { dropped: 1 });
+            thread::yield_now();
+        }
+    }
+
+    // Handles the next request received on a control connection like the VM run loop does.
+    #[cfg(feature = "gpu")]
+    fn serve_request(
+        server: Tube,
+        subscribers: &mut VmEventSubscribers,
+        display_tracker: &mut crate::gpu::DisplayTracker,
+        gpu_control_tube: &Tube,
+    ) -> Option<Tube> {
+        let response = match server.recv::<crate::VmRequest>().unwrap() {
+            crate::VmRequest::SubscribeEvents { kinds } => {
+                server.send(&crate::VmResponse::Ok).unwrap();
+                subscribers.subscribe(server, kinds).unwrap();
+                return None;
+            }
+            crate::VmRequest::GpuCommand(cmd) => {
+                let (response, events) = display_tracker.execute(&cmd, gpu_control_tube);
+                for event in events {
+                    subscribers.publish(event);
+                }
+                response
+            }
+            r => panic!("unexpected request {:?}", r),
+        };
+        server.send(&response).unwrap();
+        Some(server)
+    }
+
+    #[cfg(feature = "gpu")]
+    #[test]
+    fn display_events_with_separate_request_connection() {
+        use std::collections::BTreeMap;
+
+        use crate::gpu::*;
+
+        let (gpu_control_tube, gpu_device_tube) = Tube::pair().unwrap();
+        let gpu = thread::spawn(move || {
+            let mut displays = BTreeMap::new();
+            let mut next_id = 0;
+            let mut execute = |cmd: GpuControlCommand| match cmd {
+                GpuControlCommand::AddDisplays { displays: added } => {
+                    for params in added {
+                        displays.insert(next_id, params);
+                        next_id += 1;
+                    }
+                    GpuControlResult::DisplaysUpdated
+                }
+                GpuControlCommand::RemoveDisplays { display_ids } => {
+                    for display_id in display_ids {
+                        displays.remove(&display_id);
+                    }
+                    GpuControlResult::DisplaysUpdated
+                }
+                GpuControlCommand::ListDisplays => GpuControlResult::DisplayList {
+                    displays: displays.clone(),
+                    guest_requested: BTreeMap::new(),
+                    presented: BTreeMap::new(),
+                },
+                GpuControlCommand::Batch { .. }
+                | GpuControlCommand::DisplayState
+                | GpuControlCommand::GetDisplayTrace
+                | GpuControlCommand::SetDisplayMode { .. }
+                | GpuControlCommand::SetRefreshRate { .. } => panic!("unexpected command"),
+            };
+            // Serves until the requester is dropped at the end of the test.
+            loop {
+                let served = serve_gpu_control(&gpu_device_tube, &mut execute);
+                if let GpuControlServed::RecvFailed(_) = served {
+                    break;
+                }
+            }
+        });
+
+        let mut subscribers = VmEventSubscribers::new();
+        let mut display_tracker = DisplayTracker::new();
+        let mut serve = |server| {
+            serve_request(
+                server,
+                &mut subscribers,
+                &mut display_tracker,
+                &gpu_control_tube,
+            )
+        };
+
+        // One client subscribes to all events, another one only to removed displays.
+        let (all_events, server) = Tube::pair().unwrap();
+        all_events
+            .send(&crate::VmRequest::SubscribeEvents {
+                kinds: BTreeSet::new(),
+            })
+            .unwrap();
+        assert!(serve(server).is_none());
+        assert!(matches!(all_events.recv().unwrap(), crate::VmResponse::Ok));
+
+        let (removed_events, server) = Tube::pair().unwrap();
+        removed_events
+            .send(&crate::VmRequest::SubscribeEvents {
+                kinds: [VmEventKind::DisplayRemoved].into_iter().collect(),
+            })
+            .unwrap();
+        assert!(serve(server).is_none());
+        assert!(matches!(
+            removed_events.recv().unwrap(),
+            crate::VmResponse::Ok
+        ));
+
+        // Displays are modified through a third connection, which keeps working for requests.
+        let (requests, server) = Tube::pair().unwrap();
+        requests
+            .send(&crate::VmRequest::GpuCommand(
+                GpuControlCommand::AddDisplays {
+                    displays: vec![DisplayParameters::default()],
+                },
+            ))
+            .unwrap();
+        let server = serve(server).unwrap();
+        assert!(matches!(
+            requests.recv().unwrap(),
+            crate::VmResponse::GpuResponse(GpuControlResult::DisplaysUpdated)
+        ));
+        requests
+            .send(&crate::VmRequest::GpuCommand(
+                GpuControlCommand::RemoveDisplays {
+                    display_ids: vec![0],
+                },
+            ))
+            .unwrap();
+        serve(server).unwrap();
+        assert!(matches!(
+            requests.recv().unwrap(),
+            crate::VmResponse::GpuResponse(GpuControlResult::DisplaysUpdated)
+        ));
+
+        assert_eq!(
+            all_events.recv::<VmEvent>().unwrap(),
+            VmEvent::DisplayAdded {
+                display_id: 0,
+                params: DisplayParameters::default(),
+            }
+        );
+        assert_eq!(
+            all_events.recv::<VmEvent>().unwrap(),
+            VmEvent::DisplayRemoved { display_id: 0 }
+        );
+        assert_eq!(
+            removed_events.recv::<VmEvent>().unwrap(),
+            VmEvent::DisplayRemoved { display_id: 0 }
+        );
+
+        drop(gpu_control_tube);
+        gpu.join().unwrap();
+    }
+}
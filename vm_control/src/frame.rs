@@ -0,0 +1,202 @@
+// Copyright 2022 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Frames matching the responses sent over a control connection to their requests.
+//!
+//! Several tools may share a control connection, each with requests in flight. A client wraps
+//! each request in a `Frame` with an id of its choosing, and the response comes back in a `Frame`
+//! with the same id, so the client can hand it to whoever sent the request even if responses
+//! arrive out of order. Control connections are `SOCK_SEQPACKET` sockets, so the kernel already
+//! delimits each message with its length: a request that was only partially written is dropped
+//! instead of running into the next one.
+//!
+//! A message that isn't a `Frame` is a bare `VmRequest` from a client that predates framing, and
+//! gets a bare `VmResponse`.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+
+use base::Tube;
+use base::TubeResult;
+use serde::Deserialize;
+use serde::Serialize;
+use sync::Mutex;
+
+use crate::VmRequest;
+use crate::VmResponse;
+
+/// A message tagged with the id of the request it belongs to.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Frame<T> {
+    pub id: u64,
+    pub message: T,
+}
+
+/// A request as received on a control connection.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+pub enum VmControlMessage {
+    Framed(Frame<VmRequest>),
+    /// A request from a client that doesn't use frames.
+    Legacy(VmRequest),
+}
+
+/// Receives a request from a control connection, along with the id of its frame if it had one.
+pub fn recv_request(tube: &Tube) -> TubeResult<(Option<u64>, VmRequest)> {
+    Ok(match tube.recv()? {
+        VmControlMessage::Framed(frame) => (Some(frame.id), frame.message),
+        VmControlMessage::Legacy(request) => (None, request),
+    })
+}
+
+/// Sends the response to a request received with `recv_request`, framed with the same id if the
+/// request was framed.
+pub fn send_response(tube: &Tube, id: Option<u64>, response: &VmResponse) -> TubeResult<()> {
+    match id {
+        Some(id) => tube.send(&Frame {
+            id,
+            message: response,
+        }),
+        None => tube.send(response),
+    }
+}
+
+/// Client end of a control connection that can be shared by threads issuing requests
+/// concurrently.
+pub struct VmControlConnection {
+    send_tube: Mutex<Tube>,
+    recv_tube: Mutex<Tube>,
+    next_id: AtomicU64,
+    /// Responses received by a thread for the requests of other threads.
+    responses: Mutex<BTreeMap<u64, VmResponse>>,
+}
+
+impl VmControlConnection {
+    /// Creates a connection from two tubes to the same socket, one used to send requests and the
+    /// other to receive responses.
+    pub fn new(send_tube: Tube, recv_tube: Tube) -> VmControlConnection {
+        VmControlConnection {
+            send_tube: Mutex::new(send_tube),
+            recv_tube: Mutex::new(recv_tube),
+            next_id: AtomicU64::new(0),
+            responses: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Sends `request` and waits for its response.
+    pub fn request(&self, request: &VmRequest) -> TubeResult<VmResponse> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.send_tube.lock().send(&Frame {
+            id,
+            message: request,
+        })?;
+        loop {
+            let recv_tube = self.recv_tube.lock();
+            // Another thread may have received the response while this one waited for the tube.
+            if let Some(response) = self.responses.lock().remove(&id) {
+                return Ok(response);
+            }
+            let frame: Frame<VmResponse> = recv_tube.recv()?;
+            if frame.id == id {
+                return Ok(frame.message);
+            }
+            self.responses.lock().insert(frame.id, frame.message);
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+
+    use base::UnixSeqpacket;
+
+    use super::*;
+
+    fn connection() -> (VmControlConnection, Tube) {
+        let (client, server) = UnixSeqpacket::pair().unwrap();
+        let client_clone = client.try_clone().unwrap();
+        (
+            VmControlConnection::new(
+                Tube::new_from_unix_seqpacket(client),
+                Tube::new_from_unix_seqpacket(client_clone),
+            ),
+            Tube::new_from_unix_seqpacket(server),
+        )
+    }
+
+    // Answers a `SetKernelCmdline` request with the command line as the only degraded device.
+    fn echo(request: VmRequest) -> VmResponse {
+        match request {
+            VmRequest::SetKernelCmdline(cmdline) => VmResponse::DegradedDevices {
+                devices: vec![cmdline],
+            },
+            _ => panic!("unexpected request"),
+        }
+    }
+
+    #[test]
+    fn interleaved_requests() {
+        const REQUESTS_PER_THREAD: usize = 20;
+        let (connection, server) = connection();
+        let connection = Arc::new(connection);
+
+        let server_thread = thread::spawn(move || {
+            // Answer the requests two at a time in reverse order, so that the responses of each
+            // thread are received by the other one.
+            for _ in 0..REQUESTS_PER_THREAD {
+                let first = recv_request(&server).unwrap();
+                let second = recv_request(&server).unwrap();
+                for (id, request) in [second, first] {
+                    assert!(id.is_some());
+                    send_response(&server, id, &echo(request)).unwrap();
+                }
+            }
+        });
+
+        let clients: Vec<_> = ["a", "b"]
+            .into_iter()
+            .map(|name| {
+                let connection = connection.clone();
+                thread::spawn(move || {
+                    for i in 0..REQUESTS_PER_THREAD {
+                        let cmdline = format!("{}{}", name, i);
+                        let request = VmRequest::SetKernelCmdline(cmdline.clone());
+                        match connection.request(&request).unwrap() {
+                            VmResponse::DegradedDevices { devices } => {
+                                assert_eq!(devices, [cmdline])
+                            }
+                            r => panic!("unexpected response {}", r),
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        for client in clients {
+            client.join().unwrap();
+        }
+        server_thread.join().unwrap();
+        assert!(connection.responses.lock().is_empty());
+    }
+
+    #[test]
+    fn legacy_request() {
+        let (client, server) = Tube::pair().unwrap();
+        client
+            .send(&VmRequest::SetKernelCmdline("legacy".to_owned()))
+            .unwrap();
+
+        let (id, request) = recv_request(&server).unwrap();
+        assert_eq!(id, None);
+        send_response(&server, id, &echo(request)).unwrap();
+
+        match client.recv::<VmResponse>().unwrap() {
+            VmResponse::DegradedDevices { devices } => assert_eq!(devices, ["legacy"]),
+            r => panic!("unexpected response {}", r),
+        }
+    }
+}
@@ -8,13 +8,27 @@ use std::fmt::Display;
 #[cfg(windows)]
 use std::marker::PhantomData;
 use std::path::Path;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use std::time::Instant;
 
+use base::error;
+use base::warn;
+use base::Error as SysError;
+use base::ReadNotifier;
+use base::Tube;
+use base::TubeError;
+use base::WaitContext;
+use libc::EIO;
+use libc::ETIMEDOUT;
 use serde::Deserialize;
 use serde::Serialize;
 use serde_keyvalue::FromKeyValues;
 
 pub use crate::sys::DisplayMode;
 
+use crate::events::VmEvent;
 pub use crate::sys::handle_request;
 pub use crate::*;
 
@@ -22,6 +36,9 @@ pub const DEFAULT_DISPLAY_WIDTH: u32 = 1280;
 pub const DEFAULT_DISPLAY_HEIGHT: u32 = 1024;
 pub const DEFAULT_REFRESH_RATE: u32 = 60;
 
+/// How long `forward_gpu_command` waits for the gpu device to answer a command.
+const GPU_CONTROL_TIMEOUT: Duration = Duration::from_secs(5);
+
 fn default_refresh_rate() -> u32 {
     DEFAULT_REFRESH_RATE
 }
@@ -37,15 +54,144 @@ impl Default for DisplayMode {
     }
 }
 
+/// What to do when the guest sets a scanout to a size other than the size of its display.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ResizePolicy {
+    /// Keep the host window at its size and scale the guest image to it.
+    Scale,
+    /// Resize the host window to the size set by the guest.
+    ResizeHostWindow,
+    /// Refuse the size, and have the guest read the display info, which still has the size of the
+    /// display, again.
+    Reject,
+}
+
+impl Default for ResizePolicy {
+    fn default() -> Self {
+        ResizePolicy::Scale
+    }
+}
+
+impl Display for ResizePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ResizePolicy::Scale => write!(f, "scale"),
+            ResizePolicy::ResizeHostWindow => write!(f, "resize-host-window"),
+            ResizePolicy::Reject => write!(f, "reject"),
+        }
+    }
+}
+
+/// Which virtio-input devices receive the pointer events of a display's window.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DisplayInput {
+    /// The touchscreens shared by every display without one of its own.
+    Shared,
+    /// An absolute pointer device of the display's own, whose range is the size of the display.
+    PerDisplay,
+}
+
+impl Default for DisplayInput {
+    fn default() -> Self {
+        DisplayInput::Shared
+    }
+}
+
+impl Display for DisplayInput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DisplayInput::Shared => write!(f, "shared"),
+            DisplayInput::PerDisplay => write!(f, "per-display"),
+        }
+    }
+}
+
+/// Parameters of a display, as given to `--gpu-display` in key-value form.
+///
+/// The accepted keys are part of the command line interface: a renamed key must keep being
+/// accepted under its old name through `DisplayParametersCompat`, and the `tests` module below
+/// must cover every key.
 #[derive(Clone, Debug, PartialEq, Deserialize, FromKeyValues, Serialize)]
-#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+#[serde(try_from = "DisplayParametersCompat", rename_all = "kebab-case")]
 pub struct DisplayParameters {
-    #[serde(default)]
     pub mode: DisplayMode,
-    #[serde(default)]
     pub hidden: bool,
-    #[serde(default = "default_refresh_rate")]
     pub refresh_rate: u32,
+    pub resize_policy: ResizePolicy,
+    pub input: DisplayInput,
+    /// Three letter manufacturer ID reported in the EDID.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub manufacturer: Option<String>,
+    /// Product code reported in the EDID.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub product_id: Option<u16>,
+    /// Serial number reported in the EDID, generated from the display index if not given.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub serial: Option<u32>,
+}
+
+/// Everything `DisplayParameters` can be deserialized from, including keys that were replaced.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct DisplayParametersCompat {
+    mode: Option<DisplayMode>,
+    #[serde(default)]
+    hidden: bool,
+    #[serde(default = "default_refresh_rate")]
+    refresh_rate: u32,
+    #[serde(default)]
+    resize_policy: ResizePolicy,
+    #[serde(default)]
+    input: DisplayInput,
+    manufacturer: Option<String>,
+    product_id: Option<u16>,
+    serial: Option<u32>,
+    // Replaced by `mode=windowed[width,height]`.
+    width: Option<u32>,
+    height: Option<u32>,
+}
+
+/// Checks that `manufacturer` can be encoded as an EDID manufacturer ID, which is made of three
+/// letters.
+pub fn check_manufacturer(manufacturer: &str) -> std::result::Result<(), String> {
+    if manufacturer.len() != 3 || !manufacturer.bytes().all(|b| b.is_ascii_alphabetic()) {
+        return Err(format!(
+            "manufacturer {:?} is not made of three ASCII letters",
+            manufacturer
+        ));
+    }
+    Ok(())
+}
+
+impl TryFrom<DisplayParametersCompat> for DisplayParameters {
+    type Error = String;
+
+    fn try_from(params: DisplayParametersCompat) -> std::result::Result<Self, Self::Error> {
+        let mode = match (params.mode, params.width, params.height) {
+            (Some(mode), None, None) => mode,
+            (Some(_), _, _) => return Err("`width` and `height` can't be used with `mode`".into()),
+            (None, None, None) => DisplayMode::default(),
+            (None, width, height) => DisplayMode::Windowed(
+                width.unwrap_or(DEFAULT_DISPLAY_WIDTH),
+                height.unwrap_or(DEFAULT_DISPLAY_HEIGHT),
+            ),
+        };
+        if let Some(manufacturer) = &params.manufacturer {
+            check_manufacturer(manufacturer)?;
+        }
+        Ok(DisplayParameters {
+            mode,
+            hidden: params.hidden,
+            refresh_rate: params.refresh_rate,
+            resize_policy: params.resize_policy,
+            input: params.input,
+            manufacturer: params.manufacturer,
+            product_id: params.product_id,
+            serial: params.serial,
+        })
+    }
 }
 
 impl DisplayParameters {
@@ -54,6 +200,11 @@ impl DisplayParameters {
             mode,
             hidden,
             refresh_rate,
+            resize_policy: Default::default(),
+            input: Default::default(),
+            manufacturer: None,
+            product_id: None,
+            serial: None,
         }
     }
 
@@ -72,23 +223,225 @@ impl Default for DisplayParameters {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// Formats the parameters in the key-value form accepted by `--gpu-display`, with every key that
+/// has a value.
+impl Display for DisplayParameters {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "mode={},hidden={},refresh-rate={},resize-policy={},input={}",
+            self.mode, self.hidden, self.refresh_rate, self.resize_policy, self.input
+        )?;
+        if let Some(manufacturer) = &self.manufacturer {
+            write!(f, ",manufacturer={}", manufacturer)?;
+        }
+        if let Some(product_id) = self.product_id {
+            write!(f, ",product-id={}", product_id)?;
+        }
+        if let Some(serial) = self.serial {
+            write!(f, ",serial={}", serial)?;
+        }
+        Ok(())
+    }
+}
+
+/// A display negotiation step recorded by the gpu device.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DisplayTraceEvent {
+    /// The guest requested the EDID of a scanout.
+    GetEdid { scanout_id: u32 },
+    /// An EDID block was generated for a scanout from the given display info.
+    EdidGenerated {
+        scanout_id: u32,
+        width: u32,
+        height: u32,
+        refresh_rate: u32,
+        /// Number of times the mode of the scanout changed before the EDID was generated.
+        generation: u64,
+        edid: Vec<u8>,
+    },
+    /// Generating the EDID of a scanout failed.
+    EdidFailed { scanout_id: u32, error: String },
+    /// The guest set the resource displayed by a scanout.
+    SetScanout {
+        scanout_id: u32,
+        resource_id: u32,
+        /// Dimensions of the scanout for blob resources.
+        blob_size: Option<(u32, u32)>,
+        /// Error returned to the guest, if the request was rejected.
+        error: Option<String>,
+    },
+    /// The scanout was reported disconnected, or connected again, because the guest didn't read
+    /// its new EDID after being notified of the change.
+    SimulatedHotplug { scanout_id: u32, connected: bool },
+}
+
+/// An entry of the gpu display trace.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DisplayTraceEntry {
+    /// Sequence number of the entry. Gaps indicate entries dropped by rate limiting.
+    pub seq: u64,
+    /// Time the entry was recorded, relative to the creation of the trace.
+    pub timestamp: Duration,
+    pub event: DisplayTraceEvent,
+}
+
+/// Display sizes the guest set on a scanout, compared to the size the host display provides.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GuestRequestedModes {
+    /// The most recent sizes set by the guest, oldest first.
+    pub requested: Vec<(u32, u32)>,
+    /// Size of the host display backing the scanout.
+    pub granted: (u32, u32),
+    /// Number of scanout updates whose size didn't match `granted`.
+    pub mismatches: u64,
+}
+
+/// What the guest did with a scanout, as opposed to the parameters of the display backing it.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScanoutState {
+    /// Whether the guest set a resource on the scanout, rather than disabling it.
+    pub guest_enabled: bool,
+    /// Size of the resource the guest set on the scanout, while it is enabled.
+    pub current_mode: Option<(u32, u32)>,
+    /// Whether the scanout has a host surface to present the guest's resource on.
+    pub framebuffer_attached: bool,
+    /// Number of flushes of the scanout's resource presented on the host surface.
+    pub frames_presented: u64,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
 pub enum GpuControlCommand {
-    AddDisplays { displays: Vec<DisplayParameters> },
+    AddDisplays {
+        displays: Vec<DisplayParameters>,
+    },
+    /// Applies several display changes in one go. Every command is checked against the displays
+    /// as the commands before it leave them before any is applied, so the batch is applied
+    /// entirely or not at all, and the guest is notified of the changes once.
+    ///
+    /// Only `AddDisplays`, `RemoveDisplays`, `SetDisplayMode` and `SetRefreshRate` can be batched.
+    Batch {
+        commands: Vec<GpuControlCommand>,
+    },
+    /// Reports the state of the scanouts of the displays as set up by the guest.
+    DisplayState,
+    GetDisplayTrace,
     ListDisplays,
-    RemoveDisplays { display_ids: Vec<u32> },
+    RemoveDisplays {
+        display_ids: Vec<u32>,
+    },
+    /// Replaces the parameters of a display, changing its size and EDID in place so the guest
+    /// keeps its output instead of seeing it removed and added again.
+    SetDisplayMode {
+        display_id: u32,
+        parameters: DisplayParameters,
+    },
+    /// Changes the refresh rate advertised in the EDID of a display, keeping its size.
+    SetRefreshRate {
+        display_id: u32,
+        refresh_rate: u32,
+    },
 }
 
+/// A `GpuControlCommand` as sent to the gpu device, numbered so that a result the device sends
+/// after its requester gave up waiting isn't taken for the result of the next command.
 #[derive(Serialize, Deserialize, Debug)]
+pub struct GpuControlRequest {
+    pub seq: u64,
+    pub command: GpuControlCommand,
+}
+
+/// The result of the `GpuControlRequest` numbered `seq`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GpuControlResponse {
+    pub seq: u64,
+    pub result: GpuControlResult,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub enum GpuControlResult {
+    /// Every command of a `GpuControlCommand::Batch` was applied, with these results.
+    BatchApplied {
+        results: Vec<GpuControlResult>,
+    },
+    /// The command at `index` of a `GpuControlCommand::Batch` would fail with `result`, so none
+    /// of the commands were applied.
+    BatchRejected {
+        index: usize,
+        result: Box<GpuControlResult>,
+    },
     DisplaysUpdated,
+    /// The mode of `display_id` was changed by `GpuControlCommand::SetDisplayMode`.
+    DisplayModeSet {
+        display_id: u32,
+    },
     DisplayList {
         displays: Map<u32, DisplayParameters>,
+        /// Sizes requested by the guest for the displays that it has configured.
+        guest_requested: Map<u32, GuestRequestedModes>,
+        /// Size of the image presented on each display, which differs from the size in its
+        /// parameters after the guest resized a display with `ResizePolicy::ResizeHostWindow`.
+        presented: Map<u32, (u32, u32)>,
+    },
+    DisplayState {
+        displays: Map<u32, ScanoutState>,
+    },
+    DisplayTrace {
+        entries: Vec<DisplayTraceEntry>,
+        /// Number of entries dropped because they were recorded too quickly.
+        rate_limited: u64,
     },
     TooManyDisplays(usize),
+    InvalidDisplay {
+        reason: String,
+    },
     NoSuchDisplay {
         display_id: u32,
     },
+    /// The gpu device has no display backend to apply the command to, e.g. because none of the
+    /// backends could be opened.
+    DisplayBackendUnavailable {
+        reason: String,
+    },
+    /// No EDID can be generated for the parameters given to `display_id`.
+    EdidGenerationFailed {
+        display_id: u32,
+        reason: String,
+    },
+    /// The `field` of the command can't be applied, e.g. a display setting that can't change.
+    InvalidParameters {
+        field: String,
+        reason: String,
+    },
+}
+
+impl GpuControlResult {
+    /// Returns whether the command failed, in which case nothing was changed.
+    pub fn is_error(&self) -> bool {
+        use self::GpuControlResult::*;
+
+        match self {
+            BatchApplied { .. }
+            | DisplaysUpdated
+            | DisplayModeSet { .. }
+            | DisplayList { .. }
+            | DisplayState { .. }
+            | DisplayTrace { .. } => false,
+            BatchRejected { .. }
+            | TooManyDisplays(_)
+            | InvalidDisplay { .. }
+            | NoSuchDisplay { .. }
+            | DisplayBackendUnavailable { .. }
+            | EdidGenerationFailed { .. }
+            | InvalidParameters { .. } => true,
+        }
+    }
+
+    /// Serializes the result to JSON, for tools that react to specific results rather than
+    /// parsing the text of `Display`.
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string(self)
+    }
 }
 
 impl Display for GpuControlResult {
@@ -96,25 +449,278 @@ impl Display for GpuControlResult {
         use self::GpuControlResult::*;
 
         match self {
+            BatchApplied { results } => {
+                write!(f, "batch applied")?;
+                for (index, result) in results.iter().enumerate() {
+                    write!(f, "\n{}: {}", index, result)?;
+                }
+                Ok(())
+            }
+            BatchRejected { index, result } => {
+                write!(f, "batch_rejected {}: {}", index, result)
+            }
             DisplaysUpdated => write!(f, "displays updated"),
-            DisplayList { displays } => {
+            DisplayModeSet { display_id } => write!(f, "display {} mode set", display_id),
+            DisplayList {
+                displays,
+                guest_requested,
+                presented,
+            } => {
                 let json: serde_json::Value = serde_json::json!({
                     "displays": displays,
+                    "guest_requested": guest_requested,
+                    "presented": presented,
+                });
+                let json_pretty =
+                    serde_json::to_string_pretty(&json).map_err(|_| std::fmt::Error)?;
+                write!(f, "{}", json_pretty)
+            }
+            DisplayState { displays } => {
+                let json: serde_json::Value = serde_json::json!({
+                    "displays": displays,
+                });
+                let json_pretty =
+                    serde_json::to_string_pretty(&json).map_err(|_| std::fmt::Error)?;
+                write!(f, "{}", json_pretty)
+            }
+            DisplayTrace {
+                entries,
+                rate_limited,
+            } => {
+                let json: serde_json::Value = serde_json::json!({
+                    "entries": entries,
+                    "rate_limited": rate_limited,
                 });
                 let json_pretty =
                     serde_json::to_string_pretty(&json).map_err(|_| std::fmt::Error)?;
                 write!(f, "{}", json_pretty)
             }
             TooManyDisplays(n) => write!(f, "too_many_displays {}", n),
+            InvalidDisplay { reason } => write!(f, "invalid_display {}", reason),
             NoSuchDisplay { display_id } => write!(f, "no_such_display {}", display_id),
+            DisplayBackendUnavailable { reason } => {
+                write!(f, "display_backend_unavailable {}", reason)
+            }
+            EdidGenerationFailed { display_id, reason } => {
+                write!(f, "edid_generation_failed {}: {}", display_id, reason)
+            }
+            InvalidParameters { field, reason } => {
+                write!(f, "invalid_parameters {}: {}", field, reason)
+            }
+        }
+    }
+}
+
+static NEXT_GPU_CONTROL_SEQ: AtomicU64 = AtomicU64::new(0);
+
+/// Sends `cmd` to the gpu device and waits for its result.
+///
+/// Fails with `ETIMEDOUT` if the device doesn't answer within `GPU_CONTROL_TIMEOUT`, in which case
+/// the command may still be applied later.
+pub fn forward_gpu_command(
+    cmd: &GpuControlCommand,
+    gpu_control_tube: &Tube,
+) -> std::result::Result<GpuControlResult, SysError> {
+    forward_gpu_command_timeout(cmd, gpu_control_tube, GPU_CONTROL_TIMEOUT)
+}
+
+fn forward_gpu_command_timeout(
+    cmd: &GpuControlCommand,
+    gpu_control_tube: &Tube,
+    timeout: Duration,
+) -> std::result::Result<GpuControlResult, SysError> {
+    let seq = NEXT_GPU_CONTROL_SEQ.fetch_add(1, Ordering::Relaxed);
+    let request = GpuControlRequest {
+        seq,
+        command: cmd.clone(),
+    };
+    if let Err(e) = gpu_control_tube.send(&request) {
+        error!("fail to send command to gpu control socket: {}", e);
+        return Err(SysError::new(EIO));
+    }
+
+    let wait_ctx =
+        WaitContext::build_with(&[(gpu_control_tube.get_read_notifier(), ())]).map_err(|e| {
+            error!("failed creating WaitContext for gpu control socket: {}", e);
+            e
+        })?;
+    let deadline = Instant::now() + timeout;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let events = wait_ctx.wait_timeout(remaining).map_err(|e| {
+            error!("failed waiting on gpu control socket: {}", e);
+            e
+        })?;
+        if events.is_empty() {
+            error!("gpu device didn't answer {:?} within {:?}", cmd, timeout);
+            return Err(SysError::new(ETIMEDOUT));
+        }
+        let response: GpuControlResponse = gpu_control_tube.recv().map_err(|e| {
+            error!("fail to recv command from gpu control socket: {}", e);
+            SysError::new(EIO)
+        })?;
+        if response.seq == seq {
+            return Ok(response.result);
+        }
+        warn!(
+            "dropping late result of gpu control command {}: {}",
+            response.seq, response.result
+        );
+    }
+}
+
+/// What became of a request read from the gpu control tube by `serve_gpu_control`.
+#[derive(Debug)]
+pub enum GpuControlServed {
+    /// The command was executed and its result sent back.
+    Replied,
+    /// The command was executed but its result couldn't be sent back.
+    Unreported,
+    /// No request could be read. The requester is gone if the tube is disconnected.
+    RecvFailed(TubeError),
+}
+
+/// Reads a request from `gpu_control_tube` and sends back its result, computed by `execute`.
+///
+/// This is the gpu device side of `forward_gpu_command`, to be called when the tube is readable.
+/// `execute` is expected to apply a command entirely or not at all, so that a requester going away
+/// never leaves a command half applied, only applied without the requester knowing.
+pub fn serve_gpu_control<F>(gpu_control_tube: &Tube, execute: F) -> GpuControlServed
+where
+    F: FnOnce(GpuControlCommand) -> GpuControlResult,
+{
+    let request: GpuControlRequest = match gpu_control_tube.recv() {
+        Ok(request) => request,
+        Err(e) => return GpuControlServed::RecvFailed(e),
+    };
+    let response = GpuControlResponse {
+        seq: request.seq,
+        result: execute(request.command),
+    };
+    match gpu_control_tube.send(&response) {
+        Ok(()) => GpuControlServed::Replied,
+        Err(e) => {
+            warn!(
+                "gpu control command {} was applied but its result couldn't be sent: {}: {}",
+                response.seq, response.result, e
+            );
+            GpuControlServed::Unreported
+        }
+    }
+}
+
+/// Tracks the displays of the gpu device to report their changes to event subscribers.
+///
+/// The gpu device only reports its displays when asked, so changes it doesn't make on behalf of
+/// the control socket, like the sizes requested by the guest, are reported the next time the
+/// displays are listed or modified through the control socket.
+#[derive(Default)]
+pub struct DisplayTracker {
+    // `None` until the displays are first listed.
+    known: Option<(Map<u32, DisplayParameters>, Map<u32, GuestRequestedModes>)>,
+}
+
+impl DisplayTracker {
+    pub fn new() -> DisplayTracker {
+        Default::default()
+    }
+
+    /// Sends `cmd` to the gpu device like `VmRequest::execute` does, and returns the response
+    /// along with the events describing how the displays changed since they were last seen.
+    pub fn execute(
+        &mut self,
+        cmd: &GpuControlCommand,
+        gpu_control_tube: &Tube,
+    ) -> (VmResponse, Vec<VmEvent>) {
+        let modifies_displays = matches!(
+            cmd,
+            GpuControlCommand::AddDisplays { .. }
+                | GpuControlCommand::Batch { .. }
+                | GpuControlCommand::RemoveDisplays { .. }
+                | GpuControlCommand::SetDisplayMode { .. }
+                | GpuControlCommand::SetRefreshRate { .. }
+        );
+        if modifies_displays && self.known.is_none() {
+            // Learn about the displays that exist before the change, which aren't new.
+            self.list_displays(gpu_control_tube);
+        }
+
+        let result = match forward_gpu_command(cmd, gpu_control_tube) {
+            Ok(result) => result,
+            Err(e) => return (VmResponse::Err(e), Vec::new()),
+        };
+        let events = match &result {
+            GpuControlResult::BatchApplied { .. }
+            | GpuControlResult::DisplaysUpdated
+            | GpuControlResult::DisplayModeSet { .. } => self.list_displays(gpu_control_tube),
+            GpuControlResult::DisplayList {
+                displays,
+                guest_requested,
+                ..
+            } => self.update(displays.clone(), guest_requested.clone()),
+            _ => Vec::new(),
+        };
+        (VmResponse::GpuResponse(result), events)
+    }
+
+    fn list_displays(&mut self, gpu_control_tube: &Tube) -> Vec<VmEvent> {
+        match forward_gpu_command(&GpuControlCommand::ListDisplays, gpu_control_tube) {
+            Ok(GpuControlResult::DisplayList {
+                displays,
+                guest_requested,
+                ..
+            }) => self.update(displays, guest_requested),
+            Ok(result) => {
+                error!("unexpected result when listing displays: {}", result);
+                Vec::new()
+            }
+            Err(_) => Vec::new(),
         }
     }
+
+    fn update(
+        &mut self,
+        displays: Map<u32, DisplayParameters>,
+        guest_requested: Map<u32, GuestRequestedModes>,
+    ) -> Vec<VmEvent> {
+        let mut events = Vec::new();
+        if let Some((known_displays, known_guest_requested)) = &self.known {
+            for &display_id in known_displays.keys() {
+                if !displays.contains_key(&display_id) {
+                    events.push(VmEvent::DisplayRemoved { display_id });
+                }
+            }
+            for (&display_id, params) in &displays {
+                match known_displays.get(&display_id) {
+                    None => events.push(VmEvent::DisplayAdded {
+                        display_id,
+                        params: params.clone(),
+                    }),
+                    Some(known_params)
+                        if known_params != params
+                            || known_guest_requested.get(&display_id)
+                                != guest_requested.get(&display_id) =>
+                    {
+                        events.push(VmEvent::DisplayModified {
+                            display_id,
+                            params: params.clone(),
+                            guest_requested: guest_requested.get(&display_id).cloned(),
+                        })
+                    }
+                    Some(_) => {}
+                }
+            }
+        }
+        self.known = Some((displays, guest_requested));
+        events
+    }
 }
 
 pub enum ModifyGpuError {
     SocketFailed,
     UnexpectedResponse(VmResponse),
     UnknownCommand(String),
+    /// The gpu device rejected the command with a result for which `is_error` is true.
     GpuControl(GpuControlResult),
 }
 
@@ -136,6 +742,9 @@ pub type ModifyGpuResult = std::result::Result<GpuControlResult, ModifyGpuError>
 impl From<VmResponse> for ModifyGpuResult {
     fn from(response: VmResponse) -> Self {
         match response {
+            VmResponse::GpuResponse(gpu_response) if gpu_response.is_error() => {
+                Err(ModifyGpuError::GpuControl(gpu_response))
+            }
             VmResponse::GpuResponse(gpu_response) => Ok(gpu_response),
             r => Err(ModifyGpuError::UnexpectedResponse(r)),
         }
@@ -152,6 +761,27 @@ pub fn do_gpu_display_add<T: AsRef<Path> + std::fmt::Debug>(
         .into()
 }
 
+/// Removes `display_ids` and adds `displays` with a single request, the removals first so that
+/// the added displays can take the place of the removed ones. Nothing changes if any of them
+/// fails, and the guest is notified once.
+pub fn do_gpu_display_apply<T: AsRef<Path> + std::fmt::Debug>(
+    control_socket_path: T,
+    displays: Vec<DisplayParameters>,
+    display_ids: Vec<u32>,
+) -> ModifyGpuResult {
+    let mut commands = Vec::new();
+    if !display_ids.is_empty() {
+        commands.push(GpuControlCommand::RemoveDisplays { display_ids });
+    }
+    if !displays.is_empty() {
+        commands.push(GpuControlCommand::AddDisplays { displays });
+    }
+    let request = VmRequest::GpuCommand(GpuControlCommand::Batch { commands });
+    handle_request(&request, control_socket_path)
+        .map_err(|_| ModifyGpuError::SocketFailed)?
+        .into()
+}
+
 pub fn do_gpu_display_list<T: AsRef<Path> + std::fmt::Debug>(
     control_socket_path: T,
 ) -> ModifyGpuResult {
@@ -161,6 +791,24 @@ pub fn do_gpu_display_list<T: AsRef<Path> + std::fmt::Debug>(
         .into()
 }
 
+pub fn do_gpu_display_state<T: AsRef<Path> + std::fmt::Debug>(
+    control_socket_path: T,
+) -> ModifyGpuResult {
+    let request = VmRequest::GpuCommand(GpuControlCommand::DisplayState);
+    handle_request(&request, control_socket_path)
+        .map_err(|_| ModifyGpuError::SocketFailed)?
+        .into()
+}
+
+pub fn do_gpu_display_trace<T: AsRef<Path> + std::fmt::Debug>(
+    control_socket_path: T,
+) -> ModifyGpuResult {
+    let request = VmRequest::GpuCommand(GpuControlCommand::GetDisplayTrace);
+    handle_request(&request, control_socket_path)
+        .map_err(|_| ModifyGpuError::SocketFailed)?
+        .into()
+}
+
 pub fn do_gpu_display_remove<T: AsRef<Path> + std::fmt::Debug>(
     control_socket_path: T,
     display_ids: Vec<u32>,
@@ -170,3 +818,475 @@ pub fn do_gpu_display_remove<T: AsRef<Path> + std::fmt::Debug>(
         .map_err(|_| ModifyGpuError::SocketFailed)?
         .into()
 }
+
+pub fn do_gpu_display_set_mode<T: AsRef<Path> + std::fmt::Debug>(
+    control_socket_path: T,
+    display_id: u32,
+    parameters: DisplayParameters,
+) -> ModifyGpuResult {
+    let request = VmRequest::GpuCommand(GpuControlCommand::SetDisplayMode {
+        display_id,
+        parameters,
+    });
+    handle_request(&request, control_socket_path)
+        .map_err(|_| ModifyGpuError::SocketFailed)?
+        .into()
+}
+
+pub fn do_gpu_display_set_refresh_rate<T: AsRef<Path> + std::fmt::Debug>(
+    control_socket_path: T,
+    display_id: u32,
+    refresh_rate: u32,
+) -> ModifyGpuResult {
+    let request = VmRequest::GpuCommand(GpuControlCommand::SetRefreshRate {
+        display_id,
+        refresh_rate,
+    });
+    handle_request(&request, control_socket_path)
+        .map_err(|_| ModifyGpuError::SocketFailed)?
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_keyvalue::from_key_values;
+
+    use super::*;
+
+    fn windowed(width: u32, height: u32) -> DisplayParameters {
+        DisplayParameters::default_with_mode(DisplayMode::Windowed(width, height))
+    }
+
+    #[test]
+    fn display_parameters_keys() {
+        let cases = [
+            ("", DisplayParameters::default()),
+            ("mode=windowed[800,600]", windowed(800, 600)),
+            (
+                "hidden",
+                DisplayParameters {
+                    hidden: true,
+                    ..Default::default()
+                },
+            ),
+            (
+                "hidden=true",
+                DisplayParameters {
+                    hidden: true,
+                    ..Default::default()
+                },
+            ),
+            ("hidden=false", DisplayParameters::default()),
+            (
+                "refresh-rate=90",
+                DisplayParameters {
+                    refresh_rate: 90,
+                    ..Default::default()
+                },
+            ),
+            (
+                "resize-policy=scale",
+                DisplayParameters {
+                    resize_policy: ResizePolicy::Scale,
+                    ..Default::default()
+                },
+            ),
+            (
+                "resize-policy=resize-host-window",
+                DisplayParameters {
+                    resize_policy: ResizePolicy::ResizeHostWindow,
+                    ..Default::default()
+                },
+            ),
+            (
+                "resize-policy=reject",
+                DisplayParameters {
+                    resize_policy: ResizePolicy::Reject,
+                    ..Default::default()
+                },
+            ),
+            (
+                "input=shared",
+                DisplayParameters {
+                    input: DisplayInput::Shared,
+                    ..Default::default()
+                },
+            ),
+            (
+                "input=per-display",
+                DisplayParameters {
+                    input: DisplayInput::PerDisplay,
+                    ..Default::default()
+                },
+            ),
+            (
+                "manufacturer=ABC",
+                DisplayParameters {
+                    manufacturer: Some("ABC".to_string()),
+                    ..Default::default()
+                },
+            ),
+            (
+                "product-id=4660",
+                DisplayParameters {
+                    product_id: Some(0x1234),
+                    ..Default::default()
+                },
+            ),
+            (
+                "serial=305419896",
+                DisplayParameters {
+                    serial: Some(0x12345678),
+                    ..Default::default()
+                },
+            ),
+            (
+                "refresh-rate=30,hidden,mode=windowed[640,480]",
+                DisplayParameters::new(DisplayMode::Windowed(640, 480), true, 30),
+            ),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(
+                from_key_values::<DisplayParameters>(input).unwrap(),
+                expected,
+                "parsing {:?}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn display_parameters_replaced_keys() {
+        assert_eq!(
+            from_key_values::<DisplayParameters>("width=800,height=600").unwrap(),
+            windowed(800, 600)
+        );
+        assert_eq!(
+            from_key_values::<DisplayParameters>("width=800").unwrap(),
+            windowed(800, DEFAULT_DISPLAY_HEIGHT)
+        );
+        assert_eq!(
+            from_key_values::<DisplayParameters>("height=600,hidden").unwrap(),
+            DisplayParameters::new(
+                DisplayMode::Windowed(DEFAULT_DISPLAY_WIDTH, 600),
+                true,
+                DEFAULT_REFRESH_RATE
+            )
+        );
+        assert!(from_key_values::<DisplayParameters>("mode=windowed[800,600],width=640").is_err());
+    }
+
+    #[test]
+    fn display_parameters_invalid() {
+        for input in [
+            "mode=invalid",
+            "mode=windowed",
+            "mode=windowed[800]",
+            "mode=windowed[800,600,1]",
+            "hidden=maybe",
+            "refresh-rate=-1",
+            "refresh-rate=4294967296",
+            "refresh_rate=60",
+            "resize-policy=stretch",
+            "resize-policy=resize_host_window",
+            "input=per_display",
+            "input=none",
+            "unknown=1",
+            "width=wide",
+            "manufacturer=AB",
+            "manufacturer=ABCD",
+            "manufacturer=A1C",
+            "manufacturer=\u{e9}A",
+            "product-id=65536",
+            "serial=-1",
+        ] {
+            assert!(
+                from_key_values::<DisplayParameters>(input).is_err(),
+                "{:?} was accepted",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn display_parameters_display_round_trip() {
+        assert_eq!(
+            DisplayParameters::default().to_string(),
+            "mode=windowed[1280,1024],hidden=false,refresh-rate=60,resize-policy=scale,input=shared"
+        );
+        for params in [
+            DisplayParameters::default(),
+            DisplayParameters::new(DisplayMode::Windowed(640, 480), true, 30),
+            DisplayParameters::new(DisplayMode::Windowed(0, u32::MAX), false, 0),
+            DisplayParameters {
+                resize_policy: ResizePolicy::ResizeHostWindow,
+                ..Default::default()
+            },
+            DisplayParameters {
+                resize_policy: ResizePolicy::Reject,
+                ..Default::default()
+            },
+            DisplayParameters {
+                input: DisplayInput::PerDisplay,
+                ..Default::default()
+            },
+            DisplayParameters {
+                manufacturer: Some("xyz".to_string()),
+                product_id: Some(u16::MAX),
+                serial: Some(0),
+                ..Default::default()
+            },
+        ] {
+            assert_eq!(
+                from_key_values::<DisplayParameters>(&params.to_string()).unwrap(),
+                params
+            );
+        }
+    }
+
+    #[test]
+    fn display_state_output_parses() {
+        let displays = Map::from([
+            (
+                0,
+                ScanoutState {
+                    guest_enabled: true,
+                    current_mode: Some((1280, 720)),
+                    framebuffer_attached: true,
+                    frames_presented: 42,
+                },
+            ),
+            (1, ScanoutState::default()),
+        ]);
+        let output = GpuControlResult::DisplayState {
+            displays: displays.clone(),
+        }
+        .to_string();
+
+        let json: serde_json::Value = serde_json::from_str(&output).unwrap();
+        assert_eq!(
+            json["displays"]["0"],
+            serde_json::json!({
+                "guest_enabled": true,
+                "current_mode": [1280, 720],
+                "framebuffer_attached": true,
+                "frames_presented": 42,
+            })
+        );
+        let parsed: Map<u32, ScanoutState> =
+            serde_json::from_value(json["displays"].clone()).unwrap();
+        assert_eq!(parsed, displays);
+    }
+
+    #[test]
+    fn display_parameters_json_round_trip() {
+        let params = DisplayParameters::new(DisplayMode::Windowed(640, 480), true, 30);
+        let json = serde_json::to_value(&params).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "mode": { "windowed": [640, 480] },
+                "hidden": true,
+                "refresh-rate": 30,
+                "resize-policy": "scale",
+                "input": "shared",
+            })
+        );
+        assert_eq!(
+            serde_json::from_value::<DisplayParameters>(json).unwrap(),
+            params
+        );
+    }
+
+    #[test]
+    fn gpu_control_error_round_trip() {
+        let results = [
+            GpuControlResult::DisplayBackendUnavailable {
+                reason: "failed to open any displays".to_string(),
+            },
+            GpuControlResult::EdidGenerationFailed {
+                display_id: 2,
+                reason: "refresh rate must be at least 1".to_string(),
+            },
+            GpuControlResult::InvalidParameters {
+                field: "input".to_string(),
+                reason: "the input of display 1 can't be changed".to_string(),
+            },
+        ];
+        for result in results {
+            assert!(result.is_error());
+
+            let json = result.to_json().unwrap();
+            let parsed: GpuControlResult = serde_json::from_str(&json).unwrap();
+            assert_eq!(parsed, result);
+
+            let bytes = serde_json::to_vec(&VmResponse::GpuResponse(result)).unwrap();
+            let response: VmResponse = serde_json::from_slice(&bytes).unwrap();
+            assert!(matches!(
+                ModifyGpuResult::from(response),
+                Err(ModifyGpuError::GpuControl(e)) if e == parsed
+            ));
+        }
+    }
+
+    #[test]
+    fn gpu_control_error_json() {
+        let result = GpuControlResult::EdidGenerationFailed {
+            display_id: 1,
+            reason: "bad size".to_string(),
+        };
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&result.to_json().unwrap()).unwrap(),
+            serde_json::json!({
+                "EdidGenerationFailed": { "display_id": 1, "reason": "bad size" }
+            })
+        );
+        assert_eq!(result.to_string(), "edid_generation_failed 1: bad size");
+
+        assert!(!GpuControlResult::DisplaysUpdated.is_error());
+        assert!(matches!(
+            ModifyGpuResult::from(VmResponse::GpuResponse(GpuControlResult::DisplaysUpdated)),
+            Ok(GpuControlResult::DisplaysUpdated)
+        ));
+    }
+
+    #[test]
+    fn display_parameters_arbitrary_input() {
+        const FRAGMENTS: &[&str] = &[
+            "mode",
+            "windowed",
+            "borderless_full_screen",
+            "hidden",
+            "refresh-rate",
+            "resize-policy",
+            "resize-host-window",
+            "input",
+            "per-display",
+            "manufacturer",
+            "product-id",
+            "serial",
+            "width",
+            "height",
+            "=",
+            ",",
+            "[",
+            "]",
+            "\"",
+            "'",
+            " ",
+            "true",
+            "0",
+            "-1",
+            "4294967296",
+            "0x10",
+            "\u{e9}",
+            "\0",
+        ];
+
+        // xorshift64, so failures can be reproduced.
+        let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        for _ in 0..20_000 {
+            let len = next() % 16;
+            let input: String = (0..len)
+                .map(|_| FRAGMENTS[(next() % FRAGMENTS.len() as u64) as usize])
+                .collect();
+            // Only checks that parsing doesn't panic.
+            let _ = from_key_values::<DisplayParameters>(&input);
+        }
+    }
+
+    fn list_displays(_: GpuControlCommand) -> GpuControlResult {
+        GpuControlResult::DisplayList {
+            displays: Map::new(),
+            guest_requested: Map::new(),
+            presented: Map::new(),
+        }
+    }
+
+    #[test]
+    fn gpu_control_requester_gone_before_request() {
+        let (requester, device) = Tube::pair().unwrap();
+        drop(requester);
+        let served = serve_gpu_control(&device, |_| panic!("no command was sent"));
+        assert!(matches!(
+            served,
+            GpuControlServed::RecvFailed(TubeError::Disconnected)
+        ));
+    }
+
+    #[test]
+    fn gpu_control_requester_gone_before_result() {
+        let (requester, device) = Tube::pair().unwrap();
+        requester
+            .send(&GpuControlRequest {
+                seq: 0,
+                command: GpuControlCommand::RemoveDisplays {
+                    display_ids: vec![0],
+                },
+            })
+            .unwrap();
+        drop(requester);
+
+        let mut applied = false;
+        let served = serve_gpu_control(&device, |_| {
+            applied = true;
+            GpuControlResult::DisplaysUpdated
+        });
+        assert!(matches!(served, GpuControlServed::Unreported));
+        assert!(applied);
+    }
+
+    #[test]
+    fn gpu_control_device_gone_before_request() {
+        let (requester, device) = Tube::pair().unwrap();
+        drop(device);
+        assert!(forward_gpu_command(&GpuControlCommand::ListDisplays, &requester).is_err());
+    }
+
+    #[test]
+    fn gpu_control_device_gone_before_result() {
+        let (requester, device) = Tube::pair().unwrap();
+        let device = std::thread::spawn(move || {
+            let _: GpuControlRequest = device.recv().unwrap();
+        });
+        let result = forward_gpu_command(&GpuControlCommand::ListDisplays, &requester);
+        assert_eq!(result.unwrap_err().errno(), EIO);
+        device.join().unwrap();
+    }
+
+    #[test]
+    fn gpu_control_late_result_dropped() {
+        let (requester, device) = Tube::pair().unwrap();
+
+        // The device doesn't answer in time.
+        let result = forward_gpu_command_timeout(
+            &GpuControlCommand::ListDisplays,
+            &requester,
+            Duration::from_millis(10),
+        );
+        assert_eq!(result.unwrap_err().errno(), ETIMEDOUT);
+        assert!(matches!(
+            serve_gpu_control(&device, list_displays),
+            GpuControlServed::Replied
+        ));
+
+        // The late result isn't taken for the result of the next command.
+        let device = std::thread::spawn(move || {
+            serve_gpu_control(&device, |_| GpuControlResult::DisplaysUpdated)
+        });
+        let result = forward_gpu_command(
+            &GpuControlCommand::RemoveDisplays {
+                display_ids: vec![0],
+            },
+            &requester,
+        );
+        assert!(matches!(result, Ok(GpuControlResult::DisplaysUpdated)));
+        assert!(matches!(device.join().unwrap(), GpuControlServed::Replied));
+    }
+}
@@ -6,6 +6,8 @@
 //!
 //! The VM Control IPC protocol is synchronous, meaning that each `VmRequest` sent over a connection
 //! will receive a `VmResponse` for that request next time data is received over that connection.
+//! Clients sharing a connection between threads wrap their requests in frames instead, see the
+//! `frame` module.
 //!
 //! The wire message format is a little-endian C-struct of fixed size, along with a file descriptor
 //! if the request type expects one.
@@ -20,10 +22,15 @@ use base::MemoryMappingBuilderUnix;
 #[cfg(windows)]
 use base::MemoryMappingBuilderWindows;
 
+pub mod boot;
 pub mod client;
 pub mod display;
+pub mod events;
+pub mod frame;
+pub mod snd;
 pub mod sys;
 
+use std::collections::BTreeMap;
 use std::collections::BTreeSet;
 use std::convert::TryInto;
 use std::fmt;
@@ -41,6 +48,7 @@ pub use balloon_control::BalloonStats;
 use balloon_control::BalloonTubeCommand;
 #[cfg(feature = "balloon")]
 use balloon_control::BalloonTubeResult;
+use base::debug;
 use base::error;
 use base::info;
 use base::warn;
@@ -57,18 +65,23 @@ use base::Result;
 use base::SafeDescriptor;
 use base::SharedMemory;
 use base::Tube;
+use base::VmExitReason;
 use hypervisor::Datamatch;
 use hypervisor::IoEventAddress;
 use hypervisor::IrqRoute;
 use hypervisor::IrqSource;
+use hypervisor::MemCacheType;
 pub use hypervisor::MemSlot;
 use hypervisor::Vm;
+use hypervisor::VmCap;
 use libc::EINVAL;
 use libc::EIO;
 use libc::ENODEV;
 use libc::ENOTSUP;
 use libc::ERANGE;
 use remain::sorted;
+use resources::address_allocator::AllocatorStats;
+use resources::AddressRange;
 use resources::Alloc;
 use resources::SystemAllocator;
 use rutabaga_gfx::DeviceId;
@@ -87,8 +100,15 @@ pub use sys::VmMsyncRequest;
 #[cfg(unix)]
 pub use sys::VmMsyncResponse;
 use thiserror::Error;
+use vm_memory::access_fault_counts;
+use vm_memory::BackingObjectStats;
 use vm_memory::GuestAddress;
 
+pub use crate::boot::BootMilestone;
+pub use crate::boot::BootMilestones;
+pub use crate::boot::BootTimes;
+pub use crate::boot::BuildPhase;
+pub use crate::boot::PhaseTimer;
 use crate::display::AspectRatio;
 use crate::display::DisplaySize;
 use crate::display::GuestDisplayDensity;
@@ -96,6 +116,10 @@ use crate::display::MouseMode;
 use crate::display::WindowEvent;
 use crate::display::WindowMode;
 use crate::display::WindowVisibility;
+pub use crate::events::VmEvent;
+pub use crate::events::VmEventKind;
+pub use crate::events::VmEventSubscribers;
+pub use crate::frame::VmControlConnection;
 #[cfg(all(any(target_arch = "x86_64", target_arch = "aarch64"), feature = "gdb"))]
 pub use crate::gdb::VcpuDebug;
 #[cfg(all(any(target_arch = "x86_64", target_arch = "aarch64"), feature = "gdb"))]
@@ -106,6 +130,8 @@ pub use crate::gdb::VcpuDebugStatusMessage;
 use crate::gpu::GpuControlCommand;
 #[cfg(feature = "gpu")]
 use crate::gpu::GpuControlResult;
+pub use crate::snd::SndCapture;
+pub use crate::snd::SndInfo;
 
 /// Control the state of a particular VM CPU.
 #[derive(Clone, Debug)]
@@ -174,6 +200,12 @@ pub enum BalloonControlCommand {
     Adjust {
         num_bytes: u64,
     },
+    /// Set the size of the VM's balloon, taking the pages from the guest memory region at
+    /// `preferred_region_index` (in the order of `GuestMemory::regions`) if the guest supports it.
+    AdjustWithPreference {
+        num_bytes: u64,
+        preferred_region_index: usize,
+    },
     Stats,
 }
 
@@ -386,6 +418,8 @@ pub enum VmMemoryRequest {
         dest: VmMemoryDestination,
         /// Whether to map the memory read only (true) or read-write (false).
         prot: Protection,
+        /// The caching attributes the guest should see for the memory.
+        cache: MemCacheType,
     },
     /// Call hypervisor to free the given memory range.
     DynamicallyFreeMemoryRange {
@@ -444,7 +478,12 @@ impl VmMemoryRequest {
     ) -> VmMemoryResponse {
         use self::VmMemoryRequest::*;
         match self {
-            RegisterMemory { source, dest, prot } => {
+            RegisterMemory {
+                source,
+                dest,
+                prot,
+                cache,
+            } => {
                 // Correct on Windows because callers of this IPC guarantee descriptor is a mapping
                 // handle.
                 let (mapped_region, size, descriptor) = match source.map(gralloc, prot) {
@@ -457,11 +496,26 @@ impl VmMemoryRequest {
                     Err(e) => return VmMemoryResponse::Err(e),
                 };
 
+                // The cache type is a performance hint for the mappings that request it (e.g. gpu
+                // blobs), so fall back to a cached mapping instead of failing.
+                let cache = if cache != MemCacheType::Cached
+                    && !vm.check_capability(VmCap::MemCacheAttributes)
+                {
+                    debug!(
+                        "hypervisor can't map memory as {:?}, mapping it as cached",
+                        cache
+                    );
+                    MemCacheType::Cached
+                } else {
+                    cache
+                };
+
                 let slot = match vm.add_memory_region(
                     guest_addr,
                     mapped_region,
                     prot == Protection::read(),
                     false,
+                    cache,
                 ) {
                     Ok(slot) => slot,
                     Err(e) => return VmMemoryResponse::Err(e),
@@ -701,7 +755,7 @@ impl Display for BatControlResult {
             Ok => write!(f, "Setting battery property successfully"),
             NoBatDevice => write!(f, "No battery device created"),
             NoSuchHealth => write!(f, "Invalid Battery health setting. Only support: unknown/good/overheat/dead/overvoltage/unexpectedfailure/cold/watchdogtimerexpire/safetytimerexpire/overcurrent"),
-            NoSuchProperty => write!(f, "Battery doesn't have such property. Only support: status/health/present/capacity/aconline"),
+            NoSuchProperty => write!(f, "Battery doesn't have such property. Only support: status/health/present/capacity/aconline/temperature/cyclecount"),
             NoSuchStatus => write!(f, "Invalid Battery status setting. Only support: unknown/charging/discharging/notcharging/full"),
             NoSuchBatType => write!(f, "Invalid Battery type setting. Only support: goldfish"),
             StringParseIntErr => write!(f, "Battery property target ParseInt error"),
@@ -739,6 +793,8 @@ pub enum BatProperty {
     Present,
     Capacity,
     ACOnline,
+    Temperature,
+    CycleCount,
 }
 
 impl FromStr for BatProperty {
@@ -751,6 +807,8 @@ impl FromStr for BatProperty {
             "present" => Ok(BatProperty::Present),
             "capacity" => Ok(BatProperty::Capacity),
             "aconline" => Ok(BatProperty::ACOnline),
+            "temperature" => Ok(BatProperty::Temperature),
+            "cyclecount" => Ok(BatProperty::CycleCount),
             _ => Err(BatControlResult::NoSuchProperty),
         }
     }
@@ -846,6 +904,9 @@ pub enum BatControlCommand {
     SetPresent(u32),
     SetCapacity(u32),
     SetACOnline(u32),
+    /// Battery temperature in tenths of a degree Celsius.
+    SetTemperature(i32),
+    SetCycleCount(u32),
 }
 
 impl BatControlCommand {
@@ -869,6 +930,16 @@ impl BatControlCommand {
                     .parse::<u32>()
                     .map_err(|_| BatControlResult::StringParseIntErr)?,
             )),
+            BatProperty::Temperature => Ok(BatControlCommand::SetTemperature(
+                target
+                    .parse::<i32>()
+                    .map_err(|_| BatControlResult::StringParseIntErr)?,
+            )),
+            BatProperty::CycleCount => Ok(BatControlCommand::SetCycleCount(
+                target
+                    .parse::<u32>()
+                    .map_err(|_| BatControlResult::StringParseIntErr)?,
+            )),
         }
     }
 }
@@ -921,9 +992,11 @@ pub enum VmRequest {
     Powerbtn,
     /// Trigger a sleep button event in the guest.
     Sleepbtn,
-    /// Suspend the VM's VCPUs until resume.
+    /// Suspend the VM's VCPUs until resume. Answered once the VCPUs acknowledged it, or failed
+    /// with `VmResponse::UnresponsiveVcpus` if some didn't.
     Suspend,
-    /// Resume the VM's VCPUs that were previously suspended.
+    /// Resume the VM's VCPUs that were previously suspended, with the same acknowledgment as
+    /// `Suspend`.
     Resume,
     /// Inject a general-purpose event.
     Gpe(u32),
@@ -942,13 +1015,264 @@ pub enum VmRequest {
     #[cfg(feature = "gpu")]
     /// Command to modify the gpu.
     GpuCommand(GpuControlCommand),
-    /// Command to set battery.
-    BatCommand(BatteryType, BatControlCommand),
+    /// Command to set the battery at `index` among the batteries of type `type_`.
+    BatCommand {
+        type_: BatteryType,
+        index: usize,
+        command: BatControlCommand,
+    },
     /// Command to add/remove multiple pci devices
     HotPlugCommand {
         device: HotPlugDeviceInfo,
         add: bool,
     },
+    /// Query the boot milestones reached by the VM.
+    BootTimes,
+    /// Query the number of guest memory access faults attributed to each device.
+    GuestMemoryFaults,
+    /// Query the number of descriptors held by the regions of guest memory.
+    GuestMemoryBackingObjects,
+    /// Query the optional devices running without an interrupt because it couldn't be set up.
+    DegradedDevices,
+    /// Replace the kernel command line of a VM started with `--start-paused`, before its vcpus
+    /// first run.
+    SetKernelCmdline(String),
+    /// Add or update a record in the metrics page published to the guest.
+    SetMetricsRecord { id: u32, value: u64 },
+    /// Query the most recent guest accesses to MMIO addresses without a device, as recorded when
+    /// the VM runs with `--unmapped-mmio=log` or `--unmapped-mmio=fault`.
+    UnmappedMmioAccesses,
+    /// Turn this control connection into a stream of `VmEvent`s of the given kinds, or of all
+    /// kinds if `kinds` is empty. See the `events` module for the protocol.
+    SubscribeEvents { kinds: BTreeSet<VmEventKind> },
+    /// Query the formats negotiated with the host audio engine for each playback stream.
+    SndInfo,
+    /// Get the last `frames` frames rendered to the host audio engine. Requires loopback capture
+    /// to be enabled when the VM starts.
+    SndCapture { frames: usize },
+    /// Query the identity of the VM and the resources it was given.
+    GetVmInfo,
+    /// Query how much of each address space of the `SystemAllocator` is allocated, and to what.
+    ResourceStats,
+    /// Query the stolen time of `vcpu_id`, or of all the vcpus if `None`, as published to the
+    /// guest through the Arm paravirtualized time interface.
+    PvtimeStats { vcpu_id: Option<usize> },
+    /// Query the devices the architecture code placed at fixed MMIO addresses.
+    ListDevices,
+    /// Get the recent output of serial port `index` of type `hardware`, as named by the
+    /// `hardware` option of `--serial`. The port must have been given a `console_buffer`.
+    SerialBuffer { hardware: String, index: u8 },
+    /// Give `data` to the guest as input of serial port `index` of type `hardware`, as if it had
+    /// been read from the input of the port.
+    SerialInput {
+        hardware: String,
+        index: u8,
+        data: Vec<u8>,
+    },
+    /// Reopen the output file of serial port `index` of type `hardware`, so that a file renamed
+    /// for log rotation stops receiving the output and a new one is created at the path.
+    SerialReopenOutput { hardware: String, index: u8 },
+    /// Command for the virtual watchdog of the vcpus.
+    Vmwdt(VmwdtCommand),
+}
+
+/// Identity of a VM and the resources it was given.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct VmInfo {
+    /// UUID given with `--vm-uuid`, or generated when the VM started.
+    pub uuid: String,
+    pub vcpu_count: usize,
+    /// Size of the guest memory in bytes.
+    pub memory_size: u64,
+    /// Architecture of the guest, e.g. "x86_64" or "aarch64".
+    pub arch: String,
+    /// The last reason given for stopping the VM. While the VM is still running this is only set
+    /// after a guest panic, which doesn't stop it on its own.
+    pub last_exit_reason: Option<VmExitReason>,
+}
+
+impl Display for VmInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            serde_json::to_string_pretty(self).map_err(|_| fmt::Error)?
+        )
+    }
+}
+
+/// Time during which a vcpu was ready to run but the host ran something else.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VcpuStolenTime {
+    pub vcpu_id: usize,
+    pub stolen_time_ns: u64,
+}
+
+/// Commands for the virtual watchdog of the vcpus.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmwdtCommand {
+    /// Query the state of the watchdog of each vcpu.
+    Status,
+    /// Use a timeout of `secs` seconds for the next pets of all the vcpus instead of the one
+    /// programmed by the guest, or go back to the guest's timeout if `secs` is 0.
+    SetTimeout { secs: u32 },
+    /// Stop counting down until `Resume`, e.g. while a debugger holds the vcpus.
+    Pause,
+    /// Restart the countdown of the enabled watchdogs from the current guest time.
+    Resume,
+}
+
+/// State of the watchdog of a vcpu.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VmwdtVcpuStatus {
+    pub vcpu_id: usize,
+    pub enabled: bool,
+    /// Wall clock time of the last pet, in milliseconds since the Unix epoch, if the guest petted
+    /// the watchdog at all.
+    pub last_pet_unix_ms: Option<u64>,
+    /// Guest run time left before the watchdog bites, if it is enabled.
+    pub remaining_ms: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum VmwdtControlResult {
+    Ok,
+    Status {
+        paused: bool,
+        vcpus: Vec<VmwdtVcpuStatus>,
+    },
+}
+
+impl Display for VmwdtControlResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::VmwdtControlResult::*;
+
+        match self {
+            Ok => write!(f, "ok"),
+            Status { paused, vcpus } => {
+                if *paused {
+                    writeln!(f, "paused")?;
+                }
+                vcpus.iter().try_for_each(|status| {
+                    write!(f, "vcpu {}: ", status.vcpu_id)?;
+                    if !status.enabled {
+                        write!(f, "disabled")?;
+                    } else if let Some(remaining_ms) = status.remaining_ms {
+                        write!(f, "{} ms remaining", remaining_ms)?;
+                    } else {
+                        write!(f, "enabled")?;
+                    }
+                    match status.last_pet_unix_ms {
+                        Some(last_pet_unix_ms) => {
+                            writeln!(f, ", last pet at {} ms since the epoch", last_pet_unix_ms)
+                        }
+                        None => writeln!(f, ", never petted"),
+                    }
+                })
+            }
+        }
+    }
+}
+
+/// A device the architecture code placed at a fixed guest physical address.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct StaticMmioRegion {
+    pub name: String,
+    pub range: AddressRange,
+}
+
+/// Utilization of the address spaces of a `SystemAllocator`, keyed by the name of the pool in
+/// `SystemAllocatorConfig`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ResourceStats {
+    pub pools: BTreeMap<String, AllocatorStats>,
+}
+
+impl Display for ResourceStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<14} {:>18} {:>18} {:>18}",
+            "pool", "size", "allocated", "largest free"
+        )?;
+        for (name, pool) in &self.pools {
+            writeln!(
+                f,
+                "{:<14} {:>#18x} {:>#18x} {:>#18x}",
+                name, pool.size, pool.allocated, pool.largest_free
+            )?;
+            for allocation in &pool.allocations {
+                writeln!(
+                    f,
+                    "    {:<44} {} ({:?})",
+                    allocation.range.to_string(),
+                    allocation.tag,
+                    allocation.alloc
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Vcpus that didn't acknowledge a `VmRequest::Suspend` or `VmRequest::Resume` in time.
+#[derive(Error, Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[error("vcpus {unresponsive:?} did not acknowledge pause epoch {epoch} within {timeout_ms} ms")]
+pub struct UnresponsiveVcpus {
+    pub epoch: u64,
+    pub acked: usize,
+    pub unresponsive: Vec<usize>,
+    pub timeout_ms: u64,
+}
+
+/// Reasons a `VmRequest::SetKernelCmdline` can be rejected.
+#[sorted]
+#[derive(Error, Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum SetKernelCmdlineError {
+    /// The vcpus already started running the guest, which may have read the command line.
+    #[error("the VM already booted")]
+    AlreadyBooted,
+    /// Writing the command line to guest memory failed.
+    #[error("failed to write the kernel command line: {0}")]
+    Failed(String),
+    /// The command line is not valid or too long for the architecture.
+    #[error("invalid kernel command line: {0}")]
+    InvalidCmdline(String),
+    /// The VM does not boot a kernel from crosvm, e.g. it boots from a BIOS.
+    #[error("the VM does not boot a kernel with a command line")]
+    Unsupported,
+}
+
+/// A guest access to an MMIO address without a device.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnmappedAccess {
+    /// Address that was accessed.
+    pub address: u64,
+    /// Size of the access in bytes.
+    pub size: usize,
+    /// Whether the access was a write.
+    pub write: bool,
+    /// Id of the vcpu that made the access.
+    pub vcpu_id: usize,
+    /// Program counter of the access, if known.
+    pub pc: Option<u64>,
+}
+
+impl Display for UnmappedAccess {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "vcpu {} {} {} bytes at {:#x}",
+            self.vcpu_id,
+            if self.write { "wrote" } else { "read" },
+            self.size,
+            self.address
+        )?;
+        if let Some(pc) = self.pc {
+            write!(f, " (pc {:#x})", pc)?;
+        }
+        Ok(())
+    }
 }
 
 pub fn handle_disk_command(command: &DiskControlCommand, disk_host_tube: &Tube) -> VmResponse {
@@ -1033,10 +1357,11 @@ impl VmRequest {
         pm: &mut Option<Arc<Mutex<dyn PmResource>>>,
         #[cfg(feature = "gpu")] gpu_control_tube: &Tube,
         usb_control_tube: Option<&Tube>,
-        bat_control: &mut Option<BatControl>,
+        bat_control: &[BatControl],
         vcpu_handles: &[(JoinHandle<()>, mpsc::Sender<VcpuControl>)],
         force_s2idle: bool,
         guest_suspended_cvar: Arc<(Mutex<bool>, Condvar)>,
+        boot_milestones: &BootMilestones,
     ) -> VmResponse {
         match *self {
             VmRequest::Exit => {
@@ -1119,6 +1444,23 @@ impl VmRequest {
                 }
             }
             #[cfg(feature = "balloon")]
+            VmRequest::BalloonCommand(BalloonControlCommand::AdjustWithPreference {
+                num_bytes,
+                preferred_region_index,
+            }) => {
+                if let Some(balloon_host_tube) = balloon_host_tube {
+                    match balloon_host_tube.send(&BalloonTubeCommand::AdjustWithPreference {
+                        num_bytes,
+                        preferred_region_index,
+                    }) {
+                        Ok(_) => VmResponse::Ok,
+                        Err(_) => VmResponse::Err(SysError::last()),
+                    }
+                } else {
+                    VmResponse::Err(SysError::new(ENOTSUP))
+                }
+            }
+            #[cfg(feature = "balloon")]
             VmRequest::BalloonCommand(BalloonControlCommand::Stats) => {
                 if let Some(balloon_host_tube) = balloon_host_tube {
                     // NB: There are a few reasons stale balloon stats could be left
@@ -1179,17 +1521,9 @@ impl VmRequest {
             },
             #[cfg(feature = "gpu")]
             VmRequest::GpuCommand(ref cmd) => {
-                let res = gpu_control_tube.send(cmd);
-                if let Err(e) = res {
-                    error!("fail to send command to gpu control socket: {}", e);
-                    return VmResponse::Err(SysError::new(EIO));
-                }
-                match gpu_control_tube.recv() {
+                match gpu::forward_gpu_command(cmd, gpu_control_tube) {
                     Ok(response) => VmResponse::GpuResponse(response),
-                    Err(e) => {
-                        error!("fail to recv command from gpu control socket: {}", e);
-                        VmResponse::Err(SysError::new(EIO))
-                    }
+                    Err(e) => VmResponse::Err(e),
                 }
             }
             VmRequest::UsbCommand(ref cmd) => {
@@ -1213,32 +1547,94 @@ impl VmRequest {
                     }
                 }
             }
-            VmRequest::BatCommand(type_, ref cmd) => {
-                match bat_control {
-                    Some(battery) => {
-                        if battery.type_ != type_ {
-                            error!("ignored battery command due to battery type: expected {:?}, got {:?}", battery.type_, type_);
-                            return VmResponse::Err(SysError::new(EINVAL));
-                        }
+            VmRequest::BatCommand {
+                type_,
+                index,
+                ref command,
+            } => handle_bat_command(bat_control, type_, index, command),
+            VmRequest::HotPlugCommand { device: _, add: _ } => VmResponse::Ok,
+            VmRequest::BootTimes => VmResponse::BootTimes(boot_milestones.times()),
+            VmRequest::GuestMemoryFaults => VmResponse::GuestMemoryFaults {
+                faults: access_fault_counts(),
+            },
+            // The guest memory is owned by the run loop, which handles this before calling
+            // `execute`.
+            VmRequest::GuestMemoryBackingObjects => VmResponse::Err(SysError::new(ENOTSUP)),
+            // The degraded devices are only known to the run loop, which handles this before
+            // calling `execute`.
+            VmRequest::DegradedDevices => VmResponse::Err(SysError::new(ENOTSUP)),
+            // Needs the guest memory and boot state owned by the run loop, which handles it
+            // before calling `execute`.
+            VmRequest::SetKernelCmdline(_) => VmResponse::Err(SysError::new(ENOTSUP)),
+            // The metrics page is owned by the run loop, which handles this before calling
+            // `execute`.
+            VmRequest::SetMetricsRecord { .. } => VmResponse::Err(SysError::new(ENOTSUP)),
+            // The MMIO bus is owned by the run loop, which handles this before calling `execute`.
+            VmRequest::UnmappedMmioAccesses => VmResponse::Err(SysError::new(ENOTSUP)),
+            // The run loop owns the control connections, and handles this before calling
+            // `execute` if it supports subscriptions.
+            VmRequest::SubscribeEvents { .. } => VmResponse::Err(SysError::new(ENOTSUP)),
+            VmRequest::SndInfo => sys::snd_info(),
+            VmRequest::SndCapture { frames } => sys::snd_capture(frames),
+            // The VM's configuration is only known to the run loop, which handles this before
+            // calling `execute`.
+            VmRequest::GetVmInfo => VmResponse::Err(SysError::new(ENOTSUP)),
+            // The allocator belongs to the run loop, which handles this before calling `execute`.
+            VmRequest::ResourceStats => VmResponse::Err(SysError::new(ENOTSUP)),
+            // The pvtime region is owned by the run loop, which handles this before calling
+            // `execute`.
+            VmRequest::PvtimeStats { .. } => VmResponse::Err(SysError::new(ENOTSUP)),
+            // The static MMIO map is only known to the run loop, which handles this before
+            // calling `execute`.
+            VmRequest::ListDevices => VmResponse::Err(SysError::new(ENOTSUP)),
+            // The serial port handles are owned by the run loop, which handles these before
+            // calling `execute`.
+            VmRequest::SerialBuffer { .. }
+            | VmRequest::SerialInput { .. }
+            | VmRequest::SerialReopenOutput { .. } => VmResponse::Err(SysError::new(ENOTSUP)),
+            // The watchdog control tube is owned by the run loop, which handles this before
+            // calling `execute`.
+            VmRequest::Vmwdt(_) => VmResponse::Err(SysError::new(ENOTSUP)),
+        }
+    }
+}
 
-                        let res = battery.control_tube.send(cmd);
-                        if let Err(e) = res {
-                            error!("fail to send command to bat control socket: {}", e);
-                            return VmResponse::Err(SysError::new(EIO));
-                        }
+/// Sends `command` to the battery at `index` among the batteries of type `type_`, and returns its
+/// response.
+fn handle_bat_command(
+    bat_control: &[BatControl],
+    type_: BatteryType,
+    index: usize,
+    command: &BatControlCommand,
+) -> VmResponse {
+    if bat_control.is_empty() {
+        return VmResponse::BatResponse(BatControlResult::NoBatDevice);
+    }
+    let battery = match bat_control
+        .iter()
+        .filter(|battery| battery.type_ == type_)
+        .nth(index)
+    {
+        Some(battery) => battery,
+        None => {
+            error!(
+                "ignored battery command: no {:?} battery at index {}",
+                type_, index
+            );
+            return VmResponse::Err(SysError::new(EINVAL));
+        }
+    };
 
-                        match battery.control_tube.recv() {
-                            Ok(response) => VmResponse::BatResponse(response),
-                            Err(e) => {
-                                error!("fail to recv command from bat control socket: {}", e);
-                                VmResponse::Err(SysError::new(EIO))
-                            }
-                        }
-                    }
-                    None => VmResponse::BatResponse(BatControlResult::NoBatDevice),
-                }
-            }
-            VmRequest::HotPlugCommand { device: _, add: _ } => VmResponse::Ok,
+    if let Err(e) = battery.control_tube.send(command) {
+        error!("fail to send command to bat control socket: {}", e);
+        return VmResponse::Err(SysError::new(EIO));
+    }
+
+    match battery.control_tube.recv() {
+        Ok(response) => VmResponse::BatResponse(response),
+        Err(e) => {
+            error!("fail to recv command from bat control socket: {}", e);
+            VmResponse::Err(SysError::new(EIO))
         }
     }
 }
@@ -1267,6 +1663,48 @@ pub enum VmResponse {
     GpuResponse(GpuControlResult),
     /// Results of battery control commands.
     BatResponse(BatControlResult),
+    /// Boot milestones reached by the VM.
+    BootTimes(BootTimes),
+    /// Number of guest memory access faults per device, as counted by the VMM process.
+    GuestMemoryFaults { faults: BTreeMap<String, u64> },
+    /// Descriptors held by the regions of guest memory.
+    GuestMemoryBackingObjects(BackingObjectStats),
+    /// Optional devices running without an interrupt.
+    DegradedDevices { devices: Vec<String> },
+    /// `VmRequest::SetKernelCmdline` was rejected.
+    SetKernelCmdlineError(SetKernelCmdlineError),
+    /// The most recent guest accesses to MMIO addresses without a device, oldest first, and the
+    /// number of such accesses since the VM started.
+    UnmappedMmioAccesses {
+        accesses: Vec<UnmappedAccess>,
+        total: u64,
+    },
+    /// Formats negotiated with the host audio engine.
+    SndInfo(SndInfo),
+    /// Frames rendered to the host audio engine.
+    SndCapture(SndCapture),
+    /// Identity of the VM.
+    VmInfo(VmInfo),
+    /// All `vcpus` acknowledged the suspend or resume that started pause epoch `epoch`, after
+    /// `elapsed_us` microseconds.
+    RunStateChanged {
+        epoch: u64,
+        vcpus: usize,
+        elapsed_us: u64,
+    },
+    /// Not all the vcpus acknowledged a suspend or resume.
+    UnresponsiveVcpus(UnresponsiveVcpus),
+    /// Utilization of the address spaces of the allocator.
+    ResourceStats(ResourceStats),
+    /// Stolen time of the requested vcpus.
+    PvtimeStats(Vec<VcpuStolenTime>),
+    /// Devices placed at fixed MMIO addresses, sorted by address.
+    Devices(Vec<StaticMmioRegion>),
+    /// The recent output of a serial port, oldest first, and the number of bytes it wrote since
+    /// the VM started.
+    SerialBuffer { contents: Vec<u8>, total: u64 },
+    /// Results of virtual watchdog commands.
+    VmwdtResponse(VmwdtControlResult),
 }
 
 impl Display for VmResponse {
@@ -1297,6 +1735,60 @@ impl Display for VmResponse {
             #[cfg(feature = "gpu")]
             GpuResponse(result) => write!(f, "gpu control request result {:?}", result),
             BatResponse(result) => write!(f, "{}", result),
+            BootTimes(times) => write!(f, "{}", times),
+            GuestMemoryFaults { faults } => faults
+                .iter()
+                .try_for_each(|(device, count)| writeln!(f, "{}: {}", device, count)),
+            GuestMemoryBackingObjects(stats) => {
+                write!(
+                    f,
+                    "{} backing objects for {} regions",
+                    stats.backing_objects, stats.regions
+                )?;
+                if let Some(soft) = stats.limits.soft {
+                    write!(f, ", soft limit {}", soft)?;
+                }
+                if let Some(hard) = stats.limits.hard {
+                    write!(f, ", hard limit {}", hard)?;
+                }
+                Ok(())
+            }
+            DegradedDevices { devices } => devices
+                .iter()
+                .try_for_each(|device| writeln!(f, "{}: no interrupt", device)),
+            VmResponse::SetKernelCmdlineError(e) => write!(f, "error: {}", e),
+            UnmappedMmioAccesses { accesses, total } => {
+                for access in accesses {
+                    writeln!(f, "{}", access)?;
+                }
+                write!(f, "{} unmapped accesses in total", total)
+            }
+            SndInfo(info) => write!(f, "{}", info),
+            SndCapture(capture) => write!(f, "{}", capture),
+            VmResponse::VmInfo(info) => write!(f, "{}", info),
+            RunStateChanged {
+                epoch,
+                vcpus,
+                elapsed_us,
+            } => write!(
+                f,
+                "{} vcpus acknowledged pause epoch {} in {} us",
+                vcpus, epoch, elapsed_us
+            ),
+            VmResponse::UnresponsiveVcpus(e) => write!(f, "error: {}", e),
+            VmResponse::ResourceStats(stats) => write!(f, "{}", stats),
+            PvtimeStats(stolen_times) => stolen_times.iter().try_for_each(|stolen_time| {
+                writeln!(
+                    f,
+                    "vcpu {}: {} ns stolen",
+                    stolen_time.vcpu_id, stolen_time.stolen_time_ns
+                )
+            }),
+            Devices(regions) => regions
+                .iter()
+                .try_for_each(|region| writeln!(f, "{} {}", region.range, region.name)),
+            SerialBuffer { contents, .. } => write!(f, "{}", String::from_utf8_lossy(contents)),
+            VmwdtResponse(result) => write!(f, "{}", result),
         }
     }
 }
@@ -1369,6 +1861,110 @@ mod tests {
         recv_event.write(1).unwrap();
         assert_eq!(e1.read().unwrap(), 1);
     }
+
+    #[test]
+    fn bat_command_routing() {
+        let (first, first_device) = Tube::pair().unwrap();
+        let (second, second_device) = Tube::pair().unwrap();
+        let bat_control = [
+            BatControl {
+                type_: BatteryType::Goldfish,
+                control_tube: first,
+            },
+            BatControl {
+                type_: BatteryType::Goldfish,
+                control_tube: second,
+            },
+        ];
+
+        // Only the second battery answers, so a command sent to the first one would block.
+        let device = std::thread::spawn(move || {
+            let command: BatControlCommand = second_device.recv().unwrap();
+            assert!(matches!(command, BatControlCommand::SetCapacity(42)));
+            second_device.send(&BatControlResult::Ok).unwrap();
+        });
+        let response = handle_bat_command(
+            &bat_control,
+            BatteryType::Goldfish,
+            1,
+            &BatControlCommand::SetCapacity(42),
+        );
+        assert!(matches!(
+            response,
+            VmResponse::BatResponse(BatControlResult::Ok)
+        ));
+        device.join().unwrap();
+        // The first message the first battery gets is one sent after the routed command.
+        bat_control[0]
+            .control_tube
+            .send(&BatControlCommand::SetCapacity(7))
+            .unwrap();
+        let command: BatControlCommand = first_device.recv().unwrap();
+        assert!(matches!(command, BatControlCommand::SetCapacity(7)));
+
+        let response = handle_bat_command(
+            &bat_control,
+            BatteryType::Goldfish,
+            2,
+            &BatControlCommand::SetCapacity(42),
+        );
+        assert!(matches!(response, VmResponse::Err(e) if e.errno() == EINVAL));
+        let response = handle_bat_command(
+            &[],
+            BatteryType::Goldfish,
+            0,
+            &BatControlCommand::SetCapacity(42),
+        );
+        assert!(matches!(
+            response,
+            VmResponse::BatResponse(BatControlResult::NoBatDevice)
+        ));
+    }
+
+    #[test]
+    fn resource_stats_table() {
+        use resources::address_allocator::AllocationInfo;
+
+        let mut pools = BTreeMap::new();
+        pools.insert(
+            "io".to_string(),
+            AllocatorStats {
+                size: 0xf000,
+                ..Default::default()
+            },
+        );
+        pools.insert(
+            "low_mmio".to_string(),
+            AllocatorStats {
+                size: 0x1000_0000,
+                allocated: 0x1000,
+                largest_free: 0xfff_f000,
+                allocations: vec![AllocationInfo {
+                    alloc: Alloc::Pstore,
+                    range: AddressRange::from_start_and_end(0x3000_0000, 0x3000_0fff),
+                    tag: "pstore".to_string(),
+                }],
+            },
+        );
+        let stats = ResourceStats { pools };
+        let table = stats.to_string();
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("pool"));
+        assert_eq!(
+            lines[1].split_whitespace().collect::<Vec<_>>(),
+            ["io", "0xf000", "0x0", "0x0"]
+        );
+        assert_eq!(
+            lines[2].split_whitespace().collect::<Vec<_>>(),
+            ["low_mmio", "0x10000000", "0x1000", "0xffff000"]
+        );
+        assert!(lines[3].contains("pstore (Pstore)"), "{}", lines[3]);
+
+        let json = serde_json::to_string(&stats).unwrap();
+        let parsed: ResourceStats = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, stats);
+    }
 }
 
 #[sorted]
@@ -0,0 +1,101 @@
+// Copyright 2022 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Audio stream formats negotiated with the host audio engine, reported for debugging.
+
+use std::fmt;
+use std::fmt::Display;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+/// The playback streams currently open on the host audio engine.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SndInfo {
+    pub streams: Vec<SndStreamInfo>,
+}
+
+impl Display for SndInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            serde_json::to_string_pretty(self).map_err(|_| fmt::Error)?
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SndStreamInfo {
+    /// Identifies the stream for as long as it is open.
+    pub id: u64,
+    /// Number of channels requested by the guest.
+    pub guest_channels: usize,
+    /// Frame rate requested by the guest.
+    pub guest_frame_rate: u32,
+    /// Size of the guest buffers in frames.
+    pub guest_period_in_frames: usize,
+    /// Format the samples are rendered in.
+    pub format: SndSharedFormat,
+    /// Mix format reported by the endpoint, before it was adjusted to a supported format.
+    pub endpoint_format: Option<SndEndpointFormat>,
+    /// Number of times the format was negotiated again, e.g. after the default endpoint changed.
+    pub renegotiations: u64,
+    /// Number of times the endpoint ran out of samples to play.
+    pub underruns: u64,
+}
+
+/// Frames rendered to the host audio engine, as copied by its loopback capture.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SndCapture {
+    /// Format of `data`, `None` if nothing was rendered yet.
+    pub format: Option<SndSharedFormat>,
+    /// Interleaved samples, oldest first.
+    pub data: Vec<u8>,
+}
+
+impl Display for SndCapture {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            serde_json::to_string(self).map_err(|_| fmt::Error)?
+        )
+    }
+}
+
+/// The format shared with the host audio engine.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SndSharedFormat {
+    pub bit_depth: usize,
+    pub frame_rate: usize,
+    pub channels: usize,
+    /// Period of the audio engine in frames.
+    pub period_in_frames: usize,
+    /// Only known for extensible formats.
+    pub channel_mask: Option<u32>,
+}
+
+/// The fields of a `WAVEFORMATEX` structure.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SndEndpointFormat {
+    pub format_tag: u16,
+    pub channels: u16,
+    pub samples_per_sec: u32,
+    pub avg_bytes_per_sec: u32,
+    pub block_align: u16,
+    pub bits_per_sample: u16,
+    pub size_bytes: u16,
+    /// Set if the format is a `WAVEFORMATEXTENSIBLE`.
+    pub extensible: Option<SndEndpointFormatExtensible>,
+}
+
+/// The fields a `WAVEFORMATEXTENSIBLE` structure adds to `WAVEFORMATEX`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SndEndpointFormatExtensible {
+    pub samples: u16,
+    pub channel_mask: u32,
+    /// Name of the `KSDATAFORMAT_SUBTYPE_*` constant, or the GUID if it isn't a known one.
+    pub sub_format: String,
+}
@@ -21,3 +21,5 @@ cfg_if::cfg_if! {
 
 pub use platform::handle_request;
 pub(crate) use platform::kill_handle;
+pub(crate) use platform::snd_capture;
+pub(crate) use platform::snd_info;
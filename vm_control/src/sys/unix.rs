@@ -5,8 +5,10 @@
 #[cfg(feature = "gpu")]
 pub(crate) mod gpu;
 
+use std::io;
 use std::path::Path;
 use std::thread::JoinHandle;
+use std::time::Duration;
 
 use base::error;
 use base::AsRawDescriptor;
@@ -20,9 +22,11 @@ use base::SafeDescriptor;
 use base::Tube;
 use base::UnixSeqpacket;
 use base::SIGRTMIN;
+use hypervisor::MemCacheType;
 use hypervisor::MemSlot;
 use hypervisor::Vm;
 use libc::EINVAL;
+use libc::ENOTSUP;
 use libc::ERANGE;
 use resources::Alloc;
 use resources::SystemAllocator;
@@ -31,6 +35,7 @@ use serde::Serialize;
 use vm_memory::GuestAddress;
 
 use crate::client::HandleRequestResult;
+use crate::VmControlConnection;
 use crate::VmRequest;
 use crate::VmResponse;
 
@@ -38,32 +43,42 @@ pub fn handle_request<T: AsRef<Path> + std::fmt::Debug>(
     request: &VmRequest,
     socket_path: T,
 ) -> HandleRequestResult {
-    match UnixSeqpacket::connect(&socket_path) {
-        Ok(s) => {
-            let socket = Tube::new_from_unix_seqpacket(s);
-            if let Err(e) = socket.send(request) {
-                error!(
-                    "failed to send request to socket at '{:?}': {}",
-                    socket_path, e
-                );
-                return Err(());
-            }
-            match socket.recv() {
-                Ok(response) => Ok(response),
-                Err(e) => {
-                    error!(
-                        "failed to send request to socket at '{:?}': {}",
-                        socket_path, e
-                    );
-                    Err(())
-                }
-            }
-        }
-        Err(e) => {
-            error!("failed to connect to socket at '{:?}': {}", socket_path, e);
-            Err(())
-        }
-    }
+    let connection = connect_vm_control(&socket_path).map_err(|e| {
+        error!("failed to connect to socket at '{:?}': {}", socket_path, e);
+    })?;
+    connection.request(request).map_err(|e| {
+        error!(
+            "failed to send request to socket at '{:?}': {}",
+            socket_path, e
+        );
+    })
+}
+
+/// Opens a connection to the control socket at `socket_path` that can be shared by threads.
+pub fn connect_vm_control<T: AsRef<Path>>(socket_path: T) -> io::Result<VmControlConnection> {
+    let socket = UnixSeqpacket::connect(socket_path)?;
+    let recv_socket = socket.try_clone()?;
+    Ok(VmControlConnection::new(
+        Tube::new_from_unix_seqpacket(socket),
+        Tube::new_from_unix_seqpacket(recv_socket),
+    ))
+}
+
+/// Like `connect_vm_control`, but connecting, sending requests and waiting for their responses
+/// each fail with `ETIMEDOUT` or `EAGAIN` once `timeout` elapsed.
+pub fn connect_vm_control_with_timeout<T: AsRef<Path>>(
+    socket_path: T,
+    timeout: Duration,
+) -> io::Result<VmControlConnection> {
+    let socket = UnixSeqpacket::connect_with_timeout(socket_path, timeout)?;
+    // The timeouts belong to the socket, so they also apply to its clone.
+    socket.set_read_timeout(Some(timeout))?;
+    socket.set_write_timeout(Some(timeout))?;
+    let recv_socket = socket.try_clone()?;
+    Ok(VmControlConnection::new(
+        Tube::new_from_unix_seqpacket(socket),
+        Tube::new_from_unix_seqpacket(recv_socket),
+    ))
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -169,6 +184,7 @@ impl FsMappingRequest {
                             Box::new(arena),
                             false,
                             false,
+                            MemCacheType::Cached,
                         ) {
                             Ok(slot) => VmResponse::RegisterMemory {
                                 pfn: range.start >> 12,
@@ -209,3 +225,13 @@ impl FsMappingRequest {
 pub(crate) fn kill_handle(handle: &JoinHandle<()>) {
     let _ = handle.kill(SIGRTMIN() + 0);
 }
+
+/// The negotiated audio formats are only tracked by the Windows audio backend.
+pub(crate) fn snd_info() -> VmResponse {
+    VmResponse::Err(SysError::new(ENOTSUP))
+}
+
+/// Loopback capture is only implemented by the Windows audio backend.
+pub(crate) fn snd_capture(_frames: usize) -> VmResponse {
+    VmResponse::Err(SysError::new(ENOTSUP))
+}
@@ -2,6 +2,9 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
+use std::fmt;
+use std::fmt::Display;
+
 use serde::Deserialize;
 use serde::Serialize;
 
@@ -20,3 +23,11 @@ impl DisplayModeTrait for UnixDisplayMode {
         }
     }
 }
+
+impl Display for UnixDisplayMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Windowed(width, height) => write!(f, "windowed[{},{}]", width, height),
+        }
+    }
+}
@@ -8,8 +8,22 @@ pub(crate) mod gpu;
 use std::path::Path;
 use std::thread::JoinHandle;
 
+use base::Error as SysError;
+use libc::ENOTSUP;
+use win_audio::loopback;
+use win_audio::stream_info::stream_infos;
+use win_audio::stream_info::EndpointFormat;
+use win_audio::AudioSharedFormat;
+
 use crate::client::HandleRequestResult;
+use crate::snd::SndCapture;
+use crate::snd::SndEndpointFormat;
+use crate::snd::SndEndpointFormatExtensible;
+use crate::snd::SndInfo;
+use crate::snd::SndSharedFormat;
+use crate::snd::SndStreamInfo;
 use crate::VmRequest;
+use crate::VmResponse;
 
 // TODO(b/145563346): Make this work on Windows
 pub fn handle_request<T: AsRef<Path> + std::fmt::Debug>(
@@ -20,3 +34,62 @@ pub fn handle_request<T: AsRef<Path> + std::fmt::Debug>(
 }
 
 pub(crate) fn kill_handle(_handle: &JoinHandle<()>) {}
+
+/// Returns the formats negotiated by the playback streams of the win_audio backend.
+pub(crate) fn snd_info() -> VmResponse {
+    let streams = stream_infos()
+        .into_iter()
+        .map(|info| SndStreamInfo {
+            id: info.id,
+            guest_channels: info.guest_channels,
+            guest_frame_rate: info.guest_frame_rate,
+            guest_period_in_frames: info.guest_period_in_frames,
+            format: shared_format(info.shared_format),
+            endpoint_format: info.endpoint_format.map(endpoint_format),
+            renegotiations: info.renegotiations,
+            underruns: info.underruns,
+        })
+        .collect();
+    VmResponse::SndInfo(SndInfo { streams })
+}
+
+/// Returns the last `frames` frames copied by the loopback capture of the win_audio backend.
+pub(crate) fn snd_capture(frames: usize) -> VmResponse {
+    match loopback::capture(frames) {
+        Some(frames) => VmResponse::SndCapture(SndCapture {
+            format: frames.format.map(shared_format),
+            data: frames.data,
+        }),
+        // Loopback capture wasn't enabled.
+        None => VmResponse::Err(SysError::new(ENOTSUP)),
+    }
+}
+
+fn shared_format(format: AudioSharedFormat) -> SndSharedFormat {
+    SndSharedFormat {
+        bit_depth: format.bit_depth,
+        frame_rate: format.frame_rate,
+        channels: format.channels,
+        period_in_frames: format.shared_audio_engine_period_in_frames,
+        channel_mask: format.channel_mask,
+    }
+}
+
+fn endpoint_format(format: EndpointFormat) -> SndEndpointFormat {
+    SndEndpointFormat {
+        format_tag: format.format_tag,
+        channels: format.channels,
+        samples_per_sec: format.samples_per_sec,
+        avg_bytes_per_sec: format.avg_bytes_per_sec,
+        block_align: format.block_align,
+        bits_per_sample: format.bits_per_sample,
+        size_bytes: format.size_bytes,
+        extensible: format
+            .extensible
+            .map(|extensible| SndEndpointFormatExtensible {
+                samples: extensible.samples,
+                channel_mask: extensible.channel_mask,
+                sub_format: extensible.sub_format,
+            }),
+    }
+}
@@ -2,6 +2,8 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
+use std::fmt;
+use std::fmt::Display;
 use std::marker::PhantomData;
 
 use base::info;
@@ -37,6 +39,15 @@ impl<T> DisplayModeTrait for WinDisplayMode<T> {
     }
 }
 
+impl<T> Display for WinDisplayMode<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Windowed(width, height) => write!(f, "windowed[{},{}]", width, height),
+            Self::BorderlessFullScreen(_) => write!(f, "borderless_full_screen"),
+        }
+    }
+}
+
 impl<T> From<WinDisplayMode<T>> for WinDisplayModeArg {
     fn from(mode: WinDisplayMode<T>) -> WinDisplayModeArg {
         match mode {
@@ -16,3 +16,12 @@ bindgen_generate \
     "${BINDGEN_LINUX}/include/uapi/linux/udmabuf.h" \
     | replace_linux_int_types | rustfmt \
     > vm_memory/src/udmabuf_bindings.rs
+
+bindgen_generate \
+    --allowlist-type='uffd_msg' \
+    --allowlist-type='uffdio_.*' \
+    --allowlist-var='UFFD_.*' \
+    --allowlist-var='_?UFFDIO_?.*' \
+    "${BINDGEN_LINUX}/include/uapi/linux/userfaultfd.h" \
+    | replace_linux_int_types | rustfmt \
+    > vm_memory/src/userfaultfd_bindings.rs
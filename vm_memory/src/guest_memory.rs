@@ -4,79 +4,220 @@
 
 //! Track memory regions that are mapped to the guest VM.
 
+use std::cmp::min;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
 use std::convert::AsRef;
 use std::convert::TryFrom;
+use std::fmt;
+use std::fmt::Debug;
 use std::fs::File;
 use std::io::Read;
 use std::io::Write;
 use std::marker::Send;
 use std::marker::Sync;
 use std::mem::size_of;
+use std::path::PathBuf;
 use std::result;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::sync::Weak;
 
 use base::pagesize;
 use base::AsRawDescriptor;
 use base::AsRawDescriptors;
 use base::Error as SysError;
+use base::Event;
 use base::MappedRegion;
 use base::MemoryMapping;
 use base::MemoryMappingBuilder;
 use base::MmapError;
+use base::Protection;
 use base::RawDescriptor;
 use base::SharedMemory;
 use cros_async::mem;
 use cros_async::BackingMemory;
+use cros_async::MemRegion;
+use cros_async::ReadAsync;
+use cros_async::WriteAsync;
 use data_model::volatile_memory::*;
 use data_model::DataInit;
+use once_cell::sync::Lazy;
 use remain::sorted;
+use serde::Deserialize;
+use serde::Serialize;
+use sync::Mutex;
 use thiserror::Error;
 
 use crate::guest_address::GuestAddress;
 
 mod sys;
+#[cfg(unix)]
+#[cfg(unix)]
+pub use sys::memory_fault;
+#[cfg(unix)]
+pub use sys::memory_fault_count;
+#[cfg(unix)]
+pub use sys::set_memfd_fallback_dir;
+#[cfg(unix)]
+pub use sys::set_memory_fault_event;
+#[cfg(all(unix, feature = "userfaultfd"))]
+pub use sys::LazyBacking;
+#[cfg(all(unix, feature = "userfaultfd"))]
+pub use sys::LazyFaultHandler;
+#[cfg(unix)]
+pub use sys::MemoryFault;
 pub use sys::MemoryPolicy;
 
 #[sorted]
 #[derive(Error, Debug)]
 pub enum Error {
+    #[error("{context}: {source}")]
+    AccessFault {
+        context: AccessContext,
+        #[source]
+        source: Box<Error>,
+    },
     #[error("invalid guest address {0}")]
     InvalidGuestAddress(GuestAddress),
     #[error("invalid offset {0}")]
     InvalidOffset(u64),
     #[error("size {0} must not be zero")]
     InvalidSize(usize),
+    #[cfg(all(unix, feature = "userfaultfd"))]
+    #[error("failed to read the contents of lazy guest memory at {0}: {1}")]
+    LazySourceRead(GuestAddress, #[source] std::io::Error),
     #[error("invalid guest memory access at addr={0}: {1}")]
     MemoryAccess(GuestAddress, #[source] MmapError),
     #[error("failed to set seals on shm region: {0}")]
     MemoryAddSealsFailed(#[source] SysError),
     #[error("failed to create shm region: {0}")]
     MemoryCreationFailed(#[source] SysError),
+    #[error("failed to create guest memory file in {0}: {1}")]
+    MemoryFallbackFileFailed(PathBuf, #[source] std::io::Error),
     #[error("failed to map guest memory: {0}")]
     MemoryMappingFailed(#[source] MmapError),
     #[error("shm regions must be page aligned")]
     MemoryNotAligned,
+    #[error("failed to change the protection of guest memory: {0}")]
+    MemoryProtectionFailed(#[source] MmapError),
     #[error("memory regions overlap")]
     MemoryRegionOverlap,
     #[error("memory region size {0} is too large")]
     MemoryRegionTooLarge(u128),
+    #[error("memory regions must be sorted by address")]
+    MemoryRegionsUnsorted,
+    #[error("guest memory at {0} is protected from host access")]
+    ProtectedRegionAccess(GuestAddress),
+    #[error("guest memory range of {len:#x} bytes at {addr} is not within a single region")]
+    RangeCrossesRegion { addr: GuestAddress, len: usize },
+    #[error("guest memory range of {len:#x} bytes at {addr} has no memory at {hole}")]
+    RangeHole {
+        addr: GuestAddress,
+        len: u64,
+        hole: GuestAddress,
+    },
+    #[error("guest memory at {0} is not writable by the host")]
+    ReadOnlyRegion(GuestAddress),
     #[error("incomplete read of {completed} instead of {expected} bytes")]
     ShortRead { expected: usize, completed: usize },
     #[error("incomplete write of {completed} instead of {expected} bytes")]
     ShortWrite { expected: usize, completed: usize },
     #[error("DescriptorChain split is out of bounds: {0}")]
     SplitOutOfBounds(usize),
+    #[error("{count} backing objects exceed the limit of {limit}")]
+    TooManyBackingObjects { count: usize, limit: usize },
+    #[cfg(all(unix, feature = "userfaultfd"))]
+    #[error("userfaultfd failed: {0}")]
+    Userfaultfd(#[source] SysError),
     #[error("{0}")]
     VolatileMemoryAccess(#[source] VolatileMemoryError),
 }
 
 pub type Result<T> = result::Result<T, Error>;
 
+// Number of faulting guest memory accesses made on behalf of each device in this process.
+static FAULT_COUNTS: Lazy<Mutex<BTreeMap<String, u64>>> = Lazy::new(Default::default);
+
+/// Identifies the device, and optionally the queue, on whose behalf guest memory is accessed.
+///
+/// Errors passed through `attach` name the device so that bad guest addresses can be attributed
+/// to the driver that supplied them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AccessContext {
+    device: Arc<str>,
+    queue: Option<usize>,
+}
+
+impl AccessContext {
+    pub fn new(device: &str) -> AccessContext {
+        AccessContext {
+            device: device.into(),
+            queue: None,
+        }
+    }
+
+    /// Returns a copy of this context for accesses made through queue `queue` of the device.
+    pub fn with_queue(&self, queue: usize) -> AccessContext {
+        AccessContext {
+            device: self.device.clone(),
+            queue: Some(queue),
+        }
+    }
+
+    pub fn device(&self) -> &str {
+        &self.device
+    }
+
+    pub fn queue(&self) -> Option<usize> {
+        self.queue
+    }
+
+    /// Counts a faulting access against this context's device.
+    pub fn record_fault(&self) {
+        *FAULT_COUNTS
+            .lock()
+            .entry(self.device.to_string())
+            .or_insert(0) += 1;
+    }
+
+    /// Attributes an error in `result` to this context, counting it as a fault of the device.
+    pub fn attach<T>(&self, result: Result<T>) -> Result<T> {
+        result.map_err(|e| {
+            self.record_fault();
+            Error::AccessFault {
+                context: self.clone(),
+                source: Box::new(e),
+            }
+        })
+    }
+}
+
+impl fmt::Display for AccessContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.queue {
+            Some(queue) => write!(f, "device {} queue {}", self.device, queue),
+            None => write!(f, "device {}", self.device),
+        }
+    }
+}
+
+/// Returns the number of faulting guest memory accesses recorded for each device in this process.
+pub fn access_fault_counts() -> BTreeMap<String, u64> {
+    FAULT_COUNTS.lock().clone()
+}
+
 /// A file-like object backing `MemoryRegion`.
 #[derive(Clone, Debug)]
 pub enum BackingObject {
     Shm(Arc<SharedMemory>),
     File(Arc<File>),
+    /// Shared memory filled from a file as it is accessed. See `MemoryRegion::new_lazy`.
+    #[cfg(all(unix, feature = "userfaultfd"))]
+    Lazy(Arc<LazyBacking>),
 }
 
 impl AsRawDescriptor for BackingObject {
@@ -84,6 +225,8 @@ impl AsRawDescriptor for BackingObject {
         match self {
             Self::Shm(shm) => shm.as_raw_descriptor(),
             Self::File(f) => f.as_raw_descriptor(),
+            #[cfg(all(unix, feature = "userfaultfd"))]
+            Self::Lazy(lazy) => lazy.shm().as_raw_descriptor(),
         }
     }
 }
@@ -93,17 +236,100 @@ impl AsRef<dyn AsRawDescriptor + Sync + Send> for BackingObject {
         match self {
             BackingObject::Shm(shm) => shm.as_ref(),
             BackingObject::File(f) => f.as_ref(),
+            #[cfg(all(unix, feature = "userfaultfd"))]
+            BackingObject::Lazy(lazy) => lazy.shm(),
+        }
+    }
+}
+
+/// Limits on the number of distinct objects backing the regions of a `GuestMemory`.
+///
+/// Each backing object keeps a descriptor open for as long as it is mapped, so a VM accumulating
+/// pmem files and hotplugged regions can run into the process's descriptor limit.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BackingObjectLimits {
+    /// A warning is logged for layouts with more backing objects than this.
+    pub soft: Option<usize>,
+    /// Layouts with more backing objects than this are rejected.
+    pub hard: Option<usize>,
+}
+
+impl BackingObjectLimits {
+    /// Checks that `count` backing objects are within the limits, logging a warning if they are
+    /// over the soft limit.
+    fn check(&self, count: usize) -> Result<()> {
+        if let Some(limit) = self.hard {
+            if count > limit {
+                return Err(Error::TooManyBackingObjects { count, limit });
+            }
         }
+        if let Some(limit) = self.soft {
+            if count > limit {
+                base::warn!(
+                    "guest memory has {} backing objects, over the soft limit of {}",
+                    count,
+                    limit
+                );
+            }
+        }
+        Ok(())
     }
 }
 
+/// The descriptors held by a `GuestMemory`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackingObjectStats {
+    pub regions: usize,
+    /// Number of distinct backing objects, each holding a descriptor.
+    pub backing_objects: usize,
+    pub limits: BackingObjectLimits,
+}
+
+/// Where a region of guest memory is mapped and what backs it, as listed by
+/// `GuestMemory::regions`.
+#[derive(Clone, Debug)]
+pub struct MemoryRegionInformation<'a> {
+    /// Position of the region in the regions sorted by guest address.
+    pub index: usize,
+    pub guest_addr: GuestAddress,
+    pub size: usize,
+    /// Address of the region in this process.
+    pub host_addr: usize,
+    pub shared_obj: &'a BackingObject,
+    /// Offset of the region in `shared_obj`.
+    pub obj_offset: u64,
+}
+
+/// What the host may do with the guest memory of a region.
+///
+/// Protected VMs keep some regions, such as their firmware, out of the host's reach. Accessing
+/// them through the host mapping would fault or corrupt them, depending on the hypervisor.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemoryAccessPolicy {
+    /// The host may read and write the region.
+    ReadWrite,
+    /// The host may only read the region.
+    ReadOnly,
+    /// The host must not access the region at all.
+    Inaccessible,
+}
+
 /// A regions of memory mapped memory.
 /// Holds the memory mapping with its offset in guest memory.
 /// Also holds the backing object for the mapping and the offset in that object of the mapping.
 #[derive(Debug)]
 pub struct MemoryRegion {
+    // Declared before `mapping` so the mapping is unregistered before it is unmapped.
+    _fault_registration: sys::FaultRegistration,
     mapping: MemoryMapping,
     guest_base: GuestAddress,
+    // Whether the guest can only read the region.
+    read_only: bool,
+    // Whether the host mapping is writable, which may differ from what the guest can do.
+    host_writable: AtomicBool,
+    // What the host may do with the region, fixed when guest memory is created.
+    access_policy: MemoryAccessPolicy,
 
     shared_obj: BackingObject,
     obj_offset: u64,
@@ -123,12 +349,13 @@ impl MemoryRegion {
             .offset(offset)
             .build()
             .map_err(Error::MemoryMappingFailed)?;
-        Ok(MemoryRegion {
+        Ok(MemoryRegion::new(
             mapping,
             guest_base,
-            shared_obj: BackingObject::Shm(shm),
-            obj_offset: offset,
-        })
+            BackingObject::Shm(shm),
+            offset,
+            false,
+        ))
     }
 
     /// Creates a new MemoryRegion using the given file to get available later at `guest_base`
@@ -144,12 +371,128 @@ impl MemoryRegion {
             .offset(offset)
             .build()
             .map_err(Error::MemoryMappingFailed)?;
-        Ok(MemoryRegion {
+        Ok(MemoryRegion::new(
             mapping,
             guest_base,
-            shared_obj: BackingObject::File(file),
-            obj_offset: offset,
-        })
+            BackingObject::File(file),
+            offset,
+            false,
+        ))
+    }
+
+    /// Creates a new MemoryRegion using the given file, like `new_from_file`, that the guest can
+    /// only read. The host mapping is read-only too until it is made writable with
+    /// `set_host_writable`, which requires `file` to have been opened for writing.
+    pub fn new_from_file_read_only(
+        size: u64,
+        guest_base: GuestAddress,
+        offset: u64,
+        file: Arc<File>,
+    ) -> Result<Self> {
+        let mapping = MemoryMappingBuilder::new(size as usize)
+            .from_file(&file)
+            .offset(offset)
+            .protection(Protection::read())
+            .build()
+            .map_err(Error::MemoryMappingFailed)?;
+        Ok(MemoryRegion::new(
+            mapping,
+            guest_base,
+            BackingObject::File(file),
+            offset,
+            true,
+        ))
+    }
+
+    fn new(
+        mapping: MemoryMapping,
+        guest_base: GuestAddress,
+        shared_obj: BackingObject,
+        obj_offset: u64,
+        read_only: bool,
+    ) -> Self {
+        let label = match shared_obj {
+            BackingObject::Shm(_) => "shared memory guest region",
+            BackingObject::File(_) => "file-backed guest region",
+            #[cfg(all(unix, feature = "userfaultfd"))]
+            BackingObject::Lazy(_) => "lazily restored guest region",
+        };
+        let fault_registration = sys::FaultRegistration::new(
+            mapping.as_ptr() as usize,
+            mapping.size(),
+            guest_base,
+            label,
+        );
+        MemoryRegion {
+            _fault_registration: fault_registration,
+            mapping,
+            guest_base,
+            read_only,
+            host_writable: AtomicBool::new(!read_only),
+            access_policy: MemoryAccessPolicy::ReadWrite,
+            shared_obj,
+            obj_offset,
+        }
+    }
+
+    /// Returns whether the guest can only read the region.
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Returns what the host may do with the region.
+    pub fn access_policy(&self) -> MemoryAccessPolicy {
+        self.access_policy
+    }
+
+    /// Fails with `Error::ProtectedRegionAccess` if the access policy of the region forbids the
+    /// host to access `addr`, or to write it if `write` is set.
+    fn check_access(&self, addr: GuestAddress, write: bool) -> Result<()> {
+        match self.access_policy {
+            MemoryAccessPolicy::ReadWrite => Ok(()),
+            MemoryAccessPolicy::ReadOnly if !write => Ok(()),
+            MemoryAccessPolicy::ReadOnly | MemoryAccessPolicy::Inaccessible => {
+                Err(Error::ProtectedRegionAccess(addr))
+            }
+        }
+    }
+
+    /// Returns whether the host can write the region.
+    pub fn is_host_writable(&self) -> bool {
+        self.host_writable.load(Ordering::Acquire)
+    }
+
+    /// Changes whether the host mapping of the region is writable, leaving what the guest can do
+    /// with it unchanged: a read-only region stays read-only to the guest while the VMM stages
+    /// updates to it. Writes to the region through `GuestMemory` fail while it isn't writable,
+    /// but the caller must make sure no writes are in flight when making it read-only.
+    pub fn set_host_writable(&self, writable: bool) -> Result<()> {
+        let prot = if writable {
+            Protection::read_write()
+        } else {
+            // Stop new writes before the mapping is made read-only.
+            self.host_writable.store(false, Ordering::Release);
+            Protection::read()
+        };
+        <dyn MappedRegion>::mprotect(&self.mapping, 0, self.mapping.size(), prot)
+            .map_err(Error::MemoryProtectionFailed)?;
+        self.host_writable.store(writable, Ordering::Release);
+        Ok(())
+    }
+
+    /// Writes the changes made to `len` bytes of the region starting at `offset` back to the file
+    /// backing it. Regions backed by shared memory have no file to write back to.
+    pub fn sync(&self, offset: usize, len: usize) -> Result<()> {
+        if !matches!(self.shared_obj, BackingObject::File(_)) {
+            return Ok(());
+        }
+        // msync needs a page aligned start.
+        let start = offset - offset % pagesize();
+        let len = len
+            .checked_add(offset - start)
+            .ok_or(Error::InvalidOffset(offset as u64))?;
+        <dyn MappedRegion>::msync(&self.mapping, start, len)
+            .map_err(|e| Error::MemoryAccess(self.guest_base.unchecked_add(offset as u64), e))
     }
 
     fn start(&self) -> GuestAddress {
@@ -166,11 +509,90 @@ impl MemoryRegion {
     }
 }
 
+/// Receives notifications when the layout of a `GuestMemory` changes.
+///
+/// Devices that cache host addresses or register guest memory with another component (vhost,
+/// io_uring, ...) should subscribe with `GuestMemory::add_observer` and re-register when notified.
+pub trait MemoryObserver: Send + Sync {
+    /// Called once `mem` has become the current layout. Host addresses and registrations derived
+    /// from any earlier layout must no longer be used.
+    fn memory_layout_changed(&self, mem: &GuestMemory);
+}
+
+/// Layout state shared by every `GuestMemory` derived from the same original.
+#[derive(Default)]
+struct MemoryLayout {
+    // Generation of the current layout. Only modified with `observers` locked, so that
+    // notifications are delivered in generation order.
+    generation: AtomicU64,
+    observers: Mutex<Vec<Weak<dyn MemoryObserver>>>,
+    backing_object_limits: Mutex<BackingObjectLimits>,
+}
+
+impl Debug for MemoryLayout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MemoryLayout")
+            .field("generation", &self.generation)
+            .finish()
+    }
+}
+
+/// A `MemoryObserver` for consumers that must apply layout changes on their own thread.
+///
+/// The most recent layout is stored and `event()` is signaled; the owning thread waits on the
+/// event and calls `take()` to retrieve the layout to re-register.
+pub struct MemoryLayoutEvent {
+    event: Event,
+    pending: Mutex<Option<GuestMemory>>,
+}
+
+impl MemoryLayoutEvent {
+    pub fn new() -> base::Result<MemoryLayoutEvent> {
+        Ok(MemoryLayoutEvent {
+            event: Event::new()?,
+            pending: Mutex::new(None),
+        })
+    }
+
+    /// The event signaled when a new layout is pending.
+    pub fn event(&self) -> &Event {
+        &self.event
+    }
+
+    /// Returns the pending layout, if any, and clears the event.
+    pub fn take(&self) -> Option<GuestMemory> {
+        // The event is only signaled with `pending` locked and set, so reading it here never
+        // blocks and never leaves it signaled without a pending layout.
+        let mut pending = self.pending.lock();
+        let mem = pending.take();
+        if mem.is_some() {
+            let _ = self.event.read();
+        }
+        mem
+    }
+}
+
+impl MemoryObserver for MemoryLayoutEvent {
+    fn memory_layout_changed(&self, mem: &GuestMemory) {
+        let mut pending = self.pending.lock();
+        *pending = Some(mem.clone());
+        if let Err(e) = self.event.write(1) {
+            base::error!("failed to signal memory layout change: {}", e);
+        }
+    }
+}
+
 /// Tracks memory regions and where they are mapped in the guest, along with shm
 /// descriptors of the underlying memory regions.
 #[derive(Clone, Debug)]
 pub struct GuestMemory {
-    regions: Arc<[MemoryRegion]>,
+    // Regions are shared with the layouts derived from this one by `add_region`. They are sorted
+    // by address and never change, so they can be searched without locking.
+    regions: Arc<[Arc<MemoryRegion>]>,
+    // Index of the region found by the last lookup, tried first by the next one.
+    last_region: Arc<AtomicUsize>,
+    generation: u64,
+    layout: Arc<MemoryLayout>,
 }
 
 impl AsRawDescriptors for GuestMemory {
@@ -185,8 +607,11 @@ impl AsRawDescriptors for GuestMemory {
 }
 
 impl GuestMemory {
-    /// Creates backing shm for GuestMemory regions
-    fn create_shm(ranges: &[(GuestAddress, u64)]) -> Result<SharedMemory> {
+    /// Creates the backing object for GuestMemory regions.
+    ///
+    /// This is shared memory, except on Unix kernels without memfd support where it is an unlinked
+    /// file (see `set_memfd_fallback_dir`).
+    fn create_shm(ranges: &[(GuestAddress, u64)]) -> Result<BackingObject> {
         let mut aligned_size = 0;
         let pg_size = pagesize();
         for range in ranges {
@@ -199,27 +624,65 @@ impl GuestMemory {
 
         // NOTE: Some tests rely on the GuestMemory's name when capturing metrics.
         let name = "crosvm_guest";
-        // Shm must be mut even though it is only updated on Unix systems.
-        #[allow(unused_mut)]
-        let mut shm = SharedMemory::new(name, aligned_size).map_err(Error::MemoryCreationFailed)?;
-
-        sys::finalize_shm(&mut shm)?;
-
-        Ok(shm)
+        sys::create_backing_object(name, aligned_size)
     }
 
     /// Creates a container for guest memory regions.
     /// Valid memory regions are specified as a Vec of (Address, Size) tuples sorted by Address.
     pub fn new(ranges: &[(GuestAddress, u64)]) -> Result<GuestMemory> {
-        // Create shm
-        let shm = Arc::new(GuestMemory::create_shm(ranges)?);
+        let backing = GuestMemory::create_shm(ranges)?;
+        GuestMemory::with_backing(ranges, backing)
+    }
+
+    /// Creates a container for the guest memory regions of `ranges`, as `new` does, and the
+    /// already mapped `file_regions`, which may lie between them.
+    pub fn new_with_file_regions(
+        ranges: &[(GuestAddress, u64)],
+        file_regions: Vec<MemoryRegion>,
+    ) -> Result<GuestMemory> {
+        GuestMemory::new_with_access_policies(ranges, file_regions, &[])
+    }
+
+    /// Creates guest memory as `new_with_file_regions` does, where each region starting at an
+    /// address of `access_policies` gets the paired access policy instead of
+    /// `MemoryAccessPolicy::ReadWrite`.
+    pub fn new_with_access_policies(
+        ranges: &[(GuestAddress, u64)],
+        file_regions: Vec<MemoryRegion>,
+        access_policies: &[(GuestAddress, MemoryAccessPolicy)],
+    ) -> Result<GuestMemory> {
+        let backing = GuestMemory::create_shm(ranges)?;
+        let mut regions = GuestMemory::backed_regions(ranges, backing)?;
+        regions.extend(file_regions);
+        for &(addr, policy) in access_policies {
+            regions
+                .iter_mut()
+                .find(|region| region.guest_base == addr)
+                .ok_or(Error::InvalidGuestAddress(addr))?
+                .access_policy = policy;
+        }
+        GuestMemory::from_regions(regions)
+    }
+
+    /// Creates a container for guest memory regions laid out contiguously in `backing`.
+    fn with_backing(ranges: &[(GuestAddress, u64)], backing: BackingObject) -> Result<GuestMemory> {
+        GuestMemory::from_regions(GuestMemory::backed_regions(ranges, backing)?)
+    }
 
+    /// Maps the memory regions of `ranges`, laid out contiguously in `backing`.
+    fn backed_regions(
+        ranges: &[(GuestAddress, u64)],
+        backing: BackingObject,
+    ) -> Result<Vec<MemoryRegion>> {
         // Create memory regions
         let mut regions = Vec::<MemoryRegion>::new();
         let mut offset = 0;
 
         for range in ranges {
             if let Some(last) = regions.last() {
+                if range.0 < last.guest_base {
+                    return Err(Error::MemoryRegionsUnsorted);
+                }
                 if last
                     .guest_base
                     .checked_add(last.mapping.size() as u64)
@@ -231,29 +694,38 @@ impl GuestMemory {
 
             let size = usize::try_from(range.1)
                 .map_err(|_| Error::MemoryRegionTooLarge(range.1 as u128))?;
-            let mapping = MemoryMappingBuilder::new(size)
-                .from_shared_memory(shm.as_ref())
+            let builder = MemoryMappingBuilder::new(size);
+            let builder = match &backing {
+                BackingObject::Shm(shm) => builder.from_shared_memory(shm.as_ref()),
+                BackingObject::File(file) => builder.from_file(file.as_ref()),
+                #[cfg(all(unix, feature = "userfaultfd"))]
+                BackingObject::Lazy(lazy) => builder.from_shared_memory(lazy.shm()),
+            };
+            let mapping = builder
                 .offset(offset)
                 .build()
                 .map_err(Error::MemoryMappingFailed)?;
 
-            regions.push(MemoryRegion {
+            regions.push(MemoryRegion::new(
                 mapping,
-                guest_base: range.0,
-                shared_obj: BackingObject::Shm(shm.clone()),
-                obj_offset: offset,
-            });
+                range.0,
+                backing.clone(),
+                offset,
+                false,
+            ));
 
             offset += size as u64;
         }
 
-        Ok(GuestMemory {
-            regions: Arc::from(regions),
-        })
+        Ok(regions)
     }
 
     /// Creates a `GuestMemory` from a collection of MemoryRegions.
-    pub fn from_regions(mut regions: Vec<MemoryRegion>) -> Result<Self> {
+    pub fn from_regions(regions: Vec<MemoryRegion>) -> Result<Self> {
+        GuestMemory::from_shared_regions(regions.into_iter().map(Arc::new).collect())
+    }
+
+    fn from_shared_regions(mut regions: Vec<Arc<MemoryRegion>>) -> Result<Self> {
         // Sort the regions and ensure non overlap.
         regions.sort_by(|a, b| a.guest_base.cmp(&b.guest_base));
 
@@ -277,9 +749,72 @@ impl GuestMemory {
 
         Ok(GuestMemory {
             regions: Arc::from(regions),
+            last_region: Default::default(),
+            generation: 0,
+            layout: Default::default(),
         })
     }
 
+    /// Returns the generation of this layout. Every `update_layout` produces a layout with a
+    /// higher generation.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Returns true if no newer layout has been published by `update_layout`.
+    pub fn is_current_layout(&self) -> bool {
+        self.layout.generation.load(Ordering::Acquire) == self.generation
+    }
+
+    /// Subscribes `observer` to layout changes of this memory and every layout derived from it.
+    ///
+    /// Only a weak reference is kept, so dropping the observer unsubscribes it.
+    pub fn add_observer(&self, observer: &Arc<dyn MemoryObserver>) {
+        let mut observers = self.layout.observers.lock();
+        observers.retain(|o| o.strong_count() > 0);
+        observers.push(Arc::downgrade(observer));
+    }
+
+    /// Replaces the layout with `regions`.
+    ///
+    /// The returned `GuestMemory` becomes the current layout before any observer is notified, and
+    /// observers are then notified in the order they subscribed.
+    ///
+    /// Fails with `Error::TooManyBackingObjects` if the new layout exceeds the hard limit set by
+    /// `set_backing_object_limits`.
+    pub fn update_layout(&self, regions: Vec<MemoryRegion>) -> Result<GuestMemory> {
+        self.publish_layout(regions.into_iter().map(Arc::new).collect())
+    }
+
+    /// Replaces the layout with the current regions and `region`, as `update_layout` does.
+    pub fn add_region(&self, region: MemoryRegion) -> Result<GuestMemory> {
+        let mut regions = self.regions.to_vec();
+        regions.push(Arc::new(region));
+        self.publish_layout(regions)
+    }
+
+    fn publish_layout(&self, regions: Vec<Arc<MemoryRegion>>) -> Result<GuestMemory> {
+        let mut mem = GuestMemory::from_shared_regions(regions)?;
+        self.layout
+            .backing_object_limits
+            .lock()
+            .check(mem.backing_object_count())?;
+        mem.layout = self.layout.clone();
+
+        let mut observers = self.layout.observers.lock();
+        mem.generation = self.layout.generation.load(Ordering::Acquire) + 1;
+        self.layout
+            .generation
+            .store(mem.generation, Ordering::Release);
+
+        observers.retain(|o| o.strong_count() > 0);
+        for observer in observers.iter().filter_map(Weak::upgrade) {
+            observer.memory_layout_changed(&mem);
+        }
+
+        Ok(mem)
+    }
+
     /// Returns the end address of memory.
     ///
     /// # Examples
@@ -298,7 +833,7 @@ impl GuestMemory {
         self.regions
             .iter()
             .max_by_key(|region| region.start())
-            .map_or(GuestAddress(0), MemoryRegion::end)
+            .map_or(GuestAddress(0), |region| region.end())
     }
 
     /// Returns the total size of memory in bytes.
@@ -311,7 +846,7 @@ impl GuestMemory {
 
     /// Returns true if the given address is within the memory range available to the guest.
     pub fn address_in_range(&self, addr: GuestAddress) -> bool {
-        self.regions.iter().any(|region| region.contains(addr))
+        self.find_region(addr).is_some()
     }
 
     /// Returns true if the given range (start, end) is overlap with the memory range
@@ -359,6 +894,52 @@ impl GuestMemory {
         self.regions.len() as u64
     }
 
+    /// Returns the number of distinct objects backing the regions, each of which holds a
+    /// descriptor.
+    pub fn backing_object_count(&self) -> usize {
+        self.regions
+            .iter()
+            .map(|r| r.shared_obj.as_raw_descriptor())
+            .collect::<BTreeSet<_>>()
+            .len()
+    }
+
+    /// Limits the number of backing objects of this layout and of every layout derived from it.
+    ///
+    /// Fails with `Error::TooManyBackingObjects` if this layout already exceeds the hard limit.
+    pub fn set_backing_object_limits(&self, limits: BackingObjectLimits) -> Result<()> {
+        limits.check(self.backing_object_count())?;
+        *self.layout.backing_object_limits.lock() = limits;
+        Ok(())
+    }
+
+    pub fn backing_object_stats(&self) -> BackingObjectStats {
+        BackingObjectStats {
+            regions: self.regions.len(),
+            backing_objects: self.backing_object_count(),
+            limits: *self.layout.backing_object_limits.lock(),
+        }
+    }
+
+    /// Returns the regions, sorted by guest address.
+    ///
+    /// The iterator can be reversed to walk the regions from the top of memory down.
+    pub fn regions(
+        &self,
+    ) -> impl DoubleEndedIterator<Item = MemoryRegionInformation<'_>> + ExactSizeIterator {
+        self.regions
+            .iter()
+            .enumerate()
+            .map(|(index, region)| MemoryRegionInformation {
+                index,
+                guest_addr: region.start(),
+                size: region.mapping.size(),
+                host_addr: region.mapping.as_ptr() as usize,
+                shared_obj: &region.shared_obj,
+                obj_offset: region.obj_offset,
+            })
+    }
+
     /// Perform the specified action on each region's addresses.
     ///
     /// Callback is called with arguments:
@@ -368,23 +949,75 @@ impl GuestMemory {
     ///  * host_addr: usize
     ///  * shm: Descriptor of the backing memory region
     ///  * shm_offset: usize
+    ///
+    /// New code should prefer iterating over `regions`.
     pub fn with_regions<F, E>(&self, mut cb: F) -> result::Result<(), E>
     where
         F: FnMut(usize, GuestAddress, usize, usize, &BackingObject, u64) -> result::Result<(), E>,
     {
-        for (index, region) in self.regions.iter().enumerate() {
+        for region in self.regions() {
             cb(
-                index,
-                region.start(),
-                region.mapping.size(),
-                region.mapping.as_ptr() as usize,
-                &region.shared_obj,
+                region.index,
+                region.guest_addr,
+                region.size,
+                region.host_addr,
+                region.shared_obj,
                 region.obj_offset,
             )?;
         }
         Ok(())
     }
 
+    /// Returns the region containing `guest_addr`.
+    ///
+    /// Accesses tend to hit the same region repeatedly, so the region found by the last lookup is
+    /// tried before a binary search of the sorted regions.
+    fn find_region(&self, guest_addr: GuestAddress) -> Option<&Arc<MemoryRegion>> {
+        // The index is only a hint, any region it names is checked like the searched one.
+        let last = self.last_region.load(Ordering::Relaxed);
+        if let Some(region) = self.regions.get(last) {
+            if region.contains(guest_addr) {
+                return Some(region);
+            }
+        }
+
+        let index = self
+            .regions
+            .partition_point(|region| region.start() <= guest_addr)
+            .checked_sub(1)?;
+        let region = &self.regions[index];
+        if !region.contains(guest_addr) {
+            return None;
+        }
+        self.last_region.store(index, Ordering::Relaxed);
+        Some(region)
+    }
+
+    fn region_at(&self, guest_addr: GuestAddress) -> Result<&MemoryRegion> {
+        self.find_region(guest_addr)
+            .map(|region| region.as_ref())
+            .ok_or(Error::InvalidGuestAddress(guest_addr))
+    }
+
+    /// Returns whether `guest_addr` is in a region the guest can only read.
+    pub fn is_read_only(&self, guest_addr: GuestAddress) -> bool {
+        self.region_at(guest_addr)
+            .map_or(false, |region| region.is_read_only())
+    }
+
+    /// Changes whether the host can write the region containing `guest_addr`, as
+    /// `MemoryRegion::set_host_writable` does.
+    pub fn set_host_writable(&self, guest_addr: GuestAddress, writable: bool) -> Result<()> {
+        self.region_at(guest_addr)?.set_host_writable(writable)
+    }
+
+    /// Writes the changes made to the `len` bytes starting at `guest_addr` back to the file backing
+    /// them. The range must lie within a single region.
+    pub fn sync_region(&self, guest_addr: GuestAddress, len: usize) -> Result<()> {
+        let region = self.region_at(guest_addr)?;
+        region.sync(guest_addr.offset_from(region.start()) as usize, len)
+    }
+
     /// Writes a slice to guest memory at the specified guest address.
     /// Returns the number of bytes written.  The number of bytes written can
     /// be less than the length of the slice if there isn't enough room in the
@@ -405,7 +1038,7 @@ impl GuestMemory {
     /// # }
     /// ```
     pub fn write_at_addr(&self, buf: &[u8], guest_addr: GuestAddress) -> Result<usize> {
-        self.do_in_region(guest_addr, move |mapping, offset, _| {
+        self.do_in_writable_region(guest_addr, move |mapping, offset, _| {
             mapping
                 .write_slice(buf, offset)
                 .map_err(|e| Error::MemoryAccess(guest_addr, e))
@@ -548,15 +1181,66 @@ impl GuestMemory {
     /// # }
     /// ```
     pub fn write_obj_at_addr<T: DataInit>(&self, val: T, guest_addr: GuestAddress) -> Result<()> {
-        self.do_in_region(guest_addr, move |mapping, offset, _| {
+        self.do_in_writable_region(guest_addr, move |mapping, offset, _| {
             mapping
                 .write_obj(val, offset)
                 .map_err(|e| Error::MemoryAccess(guest_addr, e))
         })
     }
 
+    /// Copies `len` bytes from `src` to `dst` directly between the mappings, without going
+    /// through an intermediate buffer.
+    ///
+    /// The ranges may overlap, in which case the copy behaves like `memmove`. Each range must be
+    /// within a single memory region, otherwise `Error::RangeCrossesRegion` is returned and
+    /// nothing is copied.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vm_memory::{guest_memory, GuestAddress, GuestMemory};
+    ///
+    /// fn test_copy_within() -> guest_memory::Result<()> {
+    ///     let gm = GuestMemory::new(&[(GuestAddress(0x1000), 0x1000)])?;
+    ///     gm.write_all_at_addr(b"abcd", GuestAddress(0x1000))?;
+    ///     gm.copy_within(GuestAddress(0x1000), GuestAddress(0x1800), 4)?;
+    ///     let mut buf = [0u8; 4];
+    ///     gm.read_exact_at_addr(&mut buf, GuestAddress(0x1800))?;
+    ///     assert_eq!(&buf, b"abcd");
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn copy_within(&self, src: GuestAddress, dst: GuestAddress, len: usize) -> Result<()> {
+        self.region_at(src)?.check_access(src, false)?;
+        let src_slice = self.region_slice(src, len)?;
+        let dst_region = self.region_at(dst)?;
+        dst_region.check_access(dst, true)?;
+        if !dst_region.is_host_writable() {
+            return Err(Error::ReadOnlyRegion(dst));
+        }
+        let dst_slice = self.region_slice(dst, len)?;
+        sys::catch_access_fault(|| {
+            src_slice.copy_to_volatile_slice(dst_slice);
+            Ok(())
+        })
+    }
+
+    // Returns the slice of `len` bytes at `addr`, which must be within a single region.
+    fn region_slice(&self, addr: GuestAddress, len: usize) -> Result<VolatileSlice> {
+        let region = self.region_at(addr)?;
+        // The cast is safe because the region contains `addr` and fits in a usize.
+        let offset = addr.offset_from(region.start()) as usize;
+        if len > region.mapping.size() - offset {
+            return Err(Error::RangeCrossesRegion { addr, len });
+        }
+        region
+            .mapping
+            .get_slice(offset, len)
+            .map_err(Error::VolatileMemoryAccess)
+    }
+
     /// Returns a `VolatileSlice` of `len` bytes starting at `addr`. Returns an error if the slice
-    /// is not a subset of this `GuestMemory`.
+    /// is not a subset of this `GuestMemory`, or is in a region the host can't access.
     ///
     /// # Examples
     /// * Write `99` to 30 bytes starting at guest address 0x1010.
@@ -573,11 +1257,10 @@ impl GuestMemory {
     /// # }
     /// ```
     pub fn get_slice_at_addr(&self, addr: GuestAddress, len: usize) -> Result<VolatileSlice> {
-        self.regions
-            .iter()
-            .find(|region| region.contains(addr))
+        self.find_region(addr)
             .ok_or(Error::InvalidGuestAddress(addr))
             .and_then(|region| {
+                region.check_access(addr, false)?;
                 // The cast to a usize is safe here because we know that `region.contains(addr)` and
                 // it's not possible for a memory region to be larger than what fits in a usize.
                 region
@@ -613,6 +1296,8 @@ impl GuestMemory {
     }
 
     /// Reads data from a file descriptor and writes it to guest memory.
+    /// Returns the number of bytes read, which can be less than `count` if
+    /// `src` reaches its end or fails after part of the data was read.
     ///
     /// # Arguments
     /// * `guest_addr` - Begin writing memory at this offset.
@@ -644,15 +1329,42 @@ impl GuestMemory {
         guest_addr: GuestAddress,
         src: &mut F,
         count: usize,
-    ) -> Result<()> {
-        self.do_in_region(guest_addr, move |mapping, offset, _| {
+    ) -> Result<usize> {
+        if count == 0 {
+            return Ok(0);
+        }
+        self.do_in_writable_region(guest_addr, move |mapping, offset, _| {
             mapping
                 .read_to_memory(offset, src, count)
                 .map_err(|e| Error::MemoryAccess(guest_addr, e))
         })
     }
 
+    /// Reads exactly `count` bytes from a file descriptor and writes them to
+    /// guest memory.
+    ///
+    /// Returns an error if `src` ends before `count` bytes were read. Part of
+    /// the data may have been written to guest memory nevertheless.
+    pub fn read_to_memory_exact<F: Read + AsRawDescriptor>(
+        &self,
+        guest_addr: GuestAddress,
+        src: &mut F,
+        count: usize,
+    ) -> Result<()> {
+        let completed = self.read_to_memory(guest_addr, src, count)?;
+        if count == completed {
+            Ok(())
+        } else {
+            Err(Error::ShortRead {
+                expected: count,
+                completed,
+            })
+        }
+    }
+
     /// Writes data from memory to a file descriptor.
+    /// Returns the number of bytes written, which can be less than `count` if
+    /// `dst` stops accepting data or fails after part of the data was written.
     ///
     /// # Arguments
     /// * `guest_addr` - Begin reading memory from this offset.
@@ -682,7 +1394,10 @@ impl GuestMemory {
         guest_addr: GuestAddress,
         dst: &mut F,
         count: usize,
-    ) -> Result<()> {
+    ) -> Result<usize> {
+        if count == 0 {
+            return Ok(0);
+        }
         self.do_in_region(guest_addr, move |mapping, offset, _| {
             mapping
                 .write_from_memory(offset, dst, count)
@@ -690,6 +1405,109 @@ impl GuestMemory {
         })
     }
 
+    /// Writes exactly `count` bytes from guest memory to a file descriptor.
+    ///
+    /// Returns an error if `dst` stops accepting data before `count` bytes
+    /// were written. Part of the data may have been written nevertheless.
+    pub fn write_from_memory_exact<F: Write + AsRawDescriptor>(
+        &self,
+        guest_addr: GuestAddress,
+        dst: &mut F,
+        count: usize,
+    ) -> Result<()> {
+        let completed = self.write_from_memory(guest_addr, dst, count)?;
+        if count == completed {
+            Ok(())
+        } else {
+            Err(Error::ShortWrite {
+                expected: count,
+                completed,
+            })
+        }
+    }
+
+    /// Returns the `MemRegion`s covering the `count` bytes of guest memory at `guest_addr`, one
+    /// per region the range spans. As in the `BackingMemory` implementation of `GuestMemory`, the
+    /// offset of each `MemRegion` is its guest address.
+    ///
+    /// Fails with `Error::RangeHole` if part of the range isn't guest memory, and like
+    /// `do_in_region` or `do_in_writable_region` if the host may not access or write it.
+    fn mem_regions(
+        &self,
+        guest_addr: GuestAddress,
+        count: usize,
+        write: bool,
+    ) -> Result<Vec<MemRegion>> {
+        let end = guest_addr
+            .checked_add(count as u64)
+            .ok_or(Error::InvalidGuestAddress(guest_addr))?;
+        let mut mem_regions = Vec::new();
+        let mut cur = guest_addr;
+        while cur < end {
+            let region = self.region_at(cur).map_err(|_| Error::RangeHole {
+                addr: guest_addr,
+                len: count as u64,
+                hole: cur,
+            })?;
+            region.check_access(cur, write)?;
+            if write && !region.is_host_writable() {
+                return Err(Error::ReadOnlyRegion(cur));
+            }
+            let len = min(region.end(), end).offset_from(cur);
+            mem_regions.push(MemRegion {
+                offset: cur.offset(),
+                // The cast is safe because `len` is at most `count`.
+                len: len as usize,
+            });
+            cur = cur.unchecked_add(len);
+        }
+        Ok(mem_regions)
+    }
+
+    /// Reads up to `count` bytes from `src` at `file_offset`, or at its current position if
+    /// `None`, into guest memory at `guest_addr` through the executor `src` belongs to.
+    /// Returns the number of bytes read.
+    ///
+    /// Unlike `read_to_memory`, the range may span several regions, which are filled by a single
+    /// request. The whole range is checked before anything is read.
+    pub async fn async_read_to_memory<R: ReadAsync + ?Sized>(
+        &self,
+        guest_addr: GuestAddress,
+        src: &R,
+        file_offset: Option<u64>,
+        count: usize,
+    ) -> Result<usize> {
+        if count == 0 {
+            return Ok(0);
+        }
+        let mem_regions = self.mem_regions(guest_addr, count, true)?;
+        src.read_to_mem(file_offset, Arc::new(self.clone()), &mem_regions)
+            .await
+            .map_err(|e| Error::MemoryAccess(guest_addr, MmapError::ReadToMemory(e.into())))
+    }
+
+    /// Writes up to `count` bytes of guest memory at `guest_addr` to `dst` at `file_offset`, or
+    /// at its current position if `None`, through the executor `dst` belongs to. Returns the
+    /// number of bytes written.
+    ///
+    /// Unlike `write_from_memory`, the range may span several regions, which are written by a
+    /// single request. The whole range is checked before anything is written.
+    pub async fn async_write_from_memory<W: WriteAsync + ?Sized>(
+        &self,
+        guest_addr: GuestAddress,
+        dst: &W,
+        file_offset: Option<u64>,
+        count: usize,
+    ) -> Result<usize> {
+        if count == 0 {
+            return Ok(0);
+        }
+        let mem_regions = self.mem_regions(guest_addr, count, false)?;
+        dst.write_from_mem(file_offset, Arc::new(self.clone()), &mem_regions)
+            .await
+            .map_err(|e| Error::MemoryAccess(guest_addr, MmapError::WriteFromMemory(e.into())))
+    }
+
     /// Convert a GuestAddress into a pointer in the address space of this
     /// process. This should only be necessary for giving addresses to the
     /// kernel, as with vhost ioctls. Normal reads/writes to guest memory should
@@ -769,9 +1587,7 @@ impl GuestMemory {
         &self,
         guest_addr: GuestAddress,
     ) -> Result<&(dyn AsRawDescriptor + Send + Sync)> {
-        self.regions
-            .iter()
-            .find(|region| region.contains(guest_addr))
+        self.find_region(guest_addr)
             .ok_or(Error::InvalidGuestAddress(guest_addr))
             .map(|region| region.shared_obj.as_ref())
     }
@@ -791,25 +1607,44 @@ impl GuestMemory {
     /// (ii) the relative offset from the start of the target region to `guest_addr`.
     /// (iii) the absolute offset from the start of the memory mapping to the target region.
     ///
-    /// If no target region is found, an error is returned.  The callback function `F` may return
-    /// an Ok(`T`) on success or a `GuestMemoryError` on failure.
+    /// If no target region is found, an error is returned, as is `Error::ProtectedRegionAccess` if
+    /// the host can't access the target region. The callback function `F` may return
+    /// an Ok(`T`) on success or a `GuestMemoryError` on failure. If an access made by `F` raises
+    /// SIGBUS, e.g. because the file backing the region was truncated, `Error::MemoryAccess` is
+    /// returned for the faulting address.
     pub fn do_in_region<F, T>(&self, guest_addr: GuestAddress, cb: F) -> Result<T>
     where
         F: FnOnce(&MemoryMapping, usize, u64) -> Result<T>,
     {
-        self.regions
-            .iter()
-            .find(|region| region.contains(guest_addr))
+        self.find_region(guest_addr)
             .ok_or(Error::InvalidGuestAddress(guest_addr))
             .and_then(|region| {
-                cb(
-                    &region.mapping,
-                    guest_addr.offset_from(region.start()) as usize,
-                    region.obj_offset,
-                )
+                region.check_access(guest_addr, false)?;
+                sys::catch_access_fault(|| {
+                    cb(
+                        &region.mapping,
+                        guest_addr.offset_from(region.start()) as usize,
+                        region.obj_offset,
+                    )
+                })
             })
     }
 
+    /// Like `do_in_region`, but fails with `Error::ProtectedRegionAccess` if the access policy of
+    /// the region containing `guest_addr` forbids writes, or with `Error::ReadOnlyRegion` if its
+    /// host mapping isn't writable.
+    fn do_in_writable_region<F, T>(&self, guest_addr: GuestAddress, cb: F) -> Result<T>
+    where
+        F: FnOnce(&MemoryMapping, usize, u64) -> Result<T>,
+    {
+        let region = self.region_at(guest_addr)?;
+        region.check_access(guest_addr, true)?;
+        if !region.is_host_writable() {
+            return Err(Error::ReadOnlyRegion(guest_addr));
+        }
+        self.do_in_region(guest_addr, cb)
+    }
+
     /// Convert a GuestAddress into an offset within the associated shm region.
     ///
     /// Due to potential gaps within GuestMemory, it is helpful to know the
@@ -834,9 +1669,7 @@ impl GuestMemory {
     /// assert_eq!(offset, 0x35000);
     /// ```
     pub fn offset_from_base(&self, guest_addr: GuestAddress) -> Result<u64> {
-        self.regions
-            .iter()
-            .find(|region| region.contains(guest_addr))
+        self.find_region(guest_addr)
             .ok_or(Error::InvalidGuestAddress(guest_addr))
             .map(|region| region.obj_offset + guest_addr.offset_from(region.start()))
     }
@@ -844,22 +1677,37 @@ impl GuestMemory {
 
 // It is safe to implement BackingMemory because GuestMemory can be mutated any time already.
 unsafe impl BackingMemory for GuestMemory {
-    fn get_volatile_slice(
-        &self,
-        mem_range: cros_async::MemRegion,
-    ) -> mem::Result<VolatileSlice<'_>> {
+    fn get_volatile_slice(&self, mem_range: MemRegion) -> mem::Result<VolatileSlice<'_>> {
         self.get_slice_at_addr(GuestAddress(mem_range.offset as u64), mem_range.len)
-            .map_err(|_| mem::Error::InvalidOffset(mem_range.offset, mem_range.len))
+            .map_err(|e| match e {
+                Error::ProtectedRegionAccess(_) => {
+                    mem::Error::ProtectedRegionAccess(mem_range.offset, mem_range.len)
+                }
+                _ => mem::Error::InvalidOffset(mem_range.offset, mem_range.len),
+            })
     }
 }
 
 #[cfg(test)]
 mod tests {
-    #[cfg(unix)]
-    use base::kernel_has_memfd;
-
     use super::*;
 
+    /// Creates guest memory for `ranges` with every kind of backing object `GuestMemory::new` may
+    /// use: shared memory and, on Unix, the file used when the kernel lacks memfd.
+    fn new_guest_memories(ranges: &[(GuestAddress, u64)]) -> Vec<GuestMemory> {
+        #[allow(unused_mut)]
+        let mut mems = vec![GuestMemory::new(ranges).unwrap()];
+        #[cfg(unix)]
+        {
+            let size = ranges.iter().map(|r| r.1).sum();
+            let file = sys::unix::create_fallback_file(&std::env::temp_dir(), size).unwrap();
+            mems.push(
+                GuestMemory::with_backing(ranges, BackingObject::File(Arc::new(file))).unwrap(),
+            );
+        }
+        mems
+    }
+
     #[test]
     fn test_alignment() {
         let start_addr1 = GuestAddress(0x0);
@@ -874,13 +1722,13 @@ mod tests {
         let start_addr1 = GuestAddress(0x0);
         let start_addr2 = GuestAddress(0x10000);
         // The memory regions are `[0x0, 0x10000)`, `[0x10000, 0x20000)`.
-        let gm = GuestMemory::new(&[(start_addr1, 0x10000), (start_addr2, 0x10000)]).unwrap();
-
-        // Although each address in `[0x0, 0x20000)` is valid, `is_valid_range()` returns false for
-        // a range that is across multiple underlying regions.
-        assert!(gm.is_valid_range(GuestAddress(0x5000), 0x5000));
-        assert!(gm.is_valid_range(GuestAddress(0x10000), 0x5000));
-        assert!(!gm.is_valid_range(GuestAddress(0x5000), 0x10000));
+        for gm in new_guest_memories(&[(start_addr1, 0x10000), (start_addr2, 0x10000)]) {
+            // Although each address in `[0x0, 0x20000)` is valid, `is_valid_range()` returns false
+            // for a range that is across multiple underlying regions.
+            assert!(gm.is_valid_range(GuestAddress(0x5000), 0x5000));
+            assert!(gm.is_valid_range(GuestAddress(0x10000), 0x5000));
+            assert!(!gm.is_valid_range(GuestAddress(0x5000), 0x10000));
+        }
     }
 
     #[test]
@@ -890,94 +1738,287 @@ mod tests {
         assert!(GuestMemory::new(&[(start_addr1, 0x20000), (start_addr2, 0x20000)]).is_err());
     }
 
+    #[test]
+    fn unsorted_memory() {
+        let start_addr1 = GuestAddress(0x0);
+        let start_addr2 = GuestAddress(0x20000);
+        assert!(matches!(
+            GuestMemory::new(&[(start_addr2, 0x10000), (start_addr1, 0x10000)]),
+            Err(Error::MemoryRegionsUnsorted)
+        ));
+    }
+
+    /// Ranges of `count` regions of 64 KiB, separated by holes of 64 KiB.
+    fn sparse_ranges(count: u64) -> Vec<(GuestAddress, u64)> {
+        (0..count)
+            .map(|i| (GuestAddress(i * 0x20000), 0x10000))
+            .collect()
+    }
+
+    #[test]
+    fn find_region_matches_containing_region() {
+        let gm = GuestMemory::new(&sparse_ranges(33)).unwrap();
+        // Visit every region and hole in an order that alternates between cache hits and misses.
+        let mut addrs = Vec::new();
+        for i in 0..33 * 2 + 1 {
+            let addr = (i * 7919 % 67) * 0x10000;
+            addrs.extend_from_slice(&[addr, addr + 0x8000, addr + 0xffff, addr + 0x8000]);
+        }
+        for addr in addrs.into_iter().map(GuestAddress) {
+            let expected = gm.regions.iter().find(|region| region.contains(addr));
+            match gm.find_region(addr) {
+                Some(region) => {
+                    assert!(region.contains(addr), "{} not in the region found", addr);
+                    assert!(Arc::ptr_eq(region, expected.unwrap()));
+                }
+                None => assert!(expected.is_none(), "{} not found", addr),
+            }
+        }
+        assert!(gm.find_region(GuestAddress(u64::MAX)).is_none());
+    }
+
+    /// Compares the region lookup with a linear scan of the regions. Run with
+    /// `cargo test -p vm_memory -- --ignored --nocapture region_lookup_benchmark`.
+    #[test]
+    #[ignore]
+    fn region_lookup_benchmark() {
+        use std::time::Instant;
+
+        const LOOKUPS: u64 = 1_000_000;
+        let gm = GuestMemory::new(&sparse_ranges(64)).unwrap();
+        // Lookups that mostly stay in a region, as the accesses of a device do.
+        let addr = |i: u64| GuestAddress((i / 16 * 7919 % 64) * 0x20000 + i % 16 * 0x100);
+        // The sums of the region addresses found keep the lookups from being optimized out.
+        let start = Instant::now();
+        let lookup_sum: u64 = (0..LOOKUPS)
+            .map(|i| gm.find_region(addr(i)).unwrap().start().0)
+            .sum();
+        let lookup = start.elapsed();
+
+        let start = Instant::now();
+        let scan_sum: u64 = (0..LOOKUPS)
+            .map(|i| {
+                let addr = addr(i);
+                let region = gm.regions.iter().find(|region| region.contains(addr));
+                region.unwrap().start().0
+            })
+            .sum();
+        let scan = start.elapsed();
+
+        assert_eq!(lookup_sum, scan_sum);
+        println!(
+            "64 regions: lookup {:?}, linear scan {:?} for {} lookups",
+            lookup, scan, LOOKUPS
+        );
+    }
+
     #[test]
     fn region_hole() {
         let start_addr1 = GuestAddress(0x0);
         let start_addr2 = GuestAddress(0x40000);
         // The memory regions are `[0x0, 0x20000)`, `[0x40000, 0x60000)`.
-        let gm = GuestMemory::new(&[(start_addr1, 0x20000), (start_addr2, 0x20000)]).unwrap();
-
-        assert!(gm.address_in_range(GuestAddress(0x10000)));
-        assert!(!gm.address_in_range(GuestAddress(0x30000)));
-        assert!(gm.address_in_range(GuestAddress(0x50000)));
-        assert!(!gm.address_in_range(GuestAddress(0x60000)));
-        assert!(!gm.address_in_range(GuestAddress(0x60000)));
-        assert!(gm.range_overlap(GuestAddress(0x10000), GuestAddress(0x30000)),);
-        assert!(!gm.range_overlap(GuestAddress(0x30000), GuestAddress(0x40000)),);
-        assert!(gm.range_overlap(GuestAddress(0x30000), GuestAddress(0x70000)),);
-        assert_eq!(gm.checked_offset(GuestAddress(0x10000), 0x10000), None);
-        assert_eq!(
-            gm.checked_offset(GuestAddress(0x50000), 0x8000),
-            Some(GuestAddress(0x58000))
-        );
-        assert_eq!(gm.checked_offset(GuestAddress(0x50000), 0x10000), None);
-        assert!(gm.is_valid_range(GuestAddress(0x0), 0x10000));
-        assert!(gm.is_valid_range(GuestAddress(0x0), 0x20000));
-        assert!(!gm.is_valid_range(GuestAddress(0x0), 0x20000 + 1));
-
-        // While `checked_offset(GuestAddress(0x10000), 0x40000)` succeeds because 0x50000 is a
-        // valid address, `is_valid_range(GuestAddress(0x10000), 0x40000)` returns `false`
-        // because there is a hole inside of [0x10000, 0x50000).
-        assert_eq!(
-            gm.checked_offset(GuestAddress(0x10000), 0x40000),
-            Some(GuestAddress(0x50000))
-        );
-        assert!(!gm.is_valid_range(GuestAddress(0x10000), 0x40000));
+        for gm in new_guest_memories(&[(start_addr1, 0x20000), (start_addr2, 0x20000)]) {
+            assert!(gm.address_in_range(GuestAddress(0x10000)));
+            assert!(!gm.address_in_range(GuestAddress(0x30000)));
+            assert!(gm.address_in_range(GuestAddress(0x50000)));
+            assert!(!gm.address_in_range(GuestAddress(0x60000)));
+            assert!(!gm.address_in_range(GuestAddress(0x60000)));
+            assert!(gm.range_overlap(GuestAddress(0x10000), GuestAddress(0x30000)),);
+            assert!(!gm.range_overlap(GuestAddress(0x30000), GuestAddress(0x40000)),);
+            assert!(gm.range_overlap(GuestAddress(0x30000), GuestAddress(0x70000)),);
+            assert_eq!(gm.checked_offset(GuestAddress(0x10000), 0x10000), None);
+            assert_eq!(
+                gm.checked_offset(GuestAddress(0x50000), 0x8000),
+                Some(GuestAddress(0x58000))
+            );
+            assert_eq!(gm.checked_offset(GuestAddress(0x50000), 0x10000), None);
+            assert!(gm.is_valid_range(GuestAddress(0x0), 0x10000));
+            assert!(gm.is_valid_range(GuestAddress(0x0), 0x20000));
+            assert!(!gm.is_valid_range(GuestAddress(0x0), 0x20000 + 1));
+
+            // While `checked_offset(GuestAddress(0x10000), 0x40000)` succeeds because 0x50000 is a
+            // valid address, `is_valid_range(GuestAddress(0x10000), 0x40000)` returns `false`
+            // because there is a hole inside of [0x10000, 0x50000).
+            assert_eq!(
+                gm.checked_offset(GuestAddress(0x10000), 0x40000),
+                Some(GuestAddress(0x50000))
+            );
+            assert!(!gm.is_valid_range(GuestAddress(0x10000), 0x40000));
+        }
     }
 
     #[test]
     fn test_read_u64() {
         let start_addr1 = GuestAddress(0x0);
         let start_addr2 = GuestAddress(0x10000);
-        let gm = GuestMemory::new(&[(start_addr1, 0x10000), (start_addr2, 0x10000)]).unwrap();
+        for gm in new_guest_memories(&[(start_addr1, 0x10000), (start_addr2, 0x10000)]) {
+            let val1: u64 = 0xaa55aa55aa55aa55;
+            let val2: u64 = 0x55aa55aa55aa55aa;
+            gm.write_obj_at_addr(val1, GuestAddress(0x500)).unwrap();
+            gm.write_obj_at_addr(val2, GuestAddress(0x10000 + 32))
+                .unwrap();
+            let num1: u64 = gm.read_obj_from_addr(GuestAddress(0x500)).unwrap();
+            let num2: u64 = gm.read_obj_from_addr(GuestAddress(0x10000 + 32)).unwrap();
+            assert_eq!(val1, num1);
+            assert_eq!(val2, num2);
+        }
+    }
+
+    #[test]
+    fn copy_within_overlapping() {
+        for gm in new_guest_memories(&[(GuestAddress(0x0), 0x10000)]) {
+            let data: Vec<u8> = (0..16).collect();
+            let mut buf = [0u8; 16];
+
+            // Forward, towards higher addresses.
+            gm.write_all_at_addr(&data, GuestAddress(0x100)).unwrap();
+            gm.copy_within(GuestAddress(0x100), GuestAddress(0x104), 16)
+                .unwrap();
+            gm.read_exact_at_addr(&mut buf, GuestAddress(0x104))
+                .unwrap();
+            assert_eq!(&buf[..], &data[..]);
+
+            // Backward, towards lower addresses.
+            gm.write_all_at_addr(&data, GuestAddress(0x200)).unwrap();
+            gm.copy_within(GuestAddress(0x200), GuestAddress(0x1fc), 16)
+                .unwrap();
+            gm.read_exact_at_addr(&mut buf, GuestAddress(0x1fc))
+                .unwrap();
+            assert_eq!(&buf[..], &data[..]);
+        }
+    }
+
+    #[test]
+    fn copy_within_regions() {
+        for gm in new_guest_memories(&[
+            (GuestAddress(0x0), 0x10000),
+            (GuestAddress(0x10000), 0x10000),
+        ]) {
+            let data = [0x5au8; 0x100];
+            let mut buf = [0u8; 0x100];
+            gm.write_all_at_addr(&data, GuestAddress(0xf000)).unwrap();
+            gm.copy_within(GuestAddress(0xf000), GuestAddress(0x18000), 0x100)
+                .unwrap();
+            gm.read_exact_at_addr(&mut buf, GuestAddress(0x18000))
+                .unwrap();
+            assert_eq!(buf, data);
+
+            // Neither range may cross into the next region.
+            assert!(matches!(
+                gm.copy_within(GuestAddress(0xff80), GuestAddress(0x18000), 0x100),
+                Err(Error::RangeCrossesRegion { addr, len: 0x100 }) if addr == GuestAddress(0xff80)
+            ));
+            assert!(matches!(
+                gm.copy_within(GuestAddress(0x18000), GuestAddress(0xff80), 0x100),
+                Err(Error::RangeCrossesRegion { addr, len: 0x100 }) if addr == GuestAddress(0xff80)
+            ));
+            assert!(matches!(
+                gm.copy_within(GuestAddress(0x18000), GuestAddress(0x20000), 0x100),
+                Err(Error::InvalidGuestAddress(_))
+            ));
+        }
+    }
 
-        let val1: u64 = 0xaa55aa55aa55aa55;
-        let val2: u64 = 0x55aa55aa55aa55aa;
-        gm.write_obj_at_addr(val1, GuestAddress(0x500)).unwrap();
-        gm.write_obj_at_addr(val2, GuestAddress(0x10000 + 32))
+    #[test]
+    fn protected_region_access() {
+        let gm = GuestMemory::new_with_access_policies(
+            &[
+                (GuestAddress(0x0), 0x10000),
+                (GuestAddress(0x10000), 0x10000),
+                (GuestAddress(0x20000), 0x10000),
+            ],
+            Vec::new(),
+            &[
+                (GuestAddress(0x0), MemoryAccessPolicy::Inaccessible),
+                (GuestAddress(0x20000), MemoryAccessPolicy::ReadOnly),
+            ],
+        )
+        .unwrap();
+        let addr = GuestAddress(0x100);
+        let protected =
+            |r: Result<()>| matches!(r, Err(Error::ProtectedRegionAccess(a)) if a == addr);
+        let mut buf = [0u8; 4];
+
+        assert!(protected(gm.write_all_at_addr(b"abcd", addr)));
+        assert!(protected(gm.read_exact_at_addr(&mut buf, addr)));
+        assert!(protected(gm.get_slice_at_addr(addr, 4).map(|_| ())));
+        assert!(protected(gm.get_host_address(addr).map(|_| ())));
+        assert!(protected(gm.copy_within(GuestAddress(0x10000), addr, 4)));
+        assert!(matches!(
+            gm.get_volatile_slice(cros_async::MemRegion {
+                offset: 0x100,
+                len: 4
+            }),
+            Err(mem::Error::ProtectedRegionAccess(0x100, 4))
+        ));
+
+        // The host can read a read-only region, but not write it.
+        gm.read_exact_at_addr(&mut buf, GuestAddress(0x20000))
             .unwrap();
-        let num1: u64 = gm.read_obj_from_addr(GuestAddress(0x500)).unwrap();
-        let num2: u64 = gm.read_obj_from_addr(GuestAddress(0x10000 + 32)).unwrap();
-        assert_eq!(val1, num1);
-        assert_eq!(val2, num2);
+        assert!(matches!(
+            gm.write_obj_at_addr(0u32, GuestAddress(0x20000)),
+            Err(Error::ProtectedRegionAccess(_))
+        ));
+        gm.copy_within(GuestAddress(0x20000), GuestAddress(0x10000), 4)
+            .unwrap();
+
+        // The rest of memory is still accessible.
+        gm.write_all_at_addr(b"abcd", GuestAddress(0x10000))
+            .unwrap();
+        gm.read_exact_at_addr(&mut buf, GuestAddress(0x10000))
+            .unwrap();
+        assert_eq!(&buf, b"abcd");
+        assert!(gm.get_host_address(GuestAddress(0x10000)).is_ok());
+    }
+
+    #[test]
+    fn access_policy_needs_region_start() {
+        assert!(matches!(
+            GuestMemory::new_with_access_policies(
+                &[(GuestAddress(0x0), 0x10000)],
+                Vec::new(),
+                &[(GuestAddress(0x1000), MemoryAccessPolicy::Inaccessible)],
+            ),
+            Err(Error::InvalidGuestAddress(_))
+        ));
     }
 
     #[test]
     fn test_ref_load_u64() {
         let start_addr1 = GuestAddress(0x0);
         let start_addr2 = GuestAddress(0x10000);
-        let gm = GuestMemory::new(&[(start_addr1, 0x10000), (start_addr2, 0x10000)]).unwrap();
-
-        let val1: u64 = 0xaa55aa55aa55aa55;
-        let val2: u64 = 0x55aa55aa55aa55aa;
-        gm.write_obj_at_addr(val1, GuestAddress(0x500)).unwrap();
-        gm.write_obj_at_addr(val2, GuestAddress(0x10000 + 32))
-            .unwrap();
-        let num1: u64 = gm.get_ref_at_addr(GuestAddress(0x500)).unwrap().load();
-        let num2: u64 = gm
-            .get_ref_at_addr(GuestAddress(0x10000 + 32))
-            .unwrap()
-            .load();
-        assert_eq!(val1, num1);
-        assert_eq!(val2, num2);
+        for gm in new_guest_memories(&[(start_addr1, 0x10000), (start_addr2, 0x10000)]) {
+            let val1: u64 = 0xaa55aa55aa55aa55;
+            let val2: u64 = 0x55aa55aa55aa55aa;
+            gm.write_obj_at_addr(val1, GuestAddress(0x500)).unwrap();
+            gm.write_obj_at_addr(val2, GuestAddress(0x10000 + 32))
+                .unwrap();
+            let num1: u64 = gm.get_ref_at_addr(GuestAddress(0x500)).unwrap().load();
+            let num2: u64 = gm
+                .get_ref_at_addr(GuestAddress(0x10000 + 32))
+                .unwrap()
+                .load();
+            assert_eq!(val1, num1);
+            assert_eq!(val2, num2);
+        }
     }
 
     #[test]
     fn test_ref_store_u64() {
         let start_addr1 = GuestAddress(0x0);
         let start_addr2 = GuestAddress(0x10000);
-        let gm = GuestMemory::new(&[(start_addr1, 0x10000), (start_addr2, 0x10000)]).unwrap();
-
-        let val1: u64 = 0xaa55aa55aa55aa55;
-        let val2: u64 = 0x55aa55aa55aa55aa;
-        gm.get_ref_at_addr(GuestAddress(0x500)).unwrap().store(val1);
-        gm.get_ref_at_addr(GuestAddress(0x1000 + 32))
-            .unwrap()
-            .store(val2);
-        let num1: u64 = gm.read_obj_from_addr(GuestAddress(0x500)).unwrap();
-        let num2: u64 = gm.read_obj_from_addr(GuestAddress(0x1000 + 32)).unwrap();
-        assert_eq!(val1, num1);
-        assert_eq!(val2, num2);
+        for gm in new_guest_memories(&[(start_addr1, 0x10000), (start_addr2, 0x10000)]) {
+            let val1: u64 = 0xaa55aa55aa55aa55;
+            let val2: u64 = 0x55aa55aa55aa55aa;
+            gm.get_ref_at_addr(GuestAddress(0x500)).unwrap().store(val1);
+            gm.get_ref_at_addr(GuestAddress(0x1000 + 32))
+                .unwrap()
+                .store(val2);
+            let num1: u64 = gm.read_obj_from_addr(GuestAddress(0x500)).unwrap();
+            let num2: u64 = gm.read_obj_from_addr(GuestAddress(0x1000 + 32)).unwrap();
+            assert_eq!(val1, num1);
+            assert_eq!(val2, num2);
+        }
     }
 
     #[test]
@@ -986,11 +2027,12 @@ mod tests {
         let size_region1 = 0x10000;
         let start_region2 = GuestAddress(0x10000);
         let size_region2 = 0x20000;
-        let gm = GuestMemory::new(&[(start_region1, size_region1), (start_region2, size_region2)])
-            .unwrap();
-
-        let mem_size = gm.memory_size();
-        assert_eq!(mem_size, size_region1 + size_region2);
+        for gm in
+            new_guest_memories(&[(start_region1, size_region1), (start_region2, size_region2)])
+        {
+            let mem_size = gm.memory_size();
+            assert_eq!(mem_size, size_region1 + size_region2);
+        }
     }
 
     // Get the base address of the mapping for a GuestAddress.
@@ -1002,90 +2044,268 @@ mod tests {
     fn guest_to_host() {
         let start_addr1 = GuestAddress(0x0);
         let start_addr2 = GuestAddress(0x10000);
-        let mem = GuestMemory::new(&[(start_addr1, 0x10000), (start_addr2, 0x40000)]).unwrap();
-
-        // Verify the host addresses match what we expect from the mappings.
-        let addr1_base = get_mapping(&mem, start_addr1).unwrap();
-        let addr2_base = get_mapping(&mem, start_addr2).unwrap();
-        let host_addr1 = mem.get_host_address(start_addr1).unwrap();
-        let host_addr2 = mem.get_host_address(start_addr2).unwrap();
-        assert_eq!(host_addr1, addr1_base);
-        assert_eq!(host_addr2, addr2_base);
-
-        // Check that a bad address returns an error.
-        let bad_addr = GuestAddress(0x123456);
-        assert!(mem.get_host_address(bad_addr).is_err());
+        for mem in new_guest_memories(&[(start_addr1, 0x10000), (start_addr2, 0x40000)]) {
+            // Verify the host addresses match what we expect from the mappings.
+            let addr1_base = get_mapping(&mem, start_addr1).unwrap();
+            let addr2_base = get_mapping(&mem, start_addr2).unwrap();
+            let host_addr1 = mem.get_host_address(start_addr1).unwrap();
+            let host_addr2 = mem.get_host_address(start_addr2).unwrap();
+            assert_eq!(host_addr1, addr1_base);
+            assert_eq!(host_addr2, addr2_base);
+
+            // Check that a bad address returns an error.
+            let bad_addr = GuestAddress(0x123456);
+            assert!(mem.get_host_address(bad_addr).is_err());
+        }
     }
 
     #[test]
     fn guest_to_host_range() {
         let start_addr1 = GuestAddress(0x0);
         let start_addr2 = GuestAddress(0x10000);
-        let mem = GuestMemory::new(&[(start_addr1, 0x10000), (start_addr2, 0x40000)]).unwrap();
-
-        // Verify the host addresses match what we expect from the mappings.
-        let addr1_base = get_mapping(&mem, start_addr1).unwrap();
-        let addr2_base = get_mapping(&mem, start_addr2).unwrap();
-        let host_addr1 = mem.get_host_address_range(start_addr1, 0x10000).unwrap();
-        let host_addr2 = mem.get_host_address_range(start_addr2, 0x10000).unwrap();
-        assert_eq!(host_addr1, addr1_base);
-        assert_eq!(host_addr2, addr2_base);
+        for mem in new_guest_memories(&[(start_addr1, 0x10000), (start_addr2, 0x40000)]) {
+            // Verify the host addresses match what we expect from the mappings.
+            let addr1_base = get_mapping(&mem, start_addr1).unwrap();
+            let addr2_base = get_mapping(&mem, start_addr2).unwrap();
+            let host_addr1 = mem.get_host_address_range(start_addr1, 0x10000).unwrap();
+            let host_addr2 = mem.get_host_address_range(start_addr2, 0x10000).unwrap();
+            assert_eq!(host_addr1, addr1_base);
+            assert_eq!(host_addr2, addr2_base);
+
+            let host_addr3 = mem.get_host_address_range(start_addr2, 0x20000).unwrap();
+            assert_eq!(host_addr3, addr2_base);
+
+            // Check that a valid guest address with an invalid size returns an error.
+            assert!(mem.get_host_address_range(start_addr1, 0x20000).is_err());
+
+            // Check that a bad address returns an error.
+            let bad_addr = GuestAddress(0x123456);
+            assert!(mem.get_host_address_range(bad_addr, 0x10000).is_err());
+        }
+    }
 
-        let host_addr3 = mem.get_host_address_range(start_addr2, 0x20000).unwrap();
-        assert_eq!(host_addr3, addr2_base);
+    #[test]
+    fn regions_iterator() {
+        let ranges = sparse_ranges(3);
+        for gm in new_guest_memories(&ranges) {
+            let regions: Vec<MemoryRegionInformation> = gm.regions().collect();
+            assert_eq!(regions.len(), 3);
+            for (i, (region, range)) in regions.iter().zip(&ranges).enumerate() {
+                assert_eq!(region.index, i);
+                assert_eq!(region.guest_addr, range.0);
+                assert_eq!(region.size as u64, range.1);
+                assert_eq!(
+                    region.host_addr as *const u8,
+                    gm.get_host_address(range.0).unwrap()
+                );
+                // The regions are laid out contiguously in the backing object.
+                assert_eq!(region.obj_offset, i as u64 * 0x10000);
+            }
 
-        // Check that a valid guest address with an invalid size returns an error.
-        assert!(mem.get_host_address_range(start_addr1, 0x20000).is_err());
+            let top_down: Vec<usize> = gm.regions().rev().map(|region| region.index).collect();
+            assert_eq!(top_down, [2, 1, 0]);
 
-        // Check that a bad address returns an error.
-        let bad_addr = GuestAddress(0x123456);
-        assert!(mem.get_host_address_range(bad_addr, 0x10000).is_err());
+            let mut visited = Vec::new();
+            gm.with_regions::<_, ()>(|index, guest_addr, size, host_addr, _, obj_offset| {
+                visited.push((index, guest_addr, size, host_addr, obj_offset));
+                Ok(())
+            })
+            .unwrap();
+            let expected: Vec<_> = regions
+                .iter()
+                .map(|r| (r.index, r.guest_addr, r.size, r.host_addr, r.obj_offset))
+                .collect();
+            assert_eq!(visited, expected);
+        }
     }
 
     #[test]
     fn shm_offset() {
-        #[cfg(unix)]
-        {
-            // This is simply a sanity check on linux platforms to ensure the tests
-            // won't run on old kernels that don't support memfd_create.
-            if !kernel_has_memfd() {
-                return;
-            }
-        }
-
         let start_region1 = GuestAddress(0x0);
         let size_region1 = 0x10000;
         let start_region2 = GuestAddress(0x10000);
         let size_region2 = 0x20000;
-        let gm = GuestMemory::new(&[(start_region1, size_region1), (start_region2, size_region2)])
-            .unwrap();
-
-        gm.write_obj_at_addr(0x1337u16, GuestAddress(0x0)).unwrap();
-        gm.write_obj_at_addr(0x0420u16, GuestAddress(0x10000))
-            .unwrap();
+        for gm in
+            new_guest_memories(&[(start_region1, size_region1), (start_region2, size_region2)])
+        {
+            gm.write_obj_at_addr(0x1337u16, GuestAddress(0x0)).unwrap();
+            gm.write_obj_at_addr(0x0420u16, GuestAddress(0x10000))
+                .unwrap();
 
-        let _ = gm.with_regions::<_, ()>(|index, _, size, _, obj, offset| {
-            let shm = match obj {
-                BackingObject::Shm(s) => s,
-                _ => {
-                    panic!("backing object isn't SharedMemory");
+            for region in gm.regions() {
+                let builder = MemoryMappingBuilder::new(region.size);
+                let builder = match region.shared_obj {
+                    BackingObject::Shm(shm) => builder.from_shared_memory(shm),
+                    BackingObject::File(file) => builder.from_file(file),
+                    #[cfg(all(unix, feature = "userfaultfd"))]
+                    BackingObject::Lazy(lazy) => builder.from_shared_memory(lazy.shm()),
+                };
+                let mmap = builder.offset(region.obj_offset).build().unwrap();
+
+                if region.index == 0 {
+                    assert!(mmap.read_obj::<u16>(0x0).unwrap() == 0x1337u16);
                 }
-            };
-            let mmap = MemoryMappingBuilder::new(size)
-                .from_shared_memory(shm)
-                .offset(offset)
-                .build()
-                .unwrap();
 
-            if index == 0 {
-                assert!(mmap.read_obj::<u16>(0x0).unwrap() == 0x1337u16);
+                if region.index == 1 {
+                    assert!(mmap.read_obj::<u16>(0x0).unwrap() == 0x0420u16);
+                }
             }
+        }
+    }
 
-            if index == 1 {
-                assert!(mmap.read_obj::<u16>(0x0).unwrap() == 0x0420u16);
-            }
+    struct FakeObserver {
+        id: usize,
+        log: Arc<Mutex<Vec<(usize, u64, bool)>>>,
+    }
 
-            Ok(())
+    impl MemoryObserver for FakeObserver {
+        fn memory_layout_changed(&self, mem: &GuestMemory) {
+            // Record whether the new layout was already visible when notified.
+            self.log
+                .lock()
+                .push((self.id, mem.generation(), mem.is_current_layout()));
+        }
+    }
+
+    fn layout_regions(size: u64) -> Vec<MemoryRegion> {
+        let shm = Arc::new(SharedMemory::new("test", size).unwrap());
+        vec![MemoryRegion::new_from_shm(size, GuestAddress(0), 0, shm).unwrap()]
+    }
+
+    #[test]
+    fn layout_change_notifies_observers() {
+        let gm = GuestMemory::new(&[(GuestAddress(0), 0x10000)]).unwrap();
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let first: Arc<dyn MemoryObserver> = Arc::new(FakeObserver {
+            id: 0,
+            log: log.clone(),
         });
+        let second: Arc<dyn MemoryObserver> = Arc::new(FakeObserver {
+            id: 1,
+            log: log.clone(),
+        });
+        gm.add_observer(&first);
+        gm.add_observer(&second);
+        assert!(gm.is_current_layout());
+
+        let gm2 = gm.update_layout(layout_regions(0x20000)).unwrap();
+        assert_eq!(gm2.generation(), 1);
+        assert_eq!(gm2.memory_size(), 0x20000);
+        assert!(gm2.is_current_layout());
+        assert!(!gm.is_current_layout());
+        assert_eq!(*log.lock(), vec![(0, 1, true), (1, 1, true)]);
+
+        // Observers subscribed through an older layout still follow newer ones, and dropped
+        // observers are no longer notified.
+        drop(first);
+        log.lock().clear();
+        let gm3 = gm2.update_layout(layout_regions(0x10000)).unwrap();
+        assert_eq!(gm3.generation(), 2);
+        assert!(!gm2.is_current_layout());
+        assert_eq!(*log.lock(), vec![(1, 2, true)]);
+    }
+
+    #[test]
+    fn layout_event_observer() {
+        let gm = GuestMemory::new(&[(GuestAddress(0), 0x10000)]).unwrap();
+        let layout_evt = Arc::new(MemoryLayoutEvent::new().unwrap());
+        let observer: Arc<dyn MemoryObserver> = layout_evt.clone();
+        gm.add_observer(&observer);
+
+        let gm2 = gm.update_layout(layout_regions(0x20000)).unwrap();
+        let pending = layout_evt.take().unwrap();
+        assert_eq!(pending.generation(), gm2.generation());
+        assert!(layout_evt.take().is_none());
+    }
+
+    #[test]
+    fn access_context_names_device() {
+        let gm = GuestMemory::new(&[(GuestAddress(0), 0x10000)]).unwrap();
+        let context = AccessContext::new("test-access-context").with_queue(2);
+
+        let err = context
+            .attach(gm.read_obj_from_addr::<u64>(GuestAddress(0x20000)))
+            .unwrap_err();
+        let message = err.to_string();
+        assert!(
+            message.contains("device test-access-context queue 2"),
+            "{}",
+            message
+        );
+        assert!(message.contains("invalid guest address"), "{}", message);
+
+        assert!(context
+            .attach(gm.read_obj_from_addr::<u64>(GuestAddress(0)))
+            .is_ok());
+        assert_eq!(access_fault_counts().get("test-access-context"), Some(&1));
+    }
+
+    /// Reads a pattern spanning both regions of guest memory from a file through `ex` and writes
+    /// it back to the file at another offset.
+    #[cfg(unix)]
+    fn async_transfers(ex: &cros_async::Executor) {
+        use std::io::Seek;
+        use std::io::SeekFrom;
+
+        let pattern: Vec<u8> = (0..0x2000).map(|i| i as u8).collect();
+        for gm in
+            new_guest_memories(&[(GuestAddress(0), 0x10000), (GuestAddress(0x10000), 0x10000)])
+        {
+            let mut file = tempfile::tempfile().unwrap();
+            file.write_all(&pattern).unwrap();
+            let source = ex.async_from(file.try_clone().unwrap()).unwrap();
+
+            let addr = GuestAddress(0xf000);
+            let read = ex
+                .run_until(gm.async_read_to_memory(addr, &*source, Some(0), pattern.len()))
+                .unwrap()
+                .unwrap();
+            assert_eq!(read, pattern.len());
+            let mut buf = vec![0u8; pattern.len()];
+            gm.read_exact_at_addr(&mut buf[..0x1000], addr).unwrap();
+            gm.read_exact_at_addr(&mut buf[0x1000..], GuestAddress(0x10000))
+                .unwrap();
+            assert_eq!(buf, pattern);
+
+            let written = ex
+                .run_until(gm.async_write_from_memory(addr, &*source, Some(0x2000), pattern.len()))
+                .unwrap()
+                .unwrap();
+            assert_eq!(written, pattern.len());
+            let mut contents = Vec::new();
+            file.seek(SeekFrom::Start(0x2000)).unwrap();
+            file.read_to_end(&mut contents).unwrap();
+            assert_eq!(contents, pattern);
+
+            // Nothing is transferred if the range runs past the end of guest memory.
+            assert!(matches!(
+                ex.run_until(gm.async_read_to_memory(
+                    GuestAddress(0x1f000),
+                    &*source,
+                    Some(0),
+                    pattern.len()
+                ))
+                .unwrap(),
+                Err(Error::RangeHole { hole, .. }) if hole == GuestAddress(0x20000)
+            ));
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn async_transfers_fd_executor() {
+        let ex = cros_async::sys::unix::FdExecutor::new().unwrap();
+        async_transfers(&cros_async::Executor::Fd(ex));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn async_transfers_uring_executor() {
+        // io_uring isn't available on every kernel the tests run on.
+        let ex = match cros_async::sys::unix::URingExecutor::new() {
+            Ok(ex) => ex,
+            Err(_) => return,
+        };
+        async_transfers(&cros_async::Executor::Uring(ex));
     }
 }
@@ -6,11 +6,19 @@ cfg_if::cfg_if! {
     if #[cfg(unix)] {
         pub mod unix;
         use unix as platform;
+        pub use platform::set_memfd_fallback_dir;
+        pub use platform::{
+            memory_fault, memory_fault_count, set_memory_fault_event, MemoryFault,
+        };
+        #[cfg(feature = "userfaultfd")]
+        pub use platform::{LazyBacking, LazyFaultHandler};
     } else if #[cfg(windows)] {
         pub mod windows;
         use windows as platform;
     }
 }
 
-pub(crate) use platform::finalize_shm;
+pub(crate) use platform::catch_access_fault;
+pub(crate) use platform::create_backing_object;
+pub(crate) use platform::FaultRegistration;
 pub use platform::MemoryPolicy;
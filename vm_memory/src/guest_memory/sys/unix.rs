@@ -2,25 +2,70 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
+use std::cmp::min;
+use std::ffi::CString;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::FromRawFd;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use base::info;
+use base::pagesize;
+use base::unix::fallocate;
+use base::unix::FallocateMode;
+use base::AsRawDescriptor;
+use base::Descriptor;
 use base::MemfdSeals;
 use base::MemoryMappingUnix;
+use base::MmapError;
 use base::SharedMemory;
 use base::SharedMemoryUnix;
 use bitflags::bitflags;
+use once_cell::sync::Lazy;
+use sync::Mutex;
 
+use crate::BackingObject;
 use crate::Error;
 use crate::GuestAddress;
 use crate::GuestMemory;
+use crate::MemoryRegion;
 use crate::Result;
 
+mod sigbus;
+#[cfg(feature = "userfaultfd")]
+mod userfaultfd;
+
+pub(crate) use sigbus::catch_access_fault;
+pub use sigbus::memory_fault;
+pub use sigbus::memory_fault_count;
+pub use sigbus::set_memory_fault_event;
+pub(crate) use sigbus::FaultRegistration;
+pub use sigbus::MemoryFault;
+#[cfg(feature = "userfaultfd")]
+pub use userfaultfd::LazyBacking;
+#[cfg(feature = "userfaultfd")]
+pub use userfaultfd::LazyFaultHandler;
+
+// Directory in which guest memory is created when the kernel lacks memfd.
+static MEMFD_FALLBACK_DIR: Lazy<Mutex<PathBuf>> =
+    Lazy::new(|| Mutex::new(PathBuf::from("/dev/shm")));
+
 bitflags! {
     pub struct MemoryPolicy: u32 {
         const USE_HUGEPAGES = 1;
         const LOCK_GUEST_MEMORY = (1 << 1);
+        /// Never use transparent huge pages, e.g. for regions where faulting them in causes
+        /// latency spikes. `USE_HUGEPAGES` takes precedence.
+        const NO_HUGEPAGES = (1 << 2);
     }
 }
 
-pub(crate) fn finalize_shm(shm: &mut SharedMemory) -> Result<()> {
+fn finalize_shm(shm: &mut SharedMemory) -> Result<()> {
     // Seals are only a concept on Unix systems, so we must add them in conditional
     // compilation. On Windows, SharedMemory allocation cannot be updated after creation
     // regardless, so the same operation is done implicitly.
@@ -33,16 +78,183 @@ pub(crate) fn finalize_shm(shm: &mut SharedMemory) -> Result<()> {
     shm.add_seals(seals).map_err(Error::MemoryAddSealsFailed)
 }
 
+/// Sets the directory, normally a tmpfs mount, in which guest memory is created when the kernel
+/// does not support memfd. Defaults to `/dev/shm`.
+pub fn set_memfd_fallback_dir(dir: PathBuf) {
+    *MEMFD_FALLBACK_DIR.lock() = dir;
+}
+
+/// Creates the object backing guest memory, which is a sealed memfd if the kernel supports it and
+/// an unlinked file in the memfd fallback directory otherwise.
+pub(crate) fn create_backing_object(name: &str, size: u64) -> Result<BackingObject> {
+    match SharedMemory::new(name, size) {
+        Ok(mut shm) => {
+            finalize_shm(&mut shm)?;
+            Ok(BackingObject::Shm(Arc::new(shm)))
+        }
+        Err(e) if e.errno() == libc::ENOSYS => {
+            let dir = MEMFD_FALLBACK_DIR.lock().clone();
+            info!(
+                "kernel lacks memfd support, backing guest memory with a file in {}",
+                dir.display()
+            );
+            let file = create_fallback_file(&dir, size)?;
+            Ok(BackingObject::File(Arc::new(file)))
+        }
+        Err(e) => Err(Error::MemoryCreationFailed(e)),
+    }
+}
+
+/// Creates an unlinked file of `size` bytes in `dir` to back guest memory in place of a memfd.
+///
+/// Unlike the memfd, the file can't be sealed against being resized. It has no name in `dir` (or
+/// only briefly, where `O_TMPFILE` is unsupported), so only processes that were handed its
+/// descriptor can resize it.
+pub(crate) fn create_fallback_file(dir: &Path, size: u64) -> Result<File> {
+    let fallback_err = |e| Error::MemoryFallbackFileFailed(dir.to_path_buf(), e);
+    // O_EXCL prevents the file from ever being linked into `dir`.
+    let file = match OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_TMPFILE | libc::O_EXCL)
+        .open(dir)
+    {
+        Ok(file) => file,
+        // Old kernels and some filesystems don't support O_TMPFILE.
+        Err(e) if matches!(e.raw_os_error(), Some(libc::EOPNOTSUPP | libc::EISDIR)) => {
+            create_unlinked_file(dir).map_err(fallback_err)?
+        }
+        Err(e) => return Err(fallback_err(e)),
+    };
+    file.set_len(size).map_err(fallback_err)?;
+    Ok(file)
+}
+
+fn create_unlinked_file(dir: &Path) -> io::Result<File> {
+    let template = CString::new(dir.join("crosvm_guest.XXXXXX").as_os_str().as_bytes())
+        .map_err(|_| io::Error::from_raw_os_error(libc::EINVAL))?;
+    let mut template = template.into_bytes_with_nul();
+    // Safe because `template` is a writable, nul terminated buffer and the result is checked.
+    let fd = unsafe { libc::mkostemp(template.as_mut_ptr() as *mut libc::c_char, libc::O_CLOEXEC) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // Safe because `fd` was just created and is owned by nothing else.
+    let file = unsafe { File::from_raw_fd(fd) };
+    template.pop();
+    std::fs::remove_file(Path::new(std::ffi::OsStr::from_bytes(&template)))?;
+    Ok(file)
+}
+
+impl MemoryRegion {
+    /// Releases the host memory backing `len` bytes of the region at `offset`, which read as
+    /// zeroes afterwards. A hole is punched in the backing object so that the memory is released
+    /// even if the region is file-backed or mapped by another process, and the range is madvised
+    /// away if the object doesn't support that.
+    fn remove_range(&self, offset: u64, len: u64) -> Result<()> {
+        let addr = self.guest_base.unchecked_add(offset);
+        match fallocate(
+            &Descriptor(self.shared_obj.as_raw_descriptor()),
+            FallocateMode::PunchHole,
+            true,
+            self.obj_offset + offset,
+            len,
+        ) {
+            Ok(()) => Ok(()),
+            Err(e) if e.errno() == libc::EOPNOTSUPP => self
+                .mapping
+                .remove_range(offset as usize, len as usize)
+                .map_err(|e| Error::MemoryAccess(addr, e)),
+            Err(e) => Err(Error::MemoryAccess(addr, MmapError::SystemCallFailed(e))),
+        }
+    }
+
+    /// Applies the hints of `policy` to `len` bytes of the region at `offset`.
+    fn apply_policy(&self, offset: usize, len: usize, policy: MemoryPolicy) -> Result<()> {
+        let addr = self.guest_base.unchecked_add(offset as u64);
+        if policy.intersects(MemoryPolicy::USE_HUGEPAGES | MemoryPolicy::NO_HUGEPAGES) {
+            self.mapping
+                .set_hugepages_range(offset, len, policy.contains(MemoryPolicy::USE_HUGEPAGES))
+                .map_err(|e| Error::MemoryAccess(addr, e))?;
+        }
+        if policy.contains(MemoryPolicy::LOCK_GUEST_MEMORY) {
+            self.mapping
+                .lock_range(offset, len)
+                .map_err(|e| Error::MemoryAccess(addr, e))?;
+        }
+        Ok(())
+    }
+
+    /// Applies the hints of `policy` to the whole region before it is added to guest memory, e.g.
+    /// to back RAM with huge pages.
+    pub fn with_policy(self, policy: MemoryPolicy) -> Result<Self> {
+        self.apply_policy(0, self.mapping.size(), policy)?;
+        Ok(self)
+    }
+}
+
 impl GuestMemory {
-    /// Madvise away the address range in the host that is associated with the given guest range.
+    /// Releases the host memory backing the `count` bytes of guest memory at `addr`, which may
+    /// span several regions. The range reads as zeroes afterwards.
     ///
-    /// This feature is only available on Unix, where a MemoryMapping can remove a mapped range.
+    /// Nothing is released if part of the range isn't guest memory, which fails with
+    /// `Error::RangeHole`, or is read-only to the guest, which fails with `Error::ReadOnlyRegion`.
+    ///
+    /// This doesn't change the layout, so device worker threads can call it while the guest runs.
     pub fn remove_range(&self, addr: GuestAddress, count: u64) -> Result<()> {
-        self.do_in_region(addr, move |mapping, offset, _| {
-            mapping
-                .remove_range(offset, count as usize)
-                .map_err(|e| Error::MemoryAccess(addr, e))
-        })
+        let end = addr
+            .checked_add(count)
+            .ok_or(Error::InvalidGuestAddress(addr))?;
+        let mut pieces = Vec::new();
+        let mut cur = addr;
+        while cur < end {
+            let region = self.region_at(cur).map_err(|_| Error::RangeHole {
+                addr,
+                len: count,
+                hole: cur,
+            })?;
+            if region.is_read_only() {
+                return Err(Error::ReadOnlyRegion(cur));
+            }
+            let len = min(region.end(), end).offset_from(cur);
+            pieces.push((region, cur.offset_from(region.start()), len));
+            cur = cur.unchecked_add(len);
+        }
+        for (region, offset, len) in pieces {
+            region.remove_range(offset, len)?;
+        }
+        Ok(())
+    }
+
+    /// Applies the hints of `policy` to the `len` bytes of guest memory at `addr`, which may span
+    /// several regions, e.g. to use huge pages for RAM but not for the regions of devices.
+    ///
+    /// `addr` must be page aligned. Nothing is applied if part of the range isn't guest memory,
+    /// which fails with `Error::InvalidGuestAddress`.
+    pub fn set_memory_policy_for_range(
+        &self,
+        addr: GuestAddress,
+        len: u64,
+        policy: MemoryPolicy,
+    ) -> Result<()> {
+        if addr.offset() % pagesize() as u64 != 0 {
+            return Err(Error::InvalidGuestAddress(addr));
+        }
+        let end = addr
+            .checked_add(len)
+            .ok_or(Error::InvalidGuestAddress(addr))?;
+        let mut pieces = Vec::new();
+        let mut cur = addr;
+        while cur < end {
+            let region = self.region_at(cur)?;
+            let len = min(region.end(), end).offset_from(cur);
+            pieces.push((region, cur.offset_from(region.start()), len));
+            cur = cur.unchecked_add(len);
+        }
+        for (region, offset, len) in pieces {
+            region.apply_policy(offset as usize, len as usize, policy)?;
+        }
+        Ok(())
     }
 
     /// Handles guest memory policy hints/advices.
@@ -58,6 +270,14 @@ impl GuestMemory {
                 if let Err(err) = ret {
                     println!("Failed to enable HUGEPAGE for mapping {}", err);
                 }
+            } else if mem_policy.contains(MemoryPolicy::NO_HUGEPAGES) {
+                let ret = region
+                    .mapping
+                    .set_hugepages_range(0, region.mapping.size(), false);
+
+                if let Err(err) = ret {
+                    println!("Failed to disable HUGEPAGE for mapping {}", err);
+                }
             }
 
             if mem_policy.contains(MemoryPolicy::LOCK_GUEST_MEMORY) {
@@ -75,3 +295,541 @@ impl GuestMemory {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::io::Write;
+    use std::os::unix::fs::FileExt;
+    use std::os::unix::fs::MetadataExt;
+
+    use base::pagesize;
+
+    use super::*;
+    use crate::BackingObjectLimits;
+
+    #[test]
+    fn fallback_file_is_unlinked() {
+        let file = create_fallback_file(&std::env::temp_dir(), 0x30000).unwrap();
+        let metadata = file.metadata().unwrap();
+        assert_eq!(metadata.nlink(), 0);
+        assert_eq!(metadata.len(), 0x30000);
+    }
+
+    #[test]
+    fn unlinked_file_without_tmpfile() {
+        let file = create_unlinked_file(&std::env::temp_dir()).unwrap();
+        assert_eq!(file.metadata().unwrap().nlink(), 0);
+    }
+
+    #[test]
+    fn file_regions_between_ram() {
+        let file = Arc::new(create_fallback_file(&std::env::temp_dir(), 0x2000).unwrap());
+        let region =
+            MemoryRegion::new_from_file(0x2000, GuestAddress(0x10000), 0, file.clone()).unwrap();
+        let mem = GuestMemory::new_with_file_regions(
+            &[(GuestAddress(0), 0x10000), (GuestAddress(0x20000), 0x10000)],
+            vec![region],
+        )
+        .unwrap();
+        assert_eq!(mem.num_regions(), 3);
+        assert_eq!(mem.memory_size(), 0x22000);
+
+        mem.write_obj_at_addr(0x55aa_u64, GuestAddress(0x11000))
+            .unwrap();
+        let mut buf = [0u8; 8];
+        file.read_exact_at(&mut buf, 0x1000).unwrap();
+        assert_eq!(u64::from_ne_bytes(buf), 0x55aa);
+
+        let region = MemoryRegion::new_from_file(0x2000, GuestAddress(0xf000), 0, file).unwrap();
+        assert!(matches!(
+            GuestMemory::new_with_file_regions(&[(GuestAddress(0), 0x10000)], vec![region]),
+            Err(Error::MemoryRegionOverlap)
+        ));
+    }
+
+    #[test]
+    fn truncated_file_access_fault() {
+        let file = Arc::new(create_fallback_file(&std::env::temp_dir(), 0x2000).unwrap());
+        let region =
+            MemoryRegion::new_from_file(0x2000, GuestAddress(0x10000), 0, file.clone()).unwrap();
+        let mem = GuestMemory::from_regions(vec![region]).unwrap();
+        mem.write_obj_at_addr(0x55aa_u64, GuestAddress(0x11000))
+            .unwrap();
+
+        file.set_len(0x1000).unwrap();
+        match mem.read_obj_from_addr::<u64>(GuestAddress(0x11000)) {
+            Err(Error::MemoryAccess(GuestAddress(0x11000), MmapError::BusError)) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert_eq!(
+            memory_fault(),
+            Some(MemoryFault {
+                guest_addr: GuestAddress(0x11000),
+                region: "file-backed guest region",
+                thread_id: base::gettid(),
+            })
+        );
+        assert_eq!(memory_fault_count(), 1);
+
+        // The faulting page was replaced and the rest of the region is unaffected.
+        assert_eq!(
+            mem.read_obj_from_addr::<u64>(GuestAddress(0x11000))
+                .unwrap(),
+            0
+        );
+        mem.write_obj_at_addr(0x55aa_u64, GuestAddress(0x10000))
+            .unwrap();
+    }
+
+    fn file_region(guest_base: u64) -> MemoryRegion {
+        let file = Arc::new(create_fallback_file(&std::env::temp_dir(), 0x1000).unwrap());
+        MemoryRegion::new_from_file(0x1000, GuestAddress(guest_base), 0, file).unwrap()
+    }
+
+    #[test]
+    fn backing_object_count() {
+        let regions = (0..64).map(|i| file_region(0x10000 + i * 0x1000)).collect();
+        let mem =
+            GuestMemory::new_with_file_regions(&[(GuestAddress(0), 0x10000)], regions).unwrap();
+        let stats = mem.backing_object_stats();
+        assert_eq!(stats.regions, 65);
+        assert_eq!(stats.backing_objects, 65);
+        assert_eq!(stats.limits, BackingObjectLimits::default());
+
+        // Regions mapping the same file hold a single descriptor.
+        let file = Arc::new(create_fallback_file(&std::env::temp_dir(), 0x2000).unwrap());
+        let mem = mem
+            .add_region(
+                MemoryRegion::new_from_file(0x1000, GuestAddress(0x60000), 0, file.clone())
+                    .unwrap(),
+            )
+            .unwrap()
+            .add_region(
+                MemoryRegion::new_from_file(0x1000, GuestAddress(0x70000), 0x1000, file).unwrap(),
+            )
+            .unwrap();
+        let stats = mem.backing_object_stats();
+        assert_eq!(stats.regions, 67);
+        assert_eq!(stats.backing_objects, 66);
+    }
+
+    #[test]
+    fn backing_object_soft_limit() {
+        let mut mem = GuestMemory::new(&[(GuestAddress(0), 0x10000)]).unwrap();
+        mem.set_backing_object_limits(BackingObjectLimits {
+            soft: Some(8),
+            hard: None,
+        })
+        .unwrap();
+
+        // Going over the soft limit only logs a warning.
+        for i in 0..32 {
+            mem = mem.add_region(file_region(0x10000 + i * 0x1000)).unwrap();
+        }
+        assert_eq!(mem.backing_object_count(), 33);
+        assert_eq!(mem.backing_object_stats().limits.soft, Some(8));
+    }
+
+    #[test]
+    fn backing_object_hard_limit() {
+        let mut mem = GuestMemory::new(&[(GuestAddress(0), 0x10000)]).unwrap();
+        let limits = BackingObjectLimits {
+            soft: Some(8),
+            hard: Some(16),
+        };
+        mem.set_backing_object_limits(limits).unwrap();
+
+        for i in 0..15 {
+            mem = mem.add_region(file_region(0x10000 + i * 0x1000)).unwrap();
+        }
+        assert_eq!(mem.backing_object_count(), 16);
+        match mem.add_region(file_region(0x20000)) {
+            Err(Error::TooManyBackingObjects {
+                count: 17,
+                limit: 16,
+            }) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+        // The rejected layout was never published.
+        assert!(mem.is_current_layout());
+        assert_eq!(mem.num_regions(), 16);
+
+        // Limits below the current count are rejected as well.
+        assert!(matches!(
+            mem.set_backing_object_limits(BackingObjectLimits {
+                soft: None,
+                hard: Some(15),
+            }),
+            Err(Error::TooManyBackingObjects {
+                count: 16,
+                limit: 15,
+            })
+        ));
+        assert_eq!(mem.backing_object_stats().limits, limits);
+    }
+
+    // Returns the permissions of the mapping containing `addr` in this process, e.g. "rw-s".
+    fn mapping_permissions(addr: *const u8) -> String {
+        let addr = addr as usize;
+        let maps = std::fs::read_to_string("/proc/self/maps").unwrap();
+        for line in maps.lines() {
+            let mut fields = line.split_whitespace();
+            let (start, end) = fields.next().unwrap().split_once('-').unwrap();
+            let start = usize::from_str_radix(start, 16).unwrap();
+            let end = usize::from_str_radix(end, 16).unwrap();
+            if (start..end).contains(&addr) {
+                return fields.next().unwrap().to_owned();
+            }
+        }
+        panic!("no mapping contains {:#x}", addr);
+    }
+
+    // Returns the flags of the mapping containing `addr` in this process, as listed by `VmFlags` in
+    // smaps, e.g. "hg" for a range advised to use huge pages.
+    fn mapping_flags(addr: *const u8) -> Vec<String> {
+        let addr = addr as usize;
+        let smaps = std::fs::read_to_string("/proc/self/smaps").unwrap();
+        let mut in_mapping = false;
+        for line in smaps.lines() {
+            if let Some(flags) = line.strip_prefix("VmFlags:") {
+                if in_mapping {
+                    return flags.split_whitespace().map(str::to_owned).collect();
+                }
+                continue;
+            }
+            let range = line.split_whitespace().next().unwrap_or_default();
+            if let Some((start, end)) = range.split_once('-') {
+                if let (Ok(start), Ok(end)) = (
+                    usize::from_str_radix(start, 16),
+                    usize::from_str_radix(end, 16),
+                ) {
+                    in_mapping = (start..end).contains(&addr);
+                }
+            }
+        }
+        panic!("no mapping contains {:#x}", addr);
+    }
+
+    // MADV_HUGEPAGE and MADV_NOHUGEPAGE fail on kernels without transparent huge pages.
+    fn has_transparent_hugepages() -> bool {
+        Path::new("/sys/kernel/mm/transparent_hugepage").exists()
+    }
+
+    #[test]
+    fn memory_policy_for_range() {
+        if !has_transparent_hugepages() {
+            return;
+        }
+        let mem = GuestMemory::new(&[
+            (GuestAddress(0), 0x10000),
+            (GuestAddress(0x10000), 0x10000),
+            (GuestAddress(0x30000), 0x10000),
+        ])
+        .unwrap();
+        let has_flag = |addr: u64, flag: &str| {
+            mapping_flags(mem.get_host_address(GuestAddress(addr)).unwrap())
+                .iter()
+                .any(|f| f == flag)
+        };
+
+        // Nothing is advised when the range runs into the hole after the second region.
+        match mem.set_memory_policy_for_range(
+            GuestAddress(0x18000),
+            0x10000,
+            MemoryPolicy::USE_HUGEPAGES,
+        ) {
+            Err(Error::InvalidGuestAddress(GuestAddress(0x20000))) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert!(!has_flag(0x18000, "hg"));
+        match mem.set_memory_policy_for_range(
+            GuestAddress(0x18008),
+            0x1000,
+            MemoryPolicy::USE_HUGEPAGES,
+        ) {
+            Err(Error::InvalidGuestAddress(GuestAddress(0x18008))) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        // The range spans the end of the first region and the start of the second one.
+        mem.set_memory_policy_for_range(GuestAddress(0x8000), 0x10000, MemoryPolicy::USE_HUGEPAGES)
+            .unwrap();
+        for addr in [0x8000, 0xf000, 0x10000, 0x17000] {
+            assert!(has_flag(addr, "hg"), "{:#x} not advised", addr);
+        }
+        for addr in [0x0, 0x7000, 0x18000, 0x30000] {
+            assert!(!has_flag(addr, "hg"), "{:#x} advised", addr);
+        }
+
+        mem.set_memory_policy_for_range(GuestAddress(0x30000), 0x4000, MemoryPolicy::NO_HUGEPAGES)
+            .unwrap();
+        assert!(has_flag(0x30000, "nh"));
+        assert!(!has_flag(0x34000, "nh"));
+    }
+
+    #[test]
+    fn region_with_policy() {
+        if !has_transparent_hugepages() {
+            return;
+        }
+        let file = Arc::new(create_fallback_file(&std::env::temp_dir(), 0x2000).unwrap());
+        let region = MemoryRegion::new_from_file(0x2000, GuestAddress(0x10000), 0, file)
+            .unwrap()
+            .with_policy(MemoryPolicy::NO_HUGEPAGES)
+            .unwrap();
+        let mem = GuestMemory::from_regions(vec![region]).unwrap();
+        let flags = mapping_flags(mem.get_host_address(GuestAddress(0x11000)).unwrap());
+        assert!(flags.iter().any(|f| f == "nh"));
+    }
+
+    #[test]
+    fn read_only_region_protection() {
+        let file = Arc::new(create_fallback_file(&std::env::temp_dir(), 0x2000).unwrap());
+        let region =
+            MemoryRegion::new_from_file_read_only(0x2000, GuestAddress(0x10000), 0, file.clone())
+                .unwrap();
+        let mem = GuestMemory::new_with_file_regions(&[(GuestAddress(0), 0x10000)], vec![region])
+            .unwrap();
+        let host_addr = mem.get_host_address(GuestAddress(0x10000)).unwrap();
+        assert!(mem.is_read_only(GuestAddress(0x11000)));
+        assert!(!mem.is_read_only(GuestAddress(0x1000)));
+        assert_eq!(mapping_permissions(host_addr), "r--s");
+
+        match mem.write_obj_at_addr(0x55aa_u64, GuestAddress(0x11000)) {
+            Err(Error::ReadOnlyRegion(GuestAddress(0x11000))) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        // The VMM can stage an update while the guest view stays read-only.
+        mem.set_host_writable(GuestAddress(0x10000), true).unwrap();
+        assert_eq!(mapping_permissions(host_addr), "rw-s");
+        assert!(mem.is_read_only(GuestAddress(0x11000)));
+        mem.write_all_at_addr(&0x55aa_u64.to_ne_bytes(), GuestAddress(0x11000))
+            .unwrap();
+        let mut buf = [0u8; 8];
+        file.read_exact_at(&mut buf, 0x1000).unwrap();
+        assert_eq!(u64::from_ne_bytes(buf), 0x55aa);
+
+        mem.set_host_writable(GuestAddress(0x10000), false).unwrap();
+        assert_eq!(mapping_permissions(host_addr), "r--s");
+        assert!(mem.write_obj_at_addr(0_u64, GuestAddress(0x11000)).is_err());
+        assert_eq!(
+            mem.read_obj_from_addr::<u64>(GuestAddress(0x11000))
+                .unwrap(),
+            0x55aa
+        );
+
+        // Other regions are unaffected.
+        mem.write_obj_at_addr(0x55aa_u64, GuestAddress(0x1000))
+            .unwrap();
+    }
+
+    #[test]
+    fn read_only_file_stays_read_only() {
+        let path = std::env::temp_dir().join(format!("crosvm_read_only_{}", base::getpid()));
+        std::fs::write(&path, vec![0u8; 0x1000]).unwrap();
+        let file = Arc::new(File::open(&path).unwrap());
+        std::fs::remove_file(&path).unwrap();
+
+        let region =
+            MemoryRegion::new_from_file_read_only(0x1000, GuestAddress(0x10000), 0, file).unwrap();
+        match region.set_host_writable(true) {
+            Err(Error::MemoryProtectionFailed(_)) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert!(!region.is_host_writable());
+    }
+
+    #[test]
+    fn sync_region() {
+        let file = Arc::new(create_fallback_file(&std::env::temp_dir(), 0x3000).unwrap());
+        let region =
+            MemoryRegion::new_from_file(0x3000, GuestAddress(0x10000), 0, file.clone()).unwrap();
+        let mem = GuestMemory::new_with_file_regions(&[(GuestAddress(0), 0x10000)], vec![region])
+            .unwrap();
+
+        mem.write_obj_at_addr(0x55aa_u64, GuestAddress(0x11008))
+            .unwrap();
+        // The start of the range doesn't need to be page aligned.
+        mem.sync_region(GuestAddress(0x11008), 8).unwrap();
+        mem.sync_region(GuestAddress(0x10000), 0x3000).unwrap();
+        let mut buf = [0u8; 8];
+        file.read_exact_at(&mut buf, 0x1008).unwrap();
+        assert_eq!(u64::from_ne_bytes(buf), 0x55aa);
+
+        // The range must lie within the region.
+        match mem.sync_region(GuestAddress(0x12000), 0x2000) {
+            Err(Error::MemoryAccess(GuestAddress(0x12000), MmapError::InvalidAddress)) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+        match mem.sync_region(GuestAddress(0x20000), 8) {
+            Err(Error::InvalidGuestAddress(GuestAddress(0x20000))) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+        // Shared memory has no file to write back to.
+        mem.sync_region(GuestAddress(0x1000), 8).unwrap();
+    }
+
+    // Returns how many pages of the `len` bytes at `addr` are resident in memory.
+    fn resident_pages(addr: *const u8, len: usize) -> usize {
+        let mut pages = vec![0u8; len / pagesize()];
+        // Safe because `pages` has a byte for each page of the range and the result is checked.
+        let ret = unsafe { libc::mincore(addr as *mut libc::c_void, len, pages.as_mut_ptr()) };
+        assert_eq!(ret, 0);
+        pages.iter().filter(|&&page| page & 1 != 0).count()
+    }
+
+    #[test]
+    fn remove_range_across_shm_regions() {
+        let mem = GuestMemory::new(&[(GuestAddress(0), 0x10000), (GuestAddress(0x10000), 0x10000)])
+            .unwrap();
+        assert_eq!(mem.num_regions(), 2);
+        for base in [0, 0x10000] {
+            mem.write_all_at_addr(&[0xa5; 0x10000], GuestAddress(base))
+                .unwrap();
+        }
+        let low = mem.get_host_address(GuestAddress(0)).unwrap();
+        let high = mem.get_host_address(GuestAddress(0x10000)).unwrap();
+        assert_eq!(resident_pages(low, 0x10000), 0x10000 / pagesize());
+        assert_eq!(resident_pages(high, 0x10000), 0x10000 / pagesize());
+
+        mem.remove_range(GuestAddress(0x8000), 0x10000).unwrap();
+        let removed = 0x8000 / pagesize();
+        assert_eq!(resident_pages(low, 0x10000), 0x10000 / pagesize() - removed);
+        assert_eq!(
+            resident_pages(high, 0x10000),
+            0x10000 / pagesize() - removed
+        );
+        assert_eq!(
+            mem.read_obj_from_addr::<u64>(GuestAddress(0x8000)).unwrap(),
+            0
+        );
+        assert_eq!(
+            mem.read_obj_from_addr::<u64>(GuestAddress(0x17ff8))
+                .unwrap(),
+            0
+        );
+        assert_eq!(
+            mem.read_obj_from_addr::<u8>(GuestAddress(0x18000)).unwrap(),
+            0xa5
+        );
+    }
+
+    #[test]
+    fn remove_range_punches_file_hole() {
+        let file = Arc::new(create_fallback_file(&std::env::temp_dir(), 0x10000).unwrap());
+        let region =
+            MemoryRegion::new_from_file(0x10000, GuestAddress(0x10000), 0, file.clone()).unwrap();
+        let mem = GuestMemory::new_with_file_regions(&[(GuestAddress(0), 0x10000)], vec![region])
+            .unwrap();
+        mem.write_all_at_addr(&[0xa5; 0x10000], GuestAddress(0x10000))
+            .unwrap();
+        mem.sync_region(GuestAddress(0x10000), 0x10000).unwrap();
+        let blocks = file.metadata().unwrap().blocks();
+
+        mem.remove_range(GuestAddress(0x10000), 0x8000).unwrap();
+        // st_blocks counts 512 byte units.
+        assert_eq!(file.metadata().unwrap().blocks(), blocks - 0x8000 / 512);
+        assert_eq!(file.metadata().unwrap().len(), 0x10000);
+        let mut buf = [0u8; 8];
+        file.read_exact_at(&mut buf, 0).unwrap();
+        assert_eq!(buf, [0; 8]);
+        file.read_exact_at(&mut buf, 0x8000).unwrap();
+        assert_eq!(buf, [0xa5; 8]);
+    }
+
+    #[test]
+    fn remove_range_hole() {
+        let mem = GuestMemory::new(&[(GuestAddress(0), 0x10000), (GuestAddress(0x20000), 0x10000)])
+            .unwrap();
+        mem.write_obj_at_addr(0x55aa_u64, GuestAddress(0x8000))
+            .unwrap();
+        match mem.remove_range(GuestAddress(0x8000), 0x20000) {
+            Err(Error::RangeHole {
+                addr: GuestAddress(0x8000),
+                len: 0x20000,
+                hole: GuestAddress(0x10000),
+            }) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+        // Nothing was released.
+        assert_eq!(
+            mem.read_obj_from_addr::<u64>(GuestAddress(0x8000)).unwrap(),
+            0x55aa
+        );
+        mem.remove_range(GuestAddress(0x20000), 0).unwrap();
+    }
+
+    #[test]
+    fn read_to_memory_from_pipe() {
+        let mem = GuestMemory::new(&[(GuestAddress(0x1000), 0x1000)]).unwrap();
+        let (mut r, mut w) = base::pipe(true).unwrap();
+        let writer = std::thread::spawn(move || {
+            for chunk in [1u8, 2, 3, 4] {
+                w.write_all(&[chunk; 3]).unwrap();
+                std::thread::sleep(std::time::Duration::from_millis(1));
+            }
+        });
+
+        mem.read_to_memory_exact(GuestAddress(0x1000), &mut r, 6)
+            .unwrap();
+        // The writer closes the pipe after 6 more bytes, short of the 8 expected.
+        match mem.read_to_memory_exact(GuestAddress(0x1006), &mut r, 8) {
+            Err(Error::ShortRead {
+                expected: 8,
+                completed: 6,
+            }) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+        writer.join().unwrap();
+        assert_eq!(
+            mem.read_to_memory(GuestAddress(0x1010), &mut r, 8).unwrap(),
+            0
+        );
+
+        let mut buf = [0u8; 14];
+        mem.read_exact_at_addr(&mut buf, GuestAddress(0x1000))
+            .unwrap();
+        assert_eq!(buf, [1, 1, 1, 2, 2, 2, 3, 3, 3, 4, 4, 4, 0, 0]);
+    }
+
+    #[test]
+    fn zero_length_transfers() {
+        let mem = GuestMemory::new(&[(GuestAddress(0x1000), 0x1000)]).unwrap();
+        let (mut r, mut w) = base::pipe(true).unwrap();
+        // Even at an address outside guest memory, nothing is transferred and nothing fails.
+        assert_eq!(mem.read_to_memory(GuestAddress(0), &mut r, 0).unwrap(), 0);
+        assert_eq!(
+            mem.write_from_memory(GuestAddress(0), &mut w, 0).unwrap(),
+            0
+        );
+        mem.read_to_memory_exact(GuestAddress(0x2000), &mut r, 0)
+            .unwrap();
+        mem.write_from_memory_exact(GuestAddress(0x2000), &mut w, 0)
+            .unwrap();
+    }
+
+    #[test]
+    fn write_from_memory_to_pipe() {
+        let mem = GuestMemory::new(&[(GuestAddress(0x1000), 0x1000)]).unwrap();
+        mem.write_all_at_addr(&[0xa5; 0x100], GuestAddress(0x1100))
+            .unwrap();
+        let (mut r, mut w) = base::pipe(true).unwrap();
+        let reader = std::thread::spawn(move || {
+            let mut data = Vec::new();
+            let mut buf = [0u8; 16];
+            loop {
+                match r.read(&mut buf).unwrap() {
+                    0 => return data,
+                    n => data.extend_from_slice(&buf[..n]),
+                }
+            }
+        });
+
+        mem.write_from_memory_exact(GuestAddress(0x1100), &mut w, 0x100)
+            .unwrap();
+        drop(w);
+        assert_eq!(reader.join().unwrap(), [0xa5; 0x100]);
+    }
+}
@@ -0,0 +1,324 @@
+// Copyright 2022 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Recovery from SIGBUS raised by accesses to guest memory.
+//!
+//! Touching a page of a file-backed mapping past the end of the file, for instance after another
+//! process truncated it, raises SIGBUS, which would otherwise kill crosvm without any indication of
+//! what went wrong. Guest memory regions register their host mappings here. When an access to a
+//! registered mapping faults, the handler records the fault, replaces the faulting page with an
+//! anonymous zero page so the access can complete, and signals the fault event so the VM can be
+//! shut down. Accesses made through `GuestMemory` additionally fail with `Error::MemoryAccess`.
+//!
+//! SIGBUS outside of registered mappings, in a page that can't be replaced (e.g. part of a huge
+//! page), or in a process without a fault event outside of `GuestMemory` accesses keeps the default
+//! disposition.
+
+use std::cell::Cell;
+use std::fmt;
+use std::mem;
+use std::ptr::null_mut;
+use std::sync::atomic::AtomicI32;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::AtomicU8;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Once;
+
+use base::error;
+use base::pagesize;
+use base::AsRawDescriptor;
+use base::Event;
+use base::MmapError;
+use libc::c_int;
+use libc::c_void;
+use libc::pid_t;
+use libc::siginfo_t;
+use once_cell::sync::Lazy;
+use sync::Mutex;
+
+use crate::Error;
+use crate::GuestAddress;
+use crate::Result;
+
+// Maximum number of guest memory regions that can be registered at the same time.
+const MAX_REGIONS: usize = 64;
+
+const SLOT_FREE: u8 = 0;
+const SLOT_CLAIMED: u8 = 1;
+const SLOT_ACTIVE: u8 = 2;
+
+const FAULT_NONE: u8 = 0;
+const FAULT_RECORDING: u8 = 1;
+const FAULT_RECORDED: u8 = 2;
+
+// A registered mapping. Fields other than `state` are only read by the handler while `state` is
+// `SLOT_ACTIVE`.
+struct Slot {
+    state: AtomicU8,
+    host_start: AtomicUsize,
+    len: AtomicUsize,
+    guest_base: AtomicU64,
+    label_ptr: AtomicUsize,
+    label_len: AtomicUsize,
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const FREE_SLOT: Slot = Slot {
+    state: AtomicU8::new(SLOT_FREE),
+    host_start: AtomicUsize::new(0),
+    len: AtomicUsize::new(0),
+    guest_base: AtomicU64::new(0),
+    label_ptr: AtomicUsize::new(0),
+    label_len: AtomicUsize::new(0),
+};
+
+static SLOTS: [Slot; MAX_REGIONS] = [FREE_SLOT; MAX_REGIONS];
+
+static INSTALL_HANDLER: Once = Once::new();
+// Page size cached for the handler, 0 until the handler is installed.
+static PAGE_SIZE: AtomicUsize = AtomicUsize::new(0);
+
+// The first fault, exposed through `memory_fault`.
+static FAULT_STATE: AtomicU8 = AtomicU8::new(FAULT_NONE);
+static FAULT_GUEST_ADDR: AtomicU64 = AtomicU64::new(0);
+static FAULT_LABEL_PTR: AtomicUsize = AtomicUsize::new(0);
+static FAULT_LABEL_LEN: AtomicUsize = AtomicUsize::new(0);
+static FAULT_THREAD_ID: AtomicI32 = AtomicI32::new(0);
+static FAULT_COUNT: AtomicU64 = AtomicU64::new(0);
+
+// Descriptor of `FAULT_EVENT`, or -1, for use by the handler.
+static FAULT_EVENT_FD: AtomicI32 = AtomicI32::new(-1);
+static FAULT_EVENT: Lazy<Mutex<Option<Event>>> = Lazy::new(|| Mutex::new(None));
+
+thread_local! {
+    // Whether this thread is in `catch_access_fault`.
+    static THREAD_CATCHING: Cell<bool> = const { Cell::new(false) };
+    // Guest address of the last fault raised by this thread, consumed by `catch_access_fault`.
+    static THREAD_FAULT: Cell<Option<u64>> = const { Cell::new(None) };
+}
+
+/// An access to guest memory that raised SIGBUS.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemoryFault {
+    /// Guest address that was accessed.
+    pub guest_addr: GuestAddress,
+    /// Label of the guest memory region containing `guest_addr`.
+    pub region: &'static str,
+    /// Thread that made the access.
+    pub thread_id: pid_t,
+}
+
+impl fmt::Display for MemoryFault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "SIGBUS accessing {} at guest address {} from thread {}",
+            self.region, self.guest_addr, self.thread_id
+        )
+    }
+}
+
+/// Returns the first guest memory access that raised SIGBUS in this process, if any.
+pub fn memory_fault() -> Option<MemoryFault> {
+    if FAULT_STATE.load(Ordering::Acquire) != FAULT_RECORDED {
+        return None;
+    }
+    Some(MemoryFault {
+        guest_addr: GuestAddress(FAULT_GUEST_ADDR.load(Ordering::Relaxed)),
+        region: load_label(&FAULT_LABEL_PTR, &FAULT_LABEL_LEN),
+        thread_id: FAULT_THREAD_ID.load(Ordering::Relaxed),
+    })
+}
+
+/// Returns the number of guest memory accesses that raised SIGBUS in this process.
+pub fn memory_fault_count() -> u64 {
+    FAULT_COUNT.load(Ordering::Relaxed)
+}
+
+/// Sets the event signaled whenever a guest memory access raises SIGBUS.
+pub fn set_memory_fault_event(evt: Event) {
+    let mut fault_event = FAULT_EVENT.lock();
+    FAULT_EVENT_FD.store(evt.as_raw_descriptor(), Ordering::SeqCst);
+    // The previous event is only closed once the handler can no longer see its descriptor.
+    *fault_event = Some(evt);
+}
+
+/// Keeps a guest memory mapping registered with the SIGBUS handler while alive.
+#[derive(Debug)]
+pub(crate) struct FaultRegistration {
+    slot: Option<usize>,
+}
+
+impl FaultRegistration {
+    /// Registers the `len` bytes mapped at `host_start`, which hold guest memory starting at
+    /// `guest_base`.
+    pub(crate) fn new(
+        host_start: usize,
+        len: usize,
+        guest_base: GuestAddress,
+        label: &'static str,
+    ) -> FaultRegistration {
+        INSTALL_HANDLER.call_once(install_handler);
+        for (index, slot) in SLOTS.iter().enumerate() {
+            if slot
+                .state
+                .compare_exchange(
+                    SLOT_FREE,
+                    SLOT_CLAIMED,
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                )
+                .is_ok()
+            {
+                slot.host_start.store(host_start, Ordering::Relaxed);
+                slot.len.store(len, Ordering::Relaxed);
+                slot.guest_base
+                    .store(guest_base.offset(), Ordering::Relaxed);
+                slot.label_ptr
+                    .store(label.as_ptr() as usize, Ordering::Relaxed);
+                slot.label_len.store(label.len(), Ordering::Relaxed);
+                slot.state.store(SLOT_ACTIVE, Ordering::Release);
+                return FaultRegistration { slot: Some(index) };
+            }
+        }
+        error!(
+            "too many guest memory regions, SIGBUS at {} will not be handled",
+            guest_base
+        );
+        FaultRegistration { slot: None }
+    }
+}
+
+impl Drop for FaultRegistration {
+    fn drop(&mut self) {
+        if let Some(index) = self.slot {
+            SLOTS[index].state.store(SLOT_FREE, Ordering::Release);
+        }
+    }
+}
+
+/// Runs `f`, which accesses guest memory, failing with `Error::MemoryAccess` if any of its accesses
+/// raised SIGBUS.
+pub(crate) fn catch_access_fault<T>(f: impl FnOnce() -> Result<T>) -> Result<T> {
+    THREAD_FAULT.with(|fault| fault.set(None));
+    let catching = THREAD_CATCHING.with(|catching| catching.replace(true));
+    let result = f();
+    THREAD_CATCHING.with(|prev| prev.set(catching));
+    match THREAD_FAULT.with(|fault| fault.take()) {
+        Some(guest_addr) => Err(Error::MemoryAccess(
+            GuestAddress(guest_addr),
+            MmapError::BusError,
+        )),
+        None => result,
+    }
+}
+
+fn install_handler() {
+    PAGE_SIZE.store(pagesize(), Ordering::Relaxed);
+    // Safe because `sigact` is fully initialized, `handle_sigbus` has the signature expected with
+    // SA_SIGINFO and only makes async-signal-safe calls, and the result is checked.
+    let ret = unsafe {
+        let mut sigact: libc::sigaction = mem::zeroed();
+        sigact.sa_flags = libc::SA_SIGINFO | libc::SA_RESTART;
+        sigact.sa_sigaction = handle_sigbus as *const () as usize;
+        libc::sigaction(libc::SIGBUS, &sigact, null_mut())
+    };
+    if ret < 0 {
+        error!("failed to install SIGBUS handler: {}", base::Error::last());
+    }
+}
+
+fn load_label(ptr: &AtomicUsize, len: &AtomicUsize) -> &'static str {
+    let ptr = ptr.load(Ordering::Relaxed) as *const u8;
+    let len = len.load(Ordering::Relaxed);
+    // Safe because the pointer and length were stored from a `&'static str`.
+    unsafe { std::str::from_utf8_unchecked(std::slice::from_raw_parts(ptr, len)) }
+}
+
+// Returns the guest address and region label of `host_addr` if it is in a registered mapping.
+fn find_registered(host_addr: usize) -> Option<(u64, &'static str)> {
+    SLOTS.iter().find_map(|slot| {
+        if slot.state.load(Ordering::Acquire) != SLOT_ACTIVE {
+            return None;
+        }
+        let offset = host_addr.checked_sub(slot.host_start.load(Ordering::Relaxed))?;
+        if offset >= slot.len.load(Ordering::Relaxed) {
+            return None;
+        }
+        let guest_addr = slot.guest_base.load(Ordering::Relaxed) + offset as u64;
+        Some((guest_addr, load_label(&slot.label_ptr, &slot.label_len)))
+    })
+}
+
+fn record_fault(guest_addr: u64, label: &'static str) {
+    FAULT_COUNT.fetch_add(1, Ordering::Relaxed);
+    THREAD_FAULT.with(|fault| fault.set(Some(guest_addr)));
+    if FAULT_STATE
+        .compare_exchange(
+            FAULT_NONE,
+            FAULT_RECORDING,
+            Ordering::Acquire,
+            Ordering::Relaxed,
+        )
+        .is_ok()
+    {
+        FAULT_GUEST_ADDR.store(guest_addr, Ordering::Relaxed);
+        FAULT_LABEL_PTR.store(label.as_ptr() as usize, Ordering::Relaxed);
+        FAULT_LABEL_LEN.store(label.len(), Ordering::Relaxed);
+        // Safe because gettid has no side effects and can't fail.
+        let tid = unsafe { libc::syscall(libc::SYS_gettid) } as pid_t;
+        FAULT_THREAD_ID.store(tid, Ordering::Relaxed);
+        FAULT_STATE.store(FAULT_RECORDED, Ordering::Release);
+    }
+
+    let fd = FAULT_EVENT_FD.load(Ordering::SeqCst);
+    if fd >= 0 {
+        let value = 1u64;
+        // Safe because `value` is valid for 8 bytes. Failure, e.g. because the event counter is
+        // saturated, is harmless since the event is signaled either way.
+        unsafe { libc::write(fd, &value as *const u64 as *const c_void, 8) };
+    }
+}
+
+// Replaces the page containing `host_addr` with an anonymous zero page.
+fn replace_page(host_addr: usize) -> bool {
+    let page_size = PAGE_SIZE.load(Ordering::Relaxed);
+    let page = host_addr & !(page_size - 1);
+    // Safe because the page belongs to a registered guest memory mapping, whose contents are only
+    // accessed through volatile accesses, and the result is checked.
+    let addr = unsafe {
+        libc::mmap(
+            page as *mut c_void,
+            page_size,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_FIXED,
+            -1,
+            0,
+        )
+    };
+    addr != libc::MAP_FAILED
+}
+
+extern "C" fn handle_sigbus(_signum: c_int, info: *mut siginfo_t, _ucontext: *mut c_void) {
+    // Safe because the kernel passes a valid siginfo to SA_SIGINFO handlers.
+    let host_addr = unsafe { (*info).si_addr() } as usize;
+    let recoverable = THREAD_CATCHING.with(|catching| catching.get())
+        || FAULT_EVENT_FD.load(Ordering::SeqCst) >= 0;
+    if let Some((guest_addr, label)) = find_registered(host_addr).filter(|_| recoverable) {
+        if replace_page(host_addr) {
+            record_fault(guest_addr, label);
+            return;
+        }
+    }
+
+    // Restore the default disposition so the faulting access raises SIGBUS again once the
+    // handler returns, this time terminating the process.
+    // Safe because `sigact` is fully initialized and sigaction is async-signal-safe.
+    unsafe {
+        let mut sigact: libc::sigaction = mem::zeroed();
+        sigact.sa_sigaction = libc::SIG_DFL;
+        libc::sigaction(libc::SIGBUS, &sigact, null_mut());
+    }
+}
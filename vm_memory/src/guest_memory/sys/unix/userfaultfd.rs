@@ -0,0 +1,388 @@
+// Copyright 2022 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Guest memory filled on demand from a file, so that a VM can be restored from a snapshot without
+//! reading all of its memory up front.
+//!
+//! A lazy region maps sealed shared memory, like the rest of guest RAM, and registers the mapping
+//! with a userfaultfd in missing mode. The first access to each page, by the guest or the host,
+//! blocks until a `LazyFaultHandler` copies the page in from the source file. From then on the
+//! page is ordinary shared memory, and a page released with `GuestMemory::remove_range` comes back
+//! as a zero page rather than being read from the source again.
+//!
+//! Other processes mapping the shared memory, such as vhost-user backends, aren't registered with
+//! the userfaultfd: they must only access pages the guest has already touched.
+
+use std::fs::File;
+use std::mem::size_of;
+use std::os::unix::fs::FileExt;
+use std::sync::Arc;
+
+use base::ioctl_iowr_nr;
+use base::ioctl_with_mut_ref;
+use base::pagesize;
+use base::AsRawDescriptor;
+use base::Error as SysError;
+use base::Event;
+use base::FromRawDescriptor;
+use base::MemoryMappingBuilder;
+use base::SafeDescriptor;
+use base::SharedMemory;
+use base::WaitContext;
+use sync::Mutex;
+
+use super::finalize_shm;
+use crate::userfaultfd_bindings::*;
+use crate::BackingObject;
+use crate::Error;
+use crate::GuestAddress;
+use crate::GuestMemory;
+use crate::MemoryRegion;
+use crate::Result;
+
+// Defined with casts, which bindgen doesn't evaluate.
+const UFFD_API: u64 = 0xaa;
+const UFFDIO_REGISTER_MODE_MISSING: u64 = 1;
+
+ioctl_iowr_nr!(UFFDIO_API, UFFDIO, _UFFDIO_API, uffdio_api);
+ioctl_iowr_nr!(UFFDIO_REGISTER, UFFDIO, _UFFDIO_REGISTER, uffdio_register);
+ioctl_iowr_nr!(UFFDIO_COPY, UFFDIO, _UFFDIO_COPY, uffdio_copy);
+ioctl_iowr_nr!(UFFDIO_ZEROPAGE, UFFDIO, _UFFDIO_ZEROPAGE, uffdio_zeropage);
+
+/// The shared memory backing a lazy region, and where its contents come from.
+#[derive(Debug)]
+pub struct LazyBacking {
+    shm: SharedMemory,
+    uffd: SafeDescriptor,
+    source: Arc<File>,
+    source_offset: u64,
+    // One bit per page of the region, set once the page has been filled.
+    filled: Mutex<Vec<u64>>,
+}
+
+impl LazyBacking {
+    /// Returns the shared memory the pages are filled into.
+    pub fn shm(&self) -> &SharedMemory {
+        &self.shm
+    }
+}
+
+fn userfaultfd() -> Result<SafeDescriptor> {
+    // Safe because this doesn't take any memory and the result is checked.
+    let fd = unsafe { libc::syscall(libc::SYS_userfaultfd, libc::O_CLOEXEC | libc::O_NONBLOCK) };
+    if fd < 0 {
+        return Err(Error::Userfaultfd(SysError::last()));
+    }
+    // Safe because the descriptor was just created and is owned by nothing else.
+    let uffd = unsafe { SafeDescriptor::from_raw_descriptor(fd as i32) };
+
+    let mut api = uffdio_api {
+        api: UFFD_API,
+        ..Default::default()
+    };
+    // Safe because the kernel only writes within `api` and the result is checked.
+    if unsafe { ioctl_with_mut_ref(&uffd, UFFDIO_API(), &mut api) } < 0 {
+        return Err(Error::Userfaultfd(SysError::last()));
+    }
+    Ok(uffd)
+}
+
+impl MemoryRegion {
+    /// Creates a region of `size` bytes at `guest_base` whose pages are filled from `source`,
+    /// starting at `offset`, the first time they are accessed. The pages past the end of `source`
+    /// read as zeroes.
+    ///
+    /// Accesses to pages that haven't been filled yet block until a `LazyFaultHandler` of the
+    /// guest memory holding the region handles them. See `GuestMemory::lazy_fault_handler`.
+    pub fn new_lazy(
+        size: u64,
+        guest_base: GuestAddress,
+        source: Arc<File>,
+        offset: u64,
+    ) -> Result<Self> {
+        let page_size = pagesize() as u64;
+        if size == 0 || size % page_size != 0 {
+            return Err(Error::MemoryNotAligned);
+        }
+        let mut shm =
+            SharedMemory::new("crosvm_guest_lazy", size).map_err(Error::MemoryCreationFailed)?;
+        finalize_shm(&mut shm)?;
+        let mapping = MemoryMappingBuilder::new(size as usize)
+            .from_shared_memory(&shm)
+            .build()
+            .map_err(Error::MemoryMappingFailed)?;
+
+        let uffd = userfaultfd()?;
+        let mut register = uffdio_register {
+            range: uffdio_range {
+                start: mapping.as_ptr() as u64,
+                len: size,
+            },
+            mode: UFFDIO_REGISTER_MODE_MISSING,
+            ..Default::default()
+        };
+        // Safe because the kernel only writes within `register` and the result is checked. The
+        // registration ends when `mapping` is unmapped.
+        if unsafe { ioctl_with_mut_ref(&uffd, UFFDIO_REGISTER(), &mut register) } < 0 {
+            return Err(Error::Userfaultfd(SysError::last()));
+        }
+
+        let pages = (size / page_size) as usize;
+        let backing = LazyBacking {
+            shm,
+            uffd,
+            source,
+            source_offset: offset,
+            filled: Mutex::new(vec![0; (pages + 63) / 64]),
+        };
+        Ok(MemoryRegion::new(
+            mapping,
+            guest_base,
+            BackingObject::Lazy(Arc::new(backing)),
+            0,
+            false,
+        ))
+    }
+}
+
+/// Fills the pages of the lazy regions of a `GuestMemory` as they are accessed, from a thread
+/// of the VMM. See `MemoryRegion::new_lazy`.
+pub struct LazyFaultHandler {
+    regions: Vec<(Arc<MemoryRegion>, Arc<LazyBacking>)>,
+    wait_ctx: WaitContext<usize>,
+    page: Vec<u8>,
+}
+
+impl LazyFaultHandler {
+    /// Handles the faults in the lazy regions until `exit_evt` is signaled.
+    pub fn run(&mut self, exit_evt: &Event) -> Result<()> {
+        let exit_token = self.regions.len();
+        self.wait_ctx
+            .add(exit_evt, exit_token)
+            .map_err(Error::Userfaultfd)?;
+        let res = self.wait_for_faults(exit_token);
+        let _ = self.wait_ctx.delete(exit_evt);
+        res
+    }
+
+    fn wait_for_faults(&mut self, exit_token: usize) -> Result<()> {
+        loop {
+            let events = self.wait_ctx.wait().map_err(Error::Userfaultfd)?;
+            for event in events.iter().filter(|e| e.is_readable) {
+                if event.token == exit_token {
+                    return Ok(());
+                }
+                self.handle_faults(event.token)?;
+            }
+        }
+    }
+
+    // Handles the faults pending in the region at `index`.
+    fn handle_faults(&mut self, index: usize) -> Result<()> {
+        let (region, backing) = &self.regions[index];
+        let page_size = pagesize() as u64;
+        let host_base = region.mapping.as_ptr() as u64;
+        loop {
+            let mut msg = [0u8; size_of::<uffd_msg>()];
+            // Safe because the kernel only writes within `msg` and the result is checked.
+            let len = unsafe {
+                libc::read(
+                    backing.uffd.as_raw_descriptor(),
+                    msg.as_mut_ptr() as *mut libc::c_void,
+                    msg.len(),
+                )
+            };
+            if len < 0 {
+                let e = SysError::last();
+                if e.errno() == libc::EAGAIN {
+                    return Ok(());
+                }
+                return Err(Error::Userfaultfd(e));
+            }
+            // Safe because `msg` is as large as a `uffd_msg`, which is plain data.
+            let msg: uffd_msg = unsafe { std::ptr::read_unaligned(msg.as_ptr() as *const _) };
+            if u32::from(msg.event) != UFFD_EVENT_PAGEFAULT {
+                continue;
+            }
+            // Safe because the message is a page fault.
+            let address = unsafe { msg.arg.pagefault.address };
+            let offset = (address - host_base) / page_size * page_size;
+            let page = (offset / page_size) as usize;
+
+            let already_filled = {
+                let filled = backing.filled.lock();
+                filled[page / 64] & (1 << (page % 64)) != 0
+            };
+            let res = if already_filled {
+                // The page was released since it was filled.
+                let mut zeropage = uffdio_zeropage {
+                    range: uffdio_range {
+                        start: host_base + offset,
+                        len: page_size,
+                    },
+                    ..Default::default()
+                };
+                // Safe because the range is a page of the registered mapping, and the kernel only
+                // writes within `zeropage`.
+                unsafe { ioctl_with_mut_ref(&backing.uffd, UFFDIO_ZEROPAGE(), &mut zeropage) }
+            } else {
+                let guest_addr = region.guest_base.unchecked_add(offset);
+                self.page.resize(page_size as usize, 0);
+                let mut read = 0;
+                while read < self.page.len() {
+                    match backing.source.read_at(
+                        &mut self.page[read..],
+                        backing.source_offset + offset + read as u64,
+                    ) {
+                        Ok(0) => break,
+                        Ok(n) => read += n,
+                        Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                        Err(e) => return Err(Error::LazySourceRead(guest_addr, e)),
+                    }
+                }
+                // Past the end of the source.
+                self.page[read..].fill(0);
+
+                let mut copy = uffdio_copy {
+                    dst: host_base + offset,
+                    src: self.page.as_ptr() as u64,
+                    len: page_size,
+                    ..Default::default()
+                };
+                // Safe because the destination is a page of the registered mapping, the source is
+                // a page sized buffer, and the kernel only writes within `copy`.
+                unsafe { ioctl_with_mut_ref(&backing.uffd, UFFDIO_COPY(), &mut copy) }
+            };
+            if res < 0 {
+                let e = SysError::last();
+                // Several accesses faulted on the same page before it was filled.
+                if e.errno() != libc::EEXIST {
+                    return Err(Error::Userfaultfd(e));
+                }
+            }
+            backing.filled.lock()[page / 64] |= 1 << (page % 64);
+        }
+    }
+}
+
+impl GuestMemory {
+    /// Returns a handler filling the pages of the lazy regions of this memory from their source,
+    /// or `None` if there are none. See `MemoryRegion::new_lazy`.
+    ///
+    /// The handler is meant to run on a thread of its own, as any access to a page of a lazy
+    /// region that hasn't been filled yet blocks until the handler fills it.
+    pub fn lazy_fault_handler(&self) -> Result<Option<LazyFaultHandler>> {
+        let regions: Vec<_> = self
+            .regions
+            .iter()
+            .filter_map(|region| match &region.shared_obj {
+                BackingObject::Lazy(backing) => Some((region.clone(), backing.clone())),
+                _ => None,
+            })
+            .collect();
+        if regions.is_empty() {
+            return Ok(None);
+        }
+        let wait_ctx = WaitContext::new().map_err(Error::Userfaultfd)?;
+        for (index, (_, backing)) in regions.iter().enumerate() {
+            wait_ctx
+                .add(&backing.uffd, index)
+                .map_err(Error::Userfaultfd)?;
+        }
+        Ok(Some(LazyFaultHandler {
+            regions,
+            wait_ctx,
+            page: Vec::new(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    // Returns a file of `pages` pages, each filled with its index plus one.
+    fn source_file(pages: usize) -> Arc<File> {
+        let file = tempfile::tempfile().unwrap();
+        for page in 0..pages {
+            file.write_all_at(
+                &vec![page as u8 + 1; pagesize()],
+                (page * pagesize()) as u64,
+            )
+            .unwrap();
+        }
+        Arc::new(file)
+    }
+
+    // Creates a lazy region, or returns `None` if userfaultfd isn't available to the test.
+    fn lazy_region(size: u64, guest_base: GuestAddress, source: Arc<File>) -> Option<MemoryRegion> {
+        match MemoryRegion::new_lazy(size, guest_base, source, pagesize() as u64) {
+            Ok(region) => Some(region),
+            Err(Error::Userfaultfd(e)) if matches!(e.errno(), libc::EPERM | libc::ENOSYS) => None,
+            Err(e) => panic!("failed to create lazy region: {}", e),
+        }
+    }
+
+    #[test]
+    fn lazy_region_filled_on_access() {
+        let page_size = pagesize() as u64;
+        let source = source_file(3);
+        // The region starts at the second page of the source and extends past its end.
+        let region = match lazy_region(3 * page_size, GuestAddress(0x10000), source) {
+            Some(region) => region,
+            None => return,
+        };
+        let mem = GuestMemory::new_with_file_regions(&[(GuestAddress(0), 0x10000)], vec![region])
+            .unwrap();
+        let mut handler = mem.lazy_fault_handler().unwrap().unwrap();
+        let exit_evt = Event::new().unwrap();
+        let handler_exit_evt = exit_evt.try_clone().unwrap();
+        let handler_thread = thread::spawn(move || handler.run(&handler_exit_evt));
+
+        assert_eq!(
+            mem.read_obj_from_addr::<u8>(GuestAddress(0x10000)).unwrap(),
+            2
+        );
+        assert_eq!(
+            mem.read_obj_from_addr::<u8>(GuestAddress(0x10000 + page_size + 7))
+                .unwrap(),
+            3
+        );
+        assert_eq!(
+            mem.read_obj_from_addr::<u8>(GuestAddress(0x10000 + 2 * page_size))
+                .unwrap(),
+            0
+        );
+
+        // Filled pages are ordinary memory, and released pages come back as zeroes.
+        mem.write_obj_at_addr(0x55_u8, GuestAddress(0x10000))
+            .unwrap();
+        assert_eq!(
+            mem.read_obj_from_addr::<u8>(GuestAddress(0x10000)).unwrap(),
+            0x55
+        );
+        mem.remove_range(GuestAddress(0x10000 + page_size), page_size)
+            .unwrap();
+        assert_eq!(
+            mem.read_obj_from_addr::<u8>(GuestAddress(0x10000 + page_size))
+                .unwrap(),
+            0
+        );
+
+        // Regular regions are unaffected.
+        mem.write_obj_at_addr(0xaa_u8, GuestAddress(0x1000))
+            .unwrap();
+
+        exit_evt.write(1).unwrap();
+        handler_thread.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn no_lazy_regions() {
+        let mem = GuestMemory::new(&[(GuestAddress(0), 0x10000)]).unwrap();
+        assert!(mem.lazy_fault_handler().unwrap().is_none());
+    }
+}
@@ -2,10 +2,16 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
+use std::sync::Arc;
+
 use base::SharedMemory;
 use bitflags::bitflags;
 
+use crate::BackingObject;
+use crate::Error;
+use crate::GuestAddress;
 use crate::GuestMemory;
+use crate::MemoryRegion;
 use crate::Result;
 
 bitflags! {
@@ -13,13 +19,58 @@ bitflags! {
     }
 }
 
-pub(crate) fn finalize_shm(_shm: &mut SharedMemory) -> Result<()> {
+fn finalize_shm(_shm: &mut SharedMemory) -> Result<()> {
     // Seals are only a concept on Unix systems. On Windows, SharedMemory allocation cannot be
     // updated after creation regardless, so the same operation is done implicitly.
     Ok(())
 }
 
+/// Creates the shared memory backing guest memory.
+pub(crate) fn create_backing_object(name: &str, size: u64) -> Result<BackingObject> {
+    let mut shm = SharedMemory::new(name, size).map_err(Error::MemoryCreationFailed)?;
+    finalize_shm(&mut shm)?;
+    Ok(BackingObject::Shm(Arc::new(shm)))
+}
+
+/// Guest memory mappings aren't tracked on Windows, where accesses can't fault with SIGBUS.
+#[derive(Debug)]
+pub(crate) struct FaultRegistration;
+
+impl FaultRegistration {
+    pub(crate) fn new(
+        _host_start: usize,
+        _len: usize,
+        _guest_base: GuestAddress,
+        _label: &'static str,
+    ) -> FaultRegistration {
+        FaultRegistration
+    }
+}
+
+pub(crate) fn catch_access_fault<T>(f: impl FnOnce() -> Result<T>) -> Result<T> {
+    f()
+}
+
+impl MemoryRegion {
+    /// Applies the hints of `policy` to the whole region.
+    pub fn with_policy(self, _policy: MemoryPolicy) -> Result<Self> {
+        // Hints aren't supported on Windows.
+        Ok(self)
+    }
+}
+
 impl GuestMemory {
+    /// Applies the hints of `policy` to the `len` bytes of guest memory at `addr`.
+    pub fn set_memory_policy_for_range(
+        &self,
+        _addr: GuestAddress,
+        _len: u64,
+        _policy: MemoryPolicy,
+    ) -> Result<()> {
+        // Hints aren't supported on Windows.
+        Ok(())
+    }
+
     /// Handles guest memory policy hints/advices.
     pub fn set_memory_policy(&self, _mem_policy: MemoryPolicy) {
         // Hints aren't supported on Windows.
@@ -8,6 +8,8 @@ mod guest_address;
 pub mod guest_memory;
 pub mod udmabuf;
 mod udmabuf_bindings;
+#[cfg(all(unix, feature = "userfaultfd"))]
+mod userfaultfd_bindings;
 
 pub use guest_address::*;
 pub use guest_memory::Error as GuestMemoryError;
@@ -14,6 +14,7 @@ use crate::r8b_create;
 use crate::r8b_delete;
 use crate::r8b_process;
 use crate::win_audio_impl;
+use crate::AudioSampleFormat;
 use crate::CR8BResampler;
 use crate::ER8BResamplerRes_r8brr24;
 
@@ -27,9 +28,8 @@ const MONO_CHANNEL_COUNT: usize = win_audio_impl::MONO_CHANNEL_COUNT as usize;
 /// Provides a ring buffer to hold audio samples coming from the guest. Also responsible for sample
 /// rate conversion (src) if needed. We are assuming the guest's sample format is ALWAYS 16bit
 /// ints, 48kHz, and 2 channels because this is defined in Kiwi's Android Audio HAL, which
-/// we control. We are also assuming that the audio engine will always take 32bit
-/// floats if we ask for the shared format through `GetMixFormat` since it will convert
-/// to 32bit floats if it's not anyways.
+/// we control. The samples are converted to the `AudioSampleFormat` negotiated with the audio
+/// engine.
 pub struct IntermediateResamplerBuffer {
     left_resampler: CR8BResampler,
     right_resampler: CR8BResampler,
@@ -39,6 +39,8 @@ pub struct IntermediateResamplerBuffer {
     pub guest_period_in_target_sample_rate_frames: usize,
     resampled_output_buffer: Vec<u8>,
     num_channels: usize,
+    to_sample_rate: usize,
+    sample_format: AudioSampleFormat,
 }
 
 impl IntermediateResamplerBuffer {
@@ -49,6 +51,7 @@ impl IntermediateResamplerBuffer {
         shared_audio_engine_period_in_frames: usize,
         num_channels: usize,
         channel_mask: Option<u32>,
+        sample_format: AudioSampleFormat,
     ) -> Result<Self, BoxError> {
         // Convert the period to milliseconds. Even though rounding happens, it shouldn't distort
         // the result.
@@ -101,9 +104,44 @@ impl IntermediateResamplerBuffer {
                 shared_audio_engine_period_in_frames * 8,
             ),
             num_channels,
+            to_sample_rate,
+            sample_format,
         })
     }
 
+    /// The sample rate samples are converted to.
+    pub fn frame_rate(&self) -> usize {
+        self.to_sample_rate
+    }
+
+    /// Updates the audio engine period after the shared format was renegotiated. Samples already
+    /// in `ring_buf` are kept and played in periods of the new size.
+    pub fn set_shared_audio_engine_period_in_frames(&mut self, frames: usize) {
+        if frames == self.shared_audio_engine_period_in_frames {
+            return;
+        }
+        info!(
+            "Audio engine period changed from {} to {} frames",
+            self.shared_audio_engine_period_in_frames, frames
+        );
+        self.shared_audio_engine_period_in_frames = frames;
+        self.ring_buf
+            .reserve((frames * PERIOD_COUNT).saturating_sub(self.ring_buf.len()));
+        self.resampled_output_buffer
+            .reserve((frames * 8).saturating_sub(self.resampled_output_buffer.len()));
+    }
+
+    /// Updates the format samples are converted to after the shared format was renegotiated.
+    pub fn set_sample_format(&mut self, sample_format: AudioSampleFormat) {
+        if sample_format != self.sample_format {
+            info!(
+                "Audio sample format changed from {:?} to {:?}",
+                self.sample_format, sample_format
+            );
+            self.sample_format = sample_format;
+        }
+    }
+
     /// Converts the 16 bit int samples to the target sample rate and also add to the
     /// intermediate `ring_buf` if needed.
     pub fn convert_and_add(&mut self, input_buffer: &[u8]) {
@@ -211,8 +249,11 @@ impl IntermediateResamplerBuffer {
 
         if self.ring_buf.len() >= sample_threshold {
             for current_sample in self.ring_buf.drain(..sample_threshold) {
-                self.resampled_output_buffer
-                    .extend_from_slice(&current_sample.to_le_bytes());
+                encode_sample(
+                    current_sample,
+                    self.sample_format,
+                    &mut self.resampled_output_buffer,
+                );
             }
             return Some(&self.resampled_output_buffer);
         } else {
@@ -239,6 +280,22 @@ impl IntermediateResamplerBuffer {
     }
 }
 
+/// Appends `sample`, which ranges from -1.0 to 1.0, to `output` in `sample_format`.
+fn encode_sample(sample: f32, sample_format: AudioSampleFormat, output: &mut Vec<u8>) {
+    match sample_format {
+        AudioSampleFormat::Float32 => output.extend_from_slice(&sample.to_le_bytes()),
+        AudioSampleFormat::Pcm24In32 => {
+            // The 24 bits go in the most significant bits of the container.
+            let sample = (sample.clamp(-1.0, 1.0) * 8_388_607.0) as i32;
+            output.extend_from_slice(&(sample << 8).to_le_bytes());
+        }
+        AudioSampleFormat::Pcm16 => {
+            let sample = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            output.extend_from_slice(&sample.to_le_bytes());
+        }
+    }
+}
+
 impl Drop for IntermediateResamplerBuffer {
     fn drop(&mut self) {
         // Safe because this is calling to a FFI that was binded properly. Also
@@ -264,7 +321,13 @@ mod test {
     #[test]
     fn test_copy_every_other_and_convert_to_float() {
         let intermediate_src_buffer = IntermediateResamplerBuffer::new(
-            48000, 44100, 480, 448, /* num_channel */ 2, /* channel_mask */ None,
+            48000,
+            44100,
+            480,
+            448,
+            /* num_channel */ 2,
+            /* channel_mask */ None,
+            AudioSampleFormat::Float32,
         )
         .unwrap();
 
@@ -301,7 +364,13 @@ mod test {
     fn test_get_next_period() {
         // Create an intermediate buffer that won't require resampling
         let mut intermediate_src_buffer = IntermediateResamplerBuffer::new(
-            48000, 48000, 480, 513, /* num_channel */ 2, /* channel_mask */ None,
+            48000,
+            48000,
+            480,
+            513,
+            /* num_channel */ 2,
+            /* channel_mask */ None,
+            AudioSampleFormat::Float32,
         )
         .unwrap();
 
@@ -320,13 +389,49 @@ mod test {
         assert!(intermediate_src_buffer.get_next_period().is_some());
     }
 
+    #[test]
+    fn test_get_next_period_sample_formats() {
+        let samples = [0.0, 0.5, -1.0, 2.0];
+        let encode = |sample_format| {
+            let mut intermediate_src_buffer = IntermediateResamplerBuffer::new(
+                48000,
+                48000,
+                480,
+                /* shared_audio_engine_period_in_frames */ 2,
+                /* num_channel */ 2,
+                /* channel_mask */ None,
+                sample_format,
+            )
+            .unwrap();
+            intermediate_src_buffer.ring_buf.extend(samples);
+            intermediate_src_buffer.get_next_period().unwrap().clone()
+        };
+
+        let float32: Vec<u8> = samples.iter().flat_map(|x| x.to_le_bytes()).collect();
+        assert_eq!(encode(AudioSampleFormat::Float32), float32);
+        // Samples out of range are clipped.
+        let pcm24_in_32: Vec<u8> = [0i32, 4_194_303 << 8, -8_388_607 << 8, 8_388_607 << 8]
+            .iter()
+            .flat_map(|x| x.to_le_bytes())
+            .collect();
+        assert_eq!(encode(AudioSampleFormat::Pcm24In32), pcm24_in_32);
+        let pcm16: Vec<u8> = [0i16, 16383, -32767, 32767]
+            .iter()
+            .flat_map(|x| x.to_le_bytes())
+            .collect();
+        assert_eq!(encode(AudioSampleFormat::Pcm16), pcm16);
+    }
+
     #[test]
     fn test_perform_channel_conversion_mono() {
         let mut intermediate_src_buffer = IntermediateResamplerBuffer::new(
-            /* from_sample_rate */ 48000, /* to_sample_rate */ 48000,
+            /* from_sample_rate */ 48000,
+            /* to_sample_rate */ 48000,
             /* guest_period_in_frames */ 480,
-            /* shared_audio_engine_period_in_frames */ 513, /* num_channel */ 1,
+            /* shared_audio_engine_period_in_frames */ 513,
+            /* num_channel */ 1,
             /* channel_mask */ None,
+            AudioSampleFormat::Float32,
         )
         .unwrap();
 
@@ -357,6 +462,7 @@ mod test {
             448,
             /* num_channel */ 6,
             /* channel_mask */ Some(channel_mask),
+            AudioSampleFormat::Float32,
         )
         .unwrap();
 
@@ -392,6 +498,7 @@ mod test {
             448,
             /* num_channel */ 8,
             /* channel_mask */ Some(channel_mask),
+            AudioSampleFormat::Float32,
         )
         .unwrap();
 
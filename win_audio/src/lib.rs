@@ -20,6 +20,8 @@ macro_rules! check_hresult {
 }
 
 pub mod intermediate_resampler_buffer;
+pub mod loopback;
+pub mod stream_info;
 mod win_audio_impl;
 use std::error;
 use std::sync::Arc;
@@ -36,14 +38,24 @@ use win_audio_impl::*;
 
 pub type BoxError = Box<dyn error::Error + Send + Sync>;
 
+/// How the samples passed to the audio engine are encoded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AudioSampleFormat {
+    /// 32 bit IEEE floats.
+    Float32,
+    /// 24 bit ints in the most significant bits of 32 bit containers.
+    Pcm24In32,
+    /// 16 bit ints.
+    Pcm16,
+}
+
 /// Contains information about the audio engine's properties, such as its audio sample format
 /// and its period in frames.
-///
-/// This does exclude whether the bit depth is in the form of floats or ints. The bit depth form
-/// isn't used for sample rate conversion so it's excluded.
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug)]
 pub struct AudioSharedFormat {
+    /// Size of a sample in bits, including the padding of `Pcm24In32` samples.
     pub bit_depth: usize,
+    pub sample_format: AudioSampleFormat,
     pub frame_rate: usize,
     pub shared_audio_engine_period_in_frames: usize,
     pub channels: usize,
@@ -51,6 +63,30 @@ pub struct AudioSharedFormat {
     pub channel_mask: Option<u32>,
 }
 
+impl AudioSharedFormat {
+    /// Size of a frame of samples in bytes.
+    pub fn frame_size_bytes(&self) -> usize {
+        self.bit_depth * self.channels / 8
+    }
+}
+
+/// Lets the user of a playback stream find out that the stream renegotiated its
+/// `AudioSharedFormat`, e.g. because the audio engine period changed, so that it can adjust its
+/// buffers. Clones share the same pending update.
+#[derive(Clone, Default)]
+pub struct AudioSharedFormatUpdates(Arc<Mutex<Option<AudioSharedFormat>>>);
+
+impl AudioSharedFormatUpdates {
+    pub(crate) fn notify(&self, format: AudioSharedFormat) {
+        *self.0.lock() = Some(format);
+    }
+
+    /// Returns the latest format the stream switched to since the last call, if any.
+    pub fn take(&self) -> Option<AudioSharedFormat> {
+        self.0.lock().take()
+    }
+}
+
 /// Implementation of StreamSource which will create the playback stream for the Windows
 /// audio engine.
 ///
@@ -72,6 +108,10 @@ pub trait WinAudioServer: StreamSource {
     /// Returns true if audio server is a noop stream. This determine if evicting a cache is worth
     /// doing
     fn is_noop_stream(&self) -> bool;
+
+    /// Returns the updates to the format of the playback stream returned by
+    /// `new_playback_stream_and_get_shared_format`.
+    fn shared_format_updates(&self) -> AudioSharedFormatUpdates;
 }
 
 impl WinAudioServer for WinAudio {
@@ -96,6 +136,9 @@ impl WinAudioServer for WinAudio {
             return Ok((playback_buffer_stream.clone(), *audio_format));
         }
 
+        // Updates to a previous stream don't apply to the new one.
+        self.shared_format_updates.take();
+
         let (playback_buffer_stream, audio_shared_format): (
             Arc<Mutex<Box<dyn PlaybackBufferStream>>>,
             AudioSharedFormat,
@@ -103,6 +146,7 @@ impl WinAudioServer for WinAudio {
             num_channels,
             frame_rate as u32,
             buffer_size,
+            self.shared_format_updates.clone(),
         ) {
             Ok(renderer) => {
                 let audio_shared_format = renderer.device.audio_shared_format;
@@ -127,6 +171,7 @@ impl WinAudioServer for WinAudio {
                     )))),
                     AudioSharedFormat {
                         bit_depth: 16,
+                        sample_format: AudioSampleFormat::Pcm16,
                         frame_rate,
                         channels: 2,
                         shared_audio_engine_period_in_frames: frame_rate / 100,
@@ -146,6 +191,10 @@ impl WinAudioServer for WinAudio {
     fn is_noop_stream(&self) -> bool {
         false
     }
+
+    fn shared_format_updates(&self) -> AudioSharedFormatUpdates {
+        self.shared_format_updates.clone()
+    }
 }
 
 impl WinAudioServer for NoopStreamSource {
@@ -163,6 +212,7 @@ impl WinAudioServer for NoopStreamSource {
             Arc::new(Mutex::new(playback_buffer_stream)),
             AudioSharedFormat {
                 bit_depth: 16,
+                sample_format: AudioSampleFormat::Pcm16,
                 frame_rate,
                 channels: 2,
                 shared_audio_engine_period_in_frames: frame_rate / 100,
@@ -178,6 +228,11 @@ impl WinAudioServer for NoopStreamSource {
     fn is_noop_stream(&self) -> bool {
         true
     }
+
+    fn shared_format_updates(&self) -> AudioSharedFormatUpdates {
+        // The format of a noop stream never changes.
+        AudioSharedFormatUpdates::default()
+    }
 }
 
 pub fn create_win_audio_device() -> Result<WinAudio, BoxError> {
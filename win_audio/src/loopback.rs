@@ -0,0 +1,414 @@
+// Copyright 2022 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Loopback capture of the frames rendered to the audio engine, so that tests can check the guest
+//! played audio without a physical audio device.
+//!
+//! Once enabled, every buffer committed by a playback stream is copied into a ring buffer holding
+//! the last `LOOPBACK_HISTORY_SECONDS` of audio, which can be read with `capture`. The frames can
+//! also be written to a WAV file, which is done by a separate thread so that playback timing is
+//! only affected by the copy.
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::thread;
+
+use base::error;
+use base::warn;
+use once_cell::sync::Lazy;
+use sync::Mutex;
+
+use crate::AudioSampleFormat;
+use crate::AudioSharedFormat;
+
+/// Seconds of audio kept for `capture`.
+pub const LOOPBACK_HISTORY_SECONDS: usize = 10;
+
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+const WAV_HEADER_SIZE: u32 = 44;
+
+#[derive(Clone, Debug, Default)]
+pub struct LoopbackConfig {
+    /// File the rendered frames are also written to, as a WAV file.
+    pub wav_path: Option<PathBuf>,
+}
+
+/// Frames captured from the playback streams.
+#[derive(Clone, Debug)]
+pub struct LoopbackFrames {
+    /// `None` if nothing was rendered yet.
+    pub format: Option<AudioSharedFormat>,
+    /// Interleaved samples in `format`.
+    pub data: Vec<u8>,
+}
+
+static LOOPBACK: Lazy<Mutex<Option<Loopback>>> = Lazy::new(Default::default);
+
+/// Starts copying the frames rendered by the playback streams.
+pub fn enable_loopback(config: LoopbackConfig) -> io::Result<()> {
+    let wav = match config.wav_path {
+        Some(path) => Some(WavSink::Pending(File::create(path)?)),
+        None => None,
+    };
+    *LOOPBACK.lock() = Some(Loopback::new(LOOPBACK_HISTORY_SECONDS, wav));
+    Ok(())
+}
+
+/// Returns the last `frames` frames rendered, or fewer if not that many were kept. Returns `None`
+/// if loopback capture isn't enabled.
+pub fn capture(frames: usize) -> Option<LoopbackFrames> {
+    LOOPBACK
+        .lock()
+        .as_ref()
+        .map(|loopback| loopback.capture(frames))
+}
+
+/// Copies `data`, the frames just rendered in `format`, if loopback capture is enabled.
+pub(crate) fn tee(format: &AudioSharedFormat, data: &[u8]) {
+    if let Some(loopback) = LOOPBACK.lock().as_mut() {
+        loopback.tee(format, data);
+    }
+}
+
+// Whether samples in both formats can be stored one after the other.
+fn same_layout(a: &AudioSharedFormat, b: &AudioSharedFormat) -> bool {
+    a.bit_depth == b.bit_depth
+        && a.sample_format == b.sample_format
+        && a.frame_rate == b.frame_rate
+        && a.channels == b.channels
+}
+
+struct Loopback {
+    history_seconds: usize,
+    format: Option<AudioSharedFormat>,
+    frames: VecDeque<u8>,
+    wav: Option<WavSink>,
+}
+
+impl Loopback {
+    fn new(history_seconds: usize, wav: Option<WavSink>) -> Loopback {
+        Loopback {
+            history_seconds,
+            format: None,
+            frames: VecDeque::new(),
+            wav,
+        }
+    }
+
+    fn tee(&mut self, format: &AudioSharedFormat, data: &[u8]) {
+        if !matches!(&self.format, Some(current) if same_layout(current, format)) {
+            // The frames kept so far can't be interpreted in the new format.
+            self.frames.clear();
+            self.format = Some(*format);
+        }
+        let capacity = self.history_seconds * format.frame_rate * format.frame_size_bytes();
+        self.frames.extend(data);
+        if self.frames.len() > capacity {
+            self.frames.drain(..self.frames.len() - capacity);
+        }
+        if let Some(wav) = self.wav.as_mut() {
+            wav.write(format, data);
+        }
+    }
+
+    fn capture(&self, frames: usize) -> LoopbackFrames {
+        let len = match &self.format {
+            Some(format) => self
+                .frames
+                .len()
+                .min(frames.saturating_mul(format.frame_size_bytes())),
+            None => 0,
+        };
+        LoopbackFrames {
+            format: self.format,
+            data: self
+                .frames
+                .range(self.frames.len() - len..)
+                .copied()
+                .collect(),
+        }
+    }
+}
+
+// Hands the rendered frames to the thread writing the WAV file.
+enum WavSink {
+    // Waiting for the first frames, which determine the format of the file.
+    Pending(File),
+    Writing {
+        format: AudioSharedFormat,
+        sender: mpsc::Sender<Vec<u8>>,
+    },
+    Stopped,
+}
+
+impl WavSink {
+    fn write(&mut self, format: &AudioSharedFormat, data: &[u8]) {
+        if let WavSink::Pending(_) = self {
+            let file = match std::mem::replace(self, WavSink::Stopped) {
+                WavSink::Pending(file) => file,
+                _ => unreachable!(),
+            };
+            match WavSink::start(file, format) {
+                Ok(sender) => {
+                    *self = WavSink::Writing {
+                        format: *format,
+                        sender,
+                    }
+                }
+                Err(e) => error!("Failed to start writing the loopback WAV file: {}", e),
+            }
+        }
+        if let WavSink::Writing {
+            format: wav_format,
+            sender,
+        } = self
+        {
+            if !same_layout(wav_format, format) {
+                warn!(
+                    "Stopped writing the loopback WAV file, the format changed from {:?} to {:?}",
+                    wav_format, format
+                );
+                *self = WavSink::Stopped;
+            } else if sender.send(data.to_vec()).is_err() {
+                *self = WavSink::Stopped;
+            }
+        }
+    }
+
+    fn start(file: File, format: &AudioSharedFormat) -> io::Result<mpsc::Sender<Vec<u8>>> {
+        let mut writer = WavWriter::new(file, format)?;
+        let (sender, receiver) = mpsc::channel::<Vec<u8>>();
+        thread::Builder::new()
+            .name("loopback WAV writer".to_string())
+            .spawn(move || {
+                for data in receiver {
+                    if let Err(e) = writer.write_frames(&data) {
+                        error!("Failed to write the loopback WAV file: {}", e);
+                        return;
+                    }
+                }
+            })?;
+        Ok(sender)
+    }
+}
+
+/// Writes frames to a WAV file. The sizes in the header are updated after every write, so the
+/// file can be read at any point.
+///
+/// Samples are written in the format the audio engine is given. 24 bit samples are written as 32
+/// bit integers, which they are padded to.
+pub struct WavWriter<W: Write + Seek> {
+    writer: W,
+    data_len: u32,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(mut writer: W, format: &AudioSharedFormat) -> io::Result<WavWriter<W>> {
+        let format_tag = if format.sample_format == AudioSampleFormat::Float32 {
+            WAVE_FORMAT_IEEE_FLOAT
+        } else {
+            WAVE_FORMAT_PCM
+        };
+        let frame_size = format.frame_size_bytes();
+        writer.write_all(b"RIFF")?;
+        writer.write_all(&(WAV_HEADER_SIZE - 8).to_le_bytes())?;
+        writer.write_all(b"WAVE")?;
+        writer.write_all(b"fmt ")?;
+        writer.write_all(&16u32.to_le_bytes())?;
+        writer.write_all(&format_tag.to_le_bytes())?;
+        writer.write_all(&(format.channels as u16).to_le_bytes())?;
+        writer.write_all(&(format.frame_rate as u32).to_le_bytes())?;
+        writer.write_all(&((format.frame_rate * frame_size) as u32).to_le_bytes())?;
+        writer.write_all(&(frame_size as u16).to_le_bytes())?;
+        writer.write_all(&(format.bit_depth as u16).to_le_bytes())?;
+        writer.write_all(b"data")?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        Ok(WavWriter {
+            writer,
+            data_len: 0,
+        })
+    }
+
+    pub fn write_frames(&mut self, data: &[u8]) -> io::Result<()> {
+        let data_len = u32::try_from(data.len())
+            .ok()
+            .and_then(|len| self.data_len.checked_add(len))
+            .filter(|len| len.checked_add(WAV_HEADER_SIZE).is_some())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "WAV file is full"))?;
+        self.writer.write_all(data)?;
+        self.data_len = data_len;
+        self.writer.seek(SeekFrom::Start(4))?;
+        self.writer
+            .write_all(&(WAV_HEADER_SIZE - 8 + self.data_len).to_le_bytes())?;
+        self.writer
+            .seek(SeekFrom::Start(u64::from(WAV_HEADER_SIZE) - 4))?;
+        self.writer.write_all(&self.data_len.to_le_bytes())?;
+        self.writer.seek(SeekFrom::End(0))?;
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn float_format() -> AudioSharedFormat {
+        AudioSharedFormat {
+            bit_depth: 32,
+            sample_format: AudioSampleFormat::Float32,
+            frame_rate: 48000,
+            shared_audio_engine_period_in_frames: 480,
+            channels: 2,
+            channel_mask: None,
+        }
+    }
+
+    // 100ms of a 440Hz sine on both channels, split in audio engine periods.
+    fn sine_burst(format: &AudioSharedFormat) -> Vec<Vec<u8>> {
+        let frames: Vec<u8> = (0..format.frame_rate / 10)
+            .flat_map(|i| {
+                let sample = (2.0 * PI * 440.0 * i as f32 / format.frame_rate as f32).sin();
+                let bytes = sample.to_le_bytes();
+                (0..format.channels).flat_map(move |_| bytes)
+            })
+            .collect();
+        frames
+            .chunks(format.shared_audio_engine_period_in_frames * format.frame_size_bytes())
+            .map(|period| period.to_vec())
+            .collect()
+    }
+
+    fn samples(data: &[u8]) -> Vec<f32> {
+        data.chunks_exact(4)
+            .map(|sample| f32::from_le_bytes(sample.try_into().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn capture_sine_burst() {
+        let format = float_format();
+        let periods = sine_burst(&format);
+        let mut loopback = Loopback::new(LOOPBACK_HISTORY_SECONDS, None);
+        let captured = loopback.capture(480);
+        assert!(captured.format.is_none());
+        assert!(captured.data.is_empty());
+        for period in &periods {
+            loopback.tee(&format, period);
+        }
+
+        let played = periods.concat();
+        let captured = loopback.capture(1000);
+        assert_eq!(captured.data.len(), 1000 * format.frame_size_bytes());
+        assert_eq!(
+            samples(&captured.data),
+            samples(&played[played.len() - captured.data.len()..])
+        );
+
+        // Asking for more frames than were played returns all of them.
+        let captured = loopback.capture(usize::MAX);
+        assert_eq!(captured.data, played);
+        let peak = samples(&captured.data)
+            .into_iter()
+            .fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+        assert!(peak > 0.99);
+    }
+
+    #[test]
+    fn capture_keeps_history() {
+        let format = float_format();
+        let mut loopback = Loopback::new(1, None);
+        let second = vec![1u8; format.frame_rate * format.frame_size_bytes()];
+        loopback.tee(&format, &second);
+        loopback.tee(&format, &[2u8; 8]);
+
+        let captured = loopback.capture(usize::MAX);
+        assert_eq!(captured.data.len(), second.len());
+        assert_eq!(&captured.data[captured.data.len() - 8..], &[2u8; 8]);
+    }
+
+    #[test]
+    fn capture_format_change() {
+        let mut loopback = Loopback::new(LOOPBACK_HISTORY_SECONDS, None);
+        loopback.tee(&float_format(), &[1u8; 16]);
+        let format = AudioSharedFormat {
+            frame_rate: 44100,
+            ..float_format()
+        };
+        loopback.tee(&format, &[2u8; 8]);
+
+        let captured = loopback.capture(usize::MAX);
+        assert_eq!(captured.format.unwrap().frame_rate, 44100);
+        assert_eq!(captured.data, vec![2u8; 8]);
+    }
+
+    #[test]
+    fn wav_writer_float() {
+        let format = float_format();
+        let mut writer = WavWriter::new(Cursor::new(Vec::new()), &format).unwrap();
+        for period in sine_burst(&format) {
+            writer.write_frames(&period).unwrap();
+        }
+        let wav = writer.into_inner().into_inner();
+
+        let u16_at =
+            |offset: usize| u16::from_le_bytes(wav[offset..offset + 2].try_into().unwrap());
+        let u32_at =
+            |offset: usize| u32::from_le_bytes(wav[offset..offset + 4].try_into().unwrap());
+        let data_len = 4800 * format.frame_size_bytes();
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(u32_at(4) as usize, 36 + data_len);
+        assert_eq!(&wav[8..16], b"WAVEfmt ");
+        assert_eq!(u16_at(20), WAVE_FORMAT_IEEE_FLOAT);
+        assert_eq!(u16_at(22), 2);
+        assert_eq!(u32_at(24), 48000);
+        assert_eq!(u32_at(28), 48000 * 8);
+        assert_eq!(u16_at(32), 8);
+        assert_eq!(u16_at(34), 32);
+        assert_eq!(&wav[36..40], b"data");
+        assert_eq!(u32_at(40) as usize, data_len);
+        assert_eq!(wav.len(), 44 + data_len);
+        assert_eq!(&wav[44..], &sine_burst(&format).concat()[..]);
+    }
+
+    #[test]
+    fn wav_writer_pcm() {
+        let format = AudioSharedFormat {
+            bit_depth: 16,
+            sample_format: AudioSampleFormat::Pcm16,
+            frame_rate: 44100,
+            shared_audio_engine_period_in_frames: 441,
+            channels: 6,
+            channel_mask: None,
+        };
+        let mut writer = WavWriter::new(Cursor::new(Vec::new()), &format).unwrap();
+        writer.write_frames(&[0u8; 24]).unwrap();
+        let wav = writer.into_inner().into_inner();
+
+        let u16_at =
+            |offset: usize| u16::from_le_bytes(wav[offset..offset + 2].try_into().unwrap());
+        let u32_at =
+            |offset: usize| u32::from_le_bytes(wav[offset..offset + 4].try_into().unwrap());
+        assert_eq!(u16_at(20), WAVE_FORMAT_PCM);
+        assert_eq!(u16_at(22), 6);
+        assert_eq!(u32_at(24), 44100);
+        assert_eq!(u32_at(28), 44100 * 12);
+        assert_eq!(u16_at(32), 12);
+        assert_eq!(u16_at(34), 16);
+        assert_eq!(u32_at(40), 24);
+    }
+}
@@ -0,0 +1,169 @@
+// Copyright 2022 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Record of the playback streams rendering to the audio engine in this process, so the formats
+//! negotiated with it can be inspected while debugging.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+
+use once_cell::sync::Lazy;
+use sync::Mutex;
+
+use crate::AudioSharedFormat;
+
+/// The fields of the endpoint's mix format, as printed by the `Debug` implementation of
+/// `WaveAudioFormat`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EndpointFormat {
+    pub format_tag: u16,
+    pub channels: u16,
+    pub samples_per_sec: u32,
+    pub avg_bytes_per_sec: u32,
+    pub block_align: u16,
+    pub bits_per_sample: u16,
+    pub size_bytes: u16,
+    /// Only set for `WAVEFORMATEXTENSIBLE` formats.
+    pub extensible: Option<EndpointFormatExtensible>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EndpointFormatExtensible {
+    pub samples: u16,
+    pub channel_mask: u32,
+    /// Name of the `KSDATAFORMAT_SUBTYPE_*` constant, or the GUID if it isn't a known one.
+    pub sub_format: String,
+}
+
+/// A playback stream and the format negotiated for it.
+#[derive(Clone, Debug)]
+pub struct StreamInfo {
+    pub id: u64,
+    pub guest_channels: usize,
+    pub guest_frame_rate: u32,
+    pub guest_period_in_frames: usize,
+    pub shared_format: AudioSharedFormat,
+    /// Mix format reported by the endpoint before it was modified to a supported format.
+    pub endpoint_format: Option<EndpointFormat>,
+    /// Number of times the format was negotiated again after switching endpoints.
+    pub renegotiations: u64,
+    /// Number of times the endpoint had played all the samples it was given.
+    pub underruns: u64,
+}
+
+static STREAMS: Lazy<Mutex<BTreeMap<u64, StreamInfo>>> = Lazy::new(Default::default);
+static NEXT_STREAM_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Returns the playback streams currently open, in the order they were opened.
+pub fn stream_infos() -> Vec<StreamInfo> {
+    STREAMS.lock().values().cloned().collect()
+}
+
+/// Keeps a stream in the record until dropped.
+pub(crate) struct StreamRegistration {
+    id: u64,
+}
+
+impl StreamRegistration {
+    pub(crate) fn new(
+        guest_channels: usize,
+        guest_frame_rate: u32,
+        guest_period_in_frames: usize,
+        shared_format: AudioSharedFormat,
+        endpoint_format: Option<EndpointFormat>,
+    ) -> StreamRegistration {
+        let id = NEXT_STREAM_ID.fetch_add(1, Ordering::Relaxed);
+        STREAMS.lock().insert(
+            id,
+            StreamInfo {
+                id,
+                guest_channels,
+                guest_frame_rate,
+                guest_period_in_frames,
+                shared_format,
+                endpoint_format,
+                renegotiations: 0,
+                underruns: 0,
+            },
+        );
+        StreamRegistration { id }
+    }
+
+    /// Records the format negotiated with a new endpoint.
+    pub(crate) fn renegotiated(
+        &self,
+        shared_format: AudioSharedFormat,
+        endpoint_format: Option<EndpointFormat>,
+    ) {
+        self.update(|info| {
+            info.shared_format = shared_format;
+            info.endpoint_format = endpoint_format;
+            info.renegotiations += 1;
+        });
+    }
+
+    pub(crate) fn underrun(&self) {
+        self.update(|info| info.underruns += 1);
+    }
+
+    fn update(&self, f: impl FnOnce(&mut StreamInfo)) {
+        if let Some(info) = STREAMS.lock().get_mut(&self.id) {
+            f(info);
+        }
+    }
+}
+
+impl Drop for StreamRegistration {
+    fn drop(&mut self) {
+        STREAMS.lock().remove(&self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AudioSampleFormat;
+
+    fn shared_format(frame_rate: usize) -> AudioSharedFormat {
+        AudioSharedFormat {
+            bit_depth: 32,
+            sample_format: AudioSampleFormat::Float32,
+            frame_rate,
+            shared_audio_engine_period_in_frames: frame_rate / 100,
+            channels: 2,
+            channel_mask: None,
+        }
+    }
+
+    fn find(id: u64) -> Option<StreamInfo> {
+        stream_infos().into_iter().find(|info| info.id == id)
+    }
+
+    #[test]
+    fn registration_lifetime() {
+        let registration = StreamRegistration::new(2, 48000, 480, shared_format(48000), None);
+        let id = registration.id;
+        let info = find(id).unwrap();
+        assert_eq!(info.guest_period_in_frames, 480);
+        assert_eq!(info.renegotiations, 0);
+        assert_eq!(info.underruns, 0);
+
+        drop(registration);
+        assert!(find(id).is_none());
+    }
+
+    #[test]
+    fn renegotiations_and_underruns() {
+        let registration = StreamRegistration::new(2, 48000, 480, shared_format(48000), None);
+        registration.underrun();
+        registration.underrun();
+        registration.renegotiated(shared_format(44100), None);
+
+        let info = find(registration.id).unwrap();
+        assert_eq!(info.shared_format.frame_rate, 44100);
+        assert_eq!(info.renegotiations, 1);
+        assert_eq!(info.underruns, 2);
+    }
+}
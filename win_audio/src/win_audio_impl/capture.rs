@@ -0,0 +1,465 @@
+// Copyright 2022 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Captures audio from the default input device through WASAPI.
+
+use std::collections::VecDeque;
+use std::os::raw::c_void;
+use std::ptr::null_mut;
+use std::time::Duration;
+use std::time::Instant;
+
+use async_trait::async_trait;
+use audio_streams::capture::AsyncCaptureBuffer;
+use audio_streams::capture::AsyncCaptureBufferStream;
+use audio_streams::capture::CaptureBuffer;
+use audio_streams::capture::CaptureBufferStream;
+use audio_streams::capture::NoopCaptureStream;
+use audio_streams::AsyncBufferCommit;
+use audio_streams::AudioStreamsExecutor;
+use audio_streams::BoxError;
+use audio_streams::BufferCommit;
+use audio_streams::SampleFormat;
+use base::info;
+use base::warn;
+use base::AsRawDescriptor;
+use base::Event;
+use base::EventExt;
+use winapi::shared::guiddef::GUID;
+use winapi::um::audioclient::*;
+use winapi::um::audiosessiontypes::AUDCLNT_SHAREMODE_SHARED;
+use winapi::um::audiosessiontypes::AUDCLNT_STREAMFLAGS_EVENTCALLBACK;
+use winapi::um::combaseapi::CLSCTX_ALL;
+use winapi::um::mmdeviceapi::eCapture;
+use winapi::um::synchapi::WaitForSingleObject;
+use winapi::um::winbase::WAIT_OBJECT_0;
+use winapi::Interface;
+use wio::com::ComPtr;
+
+use super::DeviceRenderer;
+use super::RenderError;
+use super::AUDCLNT_STREAMFLAGS_AUTOCONVERTPCM;
+use super::AUDCLNT_STREAMFLAGS_SRC_DEFAULT_QUALITY;
+use super::MONO_CHANNEL_COUNT;
+use super::READY_TO_READ_TIMEOUT_MS;
+use crate::AudioSampleFormat;
+use crate::AudioSharedFormat;
+
+// The executor can't wait for the capture event, so async streams check for captured samples at
+// this interval instead.
+const ASYNC_POLL_INTERVAL: Duration = Duration::from_millis(2);
+
+/// Capture stream of 16 bit samples from the default audio input device.
+///
+/// Silence is captured instead if the device can't be used, e.g. because another application uses
+/// it in exclusive mode, and while the device is muted.
+pub(crate) struct WinAudioCapturer {
+    device: Option<DeviceCapturer>,
+    silence: NoopCaptureStream,
+    num_channels: usize,
+    frame_rate: u32,
+    // Guest period in frames.
+    buffer_size: usize,
+    // Captured samples, already mixed to `num_channels`.
+    samples: VecDeque<i16>,
+    buffer: Vec<u8>,
+    buffer_commit: CaptureBufferCommit,
+}
+
+impl WinAudioCapturer {
+    pub fn new(num_channels: usize, frame_rate: u32, buffer_size: usize) -> Self {
+        let device = match DeviceCapturer::new(num_channels, frame_rate) {
+            Ok(device) => Some(device),
+            Err(e) => {
+                warn!(
+                    "Failed to create DeviceCapturer, capturing silence instead: {}",
+                    e
+                );
+                None
+            }
+        };
+        WinAudioCapturer {
+            device,
+            silence: NoopCaptureStream::new(
+                num_channels,
+                SampleFormat::S16LE,
+                frame_rate,
+                buffer_size,
+            ),
+            num_channels,
+            frame_rate,
+            buffer_size,
+            samples: VecDeque::new(),
+            buffer: Vec::with_capacity(buffer_size * num_channels * 2),
+            buffer_commit: CaptureBufferCommit,
+        }
+    }
+
+    fn frame_size(&self) -> usize {
+        self.num_channels * SampleFormat::S16LE.sample_bytes()
+    }
+
+    // Pulls the samples the device captured so far. Returns whether a guest period of samples is
+    // ready.
+    fn fill(&mut self) -> bool {
+        if let Some(device) = &mut self.device {
+            if let Err(e) = device.read_available(self.num_channels, &mut self.samples) {
+                self.reattach_device(e);
+            }
+        }
+        self.samples.len() >= self.buffer_size * self.num_channels
+    }
+
+    // Replaces the device after it failed with `e` with whatever is now the default device, or
+    // captures silence if there is none.
+    fn reattach_device(&mut self, e: RenderError) {
+        warn!(
+            "Audio capture failed, switching to the default device: {}",
+            e
+        );
+        self.samples.clear();
+        self.device = match DeviceCapturer::new(self.num_channels, self.frame_rate) {
+            Ok(device) => Some(device),
+            Err(e) => {
+                warn!(
+                    "Failed to create DeviceCapturer, capturing silence instead: {}",
+                    e
+                );
+                None
+            }
+        };
+    }
+
+    // Moves a guest period of samples to `buffer`, padded with silence if fewer were captured.
+    fn take_period(&mut self) {
+        let period_samples = self.buffer_size * self.num_channels;
+        let captured = period_samples.min(self.samples.len());
+        self.buffer.clear();
+        for sample in self.samples.drain(..captured) {
+            self.buffer.extend_from_slice(&sample.to_le_bytes());
+        }
+        self.buffer.resize(period_samples * 2, 0);
+    }
+}
+
+impl CaptureBufferStream for WinAudioCapturer {
+    fn next_capture_buffer<'b, 's: 'b>(&'s mut self) -> Result<CaptureBuffer<'b>, BoxError> {
+        if self.device.is_none() {
+            return CaptureBufferStream::next_capture_buffer(&mut self.silence);
+        }
+        while !self.fill() {
+            match &self.device {
+                Some(device) => {
+                    if !device.wait() {
+                        warn!(
+                            "Waiting for captured audio timed out after {} ms",
+                            READY_TO_READ_TIMEOUT_MS
+                        );
+                        break;
+                    }
+                }
+                // The device failed, the rest of the period is silent.
+                None => break,
+            }
+        }
+        self.take_period();
+        let frame_size = self.frame_size();
+        Ok(CaptureBuffer::new(
+            frame_size,
+            &mut self.buffer,
+            &mut self.buffer_commit,
+        )?)
+    }
+}
+
+#[async_trait(?Send)]
+impl AsyncCaptureBufferStream for WinAudioCapturer {
+    async fn next_capture_buffer<'a>(
+        &'a mut self,
+        ex: &dyn AudioStreamsExecutor,
+    ) -> Result<AsyncCaptureBuffer<'a>, BoxError> {
+        if self.device.is_none() {
+            return AsyncCaptureBufferStream::next_capture_buffer(&mut self.silence, ex).await;
+        }
+        let start = Instant::now();
+        while !self.fill() && self.device.is_some() {
+            if start.elapsed() > Duration::from_millis(READY_TO_READ_TIMEOUT_MS.into()) {
+                warn!(
+                    "Waiting for captured audio timed out after {} ms",
+                    READY_TO_READ_TIMEOUT_MS
+                );
+                break;
+            }
+            ex.delay(ASYNC_POLL_INTERVAL).await?;
+        }
+        self.take_period();
+        let frame_size = self.frame_size();
+        Ok(AsyncCaptureBuffer::new(
+            frame_size,
+            &mut self.buffer,
+            &mut self.buffer_commit,
+        )?)
+    }
+}
+
+// The samples are released to WASAPI as soon as they are copied out of its buffers, so there is
+// nothing to do once the guest read them.
+struct CaptureBufferCommit;
+
+impl BufferCommit for CaptureBufferCommit {
+    fn commit(&mut self, _nframes: usize) {}
+}
+
+#[async_trait(?Send)]
+impl AsyncBufferCommit for CaptureBufferCommit {
+    async fn commit(&mut self, _nframes: usize) {}
+}
+
+// Wraps the WASAPI objects capturing from the default input device.
+struct DeviceCapturer {
+    audio_client: ComPtr<IAudioClient>,
+    audio_capture_client: ComPtr<IAudioCaptureClient>,
+    ready_to_read_event: Event,
+    audio_shared_format: AudioSharedFormat,
+}
+
+impl DeviceCapturer {
+    fn new(num_channels: usize, frame_rate: u32) -> Result<Self, RenderError> {
+        if num_channels > 2 {
+            return Err(RenderError::InvalidChannelCount(num_channels));
+        }
+
+        let device = DeviceRenderer::default_audio_endpoint(eCapture)?;
+        let mut audio_client: *mut c_void = null_mut();
+        // Safe because `device` is initialized and only `audio_client` is modified.
+        let hr = unsafe {
+            device.Activate(
+                &IAudioClient::uuidof(),
+                CLSCTX_ALL,
+                null_mut(),
+                &mut audio_client,
+            )
+        };
+        check_hresult!(
+            hr,
+            RenderError::from(hr),
+            "Capture device Activate() failed."
+        )?;
+        // Safe because `audio_client` is guaranteed to be initialized.
+        let audio_client = unsafe { ComPtr::from_raw(audio_client as *mut IAudioClient) };
+
+        let mix_format = DeviceRenderer::get_mix_format(&audio_client)?;
+        info!(
+            "Printing capture mix format from `GetMixFormat`:\n{:?}",
+            mix_format
+        );
+        let format = mix_format.create_capture_format(frame_rate);
+        info!("Audio Engine Capture Format Used: \n{:?}", format);
+
+        // Safe because `audio_client` is initialized and `format` outlives the call.
+        let hr = unsafe {
+            // The audio engine converts the samples from the mix format to `format`.
+            audio_client.Initialize(
+                AUDCLNT_SHAREMODE_SHARED,
+                AUDCLNT_STREAMFLAGS_EVENTCALLBACK
+                    | AUDCLNT_STREAMFLAGS_AUTOCONVERTPCM
+                    | AUDCLNT_STREAMFLAGS_SRC_DEFAULT_QUALITY,
+                0, /* hnsBufferDuration */
+                0, /* hnsPeriodicity */
+                format.as_ptr(),
+                null_mut(),
+            )
+        };
+        check_hresult!(
+            hr,
+            RenderError::from(hr),
+            "Capture Audio Client Initialize() failed."
+        )?;
+
+        let ready_to_read_event = Event::new_with_manual_reset(false).unwrap();
+        // Safe because `ready_to_read_event` is owned by the returned DeviceCapturer along with
+        // `audio_client`.
+        let hr = unsafe { audio_client.SetEventHandle(ready_to_read_event.as_raw_descriptor()) };
+        check_hresult!(hr, RenderError::from(hr), "SetEventHandle() failed.")?;
+
+        let mut audio_capture_client: *mut c_void = null_mut();
+        // Safe because `audio_client` is initialized.
+        let hr = unsafe {
+            audio_client.GetService(
+                &IID_IAudioCaptureClient as *const GUID,
+                &mut audio_capture_client,
+            )
+        };
+        check_hresult!(
+            hr,
+            RenderError::from(hr),
+            "Audio Client GetService() failed."
+        )?;
+        // Safe because `audio_capture_client` is guaranteed to be initialized.
+        let audio_capture_client =
+            unsafe { ComPtr::from_raw(audio_capture_client as *mut IAudioCaptureClient) };
+
+        let shared_audio_engine_period_in_frames = format.get_shared_audio_engine_period_in_frames(
+            DeviceRenderer::get_shared_device_period(&audio_client)?,
+        );
+
+        // Safe because `audio_client` is initialized.
+        let hr = unsafe { audio_client.Start() };
+        check_hresult!(
+            hr,
+            RenderError::from(hr),
+            "Capture Audio Client Start() failed."
+        )?;
+
+        Ok(DeviceCapturer {
+            audio_client,
+            audio_capture_client,
+            ready_to_read_event,
+            audio_shared_format: format.create_audio_shared_format(
+                AudioSampleFormat::Pcm16,
+                shared_audio_engine_period_in_frames,
+            ),
+        })
+    }
+
+    // Waits until the audio engine captured more samples. Returns false if it timed out.
+    fn wait(&self) -> bool {
+        // Safe because `ready_to_read_event` is a valid event.
+        let res = unsafe {
+            WaitForSingleObject(
+                self.ready_to_read_event.as_raw_descriptor(),
+                READY_TO_READ_TIMEOUT_MS,
+            )
+        };
+        res == WAIT_OBJECT_0
+    }
+
+    // Appends the samples captured so far to `samples`, mixed to `num_channels`, without waiting.
+    fn read_available(
+        &mut self,
+        num_channels: usize,
+        samples: &mut VecDeque<i16>,
+    ) -> Result<(), RenderError> {
+        loop {
+            let mut data: *mut u8 = null_mut();
+            let mut frames: u32 = 0;
+            let mut flags: u32 = 0;
+            // Safe because `audio_capture_client` is initialized and the pointers passed are valid
+            // for writes.
+            let hr = unsafe {
+                self.audio_capture_client.GetBuffer(
+                    &mut data,
+                    &mut frames,
+                    &mut flags,
+                    null_mut(),
+                    null_mut(),
+                )
+            };
+            if hr == AUDCLNT_S_BUFFER_EMPTY {
+                return Ok(());
+            }
+            check_hresult!(
+                hr,
+                RenderError::from(hr),
+                "Audio Capture Client GetBuffer() failed."
+            )?;
+
+            if flags & AUDCLNT_BUFFERFLAGS_SILENT != 0 || data.is_null() {
+                samples.extend(std::iter::repeat(0).take(frames as usize * num_channels));
+            } else {
+                let endpoint_channels = self.audio_shared_format.channels;
+                // Safe because WASAPI returned `frames` frames of the capture format at `data`,
+                // which stay valid until `ReleaseBuffer` is called.
+                let bytes = unsafe {
+                    std::slice::from_raw_parts(
+                        data,
+                        frames as usize * self.audio_shared_format.frame_size_bytes(),
+                    )
+                };
+                mix_frames(bytes, endpoint_channels, num_channels, samples);
+            }
+
+            // Safe because `frames` frames were retrieved by `GetBuffer` above.
+            let hr = unsafe { self.audio_capture_client.ReleaseBuffer(frames) };
+            check_hresult!(
+                hr,
+                RenderError::from(hr),
+                "Audio Capture Client ReleaseBuffer() failed."
+            )?;
+        }
+    }
+}
+
+impl Drop for DeviceCapturer {
+    fn drop(&mut self) {
+        // Safe because `audio_client` is initialized.
+        let hr = unsafe { self.audio_client.Stop() };
+        let _ = check_hresult!(
+            hr,
+            RenderError::from(hr),
+            "Capture Audio Client Stop() failed."
+        );
+    }
+}
+
+unsafe impl Send for DeviceCapturer {}
+
+// Appends the frames of 16 bit samples of `endpoint_channels` channels in `bytes` to `samples`.
+// Stereo guests get the front left and right channels, which are mixed for mono guests. Mono
+// endpoints are copied to both channels.
+fn mix_frames(
+    bytes: &[u8],
+    endpoint_channels: usize,
+    num_channels: usize,
+    samples: &mut VecDeque<i16>,
+) {
+    for frame in bytes.chunks_exact(endpoint_channels * 2) {
+        let left = i16::from_le_bytes([frame[0], frame[1]]);
+        let right = if endpoint_channels > 1 {
+            i16::from_le_bytes([frame[2], frame[3]])
+        } else {
+            left
+        };
+        if num_channels == MONO_CHANNEL_COUNT as usize {
+            samples.push_back(((left as i32 + right as i32) / 2) as i16);
+        } else {
+            samples.push_back(left);
+            samples.push_back(right);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frames(samples: &[i16]) -> Vec<u8> {
+        samples.iter().flat_map(|x| x.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn test_mix_frames() {
+        let mut samples = VecDeque::new();
+        mix_frames(&frames(&[100, -100, 7]), 1, 2, &mut samples);
+        assert_eq!(samples, [100, 100, -100, -100, 7, 7]);
+
+        let mut samples = VecDeque::new();
+        mix_frames(&frames(&[100, 300, -100, -300]), 2, 1, &mut samples);
+        assert_eq!(samples, [200, -200]);
+
+        // Only the front left and right channels of 5.1 are kept.
+        let mut samples = VecDeque::new();
+        mix_frames(
+            &frames(&[1, 2, 3, 4, 5, 6, 11, 12, 13, 14, 15, 16]),
+            6,
+            2,
+            &mut samples,
+        );
+        assert_eq!(samples, [1, 2, 11, 12]);
+
+        let mut samples = VecDeque::new();
+        mix_frames(&frames(&[i16::MAX, i16::MAX]), 2, 1, &mut samples);
+        assert_eq!(samples, [i16::MAX]);
+    }
+}
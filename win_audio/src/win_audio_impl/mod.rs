@@ -12,6 +12,9 @@ use std::sync::Once;
 use std::thread_local;
 use std::time::Duration;
 
+use audio_streams::capture::AsyncCaptureBufferStream;
+use audio_streams::capture::CaptureBufferStream;
+use audio_streams::AudioStreamsExecutor;
 use audio_streams::BoxError;
 use audio_streams::BufferCommit;
 use audio_streams::NoopStream;
@@ -21,6 +24,7 @@ use audio_streams::PlaybackBufferError;
 use audio_streams::PlaybackBufferStream;
 use audio_streams::SampleFormat;
 use audio_streams::StreamControl;
+use audio_streams::StreamEffect;
 use audio_streams::StreamSource;
 use base::error;
 use base::info;
@@ -30,6 +34,7 @@ use base::Error;
 use base::Event;
 use base::EventExt;
 use base::EventReadResult;
+use capture::WinAudioCapturer;
 use completion_handler::WinAudioActivateAudioInterfaceCompletionHandler;
 use completion_handler::ACTIVATE_AUDIO_INTERFACE_COMPLETION_EVENT;
 use metrics::event_details_proto::RecordDetails;
@@ -39,7 +44,6 @@ use thiserror::Error as ThisError;
 use wave_format::*;
 use winapi::shared::guiddef::GUID;
 use winapi::shared::guiddef::REFCLSID;
-use winapi::shared::ksmedia::KSDATAFORMAT_SUBTYPE_IEEE_FLOAT;
 use winapi::shared::mmreg::WAVEFORMATEX;
 use winapi::shared::winerror::S_FALSE;
 use winapi::shared::winerror::S_OK;
@@ -60,8 +64,14 @@ use winapi::um::winbase::WAIT_OBJECT_0;
 use winapi::Interface;
 use wio::com::ComPtr;
 
+use crate::loopback;
+use crate::stream_info::EndpointFormat;
+use crate::stream_info::StreamRegistration;
+use crate::AudioSampleFormat;
 use crate::AudioSharedFormat;
+use crate::AudioSharedFormatUpdates;
 
+mod capture;
 mod completion_handler;
 mod wave_format;
 
@@ -84,11 +94,13 @@ const ACTIVATE_AUDIO_EVENT_TIMEOUT: Duration = Duration::from_secs(5);
 pub struct WinAudio {
     pub cached_playback_buffer_stream:
         Option<(Arc<Mutex<Box<dyn PlaybackBufferStream>>>, AudioSharedFormat)>,
+    pub(crate) shared_format_updates: AudioSharedFormatUpdates,
 }
 impl WinAudio {
     pub fn new() -> Result<Self, BoxError> {
         Ok(WinAudio {
             cached_playback_buffer_stream: None,
+            shared_format_updates: AudioSharedFormatUpdates::default(),
         })
     }
 
@@ -129,25 +141,68 @@ impl StreamSource for WinAudio {
         let hr = WinAudio::co_init_once_per_thread();
         let _ = check_hresult!(hr, RenderError::from(hr), "Co Initialized failed");
 
-        let playback_buffer_stream: Box<dyn PlaybackBufferStream> =
-            match WinAudioRenderer::new(num_channels, frame_rate, buffer_size) {
-                Ok(renderer) => Box::new(renderer),
-                Err(e) => {
-                    warn!(
-                        "Failed to create WinAudioRenderer. Fallback to NoopStream with error: {}",
-                        e
-                    );
-                    Box::new(NoopStream::new(
-                        num_channels,
-                        SampleFormat::S16LE,
-                        frame_rate,
-                        buffer_size,
-                    ))
-                }
-            };
+        let playback_buffer_stream: Box<dyn PlaybackBufferStream> = match WinAudioRenderer::new(
+            num_channels,
+            frame_rate,
+            buffer_size,
+            // Nothing is told about the format of these streams, so there is no one to tell
+            // it changed either.
+            AudioSharedFormatUpdates::default(),
+        ) {
+            Ok(renderer) => Box::new(renderer),
+            Err(e) => {
+                warn!(
+                    "Failed to create WinAudioRenderer. Fallback to NoopStream with error: {}",
+                    e
+                );
+                Box::new(NoopStream::new(
+                    num_channels,
+                    SampleFormat::S16LE,
+                    frame_rate,
+                    buffer_size,
+                ))
+            }
+        };
 
         Ok((Box::new(NoopStreamControl::new()), playback_buffer_stream))
     }
+
+    // Returns a stream control and a buffer generator object for the default input device. The
+    // stream control object is not used. Silence is captured if the device can't be opened.
+    fn new_capture_stream(
+        &mut self,
+        num_channels: usize,
+        _format: SampleFormat,
+        frame_rate: u32,
+        buffer_size: usize,
+        _effects: &[StreamEffect],
+    ) -> Result<(Box<dyn StreamControl>, Box<dyn CaptureBufferStream>), BoxError> {
+        let hr = WinAudio::co_init_once_per_thread();
+        let _ = check_hresult!(hr, RenderError::from(hr), "Co Initialized failed");
+
+        Ok((
+            Box::new(NoopStreamControl::new()),
+            Box::new(WinAudioCapturer::new(num_channels, frame_rate, buffer_size)),
+        ))
+    }
+
+    fn new_async_capture_stream(
+        &mut self,
+        num_channels: usize,
+        _format: SampleFormat,
+        frame_rate: u32,
+        buffer_size: usize,
+        _effects: &[StreamEffect],
+        _ex: &dyn AudioStreamsExecutor,
+    ) -> Result<(Box<dyn StreamControl>, Box<dyn AsyncCaptureBufferStream>), BoxError> {
+        let hr = WinAudio::co_init_once_per_thread();
+        let _ = check_hresult!(hr, RenderError::from(hr), "Co Initialized failed");
+
+        Ok((
+            Box::new(NoopStreamControl::new()),
+            Box::new(WinAudioCapturer::new(num_channels, frame_rate, buffer_size)),
+        ))
+    }
 }
 
 /// Proxy for a `DeviceRenderer` that handles device invalidated errors by switching to a new
@@ -157,6 +212,8 @@ pub(crate) struct WinAudioRenderer {
     num_channels: usize,
     frame_rate: u32,
     incoming_buffer_size_in_frames: usize,
+    registration: StreamRegistration,
+    shared_format_updates: AudioSharedFormatUpdates,
 }
 
 impl WinAudioRenderer {
@@ -165,6 +222,7 @@ impl WinAudioRenderer {
         num_channels: usize,
         frame_rate: u32,
         incoming_buffer_size_in_frames: usize,
+        shared_format_updates: AudioSharedFormatUpdates,
     ) -> Result<Self, RenderError> {
         let start = std::time::Instant::now();
         let device = DeviceRenderer::new(num_channels, frame_rate, incoming_buffer_size_in_frames)?;
@@ -174,14 +232,33 @@ impl WinAudioRenderer {
             "DeviceRenderer took {}ms to initialize audio.",
             start.elapsed().as_millis()
         );
+        let registration = StreamRegistration::new(
+            num_channels,
+            frame_rate,
+            incoming_buffer_size_in_frames,
+            device.audio_shared_format,
+            Some(device.endpoint_format.clone()),
+        );
         Ok(Self {
             device,
             num_channels,
             frame_rate,                     // guest frame rate
             incoming_buffer_size_in_frames, // from the guest`
+            registration,
+            shared_format_updates,
         })
     }
 
+    // Records and announces that the format of the stream changed.
+    fn renegotiated(&self) {
+        self.registration.renegotiated(
+            self.device.audio_shared_format,
+            Some(self.device.endpoint_format.clone()),
+        );
+        self.shared_format_updates
+            .notify(self.device.audio_shared_format);
+    }
+
     // Drops the existing DeviceRenderer and initializes a new DeviceRenderer for the default
     // device.
     fn reattach_device(&mut self) -> Result<(), RenderError> {
@@ -190,6 +267,7 @@ impl WinAudioRenderer {
             self.frame_rate,
             self.incoming_buffer_size_in_frames,
         )?;
+        self.renegotiated();
         Ok(())
     }
 }
@@ -200,13 +278,26 @@ impl PlaybackBufferStream for WinAudioRenderer {
         const MAX_REATTACH_TRIES: usize = 50;
         for _ in 0..MAX_REATTACH_TRIES {
             match self.device.next_win_buffer() {
-                Ok(_) => return self.device.playback_buffer().map_err(|e| Box::new(e) as _),
+                Ok(_) => {
+                    if self.device.take_underrun() {
+                        self.registration.underrun();
+                    }
+                    return self.device.playback_buffer().map_err(|e| Box::new(e) as _);
+                }
                 // If the audio device was disconnected, set up whatever is now the default device
                 // and loop to try again.
                 Err(RenderError::DeviceInvalidated) => {
                     warn!("Audio device disconnected, switching to new default device");
                     self.reattach_device()?;
                 }
+                // The buffer size no longer matches the audio engine, which happens when its
+                // period changed, e.g. after an exclusive mode application released the device.
+                Err(e @ RenderError::BufferError(_)) => {
+                    if !self.device.update_period()? {
+                        return Err(Box::new(e));
+                    }
+                    self.renegotiated();
+                }
                 Err(e) => return Err(Box::new(e)),
             }
         }
@@ -222,8 +313,13 @@ pub(crate) struct DeviceRenderer {
     audio_client: ComPtr<IAudioClient>,
     win_buffer: *mut *mut u8,
     pub audio_shared_format: AudioSharedFormat,
+    // Mix format of the endpoint before it was modified.
+    pub endpoint_format: EndpointFormat,
     audio_render_client_buffer_frame_count: u32,
     ready_to_read_event: Event,
+    // Whether samples were committed yet, before which the endpoint is expected to be empty.
+    frames_committed: bool,
+    underrun: bool,
 }
 
 impl DeviceRenderer {
@@ -239,7 +335,8 @@ impl DeviceRenderer {
 
         let audio_client = DeviceRenderer::create_audio_client()?;
 
-        let format = DeviceRenderer::get_valid_mix_format(&audio_client)?;
+        let (format, sample_format, endpoint_format) =
+            DeviceRenderer::get_valid_mix_format(&audio_client)?;
 
         // Safe because `audio_client` is initialized
         let hr = unsafe {
@@ -273,18 +370,11 @@ impl DeviceRenderer {
 
         let audio_render_client = DeviceRenderer::create_audio_render_client(&*audio_client)?;
 
-        let mut shared_default_size_in_100nanoseconds: i64 = 0;
-        let mut exclusive_min: i64 = 0;
-        // Safe because `GetDevicePeriod` are taking in intialized valid i64's on the stack created above.
-        unsafe {
-            audio_client.GetDevicePeriod(
-                &mut shared_default_size_in_100nanoseconds,
-                &mut exclusive_min,
-            );
-        };
+        let shared_default_size_in_100nanoseconds =
+            DeviceRenderer::get_shared_device_period(&audio_client)?;
 
-        let shared_audio_engine_period_in_frames = format
-            .get_shared_audio_engine_period_in_frames(shared_default_size_in_100nanoseconds as f64);
+        let shared_audio_engine_period_in_frames =
+            format.get_shared_audio_engine_period_in_frames(shared_default_size_in_100nanoseconds);
 
         if incoming_buffer_size_in_frames % shared_audio_engine_period_in_frames != 0 {
             warn!(
@@ -327,19 +417,125 @@ impl DeviceRenderer {
             audio_client,
             win_buffer: MaybeUninit::uninit().as_mut_ptr(),
             audio_shared_format: format
-                .create_audio_shared_format(shared_audio_engine_period_in_frames),
+                .create_audio_shared_format(sample_format, shared_audio_engine_period_in_frames),
+            endpoint_format,
             audio_render_client_buffer_frame_count,
             ready_to_read_event,
+            frames_committed: false,
+            underrun: false,
         })
     }
 
+    // Returns the default period of the audio engine in shared mode, in 100 nanosecond units.
+    fn get_shared_device_period(audio_client: &ComPtr<IAudioClient>) -> Result<i64, RenderError> {
+        let mut shared_default_size_in_100nanoseconds: i64 = 0;
+        let mut exclusive_min: i64 = 0;
+        // Safe because `GetDevicePeriod` are taking in intialized valid i64's on the stack created above.
+        let hr = unsafe {
+            audio_client.GetDevicePeriod(
+                &mut shared_default_size_in_100nanoseconds,
+                &mut exclusive_min,
+            )
+        };
+        check_hresult!(
+            hr,
+            RenderError::from(hr),
+            "Audio Client GetDevicePeriod() failed."
+        )?;
+        Ok(shared_default_size_in_100nanoseconds)
+    }
+
+    // Queries the audio engine period again and switches to it if it changed by more than a
+    // frame. Returns whether the period was changed.
+    fn update_period(&mut self) -> Result<bool, RenderError> {
+        let period_in_frames = period_in_frames(
+            self.audio_shared_format.frame_rate as u32,
+            DeviceRenderer::get_shared_device_period(&self.audio_client)?,
+        );
+        let current_period_in_frames = self
+            .audio_shared_format
+            .shared_audio_engine_period_in_frames;
+        if period_in_frames.abs_diff(current_period_in_frames) <= 1 {
+            return Ok(false);
+        }
+        if period_in_frames == 0
+            || period_in_frames > self.audio_render_client_buffer_frame_count as usize
+        {
+            warn!(
+                "Audio engine period changed to {} frames, which doesn't fit the {} frames buffer",
+                period_in_frames, self.audio_render_client_buffer_frame_count
+            );
+            return Ok(false);
+        }
+        info!(
+            "Audio engine period changed from {} to {} frames",
+            current_period_in_frames, period_in_frames
+        );
+        self.audio_shared_format
+            .shared_audio_engine_period_in_frames = period_in_frames;
+        Ok(true)
+    }
+
+    // Returns the format to render in and how its samples are encoded, along with the endpoint's
+    // mix format it was derived from. The candidates from `WaveAudioFormat::supported_variants`
+    // are tried in order until the audio engine accepts one.
     fn get_valid_mix_format(
         audio_client: &ComPtr<IAudioClient>,
-    ) -> Result<WaveAudioFormat, RenderError> {
+    ) -> Result<(WaveAudioFormat, AudioSampleFormat, EndpointFormat), RenderError> {
+        let format = Self::get_mix_format(audio_client)?;
+
+        let mut wave_format_details = WaveFormatDetailsProto::new();
+        let mut event_code = MetricEventType::AudioFormatRequestOk;
+        wave_format_details.set_requested(WaveFormatProto::from(&format));
+
+        info!("Printing mix format from `GetMixFormat`:\n{:?}", format);
+        let endpoint_format = EndpointFormat::from(&format);
+
+        let mut hr = S_FALSE;
+        for (sample_format, candidate) in format.supported_variants() {
+            let (candidate_hr, closest_match) = Self::check_format(&*audio_client, &candidate);
+            hr = candidate_hr;
+            if hr == S_OK {
+                let modified_wave_format = WaveFormatProto::from(&candidate);
+                if &modified_wave_format != wave_format_details.get_requested() {
+                    wave_format_details.set_modified(modified_wave_format);
+                    event_code = MetricEventType::AudioFormatModifiedOk;
+                }
+                DeviceRenderer::upload_metrics(wave_format_details, event_code);
+
+                info!("Audio Engine Mix Format Used: \n{:?}", candidate);
+                return Ok((candidate, sample_format, endpoint_format));
+            }
+
+            match closest_match {
+                Some(closest_match) => {
+                    wave_format_details.set_closest_matched(WaveFormatProto::from(&closest_match));
+                    warn!(
+                        "Audio format {:?} not supported, the closest format is:\n{:?}",
+                        sample_format, closest_match
+                    );
+                }
+                None => warn!(
+                    "Audio format {:?} not supported, IsFormatSupported failed with hr: {}",
+                    sample_format, hr
+                ),
+            }
+        }
+        error!("None of the audio formats are supported");
+
+        // Get last error here just incase `upload_metrics` causes an error.
+        let last_error = Error::last();
+        DeviceRenderer::upload_metrics(wave_format_details, MetricEventType::AudioFormatFailed);
+
+        Err(RenderError::WindowsError(hr, last_error))
+    }
+
+    // Returns the format the audio engine mixes the streams of the endpoint of `audio_client` in.
+    fn get_mix_format(audio_client: &ComPtr<IAudioClient>) -> Result<WaveAudioFormat, RenderError> {
         // Safe because `format_ptr` is owned by this unsafe block. `format_ptr` is guarenteed to
         // be not null by the time it reached `WaveAudioFormat::new` (check_hresult! should make
         // sure of that), which is also release the pointer passed in.
-        let mut format = unsafe {
+        unsafe {
             let mut format_ptr: *mut WAVEFORMATEX = std::ptr::null_mut();
             let hr = audio_client.GetMixFormat(&mut format_ptr);
             check_hresult!(
@@ -348,35 +544,16 @@ impl DeviceRenderer {
                 "Failed to retrieve audio engine's shared format"
             )?;
 
-            WaveAudioFormat::new(format_ptr)
-        };
-
-        let mut wave_format_details = WaveFormatDetailsProto::new();
-        let mut event_code = MetricEventType::AudioFormatRequestOk;
-        wave_format_details.set_requested(WaveFormatProto::from(&format));
-
-        info!("Printing mix format from `GetMixFormat`:\n{:?}", format);
-        const BIT_DEPTH: usize = 32;
-        format.modify_mix_format(BIT_DEPTH, KSDATAFORMAT_SUBTYPE_IEEE_FLOAT);
-
-        let modified_wave_format = WaveFormatProto::from(&format);
-        if &modified_wave_format != wave_format_details.get_requested() {
-            wave_format_details.set_modified(modified_wave_format);
-            event_code = MetricEventType::AudioFormatModifiedOk;
+            Ok(WaveAudioFormat::new(format_ptr))
         }
-
-        info!("Audio Engine Mix Format Used: \n{:?}", format);
-        Self::check_format(&*audio_client, &format, wave_format_details, event_code)?;
-
-        Ok(format)
     }
 
+    // Asks the audio engine whether it can render in `format` in shared mode. Returns `S_OK` if it
+    // can, along with the closest format it suggests instead if it returns `S_FALSE`.
     fn check_format(
         audio_client: &IAudioClient,
         format: &WaveAudioFormat,
-        mut wave_format_details: WaveFormatDetailsProto,
-        event_code: MetricEventType,
-    ) -> Result<(), RenderError> {
+    ) -> (i32, Option<WaveAudioFormat>) {
         let mut closest_match_format: *mut WAVEFORMATEX = std::ptr::null_mut();
         // Safe because all values passed into `IsFormatSupport` is owned by us and we will
         // guarentee they won't be dropped and are valid.
@@ -388,32 +565,14 @@ impl DeviceRenderer {
             )
         };
 
-        // If the audio engine does not support the format.
-        if hr != S_OK {
-            if hr == S_FALSE {
-                // Safe because if the `hr` value is `S_FALSE`, then `IsFormatSupported` must've
-                // given us a closest match.
-                let closest_match_enum = unsafe { WaveAudioFormat::new(closest_match_format) };
-                wave_format_details.set_closest_matched(WaveFormatProto::from(&closest_match_enum));
-
-                error!(
-                    "Current audio format not supported, the closest format is:\n{:?}",
-                    closest_match_enum
-                );
-            } else {
-                error!("IsFormatSupported failed with hr: {}", hr);
-            }
-
-            // Get last error here just incase `upload_metrics` causes an error.
-            let last_error = Error::last();
-            DeviceRenderer::upload_metrics(wave_format_details, MetricEventType::AudioFormatFailed);
-
-            Err(RenderError::WindowsError(hr, last_error))
+        let closest_match = if hr == S_FALSE {
+            // Safe because if the `hr` value is `S_FALSE`, then `IsFormatSupported` must've
+            // given us a closest match.
+            Some(unsafe { WaveAudioFormat::new(closest_match_format) })
         } else {
-            DeviceRenderer::upload_metrics(wave_format_details, event_code);
-
-            Ok(())
-        }
+            None
+        };
+        (hr, closest_match)
     }
 
     fn upload_metrics(
@@ -466,9 +625,9 @@ impl DeviceRenderer {
         Ok(())
     }
 
-    // Create the `IAudioClient` which is used to create `IAudioRenderClient` which is used for
-    // audio playback.
-    fn create_audio_client() -> Result<ComPtr<IAudioClient>, RenderError> {
+    // Returns the default device of the console role for `data_flow`, i.e. the default speakers
+    // for `eRender` and the default microphone for `eCapture`.
+    fn default_audio_endpoint(data_flow: EDataFlow) -> Result<ComPtr<IMMDevice>, RenderError> {
         let mut device_enumerator: *mut c_void = null_mut();
 
         // Creates a device enumerator in order to select our default audio device.
@@ -497,7 +656,7 @@ impl DeviceRenderer {
         // Safe because `device_enumerator` is guaranteed to be initialized otherwise this method would've
         // exited
         let hr =
-            unsafe { device_enumerator.GetDefaultAudioEndpoint(eRender, eConsole, &mut device) };
+            unsafe { device_enumerator.GetDefaultAudioEndpoint(data_flow, eConsole, &mut device) };
         check_hresult!(
             hr,
             RenderError::from(hr),
@@ -507,6 +666,15 @@ impl DeviceRenderer {
         // Safe because `device` is guaranteed to be initialized
         let device = unsafe { ComPtr::from_raw(device) };
         Self::print_device_info(&*device)?;
+        Ok(device)
+    }
+
+    // Create the `IAudioClient` which is used to create `IAudioRenderClient` which is used for
+    // audio playback.
+    fn create_audio_client() -> Result<ComPtr<IAudioClient>, RenderError> {
+        // The client is activated through automatic stream routing below rather than through the
+        // device, which is only looked up to log which one is the default.
+        Self::default_audio_endpoint(eRender)?;
 
         // Call Windows API functions to get the `async_op` which will be used to retrieve the
         // AudioClient. More details above function definition.
@@ -699,6 +867,11 @@ impl DeviceRenderer {
                     "Audio Client GetCurrentPadding() failed."
                 )?;
 
+                // The endpoint played everything it was given before we could give it more.
+                if *num_frames_padding == 0 && self.frames_committed {
+                    self.underrun = true;
+                }
+
                 // If the available free frames is less than the frames that are being sent over from the guest, then
                 // we want to only grab the number of frames available.
                 let num_frames_available =
@@ -731,14 +904,17 @@ impl DeviceRenderer {
         Ok(())
     }
 
+    // Returns whether the endpoint ran out of samples since the last call.
+    fn take_underrun(&mut self) -> bool {
+        std::mem::replace(&mut self.underrun, false)
+    }
+
     fn playback_buffer(&mut self) -> Result<PlaybackBuffer, RenderError> {
         if self.win_buffer.is_null() {
             return Err(RenderError::InvalidBuffer);
         }
 
-        let frame_size_bytes = self.audio_shared_format.bit_depth as usize
-            * self.audio_shared_format.channels as usize
-            / 8;
+        let frame_size_bytes = self.audio_shared_format.frame_size_bytes();
 
         // Safe because `win_buffer` is allocated and retrieved from WASAPI. The size requested,
         // which we specified in `next_win_buffer` is exactly
@@ -760,6 +936,21 @@ impl DeviceRenderer {
 impl BufferCommit for DeviceRenderer {
     // Called after buffer from WASAPI is filled. This will allow the audio bytes to be played as sound.
     fn commit(&mut self, nframes: usize) {
+        if !self.win_buffer.is_null() && nframes > 0 {
+            // Safe because `win_buffer` was retrieved from WASAPI with room for
+            // `shared_audio_engine_period_in_frames` frames, and `PlaybackBuffer` doesn't commit
+            // more frames than that.
+            let frames = unsafe {
+                std::slice::from_raw_parts(
+                    *self.win_buffer,
+                    nframes.min(
+                        self.audio_shared_format
+                            .shared_audio_engine_period_in_frames,
+                    ) * self.audio_shared_format.frame_size_bytes(),
+                )
+            };
+            loopback::tee(&self.audio_shared_format, frames);
+        }
         // Safe because `audio_render_client` is initialized and parameters passed
         // into `ReleaseBuffer()` are valid
         unsafe {
@@ -770,6 +961,7 @@ impl BufferCommit for DeviceRenderer {
                 "Audio Render Client ReleaseBuffer() failed"
             );
         }
+        self.frames_committed = true;
     }
 }
 
@@ -800,6 +992,9 @@ pub enum RenderError {
     /// "unknown win audio error HResult: {}, error code: {}"
     #[error("unknown win audio error HResult: {0}, error code: {1}")]
     WindowsError(i32, Error),
+    /// The requested buffer doesn't fit the audio engine's buffer.
+    #[error("win audio buffer error HResult: {0}")]
+    BufferError(i32),
     #[error("buffer pointer is null")]
     InvalidBuffer,
     #[error("playback buffer error: {0}")]
@@ -820,6 +1015,9 @@ impl From<i32> for RenderError {
     fn from(winapi_error_code: i32) -> Self {
         match winapi_error_code {
             AUDCLNT_E_DEVICE_INVALIDATED => Self::DeviceInvalidated,
+            AUDCLNT_E_BUFFER_ERROR | AUDCLNT_E_BUFFER_SIZE_ERROR | AUDCLNT_E_BUFFER_TOO_LARGE => {
+                Self::BufferError(winapi_error_code)
+            }
             _ => Self::WindowsError(winapi_error_code, Error::last()),
         }
     }
@@ -840,6 +1038,7 @@ mod tests {
     use std::thread;
 
     use once_cell::sync::Lazy;
+    use winapi::shared::ksmedia::KSDATAFORMAT_SUBTYPE_IEEE_FLOAT;
     use winapi::shared::mmreg::WAVEFORMATEXTENSIBLE;
     use winapi::shared::mmreg::WAVE_FORMAT_EXTENSIBLE;
     use winapi::shared::winerror::S_OK;
@@ -960,13 +1159,10 @@ mod tests {
         let format = unsafe { WaveAudioFormat::new(format_ptr) };
 
         // Test format from `GetMixFormat`. This should ALWAYS be valid.
-        assert!(DeviceRenderer::check_format(
-            &*audio_client,
-            &format,
-            WaveFormatDetailsProto::new(),
-            MetricEventType::AudioFormatRequestOk,
-        )
-        .is_ok());
+        assert_eq!(
+            DeviceRenderer::check_format(&*audio_client, &format).0,
+            S_OK
+        );
 
         let format = WAVEFORMATEXTENSIBLE {
             Format: WAVEFORMATEX {
@@ -989,13 +1185,10 @@ mod tests {
         let format = unsafe { WaveAudioFormat::new((&format) as *const _ as *mut WAVEFORMATEX) };
 
         // Test valid custom format.
-        assert!(DeviceRenderer::check_format(
-            &*audio_client,
-            &format,
-            WaveFormatDetailsProto::new(),
-            MetricEventType::AudioFormatRequestOk,
-        )
-        .is_ok());
+        assert_eq!(
+            DeviceRenderer::check_format(&*audio_client, &format).0,
+            S_OK
+        );
 
         let format = WAVEFORMATEXTENSIBLE {
             Format: WAVEFORMATEX {
@@ -1019,12 +1212,9 @@ mod tests {
         let format = unsafe { WaveAudioFormat::new((&format) as *const _ as *mut WAVEFORMATEX) };
 
         // Test invalid format
-        assert!(DeviceRenderer::check_format(
-            &*audio_client,
-            &format,
-            WaveFormatDetailsProto::new(),
-            MetricEventType::AudioFormatRequestOk,
-        )
-        .is_err());
+        assert_ne!(
+            DeviceRenderer::check_format(&*audio_client, &format).0,
+            S_OK
+        );
     }
 }
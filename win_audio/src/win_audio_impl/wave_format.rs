@@ -2,10 +2,14 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
+// The formats are packed structs, referencing their fields would be undefined behavior.
+#![deny(unaligned_references)]
+
 use std::convert::TryInto;
 use std::fmt;
 use std::fmt::Debug;
 use std::fmt::Formatter;
+use std::ptr;
 
 use base::warn;
 use metrics::event_details_proto::WaveFormat;
@@ -28,9 +32,13 @@ use winapi::shared::mmreg::WAVEFORMATEX;
 use winapi::shared::mmreg::WAVEFORMATEXTENSIBLE;
 use winapi::shared::mmreg::WAVE_FORMAT_EXTENSIBLE;
 use winapi::shared::mmreg::WAVE_FORMAT_IEEE_FLOAT;
+use winapi::shared::mmreg::WAVE_FORMAT_PCM;
 #[cfg(not(test))]
 use winapi::um::combaseapi::CoTaskMemFree;
 
+use crate::stream_info::EndpointFormat;
+use crate::stream_info::EndpointFormatExtensible;
+use crate::AudioSampleFormat;
 use crate::AudioSharedFormat;
 use crate::MONO_CHANNEL_COUNT;
 use crate::STEREO_CHANNEL_COUNT;
@@ -41,6 +49,7 @@ pub type SubFormatProto = WaveFormat_WaveFormatSubFormat;
 
 /// Wrapper around `WAVEFORMATEX` and `WAVEFORMATEXTENSIBLE` to hide some of the unsafe calls
 /// that could be made.
+#[derive(Clone)]
 pub enum WaveAudioFormat {
     /// Format where channels are capped at 2.
     WaveFormat(WAVEFORMATEX),
@@ -78,11 +87,123 @@ impl WaveAudioFormat {
         result
     }
 
-    pub fn get_num_channels(&self) -> u16 {
+    // `WAVEFORMATEX` and `WAVEFORMATEXTENSIBLE` are `#[repr(packed)]`, so their fields may be
+    // unaligned and must not be referenced. The accessors below read them by value instead.
+
+    /// Returns a copy of the `WAVEFORMATEX` part of the format.
+    fn wave_format(&self) -> WAVEFORMATEX {
+        // Safe because `as_ptr` points to a `WAVEFORMATEX` owned by `self`, which is read without
+        // assuming it is aligned.
+        unsafe { ptr::read_unaligned(self.as_ptr()) }
+    }
+
+    /// Returns a copy of the format if it is a `WAVEFORMATEXTENSIBLE`.
+    fn wave_format_extensible(&self) -> Option<WAVEFORMATEXTENSIBLE> {
         match self {
-            WaveAudioFormat::WaveFormat(wave_format) => wave_format.nChannels,
+            WaveAudioFormat::WaveFormat(_) => None,
             WaveAudioFormat::WaveFormatExtensible(wave_format_extensible) => {
-                wave_format_extensible.Format.nChannels
+                // Safe because the reference points to a valid `WAVEFORMATEXTENSIBLE`, which is
+                // read without assuming it is aligned.
+                Some(unsafe { ptr::read_unaligned(wave_format_extensible) })
+            }
+        }
+    }
+
+    pub fn format_tag(&self) -> u16 {
+        self.wave_format().wFormatTag
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.wave_format().nChannels
+    }
+
+    pub fn samples_per_sec(&self) -> u32 {
+        self.wave_format().nSamplesPerSec
+    }
+
+    pub fn avg_bytes_per_sec(&self) -> u32 {
+        self.wave_format().nAvgBytesPerSec
+    }
+
+    pub fn block_align(&self) -> u16 {
+        self.wave_format().nBlockAlign
+    }
+
+    pub fn bits_per_sample(&self) -> u16 {
+        self.wave_format().wBitsPerSample
+    }
+
+    /// Size in bytes of the extra information following the `WAVEFORMATEX` part.
+    pub fn size_bytes(&self) -> u16 {
+        self.wave_format().cbSize
+    }
+
+    /// Valid bits per sample, only set for `WAVEFORMATEXTENSIBLE` formats.
+    pub fn samples(&self) -> Option<u16> {
+        self.wave_format_extensible().map(|format| format.Samples)
+    }
+
+    /// Only set for `WAVEFORMATEXTENSIBLE` formats.
+    pub fn channel_mask(&self) -> Option<u32> {
+        self.wave_format_extensible()
+            .map(|format| format.dwChannelMask)
+    }
+
+    /// Only set for `WAVEFORMATEXTENSIBLE` formats.
+    pub fn sub_format(&self) -> Option<GUID> {
+        self.wave_format_extensible().map(|format| format.SubFormat)
+    }
+
+    /// Returns the formats to try rendering in, derived from this format, in order of preference:
+    /// 32 bit float, 24 bit PCM in 32 bit containers and 16 bit PCM.
+    ///
+    /// A `WAVEFORMATEX` can't describe samples narrower than their container, so there is no 24 bit
+    /// candidate for it.
+    pub fn supported_variants(&self) -> Vec<(AudioSampleFormat, WaveAudioFormat)> {
+        let mut variants = Vec::new();
+
+        let mut float32 = self.clone();
+        float32.modify_mix_format(32, KSDATAFORMAT_SUBTYPE_IEEE_FLOAT);
+        variants.push((AudioSampleFormat::Float32, float32));
+
+        if let WaveAudioFormat::WaveFormatExtensible(_) = self {
+            let mut pcm24_in_32 = self.clone();
+            pcm24_in_32.modify_mix_format(32, KSDATAFORMAT_SUBTYPE_PCM);
+            if let WaveAudioFormat::WaveFormatExtensible(wave_format_extensible) = &mut pcm24_in_32
+            {
+                wave_format_extensible.Samples = 24;
+            }
+            variants.push((AudioSampleFormat::Pcm24In32, pcm24_in_32));
+        }
+
+        let mut pcm16 = self.clone();
+        pcm16.modify_mix_format(16, KSDATAFORMAT_SUBTYPE_PCM);
+        variants.push((AudioSampleFormat::Pcm16, pcm16));
+
+        variants
+    }
+
+    /// Returns the format to capture in from an endpoint mixing in this format: 16 bit PCM at
+    /// `frame_rate`, which the audio engine converts the endpoint's samples to.
+    ///
+    /// The channels are kept, except that a `WAVEFORMATEX` is limited to stereo.
+    pub fn create_capture_format(&self, frame_rate: u32) -> WaveAudioFormat {
+        let mut format = self.clone();
+        format.modify_mix_format(16, KSDATAFORMAT_SUBTYPE_PCM);
+        format.set_samples_per_sec(frame_rate);
+        format
+    }
+
+    fn set_samples_per_sec(&mut self, samples_per_sec: u32) {
+        let avg_bytes_per_sec = self.block_align() as u32 * samples_per_sec;
+        match self {
+            WaveAudioFormat::WaveFormat(wave_format) => {
+                wave_format.nSamplesPerSec = samples_per_sec;
+                wave_format.nAvgBytesPerSec = avg_bytes_per_sec;
+            }
+            WaveAudioFormat::WaveFormatExtensible(wave_format_extensible) => {
+                wave_format_extensible.Format.nSamplesPerSec = samples_per_sec;
+                wave_format_extensible.Format.nAvgBytesPerSec = avg_bytes_per_sec;
             }
         }
     }
@@ -91,7 +212,20 @@ impl WaveAudioFormat {
     // Currently it should only modify the bit_depth if it's != 32 and the data format if it's not
     // float.
     pub fn modify_mix_format(&mut self, target_bit_depth: usize, ks_data_format: GUID) {
-        let default_num_channels = self.get_num_channels();
+        let default_num_channels = self.channels();
+        let bits_per_sample = self.bits_per_sample();
+        let samples_per_sec = self.samples_per_sec();
+        let format_tag = self.format_tag();
+        let is_target_sub_format = self.sub_format().map_or(false, |sub_format| {
+            IsEqualGUID(&sub_format, &ks_data_format)
+        });
+        let target_bit_depth = target_bit_depth as u16;
+        let is_target_valid_bits = self.samples() == Some(target_bit_depth);
+        let target_format_tag = if IsEqualGUID(&ks_data_format, &KSDATAFORMAT_SUBTYPE_PCM) {
+            WAVE_FORMAT_PCM
+        } else {
+            WAVE_FORMAT_IEEE_FLOAT
+        };
 
         fn calc_avg_bytes_per_sec(num_channels: u16, bit_depth: u16, samples_per_sec: u32) -> u32 {
             num_channels as u32 * (bit_depth as u32 / 8) * samples_per_sec
@@ -107,65 +241,50 @@ impl WaveAudioFormat {
                     warn!("WAVEFORMATEX shouldn't have >2 channels.");
                 }
 
-                // Force the format to be the only supported format (32 bit float)
-                if wave_format.wBitsPerSample != target_bit_depth as u16
-                    || wave_format.wFormatTag != WAVE_FORMAT_IEEE_FLOAT
-                {
-                    wave_format.wFormatTag = WAVE_FORMAT_IEEE_FLOAT;
-                    wave_format.nChannels =
+                if bits_per_sample != target_bit_depth || format_tag != target_format_tag {
+                    let n_channels =
                         std::cmp::min(STEREO_CHANNEL_COUNT as u16, default_num_channels);
-                    wave_format.wBitsPerSample = target_bit_depth as u16;
-                    wave_format.nAvgBytesPerSec = calc_avg_bytes_per_sec(
-                        wave_format.nChannels,
-                        wave_format.wBitsPerSample,
-                        wave_format.nSamplesPerSec,
-                    );
-                    wave_format.nBlockAlign =
-                        calc_block_align(wave_format.nChannels, wave_format.wBitsPerSample);
+                    wave_format.wFormatTag = target_format_tag;
+                    wave_format.nChannels = n_channels;
+                    wave_format.wBitsPerSample = target_bit_depth;
+                    wave_format.nAvgBytesPerSec =
+                        calc_avg_bytes_per_sec(n_channels, target_bit_depth, samples_per_sec);
+                    wave_format.nBlockAlign = calc_block_align(n_channels, target_bit_depth);
                 }
             }
             WaveAudioFormat::WaveFormatExtensible(wave_format_extensible) => {
-                // WAVE_FORMAT_EXTENSIBLE uses the #[repr(packed)] flag so the compiler might
-                // unalign the fields. Thus, the fields will be copied to a local variable to
-                // prevent segfaults. For more information:
-                // https://github.com/rust-lang/rust/issues/46043
-                let sub_format = wave_format_extensible.SubFormat;
-
-                if wave_format_extensible.Format.wBitsPerSample != target_bit_depth as u16
-                    || !IsEqualGUID(&sub_format, &ks_data_format)
+                if bits_per_sample != target_bit_depth
+                    || !is_target_valid_bits
+                    || !is_target_sub_format
                 {
+                    let n_channels = default_num_channels;
                     // wFormatTag won't be changed
-                    wave_format_extensible.Format.nChannels = default_num_channels;
-                    wave_format_extensible.Format.wBitsPerSample = target_bit_depth as u16;
+                    wave_format_extensible.Format.nChannels = n_channels;
+                    wave_format_extensible.Format.wBitsPerSample = target_bit_depth;
                     // nSamplesPerSec should stay the same
                     // Calculated with a bit depth of 32bits
-                    wave_format_extensible.Format.nAvgBytesPerSec = calc_avg_bytes_per_sec(
-                        wave_format_extensible.Format.nChannels,
-                        wave_format_extensible.Format.wBitsPerSample,
-                        wave_format_extensible.Format.nSamplesPerSec,
-                    );
-                    wave_format_extensible.Format.nBlockAlign = calc_block_align(
-                        wave_format_extensible.Format.nChannels,
-                        wave_format_extensible.Format.wBitsPerSample,
-                    );
+                    wave_format_extensible.Format.nAvgBytesPerSec =
+                        calc_avg_bytes_per_sec(n_channels, target_bit_depth, samples_per_sec);
+                    wave_format_extensible.Format.nBlockAlign =
+                        calc_block_align(n_channels, target_bit_depth);
                     // 22 is the size typically used when the format tag is WAVE_FORMAT_EXTENSIBLE.
                     // Since the `Initialize` syscall takes in a WAVEFORMATEX, this tells Windows
                     // how many bytes are left after the `Format` field
                     // (ie. Samples, dwChannelMask, SubFormat) so that it can cast to
                     // WAVEFORMATEXTENSIBLE safely.
                     wave_format_extensible.Format.cbSize = 22;
-                    wave_format_extensible.Samples = target_bit_depth as u16;
-                    let n_channels = wave_format_extensible.Format.nChannels;
+                    wave_format_extensible.Samples = target_bit_depth;
                     // The channel masks are defined here:
                     // https://docs.microsoft.com/en-us/windows/win32/api/mmreg/ns-mmreg-waveformatextensible#remarks
-                    wave_format_extensible.dwChannelMask = match n_channels {
-                        STEREO_CHANNEL_COUNT => SPEAKER_FRONT_LEFT | SPEAKER_FRONT_RIGHT,
-                        MONO_CHANNEL_COUNT => SPEAKER_FRONT_CENTER,
-                        _ => {
-                            // Don't change channel mask if it's >2 channels.
-                            wave_format_extensible.dwChannelMask
-                        }
+                    let channel_mask = match n_channels {
+                        STEREO_CHANNEL_COUNT => Some(SPEAKER_FRONT_LEFT | SPEAKER_FRONT_RIGHT),
+                        MONO_CHANNEL_COUNT => Some(SPEAKER_FRONT_CENTER),
+                        // Don't change channel mask if it's >2 channels.
+                        _ => None,
                     };
+                    if let Some(channel_mask) = channel_mask {
+                        wave_format_extensible.dwChannelMask = channel_mask;
+                    }
                     wave_format_extensible.SubFormat = ks_data_format;
                 }
             }
@@ -183,43 +302,26 @@ impl WaveAudioFormat {
 
     pub fn get_shared_audio_engine_period_in_frames(
         &self,
-        shared_default_size_in_100nanoseconds: f64,
+        shared_default_size_in_100nanoseconds: i64,
     ) -> usize {
-        // a 100 nanosecond unit is 1 * 10^-7 seconds
-        //
-        // To convert a 100nanoseconds value to # of frames in a period, we multiple by the
-        // frame rate (nSamplesPerSec. Sample rate == Frame rate) and then divide by 10000000
-        // in order to convert 100nanoseconds to seconds.
-        let samples_per_sec = match self {
-            WaveAudioFormat::WaveFormat(wave_format) => wave_format.nSamplesPerSec,
-            WaveAudioFormat::WaveFormatExtensible(wave_format_extensible) => {
-                wave_format_extensible.Format.nSamplesPerSec
-            }
-        };
-
-        ((samples_per_sec as f64 * shared_default_size_in_100nanoseconds as f64) / 10000000.0)
-            .ceil() as usize
+        period_in_frames(
+            self.samples_per_sec(),
+            shared_default_size_in_100nanoseconds,
+        )
     }
 
     pub fn create_audio_shared_format(
         &self,
+        sample_format: AudioSampleFormat,
         shared_audio_engine_period_in_frames: usize,
     ) -> AudioSharedFormat {
-        match self {
-            WaveAudioFormat::WaveFormat(wave_format) => AudioSharedFormat {
-                bit_depth: wave_format.wBitsPerSample as usize,
-                frame_rate: wave_format.nSamplesPerSec as usize,
-                shared_audio_engine_period_in_frames,
-                channels: wave_format.nChannels as usize,
-                channel_mask: None,
-            },
-            WaveAudioFormat::WaveFormatExtensible(wave_format_extensible) => AudioSharedFormat {
-                bit_depth: wave_format_extensible.Format.wBitsPerSample as usize,
-                frame_rate: wave_format_extensible.Format.nSamplesPerSec as usize,
-                shared_audio_engine_period_in_frames,
-                channels: wave_format_extensible.Format.nChannels as usize,
-                channel_mask: Some(wave_format_extensible.dwChannelMask),
-            },
+        AudioSharedFormat {
+            bit_depth: self.bits_per_sample() as usize,
+            sample_format,
+            frame_rate: self.samples_per_sec() as usize,
+            shared_audio_engine_period_in_frames,
+            channels: self.channels() as usize,
+            channel_mask: self.channel_mask(),
         }
     }
 
@@ -249,46 +351,28 @@ impl WaveAudioFormat {
 
 impl Debug for WaveAudioFormat {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        let res = match self {
-            WaveAudioFormat::WaveFormat(wave_format) => {
-                format!(
-                    "wFormatTag: {}, \nnChannels: {}, \nnSamplesPerSec: {}, \nnAvgBytesPerSec: \
-                    {}, \nnBlockAlign: {}, \nwBitsPerSample: {}, \ncbSize: {}",
-                    { wave_format.wFormatTag },
-                    { wave_format.nChannels },
-                    { wave_format.nSamplesPerSec },
-                    { wave_format.nAvgBytesPerSec },
-                    { wave_format.nBlockAlign },
-                    { wave_format.wBitsPerSample },
-                    { wave_format.cbSize },
-                )
-            }
-            WaveAudioFormat::WaveFormatExtensible(wave_format_extensible) => {
-                let audio_engine_format = format!(
-                    "wFormatTag: {}, \nnChannels: {}, \nnSamplesPerSec: {}, \nnAvgBytesPerSec: \
-                    {}, \nnBlockAlign: {}, \nwBitsPerSample: {}, \ncbSize: {}",
-                    { wave_format_extensible.Format.wFormatTag },
-                    { wave_format_extensible.Format.nChannels },
-                    { wave_format_extensible.Format.nSamplesPerSec },
-                    { wave_format_extensible.Format.nAvgBytesPerSec },
-                    { wave_format_extensible.Format.nBlockAlign },
-                    { wave_format_extensible.Format.wBitsPerSample },
-                    { wave_format_extensible.Format.cbSize },
-                );
-
-                let subformat = wave_format_extensible.SubFormat;
+        let audio_engine_format = format!(
+            "wFormatTag: {}, \nnChannels: {}, \nnSamplesPerSec: {}, \nnAvgBytesPerSec: \
+            {}, \nnBlockAlign: {}, \nwBitsPerSample: {}, \ncbSize: {}",
+            self.format_tag(),
+            self.channels(),
+            self.samples_per_sec(),
+            self.avg_bytes_per_sec(),
+            self.block_align(),
+            self.bits_per_sample(),
+            self.size_bytes(),
+        );
 
-                if !IsEqualGUID(
-                    &{ wave_format_extensible.SubFormat },
-                    &KSDATAFORMAT_SUBTYPE_IEEE_FLOAT,
-                ) {
+        let res = match (self.samples(), self.channel_mask(), self.sub_format()) {
+            (Some(samples), Some(channel_mask), Some(subformat)) => {
+                if !IsEqualGUID(&subformat, &KSDATAFORMAT_SUBTYPE_IEEE_FLOAT) {
                     warn!("Audio Engine format is NOT IEEE FLOAT");
                 }
 
                 let audio_engine_extensible_format = format!(
                     "\nSamples: {}, \ndwChannelMask: {}, \nSubFormat: {}-{}-{}-{:?}",
-                    { wave_format_extensible.Samples },
-                    { wave_format_extensible.dwChannelMask },
+                    samples,
+                    channel_mask,
                     subformat.Data1,
                     subformat.Data2,
                     subformat.Data3,
@@ -297,6 +381,7 @@ impl Debug for WaveAudioFormat {
 
                 format!("{}{}", audio_engine_format, audio_engine_extensible_format)
             }
+            _ => audio_engine_format,
         };
         write!(f, "{}", res)
     }
@@ -334,36 +419,14 @@ impl PartialEq for WaveAudioFormat {
                 .any(|ord| ord != std::cmp::Ordering::Equal)
         }
 
-        match self {
-            WaveAudioFormat::WaveFormat(wave_format) => match other {
-                WaveAudioFormat::WaveFormat(other_wave_format) => {
-                    if wave_format.cbSize != other_wave_format.cbSize {
-                        return false;
-                    }
-                    are_formats_same(
-                        wave_format as *const _ as *const u8,
-                        other_wave_format as *const _ as *const u8,
-                        wave_format.cbSize as usize,
-                    )
-                }
-                WaveAudioFormat::WaveFormatExtensible(_) => unreachable!(),
-            },
-            WaveAudioFormat::WaveFormatExtensible(wave_format_extensible) => match other {
-                WaveAudioFormat::WaveFormatExtensible(other_wave_format_extensible) => {
-                    if wave_format_extensible.Format.cbSize
-                        != other_wave_format_extensible.Format.cbSize
-                    {
-                        return false;
-                    }
-                    are_formats_same(
-                        wave_format_extensible as *const _ as *const u8,
-                        other_wave_format_extensible as *const _ as *const u8,
-                        wave_format_extensible.Format.cbSize as usize,
-                    )
-                }
-                WaveAudioFormat::WaveFormat(_) => unreachable!(),
-            },
+        if self.size_bytes() != other.size_bytes() {
+            return false;
         }
+        are_formats_same(
+            self.as_ptr() as *const u8,
+            other.as_ptr() as *const u8,
+            self.size_bytes() as usize,
+        )
     }
 }
 
@@ -371,58 +434,51 @@ impl From<&WaveAudioFormat> for WaveFormatProto {
     fn from(format: &WaveAudioFormat) -> WaveFormatProto {
         let mut wave_format_proto = WaveFormatProto::new();
 
-        match format {
-            WaveAudioFormat::WaveFormat(wave_format) => {
-                wave_format_proto.set_format_tag(wave_format.wFormatTag.into());
-                wave_format_proto.set_channels(wave_format.nChannels.into());
-                wave_format_proto.set_samples_per_sec(
-                    wave_format
-                        .nSamplesPerSec
-                        .try_into()
-                        .expect("Failed to cast nSamplesPerSec to i32"),
-                );
-                wave_format_proto.set_avg_bytes_per_sec(
-                    wave_format
-                        .nAvgBytesPerSec
-                        .try_into()
-                        .expect("Failed to cast nAvgBytesPerSec"),
-                );
-                wave_format_proto.set_block_align(wave_format.nBlockAlign.into());
-                wave_format_proto.set_bits_per_sample(wave_format.wBitsPerSample.into());
-                wave_format_proto.set_size_bytes(wave_format.cbSize.into());
-            }
-            WaveAudioFormat::WaveFormatExtensible(wave_format_extensible) => {
-                wave_format_proto.set_format_tag(wave_format_extensible.Format.wFormatTag.into());
-                wave_format_proto.set_channels(wave_format_extensible.Format.nChannels.into());
-                wave_format_proto.set_samples_per_sec(
-                    wave_format_extensible
-                        .Format
-                        .nSamplesPerSec
-                        .try_into()
-                        .expect("Failed to cast nSamplesPerSec to i32"),
-                );
-                wave_format_proto.set_avg_bytes_per_sec(
-                    wave_format_extensible
-                        .Format
-                        .nAvgBytesPerSec
-                        .try_into()
-                        .expect("Failed to cast nAvgBytesPerSec"),
-                );
-                wave_format_proto.set_block_align(wave_format_extensible.Format.nBlockAlign.into());
-                wave_format_proto
-                    .set_bits_per_sample(wave_format_extensible.Format.wBitsPerSample.into());
-                wave_format_proto.set_size_bytes(wave_format_extensible.Format.cbSize.into());
-                wave_format_proto.set_samples(wave_format_extensible.Samples.into());
-                wave_format_proto.set_channel_mask(wave_format_extensible.dwChannelMask.into());
-                let sub_format = wave_format_extensible.SubFormat;
-                wave_format_proto.set_sub_format(GuidWrapper(&sub_format).into());
-            }
+        wave_format_proto.set_format_tag(format.format_tag().into());
+        wave_format_proto.set_channels(format.channels().into());
+        wave_format_proto.set_samples_per_sec(
+            format
+                .samples_per_sec()
+                .try_into()
+                .expect("Failed to cast nSamplesPerSec to i32"),
+        );
+        wave_format_proto.set_avg_bytes_per_sec(
+            format
+                .avg_bytes_per_sec()
+                .try_into()
+                .expect("Failed to cast nAvgBytesPerSec"),
+        );
+        wave_format_proto.set_block_align(format.block_align().into());
+        wave_format_proto.set_bits_per_sample(format.bits_per_sample().into());
+        wave_format_proto.set_size_bytes(format.size_bytes().into());
+        if let Some(samples) = format.samples() {
+            wave_format_proto.set_samples(samples.into());
+        }
+        if let Some(channel_mask) = format.channel_mask() {
+            wave_format_proto.set_channel_mask(channel_mask.into());
+        }
+        if let Some(sub_format) = format.sub_format() {
+            wave_format_proto.set_sub_format(GuidWrapper(&sub_format).into());
         }
 
         wave_format_proto
     }
 }
 
+/// Converts a period in 100 nanosecond units to a number of frames at `frame_rate`, rounding up
+/// so a period always has room for all its samples.
+pub fn period_in_frames(frame_rate: u32, period_in_100nanoseconds: i64) -> usize {
+    // a 100 nanosecond unit is 1 * 10^-7 seconds
+    //
+    // To convert a 100nanoseconds value to # of frames in a period, we multiple by the
+    // frame rate (nSamplesPerSec. Sample rate == Frame rate) and then divide by 10000000
+    // in order to convert 100nanoseconds to seconds.
+    const UNITS_PER_SECOND: u64 = 10_000_000;
+    let period_in_100nanoseconds = period_in_100nanoseconds.max(0) as u64;
+    ((frame_rate as u64 * period_in_100nanoseconds + UNITS_PER_SECOND - 1) / UNITS_PER_SECOND)
+        as usize
+}
+
 struct GuidWrapper<'a>(&'a GUID);
 
 impl<'a> From<GuidWrapper<'a>> for SubFormatProto {
@@ -450,6 +506,61 @@ impl<'a> From<GuidWrapper<'a>> for SubFormatProto {
     }
 }
 
+impl From<&WaveAudioFormat> for EndpointFormat {
+    fn from(format: &WaveAudioFormat) -> EndpointFormat {
+        let extensible = match (format.samples(), format.channel_mask(), format.sub_format()) {
+            (Some(samples), Some(channel_mask), Some(sub_format)) => {
+                Some(EndpointFormatExtensible {
+                    samples,
+                    channel_mask,
+                    sub_format: sub_format_name(&sub_format),
+                })
+            }
+            _ => None,
+        };
+        EndpointFormat {
+            format_tag: format.format_tag(),
+            channels: format.channels(),
+            samples_per_sec: format.samples_per_sec(),
+            avg_bytes_per_sec: format.avg_bytes_per_sec(),
+            block_align: format.block_align(),
+            bits_per_sample: format.bits_per_sample(),
+            size_bytes: format.size_bytes(),
+            extensible,
+        }
+    }
+}
+
+fn sub_format_name(guid: &GUID) -> String {
+    let known = [
+        (&KSDATAFORMAT_SUBTYPE_ANALOG, "ANALOG"),
+        (&KSDATAFORMAT_SUBTYPE_PCM, "PCM"),
+        (&KSDATAFORMAT_SUBTYPE_IEEE_FLOAT, "IEEE_FLOAT"),
+        (&KSDATAFORMAT_SUBTYPE_DRM, "DRM"),
+        (&KSDATAFORMAT_SUBTYPE_ALAW, "ALAW"),
+        (&KSDATAFORMAT_SUBTYPE_MULAW, "MULAW"),
+        (&KSDATAFORMAT_SUBTYPE_ADPCM, "ADPCM"),
+        (&KSDATAFORMAT_SUBTYPE_MPEG, "MPEG"),
+    ];
+    match known.iter().find(|(known, _)| IsEqualGUID(guid, known)) {
+        Some((_, name)) => name.to_string(),
+        None => format!(
+            "{:08x}-{:04x}-{:04x}-{:02x}{:02x}-{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}",
+            guid.Data1,
+            guid.Data2,
+            guid.Data3,
+            guid.Data4[0],
+            guid.Data4[1],
+            guid.Data4[2],
+            guid.Data4[3],
+            guid.Data4[4],
+            guid.Data4[5],
+            guid.Data4[6],
+            guid.Data4[7],
+        ),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use winapi::shared::ksmedia::KSDATAFORMAT_SUBTYPE_PCM;
@@ -931,13 +1042,16 @@ mod tests {
         };
 
         // The period will most likely never be 123, but this is ok for testing.
-        let audio_shared_format =
-            format.create_audio_shared_format(/* shared_audio_engine_period_in_frames= */ 123);
+        let audio_shared_format = format.create_audio_shared_format(
+            AudioSampleFormat::Pcm16,
+            /* shared_audio_engine_period_in_frames= */ 123,
+        );
 
         assert_eq!(
             audio_shared_format.bit_depth,
             wave_format.wBitsPerSample as usize
         );
+        assert_eq!(audio_shared_format.sample_format, AudioSampleFormat::Pcm16);
         assert_eq!(audio_shared_format.channels, wave_format.nChannels as usize);
         assert_eq!(
             audio_shared_format.frame_rate,
@@ -973,8 +1087,10 @@ mod tests {
         };
 
         // The period will most likely never be 123, but this is ok for testing.
-        let audio_shared_format =
-            format.create_audio_shared_format(/* shared_audio_engine_period_in_frames= */ 123);
+        let audio_shared_format = format.create_audio_shared_format(
+            AudioSampleFormat::Float32,
+            /* shared_audio_engine_period_in_frames= */ 123,
+        );
 
         assert_eq!(
             audio_shared_format.bit_depth,
@@ -1070,4 +1186,361 @@ mod tests {
 
         assert_eq!(wave_format_proto, expected);
     }
+    #[test]
+    fn test_wave_format_extensible_to_endpoint_format_conversion() {
+        let wave_format_extensible = WAVEFORMATEXTENSIBLE {
+            Format: WAVEFORMATEX {
+                wFormatTag: WAVE_FORMAT_EXTENSIBLE,
+                nChannels: 2,
+                nSamplesPerSec: 48000,
+                nAvgBytesPerSec: 8 * 48000,
+                nBlockAlign: 8,
+                wBitsPerSample: 32,
+                cbSize: 22,
+            },
+            Samples: 32,
+            dwChannelMask: SPEAKER_FRONT_LEFT | SPEAKER_FRONT_RIGHT,
+            SubFormat: KSDATAFORMAT_SUBTYPE_IEEE_FLOAT,
+        };
+
+        // Safe because we can convert a struct to a pointer declared above. Also that means the
+        // pointer can be safely deferenced.
+        let wave_audio_format = unsafe {
+            WaveAudioFormat::new((&wave_format_extensible) as *const _ as *mut WAVEFORMATEX)
+        };
+
+        assert_eq!(
+            EndpointFormat::from(&wave_audio_format),
+            EndpointFormat {
+                format_tag: WAVE_FORMAT_EXTENSIBLE,
+                channels: 2,
+                samples_per_sec: 48000,
+                avg_bytes_per_sec: 8 * 48000,
+                block_align: 8,
+                bits_per_sample: 32,
+                size_bytes: 22,
+                extensible: Some(EndpointFormatExtensible {
+                    samples: 32,
+                    channel_mask: SPEAKER_FRONT_LEFT | SPEAKER_FRONT_RIGHT,
+                    sub_format: "IEEE_FLOAT".to_string(),
+                }),
+            }
+        );
+    }
+
+    #[test]
+    fn test_unknown_sub_format_name() {
+        let guid = GUID {
+            Data1: 0x12345678,
+            Data2: 0x9abc,
+            Data3: 0xdef0,
+            Data4: [0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef],
+        };
+        assert_eq!(
+            sub_format_name(&guid),
+            "12345678-9abc-def0-0123-456789abcdef"
+        );
+    }
+    #[test]
+    fn test_accessors_on_misaligned_format() {
+        let wave_format_extensible = WAVEFORMATEXTENSIBLE {
+            Format: WAVEFORMATEX {
+                wFormatTag: WAVE_FORMAT_EXTENSIBLE,
+                nChannels: 2,
+                nSamplesPerSec: 44100,
+                nAvgBytesPerSec: 8 * 44100,
+                nBlockAlign: 8,
+                wBitsPerSample: 32,
+                cbSize: 22,
+            },
+            Samples: 24,
+            dwChannelMask: SPEAKER_FRONT_LEFT | SPEAKER_FRONT_RIGHT,
+            SubFormat: KSDATAFORMAT_SUBTYPE_PCM,
+        };
+
+        // Copy the format to an odd offset so none of its multi-byte fields are aligned.
+        let mut bytes = vec![0u8; std::mem::size_of::<WAVEFORMATEXTENSIBLE>() + 1];
+        // Safe because `bytes` has room for the format after its first byte, and the format is
+        // written without assuming it is aligned.
+        let format_ptr = unsafe {
+            let format_ptr = bytes.as_mut_ptr().add(1) as *mut WAVEFORMATEXTENSIBLE;
+            ptr::write_unaligned(format_ptr, wave_format_extensible);
+            format_ptr as *mut WAVEFORMATEX
+        };
+        // Safe because `format_ptr` points to the format written above.
+        let mut wave_audio_format = unsafe { WaveAudioFormat::new(format_ptr) };
+
+        assert_eq!(wave_audio_format.format_tag(), WAVE_FORMAT_EXTENSIBLE);
+        assert_eq!(wave_audio_format.channels(), 2);
+        assert_eq!(wave_audio_format.samples_per_sec(), 44100);
+        assert_eq!(wave_audio_format.avg_bytes_per_sec(), 8 * 44100);
+        assert_eq!(wave_audio_format.block_align(), 8);
+        assert_eq!(wave_audio_format.bits_per_sample(), 32);
+        assert_eq!(wave_audio_format.size_bytes(), 22);
+        assert_eq!(wave_audio_format.samples(), Some(24));
+        assert_eq!(
+            wave_audio_format.channel_mask(),
+            Some(SPEAKER_FRONT_LEFT | SPEAKER_FRONT_RIGHT)
+        );
+        assert!(IsEqualGUID(
+            &wave_audio_format.sub_format().unwrap(),
+            &KSDATAFORMAT_SUBTYPE_PCM
+        ));
+
+        wave_audio_format.modify_mix_format(32, KSDATAFORMAT_SUBTYPE_IEEE_FLOAT);
+        assert_eq!(wave_audio_format.samples(), Some(32));
+        assert!(IsEqualGUID(
+            &wave_audio_format.sub_format().unwrap(),
+            &KSDATAFORMAT_SUBTYPE_IEEE_FLOAT
+        ));
+    }
+    #[test]
+    fn test_supported_variants_wave_format_ex() {
+        let format = WAVEFORMATEX {
+            wFormatTag: WAVE_FORMAT_PCM,
+            nChannels: 2,
+            nSamplesPerSec: 44100,
+            nAvgBytesPerSec: 4 * 44100,
+            nBlockAlign: 4,
+            wBitsPerSample: 16,
+            cbSize: 0,
+        };
+
+        // Safe because we can convert a struct to a pointer declared above. Also that means the
+        // pointer can be safely deferenced.
+        let format =
+            unsafe { WaveAudioFormat::new((&format) as *const WAVEFORMATEX as *mut WAVEFORMATEX) };
+
+        let variants = format.supported_variants();
+        let sample_formats: Vec<_> = variants.iter().map(|(format, _)| *format).collect();
+        // There is no 24 bit variant, since a WAVEFORMATEX can't describe it.
+        assert_eq!(
+            sample_formats,
+            [AudioSampleFormat::Float32, AudioSampleFormat::Pcm16]
+        );
+
+        let float32 = &variants[0].1;
+        assert_eq!(float32.format_tag(), WAVE_FORMAT_IEEE_FLOAT);
+        assert_eq!(float32.bits_per_sample(), 32);
+        assert_eq!(float32.block_align(), 8);
+        assert_eq!(float32.avg_bytes_per_sec(), 8 * 44100);
+
+        let pcm16 = &variants[1].1;
+        assert_eq!(pcm16.format_tag(), WAVE_FORMAT_PCM);
+        assert_eq!(pcm16.bits_per_sample(), 16);
+        assert_eq!(pcm16.block_align(), 4);
+        assert_eq!(pcm16.avg_bytes_per_sec(), 4 * 44100);
+
+        for (_, variant) in &variants {
+            assert_eq!(variant.channels(), 2);
+            assert_eq!(variant.samples_per_sec(), 44100);
+            assert_eq!(variant.size_bytes(), 0);
+        }
+        // The device's format is left alone.
+        assert_eq!(format.format_tag(), WAVE_FORMAT_PCM);
+        assert_eq!(format.bits_per_sample(), 16);
+    }
+
+    #[test]
+    fn test_supported_variants_wave_format_extensible() {
+        let format = WAVEFORMATEXTENSIBLE {
+            Format: WAVEFORMATEX {
+                wFormatTag: WAVE_FORMAT_EXTENSIBLE,
+                nChannels: 6,
+                nSamplesPerSec: 48000,
+                nAvgBytesPerSec: 24 * 48000,
+                nBlockAlign: 24,
+                wBitsPerSample: 32,
+                cbSize: 22,
+            },
+            Samples: 32,
+            dwChannelMask: SPEAKER_FRONT_LEFT
+                | SPEAKER_FRONT_RIGHT
+                | SPEAKER_FRONT_CENTER
+                | SPEAKER_LOW_FREQUENCY
+                | SPEAKER_BACK_LEFT
+                | SPEAKER_BACK_RIGHT,
+            SubFormat: KSDATAFORMAT_SUBTYPE_IEEE_FLOAT,
+        };
+
+        // Safe because `GetMixFormat` casts `WAVEFORMATEXTENSIBLE` into a `WAVEFORMATEX` like so.
+        let format = unsafe {
+            WaveAudioFormat::new((&format) as *const WAVEFORMATEXTENSIBLE as *mut WAVEFORMATEX)
+        };
+
+        let variants = format.supported_variants();
+        let sample_formats: Vec<_> = variants.iter().map(|(format, _)| *format).collect();
+        assert_eq!(
+            sample_formats,
+            [
+                AudioSampleFormat::Float32,
+                AudioSampleFormat::Pcm24In32,
+                AudioSampleFormat::Pcm16
+            ]
+        );
+
+        // The first candidate is the device's format, since it already is 32 bit float.
+        assert_eq!(variants[0].1, format);
+
+        let pcm24_in_32 = &variants[1].1;
+        assert_eq!(pcm24_in_32.bits_per_sample(), 32);
+        assert_eq!(pcm24_in_32.samples(), Some(24));
+        assert_eq!(pcm24_in_32.block_align(), 24);
+        assert_eq!(pcm24_in_32.avg_bytes_per_sec(), 24 * 48000);
+        assert!(IsEqualGUID(
+            &pcm24_in_32.sub_format().unwrap(),
+            &KSDATAFORMAT_SUBTYPE_PCM
+        ));
+
+        let pcm16 = &variants[2].1;
+        assert_eq!(pcm16.bits_per_sample(), 16);
+        assert_eq!(pcm16.samples(), Some(16));
+        assert_eq!(pcm16.block_align(), 12);
+        assert_eq!(pcm16.avg_bytes_per_sec(), 12 * 48000);
+        assert!(IsEqualGUID(
+            &pcm16.sub_format().unwrap(),
+            &KSDATAFORMAT_SUBTYPE_PCM
+        ));
+
+        for (_, variant) in &variants {
+            assert_eq!(variant.format_tag(), WAVE_FORMAT_EXTENSIBLE);
+            assert_eq!(variant.channels(), 6);
+            assert_eq!(variant.samples_per_sec(), 48000);
+            assert_eq!(variant.size_bytes(), 22);
+            // The channel mask of a >2 channel format is kept.
+            assert_eq!(variant.channel_mask(), format.channel_mask());
+        }
+    }
+
+    #[test]
+    fn test_create_capture_format_wave_format_ex() {
+        let format = WAVEFORMATEX {
+            wFormatTag: WAVE_FORMAT_IEEE_FLOAT,
+            nChannels: 4,
+            nSamplesPerSec: 96000,
+            nAvgBytesPerSec: 16 * 96000,
+            nBlockAlign: 16,
+            wBitsPerSample: 32,
+            cbSize: 0,
+        };
+
+        // Safe because we can convert a struct to a pointer declared above. Also that means the
+        // pointer can be safely deferenced.
+        let format =
+            unsafe { WaveAudioFormat::new((&format) as *const WAVEFORMATEX as *mut WAVEFORMATEX) };
+
+        let capture_format = format.create_capture_format(48000);
+        assert_eq!(capture_format.format_tag(), WAVE_FORMAT_PCM);
+        assert_eq!(capture_format.channels(), 2);
+        assert_eq!(capture_format.bits_per_sample(), 16);
+        assert_eq!(capture_format.samples_per_sec(), 48000);
+        assert_eq!(capture_format.block_align(), 4);
+        assert_eq!(capture_format.avg_bytes_per_sec(), 4 * 48000);
+        // The endpoint's format is left alone.
+        assert_eq!(format.samples_per_sec(), 96000);
+    }
+
+    #[test]
+    fn test_create_capture_format_wave_format_extensible() {
+        let mono_format = WAVEFORMATEXTENSIBLE {
+            Format: WAVEFORMATEX {
+                wFormatTag: WAVE_FORMAT_EXTENSIBLE,
+                nChannels: 1,
+                nSamplesPerSec: 48000,
+                nAvgBytesPerSec: 4 * 48000,
+                nBlockAlign: 4,
+                wBitsPerSample: 32,
+                cbSize: 22,
+            },
+            Samples: 32,
+            dwChannelMask: SPEAKER_FRONT_CENTER,
+            SubFormat: KSDATAFORMAT_SUBTYPE_IEEE_FLOAT,
+        };
+        let mut surround_format = mono_format;
+        surround_format.Format.nChannels = 6;
+        surround_format.Format.nAvgBytesPerSec = 24 * 48000;
+        surround_format.Format.nBlockAlign = 24;
+        surround_format.dwChannelMask = SPEAKER_FRONT_LEFT
+            | SPEAKER_FRONT_RIGHT
+            | SPEAKER_FRONT_CENTER
+            | SPEAKER_LOW_FREQUENCY
+            | SPEAKER_BACK_LEFT
+            | SPEAKER_BACK_RIGHT;
+
+        for (format, channels) in [(mono_format, 1), (surround_format, 6)] {
+            // Safe because `GetMixFormat` casts `WAVEFORMATEXTENSIBLE` into a `WAVEFORMATEX` like
+            // so.
+            let format = unsafe {
+                WaveAudioFormat::new((&format) as *const WAVEFORMATEXTENSIBLE as *mut WAVEFORMATEX)
+            };
+
+            let capture_format = format.create_capture_format(44100);
+            assert_eq!(capture_format.format_tag(), WAVE_FORMAT_EXTENSIBLE);
+            assert_eq!(capture_format.channels(), channels);
+            assert_eq!(capture_format.bits_per_sample(), 16);
+            assert_eq!(capture_format.samples(), Some(16));
+            assert_eq!(capture_format.samples_per_sec(), 44100);
+            assert_eq!(capture_format.block_align(), 2 * channels);
+            assert_eq!(
+                capture_format.avg_bytes_per_sec(),
+                2 * channels as u32 * 44100
+            );
+            assert_eq!(capture_format.channel_mask(), format.channel_mask());
+            assert!(IsEqualGUID(
+                &capture_format.sub_format().unwrap(),
+                &KSDATAFORMAT_SUBTYPE_PCM
+            ));
+        }
+    }
+
+    #[test]
+    fn test_capture_format_shared_audio_engine_period_in_frames() {
+        let format = WAVEFORMATEX {
+            wFormatTag: WAVE_FORMAT_IEEE_FLOAT,
+            nChannels: 2,
+            nSamplesPerSec: 48000,
+            nAvgBytesPerSec: 8 * 48000,
+            nBlockAlign: 8,
+            wBitsPerSample: 32,
+            cbSize: 0,
+        };
+
+        // Safe because we can convert a struct to a pointer declared above. Also that means the
+        // pointer can be safely deferenced.
+        let format =
+            unsafe { WaveAudioFormat::new((&format) as *const WAVEFORMATEX as *mut WAVEFORMATEX) };
+
+        // The period is counted in frames at the guest's rate, not the endpoint's.
+        let capture_format = format.create_capture_format(44100);
+        assert_eq!(
+            capture_format.get_shared_audio_engine_period_in_frames(100_000),
+            441
+        );
+        let capture_format = format.create_capture_format(48000);
+        assert_eq!(
+            capture_format.get_shared_audio_engine_period_in_frames(100_000),
+            480
+        );
+        assert_eq!(
+            capture_format.get_shared_audio_engine_period_in_frames(30_000),
+            144
+        );
+    }
+
+    #[test]
+    fn test_period_in_frames() {
+        // The usual 10ms period.
+        assert_eq!(period_in_frames(48000, 100_000), 480);
+        assert_eq!(period_in_frames(44100, 100_000), 441);
+        // 3ms at 44.1kHz is 132.3 frames.
+        assert_eq!(period_in_frames(44100, 30_000), 133);
+        // Just over a whole number of frames rounds up.
+        assert_eq!(period_in_frames(48000, 100_001), 481);
+        // Just under a whole number of frames still rounds up to it, not past it.
+        assert_eq!(period_in_frames(48000, 99_999), 480);
+        // A single 100ns unit still needs a frame.
+        assert_eq!(period_in_frames(48000, 1), 1);
+        assert_eq!(period_in_frames(48000, 0), 0);
+        assert_eq!(period_in_frames(48000, -100_000), 0);
+    }
 }
@@ -85,7 +85,7 @@ where
 
     // Load the whole kernel image to kernel_start
     guest_mem
-        .read_to_memory(kernel_start, kernel_image, kernel_size)
+        .read_to_memory_exact(kernel_start, kernel_image, kernel_size)
         .map_err(|_| Error::ReadKernelImage)?;
 
     Ok((params, kernel_start.offset() + kernel_size as u64))
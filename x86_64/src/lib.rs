@@ -71,6 +71,7 @@ use arch::MsrFilter;
 use arch::MsrRWType;
 use arch::MsrValueFrom;
 use arch::RunnableLinuxVm;
+use arch::SerialPortHandles;
 use arch::VmComponents;
 use arch::VmImage;
 use base::warn;
@@ -135,6 +136,9 @@ use sync::Mutex;
 use thiserror::Error;
 use vm_control::BatControl;
 use vm_control::BatteryType;
+use vm_control::BootMilestones;
+use vm_control::PhaseTimer;
+use vm_control::SetKernelCmdlineError;
 use vm_memory::GuestAddress;
 use vm_memory::GuestMemory;
 use vm_memory::GuestMemoryError;
@@ -210,6 +214,8 @@ pub enum Error {
     InsertBus(devices::BusError),
     #[error("the kernel extends past the end of RAM")]
     InvalidCpuConfig,
+    #[error("{0}")]
+    InvalidSerialParameters(arch::InvalidSerialParameters),
     #[error("invalid CPU config parameters")]
     KernelOffsetPastEnd,
     #[error("error loading bios: {0}")]
@@ -264,6 +270,8 @@ pub enum Error {
     SetupSmbios(smbios::Error),
     #[error("failed to set up sregs: {0}")]
     SetupSregs(base::Error),
+    #[error("{0} batteries requested, but the ACPI tables only describe one")]
+    TooManyBatteries(usize),
     #[error("failed to translate virtual address")]
     TranslatingVirtAddr,
     #[error("protected VMs not supported on x86_64")]
@@ -563,7 +571,7 @@ impl arch::LinuxArch for X8664arch {
         system_allocator: &mut SystemAllocator,
         serial_parameters: &BTreeMap<(SerialHardware, u8), SerialParameters>,
         serial_jail: Option<Minijail>,
-        battery: (Option<BatteryType>, Option<Minijail>),
+        batteries: Vec<(BatteryType, Option<Minijail>)>,
         mut vm: V,
         ramoops_region: Option<arch::pstore::RamoopsRegion>,
         devs: Vec<(Box<dyn BusDeviceObj>, Option<Minijail>)>,
@@ -576,9 +584,12 @@ impl arch::LinuxArch for X8664arch {
         V: VmX86_64,
         Vcpu: VcpuX86_64,
     {
+        let mut timer = PhaseTimer::new();
         if components.hv_cfg.protection_type != ProtectionType::Unprotected {
             return Err(Error::UnsupportedProtectionType);
         }
+        arch::check_serial_parameters(serial_parameters, arch::SERIAL_ADDR.len() as u8)
+            .map_err(Error::InvalidSerialParameters)?;
 
         let mem = vm.get_memory().clone();
 
@@ -636,6 +647,7 @@ impl arch::LinuxArch for X8664arch {
             Some(pcie_vcfg_range.start),
         )
         .map_err(Error::CreatePciRoot)?;
+        timer.phase_done("generate_pci_root");
 
         let pci = Arc::new(Mutex::new(pci));
         pci.lock().enable_pcie_cfg_mmio(pcie_cfg_mmio_range.start);
@@ -694,12 +706,13 @@ impl arch::LinuxArch for X8664arch {
         if !components.no_rtc {
             Self::setup_legacy_cmos_device(&io_bus, components.memory_size)?;
         }
-        Self::setup_serial_devices(
+        let serial_ports = Self::setup_serial_devices(
             components.hv_cfg.protection_type,
             irq_chip.as_irq_chip_mut(),
             &io_bus,
             serial_parameters,
             serial_jail,
+            &components.boot_milestones,
         )?;
         Self::setup_debugcon_devices(
             components.hv_cfg.protection_type,
@@ -707,6 +720,8 @@ impl arch::LinuxArch for X8664arch {
             serial_parameters,
             debugcon_jail,
         )?;
+        let (serial_console_buffers, serial_inputs, serial_output_files) =
+            SerialPortHandles::split(serial_ports);
 
         let bios_size = if let VmImage::Bios(ref bios) = components.vm_image {
             bios.metadata().map_err(Error::LoadBios)?.len()
@@ -745,7 +760,7 @@ impl arch::LinuxArch for X8664arch {
             &components.direct_fixed_evts,
             irq_chip.as_irq_chip_mut(),
             sci_irq,
-            battery,
+            batteries,
             &mmio_bus,
             max_bus,
             &mut resume_notify_devices,
@@ -760,6 +775,7 @@ impl arch::LinuxArch for X8664arch {
         irq_chip
             .finalize_devices(system_allocator, &io_bus, &mmio_bus)
             .map_err(Error::RegisterIrqfd)?;
+        timer.phase_done("add devices");
 
         // All of these bios generated tables are set manually for the benefit of the kernel boot
         // flow (since there's no BIOS to set it) and for the BIOS boot flow since crosvm doesn't
@@ -774,8 +790,13 @@ impl arch::LinuxArch for X8664arch {
             mptable::setup_mptable(&mem, vcpu_count as u8, &pci_irqs)
                 .map_err(Error::SetupMptable)?;
         }
-        smbios::setup_smbios(&mem, components.dmi_path, &components.oem_strings)
-            .map_err(Error::SetupSmbios)?;
+        smbios::setup_smbios(
+            &mem,
+            components.dmi_path,
+            &components.oem_strings,
+            &components.vm_uuid,
+        )
+        .map_err(Error::SetupSmbios)?;
 
         let host_cpus = if components.host_cpu_topology {
             components.vcpu_affinity.clone()
@@ -799,6 +820,7 @@ impl arch::LinuxArch for X8664arch {
             components.force_s2idle,
         )
         .ok_or(Error::CreateAcpi)?;
+        timer.phase_done("create tables");
 
         let mut cmdline = Self::get_base_linux_cmdline();
 
@@ -869,6 +891,8 @@ impl arch::LinuxArch for X8664arch {
         for vcpu in vcpu_init.iter_mut() {
             vcpu.msrs = msrs.clone();
         }
+        timer.phase_done("load image");
+        timer.finish("build_vm", &components.boot_milestones);
 
         Ok(RunnableLinuxVm {
             vm,
@@ -878,6 +902,11 @@ impl arch::LinuxArch for X8664arch {
             vcpu_init,
             no_smt: components.no_smt,
             irq_chip: irq_chip.try_box_clone().map_err(Error::CloneIrqChip)?,
+            metrics_page: None,
+            pvtime: None,
+            ramoops_region,
+            degraded_devices: Vec::new(),
+            fdt_address: None,
             has_bios: matches!(components.vm_image, VmImage::Bios(_)),
             io_bus,
             mmio_bus,
@@ -885,8 +914,13 @@ impl arch::LinuxArch for X8664arch {
             suspend_evt,
             resume_notify_devices,
             rt_cpus: components.rt_cpus,
+            serial_console_buffers,
+            serial_inputs,
+            serial_output_files,
+            static_mmio_map: Vec::new(),
             delay_rt: components.delay_rt,
             bat_control,
+            boot_milestones: components.boot_milestones,
             #[cfg(all(target_arch = "x86_64", feature = "gdb"))]
             gdb: components.gdb,
             pm: Some(acpi_dev_resource.pm),
@@ -894,6 +928,7 @@ impl arch::LinuxArch for X8664arch {
             #[cfg(unix)]
             platform_devices: Vec::new(),
             hotplug_bus: BTreeMap::new(),
+            vmwdt_control: None,
         })
     }
 
@@ -966,6 +1001,35 @@ impl arch::LinuxArch for X8664arch {
         )
         .map_err(Error::ConfigurePciDevice)
     }
+
+    fn set_kernel_cmdline<V: VmX86_64, Vcpu: VcpuX86_64>(
+        linux: &RunnableLinuxVm<V, Vcpu>,
+        cmdline: &str,
+    ) -> std::result::Result<(), SetKernelCmdlineError> {
+        let mut new_cmdline = kernel_cmdline::Cmdline::new(CMDLINE_MAX_SIZE as usize);
+        new_cmdline
+            .insert_str(cmdline)
+            .map_err(|e| SetKernelCmdlineError::InvalidCmdline(e.to_string()))?;
+        // `Cmdline` only accepts printable ASCII, so there is no NUL in the middle.
+        let cmdline = CString::new(new_cmdline.as_str()).unwrap();
+
+        // A bios reads the command line from CMDLINE_OFFSET as well, see `build_vm`.
+        let mem = linux.vm.get_memory();
+        mem.write_all_at_addr(cmdline.to_bytes_with_nul(), GuestAddress(CMDLINE_OFFSET))
+            .map_err(|e| SetKernelCmdlineError::Failed(e.to_string()))?;
+        if linux.has_bios {
+            return Ok(());
+        }
+
+        // The kernel also gets the command line size from the zero page.
+        let zero_page_addr = GuestAddress(ZERO_PAGE_OFFSET);
+        let mut params: boot_params = mem
+            .read_obj_from_addr(zero_page_addr)
+            .map_err(|e| SetKernelCmdlineError::Failed(e.to_string()))?;
+        params.hdr.cmdline_size = cmdline.to_bytes_with_nul().len() as u32;
+        mem.write_obj_at_addr(params, zero_page_addr)
+            .map_err(|e| SetKernelCmdlineError::Failed(e.to_string()))
+    }
 }
 
 #[cfg(all(target_arch = "x86_64", feature = "gdb"))]
@@ -1357,7 +1421,7 @@ impl X8664arch {
         bios_image
             .seek(io::SeekFrom::Start(0))
             .map_err(Error::LoadBios)?;
-        mem.read_to_memory(
+        mem.read_to_memory_exact(
             bios_start(bios_image_length),
             bios_image,
             bios_image_length as usize,
@@ -1616,7 +1680,7 @@ impl X8664arch {
     /// * - `suspend_evt` the event object which used to suspend the vm
     /// * - `sdts` ACPI system description tables
     /// * - `irq_chip` the IrqChip object for registering irq events
-    /// * - `battery` indicate whether to create the battery
+    /// * - `batteries` the batteries to create, at most one
     /// * - `mmio_bus` the MMIO bus to add the devices to
     fn setup_acpi_devices(
         pci_root: Arc<Mutex<PciRoot>>,
@@ -1630,33 +1694,42 @@ impl X8664arch {
         #[cfg(feature = "direct")] direct_fixed_evts: &[devices::ACPIPMFixedEvent],
         irq_chip: &mut dyn IrqChip,
         sci_irq: u32,
-        battery: (Option<BatteryType>, Option<Minijail>),
+        batteries: Vec<(BatteryType, Option<Minijail>)>,
         #[cfg_attr(windows, allow(unused_variables))] mmio_bus: &devices::Bus,
         max_bus: u8,
         resume_notify_devices: &mut Vec<Arc<Mutex<dyn BusResumeDevice>>>,
-    ) -> Result<(acpi::AcpiDevResource, Option<BatControl>)> {
+    ) -> Result<(acpi::AcpiDevResource, Vec<BatControl>)> {
         // The AML data for the acpi devices
         let mut amls = Vec::new();
 
-        let bat_control = if let Some(battery_type) = battery.0 {
+        // Every goldfish battery would get the same AML device name.
+        if batteries.len() > 1 {
+            return Err(Error::TooManyBatteries(batteries.len()));
+        }
+        let mut bat_control = Vec::new();
+        #[cfg_attr(windows, allow(unused_variables))]
+        for (battery_type, battery_jail) in batteries {
             match battery_type {
                 #[cfg(unix)]
                 BatteryType::Goldfish => {
                     let (control_tube, _mmio_base) = arch::sys::unix::add_goldfish_battery(
-                        &mut amls, battery.1, mmio_bus, irq_chip, sci_irq, resources,
+                        &mut amls,
+                        battery_jail,
+                        mmio_bus,
+                        irq_chip,
+                        Some(sci_irq),
+                        resources,
                     )
                     .map_err(Error::CreateBatDevices)?;
-                    Some(BatControl {
+                    bat_control.push(BatControl {
                         type_: BatteryType::Goldfish,
                         control_tube,
-                    })
+                    });
                 }
                 #[cfg(windows)]
-                _ => None,
+                _ => {}
             }
-        } else {
-            None
-        };
+        }
 
         let pm_alloc = resources.get_anon_alloc();
         let pm_iobase = match resources.io_allocator() {
@@ -1804,23 +1877,28 @@ impl X8664arch {
     /// * - `irq_chip` the IrqChip object for registering irq events
     /// * - `io_bus` the I/O bus to add the devices to
     /// * - `serial_parmaters` - definitions for how the serial devices should be configured
+    /// * - `boot_milestones` - where the first serial output is recorded
+    ///
+    /// Returns the handles to the ports, by port number.
     fn setup_serial_devices(
         protection_type: ProtectionType,
         irq_chip: &mut dyn IrqChip,
         io_bus: &devices::Bus,
         serial_parameters: &BTreeMap<(SerialHardware, u8), SerialParameters>,
         serial_jail: Option<Minijail>,
-    ) -> Result<()> {
+        boot_milestones: &BootMilestones,
+    ) -> Result<BTreeMap<u8, SerialPortHandles>> {
         let com_evt_1_3 = devices::IrqEdgeEvent::new().map_err(Error::CreateEvent)?;
         let com_evt_2_4 = devices::IrqEdgeEvent::new().map_err(Error::CreateEvent)?;
 
-        arch::add_serial_devices(
+        let ports = arch::add_serial_devices(
             protection_type,
             io_bus,
             com_evt_1_3.get_trigger(),
             com_evt_2_4.get_trigger(),
             serial_parameters,
-            serial_jail,
+            serial_jail.as_ref(),
+            boot_milestones,
         )
         .map_err(Error::CreateSerialDevices)?;
 
@@ -1836,7 +1914,7 @@ impl X8664arch {
             .register_edge_irq_event(X86_64_SERIAL_2_4_IRQ, &com_evt_2_4, source)
             .map_err(Error::RegisterIrqfd)?;
 
-        Ok(())
+        Ok(ports)
     }
 
     fn setup_debugcon_devices(
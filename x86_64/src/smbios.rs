@@ -13,6 +13,7 @@ use std::slice;
 use data_model::DataInit;
 use remain::sorted;
 use thiserror::Error;
+use uuid::Uuid;
 use vm_memory::GuestAddress;
 use vm_memory::GuestMemory;
 
@@ -266,10 +267,21 @@ fn setup_smbios_from_file(mem: &GuestMemory, path: &Path) -> Result<()> {
     Err(Error::InvalidInput)
 }
 
+/// Returns `uuid` laid out as in the system information table, which since SMBIOS 2.6 stores its
+/// first three fields little-endian.
+fn smbios_uuid(uuid: &Uuid) -> [u8; 16] {
+    let mut bytes = *uuid.as_bytes();
+    bytes[0..4].reverse();
+    bytes[4..6].reverse();
+    bytes[6..8].reverse();
+    bytes
+}
+
 pub fn setup_smbios(
     mem: &GuestMemory,
     dmi_path: Option<PathBuf>,
     oem_strings: &[String],
+    vm_uuid: &Uuid,
 ) -> Result<()> {
     if let Some(dmi_path) = dmi_path {
         return setup_smbios_from_file(mem, &dmi_path);
@@ -307,6 +319,7 @@ pub fn setup_smbios(
             handle,
             manufacturer: 1, // First string written in this section
             product_name: 2, // Second string written in this section
+            uuid: smbios_uuid(vm_uuid),
             ..Default::default()
         };
         curptr = write_and_incr(mem, smbios_sysinfo, curptr)?;
@@ -408,11 +421,33 @@ mod tests {
         let mem = GuestMemory::new(&[(GuestAddress(SMBIOS_START), 4096)]).unwrap();
 
         // Use default 3.0 SMBIOS format.
-        setup_smbios(&mem, None, &Vec::new()).unwrap();
+        setup_smbios(&mem, None, &Vec::new(), &Uuid::nil()).unwrap();
 
         let smbios_ep: Smbios30Entrypoint =
             mem.read_obj_from_addr(GuestAddress(SMBIOS_START)).unwrap();
 
         assert_eq!(compute_checksum(&smbios_ep), 0);
     }
+
+    #[test]
+    fn system_uuid() {
+        let mem = GuestMemory::new(&[(GuestAddress(SMBIOS_START), 4096)]).unwrap();
+        let uuid = Uuid::parse_str("23546c3d-962d-4ebc-94d9-4acf50996944").unwrap();
+        setup_smbios(&mem, None, &Vec::new(), &uuid).unwrap();
+
+        // The system information follows the BIOS information and its "crosvm" and "0" strings.
+        let sysinfo_addr = GuestAddress(SMBIOS_START).unchecked_add(
+            (mem::size_of::<Smbios30Entrypoint>() + mem::size_of::<SmbiosBiosInfo>() + 10) as u64,
+        );
+        let sysinfo: SmbiosSysInfo = mem.read_obj_from_addr(sysinfo_addr).unwrap();
+        assert_eq!(sysinfo.typ, SYSTEM_INFORMATION);
+        let sysinfo_uuid = sysinfo.uuid;
+        assert_eq!(
+            sysinfo_uuid,
+            [
+                0x3d, 0x6c, 0x54, 0x23, 0x2d, 0x96, 0xbc, 0x4e, 0x94, 0xd9, 0x4a, 0xcf, 0x50, 0x99,
+                0x69, 0x44
+            ]
+        );
+    }
 }
@@ -33,6 +33,7 @@ use hypervisor::VmX86_64;
 use resources::AddressRange;
 use resources::SystemAllocator;
 use sync::Mutex;
+use uuid::Uuid;
 use vm_memory::GuestAddress;
 use vm_memory::GuestMemory;
 
@@ -227,7 +228,8 @@ where
 
     // Note that this puts the mptable at 0x9FC00 in guest physical memory.
     mptable::setup_mptable(&guest_mem, 1, &pci_irqs).expect("failed to setup mptable");
-    smbios::setup_smbios(&guest_mem, None, &Vec::new()).expect("failed to setup smbios");
+    smbios::setup_smbios(&guest_mem, None, &Vec::new(), &Uuid::nil())
+        .expect("failed to setup smbios");
 
     let mut apic_ids = Vec::new();
     acpi::create_acpi_tables(